@@ -23,7 +23,7 @@ echo -e "${BLUE}========================================${NC}"
 echo -e "\n${YELLOW}Checking server status...${NC}"
 if ! curl -s "${BASE_URL}/health" > /dev/null 2>&1; then
     echo -e "${RED}❌ Server is not running on ${BASE_URL}${NC}"
-    echo "Please start the server with: cargo run --bin btc_options_api"
+    echo "Please start the server with: AUTH_DISABLED=true cargo run --bin btc_options_api"
     exit 1
 fi
 echo -e "${GREEN}✓ Server is running${NC}"
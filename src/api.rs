@@ -0,0 +1,3937 @@
+// HTTP handlers and shared application state.
+// Handlers only depend on the source traits, so they can be mounted against
+// fakes (see tests/unit_tests.rs) as well as the live oracles and wallet.
+
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use async_graphql::SimpleObject;
+use chrono::{NaiveDate, NaiveTime, Utc};
+use std::collections::HashMap;
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+use crate::{api_keys, audit, auth, catalog, conversions, credit_tiers, export, graphql, iv_history, kyc, orderbook, payments, pools, price_history, pricing, pricing_audit, referrals, settlement, stats, strikes, trading_state, validation, vol};
+use crate::export::{ExportFormat, ExportTable};
+use crate::expiry::ExpiryNoticeConfig;
+use crate::audit::AuditFilter;
+use crate::contract_search::ContractSearchQuery;
+use crate::conversions::Conversion;
+use crate::trading_state::TradingState;
+use crate::repository::{self, Repository};
+use crate::error::{ApiError, ErrorCode};
+use crate::day_count::{self, DayCountConvention};
+use crate::utils::{format_expires_timestamp, year_fraction, cents_to_usd,
+                   db_string_to_float, duration_to_seconds, format_btc, format_sats, btc_to_sats, sats_to_btc};
+use crate::models::{legacy_product_symbol, product_symbol, Asset, OptionSide, Contract, ContractAmendment, ContractRecord, ContractStatus, ExerciseStyle, IvProvenance, PremiumQuote, QuoteCurrency, TradeSnapshot};
+use crate::pricing::Greeks;
+use crate::mutiny_wallet::{MutinyWallet, Network};
+use crate::pools::Pool;
+use crate::risk_manager::{aggregate_positions, MarginCache, Position, RiskManager};
+use crate::rolling_metrics::{MetricsWindow, ProductMetrics, RollingMetrics};
+use crate::options_grid::GridConfig;
+use crate::orderbook::{NewQuote, OrderbookConfig, RestingQuote};
+use crate::quoting::{BookExposure, Quote, QuotingConfig};
+use crate::quote_sanity::{self, QuotedOption, Violation};
+use crate::attestation::{self, Attestation, OracleSigner};
+use crate::dlc::{self, DlcConfig};
+use crate::health::{self, Dependencies, DependencyStatus, HealthConfig, OverallStatus, SourceHealth};
+use crate::supervisor::Supervisor;
+use crate::timeouts::{deadline, UpstreamTimeouts};
+use crate::lightning::LightningNode;
+use crate::payments::{PaymentConfig, PaymentMethod, PaymentRequest, PaymentTarget, PremiumPayment};
+use crate::reservations::{ReservationConfig, ReservedMargin};
+use crate::fees::{self, FeeSchedule, TradeFee};
+use crate::api_v1::DeprecationConfig;
+use crate::margin::{MarginModel, MaxLossMargin};
+use crate::position_limits::{product_quantity, BookGreeks, PositionLimits};
+use crate::iv_policy::{DefaultIvPolicy, IvResolver};
+use crate::rates::{RateCurve, RateSource};
+use crate::basis::BasisSource;
+use crate::kyc::{KycConfig, KycStatus};
+use crate::auth::{AuthConfig, Claims, JwtConfig, Role};
+use crate::price_guards::PriceGuards;
+use crate::sources::{IvSource, PriceSource, PriceUpdate, WalletSource};
+use crate::table_cache::ResponseCache;
+use crate::utilization::{Utilization, UtilizationMonitorConfig};
+use crate::outbox::EventBroadcast;
+
+/// Register the health check and all API routes
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg
+        // Health check endpoints
+        .route("/", web::get().to(health_check))
+        .route("/health", web::get().to(health_check))
+        // Register API endpoints
+        .service(web::resource("/contract").route(web::post().to(post_contract)))
+        .service(
+            web::resource("/contract/{id}")
+                .route(web::get().to(get_contract))
+                .route(web::patch().to(patch_contract)),
+        )
+        .service(web::resource("/contract/{id}/exercise").route(web::post().to(post_exercise_contract)))
+        .service(web::resource("/contract/{id}/payment").route(web::get().to(get_contract_payment)))
+        .service(web::resource("/contract/{id}/dlc").route(web::get().to(get_contract_dlc)))
+        .service(web::resource("/contract/{id}/pricing-audit").route(web::get().to(get_contract_pricing_audit)))
+        .service(web::resource("/attestations/{date}").route(web::get().to(get_attestations)))
+        .service(web::resource("/contract/{id}/close").route(web::post().to(post_close_contract)))
+        .service(web::resource("/contracts").route(web::get().to(get_contracts)))
+        .service(web::resource("/contracts/search").route(web::get().to(get_contracts_search)))
+        .service(web::resource("/optionsTable").route(web::get().to(get_options_table)))
+        .service(web::resource("/products").route(web::get().to(get_products)))
+        .service(web::resource("/maxQuantity").route(web::get().to(get_max_quantity)))
+        .service(web::resource("/price").route(web::get().to(get_price)))
+        .service(web::resource("/oracle/prices").route(web::get().to(get_oracle_prices)))
+        .service(web::resource("/pools").route(web::get().to(get_pools)))
+        .service(web::resource("/pools/{id}").route(web::get().to(get_pool)))
+        .service(web::resource("/pools/{id}/contract").route(web::post().to(post_pool_contract)))
+        .service(web::resource("/pools/{id}/maxQuantity").route(web::get().to(get_pool_max_quantity)))
+        .service(web::resource("/orderbook").route(web::get().to(get_orderbook)))
+        .service(web::resource("/orderbook/take").route(web::post().to(post_take_quote)))
+        .service(web::resource("/positions").route(web::get().to(get_positions)))
+        .service(web::resource("/delta").route(web::get().to(get_delta)))
+        .service(web::resource("/realizedVol").route(web::get().to(get_realized_vol)))
+        .service(web::resource("/ivSurface/fit").route(web::get().to(get_iv_surface_fit)))
+        .service(web::resource("/risk/var").route(web::get().to(get_var)))
+        .service(web::resource("/risk/scenario").route(web::post().to(post_risk_scenario)))
+        .service(web::resource("/riskStatus").route(web::get().to(get_risk_status)))
+        .service(web::resource("/ws/price").route(web::get().to(ws_price)))
+        .service(web::resource("/ws/events").route(web::get().to(ws_events)))
+        .service(web::resource("/tradingState").route(web::get().to(get_trading_state)))
+        .service(
+            web::resource("/graphql")
+                .route(web::post().to(graphql::post_graphql))
+                .route(web::get().to(graphql::get_graphiql)),
+        )
+        // Admin endpoints
+        .service(web::resource("/auth/login").route(web::post().to(post_login)))
+        .service(web::resource("/auth/session").route(web::get().to(get_session)))
+        .service(web::resource("/admin/audit").route(web::get().to(get_audit_log)))
+        .service(web::resource("/admin/tradingState").route(web::post().to(post_trading_state)))
+        .service(web::resource("/admin/iv/refresh").route(web::post().to(post_iv_refresh)))
+        .service(web::resource("/admin/iv/status").route(web::get().to(get_iv_status)))
+        .service(web::resource("/admin/creditTiers").route(web::get().to(get_credit_tiers)))
+        .service(web::resource("/admin/referrals").route(web::get().to(get_referrals)))
+        .service(
+            web::resource("/admin/creditTiers/{name}")
+                .route(web::put().to(put_credit_tier))
+                .route(web::delete().to(delete_credit_tier)),
+        )
+        .service(
+            web::resource("/admin/accounts/{account}/creditTier")
+                .route(web::get().to(get_account_credit_tier))
+                .route(web::put().to(put_account_credit_tier)),
+        )
+        .service(
+            web::resource("/admin/accounts/{account}/kyc")
+                .route(web::get().to(get_account_kyc))
+                .route(web::put().to(put_account_kyc)),
+        )
+        .service(web::resource("/export/contracts").route(web::get().to(get_export_contracts)))
+        .service(web::resource("/export/premiumHistory").route(web::get().to(get_export_premium_history)))
+        .service(web::resource("/settlements").route(web::get().to(get_settlements)))
+        // Analytics endpoints
+        .service(web::resource("/topBanner").route(web::get().to(get_top_banner)))
+        .service(web::resource("/marketHighlights").route(web::get().to(get_market_highlights)))
+        .service(web::resource("/topGainers").route(web::get().to(get_top_gainers)))
+        .service(web::resource("/topLosers").route(web::get().to(get_top_losers)))
+        .service(web::resource("/topVolume").route(web::get().to(get_top_volume)))
+        .service(web::resource("/volMovers").route(web::get().to(get_vol_movers)))
+        .service(web::resource("/stats/history").route(web::get().to(get_stats_history)))
+        .service(web::resource("/spot/history").route(web::get().to(get_spot_history)));
+}
+
+// Request/Response structures. Those also served over GraphQL derive SimpleObject.
+#[derive(Serialize, Clone, SimpleObject)]
+pub(crate) struct OptionsTableResponse {
+    underlying: Asset,
+    side: OptionSide,
+    strike_price: f64,
+    expire: String,
+    expires: i64,     // Expiry timestamp the tenor snaps to, to buy the option at
+    premium: String,  // Ask: what a buyer pays, in premium_currency as string for precision
+    bid: String,      // What the pool would pay, in premium_currency
+    mid: String,      // Black-Scholes value, in premium_currency
+    premium_currency: QuoteCurrency,
+    max_quantity: String,  // BTC amount as string for precision
+    iv: f64,
+    delta: f64,
+    margin_per_contract: f64,     // USD margin the pool holds against one option sold
+    pool_utilization_if_max: f64,  // % of pool collateral used once max_quantity is sold
+    warnings: Vec<OptionWarning>,
+    generated_at: i64,  // Unix timestamp when this table was priced
+}
+
+// Data quality and capacity caveats of an options table row
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, async_graphql::Enum)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum OptionWarning {
+    LowLiquidityIv,  // No market IV for the option; priced at the default IV
+    NoCapacity,      // Nothing can be sold: collateral is used up or trading is not open
+    BelowIntrinsic,          // The ask was below intrinsic value
+    ParityBreach,            // The call and put of the strike broke put-call parity
+    NegativeCalendarSpread,  // The ask was below that of an earlier expiry
+}
+
+impl From<Violation> for OptionWarning {
+    fn from(violation: Violation) -> Self {
+        match violation {
+            Violation::BelowIntrinsic => OptionWarning::BelowIntrinsic,
+            Violation::ParityBreach => OptionWarning::ParityBreach,
+            Violation::NegativeCalendarSpread => OptionWarning::NegativeCalendarSpread,
+        }
+    }
+}
+
+// POST /contract body: `premium` is in `premium_currency` (BTC when omitted)
+#[derive(Deserialize)]
+struct ContractRequest {
+    #[serde(flatten)]
+    contract: Contract,
+    #[serde(default)]
+    premium_currency: QuoteCurrency,
+    #[serde(default)]
+    exercise_style: ExerciseStyle,
+    #[serde(default)]
+    payment_method: PaymentMethod,  // Used when premiums must be paid
+    referral_code: Option<String>,   // Attributes the trade to a referral campaign or affiliate
+}
+
+// POST /orderbook/take body: quantity to buy from a resting quote (BTC)
+#[derive(Deserialize)]
+struct TakeQuoteRequest {
+    quote_id: i64,
+    quantity: f64,
+    #[serde(default)]
+    payment_method: PaymentMethod,
+    referral_code: Option<String>,
+}
+
+// POST /contract/{id}/close body: quantity to sell back to the pool (BTC)
+#[derive(Deserialize)]
+struct CloseRequest {
+    quantity: f64,
+}
+
+// PATCH /contract/{id} body: terms to extend, each unchanged when omitted
+#[derive(Deserialize)]
+struct AmendRequest {
+    expires: Option<i64>,
+    quantity: Option<f64>,  // New quantity as traded, BTC
+    premium: Option<f64>,   // New premium per unit, BTC
+}
+
+// Contract response with string fields for precision
+#[derive(Serialize, SimpleObject)]
+pub(crate) struct ContractResponse {
+    underlying: Asset,
+    side: OptionSide,
+    strike_price: f64,
+    quantity: String,  // BTC amount as string
+    open_quantity: String,  // Quantity not yet closed, BTC amount as string
+    expires: i64,
+    premium: String,   // BTC amount as string
+    premium_currency: QuoteCurrency,
+    quoted_premium: Option<String>,   // Premium as agreed, in premium_currency
+    trade_btc_price: Option<f64>,     // BTC price the quoted premium was converted at
+    expiring_soon: bool,              // Expires within the expiry notice window
+}
+
+// Stored contract with live values, for the frontend position page
+#[derive(Serialize)]
+struct ContractDetailResponse {
+    #[serde(flatten)]
+    contract: ContractRecord,
+    spot_price: f64,                   // Of the underlying
+    btc_price: f64,
+    iv: f64,                           // Used for the mark and Greeks
+    time_to_expiry_secs: i64,          // 0 once expired
+    time_to_expiry_years: f64,
+    moneyness: f64,                    // Spot / strike
+    moneyness_label: &'static str,     // ITM, ATM (within 1%) or OTM for the holder
+    edge_at_trade_btc: Option<f64>,    // Premium over the Black-Scholes value at trade time, per unit
+    mark_premium_usd: f64,             // Black-Scholes value of one option
+    mark_premium_btc: f64,
+    mark_premium: String,              // In premium_currency
+    mark_value_usd: f64,               // Of the whole contract
+    unrealized_pnl_usd: f64,           // Pool P&L: premium received minus current mark
+    greeks: Greeks,                    // Of one option held long
+    position_greeks: Greeks,           // Of the whole contract held long
+    margin_usd: Option<f64>,           // Standalone margin; None once no longer open
+    marginal_margin_usd: Option<f64>,  // Book margin this contract adds after netting
+    expiring_soon: bool,
+    conversions: Vec<Conversion>,      // Premium, payout and close flows with the BTC price applied
+}
+
+// Result of an early exercise
+#[derive(Serialize)]
+struct ExerciseResponse {
+    #[serde(flatten)]
+    contract: ContractRecord,
+    payoff_usd: f64,
+    payoff: String,             // In premium_currency, at the exercise BTC price
+    released_margin_usd: f64,   // Book margin the contract no longer takes up
+}
+
+// One resting offer of the pool
+#[derive(Serialize)]
+struct OrderbookQuoteResponse {
+    product_symbol: String,         // Deribit style, e.g. BTC-28MAR25-50000-C
+    legacy_product_symbol: String,  // Former relative format, e.g. BTC-2d-50000-Put
+    #[serde(flatten)]
+    quote: RestingQuote,
+}
+
+// Result of taking a resting quote
+#[derive(Serialize)]
+struct TakeQuoteResponse {
+    contract_id: i64,
+    quote: RestingQuote,  // With the size left after this fill
+    payment: Option<PremiumPayment>,  // Set while premiums must be paid
+    fee: TradeFee,
+    #[serde(flatten)]
+    risk: TradeRisk,
+}
+
+// Settlement prices attested for one UTC day
+#[derive(Serialize)]
+struct AttestationsResponse {
+    date: String,
+    attestations: Vec<Attestation>,
+}
+
+// A contract recorded as pending, with the payment that opens it
+#[derive(Serialize)]
+struct PendingContractResponse {
+    contract_id: i64,
+    payment: PremiumPayment,
+    fee: TradeFee,
+    #[serde(flatten)]
+    risk: TradeRisk,
+}
+
+// A contract created open
+#[derive(Serialize)]
+struct ContractCreatedResponse {
+    contract_id: i64,
+    fee: TradeFee,
+    #[serde(flatten)]
+    risk: TradeRisk,
+}
+
+/// The pre-trade risk check of an accepted contract, as its collateral was reserved
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct TradeRisk {
+    pub pool_id: i64,
+    pub margin_consumed_usd: f64,       // Margin the contract added to the pool's book
+    pub remaining_collateral_usd: f64,  // Pool collateral left once the book holds the contract
+    pub pool_utilization_percent: f64,  // Margin of the book with the contract, as % of the pool's collateral
+    pub spot_price: f64,                // Of the underlying
+    pub btc_price: f64,                 // Valuing the pool and converting premiums
+    pub iv: f64,                        // The contract was priced and margined at
+    pub iv_source: IvProvenance,
+}
+
+/// A contract accepted by `accept_contract`
+#[derive(Clone, Debug)]
+pub struct AcceptedContract {
+    pub contract_id: i64,
+    pub payment: Option<PremiumPayment>,  // The payment that opens it, while premiums must be paid
+    pub fee: TradeFee,                    // Charged on top of the premium
+    pub risk: TradeRisk,
+}
+
+// Result of a partial or full close
+#[derive(Serialize)]
+struct CloseResponse {
+    #[serde(flatten)]
+    contract: ContractRecord,
+    closed_quantity_now: String,  // Closed by this request, BTC amount as string
+    close_price_usd: f64,         // Mark of one option the pool bought it back at
+    proceeds_usd: f64,            // Paid to the buyer
+    proceeds: String,             // In premium_currency
+    released_margin_usd: f64,     // Book margin the closed quantity no longer takes up
+}
+
+// Result of an amendment
+#[derive(Serialize)]
+struct AmendResponse {
+    #[serde(flatten)]
+    contract: ContractRecord,
+    additional_premium: String,  // Owed by the buyer for the amendment, BTC amount as string
+}
+
+// One contract found by GET /contracts/search
+#[derive(Serialize)]
+struct ContractSearchResult {
+    product_symbol: String,
+    legacy_product_symbol: String,
+    #[serde(flatten)]
+    contract: ContractRecord,
+}
+
+// One row of the position blotter: a product's net position valued at the current mark
+#[derive(Serialize)]
+struct PositionResponse {
+    product_symbol: String,
+    legacy_product_symbol: String,
+    #[serde(flatten)]
+    position: Position,
+    expire: String,
+    iv: f64,
+    mark_premium_usd: f64,      // Black-Scholes value of one option
+    mark_premium_btc: f64,
+    mark_value_usd: f64,        // Of the whole net position
+    unrealized_pnl_usd: f64,    // Pool P&L: premium received minus current mark
+    margin_usd: f64,            // Standalone margin of the net short; 0 for net longs
+}
+
+#[derive(Serialize, SimpleObject)]
+pub(crate) struct TopBannerResponse {
+    volume_24hr: f64,
+    open_interest_usd: f64,
+    contract_count: i64,
+}
+
+#[derive(Serialize, SimpleObject)]
+pub(crate) struct MarketHighlightItem {
+    product_symbol: String,
+    legacy_product_symbol: String,
+    side: OptionSide,
+    strike_price: f64,
+    expire: String,
+    expires: i64,
+    volume_24hr: f64,
+    price_change_24hr_percent: f64,
+}
+
+#[derive(Serialize, SimpleObject)]
+pub(crate) struct TopGainerItem {
+    product_symbol: String,
+    legacy_product_symbol: String,
+    side: OptionSide,
+    strike_price: f64,
+    expire: String,
+    expires: i64,
+    window: MetricsWindow,
+    change_percent: f64,       // Over window
+    change_24hr_percent: f64,  // Same as change_percent, named for the default window
+    last_price: f64,
+}
+
+#[derive(Serialize, SimpleObject)]
+pub(crate) struct TopVolumeItem {
+    product_symbol: String,
+    legacy_product_symbol: String,
+    side: OptionSide,
+    strike_price: f64,
+    expire: String,
+    expires: i64,
+    window: MetricsWindow,
+    volume_usd: f64,
+    last_price: f64,
+}
+
+#[derive(Serialize)]
+struct VolMoverItem {
+    product_symbol: String,
+    legacy_product_symbol: String,
+    underlying: Asset,
+    side: OptionSide,
+    strike_price: f64,
+    expire: String,
+    expires: i64,
+    window: MetricsWindow,
+    iv: f64,                 // Latest snapshot
+    iv_before: f64,          // Last snapshot before the window, or the first in it
+    iv_change: f64,          // In vol points
+    iv_change_percent: f64,  // Relative to iv_before
+    snapshot_at: i64,        // Of the latest snapshot
+}
+
+#[derive(Deserialize, Default)]
+pub(crate) struct OptionsTableQuery {
+    pub(crate) strike_step: Option<f64>,       // USD between strikes
+    pub(crate) strike_percent: Option<f64>,    // Percent of spot between strikes (overrides strike_step)
+    pub(crate) strikes_per_side: Option<u32>,  // Strikes above and below the center strike
+    pub(crate) tenors: Option<String>,         // Comma separated, e.g. "12h,1d,7d"
+    #[serde(default)]
+    pub(crate) asset: Asset,
+    #[serde(default)]
+    pub(crate) premium_currency: QuoteCurrency,
+}
+
+#[derive(Deserialize)]
+struct MaxQuantityQuery {
+    #[serde(default)]
+    asset: Asset,
+    side: OptionSide,
+    strike: f64,
+    expires: i64,  // Unix timestamp in seconds
+    premium: f64,  // In premium_currency
+    #[serde(default)]
+    premium_currency: QuoteCurrency,
+}
+
+#[derive(Serialize)]
+struct MaxQuantityResponse {
+    max_quantity: f64,
+    available_collateral_usd: f64,
+    existing_risk_usd: f64,
+    total_collateral_usd: f64,
+    pool_id: i64,
+    pool_balance_btc: f64,
+    btc_price: f64,
+    underlying: Asset,
+    spot_price: f64,  // Of the underlying; equals btc_price for BTC options
+    iv: f64,
+    margin_model: &'static str,
+}
+
+// GET /price: Black-Scholes value of one option; spot and IV come from the oracles unless given
+#[derive(Deserialize)]
+struct PriceQuery {
+    #[serde(default)]
+    asset: Asset,
+    side: OptionSide,
+    strike: f64,
+    expires: i64,       // Unix timestamp in seconds
+    iv: Option<f64>,    // Annualized, e.g. 0.55
+    spot: Option<f64>,  // USD per unit of the underlying
+}
+
+// Where an input of GET /price came from
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum PriceInput {
+    Request,
+    Oracle,
+    Default,  // The IV oracle had no IV for the option
+}
+
+#[derive(Serialize)]
+struct PriceResponse {
+    underlying: Asset,
+    side: OptionSide,
+    strike_price: f64,
+    expires: i64,
+    time_to_expiry: f64,  // Years under day_count
+    day_count: DayCountConvention,
+    spot_price: f64,
+    spot_source: PriceInput,
+    iv: f64,
+    iv_source: PriceInput,
+    risk_free_rate: f64,
+    carry: Option<f64>,   // Annualized basis the option is priced with, under PRICING_BASIS
+    forward_price: f64,   // Forward of the underlying at expiry under the carry
+    btc_price: f64,       // Premiums are converted to BTC at this price
+    premium_usd: f64,
+    premium_btc: String,  // 8 decimal string
+    greeks: Greeks,       // Of one option held long
+    priced_at: i64,
+}
+
+// GET /products filters: active products on every underlying by default
+#[derive(Deserialize)]
+struct ProductsQuery {
+    asset: Option<Asset>,
+    #[serde(default)]
+    status: catalog::ProductStatus,
+}
+
+// Underlying for the per-asset risk endpoints (BTC when omitted)
+#[derive(Deserialize)]
+struct AssetQuery {
+    #[serde(default)]
+    asset: Asset,
+}
+
+#[derive(Deserialize)]
+struct OracleSourcesQuery {
+    #[serde(default)]
+    asset: Asset,
+    sources: Option<String>,  // Comma separated exchange names; all feeds when omitted
+}
+
+#[derive(Serialize)]
+struct OracleSourcesResponse {
+    asset: Asset,
+    price: Option<f64>,     // Aggregate price, or the median of the requested sources
+    error: Option<String>,  // Why there is no price
+    sources: Vec<SourceHealth>,
+}
+
+// Optional underlying filter for the analytics endpoints (all assets when omitted)
+#[derive(Deserialize)]
+struct AssetFilter {
+    asset: Option<Asset>,
+}
+
+// Query of the leaderboards
+#[derive(Deserialize)]
+struct LeaderboardQuery {
+    asset: Option<Asset>,
+    #[serde(default)]
+    window: MetricsWindow,  // 1h, 4h, 24h (default) or 7d
+}
+
+#[derive(Deserialize)]
+struct VolMoversQuery {
+    asset: Option<Asset>,
+    #[serde(default)]
+    window: MetricsWindow,
+    limit: Option<usize>,  // 10 by default, at most iv_history::MAX_VOL_MOVERS
+}
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    #[serde(default)]
+    format: ExportFormat,
+    from: Option<String>,  // YYYY-MM-DD or Unix seconds, inclusive
+    to: Option<String>,    // YYYY-MM-DD (whole day) or Unix seconds, exclusive
+}
+
+// POST /admin/tradingState body
+#[derive(Deserialize)]
+struct TradingStateRequest {
+    state: TradingState,
+    reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StatsHistoryQuery {
+    #[serde(default)]
+    asset: Asset,
+    from: Option<String>,  // YYYY-MM-DD or Unix seconds, inclusive (default 7 days ago)
+    to: Option<String>,    // YYYY-MM-DD (whole day) or Unix seconds, exclusive (default now)
+}
+
+#[derive(Deserialize)]
+struct SpotHistoryQuery {
+    #[serde(default)]
+    asset: Asset,
+    from: Option<String>,      // As for /stats/history (default 7 days ago)
+    to: Option<String>,        // As for /stats/history (default now)
+    interval: Option<String>,  // Candle length such as 5m, 1h or 1d (default 1h)
+}
+
+#[derive(Serialize)]
+struct SpotHistoryResponse {
+    asset: Asset,
+    from: i64,
+    to: i64,
+    interval_secs: i64,
+    candles: Vec<price_history::Candle>,
+}
+
+// GET /settlements filters
+#[derive(Deserialize)]
+struct SettlementsQuery {
+    asset: Option<Asset>,
+    from: Option<String>,  // YYYY-MM-DD or Unix seconds, inclusive (default 30 days ago)
+    to: Option<String>,    // YYYY-MM-DD (whole day) or Unix seconds, exclusive (default now)
+}
+
+#[derive(Deserialize)]
+struct ReferralsQuery {
+    from: Option<String>,  // YYYY-MM-DD or Unix seconds, inclusive (default 30 days ago)
+    to: Option<String>,    // YYYY-MM-DD (whole day) or Unix seconds, exclusive (default now)
+}
+
+#[derive(Serialize)]
+struct ReferralsResponse {
+    from: i64,
+    to: i64,
+    referrals: Vec<referrals::ReferralSummary>,
+}
+
+#[derive(Deserialize)]
+struct VarQuery {
+    horizon_days: Option<f64>,
+    #[serde(default)]
+    asset: Asset,
+}
+
+#[derive(Deserialize)]
+struct ScenarioShock {
+    name: Option<String>,
+    spot_move_percent: f64,   // e.g. -20.0 = spot down 20%
+    #[serde(default)]
+    iv_shift: f64,            // absolute IV shift, e.g. 0.10 = +10 vol points
+}
+
+// GET /riskStatus: trading state against the collateral utilization of all pools
+#[derive(Serialize)]
+struct RiskStatusResponse {
+    trading: trading_state::TradingStatus,
+    #[serde(flatten)]
+    utilization: Utilization,
+    utilization_monitor: Option<UtilizationMonitorConfig>,  // None when the monitor is off
+}
+
+// GET /pools/{id}: a pool with its collateral, margin and recent sales
+#[derive(Serialize)]
+struct PoolRiskResponse {
+    #[serde(flatten)]
+    pool: Pool,
+    balance_btc: f64,
+    btc_price: f64,
+    total_collateral_usd: f64,      // balance_btc * btc_price * collateral_rate
+    margin_used_usd: f64,           // Margin of the pool's open book
+    available_collateral_usd: f64,
+    utilization_percent: f64,       // margin_used_usd as % of total_collateral_usd
+    active_contracts: usize,
+    open_notional_usd: f64,         // Open quantity at spot
+    contracts_24hr: i64,
+    premium_24hr_btc: f64,
+    margin_model: &'static str,
+}
+
+#[derive(Deserialize)]
+struct ScenarioRequest {
+    #[serde(default)]
+    asset: Asset,  // Underlying whose spot is shocked; the rest of the book is held constant
+    scenarios: Vec<ScenarioShock>,
+}
+
+#[derive(Serialize)]
+struct ScenarioResponse {
+    name: String,
+    spot_move_percent: f64,
+    iv_shift: f64,
+    spot_price: f64,
+    pnl_usd: f64,
+    margin_required_usd: f64,
+    margin_change_usd: f64,
+    collateral_usd: f64,
+    excess_collateral_usd: f64,
+}
+
+// Application state
+pub struct AppState {
+    repository: Repository,
+    iv_oracle: Arc<dyn IvSource>,
+    iv_policy: Arc<DefaultIvPolicy>,  // Fills the gaps of iv_oracle
+    rates: Arc<RateSource>,  // Risk-free rates by maturity
+    basis: Arc<BasisSource>,  // Carry quotes and marks are priced at
+    price_oracle: Arc<dyn PriceSource>,
+    mutiny_wallet: Arc<dyn WalletSource>,
+    pool_address: String,
+    network_wallets: HashMap<Network, Arc<dyn WalletSource>>,  // Of pools besides the default one
+    options_grid: GridConfig,
+    options_table_cache: ResponseCache<Vec<OptionsTableResponse>>,
+    rolling_metrics: Arc<RollingMetrics>,  // 24h volume and premium changes per product
+    price_guards: PriceGuards,
+    assets: Vec<Asset>,  // Underlyings open for trading
+    expiry_notice: ExpiryNoticeConfig,
+    position_limits: PositionLimits,
+    kyc: Option<KycConfig>,  // None unless KYC_LIMITS gates trade size
+    auth: AuthConfig,
+    jwt: Option<JwtConfig>,  // None unless JWT_SECRET enables session login
+    api_v1: DeprecationConfig,
+    margin_model: Arc<dyn MarginModel>,
+    margin_cache: Option<Arc<MarginCache>>,
+    events: EventBroadcast,  // Outbox events as delivered, for /ws/events
+    utilization_monitor: Option<UtilizationMonitorConfig>,
+    orderbook: OrderbookConfig,
+    quoting: QuotingConfig,
+    payments: PaymentConfig,
+    reservations: ReservationConfig,
+    fees: FeeSchedule,
+    lightning: Option<Arc<dyn LightningNode>>,
+    dlc: DlcConfig,
+    oracle_signer: Option<Arc<OracleSigner>>,  // None unless ORACLE_SIGNING_KEY enables attestations and DLCs
+    health: HealthConfig,
+    supervisor: Supervisor,
+    timeouts: UpstreamTimeouts,
+}
+
+
+impl AppState {
+    pub fn new(
+        repository: Repository,
+        iv_oracle: Arc<dyn IvSource>,
+        price_oracle: Arc<dyn PriceSource>,
+        mutiny_wallet: Arc<dyn WalletSource>,
+        pool_address: String,
+        options_grid: GridConfig,
+        options_table_cache_ttl: Duration,
+    ) -> Self {
+        Self {
+            repository,
+            iv_oracle,
+            iv_policy: Arc::new(DefaultIvPolicy::default()),
+            rates: Arc::new(RateSource::default()),
+            basis: Arc::new(BasisSource::default()),
+            price_oracle,
+            mutiny_wallet,
+            pool_address,
+            network_wallets: HashMap::new(),
+            options_grid,
+            options_table_cache: ResponseCache::new(options_table_cache_ttl),
+            rolling_metrics: Arc::new(RollingMetrics::default()),
+            price_guards: PriceGuards::default(),
+            assets: vec![Asset::Btc],
+            expiry_notice: ExpiryNoticeConfig::default(),
+            position_limits: PositionLimits::default(),
+            kyc: None,
+            auth: AuthConfig::default(),
+            jwt: None,
+            api_v1: DeprecationConfig::default(),
+            margin_model: Arc::new(MaxLossMargin),
+            margin_cache: None,
+            events: EventBroadcast::default(),
+            utilization_monitor: None,
+            orderbook: OrderbookConfig::default(),
+            quoting: QuotingConfig::default(),
+            payments: PaymentConfig::default(),
+            reservations: ReservationConfig::default(),
+            fees: FeeSchedule::default(),
+            lightning: None,
+            dlc: DlcConfig::default(),
+            oracle_signer: None,
+            health: HealthConfig::default(),
+            supervisor: Supervisor::default(),
+            timeouts: UpstreamTimeouts::default(),
+        }
+    }
+
+    /// Wallets the balances of pools on each network are read with. The default pool
+    /// always uses the wallet given to `new`.
+    pub fn with_network_wallets(mut self, network_wallets: HashMap<Network, Arc<dyn WalletSource>>) -> Self {
+        self.network_wallets = network_wallets;
+        self
+    }
+
+    /// 24h metrics rebuilt from the database; empty by default, counting trades from startup
+    pub fn with_rolling_metrics(mut self, rolling_metrics: Arc<RollingMetrics>) -> Self {
+        self.rolling_metrics = rolling_metrics;
+        self
+    }
+
+    /// Replace the default checks applied to spot prices before accepting a trade
+    pub fn with_price_guards(mut self, price_guards: PriceGuards) -> Self {
+        self.price_guards = price_guards;
+        self
+    }
+
+    /// Risk-free rate curve options are priced and margined at (flat at 0 by default)
+    pub fn with_rates(mut self, rates: Arc<RateSource>) -> Self {
+        self.rates = rates;
+        self
+    }
+
+    /// Perpetual funding or futures basis options are priced with (none by default)
+    pub fn with_basis(mut self, basis: Arc<BasisSource>) -> Self {
+        self.basis = basis;
+        self
+    }
+
+    /// Underlyings open for trading (BTC only by default)
+    pub fn with_assets(mut self, assets: Vec<Asset>) -> Self {
+        self.assets = assets;
+        self
+    }
+
+    /// Window before expiry in which contracts are flagged expiring_soon
+    pub fn with_expiry_notice(mut self, expiry_notice: ExpiryNoticeConfig) -> Self {
+        self.expiry_notice = expiry_notice;
+        self
+    }
+
+    /// Replace the default limits on contract, product and counterparty size
+    pub fn with_position_limits(mut self, position_limits: PositionLimits) -> Self {
+        self.position_limits = position_limits;
+        self
+    }
+
+    /// Margin positions with `margin_model` instead of the max-loss model
+    /// Cap the cumulative notional of accounts by KYC status
+    pub fn with_kyc(mut self, kyc: Option<KycConfig>) -> Self {
+        self.kyc = kyc;
+        self
+    }
+
+    /// What options without an IV are priced at (IV_DEFAULT by default)
+    pub fn with_iv_policy(mut self, iv_policy: Arc<DefaultIvPolicy>) -> Self {
+        self.iv_policy = iv_policy;
+        self
+    }
+
+    pub fn with_margin_model(mut self, margin_model: Arc<dyn MarginModel>) -> Self {
+        self.margin_model = margin_model;
+        self
+    }
+
+    /// Reuse the margin of position groups unchanged since the last margin run (off by default)
+    pub fn with_margin_cache(mut self, margin_cache: Option<Arc<MarginCache>>) -> Self {
+        self.margin_cache = margin_cache;
+        self
+    }
+
+    /// Feed the outbox poller publishes delivered events to, streamed by /ws/events
+    pub fn with_events(mut self, events: EventBroadcast) -> Self {
+        self.events = events;
+        self
+    }
+
+    pub fn events(&self) -> &EventBroadcast {
+        &self.events
+    }
+
+    /// Thresholds of the utilization monitor, reported by /riskStatus (none by default)
+    pub fn with_utilization_monitor(mut self, utilization_monitor: Option<UtilizationMonitorConfig>) -> Self {
+        self.utilization_monitor = utilization_monitor;
+        self
+    }
+
+    /// Price spread, size and lifetime of the pool's resting quotes
+    pub fn with_orderbook(mut self, orderbook: OrderbookConfig) -> Self {
+        self.orderbook = orderbook;
+        self
+    }
+
+    /// Spread and markups the pool quotes around the Black-Scholes mid (none by default)
+    pub fn with_quoting(mut self, quoting: QuotingConfig) -> Self {
+        self.quoting = quoting;
+        self
+    }
+
+    /// Whether and how buyers pay premiums on-chain before contracts open
+    pub fn with_payments(mut self, payments: PaymentConfig) -> Self {
+        self.payments = payments;
+        self
+    }
+
+    /// How long a trade may hold collateral between its risk check and its insert
+    pub fn with_reservations(mut self, reservations: ReservationConfig) -> Self {
+        self.reservations = reservations;
+        self
+    }
+
+    /// Fee schedule of accounts whose credit tier sets none (no fees by default)
+    pub fn with_fees(mut self, fees: FeeSchedule) -> Self {
+        self.fees = fees;
+        self
+    }
+
+    /// Node issuing invoices to buyers paying premiums over Lightning (none by default)
+    pub fn with_lightning(mut self, lightning: Option<Arc<dyn LightningNode>>) -> Self {
+        self.lightning = lightning;
+        self
+    }
+
+    /// Price precision of the DLC descriptors of contracts
+    pub fn with_dlc(mut self, dlc: DlcConfig) -> Self {
+        self.dlc = dlc;
+        self
+    }
+
+    /// Key settlement prices are attested and DLC events announced with; without one both
+    /// are unavailable
+    pub fn with_oracle_signer(mut self, oracle_signer: Option<Arc<OracleSigner>>) -> Self {
+        self.oracle_signer = oracle_signer;
+        self
+    }
+
+    fn oracle_signer(&self) -> Result<&OracleSigner, ApiError> {
+        self.oracle_signer.as_deref().ok_or_else(|| {
+            ApiError::ExternalApiError("the price oracle has no signing key, set ORACLE_SIGNING_KEY".to_string())
+                .with_code(ErrorCode::OracleDisabled)
+        })
+    }
+
+    pub fn with_health(mut self, health: HealthConfig) -> Self {
+        self.health = health;
+        self
+    }
+
+    /// Supervisor of the background workers, whose state /health reports
+    pub fn with_supervisor(mut self, supervisor: Supervisor) -> Self {
+        self.supervisor = supervisor;
+        self
+    }
+
+    /// Deadlines of the price oracle and wallet calls made by handlers
+    pub fn with_upstream_timeouts(mut self, timeouts: UpstreamTimeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Let requests without credentials through as the anonymous actor (off by default)
+    pub fn with_auth(mut self, auth: AuthConfig) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    pub(crate) fn auth(&self) -> AuthConfig {
+        self.auth
+    }
+
+    /// Issue and accept session tokens for users logging in at /auth/login
+    pub fn with_jwt(mut self, jwt: Option<JwtConfig>) -> Self {
+        self.jwt = jwt;
+        self
+    }
+
+    pub(crate) fn jwt(&self) -> Option<&JwtConfig> {
+        self.jwt.as_ref()
+    }
+
+    /// Deprecation and sunset dates announced on v1 responses (none by default)
+    pub fn with_api_v1(mut self, api_v1: DeprecationConfig) -> Self {
+        self.api_v1 = api_v1;
+        self
+    }
+
+    pub(crate) fn api_v1(&self) -> &DeprecationConfig {
+        &self.api_v1
+    }
+
+    pub(crate) fn repository(&self) -> &Repository {
+        &self.repository
+    }
+
+    // The IV surface with the default IV policy
+    fn ivs(&self) -> IvResolver {
+        IvResolver::new(self.iv_oracle.clone(), self.iv_policy.clone())
+    }
+
+    // The current risk-free rate curve
+    pub(crate) fn rates(&self) -> Arc<RateCurve> {
+        self.rates.curve()
+    }
+
+    // Annualized carry of options on `asset` expiring at `expires`, while PRICING_BASIS provides one
+    pub(crate) fn carry(&self, asset: Asset, expires: i64, now: i64) -> Option<f64> {
+        self.basis.carry(asset).map(|curve| curve.rate_to(expires, now))
+    }
+
+    // Spot the options on `asset` expiring at `expires` are priced at with rate `r`: the present
+    // value of the forward under the basis, otherwise spot itself
+    pub(crate) fn pricing_spot(&self, asset: Asset, spot_price: f64, r: f64, expires: i64, now: i64) -> f64 {
+        match self.carry(asset, expires, now) {
+            Some(carry) => pricing::forward_spot(spot_price, r, carry, year_fraction(expires, now)),
+            None => spot_price,
+        }
+    }
+
+    fn risk_manager(&self, risk_margin: f64) -> RiskManager {
+        RiskManager::new(risk_margin)
+            .with_max_contract_quantity(self.position_limits.max_contract_quantity)
+            .with_margin_model(self.margin_model.clone())
+            .with_margin_cache(self.margin_cache.clone())
+    }
+
+    fn check_asset(&self, asset: Asset) -> Result<(), ApiError> {
+        if self.assets.contains(&asset) {
+            Ok(())
+        } else {
+            Err(ApiError::ValidationError(format!("{} options are not enabled on this server", asset))
+                .with_code(ErrorCode::AssetNotEnabled)
+                .with_details(json!({"asset": asset})))
+        }
+    }
+
+    pub(crate) async fn spot_price(&self, asset: Asset) -> Result<f64, ApiError> {
+        deadline("Price oracle", self.timeouts.price_oracle, async {
+            self.price_oracle
+                .get_price(asset)
+                .await
+                .map_err(|e| ApiError::PriceOracleError(e.to_string()))
+        })
+        .await
+    }
+
+    // Spot of each of `assets`, refusing stale, thin or jumpy prices
+    async fn guarded_spot_prices(&self, assets: &[Asset]) -> Result<HashMap<Asset, f64>, ApiError> {
+        let mut spot_prices = HashMap::new();
+        for &asset in assets {
+            let quote = deadline("Price oracle", self.timeouts.price_oracle, async {
+                self.price_oracle
+                    .get_asset_price_quote(asset)
+                    .await
+                    .map_err(|e| ApiError::PriceOracleError(e.to_string()))
+            })
+            .await?;
+            self.price_guards.check(asset, &quote)?;
+            spot_prices.insert(asset, quote.price);
+        }
+        Ok(spot_prices)
+    }
+
+    // Adds the spot of BTC (collateral) and of every underlying in `contracts`
+    // that `spot_prices` doesn't already have
+    async fn book_spot_prices(
+        &self,
+        contracts: &[Contract],
+        mut spot_prices: HashMap<Asset, f64>,
+    ) -> Result<HashMap<Asset, f64>, ApiError> {
+        let assets = std::iter::once(Asset::Btc).chain(contracts.iter().map(|c| c.underlying));
+        for asset in assets {
+            if let std::collections::hash_map::Entry::Vacant(entry) = spot_prices.entry(asset) {
+                entry.insert(self.spot_price(asset).await?);
+            }
+        }
+        Ok(spot_prices)
+    }
+
+    // Helper method to get pool balance in BTC
+    pub fn pool_address(&self) -> &str {
+        &self.pool_address
+    }
+
+    /// The pool configured by POOL_ADDRESS
+    pub fn default_pool(&self) -> Pool {
+        Pool::default_from_env(&self.pool_address)
+    }
+
+    /// Pool `id`, NOT_FOUND for unknown ids
+    pub async fn pool(&self, id: i64) -> Result<Pool, ApiError> {
+        if id == pools::DEFAULT_POOL_ID {
+            return Ok(self.default_pool());
+        }
+        self.repository
+            .run(move |conn| pools::load_pool(conn, id))
+            .await?
+            .ok_or_else(|| ApiError::NotFound(format!("Pool {} not found", id)))
+    }
+
+    /// All pools, the default pool first
+    pub async fn pools(&self) -> Result<Vec<Pool>, ApiError> {
+        let mut all = vec![self.default_pool()];
+        all.extend(self.repository.run(|conn| pools::load_pools(conn)).await?);
+        Ok(all)
+    }
+
+    /// Margin of the open book of every pool against the collateral of all pools
+    pub async fn collateral_utilization(&self) -> Result<Utilization, ApiError> {
+        let now = Utc::now().timestamp();
+
+        let mut spot_prices = HashMap::new();
+        let (mut margin_required_usd, mut total_collateral_usd) = (0.0, 0.0);
+        for pool in self.pools().await? {
+            let balance_btc = self.pool_balance_btc(&pool).await?;
+            let book = self.repository.pool_active_contracts(pool.id, now).await?;
+            spot_prices = self.book_spot_prices(&book, spot_prices).await?;
+            total_collateral_usd += balance_btc * spot_prices[&Asset::Btc] * pool.collateral_rate;
+            let risk_manager = self.risk_manager(pool.risk_margin);
+            margin_required_usd += book_risk_blocking(self, &risk_manager, book, &spot_prices).await?;
+        }
+        Ok(Utilization::new(margin_required_usd, total_collateral_usd))
+    }
+
+    pub async fn get_pool_balance_btc(&self) -> Result<f64, ApiError> {
+        self.pool_balance_btc(&self.default_pool()).await
+    }
+
+    pub async fn pool_balance_btc(&self, pool: &Pool) -> Result<f64, ApiError> {
+        let wallet = if pool.id == pools::DEFAULT_POOL_ID {
+            &self.mutiny_wallet
+        } else {
+            self.network_wallets
+                .get(&pool.network)
+                .ok_or_else(|| ApiError::ExternalApiError(format!("No wallet for the {} network", pool.network)))?
+        };
+        let wallet_balance = deadline("Mutiny wallet", self.timeouts.wallet, async {
+            wallet
+                .get_wallet_balance(&pool.address)
+                .await
+                .map_err(|e| ApiError::ExternalApiError(format!("Failed to get pool balance: {}", e)))
+        })
+        .await?;
+        
+        // Convert satoshis to BTC
+        Ok(MutinyWallet::satoshis_to_btc(wallet_balance.total_balance))
+    }
+}
+
+// GET /ws/price - WebSocket pushing a PriceUpdate JSON message for every new BTC price.
+// The current price is sent on connect; sources without push updates only send that.
+async fn ws_price(
+    req: HttpRequest,
+    body: web::Payload,
+    state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (response, mut session, mut messages) = actix_ws::handle(&req, body)?;
+    let mut updates = state.price_oracle.subscribe();
+    let initial = state.spot_price(Asset::Btc).await.ok().map(|price| PriceUpdate {
+        price,
+        timestamp: Utc::now().timestamp(),
+        stale: state.price_oracle.is_stale(),
+    });
+
+    actix_web::rt::spawn(async move {
+        if let Some(update) = initial {
+            if session.text(serde_json::to_string(&update).unwrap_or_default()).await.is_err() {
+                return;
+            }
+        }
+
+        loop {
+            tokio::select! {
+                update = async { updates.as_mut()?.recv().await.ok() }, if updates.is_some() => {
+                    match update {
+                        Some(update) => {
+                            if session.text(serde_json::to_string(&update).unwrap_or_default()).await.is_err() {
+                                return;
+                            }
+                        }
+                        // Lagged receivers resubscribe and pick up from the next price
+                        None => updates = state.price_oracle.subscribe(),
+                    }
+                }
+                message = messages.recv() => match message {
+                    Some(Ok(actix_ws::Message::Ping(bytes))) => {
+                        if session.pong(&bytes).await.is_err() {
+                            return;
+                        }
+                    }
+                    Some(Ok(actix_ws::Message::Close(reason))) => {
+                        let _ = session.close(reason).await;
+                        return;
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(_)) | None => return,
+                },
+            }
+        }
+    });
+
+    Ok(response)
+}
+
+// GET /ws/events - WebSocket pushing every outbox event (trade.created, contract.settled, ...)
+// as a JSON message once the poller has delivered it, with the outbox id to drop repeats by
+async fn ws_events(
+    req: HttpRequest,
+    body: web::Payload,
+    state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (response, mut session, mut messages) = actix_ws::handle(&req, body)?;
+    let mut events = state.events.subscribe();
+
+    actix_web::rt::spawn(async move {
+        loop {
+            tokio::select! {
+                event = events.recv() => match event {
+                    Ok(event) => {
+                        if session.text(serde_json::to_string(&event).unwrap_or_default()).await.is_err() {
+                            return;
+                        }
+                    }
+                    // Lagged receivers skip to the oldest event still buffered
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => return,
+                },
+                message = messages.recv() => match message {
+                    Some(Ok(actix_ws::Message::Ping(bytes))) => {
+                        if session.pong(&bytes).await.is_err() {
+                            return;
+                        }
+                    }
+                    Some(Ok(actix_ws::Message::Close(reason))) => {
+                        let _ = session.close(reason).await;
+                        return;
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(_)) | None => return,
+                },
+            }
+        }
+    });
+
+    Ok(response)
+}
+
+// GET / - Health check endpoint: probes every dependency and reports the background workers,
+// 503 when the database or the price oracle is down so load balancers stop routing here
+async fn health_check(state: web::Data<Arc<AppState>>) -> Result<impl Responder, ApiError> {
+    let config = &state.health;
+    let (database, price_oracle, wallet) = futures::join!(
+        health::check_database(&state.repository, config),
+        health::check_price_oracle(state.price_oracle.as_ref(), state.price_guards.max_age, config),
+        health::check_wallet(state.mutiny_wallet.as_ref(), &state.pool_address, config),
+    );
+    let dependencies = Dependencies {
+        database,
+        price_oracle,
+        iv_oracle: health::check_iv_oracle(state.iv_oracle.as_ref(), config),
+        wallet,
+    };
+    let tasks = state.supervisor.tasks();
+    let status = health::overall_status(&dependencies, &tasks);
+    // Unknown while the database is down
+    let trading_state = match dependencies.database.status {
+        DependencyStatus::Down => None,
+        _ => state.repository.run(|conn| Ok(trading_state::load(conn)?)).await.ok().map(|s| s.state),
+    };
+
+    let mut response = match status {
+        OverallStatus::Unhealthy => HttpResponse::ServiceUnavailable(),
+        _ => HttpResponse::Ok(),
+    };
+    Ok(response.json(serde_json::json!({
+        "status": status,
+        "service": "BTC Options API",
+        "version": "1.0.0",
+        "price_stale": state.price_oracle.is_stale(),
+        "trading_state": trading_state,
+        "dependencies": dependencies,
+        "tasks": tasks
+    })))
+}
+
+// GET /tradingState - Whether the venue accepts new positions, and why not
+async fn get_trading_state(state: web::Data<Arc<AppState>>) -> Result<impl Responder, ApiError> {
+    let status = state.repository.run(|conn| Ok(trading_state::load(conn)?)).await?;
+    Ok(HttpResponse::Ok().json(status))
+}
+
+// GET /riskStatus - Trading state, collateral utilization and the thresholds that move it
+async fn get_risk_status(state: web::Data<Arc<AppState>>) -> Result<impl Responder, ApiError> {
+    let utilization = state.collateral_utilization().await?;
+    let trading = state.repository.run(|conn| Ok(trading_state::load(conn)?)).await?;
+    Ok(HttpResponse::Ok().json(RiskStatusResponse {
+        trading,
+        utilization,
+        utilization_monitor: state.utilization_monitor,
+    }))
+}
+
+// POST /admin/tradingState - Open, reduce-only or halt trading
+async fn post_trading_state(
+    req: HttpRequest,
+    request: web::Json<TradingStateRequest>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    let actor = require_role(&req, &state, Role::Admin).await?;
+    let TradingStateRequest { state: trading_state, reason } = request.into_inner();
+    let status = state
+        .repository
+        .run(move |conn| Ok(trading_state::set(conn, trading_state, reason.as_deref(), &actor, false)?))
+        .await?;
+    println!("🚦 Trading state set to {} by {}", status.state, status.updated_by.as_deref().unwrap_or_default());
+    Ok(HttpResponse::Ok().json(status))
+}
+
+// POST /admin/iv/refresh - Refresh the IV surfaces now rather than at the next scheduled refresh
+async fn post_iv_refresh(req: HttpRequest, state: web::Data<Arc<AppState>>) -> Result<impl Responder, ApiError> {
+    let actor = require_role(&req, &state, Role::Admin).await?;
+    state
+        .iv_oracle
+        .refresh()
+        .await
+        .map_err(|e| ApiError::ExternalApiError(format!("IV refresh failed: {}", e)))?;
+    println!("📊 IV surfaces refreshed on demand by {}", actor);
+    Ok(HttpResponse::Ok().json(state.iv_oracle.status()))
+}
+
+// GET /admin/iv/status - Size, last refresh and last error of each IV surface
+async fn get_iv_status(req: HttpRequest, state: web::Data<Arc<AppState>>) -> Result<impl Responder, ApiError> {
+    require_role(&req, &state, Role::Admin).await?;
+    Ok(HttpResponse::Ok().json(state.iv_oracle.status()))
+}
+
+#[derive(Serialize)]
+struct CreditTiersResponse {
+    tiers: Vec<credit_tiers::CreditTier>,
+    assignments: Vec<credit_tiers::TierAssignment>,
+}
+
+#[derive(Serialize)]
+struct AccountCreditTierResponse {
+    account: String,
+    assigned_tier: Option<String>,                     // None when the account uses the default tier
+    effective_tier: Option<credit_tiers::CreditTier>,  // Tier whose limits apply, if any
+}
+
+#[derive(Deserialize)]
+struct AccountCreditTierRequest {
+    tier: Option<String>,  // None returns the account to the default tier
+}
+
+// GET /admin/creditTiers - Credit tiers and the accounts assigned to them
+async fn get_credit_tiers(req: HttpRequest, state: web::Data<Arc<AppState>>) -> Result<impl Responder, ApiError> {
+    require_role(&req, &state, Role::Admin).await?;
+    let response = state
+        .repository
+        .run(|conn| {
+            Ok(CreditTiersResponse {
+                tiers: credit_tiers::load_tiers(conn)?,
+                assignments: credit_tiers::load_assignments(conn)?,
+            })
+        })
+        .await?;
+    Ok(HttpResponse::Ok().json(response))
+}
+
+// PUT /admin/creditTiers/{name} - Create a credit tier or replace its limits
+async fn put_credit_tier(
+    req: HttpRequest,
+    path: web::Path<String>,
+    terms: web::Json<credit_tiers::CreditTierTerms>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    let actor = require_role(&req, &state, Role::Admin).await?;
+    let name = path.into_inner();
+    let terms = terms.into_inner();
+    let now = Utc::now().timestamp();
+    let tier = state
+        .repository
+        .run(move |conn| credit_tiers::set_tier(conn, &name, terms, &actor, now))
+        .await?;
+    println!("🏷️  Credit tier {} set", tier.name);
+    Ok(HttpResponse::Ok().json(tier))
+}
+
+// DELETE /admin/creditTiers/{name} - Delete a credit tier no account is assigned to
+async fn delete_credit_tier(
+    req: HttpRequest,
+    path: web::Path<String>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    let actor = require_role(&req, &state, Role::Admin).await?;
+    let name = path.into_inner();
+    state.repository.run(move |conn| credit_tiers::delete_tier(conn, &name, &actor)).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// GET /admin/accounts/{account}/creditTier - Tier assigned to an account and the tier that applies
+async fn get_account_credit_tier(
+    req: HttpRequest,
+    path: web::Path<String>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    require_role(&req, &state, Role::Admin).await?;
+    let account = path.into_inner();
+    let response = state.repository.run(move |conn| account_credit_tier(conn, account)).await?;
+    Ok(HttpResponse::Ok().json(response))
+}
+
+// PUT /admin/accounts/{account}/creditTier - Assign an account to a tier, or to the default tier with null
+async fn put_account_credit_tier(
+    req: HttpRequest,
+    path: web::Path<String>,
+    request: web::Json<AccountCreditTierRequest>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    let actor = require_role(&req, &state, Role::Admin).await?;
+    let account = path.into_inner();
+    let tier = request.into_inner().tier;
+    let now = Utc::now().timestamp();
+    let response = state
+        .repository
+        .run(move |conn| {
+            credit_tiers::assign_tier(conn, &account, tier.as_deref(), &actor, now)?;
+            account_credit_tier(conn, account)
+        })
+        .await?;
+    println!(
+        "🏷️  Account {} assigned to credit tier {}",
+        response.account,
+        response.assigned_tier.as_deref().unwrap_or(credit_tiers::DEFAULT_TIER)
+    );
+    Ok(HttpResponse::Ok().json(response))
+}
+
+fn account_credit_tier(conn: &rusqlite::Connection, account: String) -> Result<AccountCreditTierResponse, ApiError> {
+    Ok(AccountCreditTierResponse {
+        assigned_tier: credit_tiers::load_assignment(conn, &account)?.map(|assignment| assignment.tier),
+        effective_tier: credit_tiers::account_tier(conn, &account)?,
+        account,
+    })
+}
+
+#[derive(Serialize)]
+struct AccountKycResponse {
+    #[serde(flatten)]
+    kyc: kyc::AccountKyc,
+    cumulative_notional_usd: f64,              // Bought so far, at spot at trade
+    max_cumulative_notional_usd: Option<f64>,  // Cap of the status; None if unlimited or KYC is off
+}
+
+#[derive(Deserialize)]
+struct AccountKycRequest {
+    kyc_status: KycStatus,
+}
+
+// GET /admin/accounts/{account}/kyc - KYC status of an account and how much it may still trade
+async fn get_account_kyc(
+    req: HttpRequest,
+    path: web::Path<String>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    require_role(&req, &state, Role::Admin).await?;
+    let account = path.into_inner();
+    let kyc = state.kyc.clone();
+    let response = state.repository.run(move |conn| account_kyc(conn, &account, kyc.as_ref())).await?;
+    Ok(HttpResponse::Ok().json(response))
+}
+
+// PUT /admin/accounts/{account}/kyc - Record the outcome of an account's verification
+async fn put_account_kyc(
+    req: HttpRequest,
+    path: web::Path<String>,
+    request: web::Json<AccountKycRequest>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    let actor = require_role(&req, &state, Role::Admin).await?;
+    let account = path.into_inner();
+    let status = request.into_inner().kyc_status;
+    let now = Utc::now().timestamp();
+    let kyc = state.kyc.clone();
+    let response = state
+        .repository
+        .run(move |conn| {
+            kyc::set_status(conn, &account, status, &actor, now)?;
+            account_kyc(conn, &account, kyc.as_ref())
+        })
+        .await?;
+    println!("🪪 KYC status of account {} set to {}", response.kyc.account, response.kyc.kyc_status);
+    Ok(HttpResponse::Ok().json(response))
+}
+
+fn account_kyc(conn: &rusqlite::Connection, account: &str, config: Option<&KycConfig>) -> Result<AccountKycResponse, ApiError> {
+    let kyc = kyc::load_account(conn, account)?;
+    Ok(AccountKycResponse {
+        cumulative_notional_usd: kyc::cumulative_notional_usd(conn, account)?,
+        max_cumulative_notional_usd: config.and_then(|config| config.max_notional_usd(kyc.kyc_status)),
+        kyc,
+    })
+}
+
+#[derive(Deserialize)]
+struct LoginRequest {
+    username: String,
+    password: String,
+}
+
+// POST /auth/login - Exchange a username and password for a session token
+async fn post_login(request: web::Json<LoginRequest>, state: web::Data<Arc<AppState>>) -> Result<impl Responder, ApiError> {
+    let jwt = state
+        .jwt
+        .clone()
+        .ok_or_else(|| ApiError::NotFound("session login is not enabled".to_string()))?;
+    let LoginRequest { username, password } = request.into_inner();
+    let user = state
+        .repository
+        .run(move |conn| auth::authenticate(conn, &username, &password))
+        .await?
+        .ok_or_else(|| ApiError::Unauthorized("invalid username or password".to_string()))?;
+    let session = jwt.issue(&user.username, user.role, Utc::now().timestamp())?;
+    println!("🔐 {} logged in as {}", user.username, user.role);
+    Ok(HttpResponse::Ok().json(session))
+}
+
+// GET /auth/session - Claims of the session token the request carries
+async fn get_session(req: HttpRequest) -> Result<impl Responder, ApiError> {
+    let claims = req
+        .extensions()
+        .get::<Claims>()
+        .cloned()
+        .ok_or_else(|| ApiError::Unauthorized("missing session token".to_string()))?;
+    Ok(HttpResponse::Ok().json(claims))
+}
+
+// Reject the request unless its session token has at least `role`, or it carries a valid
+// X-API-Key header. API keys have every role. Returns the actor recorded in the audit log
+// for the request: the user of the session or the name of the key.
+pub(crate) async fn require_role(req: &HttpRequest, state: &AppState, role: Role) -> Result<String, ApiError> {
+    let claims = req.extensions().get::<Claims>().cloned();
+    match claims {
+        Some(claims) if claims.role >= role => Ok(claims.sub),
+        Some(claims) => Err(ApiError::Forbidden(format!("{} role required, {} has {}", role, claims.sub, claims.role))
+            .with_details(json!({"required_role": role, "role": claims.role}))),
+        None => require_api_key(req, state).await,
+    }
+}
+
+// Reject the request unless it carries a valid X-API-Key header, even before any key has
+// been issued with `optadmin rotate-api-key`. Only AUTH_DISABLED lets requests without one
+// through, as the anonymous actor.
+// Returns the actor recorded in the audit log for the request.
+async fn require_api_key(req: &HttpRequest, state: &AppState) -> Result<String, ApiError> {
+    let key = req
+        .headers()
+        .get(api_keys::API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
+
+    let actor = state.repository.api_key_actor(key).await?;
+    state
+        .auth
+        .actor(actor)
+        .ok_or_else(|| ApiError::Unauthorized(format!("missing or invalid {} header", api_keys::API_KEY_HEADER)))
+}
+
+// Margin of a book that may span several underlyings, each at its own spot and IV surface
+fn book_risk(
+    risk_manager: &RiskManager,
+    contracts: &[Contract],
+    spot_prices: &HashMap<Asset, f64>,
+    rates: &RateCurve,
+    ivs: &IvResolver,
+) -> Result<f64, ApiError> {
+    let iv_oracle_closure = |asset: Asset, side_str: &str, strike: f64, expire: &str| {
+        Some(ivs.lookup(asset, side_str, strike, expire).value)
+    };
+    risk_manager
+        .calculate_multi_asset_portfolio_risk(contracts, spot_prices, rates, &iv_oracle_closure)
+        .ok_or_else(|| ApiError::PriceOracleError("missing spot price for an underlying in the book".to_string()))
+}
+
+// Net Greeks of `contracts` sold by the pool, each at its IV as in margining
+fn book_greeks(
+    contracts: &[Contract],
+    spot_prices: &HashMap<Asset, f64>,
+    rates: &RateCurve,
+    ivs: &IvResolver,
+    now: i64,
+) -> Result<BookGreeks, ApiError> {
+    let mut greeks = BookGreeks::default();
+    for contract in contracts {
+        let spot_price = *spot_prices
+            .get(&contract.underlying)
+            .ok_or_else(|| ApiError::PriceOracleError("missing spot price for an underlying in the book".to_string()))?;
+        let iv = ivs.option_iv(contract.underlying, contract.side, contract.strike_price, contract.expires).value;
+        let t = year_fraction(contract.expires, now);
+        let rate = rates.rate_to(contract.expires, now);
+        let option = pricing::option_greeks(&contract.side, spot_price, contract.strike_price, rate, iv, t);
+        greeks.add(contract, &option, spot_price);
+    }
+    Ok(greeks)
+}
+
+// book_risk on the blocking pool, so margining a large book does not hold up an async worker
+async fn book_risk_blocking(
+    state: &AppState,
+    risk_manager: &RiskManager,
+    contracts: Vec<Contract>,
+    spot_prices: &HashMap<Asset, f64>,
+) -> Result<f64, ApiError> {
+    let (risk_manager, spot_prices, ivs, rates) = (risk_manager.clone(), spot_prices.clone(), state.ivs(), state.rates());
+    tokio::task::spawn_blocking(move || book_risk(&risk_manager, &contracts, &spot_prices, &rates, &ivs))
+        .await
+        .map_err(|e| ApiError::DatabaseError(format!("Risk task failed: {}", e)))?
+}
+
+// POST /contract - Create new contract
+async fn post_contract(
+    req: HttpRequest,
+    request: web::Json<ContractRequest>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    let actor = require_role(&req, &state, Role::Trader).await?;
+    let ContractRequest { contract, premium_currency, exercise_style, payment_method, referral_code } = request.into_inner();
+    let accepted = accept_contract(
+        &state,
+        pools::DEFAULT_POOL_ID,
+        actor,
+        contract,
+        premium_currency,
+        exercise_style,
+        payment_method,
+        referral_code,
+        None,
+    )
+    .await?;
+    Ok(contract_response(accepted))
+}
+
+// 200 with the risk check for an open contract, 202 with the payment to make for a pending one
+fn contract_response(accepted: AcceptedContract) -> HttpResponse {
+    let AcceptedContract { contract_id, payment, fee, risk } = accepted;
+    match payment {
+        // The contract opens once the buyer's payment confirms
+        Some(payment) => HttpResponse::Accepted().json(PendingContractResponse { contract_id, payment, fee, risk }),
+        None => HttpResponse::Ok().json(ContractCreatedResponse { contract_id, fee, risk }),
+    }
+}
+
+// POST /pools/{id}/contract - Create a contract sold from pool `id`
+async fn post_pool_contract(
+    req: HttpRequest,
+    path: web::Path<i64>,
+    request: web::Json<ContractRequest>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    let actor = require_role(&req, &state, Role::Trader).await?;
+    let ContractRequest { contract, premium_currency, exercise_style, payment_method, referral_code } = request.into_inner();
+    let accepted = accept_contract(
+        &state,
+        path.into_inner(),
+        actor,
+        contract,
+        premium_currency,
+        exercise_style,
+        payment_method,
+        referral_code,
+        None,
+    )
+    .await?;
+    Ok(contract_response(accepted))
+}
+
+// Check a new contract against the trading state, guarded prices, collateral and position
+// limits, and insert it. A contract taken from a resting quote fills `resting_quote` in the
+// same transaction. The contract is sold from, and margined against the book of, pool `pool_id`,
+// and attributed to `referral_code` when one is given.
+// Returns the contract id, the payment to make when premiums must be paid, and the risk check.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn accept_contract(
+    state: &AppState,
+    pool_id: i64,
+    actor: String,
+    mut contract: Contract,
+    premium_currency: QuoteCurrency,
+    exercise_style: ExerciseStyle,
+    payment_method: PaymentMethod,
+    referral_code: Option<String>,
+    resting_quote: Option<i64>,
+) -> Result<AcceptedContract, ApiError> {
+    // Every contract sells a new option from the pool, so it needs an open venue
+    state.repository.run(|conn| Ok(trading_state::load(conn)?)).await?.state.check_open_position()?;
+    let pool = state.pool(pool_id).await?;
+
+    // Log incoming contract request
+    println!("📥 POST /contract request:");
+    println!("   Underlying: {}", contract.underlying);
+    println!("   Side: {:?}", contract.side);
+    println!("   Strike: ${:.2}", contract.strike_price);
+    println!("   Quantity: {:.8} {}", contract.quantity, contract.underlying);
+    println!("   Premium: {} {}", premium_currency.format(contract.premium), premium_currency);
+    println!("   Expires: {}", contract.expires);
+    
+    // Validation
+    let now = Utc::now().timestamp();
+    if contract.expires <= now {
+        eprintln!("❌ Contract validation failed: expiration date ({}) is not in the future (now: {})", contract.expires, now);
+        return Err(ApiError::ValidationError("Contract expiration date must be in the future.".to_string())
+            .with_code(ErrorCode::InvalidExpiry)
+            .with_details(json!({"expires": contract.expires, "now": now})));
+    }
+    state.check_asset(contract.underlying)?;
+    // On the strike tick of the underlying, so float noise does not make a product of its own
+    contract.strike_price = strikes::deployment().validate(contract.underlying, contract.strike_price)?;
+    // Whole satoshis between the minimum and maximum trade size, stored exactly as validated
+    contract.quantity = sats_to_btc(validation::trade_quantity_sats(contract.quantity, &state.position_limits)?);
+    if state.payments.required && payment_method == PaymentMethod::Lightning && state.lightning.is_none() {
+        return Err(ApiError::ValidationError("Lightning payments are not available".to_string())
+            .with_code(ErrorCode::PaymentMethodUnavailable)
+            .with_details(json!({"payment_method": "lightning"})));
+    }
+    // The payment watcher follows the chain of the default pool only
+    if state.payments.required && payment_method == PaymentMethod::Onchain && pool.network != state.default_pool().network {
+        return Err(ApiError::ValidationError(format!("On-chain premium payments are not available on {}", pool.network))
+            .with_code(ErrorCode::PaymentMethodUnavailable)
+            .with_details(json!({"payment_method": "onchain", "pool_id": pool.id})));
+    }
+    let referral_code = referral_code.as_deref().map(referrals::normalize_code).transpose()?;
+
+    // Get collateral parameters
+    let collateral_rate = pool.collateral_rate;
+
+    // Get real pool balance from Mutiny wallet (actual BTC balance from blockchain)
+    let pool_qty: f64 = state.pool_balance_btc(&pool).await?;
+
+    // Refuse to trade on a stale, thin or jumpy price of the underlying or of BTC,
+    // which values the pool collateral
+    let spot_prices = state.guarded_spot_prices(&[Asset::Btc, contract.underlying]).await?;
+    // Open and reserved contracts on other underlyings are margined at their current spot
+    let mut book_contracts = state.repository.pool_active_contracts(pool.id, now).await?;
+    book_contracts.extend(state.repository.pool_reserved_contracts(pool.id, now).await?);
+    let spot_prices = state.book_spot_prices(&book_contracts, spot_prices).await?;
+    let btc_price = spot_prices[&Asset::Btc];
+    let spot_price = spot_prices[&contract.underlying];
+
+    // Premiums quoted in USD or USDT are converted to BTC at the guarded BTC price,
+    // which is recorded with the contract
+    let quote = PremiumQuote::new(premium_currency, contract.premium, btc_price);
+    contract.premium = quote.premium_btc();
+    if premium_currency != QuoteCurrency::Btc {
+        println!("   Premium in BTC: {:.8} (at ${:.2})", contract.premium, btc_price);
+    }
+
+    // Initialize risk manager
+    let rates = state.rates();
+    
+    let risk_manager = state.risk_manager(pool.risk_margin);
+    
+    // Get IV for the new contract
+    let time_to_expiry = year_fraction(contract.expires, now);
+    let risk_free_rate = rates.rate_to(contract.expires, now);
+    let side_str = match contract.side {
+        OptionSide::Call => "C",
+        OptionSide::Put => "P",
+    };
+    let expire_timestamp_ms = (contract.expires * 1000).to_string();
+    // Recorded with the contract, so trades priced off a default IV can be told apart
+    let iv_quote = state.ivs().lookup(contract.underlying, side_str, contract.strike_price, &expire_timestamp_ms);
+    state.iv_policy.check_trade(&iv_quote, contract.underlying, contract.side, contract.strike_price, contract.expires)?;
+    let iv = iv_quote.value;
+    
+    // Sanity check the IV against 7d realized vol from spot history
+    let realized_vol_7d = state.repository.realized_vol(contract.underlying, "7d").await?.close_to_close;
+    if !risk_manager.is_iv_consistent_with_realized(iv, realized_vol_7d) {
+        eprintln!("⚠️  IV sanity check: implied vol {:.4} is far below 7d realized vol {:.4}",
+            iv, realized_vol_7d.unwrap_or(0.0));
+    }
+    
+    // Recorded with the contract for later slippage and edge analysis
+    let pricing_spot = state.pricing_spot(contract.underlying, spot_price, risk_free_rate, contract.expires, now);
+    let mark_premium_usd = pricing::option_price(&contract.side, pricing_spot, contract.strike_price, risk_free_rate, iv, time_to_expiry);
+    let snapshot = TradeSnapshot { spot_price, iv, iv_source: iv_quote.source, mark_premium: mark_premium_usd / btc_price };
+
+    // Check the contract against the active book and reserve the collateral it takes, so
+    // concurrent requests, on this instance or another, cannot both pass the collateral check
+    let ivs = state.ivs();
+    let position_limits = state.position_limits.clone();
+    let kyc = state.kyc.clone();
+    let counterparty = actor.clone();
+    let total_collateral_usd = pool_qty * btc_price * collateral_rate;
+    let checked_contract = contract.clone();
+    let fee_schedule = state.fees;
+    let (reservation, (margin, book_margin_usd, fee)) = state
+        .repository
+        .reserve_collateral(pool.id, contract.clone(), actor.clone(), now, state.reservations.ttl, move |conn, book| {
+            let contract = &checked_contract;
+            let existing_contracts = book.contracts();
+            credit_tiers::check_account(conn, &counterparty, contract, &book.counterparty, &spot_prices)?;
+            if let Some(kyc) = &kyc {
+                kyc.check(conn, &counterparty, contract.quantity * spot_price)?;
+            }
+
+            check_collateral_and_limits(
+                &risk_manager,
+                &ivs,
+                &position_limits,
+                contract,
+                &counterparty,
+                &existing_contracts,
+                &book.counterparty,
+                &spot_prices,
+                CollateralTerms { total_collateral_usd, rates: rates.clone(), iv, time_to_expiry, now },
+            )?;
+
+            // The margin the contract adds, and what the open contracts leave of the pool
+            let existing_risk = book_risk(&risk_manager, &existing_contracts, &spot_prices, &rates, &ivs)?;
+            let mut with_contract = existing_contracts;
+            with_contract.push(contract.clone());
+            let new_risk = book_risk(&risk_manager, &with_contract, &spot_prices, &rates, &ivs)?;
+            let open_risk = book_risk(&risk_manager, &book.open, &spot_prices, &rates, &ivs)?;
+            let margin = ReservedMargin {
+                margin_usd: (new_risk - existing_risk).max(0.0),
+                available_usd: total_collateral_usd - open_risk,
+            };
+            // Charged by the counterparty's credit tier as it stands at the check
+            let premium_sats = btc_to_sats(contract.premium * contract.quantity);
+            let fee = fees::trade_fee(conn, &counterparty, premium_sats, btc_price, &fee_schedule)?;
+            Ok((margin, (margin, new_risk, fee)))
+        })
+        .await?;
+    let risk = TradeRisk {
+        pool_id: pool.id,
+        margin_consumed_usd: margin.margin_usd,
+        remaining_collateral_usd: total_collateral_usd - book_margin_usd,
+        pool_utilization_percent: Utilization::new(book_margin_usd, total_collateral_usd).utilization_percent,
+        spot_price,
+        btc_price,
+        iv,
+        iv_source: snapshot.iv_source,
+    };
+
+    // The collateral is held from here on, and released if the trade falls through
+    let inserted = insert_reserved_trade(
+        state, reservation, &pool, contract.clone(), quote, snapshot, &fee, exercise_style, payment_method, referral_code, resting_quote, actor, now,
+    )
+    .await;
+    if inserted.is_err() {
+        if let Err(e) = state.repository.release_reservation(reservation).await {
+            eprintln!("⚠️  Failed to release collateral reservation {}: {}", reservation, e);
+        }
+    }
+    let (contract_id, payment) = inserted?;
+
+    state.rolling_metrics.record_trade(&contract, now);
+    // Max quantities in the cached options table no longer reflect the portfolio
+    state.options_table_cache.invalidate();
+
+    Ok(AcceptedContract { contract_id, payment, fee, risk })
+}
+
+// Request the premium and fee of a trade holding collateral reservation `reservation`, then
+// insert it with its fee and referral code, filling `resting_quote` if it was placed against one
+#[allow(clippy::too_many_arguments)]
+async fn insert_reserved_trade(
+    state: &AppState,
+    reservation: i64,
+    pool: &Pool,
+    contract: Contract,
+    quote: PremiumQuote,
+    snapshot: TradeSnapshot,
+    fee: &TradeFee,
+    exercise_style: ExerciseStyle,
+    payment_method: PaymentMethod,
+    referral_code: Option<String>,
+    resting_quote: Option<i64>,
+    actor: String,
+    now: i64,
+) -> Result<(i64, Option<PremiumPayment>), ApiError> {
+    // While premiums must be paid, the contract is pending until the payment of premium and
+    // fee confirms
+    let premium_sats = btc_to_sats(contract.premium * contract.quantity) + fee.fee_sats;
+    let payment = if state.payments.required && premium_sats > 0 {
+        let due_at = (now + state.payments.timeout.as_secs() as i64).min(contract.expires);
+        let target = match (payment_method, &state.lightning) {
+            // The invoice simply expires if the trade falls through
+            (PaymentMethod::Lightning, Some(lightning)) => {
+                let memo = format!(
+                    "{} {} {} expiring {} x {:.8}",
+                    contract.underlying, contract.side, contract.strike_price, contract.expires, contract.quantity
+                );
+                let invoice = lightning
+                    .create_invoice(premium_sats, &memo, due_at - now)
+                    .await
+                    .map_err(|e| ApiError::ExternalApiError(format!("Lightning invoice unavailable: {}", e)))?;
+                PaymentTarget::Lightning(invoice)
+            }
+            _ => PaymentTarget::Onchain {
+                address: pool.address.clone(),
+                confirmations_required: state.payments.confirmations,
+            },
+        };
+        Some(PaymentRequest { target, amount_sats: premium_sats, due_at })
+    } else {
+        None
+    };
+
+    let quantity_sats = btc_to_sats(contract.quantity);
+    let (fee, account) = (fee.clone(), actor.clone());
+    state
+        .repository
+        .insert_reserved_contract(reservation, pool.id, contract, quote, snapshot, exercise_style, payment, actor, now, move |conn, id| {
+            if let Some(quote_id) = resting_quote {
+                orderbook::fill_quote(conn, quote_id, quantity_sats, now)?;
+            }
+            fees::record(conn, id, &account, &fee, now)?;
+            if let Some(code) = &referral_code {
+                referrals::record(conn, id, code, &account, now)?;
+            }
+            Ok(())
+        })
+        .await
+}
+
+// Market and pool inputs of the collateral check of one contract
+#[derive(Clone)]
+struct CollateralTerms {
+    total_collateral_usd: f64,  // Pool collateral available for margin
+    rates: Arc<RateCurve>,
+    iv: f64,                    // Of the contract
+    time_to_expiry: f64,        // Of the contract, in years
+    now: i64,
+}
+
+// Check that `contract` fits the pool next to `existing_contracts`, the active book of its pool,
+// the concentration limits on its product and on `counterparty`, whose other active
+// contracts are `counterparty_contracts`, and the net Greek limits of the book. Shared by
+// new contracts and amendments.
+#[allow(clippy::too_many_arguments)]
+fn check_collateral_and_limits(
+    risk_manager: &RiskManager,
+    ivs: &IvResolver,
+    position_limits: &PositionLimits,
+    contract: &Contract,
+    counterparty: &str,
+    existing_contracts: &[Contract],
+    counterparty_contracts: &[Contract],
+    spot_prices: &HashMap<Asset, f64>,
+    terms: CollateralTerms,
+) -> Result<(), ApiError> {
+    let CollateralTerms { total_collateral_usd, rates, iv, time_to_expiry, now } = terms;
+    let spot_price = spot_prices[&contract.underlying];
+    let risk_free_rate = rates.rate_to(contract.expires, now);
+    // Calculate current risk exposure WITHOUT the new contract
+    let total_existing_risk = book_risk(
+        risk_manager,
+        existing_contracts,
+        spot_prices,
+        &rates,
+        ivs,
+    )?;
+
+    // Calculate available collateral
+    let available_collateral_usd = total_collateral_usd - total_existing_risk;
+
+    // Calculate maximum allowed quantity for this specific contract
+    let max_quantity = risk_manager.calculate_max_quantity(
+        &contract.side,
+        contract.strike_price,
+        contract.premium,
+        spot_price,
+        iv,
+        time_to_expiry,
+        risk_free_rate,
+        available_collateral_usd,
+        total_existing_risk,
+    );
+
+    // Log risk calculation details
+    println!("📊 Contract Risk Analysis:");
+    println!("   Contract: {} {} expires {} @ ${} for {} qty", 
+        contract.underlying, contract.side, contract.expires, contract.strike_price, contract.quantity);
+    println!("   Max allowed quantity: {:.2}", max_quantity);
+    println!("   Available collateral: ${:.2}", available_collateral_usd);
+    println!("   Existing portfolio risk: ${:.2}", total_existing_risk);
+
+    // Check if requested quantity exceeds maximum allowed
+    if contract.quantity > max_quantity {
+        eprintln!("❌ Contract validation failed: requested quantity ({:.8}) exceeds maximum allowed ({:.8})", 
+            contract.quantity, max_quantity);
+        eprintln!("   Available collateral: ${:.2}", available_collateral_usd);
+        eprintln!("   Existing risk exposure: ${:.2}", total_existing_risk);
+        eprintln!("   Total collateral pool: ${:.2}", total_collateral_usd);
+        return Err(ApiError::ValidationError(
+            format!(
+                "Requested quantity ({:.8}) exceeds maximum allowed quantity ({:.8}). \
+                Available collateral: ${:.2}, \
+                Existing risk exposure: ${:.2}, \
+                Total collateral pool: ${:.2}",
+                contract.quantity,
+                max_quantity,
+                available_collateral_usd,
+                total_existing_risk,
+                total_collateral_usd
+            ),
+        )
+        .with_code(ErrorCode::InsufficientCollateral)
+        .with_details(json!({
+            "requested_quantity": contract.quantity,
+            "max_quantity": max_quantity,
+            "available_collateral_usd": available_collateral_usd,
+            "existing_risk_usd": total_existing_risk,
+            "total_collateral_usd": total_collateral_usd,
+        })));
+    }
+
+    // Concentration limits on the product and on the counterparty
+    let product_margin_usd = risk_manager
+        .calculate_position_risk(
+            &contract.side,
+            contract.strike_price,
+            contract.premium,
+            product_quantity(existing_contracts, contract) + contract.quantity,
+            spot_price,
+            iv,
+            time_to_expiry,
+            risk_free_rate,
+        )
+        .margin_required;
+    position_limits.check_product(
+        contract,
+        existing_contracts,
+        spot_price,
+        product_margin_usd,
+        total_collateral_usd,
+    )?;
+    position_limits.check_counterparty(counterparty, contract, counterparty_contracts, spot_prices)?;
+    if position_limits.limits_greeks() {
+        let current = book_greeks(existing_contracts, spot_prices, &rates, ivs, now)?;
+        let mut greeks = current;
+        let option = pricing::option_greeks(&contract.side, spot_price, contract.strike_price, risk_free_rate, iv, time_to_expiry);
+        greeks.add(contract, &option, spot_price);
+        position_limits.check_greeks(&current, &greeks)?;
+    }
+
+    // Now check total risk with the new contract
+    let mut existing_contracts = existing_contracts.to_vec();
+    existing_contracts.push(contract.clone());
+    let total_risk_with_new = book_risk(
+        risk_manager,
+        &existing_contracts,
+        spot_prices,
+        &rates,
+        ivs,
+    )?;
+
+    if total_risk_with_new > total_collateral_usd {
+        // This should not happen if max_quantity check above is working correctly
+        // But we keep it as a safety check
+        let position_risk = risk_manager.calculate_position_risk(
+            &contract.side,
+            contract.strike_price,
+            contract.premium,
+            contract.quantity,
+            spot_price,
+            iv,
+            time_to_expiry,
+            risk_free_rate,
+        );
+
+        eprintln!("❌ Contract validation failed: risk exceeds available collateral");
+        eprintln!("   New position margin required: ${:.2}", position_risk.margin_required);
+        eprintln!("   Total portfolio margin would be: ${:.2}", total_risk_with_new);
+        eprintln!("   Available collateral: ${:.2}", total_collateral_usd);
+
+        return Err(ApiError::ValidationError(
+            format!(
+                "Contract risk exceeds available collateral. \
+                New position margin required: ${:.2}, \
+                Total portfolio margin would be: ${:.2}, \
+                Available collateral: ${:.2}",
+                position_risk.margin_required,
+                total_risk_with_new,
+                total_collateral_usd
+            ),
+        )
+        .with_code(ErrorCode::InsufficientCollateral)
+        .with_details(json!({
+            "margin_required_usd": position_risk.margin_required,
+            "total_margin_usd": total_risk_with_new,
+            "available_collateral_usd": total_collateral_usd,
+        })));
+    }
+
+    Ok(())
+}
+
+// GET /pools - Every pool contracts can be sold from, the default pool first
+async fn get_pools(state: web::Data<Arc<AppState>>) -> Result<impl Responder, ApiError> {
+    Ok(HttpResponse::Ok().json(state.pools().await?))
+}
+
+// GET /pools/{id} - A pool's collateral against the margin of its own open book
+async fn get_pool(path: web::Path<i64>, state: web::Data<Arc<AppState>>) -> Result<impl Responder, ApiError> {
+    let pool = state.pool(path.into_inner()).await?;
+    let now = Utc::now().timestamp();
+
+    let balance_btc = state.pool_balance_btc(&pool).await?;
+    let book = state.repository.pool_active_contracts(pool.id, now).await?;
+    let spot_prices = state.book_spot_prices(&book, HashMap::new()).await?;
+    let btc_price = spot_prices[&Asset::Btc];
+    let risk_manager = state.risk_manager(pool.risk_margin);
+    let open_notional_usd = book.iter().map(|c| c.quantity * spot_prices[&c.underlying]).sum();
+    let active_contracts = book.len();
+    let margin_used_usd = book_risk_blocking(&state, &risk_manager, book, &spot_prices).await?;
+
+    let pool_id = pool.id;
+    let (contracts_24hr, premium_24hr_btc) =
+        state.repository.run(move |conn| pools::pool_sales_since(conn, pool_id, now - 86_400)).await?;
+
+    let total_collateral_usd = balance_btc * btc_price * pool.collateral_rate;
+    Ok(HttpResponse::Ok().json(PoolRiskResponse {
+        balance_btc,
+        btc_price,
+        total_collateral_usd,
+        margin_used_usd,
+        available_collateral_usd: total_collateral_usd - margin_used_usd,
+        utilization_percent: if total_collateral_usd > 0.0 { margin_used_usd / total_collateral_usd * 100.0 } else { 0.0 },
+        active_contracts,
+        open_notional_usd,
+        contracts_24hr,
+        premium_24hr_btc,
+        margin_model: risk_manager.margin_model_name(),
+        pool,
+    }))
+}
+
+// GET /maxQuantity - Largest quantity POST /contract would currently accept
+async fn get_max_quantity(
+    query: web::Query<MaxQuantityQuery>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    let pool = state.default_pool();
+    Ok(HttpResponse::Ok().json(max_quantity(&state, pool, &query).await?))
+}
+
+// GET /price - Black-Scholes premium and Greeks of one option, with the inputs used
+async fn get_price(query: web::Query<PriceQuery>, state: web::Data<Arc<AppState>>) -> Result<impl Responder, ApiError> {
+    let now = Utc::now().timestamp();
+    if query.expires <= now {
+        return Err(ApiError::ValidationError("Option expiration date must be in the future.".to_string())
+            .with_code(ErrorCode::InvalidExpiry)
+            .with_details(json!({"expires": query.expires, "now": now})));
+    }
+    let positive = |value: Option<f64>| value.is_none_or(|v| v.is_finite() && v > 0.0);
+    if !(positive(Some(query.strike)) && positive(query.spot) && positive(query.iv)) {
+        return Err(ApiError::ValidationError("strike, spot and iv must be positive".to_string()));
+    }
+    state.check_asset(query.asset)?;
+
+    let risk_free_rate = state.rates().rate_to(query.expires, now);
+    let (spot_price, spot_source) = match query.spot {
+        Some(spot) => (spot, PriceInput::Request),
+        None => (state.spot_price(query.asset).await?, PriceInput::Oracle),
+    };
+    // A BTC option given a spot is converted at that spot too
+    let btc_price = match query.asset {
+        Asset::Btc => spot_price,
+        _ => state.spot_price(Asset::Btc).await?,
+    };
+    // Same IV lookup and default post_contract prices new contracts with
+    let (iv, iv_source) = match query.iv {
+        Some(iv) => (iv, PriceInput::Request),
+        None => {
+            let quote = state.ivs().option_iv(query.asset, query.side, query.strike, query.expires);
+            match quote.source {
+                IvProvenance::Default => (quote.value, PriceInput::Default),
+                _ => (quote.value, PriceInput::Oracle),
+            }
+        }
+    };
+
+    let t = year_fraction(query.expires, now);
+    let carry = state.carry(query.asset, query.expires, now);
+    let pricing_spot = state.pricing_spot(query.asset, spot_price, risk_free_rate, query.expires, now);
+    let premium_usd = pricing::option_price(&query.side, pricing_spot, query.strike, risk_free_rate, iv, t);
+    Ok(HttpResponse::Ok().json(PriceResponse {
+        underlying: query.asset,
+        side: query.side,
+        strike_price: query.strike,
+        expires: query.expires,
+        time_to_expiry: t,
+        day_count: day_count::deployment().convention,
+        spot_price,
+        spot_source,
+        iv,
+        iv_source,
+        risk_free_rate,
+        carry,
+        forward_price: pricing_spot * (risk_free_rate * t).exp(),
+        btc_price,
+        premium_usd,
+        premium_btc: format_btc(premium_usd / btc_price),
+        greeks: pricing::option_greeks(&query.side, pricing_spot, query.strike, risk_free_rate, iv, t),
+        priced_at: now,
+    }))
+}
+
+// GET /pools/{id}/maxQuantity - Largest quantity POST /pools/{id}/contract would currently accept
+async fn get_pool_max_quantity(
+    path: web::Path<i64>,
+    query: web::Query<MaxQuantityQuery>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    let pool = state.pool(path.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(max_quantity(&state, pool, &query).await?))
+}
+
+async fn max_quantity(state: &AppState, pool: Pool, query: &MaxQuantityQuery) -> Result<MaxQuantityResponse, ApiError> {
+    let now = Utc::now().timestamp();
+    if query.expires <= now {
+        return Err(ApiError::ValidationError("Contract expiration date must be in the future.".to_string())
+            .with_code(ErrorCode::InvalidExpiry)
+            .with_details(json!({"expires": query.expires, "now": now})));
+    }
+    if query.strike <= 0.0 || query.premium < 0.0 {
+        return Err(ApiError::ValidationError(
+            "strike must be positive and premium must not be negative".to_string(),
+        ));
+    }
+    state.check_asset(query.asset)?;
+
+    let collateral_rate = pool.collateral_rate;
+    let risk_free_rate = state.rates().rate_to(query.expires, now);
+
+    let pool_qty: f64 = state.pool_balance_btc(&pool).await?;
+
+    let risk_manager = state.risk_manager(pool.risk_margin);
+    let existing_contracts = state.repository.pool_active_contracts(pool.id, now).await?;
+    let spot_prices = HashMap::from([(query.asset, state.spot_price(query.asset).await?)]);
+    let spot_prices = state.book_spot_prices(&existing_contracts, spot_prices).await?;
+    let btc_price = spot_prices[&Asset::Btc];
+    let spot_price = spot_prices[&query.asset];
+    let premium_btc = query.premium_currency.to_btc(query.premium, btc_price);
+
+    // Same breakdown post_contract uses to accept or reject the order
+    let total_existing_risk =
+        book_risk_blocking(state, &risk_manager, existing_contracts.clone(), &spot_prices).await?;
+    let total_collateral_usd = pool_qty * btc_price * collateral_rate;
+    let available_collateral_usd = total_collateral_usd - total_existing_risk;
+
+    let time_to_expiry = year_fraction(query.expires, now);
+    let iv_quote = state.ivs().option_iv(query.asset, query.side, query.strike, query.expires);
+    let iv = iv_quote.value;
+
+    // Nothing can be bought at a default IV the policy refuses to trade at
+    let max_quantity = if state.iv_policy.allows_trade(&iv_quote) {
+        risk_manager.calculate_max_quantity(
+            &query.side,
+            query.strike,
+            premium_btc,
+            spot_price,
+            iv,
+            time_to_expiry,
+            risk_free_rate,
+            available_collateral_usd,
+            total_existing_risk,
+        )
+    } else {
+        0.0
+    };
+
+    Ok(MaxQuantityResponse {
+        max_quantity,
+        available_collateral_usd,
+        existing_risk_usd: total_existing_risk,
+        total_collateral_usd,
+        pool_id: pool.id,
+        pool_balance_btc: pool_qty,
+        btc_price,
+        underlying: query.asset,
+        spot_price,
+        iv,
+        margin_model: risk_manager.margin_model_name(),
+    })
+}
+
+// GET /admin/audit - Audit log entries, newest first, optionally filtered
+async fn get_audit_log(
+    req: HttpRequest,
+    query: web::Query<AuditFilter>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    require_role(&req, &state, Role::Admin).await?;
+    if let Some(limit) = query.limit {
+        if limit == 0 || limit > audit::MAX_QUERY_LIMIT {
+            return Err(ApiError::ValidationError(format!(
+                "limit must be between 1 and {}",
+                audit::MAX_QUERY_LIMIT
+            )));
+        }
+    }
+
+    let entries = state.repository.audit_entries(query.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(entries))
+}
+
+// Streams `table` as a file download in the requested format
+async fn export_response(
+    req: &HttpRequest,
+    query: &ExportQuery,
+    state: &AppState,
+    table: &'static ExportTable,
+) -> Result<HttpResponse, ApiError> {
+    require_role(req, state, Role::Viewer).await?;
+    let from = match &query.from {
+        Some(from) => export::parse_date_bound(from, false).map_err(ApiError::ValidationError)?,
+        None => 0,
+    };
+    let to = match &query.to {
+        Some(to) => export::parse_date_bound(to, true).map_err(ApiError::ValidationError)?,
+        None => i64::MAX,
+    };
+    if from >= to {
+        return Err(ApiError::ValidationError("from must be before to".to_string()));
+    }
+
+    let stream = export::stream_export(state.repository.read_pool().clone(), table, query.format, from, to);
+    Ok(HttpResponse::Ok()
+        .content_type(query.format.content_type())
+        .insert_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"{}.{}\"", table.name, query.format.extension()),
+        ))
+        .streaming(stream))
+}
+
+// GET /settlements - Settlements and exercises in the date range with the pool's P&L on each
+async fn get_settlements(
+    req: HttpRequest,
+    query: web::Query<SettlementsQuery>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    require_role(&req, &state, Role::Viewer).await?;
+    let now = Utc::now().timestamp();
+    let bound = |value: &Option<String>, upper: bool, default: i64| match value {
+        Some(value) => export::parse_date_bound(value, upper).map_err(ApiError::ValidationError),
+        None => Ok(default),
+    };
+    let from = bound(&query.from, false, now - 30 * 24 * 60 * 60)?;
+    // Up to and including the current second
+    let to = bound(&query.to, true, now + 1)?;
+    if from >= to {
+        return Err(ApiError::ValidationError("from must be before to".to_string()));
+    }
+
+    let asset = query.asset;
+    let report = state
+        .repository
+        .read(move |conn| settlement::settlement_report(conn, from, to, asset))
+        .await?;
+    Ok(HttpResponse::Ok().json(report))
+}
+
+// GET /admin/referrals - Volume and fees of the trades placed with each referral code in the date range
+async fn get_referrals(
+    req: HttpRequest,
+    query: web::Query<ReferralsQuery>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    require_role(&req, &state, Role::Admin).await?;
+    let now = Utc::now().timestamp();
+    let bound = |value: &Option<String>, upper: bool, default: i64| match value {
+        Some(value) => export::parse_date_bound(value, upper).map_err(ApiError::ValidationError),
+        None => Ok(default),
+    };
+    let from = bound(&query.from, false, now - 30 * 24 * 60 * 60)?;
+    let to = bound(&query.to, true, now + 1)?;
+    if from >= to {
+        return Err(ApiError::ValidationError("from must be before to".to_string()));
+    }
+
+    let referrals = state.repository.read(move |conn| referrals::summarize(conn, from, to)).await?;
+    Ok(HttpResponse::Ok().json(ReferralsResponse { from, to, referrals }))
+}
+
+// GET /export/contracts - Contracts created in the date range as CSV or Parquet
+async fn get_export_contracts(
+    req: HttpRequest,
+    query: web::Query<ExportQuery>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, ApiError> {
+    export_response(&req, &query, &state, &export::CONTRACTS).await
+}
+
+// GET /export/premiumHistory - Premium observations in the date range as CSV or Parquet
+async fn get_export_premium_history(
+    req: HttpRequest,
+    query: web::Query<ExportQuery>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, ApiError> {
+    export_response(&req, &query, &state, &export::PREMIUM_HISTORY).await
+}
+
+// GET /contract/{id} - One contract with live mark, Greeks and margin
+async fn get_contract(
+    req: HttpRequest,
+    path: web::Path<i64>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    require_role(&req, &state, Role::Viewer).await?;
+    let contract = state.repository.contract_record(path.into_inner()).await?;
+    let now = Utc::now().timestamp();
+
+    let risk_free_rate = state.rates().rate_to(contract.expires, now);
+    // Margined with the risk margin of the pool the contract was sold from
+    let pool = state.pool(contract.pool_id).await?;
+    let risk_manager = state.risk_manager(pool.risk_margin);
+
+    let spot_price = state.spot_price(contract.underlying).await?;
+    let btc_price = state.spot_price(Asset::Btc).await?;
+    let time_to_expiry_secs = (contract.expires - now).max(0);
+    let t = year_fraction(now + time_to_expiry_secs, now);
+    // Same IV lookup and default post_contract margins new contracts with
+    let iv = state.ivs().option_iv(contract.underlying, contract.side, contract.strike_price, contract.expires).value;
+
+    let pricing_spot = state.pricing_spot(contract.underlying, spot_price, risk_free_rate, contract.expires, now);
+    let mark_premium_usd = pricing::option_price(&contract.side, pricing_spot, contract.strike_price, risk_free_rate, iv, t);
+    let mark_premium_btc = mark_premium_usd / btc_price;
+    let greeks = pricing::option_greeks(&contract.side, pricing_spot, contract.strike_price, risk_free_rate, iv, t);
+
+    let moneyness = spot_price / contract.strike_price;
+    let moneyness_label = if (moneyness - 1.0).abs() < 0.01 {
+        "ATM"
+    } else if (contract.side == OptionSide::Call) == (moneyness > 1.0) {
+        "ITM"
+    } else {
+        "OTM"
+    };
+
+    // Margin only applies while the contract is part of the open book, which pending contracts are
+    let in_book = matches!(contract.status, ContractStatus::Open | ContractStatus::Pending);
+    let (margin_usd, marginal_margin_usd) = if in_book && contract.expires > now {
+        let margin = risk_manager
+            .calculate_position_risk(
+                &contract.side,
+                contract.strike_price,
+                contract.premium,
+                contract.open_quantity(),
+                spot_price,
+                iv,
+                t,
+                risk_free_rate,
+            )
+            .margin_required;
+
+        let spot_prices = HashMap::from([(Asset::Btc, btc_price), (contract.underlying, spot_price)]);
+        let this = contract.to_contract();
+        let marginal_margin =
+            released_book_margin(&state, pool.id, &risk_manager, &this, this.quantity, spot_prices, now).await?;
+        (Some(margin), Some(marginal_margin))
+    } else {
+        (None, None)
+    };
+
+    let id = contract.id;
+    let conversions = state.repository.run(move |conn| conversions::load_conversions(conn, id)).await?;
+
+    let open_quantity = contract.open_quantity();
+    let mark_value_usd = mark_premium_usd * open_quantity;
+    Ok(HttpResponse::Ok().json(ContractDetailResponse {
+        spot_price,
+        btc_price,
+        iv,
+        time_to_expiry_secs,
+        time_to_expiry_years: t,
+        moneyness,
+        moneyness_label,
+        edge_at_trade_btc: contract.edge_at_trade(),
+        mark_premium_usd,
+        mark_premium_btc,
+        mark_premium: contract.premium_currency.format(contract.premium_currency.from_btc(mark_premium_btc, btc_price)),
+        mark_value_usd,
+        unrealized_pnl_usd: contract.premium * open_quantity * btc_price - mark_value_usd,
+        position_greeks: greeks.scaled(open_quantity),
+        greeks,
+        margin_usd,
+        marginal_margin_usd,
+        expiring_soon: state.expiry_notice.is_expiring_soon(contract.expires, now),
+        conversions,
+        contract,
+    }))
+}
+
+// GET /contract/{id}/payment - Deposit address or invoice, amount and status of a contract's premium payment
+async fn get_contract_payment(
+    req: HttpRequest,
+    path: web::Path<i64>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    require_role(&req, &state, Role::Viewer).await?;
+    let id = path.into_inner();
+    let payment = state.repository.run(move |conn| payments::load_payment(conn, id)).await?;
+    Ok(HttpResponse::Ok().json(payment))
+}
+
+// GET /contract/{id}/dlc - Payout curve and oracle event to collateralize the contract as a DLC
+async fn get_contract_dlc(
+    req: HttpRequest,
+    path: web::Path<i64>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    require_role(&req, &state, Role::Viewer).await?;
+    let signer = state.oracle_signer()?;
+    let contract = state.repository.contract_record(path.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(dlc::build_descriptor(&contract, &state.dlc, signer)?))
+}
+
+// GET /contract/{id}/pricing-audit - Trade replayed at its trade-time spot, IV and expiry, against fair value
+async fn get_contract_pricing_audit(
+    req: HttpRequest,
+    path: web::Path<i64>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    require_role(&req, &state, Role::Viewer).await?;
+    let contract = state.repository.contract_record(path.into_inner()).await?;
+    let report = pricing_audit::audit(&contract, &state.rates(), &pricing_audit::PricingAuditConfig::from_env())?;
+    Ok(HttpResponse::Ok().json(report))
+}
+
+// GET /attestations/{date} - Signed settlement prices of maturities on a UTC day (YYYY-MM-DD)
+async fn get_attestations(
+    path: web::Path<String>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    state.oracle_signer()?;
+    let date = path.into_inner();
+    let day = NaiveDate::parse_from_str(&date, "%Y-%m-%d")
+        .map_err(|_| ApiError::ValidationError(format!("Invalid date '{}', expected YYYY-MM-DD", date)))?;
+    let from = day.and_time(NaiveTime::MIN).and_utc().timestamp();
+    let attestations = state
+        .repository
+        .run(move |conn| attestation::load_attestations(conn, from, from + 86_400))
+        .await?;
+    Ok(HttpResponse::Ok().json(AttestationsResponse { date, attestations }))
+}
+
+// Book margin released by taking `quantity` of `contract` off the open book of pool
+// `pool_id`, after netting
+#[allow(clippy::too_many_arguments)]
+async fn released_book_margin(
+    state: &AppState,
+    pool_id: i64,
+    risk_manager: &RiskManager,
+    contract: &Contract,
+    quantity: f64,
+    spot_prices: HashMap<Asset, f64>,
+    now: i64,
+) -> Result<f64, ApiError> {
+    let book = state.repository.pool_active_contracts(pool_id, now).await?;
+    let mut without = book.clone();
+    if let Some(index) = without.iter().position(|c| c == contract) {
+        without[index].quantity -= quantity;
+    }
+    let spot_prices = state.book_spot_prices(&book, spot_prices).await?;
+    let with_margin = book_risk_blocking(state, risk_manager, book, &spot_prices).await?;
+    let without_margin = book_risk_blocking(state, risk_manager, without, &spot_prices).await?;
+    Ok(with_margin - without_margin)
+}
+
+// POST /contract/{id}/exercise - Exercise an in-the-money American contract early.
+// Only the buyer recorded on the contract may exercise it.
+async fn post_exercise_contract(
+    req: HttpRequest,
+    path: web::Path<i64>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    let actor = require_role(&req, &state, Role::Trader).await?;
+    let id = path.into_inner();
+    let contract = state.repository.contract_record(id).await?;
+    let now = Utc::now().timestamp();
+
+    check_buyer_action(&contract, &actor, "exercise", now)?;
+    if contract.exercise_style != ExerciseStyle::American {
+        return Err(ApiError::ValidationError(format!(
+            "Contract {} is European and settles at expiry",
+            id
+        )));
+    }
+    // Exercise takes risk off the pool, so it is allowed while reduce-only
+    state.repository.run(|conn| Ok(trading_state::load(conn)?)).await?.state.check_reduce_position()?;
+
+    // Moneyness and payoff at a guarded price, as for new trades
+    let spot_prices = state.guarded_spot_prices(&[Asset::Btc, contract.underlying]).await?;
+    let btc_price = spot_prices[&Asset::Btc];
+    let spot_price = spot_prices[&contract.underlying];
+    let payoff_usd = contract.payoff_usd(spot_price);
+    if payoff_usd <= 0.0 {
+        return Err(ApiError::ValidationError(format!(
+            "Contract {} is out of the money: {} {} strike ${:.2} with {} at ${:.2}",
+            id, contract.underlying, contract.side, contract.strike_price, contract.underlying, spot_price
+        )));
+    }
+
+    // Margined with the risk margin of the pool the contract was sold from
+    let pool = state.pool(contract.pool_id).await?;
+    let risk_manager = state.risk_manager(pool.risk_margin);
+    let this = contract.to_contract();
+    let released_margin_usd =
+        released_book_margin(&state, pool.id, &risk_manager, &this, this.quantity, spot_prices, now).await?;
+
+    let exercised = state.repository.exercise_contract(id, spot_price, btc_price, now, actor).await?;
+    println!("🏁 Contract {} exercised at ${:.2}, payoff ${:.2}", id, spot_price, payoff_usd);
+
+    // Margin and max quantities change once the contract leaves the book
+    state.options_table_cache.invalidate();
+
+    let payoff = exercised.settlement_payoff().unwrap_or_default();
+    Ok(HttpResponse::Ok().json(ExerciseResponse {
+        payoff_usd,
+        payoff: exercised.premium_currency.format(payoff),
+        released_margin_usd,
+        contract: exercised,
+    }))
+}
+
+// POST /contract/{id}/close - Sell some or all of a contract back to the pool at the mark.
+// Only the buyer may close; the remaining quantity stays open under the same id.
+async fn post_close_contract(
+    req: HttpRequest,
+    path: web::Path<i64>,
+    body: web::Json<CloseRequest>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    let actor = require_role(&req, &state, Role::Trader).await?;
+    let id = path.into_inner();
+    let contract = state.repository.contract_record(id).await?;
+    let now = Utc::now().timestamp();
+
+    check_buyer_action(&contract, &actor, "close", now)?;
+    // The repository checks the quantity against the open quantity under the write lock
+    let quantity_sats =
+        validation::close_quantity_sats(body.quantity, btc_to_sats(contract.open_quantity()), &state.position_limits)?;
+    // Closing takes risk off the pool, so it is allowed while reduce-only
+    state.repository.run(|conn| Ok(trading_state::load(conn)?)).await?.state.check_reduce_position()?;
+
+    let spot_prices = state.guarded_spot_prices(&[Asset::Btc, contract.underlying]).await?;
+    let btc_price = spot_prices[&Asset::Btc];
+    let spot_price = spot_prices[&contract.underlying];
+
+    let risk_free_rate = state.rates().rate_to(contract.expires, now);
+    // Margined with the risk margin of the pool the contract was sold from
+    let pool = state.pool(contract.pool_id).await?;
+    let risk_manager = state.risk_manager(pool.risk_margin);
+
+    // Bought back at the same mark GET /contract/{id} shows, default IV or not, as closing reduces risk
+    let iv = state.ivs().option_iv(contract.underlying, contract.side, contract.strike_price, contract.expires).value;
+    let t = year_fraction(contract.expires, now);
+    let pricing_spot = state.pricing_spot(contract.underlying, spot_price, risk_free_rate, contract.expires, now);
+    let close_price_usd = pricing::option_price(&contract.side, pricing_spot, contract.strike_price, risk_free_rate, iv, t);
+    let quantity = sats_to_btc(quantity_sats);
+    let proceeds_usd = close_price_usd * quantity;
+
+    let released_margin_usd = released_book_margin(
+        &state,
+        pool.id,
+        &risk_manager,
+        &contract.to_contract(),
+        quantity,
+        spot_prices,
+        now,
+    )
+    .await?;
+
+    let closed = state
+        .repository
+        .close_contract(id, quantity_sats, close_price_usd, btc_price, now, actor)
+        .await?;
+    println!(
+        "↩️  Contract {} closed {} at ${:.2}, {} left open",
+        id,
+        format_sats(quantity_sats),
+        close_price_usd,
+        format_sats(btc_to_sats(closed.open_quantity()))
+    );
+
+    // Margin and max quantities change with the open book
+    state.options_table_cache.invalidate();
+
+    Ok(HttpResponse::Ok().json(CloseResponse {
+        closed_quantity_now: format_sats(quantity_sats),
+        close_price_usd,
+        proceeds_usd,
+        proceeds: closed.premium_currency.format(closed.premium_currency.from_usd(proceeds_usd, btc_price)),
+        released_margin_usd,
+        contract: closed,
+    }))
+}
+
+// PATCH /contract/{id} - Extend an open contract: a later expiry, a larger quantity and/or a
+// higher premium per unit. The amended contract passes the same collateral and limit checks as
+// a new one, against the book without its current terms. Only the buyer may amend.
+async fn patch_contract(
+    req: HttpRequest,
+    path: web::Path<i64>,
+    body: web::Json<AmendRequest>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    let actor = require_role(&req, &state, Role::Trader).await?;
+    let id = path.into_inner();
+    let contract = state.repository.contract_record(id).await?;
+    let now = Utc::now().timestamp();
+
+    check_buyer_action(&contract, &actor, "amend", now)?;
+    // The extra premium is not collected on-chain or over Lightning
+    if state.payments.required {
+        return Err(ApiError::ValidationError("Contracts cannot be amended while premiums must be paid".to_string()));
+    }
+    // Amendments add risk to the pool, so they need an open venue
+    state.repository.run(|conn| Ok(trading_state::load(conn)?)).await?.state.check_open_position()?;
+    state.check_asset(contract.underlying)?;
+
+    let AmendRequest { expires, quantity, premium } = body.into_inner();
+    let expires = expires.unwrap_or(contract.expires);
+    if expires < contract.expires {
+        return Err(ApiError::ValidationError(format!("The expiry of contract {} may only be extended", id))
+            .with_code(ErrorCode::InvalidExpiry)
+            .with_details(json!({"expires": expires, "current_expires": contract.expires})));
+    }
+    let quantity_sats = match quantity {
+        Some(quantity) => validation::trade_quantity_sats(quantity, &state.position_limits)?,
+        None => btc_to_sats(contract.quantity),
+    };
+    if quantity_sats < btc_to_sats(contract.quantity) {
+        return Err(ApiError::ValidationError(format!(
+            "The quantity of contract {} may only be increased; close part of it instead",
+            id
+        ))
+        .with_code(ErrorCode::InvalidQuantity)
+        .with_details(json!({"quantity": format_sats(quantity_sats), "current_quantity": format_sats(btc_to_sats(contract.quantity))})));
+    }
+    let premium_sats = match premium {
+        Some(premium) if premium.is_finite() && premium >= contract.premium => btc_to_sats(premium),
+        Some(premium) => {
+            return Err(ApiError::ValidationError(format!(
+                "The premium of contract {} may not be lowered below {}, got {}",
+                id,
+                format_sats(btc_to_sats(contract.premium)),
+                premium
+            )))
+        }
+        None => btc_to_sats(contract.premium),
+    };
+    let amendment = ContractAmendment { expires, quantity_sats, premium_sats };
+    if expires == contract.expires
+        && quantity_sats == btc_to_sats(contract.quantity)
+        && premium_sats == btc_to_sats(contract.premium)
+    {
+        return Err(ApiError::ValidationError("Nothing to amend: give a later expires, a larger quantity or a higher premium".to_string()));
+    }
+
+    // Margined against the pool the contract was sold from, at guarded prices as for new trades
+    let pool = state.pool(contract.pool_id).await?;
+    let pool_qty = state.pool_balance_btc(&pool).await?;
+    let spot_prices = state.guarded_spot_prices(&[Asset::Btc, contract.underlying]).await?;
+    let open_contracts = state.repository.pool_active_contracts(pool.id, now).await?;
+    let spot_prices = state.book_spot_prices(&open_contracts, spot_prices).await?;
+    let btc_price = spot_prices[&Asset::Btc];
+
+    let risk_manager = state.risk_manager(pool.risk_margin);
+    let iv_quote = state.ivs().option_iv(contract.underlying, contract.side, contract.strike_price, expires);
+    state.iv_policy.check_trade(&iv_quote, contract.underlying, contract.side, contract.strike_price, expires)?;
+    let iv = iv_quote.value;
+    let terms = CollateralTerms {
+        total_collateral_usd: pool_qty * btc_price * pool.collateral_rate,
+        rates: state.rates(),
+        iv,
+        time_to_expiry: year_fraction(expires, now),
+        now,
+    };
+
+    let ivs = state.ivs();
+    let position_limits = state.position_limits.clone();
+    let kyc = state.kyc.clone();
+    // The amendment adds to the notional the buyer has traded
+    let added_quantity = sats_to_btc(amendment.quantity_sats) - contract.quantity;
+    let counterparty = actor.clone();
+    let amended = state
+        .repository
+        .amend_contract_checked(id, amendment, btc_price, actor, now, move |conn, amended, book, counterparty_contracts| {
+            credit_tiers::check_account(conn, &counterparty, amended, counterparty_contracts, &spot_prices)?;
+            if let Some(kyc) = &kyc {
+                kyc.check(conn, &counterparty, added_quantity * spot_prices[&amended.underlying])?;
+            }
+            check_collateral_and_limits(
+                &risk_manager,
+                &ivs,
+                &position_limits,
+                amended,
+                &counterparty,
+                book,
+                counterparty_contracts,
+                &spot_prices,
+                terms,
+            )
+        })
+        .await?;
+    let additional_premium = amended.premium * amended.quantity - contract.premium * contract.quantity;
+    println!(
+        "✏️  Contract {} amended: expires {}, quantity {}, premium {}",
+        id,
+        amended.expires,
+        format_sats(btc_to_sats(amended.quantity)),
+        format_sats(btc_to_sats(amended.premium))
+    );
+
+    // Margin and max quantities change with the open book
+    state.options_table_cache.invalidate();
+
+    Ok(HttpResponse::Ok().json(AmendResponse {
+        contract: amended,
+        additional_premium: format_sats(btc_to_sats(additional_premium)),
+    }))
+}
+
+// Checks before the buyer exercises or closes a contract: only the buyer recorded on the
+// contract may act on it, and only while it is open
+fn check_buyer_action(contract: &ContractRecord, actor: &str, action: &str, now: i64) -> Result<(), ApiError> {
+    if contract.counterparty.as_deref() != Some(actor) {
+        return Err(ApiError::Unauthorized(format!("only the buyer of contract {} may {} it", contract.id, action)));
+    }
+    if contract.status != ContractStatus::Open {
+        return Err(ApiError::ValidationError(format!("Contract {} is {}, not open", contract.id, contract.status))
+            .with_code(ErrorCode::ContractNotOpen)
+            .with_details(json!({"contract_id": contract.id, "status": contract.status})));
+    }
+    if contract.expires <= now {
+        return Err(ApiError::ValidationError(format!("Contract {} has expired and settles at expiry", contract.id)));
+    }
+    Ok(())
+}
+
+// GET /contracts - List all contracts
+async fn get_contracts(req: HttpRequest, state: web::Data<Arc<AppState>>) -> Result<impl Responder, ApiError> {
+    require_role(&req, &state, Role::Viewer).await?;
+    Ok(HttpResponse::Ok().json(list_contracts(&state).await?))
+}
+
+pub(crate) async fn list_contracts(state: &AppState) -> Result<Vec<ContractResponse>, ApiError> {
+    let now = Utc::now().timestamp();
+    let contracts = state
+        .repository
+        .all_contracts()
+        .await?
+        .into_iter()
+        .map(|contract| ContractResponse {
+            open_quantity: format_sats(contract.open_quantity_sats()),
+            underlying: contract.underlying,
+            side: contract.side,
+            strike_price: cents_to_usd(contract.strike_price_cents),
+            quantity: format_sats(contract.quantity_sats),  // Exact 8 decimal string
+            expires: contract.expires,
+            premium: format_sats(contract.premium_sats),    // Exact 8 decimal string
+            premium_currency: contract.premium_currency,
+            quoted_premium: contract
+                .quoted_premium_minor
+                .map(|minor| contract.premium_currency.format(contract.premium_currency.from_minor(minor))),
+            trade_btc_price: contract.trade_btc_price_cents.map(cents_to_usd),
+            expiring_soon: state.expiry_notice.is_expiring_soon(contract.expires, now),
+        })
+        .collect();
+    Ok(contracts)
+}
+
+// GET /contracts/search - Contracts matching a symbol fragment and range filters, newest first
+async fn get_contracts_search(
+    req: HttpRequest,
+    query: web::Query<ContractSearchQuery>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    require_role(&req, &state, Role::Viewer).await?;
+    query.validate()?;
+    if let Some(asset) = query.underlying {
+        state.check_asset(asset)?;
+    }
+
+    // The moneyness band is only priced when asked for
+    let mut spots = HashMap::new();
+    if query.moneyness_percent.is_some() {
+        let assets = query.underlying.map(|asset| vec![asset]).unwrap_or_else(|| state.assets.clone());
+        for asset in assets {
+            spots.insert(asset, state.spot_price(asset).await?);
+        }
+    }
+
+    let now = Utc::now().timestamp();
+    let contracts = state.repository.search_contracts(query.into_inner(), spots, now).await?;
+    let results: Vec<ContractSearchResult> = contracts
+        .into_iter()
+        .map(|contract| ContractSearchResult {
+            product_symbol: product_symbol(contract.underlying, &contract.side, contract.strike_price, contract.expires),
+            legacy_product_symbol: legacy_product_symbol(contract.underlying, &contract.side, contract.strike_price, contract.expires),
+            contract,
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(results))
+}
+
+// GET /optionsTable - Generate options table with automatic parameters
+async fn get_options_table(
+    query: web::Query<OptionsTableQuery>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    Ok(HttpResponse::Ok().json(&*options_table(&state, &query).await?))
+}
+
+pub(crate) async fn options_table(
+    state: &AppState,
+    query: &OptionsTableQuery,
+) -> Result<Arc<Vec<OptionsTableResponse>>, ApiError> {
+    let asset = query.asset;
+    state.check_asset(asset)?;
+
+    // Server defaults for the underlying, optionally overridden per request
+    let grid = state
+        .options_grid
+        .for_asset(asset)
+        .with_overrides(
+            query.strike_step,
+            query.strike_percent,
+            query.strikes_per_side,
+            query.tenors.as_deref(),
+        )
+        .map_err(ApiError::ValidationError)?;
+
+    // Current spot of the underlying; premiums are quoted in BTC
+    let spot_price = state.spot_price(asset).await?;
+    let btc_price = state.spot_price(Asset::Btc).await?;
+    
+    // Nothing is sold unless the venue is open
+    let trading_state = state.repository.run(|conn| Ok(trading_state::load(conn)?)).await?.state;
+
+    // Serve a cached table if neither oracle has refreshed and no contract was written since
+    let premium_currency = query.premium_currency;
+    let cache_key = format!("{}:{}:{:?}:{}", asset, premium_currency, grid, trading_state);
+    let source_versions = vec![state.iv_oracle.version(), state.price_oracle.version()];
+    if let Some(table) = state.options_table_cache.get(&cache_key, &source_versions) {
+        return Ok(table);
+    }
+    let generated_at = Utc::now().timestamp();
+
+    println!("📊 Generating {} options table for spot price: ${:.2}", asset, spot_price);
+    
+    // Check IV cache status
+    let cache_size = state.iv_oracle.cache_size();
+    if cache_size > 0 {
+        println!("✅ IV cache populated with {} entries", cache_size);
+    } else {
+        println!("⚠️ IV cache is empty - fetching may be slower");
+    }
+
+    let strike_prices = grid.strikes(spot_price);
+    let expires = grid.tenors.clone();
+    println!("🎯 Generated {} strike prices: {:?}", strike_prices.len(), strike_prices);
+    println!("⏰ Generated expiries: {:?}", expires);
+
+    let priced = price_grid(state, asset, &grid, spot_price, btc_price, generated_at).await?;
+    let pool_qty = priced.pool_qty;
+    let collateral_rate = priced.collateral_rate;
+
+    println!("💰 Risk Analysis:");
+    println!("   Total Collateral: ${:.2}", priced.total_collateral_usd);
+    println!("   Existing Risk Exposure: ${:.2}", priced.total_existing_risk);
+    println!("   Available Collateral: ${:.2}", priced.total_collateral_usd - priced.total_existing_risk);
+    println!("   Risk Margin: {:.0}%", (priced.risk_margin - 1.0) * 100.0);
+
+    let table: Vec<OptionsTableResponse> = priced
+        .options
+        .into_iter()
+        .map(|option| {
+            let max_quantity = if trading_state == TradingState::Open { option.max_quantity } else { 0.0 };
+            let pool_utilization_if_max = Utilization::new(
+                priced.total_existing_risk + option.margin_per_contract * max_quantity,
+                priced.total_collateral_usd,
+            )
+            .utilization_percent;
+            let mut warnings = Vec::new();
+            if option.iv_source == IvProvenance::Default {
+                warnings.push(OptionWarning::LowLiquidityIv);
+            }
+            if max_quantity <= 0.0 {
+                warnings.push(OptionWarning::NoCapacity);
+            }
+            warnings.extend(option.violations.into_iter().map(OptionWarning::from));
+            OptionsTableResponse {
+                underlying: asset,
+                side: option.side,
+                strike_price: option.strike_price,
+                expire: option.expire,
+                expires: option.expires,
+                premium: premium_currency.format(premium_currency.from_btc(option.quote.ask, btc_price)),
+                bid: premium_currency.format(premium_currency.from_btc(option.quote.bid, btc_price)),
+                mid: premium_currency.format(premium_currency.from_btc(option.quote.mid, btc_price)),
+                premium_currency,
+                // Format as string with 8 decimals
+                max_quantity: format_btc(max_quantity),
+                iv: option.iv,
+                delta: option.delta,
+                margin_per_contract: option.margin_per_contract,
+                pool_utilization_if_max,
+                warnings,
+                generated_at,
+            }
+        })
+        .collect();
+
+    // Display formatted options table
+    println!("\n📊 Generated Options Table Summary:");
+    println!("   Total Options: {}", table.len());
+    println!("   Strike Prices: {} (from ${} to ${})", 
+        strike_prices.len(), 
+        strike_prices.first().unwrap_or(&0.0),
+        strike_prices.last().unwrap_or(&0.0)
+    );
+    println!("   Expiries: {}", expires.len());
+    println!("   Current {} Price: ${:.2}", asset, spot_price);
+    
+    // Create formatted table display
+    println!("\n🎯 Options Table:");
+    println!("{:-<140}", "-");
+    println!("{:<6} {:<10} {:<10} {:<12} {:<12} {:<10} {:<10} {:<12}", 
+        "Type", "Strike", "Expiry", format!("Premium({})", premium_currency), "Max Qty", "IV", "Delta", "Value(USD)");
+    println!("{:-<140}", "-");
+    
+    // Group by expiry for better display
+    for expire in &expires {
+        println!("\n📅 Expiry: {}", expire);
+        
+        // Sort options for this expiry by strike price
+        let mut expiry_options: Vec<&OptionsTableResponse> = table.iter()
+            .filter(|opt| opt.expire == *expire)
+            .collect();
+        expiry_options.sort_by(|a, b| {
+            a.strike_price.partial_cmp(&b.strike_price).unwrap()
+                .then(a.side.to_string().cmp(&b.side.to_string()))
+        });
+        
+        for opt in expiry_options {
+            // Convert string premium to float only for calculation
+            let premium_f64 = db_string_to_float(&opt.premium).unwrap_or(0.0);
+            let option_value = premium_currency.to_btc(premium_f64, btc_price) * btc_price;
+            println!("{:<6} ${:<9.0} {:<10} {:<12} {:<11} {:<9.4} {:<9.4} ${:<11.2}", 
+                format!("{}", opt.side),
+                opt.strike_price,
+                opt.expire,
+                opt.premium,     // Already formatted string
+                opt.max_quantity, // Already formatted string
+                opt.iv,
+                opt.delta,
+                option_value
+            );
+        }
+    }
+    
+    println!("{:-<140}", "-");
+    println!("\n💰 Pool Information:");
+    println!("   Pool Balance: {} BTC (${:.2} USD)", pool_qty, pool_qty * btc_price);
+    println!("   Collateral Rate: {:.0}%", collateral_rate * 100.0);
+    
+    Ok(state.options_table_cache.insert(cache_key, source_versions, table))
+}
+
+// GET /products - The listed product catalog
+async fn get_products(
+    query: web::Query<ProductsQuery>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    let (status, asset) = (query.status, query.asset);
+    let products = state
+        .repository
+        .read(move |conn| catalog::load_products(conn, Some(status), asset))
+        .await?;
+    Ok(HttpResponse::Ok().json(products))
+}
+
+// GET /orderbook - The pool's live resting quotes on one underlying. Once they have all
+// expired or filled, the grid is repriced and a fresh set is posted, unless trading is not open.
+async fn get_orderbook(
+    query: web::Query<AssetQuery>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    let asset = query.asset;
+    state.check_asset(asset)?;
+    let now = Utc::now().timestamp();
+
+    let mut quotes = state.repository.run(move |conn| orderbook::load_live_quotes(conn, asset, now)).await?;
+    let trading_open = state.repository.run(|conn| Ok(trading_state::load(conn)?)).await?.state.check_open_position().is_ok();
+    if quotes.is_empty() && trading_open {
+        // Offers are only posted on prices that pass the trade-time guards
+        let spot_prices = state.guarded_spot_prices(&[Asset::Btc, asset]).await?;
+        let grid = state.options_grid.for_asset(asset);
+        let priced = price_grid(&state, asset, &grid, spot_prices[&asset], spot_prices[&Asset::Btc], now).await?;
+        let new_quotes: Vec<NewQuote> = priced
+            .options
+            .into_iter()
+            .map(|option| {
+                let (price, size) = state.orderbook.offer(option.quote.ask, option.max_quantity);
+                NewQuote {
+                    underlying: asset,
+                    side: option.side,
+                    strike_price: option.strike_price,
+                    expires: option.expires,
+                    price,
+                    size,
+                    iv: option.iv,
+                }
+            })
+            .collect();
+        let ttl_secs = state.orderbook.quote_ttl_secs;
+        quotes = state
+            .repository
+            .run(move |conn| orderbook::post_quotes(conn, asset, &new_quotes, now, ttl_secs))
+            .await?;
+        println!("📗 Posted {} {} resting quotes for {}s", quotes.len(), asset, ttl_secs);
+    }
+
+    let book: Vec<OrderbookQuoteResponse> = quotes
+        .into_iter()
+        .map(|quote| OrderbookQuoteResponse {
+            product_symbol: product_symbol(quote.underlying, &quote.side, quote.strike_price, quote.expires),
+            legacy_product_symbol: legacy_product_symbol(quote.underlying, &quote.side, quote.strike_price, quote.expires),
+            quote,
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(book))
+}
+
+// POST /orderbook/take - Buy from a resting quote at its posted price. The contract is
+// checked like POST /contract and the quote is filled in the same transaction.
+async fn post_take_quote(
+    req: HttpRequest,
+    body: web::Json<TakeQuoteRequest>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    let actor = require_role(&req, &state, Role::Trader).await?;
+    let TakeQuoteRequest { quote_id, quantity, payment_method, referral_code } = body.into_inner();
+    let quote = state.repository.run(move |conn| orderbook::load_quote(conn, quote_id)).await?;
+
+    let contract = Contract {
+        underlying: quote.underlying,
+        side: quote.side,
+        strike_price: quote.strike_price,
+        quantity,
+        expires: quote.expires,
+        premium: quote.price,
+    };
+    // Resting quotes are sized against the default pool
+    let AcceptedContract { contract_id, payment, fee, risk } = accept_contract(
+        &state,
+        pools::DEFAULT_POOL_ID,
+        actor,
+        contract,
+        QuoteCurrency::Btc,
+        ExerciseStyle::European,
+        payment_method,
+        referral_code,
+        Some(quote_id),
+    )
+    .await?;
+    let quote = state.repository.run(move |conn| orderbook::load_quote(conn, quote_id)).await?;
+    println!("📕 Quote {} taken for {:.8}, {:.8} left", quote_id, quantity, quote.remaining);
+
+    Ok(HttpResponse::Ok().json(TakeQuoteResponse { contract_id, quote, payment, fee, risk }))
+}
+
+// One product of the options grid priced at the current spot and IV
+struct PricedOption {
+    side: OptionSide,
+    strike_price: f64,
+    expire: String,     // Tenor, e.g. "1d"
+    expires: i64,       // Unix timestamp the tenor ends at
+    iv: f64,
+    iv_source: IvProvenance,
+    delta: f64,
+    quote: Quote,       // In BTC; buyers pay the ask
+    margin_per_contract: f64,  // USD margin of one option sold
+    max_quantity: f64,  // Largest quantity the pool can sell given the open book
+    violations: Vec<Violation>,  // Arbitrage found in the quote
+}
+
+// The priced grid and the collateral figures its max quantities were derived from
+struct PricedGrid {
+    pool_qty: f64,
+    collateral_rate: f64,
+    risk_margin: f64,
+    total_collateral_usd: f64,
+    total_existing_risk: f64,
+    options: Vec<PricedOption>,
+}
+
+// Price every product of `grid` with Black-Scholes at the oracle IV, quote it around that
+// mid shaded with the open book, and size it against the collateral left over by the book
+// of the default pool. Shared by /optionsTable and the orderbook.
+async fn price_grid(
+    state: &AppState,
+    asset: Asset,
+    grid: &GridConfig,
+    spot_price: f64,
+    btc_price: f64,
+    now: i64,
+) -> Result<PricedGrid, ApiError> {
+    // Get financial parameters
+    let rates = state.rates();
+    let pool = state.default_pool();
+    let collateral_rate = pool.collateral_rate;
+
+    // Get real pool balance from Mutiny wallet (actual BTC balance from blockchain)
+    let pool_qty: f64 = state.pool_balance_btc(&pool).await?;
+
+    // Initialize risk manager with the pool's safety margin
+    let risk_margin = pool.risk_margin;
+    let risk_manager = state.risk_manager(risk_margin);
+
+    // Get existing contracts to calculate current risk exposure
+    let existing_contracts = state.repository.pool_active_contracts(pool.id, now).await?;
+
+    // Calculate total existing risk exposure
+    let spot_prices = HashMap::from([(Asset::Btc, btc_price), (asset, spot_price)]);
+    let spot_prices = state.book_spot_prices(&existing_contracts, spot_prices).await?;
+    let total_existing_risk =
+        book_risk_blocking(state, &risk_manager, existing_contracts.clone(), &spot_prices).await?;
+
+    // Calculate available collateral
+    let total_collateral_usd = pool_qty * btc_price * collateral_rate;
+    let available_collateral_usd = total_collateral_usd - total_existing_risk;
+
+    // The open book per product, which the quotes are shaded and skewed with
+    let ivs = state.ivs();
+    let position_delta = |position: &Position| {
+        let iv = ivs.option_iv(asset, position.side, position.strike_price, position.expires).value;
+        let t = year_fraction(position.expires, now);
+        let risk_free_rate = rates.rate_to(position.expires, now);
+        let pricing_spot = state.pricing_spot(asset, spot_price, risk_free_rate, position.expires, now);
+        pricing::option_delta(&position.side, pricing_spot, position.strike_price, risk_free_rate, iv, t)
+    };
+    let positions = aggregate_positions(&existing_contracts, now);
+    let book = BookExposure::new(&positions, asset, spot_price, total_collateral_usd, position_delta);
+
+    let mut options = Vec::new();
+    let sides = [OptionSide::Call, OptionSide::Put];
+
+    for strike_price in grid.strikes(spot_price) {
+        for expire in &grid.tenors {
+            for side in &sides {
+                // Get IV from oracle
+                let side_str = match side {
+                    OptionSide::Call => "C",
+                    OptionSide::Put => "P",
+                };
+
+                // Snapped to the exchange expiry time; the IV oracle is keyed by it in milliseconds
+                let Some(expires) = grid.expiry(expire, now) else { continue };
+                let expire_for_iv = (expires * 1000).to_string();
+
+                // Get IV from cache (should be pre-populated)
+                let iv_quote = ivs.lookup(asset, side_str, strike_price, &expire_for_iv);
+                let iv = iv_quote.value;
+
+                let t = year_fraction(expires, now);
+                let risk_free_rate = rates.rate_to(expires, now);
+
+                // Calculate premium using Black-Scholes (returns USD value)
+                let pricing_spot = state.pricing_spot(asset, spot_price, risk_free_rate, expires, now);
+                let premium_usd = pricing::option_price(side, pricing_spot, strike_price, risk_free_rate, iv, t);
+                let greeks = pricing::option_greeks(side, pricing_spot, strike_price, risk_free_rate, iv, t);
+
+                // Quote around the mid and convert from USD to BTC; the pool sells at the ask
+                let quote = state.quoting.quote(premium_usd, *side, strike_price, &greeks, &book).scaled(1.0 / btc_price);
+                let premium_btc = quote.ask;
+
+                // Calculate risk-based max_quantity considering:
+                // 1. Option-specific risk (max loss potential)
+                // 2. Existing portfolio risk exposure
+                // 3. Available collateral after risk margin
+                let margin_per_contract = risk_manager
+                    .calculate_position_risk(side, strike_price, premium_btc, 1.0, spot_price, iv, t, risk_free_rate)
+                    .margin_required;
+                // 4. Whether the IV policy lets the option trade at all
+                let max_quantity = if state.iv_policy.allows_trade(&iv_quote) {
+                    risk_manager.calculate_max_quantity(
+                        side,
+                        strike_price,
+                        premium_btc,
+                        spot_price,
+                        iv,
+                        t,
+                        risk_free_rate,
+                        available_collateral_usd,
+                        total_existing_risk,
+                    )
+                } else {
+                    0.0
+                };
+
+                options.push(PricedOption {
+                    side: *side,
+                    strike_price,
+                    expire: expire.clone(),
+                    expires,
+                    iv,
+                    iv_source: iv_quote.source,
+                    delta: greeks.delta,
+                    quote,
+                    margin_per_contract,
+                    max_quantity,
+                    violations: Vec::new(),
+                });
+            }
+        }
+    }
+
+    // Patchy IV data must not quote free money; margins stay those of the model asks, which
+    // adjustments only raise
+    let mut quoted: Vec<QuotedOption> = options
+        .iter()
+        .map(|option| QuotedOption {
+            side: option.side,
+            strike_price: option.strike_price,
+            expires: option.expires,
+            quote: option.quote,
+            violations: Vec::new(),
+        })
+        .collect();
+    let market = quote_sanity::Market { spot_price, rates, carry: state.basis.carry(asset), scale: 1.0 / btc_price, now };
+    quote_sanity::check(state.quoting.sanity, &mut quoted, &market);
+    for (option, checked) in options.iter_mut().zip(quoted) {
+        option.quote = checked.quote;
+        option.violations = checked.violations;
+    }
+
+    Ok(PricedGrid {
+        pool_qty,
+        collateral_rate,
+        risk_margin,
+        total_collateral_usd,
+        total_existing_risk,
+        options,
+    })
+}
+
+// Open contracts on one underlying
+async fn active_contracts_for(state: &AppState, asset: Asset, now: i64) -> Result<Vec<Contract>, ApiError> {
+    let mut contracts = state.repository.active_contracts(now).await?;
+    contracts.retain(|c| c.underlying == asset);
+    Ok(contracts)
+}
+
+// GET /positions - Open book aggregated per product with marks and margin
+async fn get_positions(
+    query: web::Query<AssetFilter>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    let now = Utc::now().timestamp();
+    let mut contracts = state.repository.active_contracts(now).await?;
+    if let Some(asset) = query.asset {
+        contracts.retain(|c| c.underlying == asset);
+    }
+
+    let rates = state.rates();
+    let risk_margin = env::var("RISK_MARGIN")
+        .unwrap_or_else(|_| "1.2".to_string())
+        .parse()
+        .unwrap_or(1.2);
+    let risk_manager = state.risk_manager(risk_margin);
+
+    let positions = aggregate_positions(&contracts, now);
+    let spot_prices = state.book_spot_prices(&contracts, HashMap::new()).await?;
+    let btc_price = spot_prices[&Asset::Btc];
+    let ivs = state.ivs();
+
+    let mut rows = Vec::with_capacity(positions.len());
+    for position in positions {
+        let spot_price = spot_prices[&position.underlying];
+        let t = year_fraction(position.expires, now);
+        let risk_free_rate = rates.rate_to(position.expires, now);
+        let iv = ivs.option_iv(position.underlying, position.side, position.strike_price, position.expires).value;
+
+        let pricing_spot = state.pricing_spot(position.underlying, spot_price, risk_free_rate, position.expires, now);
+        let mark_premium_usd = pricing::option_price(&position.side, pricing_spot, position.strike_price, risk_free_rate, iv, t);
+        let mark_value_usd = mark_premium_usd * position.net_quantity;
+        let margin_usd = if position.net_quantity > 0.0 {
+            risk_manager
+                .calculate_position_risk(
+                    &position.side,
+                    position.strike_price,
+                    position.average_premium,
+                    position.net_quantity,
+                    spot_price,
+                    iv,
+                    t,
+                    risk_free_rate,
+                )
+                .margin_required
+        } else {
+            0.0
+        };
+
+        let expire = format_expires_timestamp(position.expires);
+        rows.push(PositionResponse {
+            product_symbol: product_symbol(position.underlying, &position.side, position.strike_price, position.expires),
+            legacy_product_symbol: legacy_product_symbol(position.underlying, &position.side, position.strike_price, position.expires),
+            expire,
+            iv,
+            mark_premium_usd,
+            mark_premium_btc: mark_premium_usd / btc_price,
+            mark_value_usd,
+            unrealized_pnl_usd: position.average_premium * position.net_quantity * btc_price - mark_value_usd,
+            margin_usd,
+            position,
+        });
+    }
+
+    Ok(HttpResponse::Ok().json(rows))
+}
+
+// GET /delta - Calculate portfolio delta for one underlying
+async fn get_delta(
+    query: web::Query<AssetQuery>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    let now = Utc::now().timestamp();
+    let asset = query.asset;
+    let contracts = active_contracts_for(&state, asset, now).await?;
+
+    if contracts.is_empty() {
+        return Ok(HttpResponse::Ok().json(0.0));
+    }
+
+    let spot_price = state.spot_price(asset).await?;
+
+    let rates = state.rates();
+
+    let ivs = state.ivs();
+    let mut total_delta = 0.0;
+
+    for contract in contracts.iter() {
+        let t = year_fraction(contract.expires, now);
+        let iv = ivs.option_iv(asset, contract.side, contract.strike_price, contract.expires).value;
+
+        let risk_free_rate = rates.rate_to(contract.expires, now);
+        let pricing_spot = state.pricing_spot(asset, spot_price, risk_free_rate, contract.expires, now);
+        let delta = pricing::option_delta(&contract.side, pricing_spot, contract.strike_price, risk_free_rate, iv, t);
+
+        total_delta += delta * contract.quantity;
+    }
+
+    Ok(HttpResponse::Ok().json(total_delta))
+}
+
+// GET /ivSurface/fit - SVI parameters and fit quality of each expiry of the IV surface
+async fn get_iv_surface_fit(
+    query: web::Query<AssetFilter>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    let mut fits = state.iv_oracle.surface_fits();
+    if let Some(asset) = query.asset {
+        fits.retain(|fit| fit.asset == asset);
+    }
+    Ok(HttpResponse::Ok().json(fits))
+}
+
+// GET /oracle/prices - Latest price of each exchange feed behind the spot price, and whether it is live
+async fn get_oracle_prices(
+    query: web::Query<OracleSourcesQuery>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    let OracleSourcesQuery { asset, sources } = query.into_inner();
+    state.check_asset(asset)?;
+    let sources: Vec<String> = sources
+        .unwrap_or_default()
+        .split(',')
+        .map(|source| source.trim().to_lowercase())
+        .filter(|source| !source.is_empty())
+        .collect();
+
+    let limit = state.timeouts.price_oracle;
+    let (price, prices) = futures::join!(
+        deadline("Price oracle", limit, async {
+            state.price_oracle.get_price_from(asset, &sources).await.map_err(|e| ApiError::PriceOracleError(e.to_string()))
+        }),
+        deadline("Price oracle", limit, async {
+            state.price_oracle.source_prices(asset, &sources).await.map_err(|e| ApiError::PriceOracleError(e.to_string()))
+        }),
+    );
+    Ok(HttpResponse::Ok().json(OracleSourcesResponse {
+        asset,
+        error: price.as_ref().err().map(|e| e.to_string()),
+        price: price.ok(),
+        sources: health::source_health(prices?, state.price_guards.max_age, Utc::now().timestamp()),
+    }))
+}
+
+// GET /realizedVol - Rolling realized volatility from spot history
+async fn get_realized_vol(
+    query: web::Query<AssetQuery>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    let asset = query.asset;
+    let realized = state
+        .repository
+        .read(move |conn| {
+            vol::REALIZED_VOL_WINDOWS
+                .iter()
+                .map(|window| Ok(vol::realized_vol(conn, asset, window)?))
+                .collect::<Result<Vec<_>, ApiError>>()
+        })
+        .await?;
+
+    Ok(HttpResponse::Ok().json(realized))
+}
+
+// GET /risk/var - Portfolio Value-at-Risk and Expected Shortfall
+async fn get_var(
+    query: web::Query<VarQuery>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    let horizon_days = query.horizon_days.unwrap_or(1.0);
+    if !(horizon_days > 0.0 && horizon_days <= 30.0) {
+        return Err(ApiError::ValidationError(
+            "horizon_days must be greater than 0 and at most 30.".to_string(),
+        ));
+    }
+
+    let now = Utc::now().timestamp();
+    let asset = query.asset;
+    let contracts = active_contracts_for(&state, asset, now).await?;
+
+    let spot_price = state.spot_price(asset).await?;
+
+    let rates = state.rates();
+    let risk_margin = env::var("RISK_MARGIN")
+        .unwrap_or_else(|_| "1.2".to_string())
+        .parse()
+        .unwrap_or(1.2);
+    let risk_manager = state.risk_manager(risk_margin);
+
+    // Shock spot with 7d realized vol, falling back to a conservative default
+    let realized_vol_7d = state.repository.realized_vol(asset, "7d").await?.close_to_close;
+    let spot_vol = realized_vol_7d.filter(|v| *v > 0.0).unwrap_or(0.6);
+
+    let ivs = state.ivs();
+    let iv_oracle_closure = |side_str: &str, strike: f64, expire: &str| Some(ivs.lookup(asset, side_str, strike, expire).value);
+
+    let var = risk_manager.calculate_var(
+        &contracts,
+        spot_price,
+        &rates,
+        &iv_oracle_closure,
+        spot_vol,
+        horizon_days,
+    );
+
+    Ok(HttpResponse::Ok().json(var))
+}
+
+// POST /risk/scenario - Reprice the open book under spot/IV stress scenarios
+async fn post_risk_scenario(
+    request: web::Json<ScenarioRequest>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    if request.scenarios.is_empty() || request.scenarios.len() > 50 {
+        return Err(ApiError::ValidationError(
+            "Between 1 and 50 scenarios must be provided.".to_string(),
+        ));
+    }
+    if let Some(bad) = request.scenarios.iter().find(|s| s.spot_move_percent <= -100.0) {
+        return Err(ApiError::ValidationError(format!(
+            "spot_move_percent must be greater than -100 (got {}).",
+            bad.spot_move_percent
+        )));
+    }
+
+    let now = Utc::now().timestamp();
+    let asset = request.asset;
+    // Shocks the book of the default pool against its collateral
+    let pool = state.default_pool();
+    let book = state.repository.pool_active_contracts(pool.id, now).await?;
+    let (contracts, other_contracts): (Vec<Contract>, Vec<Contract>) =
+        book.iter().cloned().partition(|c| c.underlying == asset);
+
+    let spot_prices = HashMap::from([(asset, state.spot_price(asset).await?)]);
+    let spot_prices = state.book_spot_prices(&book, spot_prices).await?;
+    let btc_price = spot_prices[&Asset::Btc];
+    let spot_price = spot_prices[&asset];
+    let pool_qty: f64 = state.pool_balance_btc(&pool).await?;
+
+    let rates = state.rates();
+    let collateral_rate = pool.collateral_rate;
+    let risk_manager = state.risk_manager(pool.risk_margin);
+
+    let ivs = state.ivs();
+    let iv_oracle_closure = |side_str: &str, strike: f64, expire: &str| Some(ivs.lookup(asset, side_str, strike, expire).value);
+
+    // Positions on other underlyings keep their current margin in every scenario
+    let current_margin = book_risk(&risk_manager, &book, &spot_prices, &rates, &ivs)?;
+    let other_margin = book_risk(&risk_manager, &other_contracts, &spot_prices, &rates, &ivs)?;
+
+    let results: Vec<ScenarioResponse> = request
+        .scenarios
+        .iter()
+        .enumerate()
+        .map(|(i, shock)| {
+            let impact = risk_manager.evaluate_scenario(
+                &contracts,
+                spot_price,
+                &rates,
+                &iv_oracle_closure,
+                shock.spot_move_percent,
+                shock.iv_shift,
+            );
+            let margin_required_usd = impact.margin_required_usd + other_margin;
+            // Pool collateral is held in BTC, so its USD value moves with a BTC shock
+            let collateral_price = if asset == Asset::Btc { impact.shocked_spot_price } else { btc_price };
+            let collateral_usd = pool_qty * collateral_price * collateral_rate;
+
+            ScenarioResponse {
+                name: shock.name.clone().unwrap_or_else(|| format!("scenario_{}", i + 1)),
+                spot_move_percent: shock.spot_move_percent,
+                iv_shift: shock.iv_shift,
+                spot_price: impact.shocked_spot_price,
+                pnl_usd: impact.pnl_usd,
+                margin_required_usd,
+                margin_change_usd: margin_required_usd - current_margin,
+                collateral_usd,
+                excess_collateral_usd: collateral_usd - margin_required_usd,
+            }
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(results))
+}
+
+// GET /topBanner - Market statistics
+async fn get_top_banner(
+    query: web::Query<AssetFilter>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    Ok(HttpResponse::Ok().json(top_banner(&state, query.asset).await?))
+}
+
+pub(crate) async fn top_banner(state: &AppState, asset: Option<Asset>) -> Result<TopBannerResponse, ApiError> {
+    let now = Utc::now().timestamp();
+    let volume_24hr = state.rolling_metrics.volume(now, MetricsWindow::TwentyFourHours, asset);
+
+    let (open_interest_btc, contract_count) = state
+        .repository
+        .read(move |conn| {
+            Ok((
+                repository::open_interest_btc(conn, now, asset)?,
+                repository::active_contract_count(conn, now, asset)?,
+            ))
+        })
+        .await?;
+
+    let btc_price = state.spot_price(Asset::Btc).await?;
+    
+    let open_interest_usd = open_interest_btc * btc_price;
+
+    Ok(TopBannerResponse {
+        volume_24hr,
+        open_interest_usd,
+        contract_count,
+    })
+}
+
+// GET /marketHighlights - Top products by volume
+async fn get_market_highlights(
+    query: web::Query<AssetFilter>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    Ok(HttpResponse::Ok().json(market_highlights(&state, query.asset).await?))
+}
+
+pub(crate) async fn market_highlights(state: &AppState, asset: Option<Asset>) -> Result<Vec<MarketHighlightItem>, ApiError> {
+    let now = Utc::now().timestamp();
+    let mut products: Vec<ProductMetrics> = state
+        .rolling_metrics
+        .products(now, MetricsWindow::TwentyFourHours, asset)
+        .into_iter()
+        .filter(|metrics| metrics.trades > 0)
+        .collect();
+    products.sort_by(|a, b| b.volume.total_cmp(&a.volume));
+    products.truncate(6);
+
+    let mut highlights = Vec::new();
+
+    for metrics in products {
+        let product = metrics.product;
+        let strike_price = cents_to_usd(product.strike_price_cents);
+        let current_premium = metrics.avg_premium.unwrap_or(0.0);
+        // Premium from 24 hours ago, 0.0 when unknown
+        let premium_24hr_ago = metrics.baseline_premium.unwrap_or(0.0);
+
+        let price_change_percent = if premium_24hr_ago > 0.0 {
+            ((current_premium - premium_24hr_ago) / premium_24hr_ago) * 100.0
+        } else {
+            0.0
+        };
+
+        let expire_string = format_expires_timestamp(product.expires);
+
+        highlights.push(MarketHighlightItem {
+            product_symbol: product_symbol(product.underlying, &product.side, strike_price, product.expires),
+            legacy_product_symbol: legacy_product_symbol(product.underlying, &product.side, strike_price, product.expires),
+            side: product.side,
+            strike_price,
+            expire: expire_string,
+            expires: product.expires,
+            volume_24hr: metrics.volume,
+            price_change_24hr_percent: price_change_percent,
+        });
+    }
+
+    Ok(highlights)
+}
+
+// GET /topGainers - Top gainers by percentage
+async fn get_top_gainers(
+    query: web::Query<LeaderboardQuery>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    Ok(HttpResponse::Ok().json(top_gainers(&state, query.asset, query.window).await?))
+}
+
+// GET /topLosers - Top losers by percentage
+async fn get_top_losers(
+    query: web::Query<LeaderboardQuery>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    Ok(HttpResponse::Ok().json(top_losers(&state, query.asset, query.window).await?))
+}
+
+pub(crate) async fn top_gainers(state: &AppState, asset: Option<Asset>, window: MetricsWindow) -> Result<Vec<TopGainerItem>, ApiError> {
+    let mut changes = premium_changes(state, asset, window);
+    changes.sort_by(|a, b| b.change_percent.total_cmp(&a.change_percent));
+    changes.truncate(5);
+    Ok(changes)
+}
+
+pub(crate) async fn top_losers(state: &AppState, asset: Option<Asset>, window: MetricsWindow) -> Result<Vec<TopGainerItem>, ApiError> {
+    let mut changes = premium_changes(state, asset, window);
+    changes.sort_by(|a, b| a.change_percent.total_cmp(&b.change_percent));
+    changes.truncate(5);
+    Ok(changes)
+}
+
+// Premium change over `window` of every live product, in product order
+fn premium_changes(state: &AppState, asset: Option<Asset>, window: MetricsWindow) -> Vec<TopGainerItem> {
+    let now = Utc::now().timestamp();
+    state
+        .rolling_metrics
+        .products(now, window, asset)
+        .into_iter()
+        .filter(|metrics| metrics.product.expires > now)
+        .filter_map(|metrics| {
+            // Products with no earlier premium compare against their first trade in the window
+            let change_percent = metrics.change_percent()?;
+            let product = metrics.product;
+            let strike_price = cents_to_usd(product.strike_price_cents);
+            Some(TopGainerItem {
+                product_symbol: product_symbol(product.underlying, &product.side, strike_price, product.expires),
+                legacy_product_symbol: legacy_product_symbol(product.underlying, &product.side, strike_price, product.expires),
+                side: product.side,
+                strike_price,
+                expire: format_expires_timestamp(product.expires),
+                expires: product.expires,
+                window,
+                change_percent,
+                change_24hr_percent: change_percent,
+                last_price: metrics.last_premium?,
+            })
+        })
+        .collect()
+}
+
+// GET /topVolume - Top products by volume
+async fn get_top_volume(
+    query: web::Query<LeaderboardQuery>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    Ok(HttpResponse::Ok().json(top_volume(&state, query.asset, query.window).await?))
+}
+
+pub(crate) async fn top_volume(state: &AppState, asset: Option<Asset>, window: MetricsWindow) -> Result<Vec<TopVolumeItem>, ApiError> {
+    let now = Utc::now().timestamp();
+
+    let btc_price = state.spot_price(Asset::Btc).await?;
+
+    // Ranked by BTC notional (quantity * premium)
+    let mut products: Vec<ProductMetrics> = state
+        .rolling_metrics
+        .products(now, window, asset)
+        .into_iter()
+        .filter(|metrics| metrics.trades > 0)
+        .collect();
+    products.sort_by(|a, b| b.notional_btc.total_cmp(&a.notional_btc));
+    products.truncate(5);
+
+    let mut top_volume = Vec::new();
+
+    for metrics in products {
+        let product = metrics.product;
+        let expire_string = format_expires_timestamp(product.expires);
+        let strike_price = cents_to_usd(product.strike_price_cents);
+
+        top_volume.push(TopVolumeItem {
+            product_symbol: product_symbol(product.underlying, &product.side, strike_price, product.expires),
+            legacy_product_symbol: legacy_product_symbol(product.underlying, &product.side, strike_price, product.expires),
+            side: product.side,
+            strike_price,
+            expire: expire_string,
+            expires: product.expires,
+            window,
+            volume_usd: metrics.notional_btc * btc_price,
+            last_price: metrics.avg_premium.unwrap_or(0.0),
+        });
+    }
+
+    Ok(top_volume)
+}
+
+// GET /volMovers - Products whose IV moved the most
+async fn get_vol_movers(
+    query: web::Query<VolMoversQuery>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    let now = Utc::now().timestamp();
+    let (asset, window) = (query.asset, query.window);
+    let limit = query.limit.unwrap_or(10).clamp(1, iv_history::MAX_VOL_MOVERS);
+    let since = now - window.secs();
+    let movers = state
+        .repository
+        .read(move |conn| iv_history::vol_movers(conn, now, since, asset, limit))
+        .await?;
+
+    let items: Vec<VolMoverItem> = movers
+        .into_iter()
+        .map(|mover| VolMoverItem {
+            product_symbol: product_symbol(mover.underlying, &mover.side, mover.strike_price, mover.expires),
+            legacy_product_symbol: legacy_product_symbol(mover.underlying, &mover.side, mover.strike_price, mover.expires),
+            underlying: mover.underlying,
+            side: mover.side,
+            strike_price: mover.strike_price,
+            expire: format_expires_timestamp(mover.expires),
+            expires: mover.expires,
+            window,
+            iv: mover.iv,
+            iv_before: mover.iv_before,
+            iv_change: mover.change(),
+            iv_change_percent: mover.change() / mover.iv_before * 100.0,
+            snapshot_at: mover.snapshot_at,
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(items))
+}
+
+// GET /stats/history - Hourly volume, open interest and notional snapshots for charting
+async fn get_stats_history(
+    query: web::Query<StatsHistoryQuery>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    let now = Utc::now().timestamp();
+    let bound = |value: &Option<String>, upper: bool, default: i64| match value {
+        Some(value) => export::parse_date_bound(value, upper).map_err(ApiError::ValidationError),
+        None => Ok(default),
+    };
+    let from = bound(&query.from, false, now - 7 * 24 * 60 * 60)?;
+    let to = bound(&query.to, true, now)?;
+    if from >= to {
+        return Err(ApiError::ValidationError("from must be before to".to_string()));
+    }
+    if to - from > 366 * 24 * 60 * 60 {
+        return Err(ApiError::ValidationError("Range must not exceed 366 days".to_string()));
+    }
+
+    let asset = query.asset;
+    let history = state
+        .repository
+        .read(move |conn| stats::load_market_stats(conn, asset, from, to))
+        .await?;
+
+    Ok(HttpResponse::Ok().json(history))
+}
+
+// GET /spot/history - OHLC candles of the sampled spot price for charting
+async fn get_spot_history(
+    query: web::Query<SpotHistoryQuery>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<impl Responder, ApiError> {
+    let SpotHistoryQuery { asset, from, to, interval } = query.into_inner();
+    state.check_asset(asset)?;
+    let now = Utc::now().timestamp();
+    let bound = |value: Option<String>, upper: bool, default: i64| match value {
+        Some(value) => export::parse_date_bound(&value, upper).map_err(ApiError::ValidationError),
+        None => Ok(default),
+    };
+    let from = bound(from, false, now - 7 * 24 * 60 * 60)?;
+    let to = bound(to, true, now)?;
+    if from >= to {
+        return Err(ApiError::ValidationError("from must be before to".to_string()));
+    }
+    let interval = interval.unwrap_or_else(|| "1h".to_string());
+    let interval_secs = duration_to_seconds(&interval);
+    if interval_secs <= 0 {
+        return Err(ApiError::ValidationError(format!(
+            "Invalid interval '{}', expected minutes, hours or days such as 5m, 1h or 1d",
+            interval
+        )));
+    }
+    let candle_count = (to - from + interval_secs - 1) / interval_secs;
+    if candle_count > price_history::MAX_CANDLES {
+        return Err(ApiError::ValidationError(format!(
+            "Range spans {} candles of {}, at most {} are returned; use a longer interval",
+            candle_count, interval, price_history::MAX_CANDLES
+        ))
+        .with_details(json!({"value": candle_count, "max": price_history::MAX_CANDLES})));
+    }
+
+    let candles = state
+        .repository
+        .read(move |conn| price_history::candles(conn, asset, from, to, interval_secs))
+        .await?;
+    Ok(HttpResponse::Ok().json(SpotHistoryResponse { asset, from, to, interval_secs, candles }))
+}
@@ -0,0 +1,107 @@
+// API keys for machine clients.
+// Keys are random 32-byte tokens shown once at creation; only their SHA-256 hash is stored.
+
+use crate::audit;
+use chrono::Utc;
+use rand::RngCore;
+use rusqlite::{params, Connection, OptionalExtension, Result};
+use sha2::{Digest, Sha256};
+
+pub const API_KEY_HEADER: &str = "X-API-Key";
+
+const KEY_PREFIX: &str = "bom_";
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn generate_api_key() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("{}{}", KEY_PREFIX, to_hex(&bytes))
+}
+
+pub fn hash_api_key(key: &str) -> String {
+    to_hex(&Sha256::digest(key.as_bytes()))
+}
+
+/// Revoke every active key registered under `name` and issue a new one, recording `actor`
+/// in the audit log. Returns the new plaintext key, which is not recoverable afterwards.
+pub fn rotate_api_key(conn: &Connection, name: &str, actor: &str) -> Result<String> {
+    let key = generate_api_key();
+    let now = Utc::now().timestamp();
+
+    let tx = conn.unchecked_transaction()?;
+    let revoked = tx.execute(
+        "UPDATE api_keys SET revoked_at = ?1 WHERE name = ?2 AND revoked_at IS NULL",
+        params![now, name],
+    )?;
+    tx.execute(
+        "INSERT INTO api_keys (name, key_hash, created_at) VALUES (?1, ?2, ?3)",
+        params![name, hash_api_key(&key), now],
+    )?;
+    let key_id = tx.last_insert_rowid();
+    // Never log the key or its hash
+    audit::record(
+        &tx,
+        actor,
+        audit::API_KEY_ROTATE,
+        Some(key_id),
+        Some(&serde_json::json!({ "name": name, "active_keys": revoked })),
+        Some(&serde_json::json!({ "name": name, "active_keys": 1 })),
+    )?;
+    tx.commit()?;
+
+    Ok(key)
+}
+
+/// Whether any unrevoked key exists
+pub fn has_active_keys(conn: &Connection) -> Result<bool> {
+    conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM api_keys WHERE revoked_at IS NULL)",
+        [],
+        |row| row.get(0),
+    )
+}
+
+pub fn verify_api_key(conn: &Connection, key: &str) -> Result<bool> {
+    Ok(api_key_name(conn, key)?.is_some())
+}
+
+/// Name an active key was issued under, or None if the key is unknown or revoked
+pub fn api_key_name(conn: &Connection, key: &str) -> Result<Option<String>> {
+    conn.query_row(
+        "SELECT name FROM api_keys WHERE key_hash = ?1 AND revoked_at IS NULL",
+        params![hash_api_key(key)],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotate_revokes_previous_key() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init_db(&conn).unwrap();
+        assert!(!has_active_keys(&conn).unwrap());
+
+        let first = rotate_api_key(&conn, "frontend", "test").unwrap();
+        assert!(has_active_keys(&conn).unwrap());
+        assert!(verify_api_key(&conn, &first).unwrap());
+
+        let second = rotate_api_key(&conn, "frontend", "test").unwrap();
+        assert_ne!(first, second);
+        assert!(!verify_api_key(&conn, &first).unwrap());
+        assert!(verify_api_key(&conn, &second).unwrap());
+        assert!(!verify_api_key(&conn, "bom_not_a_key").unwrap());
+        assert_eq!(api_key_name(&conn, &second).unwrap().as_deref(), Some("frontend"));
+
+        let rotations = audit::query(&conn, &audit::AuditFilter::default()).unwrap();
+        assert_eq!(rotations.len(), 2);
+        assert_eq!(rotations[0].action, audit::API_KEY_ROTATE);
+        assert!(!rotations[0].post_state.as_ref().unwrap().to_string().contains("bom_"));
+    }
+}
@@ -0,0 +1,103 @@
+// Version 1 of the HTTP API.
+// v1 is served under /v1 and, for clients from before versioning, without a prefix. It is
+// deprecated in favour of /v2 (see api_v2.rs), which shares its handlers. Every v1 response
+// says so: a Deprecation header (RFC 9745) dated API_V1_DEPRECATED_AT, by default the day /v2
+// was released, a Link to the same path under /v2, and once API_V1_SUNSET is set a Sunset
+// header (RFC 8594) with the date v1 will be removed.
+
+use crate::api::AppState;
+use crate::export::parse_date_bound;
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue, LINK};
+use actix_web::middleware::Next;
+use actix_web::{web, Error};
+use chrono::DateTime;
+use std::env;
+use std::sync::Arc;
+
+pub const PREFIX: &str = "/v1";
+
+// 2026-10-18, the day /v2 was released
+pub const DEFAULT_DEPRECATED_AT: i64 = 1_792_281_600;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DeprecationConfig {
+    pub deprecated_at: i64,      // Unix seconds
+    pub sunset_at: Option<i64>,  // Unix seconds v1 is removed at; no Sunset header until set
+}
+
+impl Default for DeprecationConfig {
+    fn default() -> Self {
+        Self { deprecated_at: DEFAULT_DEPRECATED_AT, sunset_at: None }
+    }
+}
+
+impl DeprecationConfig {
+    /// API_V1_DEPRECATED_AT (DEFAULT_DEPRECATED_AT) and API_V1_SUNSET (unset), each YYYY-MM-DD
+    /// or Unix seconds. Invalid dates are ignored.
+    pub fn from_env() -> Self {
+        let date = |name: &str| env::var(name).ok().and_then(|v| parse_date_bound(v.trim(), false).ok());
+        Self {
+            deprecated_at: date("API_V1_DEPRECATED_AT").unwrap_or(DEFAULT_DEPRECATED_AT),
+            sunset_at: date("API_V1_SUNSET"),
+        }
+    }
+
+    /// Headers of a v1 response to a request for `path`
+    pub fn headers(&self, path: &str) -> Vec<(HeaderName, String)> {
+        let v1_path = path.strip_prefix(PREFIX).filter(|rest| rest.is_empty() || rest.starts_with('/')).unwrap_or(path);
+        let mut headers = vec![
+            (HeaderName::from_static("deprecation"), format!("@{}", self.deprecated_at)),
+            (LINK, format!("<{}{}>; rel=\"successor-version\"", crate::api_v2::PREFIX, v1_path)),
+        ];
+        if let Some(sunset) = self.sunset_at.and_then(|at| DateTime::from_timestamp(at, 0)) {
+            headers.push((HeaderName::from_static("sunset"), sunset.format("%a, %d %b %Y %H:%M:%S GMT").to_string()));
+        }
+        headers
+    }
+}
+
+/// Middleware adding the deprecation headers to v1 responses; see the module comment
+pub async fn middleware(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let v2 = req.path().strip_prefix(crate::api_v2::PREFIX).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'));
+    let deprecation = match req.app_data::<web::Data<Arc<AppState>>>() {
+        Some(state) if !v2 => Some(*state.api_v1()),
+        _ => None,
+    };
+    let path = req.path().to_string();
+    let mut res = next.call(req).await?.map_into_boxed_body();
+    if let Some(deprecation) = deprecation {
+        for (name, value) in deprecation.headers(&path) {
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                res.headers_mut().insert(name, value);
+            }
+        }
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_headers() {
+        let header = |headers: &[(HeaderName, String)], name: &str| {
+            headers.iter().find(|(n, _)| n.as_str() == name).map(|(_, value)| value.clone())
+        };
+        let headers = DeprecationConfig::default().headers("/v1/contracts");
+        assert_eq!(header(&headers, "deprecation").as_deref(), Some("@1792281600"));
+        assert_eq!(header(&headers, "link").as_deref(), Some("</v2/contracts>; rel=\"successor-version\""));
+        assert_eq!(header(&headers, "sunset"), None);
+
+        let config = DeprecationConfig { deprecated_at: 1_735_689_600, sunset_at: Some(1_751_328_000) };
+        let headers = config.headers("/contract/7");
+        assert_eq!(header(&headers, "deprecation").as_deref(), Some("@1735689600"));
+        assert_eq!(header(&headers, "link").as_deref(), Some("</v2/contract/7>; rel=\"successor-version\""));
+        assert_eq!(header(&headers, "sunset").as_deref(), Some("Tue, 01 Jul 2025 00:00:00 GMT"));
+    }
+}
@@ -0,0 +1,129 @@
+// Version 2 of the HTTP API.
+// Every endpoint is served under /v2 with the same requests and handlers as v1. v2 JSON bodies
+// carry machine-readable values next to the display strings of v1, so clients need not parse
+// display text: each enum shown for display ("Call", "BTC", "ACT/365") gets a stable lowercase
+// code in a `<field>_code` field, and each `expires` Unix timestamp an ISO-8601 (RFC 3339, UTC)
+// `expires_iso` next to it and to any tenor string such as "2d". v1 responses are unchanged.
+// GraphQL, WebSocket and file export responses pass through as they are.
+
+use actix_web::body::{self, BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::middleware::Next;
+use actix_web::Error;
+use chrono::{DateTime, SecondsFormat};
+use serde_json::{Map, Value};
+
+pub const PREFIX: &str = "/v2";
+
+// Fields holding display strings of enums
+const CODED_FIELDS: &[&str] = &["side", "underlying", "asset", "currency", "premium_currency", "day_count"];
+
+// Stable code of an enum's display string
+fn code(display: &str) -> Option<&'static str> {
+    Some(match display {
+        "Call" => "call",
+        "Put" => "put",
+        "BTC" => "btc",
+        "ETH" => "eth",
+        "USD" => "usd",
+        "USDT" => "usdt",
+        "ACT/365" => "act_365",
+        "ACT/252" => "act_252",
+        _ => return None,
+    })
+}
+
+/// Add the v2 fields to every object in `value`; see the module comment
+pub fn annotate(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            object.values_mut().for_each(annotate);
+            annotate_object(object);
+        }
+        Value::Array(values) => values.iter_mut().for_each(annotate),
+        _ => {}
+    }
+}
+
+fn annotate_object(object: &mut Map<String, Value>) {
+    for field in CODED_FIELDS {
+        if let Some(code) = object.get(*field).and_then(Value::as_str).and_then(code) {
+            object.insert(format!("{}_code", field), Value::from(code));
+        }
+    }
+    let expires_iso = object
+        .get("expires")
+        .and_then(Value::as_i64)
+        .and_then(|expires| DateTime::from_timestamp(expires, 0))
+        .map(|expires| expires.to_rfc3339_opts(SecondsFormat::Secs, true));
+    if let Some(expires_iso) = expires_iso {
+        object.insert("expires_iso".to_string(), Value::from(expires_iso));
+    }
+}
+
+/// Middleware of the /v2 scope annotating its JSON responses
+pub async fn middleware(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let annotated = !req.path().starts_with(&format!("{}/graphql", PREFIX));
+    let res = next.call(req).await?;
+    let is_json = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    if !annotated || !is_json {
+        return Ok(res.map_into_boxed_body());
+    }
+
+    let (req, res) = res.into_parts();
+    let (head, body) = res.into_parts();
+    let bytes = body::to_bytes(body).await.map_err(|e| actix_web::error::ErrorInternalServerError(e.into()))?;
+    let bytes = match serde_json::from_slice::<Value>(&bytes) {
+        Ok(mut value) => {
+            annotate(&mut value);
+            serde_json::to_vec(&value).map_err(actix_web::error::ErrorInternalServerError)?
+        }
+        Err(_) => bytes.to_vec(),
+    };
+    let res = head.set_body(bytes).map_into_boxed_body();
+    Ok(ServiceResponse::new(req, res))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_annotate() {
+        let mut value = json!({
+            "contracts": [
+                {"side": "Put", "underlying": "ETH", "premium_currency": "USDT", "expires": 1735891200, "expire": "2d"},
+                {"side": "Sideways", "expires": null}
+            ],
+            "day_count": "ACT/365",
+            "status": "open"
+        });
+        annotate(&mut value);
+        assert_eq!(
+            value,
+            json!({
+                "contracts": [
+                    {
+                        "side": "Put", "side_code": "put",
+                        "underlying": "ETH", "underlying_code": "eth",
+                        "premium_currency": "USDT", "premium_currency_code": "usdt",
+                        "expires": 1735891200, "expires_iso": "2025-01-03T08:00:00Z",
+                        "expire": "2d"
+                    },
+                    {"side": "Sideways", "expires": null}
+                ],
+                "day_count": "ACT/365", "day_count_code": "act_365",
+                "status": "open"
+            })
+        );
+    }
+}
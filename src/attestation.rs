@@ -0,0 +1,491 @@
+// Signed settlement price attestations.
+// When contracts on an underlying reach expiry, the oracle takes the settlement price (the
+// TWAP or median of the samples taken before expiry, see settlement.rs) and signs it with
+// the service's secp256k1 key (BIP-340 Schnorr), under the same event id as the contracts'
+// DLC descriptors. Attestations are stored in settlement_prices and served by
+// GET /attestations/{date}, so anyone can check the price contracts settle at against the
+// oracle's public key.
+// For DLCs, each binary digit of the price is also signed on its own, with a nonce derived
+// from the key, the event id and the digit's position. The nonces are fixed before the
+// price is known, so DLC descriptors can announce their R-points in advance and the digit
+// signatures then unlock the matching contract execution transaction. A nonce must never
+// sign both values of its digit, so every event is attested once.
+
+use crate::dlc;
+use crate::error::{ApiError, ApiResult};
+use crate::models::{Asset, ContractStatus};
+use crate::repository::Repository;
+use crate::settlement::{self, SettlementConfig, SettlementMethod, SettlementPrice};
+use crate::sources::PriceSource;
+use crate::supervisor::Supervisor;
+use crate::utils::{cents_to_usd, usd_to_cents};
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use secp256k1::{schnorr, All, Keypair, Message, Parity, Scalar, Secp256k1, SecretKey, XOnlyPublicKey};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::interval;
+
+pub struct OracleSigner {
+    secp: Secp256k1<All>,
+    keypair: Keypair,
+}
+
+impl OracleSigner {
+    pub fn from_secret_hex(secret_hex: &str) -> Result<Self, String> {
+        let secret = SecretKey::from_str(secret_hex.trim()).map_err(|e| format!("invalid signing key: {}", e))?;
+        let secp = Secp256k1::new();
+        let keypair = Keypair::from_secret_key(&secp, &secret);
+        Ok(Self { secp, keypair })
+    }
+
+    /// A fresh random key. The service never attests with one: an attestation has to stay
+    /// verifiable with the key DLCs were built on, across restarts.
+    pub fn generate() -> Self {
+        loop {
+            if let Ok(secret) = SecretKey::from_slice(&rand::random::<[u8; 32]>()) {
+                let secp = Secp256k1::new();
+                let keypair = Keypair::from_secret_key(&secp, &secret);
+                return Self { secp, keypair };
+            }
+        }
+    }
+
+    /// ORACLE_SIGNING_KEY (hex secret key). None when unset, turning attestations and DLC
+    /// descriptors off.
+    pub fn from_env() -> Result<Option<Self>, String> {
+        match env::var("ORACLE_SIGNING_KEY") {
+            Ok(secret_hex) if !secret_hex.trim().is_empty() => Self::from_secret_hex(&secret_hex).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// x-only public key, hex
+    pub fn public_key_hex(&self) -> String {
+        self.keypair.x_only_public_key().0.to_string()
+    }
+
+    /// BIP-340 signature of the SHA-256 of `message`, hex
+    pub fn sign(&self, message: &str) -> String {
+        self.secp
+            .sign_schnorr_no_aux_rand(&message_digest(message), &self.keypair)
+            .to_string()
+    }
+
+    /// x-only R-points of the nonces the `nb_digits` digits of `event_id` are signed with,
+    /// most significant digit first, hex
+    pub fn digit_nonce_points(&self, event_id: &str, nb_digits: u32) -> Vec<String> {
+        (0..nb_digits)
+            .map(|index| self.digit_nonce(event_id, index).x_only_public_key(&self.secp).0.to_string())
+            .collect()
+    }
+
+    /// BIP-340 signature of each of `digits` ("0" or "1"), the i-th with the i-th nonce of
+    /// `event_id`, hex
+    pub fn sign_digits(&self, event_id: &str, digits: &[u8]) -> Vec<String> {
+        digits
+            .iter()
+            .zip(0..)
+            .map(|(digit, index)| self.sign_with_nonce(&digit.to_string(), self.digit_nonce(event_id, index)))
+            .collect()
+    }
+
+    // Secret nonce of digit `index` of `event_id`, known only to the holder of the key
+    fn digit_nonce(&self, event_id: &str, index: u32) -> SecretKey {
+        let secret = self.keypair.secret_bytes();
+        (0u32..)
+            .find_map(|counter| {
+                let hash = tagged_hash(
+                    NONCE_TAG,
+                    &[&secret, event_id.as_bytes(), &index.to_be_bytes(), &counter.to_be_bytes()],
+                );
+                SecretKey::from_slice(&hash).ok()
+            })
+            .expect("a valid nonce is found within a few hashes")
+    }
+
+    // BIP-340 signature of the SHA-256 of `message` with the given secret nonce
+    fn sign_with_nonce(&self, message: &str, nonce: SecretKey) -> String {
+        let (public_key, parity) = self.keypair.x_only_public_key();
+        let secret = match parity {
+            Parity::Even => self.keypair.secret_key(),
+            Parity::Odd => self.keypair.secret_key().negate(),
+        };
+        let (r, r_parity) = nonce.x_only_public_key(&self.secp);
+        let nonce = match r_parity {
+            Parity::Even => nonce,
+            Parity::Odd => nonce.negate(),
+        };
+        let digest = Sha256::digest(message.as_bytes());
+        let challenge = reduce(tagged_hash("BIP0340/challenge", &[&r.serialize(), &public_key.serialize(), &digest]));
+        // s = k + e * d
+        let s = secret
+            .mul_tweak(&challenge)
+            .and_then(|product| product.add_tweak(&Scalar::from(nonce)))
+            .expect("a zero challenge or signature scalar has negligible probability");
+        let mut signature = [0u8; 64];
+        signature[..32].copy_from_slice(&r.serialize());
+        signature[32..].copy_from_slice(&s.secret_bytes());
+        schnorr::Signature::from_slice(&signature).expect("64 bytes").to_string()
+    }
+}
+
+const NONCE_TAG: &str = "btc-option-manager/oracle/digit-nonce";
+
+fn message_digest(message: &str) -> Message {
+    Message::from_digest(Sha256::digest(message.as_bytes()).into())
+}
+
+// BIP-340 tagged hash: SHA-256(SHA-256(tag) || SHA-256(tag) || data)
+fn tagged_hash(tag: &str, data: &[&[u8]]) -> [u8; 32] {
+    let tag_hash = Sha256::digest(tag.as_bytes());
+    let mut hasher = Sha256::new();
+    hasher.update(tag_hash);
+    hasher.update(tag_hash);
+    for part in data {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+// A 256-bit hash as a scalar modulo the curve order n. Hashes are below 2^256 < 2n, so
+// subtracting n once is enough.
+fn reduce(hash: [u8; 32]) -> Scalar {
+    Scalar::from_be_bytes(hash).unwrap_or_else(|_| {
+        let order = Scalar::MAX.to_be_bytes();  // n - 1
+        let mut reduced = [0u8; 32];
+        let mut borrow = 1i16;  // Subtracting n is subtracting n - 1, then 1
+        for i in (0..32).rev() {
+            let diff = hash[i] as i16 - order[i] as i16 - borrow;
+            borrow = (diff < 0) as i16;
+            reduced[i] = diff.rem_euclid(256) as u8;
+        }
+        Scalar::from_be_bytes(reduced).expect("a hash minus n is below n")
+    })
+}
+
+/// Whether `signature_hex` signs `message` under `public_key_hex`
+pub fn verify(public_key_hex: &str, message: &str, signature_hex: &str) -> bool {
+    let (Ok(public_key), Ok(signature)) =
+        (XOnlyPublicKey::from_str(public_key_hex), schnorr::Signature::from_str(signature_hex))
+    else {
+        return false;
+    };
+    Secp256k1::verification_only()
+        .verify_schnorr(&signature, &message_digest(message), &public_key)
+        .is_ok()
+}
+
+/// Text the oracle signs: the event id and the price in USD
+pub fn attestation_message(event_id: &str, price: f64) -> String {
+    format!("{}:{:.2}", event_id, price)
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Attestation {
+    pub underlying: Asset,
+    pub timestamp: i64,    // Maturity the price settles
+    pub price: f64,        // USD
+    pub event_id: String,  // As in the DLC descriptors
+    pub message: String,   // Signed text
+    pub public_key: String,
+    pub signature: String,
+    pub digit_signatures: Vec<String>,  // Of each digit of dlc::outcome_digits, for DLCs
+    pub observed_at: i64,  // When the price was read
+    pub method: SettlementMethod,
+    pub sample_count: usize,
+    pub window_start: Option<i64>,
+}
+
+impl Attestation {
+    /// Attestation of `settlement`, with the price also signed digit by digit as a DLC
+    /// event of `nb_digits` binary digits
+    pub fn sign(
+        signer: &OracleSigner,
+        underlying: Asset,
+        timestamp: i64,
+        settlement: SettlementPrice,
+        nb_digits: u32,
+        observed_at: i64,
+    ) -> Self {
+        let price = cents_to_usd(usd_to_cents(settlement.price));
+        let event_id = dlc::event_id(underlying, timestamp);
+        let message = attestation_message(&event_id, price);
+        Self {
+            underlying,
+            timestamp,
+            price,
+            signature: signer.sign(&message),
+            digit_signatures: signer.sign_digits(&event_id, &dlc::outcome_digits(price, nb_digits)),
+            public_key: signer.public_key_hex(),
+            event_id,
+            message,
+            observed_at,
+            method: settlement.method,
+            sample_count: settlement.sample_count,
+            window_start: settlement.window_start,
+        }
+    }
+
+    pub fn verify(&self) -> bool {
+        let digits = dlc::outcome_digits(self.price, self.digit_signatures.len() as u32);
+        self.message == attestation_message(&self.event_id, self.price)
+            && verify(&self.public_key, &self.message, &self.signature)
+            && digits
+                .iter()
+                .zip(&self.digit_signatures)
+                .all(|(digit, signature)| verify(&self.public_key, &digit.to_string(), signature))
+    }
+}
+
+fn attestation_from_row(row: &Row) -> rusqlite::Result<Attestation> {
+    let event_id: String = row.get(3)?;
+    let price = cents_to_usd(row.get(2)?);
+    Ok(Attestation {
+        underlying: row.get(0)?,
+        timestamp: row.get(1)?,
+        price,
+        message: attestation_message(&event_id, price),
+        event_id,
+        public_key: row.get(4)?,
+        signature: row.get(5)?,
+        digit_signatures: row.get::<_, String>(10)?.split_whitespace().map(str::to_string).collect(),
+        observed_at: row.get(6)?,
+        method: row.get(7)?,
+        sample_count: row.get::<_, i64>(8)? as usize,
+        window_start: row.get(9)?,
+    })
+}
+
+/// Store an attestation unless the maturity is already attested
+pub fn record_attestation(conn: &Connection, attestation: &Attestation) -> ApiResult<bool> {
+    let inserted = conn.execute(
+        "INSERT OR IGNORE INTO settlement_prices
+         (underlying, timestamp, price_cents, event_id, public_key, signature, observed_at, method, sample_count, window_start,
+          digit_signatures)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        params![
+            attestation.underlying,
+            attestation.timestamp,
+            usd_to_cents(attestation.price),
+            attestation.event_id,
+            attestation.public_key,
+            attestation.signature,
+            attestation.observed_at,
+            attestation.method,
+            attestation.sample_count as i64,
+            attestation.window_start,
+            attestation.digit_signatures.join(" ")
+        ],
+    )?;
+    Ok(inserted > 0)
+}
+
+/// Attestations of maturities in [from, to), oldest first
+pub fn load_attestations(conn: &Connection, from: i64, to: i64) -> ApiResult<Vec<Attestation>> {
+    let mut stmt = conn.prepare(
+        "SELECT underlying, timestamp, price_cents, event_id, public_key, signature, observed_at,
+                method, sample_count, window_start, digit_signatures
+         FROM settlement_prices
+         WHERE timestamp >= ?1 AND timestamp < ?2
+         ORDER BY timestamp ASC, underlying ASC",
+    )?;
+    let rows = stmt.query_map(params![from, to], attestation_from_row)?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// Attested settlement price of `underlying` at maturity `timestamp`, in USD
+pub fn attested_price(conn: &Connection, underlying: Asset, timestamp: i64) -> ApiResult<Option<f64>> {
+    let cents: Option<i64> = conn
+        .query_row(
+            "SELECT price_cents FROM settlement_prices WHERE underlying = ?1 AND timestamp = ?2",
+            params![underlying, timestamp],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(cents.map(cents_to_usd))
+}
+
+/// Contract expiries up to `now` that have no attestation yet, oldest first
+pub fn unattested_maturities(conn: &Connection, now: i64) -> ApiResult<Vec<(Asset, i64)>> {
+    let mut stmt = conn.prepare(
+        "SELECT DISTINCT c.underlying, c.expires FROM contracts c
+         LEFT JOIN settlement_prices s ON s.underlying = c.underlying AND s.timestamp = c.expires
+         WHERE c.expires <= ?1 AND c.status != ?2 AND s.timestamp IS NULL
+         ORDER BY c.expires ASC",
+    )?;
+    let rows = stmt.query_map(params![now, ContractStatus::Cancelled], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// Attest the settlement price of every maturity that has passed unattested: the price
+/// sampled over its window, or the current price when too few samples were taken, signed
+/// whole and in `nb_digits` digits. Returns the number of attestations recorded.
+pub async fn attest_matured(
+    repository: &Repository,
+    price_source: &dyn PriceSource,
+    signer: &OracleSigner,
+    config: &SettlementConfig,
+    nb_digits: u32,
+    now: i64,
+) -> ApiResult<usize> {
+    let maturities = repository.run(move |conn| unattested_maturities(conn, now)).await?;
+    let mut prices: HashMap<Asset, f64> = HashMap::new();
+    let mut recorded = 0;
+    for (underlying, timestamp) in maturities {
+        let config = *config;
+        let sampled = repository
+            .run(move |conn| settlement::sampled_price(conn, &config, underlying, timestamp))
+            .await?;
+        let settlement = match (sampled, prices.get(&underlying)) {
+            (Some(sampled), _) => sampled,
+            (None, Some(price)) => SettlementPrice::spot(*price),
+            (None, None) => {
+                let price = price_source
+                    .get_price(underlying)
+                    .await
+                    .map_err(|e| ApiError::PriceOracleError(format!("no {} price to attest: {}", underlying, e)))?;
+                if config.method != SettlementMethod::Spot {
+                    eprintln!("⚠️  Too few {} samples before {}, attesting the spot price", underlying, timestamp);
+                }
+                prices.insert(underlying, price);
+                SettlementPrice::spot(price)
+            }
+        };
+        let attestation = Attestation::sign(signer, underlying, timestamp, settlement, nb_digits, now);
+        if repository.run(move |conn| record_attestation(conn, &attestation)).await? {
+            recorded += 1;
+        }
+    }
+    Ok(recorded)
+}
+
+/// Check every ATTESTATION_CHECK_INTERVAL_SECS (60) for maturities to attest
+pub fn start_attestation_job(
+    supervisor: &Supervisor,
+    repository: Repository,
+    price_source: Arc<dyn PriceSource>,
+    signer: Arc<OracleSigner>,
+    config: SettlementConfig,
+    nb_digits: u32,
+) {
+    let check_interval_secs: u64 = env::var("ATTESTATION_CHECK_INTERVAL_SECS")
+        .unwrap_or_else(|_| "60".to_string())
+        .parse()
+        .unwrap_or(60);
+    supervisor.spawn("attestation", move || {
+        let (repository, price_source, signer) = (repository.clone(), price_source.clone(), signer.clone());
+        async move {
+            let mut ticker = interval(Duration::from_secs(check_interval_secs.max(1)));
+            loop {
+                ticker.tick().await;
+                let now = Utc::now().timestamp();
+                match attest_matured(&repository, price_source.as_ref(), &signer, &config, nb_digits, now).await {
+                    Ok(0) => {}
+                    Ok(recorded) => println!("🔏 Attested {} settlement price(s)", recorded),
+                    Err(e) => eprintln!("Error attesting settlement prices: {}", e),
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Contract, OptionSide};
+    use crate::repository::insert_contract;
+
+    const SECRET: &str = "0000000000000000000000000000000000000000000000000000000000000003";
+
+    #[test]
+    fn test_attestation_signature_verifies() {
+        let signer = OracleSigner::from_secret_hex(SECRET).unwrap();
+        // BIP-340 test vector 0 public key
+        assert_eq!(signer.public_key_hex(), "f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9");
+
+        let attestation =
+            Attestation::sign(&signer, Asset::Btc, 1_800_000_000, SettlementPrice::spot(101_234.567), 20, 1_800_000_030);
+        assert_eq!(attestation.message, "btcusd-1800000000:101234.57");
+        assert!(attestation.verify());
+
+        let mut forged = attestation.clone();
+        forged.price = 90_000.0;
+        forged.message = attestation_message(&forged.event_id, forged.price);
+        assert!(!forged.verify());
+        assert!(!verify(&OracleSigner::generate().public_key_hex(), &attestation.message, &attestation.signature));
+        assert!(OracleSigner::from_secret_hex("not a key").is_err());
+    }
+
+    #[test]
+    fn test_digits_are_signed_with_the_announced_nonces() {
+        // Keys 1 to 4 include points of both parities
+        for key in 1..=4 {
+            let signer = OracleSigner::from_secret_hex(&format!("{:064x}", key)).unwrap();
+            let attestation =
+                Attestation::sign(&signer, Asset::Btc, 1_800_000_000, SettlementPrice::spot(101_234.567), 20, 1_800_000_030);
+            let digits = dlc::outcome_digits(101_234.567, 20);
+            assert_eq!(attestation.digit_signatures.len(), 20);
+            assert!(attestation.verify());
+
+            let nonces = signer.digit_nonce_points(&attestation.event_id, 20);
+            for ((digit, signature), nonce) in digits.iter().zip(&attestation.digit_signatures).zip(&nonces) {
+                assert!(verify(&signer.public_key_hex(), &digit.to_string(), signature));
+                assert!(!verify(&signer.public_key_hex(), &(1 - digit).to_string(), signature));
+                // The signature's R is the announced nonce
+                assert_eq!(&signature[..64], nonce);
+            }
+            // Nonces are fixed per event and per digit
+            assert_eq!(nonces, signer.digit_nonce_points(&attestation.event_id, 20));
+            assert_ne!(nonces, signer.digit_nonce_points("btcusd-1800086400", 20));
+            assert_eq!(nonces.iter().collect::<std::collections::HashSet<_>>().len(), 20);
+
+            // Digits 0 and 3 are 0 and 1
+            let mut forged = attestation.clone();
+            forged.digit_signatures.swap(0, 3);
+            assert!(!forged.verify());
+        }
+    }
+
+    #[test]
+    fn test_matured_contracts_are_attested_once() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init_db(&conn).unwrap();
+        let now = 1_800_000_000;
+        let contract = |expires: i64| Contract {
+            underlying: Asset::Btc,
+            side: OptionSide::Call,
+            strike_price: 100000.0,
+            quantity: 0.1,
+            expires,
+            premium: 0.01,
+        };
+        insert_contract(&conn, &contract(now - 60), None).unwrap();
+        insert_contract(&conn, &contract(now - 60), None).unwrap();
+        insert_contract(&conn, &contract(now + 60), None).unwrap();
+        assert_eq!(unattested_maturities(&conn, now).unwrap(), vec![(Asset::Btc, now - 60)]);
+
+        let signer = OracleSigner::from_secret_hex(SECRET).unwrap();
+        let twap = SettlementPrice {
+            price: 100_500.0,
+            method: SettlementMethod::Twap,
+            sample_count: 30,
+            window_start: Some(now - 1860),
+        };
+        let attestation = Attestation::sign(&signer, Asset::Btc, now - 60, twap, 20, now);
+        assert!(record_attestation(&conn, &attestation).unwrap());
+        assert!(!record_attestation(&conn, &attestation).unwrap());
+        assert!(unattested_maturities(&conn, now).unwrap().is_empty());
+
+        assert_eq!(attested_price(&conn, Asset::Btc, now - 60).unwrap(), Some(100_500.0));
+        assert_eq!(attested_price(&conn, Asset::Eth, now - 60).unwrap(), None);
+        let loaded = load_attestations(&conn, now - 86_400, now).unwrap();
+        assert_eq!(loaded, vec![attestation]);
+        assert!(loaded[0].verify());
+    }
+}
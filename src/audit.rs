@@ -0,0 +1,137 @@
+// Append-only audit log of state-changing operations.
+// Entries are written in the same transaction as the change they describe, and triggers
+// on audit_log reject any UPDATE or DELETE, so the trail cannot be rewritten through SQL.
+
+use chrono::Utc;
+use rusqlite::{params, Connection, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub const CONTRACT_CREATE: &str = "contract.create";
+pub const CONTRACT_EXPIRE: &str = "contract.expire";
+pub const CONTRACT_SETTLE: &str = "contract.settle";
+pub const CONTRACT_EXERCISE: &str = "contract.exercise";
+pub const CONTRACT_CLOSE: &str = "contract.close";
+pub const CONTRACT_AMEND: &str = "contract.amend";
+pub const CONTRACT_ACTIVATE: &str = "contract.activate";
+pub const CONTRACT_CANCEL: &str = "contract.cancel";
+pub const API_KEY_ROTATE: &str = "api_key.rotate";
+pub const TRADING_STATE_CHANGE: &str = "trading_state.change";
+pub const POOL_CREATE: &str = "pool.create";
+pub const CREDIT_TIER_SET: &str = "credit_tier.set";
+pub const CREDIT_TIER_DELETE: &str = "credit_tier.delete";
+pub const CREDIT_TIER_ASSIGN: &str = "credit_tier.assign";
+pub const ACCOUNT_KYC: &str = "account.kyc";
+pub const USER_SET: &str = "user.set";
+
+// Actor recorded for HTTP requests made before any API key has been issued
+pub const ANONYMOUS_ACTOR: &str = "anonymous";
+
+pub const DEFAULT_QUERY_LIMIT: u32 = 100;
+pub const MAX_QUERY_LIMIT: u32 = 1000;
+
+#[derive(Serialize, Clone, Debug)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub actor: String,
+    pub action: String,
+    pub entity_id: Option<i64>,
+    pub pre_state: Option<Value>,
+    pub post_state: Option<Value>,
+    pub created_at: i64,
+}
+
+// Filters for reading the log; every field is optional
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct AuditFilter {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub entity_id: Option<i64>,
+    pub since: Option<i64>,  // Unix seconds, inclusive
+    pub until: Option<i64>,  // Unix seconds, exclusive
+    pub limit: Option<u32>,
+}
+
+/// Append an entry. `pre` and `post` are snapshots of the entity before and after the change.
+pub fn record(
+    conn: &Connection,
+    actor: &str,
+    action: &str,
+    entity_id: Option<i64>,
+    pre: Option<&Value>,
+    post: Option<&Value>,
+) -> Result<i64> {
+    conn.execute(
+        "INSERT INTO audit_log (actor, action, entity_id, pre_state, post_state, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            actor,
+            action,
+            entity_id,
+            pre.map(|v| v.to_string()),
+            post.map(|v| v.to_string()),
+            Utc::now().timestamp()
+        ],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// Entries matching `filter`, newest first
+pub fn query(conn: &Connection, filter: &AuditFilter) -> Result<Vec<AuditEntry>> {
+    let limit = filter.limit.unwrap_or(DEFAULT_QUERY_LIMIT).min(MAX_QUERY_LIMIT);
+    let mut stmt = conn.prepare(
+        "SELECT id, actor, action, entity_id, pre_state, post_state, created_at FROM audit_log
+         WHERE (?1 IS NULL OR actor = ?1)
+           AND (?2 IS NULL OR action = ?2)
+           AND (?3 IS NULL OR entity_id = ?3)
+           AND (?4 IS NULL OR created_at >= ?4)
+           AND (?5 IS NULL OR created_at < ?5)
+         ORDER BY id DESC
+         LIMIT ?6",
+    )?;
+    let rows = stmt.query_map(
+        params![filter.actor, filter.action, filter.entity_id, filter.since, filter.until, limit],
+        |row| {
+            let pre_state: Option<String> = row.get(4)?;
+            let post_state: Option<String> = row.get(5)?;
+            Ok(AuditEntry {
+                id: row.get(0)?,
+                actor: row.get(1)?,
+                action: row.get(2)?,
+                entity_id: row.get(3)?,
+                pre_state: pre_state.and_then(|s| serde_json::from_str(&s).ok()),
+                post_state: post_state.and_then(|s| serde_json::from_str(&s).ok()),
+                created_at: row.get(6)?,
+            })
+        },
+    )?;
+    rows.collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_audit_log_is_append_only_and_filterable() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init_db(&conn).unwrap();
+
+        record(&conn, "frontend", CONTRACT_CREATE, Some(1), None, Some(&json!({"id": 1}))).unwrap();
+        record(&conn, "optadmin:ops", CONTRACT_SETTLE, Some(1), Some(&json!({"status": "expired"})), Some(&json!({"status": "settled"}))).unwrap();
+
+        let all = query(&conn, &AuditFilter::default()).unwrap();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].action, CONTRACT_SETTLE);
+        assert_eq!(all[0].pre_state, Some(json!({"status": "expired"})));
+
+        let by_actor = query(&conn, &AuditFilter { actor: Some("frontend".to_string()), ..Default::default() }).unwrap();
+        assert_eq!(by_actor.len(), 1);
+        assert_eq!(by_actor[0].post_state, Some(json!({"id": 1})));
+
+        assert!(conn.execute("UPDATE audit_log SET actor = 'someone-else'", []).is_err());
+        assert!(conn.execute("DELETE FROM audit_log", []).is_err());
+        assert_eq!(query(&conn, &AuditFilter { limit: Some(1), ..Default::default() }).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,335 @@
+// Session auth for the frontend.
+// Users log in at POST /auth/login with a password and receive a short-lived HS256 JWT whose
+// claims carry their role: viewer (reports), trader (also trades) or admin (also /admin).
+// The middleware validates bearer tokens on every request and stores the claims for the
+// route guards; an invalid or expired token is refused with 401 before the handler runs.
+// API keys keep working for machines alongside sessions and have every role. Guarded routes
+// refuse requests carrying neither unless AUTH_DISABLED is set for local development.
+// Users are managed with `optadmin users`; only the Argon2 hash of a password is stored.
+
+use crate::api::AppState;
+use crate::audit;
+use crate::error::{ApiError, ApiResult};
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderMap, AUTHORIZATION};
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpMessage};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rand::RngCore;
+use rusqlite::types::{FromSql, FromSqlError, ToSql, ToSqlOutput, ValueRef};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+const MIN_SECRET_LEN: usize = 32;
+
+/// Roles in increasing order of access; each role has the access of those below it
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Viewer,
+    Trader,
+    Admin,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::Viewer => write!(f, "viewer"),
+            Role::Trader => write!(f, "trader"),
+            Role::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = FromSqlError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "viewer" => Ok(Role::Viewer),
+            "trader" => Ok(Role::Trader),
+            "admin" => Ok(Role::Admin),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl ToSql for Role {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.to_string().into())
+    }
+}
+
+impl FromSql for Role {
+    fn column_result(value: ValueRef<'_>) -> std::result::Result<Self, FromSqlError> {
+        value.as_str()?.parse()
+    }
+}
+
+/// Claims of a session token
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Claims {
+    pub sub: String,  // Username, also the actor and counterparty of the session's requests
+    pub role: Role,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+/// A token issued at login
+#[derive(Serialize, Clone, Debug)]
+pub struct Session {
+    pub token: String,
+    pub token_type: &'static str,
+    pub role: Role,
+    pub expires_at: i64,
+}
+
+/// Whether guarded routes may be used without credentials
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AuthConfig {
+    pub disabled: bool,  // Requests without a valid key or session act as the anonymous actor
+}
+
+impl AuthConfig {
+    /// AUTH_DISABLED (false). Only meant for local development: with it set, anyone reaching
+    /// the API or the FIX gateway can trade and administer the pool.
+    pub fn from_env() -> Self {
+        Self {
+            disabled: env::var("AUTH_DISABLED").map(|v| v == "true" || v == "1").unwrap_or(false),
+        }
+    }
+
+    /// Actor of a request whose API key resolved to `key_actor`: the key's name, or the
+    /// anonymous actor while auth is disabled. None if the request may not proceed.
+    pub fn actor(&self, key_actor: Option<String>) -> Option<String> {
+        key_actor.or_else(|| self.disabled.then(|| audit::ANONYMOUS_ACTOR.to_string()))
+    }
+}
+
+#[derive(Clone)]
+pub struct JwtConfig {
+    secret: Vec<u8>,
+    pub ttl: Duration,
+}
+
+impl fmt::Debug for JwtConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JwtConfig").field("ttl", &self.ttl).finish_non_exhaustive()
+    }
+}
+
+impl JwtConfig {
+    pub fn new(secret: &[u8], ttl: Duration) -> Result<Self, String> {
+        if secret.len() < MIN_SECRET_LEN {
+            return Err(format!("the JWT secret must be at least {} bytes", MIN_SECRET_LEN));
+        }
+        Ok(Self { secret: secret.to_vec(), ttl })
+    }
+
+    /// JWT_SECRET and JWT_TTL_SECS (900). None when JWT_SECRET is unset, turning login off.
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let secret = match env::var("JWT_SECRET") {
+            Ok(secret) if !secret.is_empty() => secret,
+            _ => return Ok(None),
+        };
+        let ttl = env::var("JWT_TTL_SECS").ok().and_then(|v| v.parse().ok()).filter(|secs| *secs > 0).unwrap_or(900);
+        Self::new(secret.as_bytes(), Duration::from_secs(ttl)).map(Some)
+    }
+
+    /// Token for `username` with `role`, valid from `now` for the configured lifetime
+    pub fn issue(&self, username: &str, role: Role, now: i64) -> ApiResult<Session> {
+        let claims = Claims { sub: username.to_string(), role, iat: now, exp: now + self.ttl.as_secs() as i64 };
+        let token = jsonwebtoken::encode(&Header::new(Algorithm::HS256), &claims, &EncodingKey::from_secret(&self.secret))
+            .map_err(|e| ApiError::DatabaseError(format!("failed to sign session token: {}", e)))?;
+        Ok(Session { token, token_type: "Bearer", role, expires_at: claims.exp })
+    }
+
+    /// Claims of `token` if it is signed with our secret and not expired
+    pub fn verify(&self, token: &str) -> ApiResult<Claims> {
+        let validation = Validation::new(Algorithm::HS256);
+        jsonwebtoken::decode::<Claims>(token, &DecodingKey::from_secret(&self.secret), &validation)
+            .map(|data| data.claims)
+            .map_err(|e| ApiError::Unauthorized(format!("invalid session token: {}", e)))
+    }
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+/// Middleware validating the bearer token of a request, if any; see the module comment
+pub async fn middleware(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    if let Some(token) = bearer_token(req.headers()) {
+        let claims = match req.app_data::<web::Data<Arc<AppState>>>().and_then(|state| state.jwt()) {
+            Some(jwt) => jwt.verify(token),
+            None => Err(ApiError::Unauthorized("session login is not enabled".to_string())),
+        };
+        match claims {
+            Ok(claims) => {
+                req.extensions_mut().insert(claims);
+            }
+            Err(e) => return Ok(req.error_response(e)),
+        }
+    }
+    Ok(next.call(req).await?.map_into_boxed_body())
+}
+
+/// A user who can log in
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct User {
+    pub username: String,
+    pub role: Role,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+pub fn hash_password(password: &str) -> ApiResult<String> {
+    let mut salt = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let salt = SaltString::encode_b64(&salt).map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| ApiError::DatabaseError(format!("failed to hash password: {}", e)))
+}
+
+/// Create user `username` or reset its password and role, audited under `actor`
+pub fn set_user(conn: &Connection, username: &str, password: &str, role: Role, actor: &str, now: i64) -> ApiResult<User> {
+    if username.trim().is_empty() || username.trim() != username {
+        return Err(ApiError::ValidationError("Username must be set, without surrounding spaces".to_string()));
+    }
+    if password.len() < 12 {
+        return Err(ApiError::ValidationError("Passwords must be at least 12 characters".to_string()));
+    }
+    let password_hash = hash_password(password)?;
+    let tx = conn.unchecked_transaction()?;
+    let before = load_user(&tx, username)?;
+    tx.execute(
+        "INSERT INTO users (username, password_hash, role, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?4)
+         ON CONFLICT(username) DO UPDATE SET password_hash = excluded.password_hash, role = excluded.role,
+             updated_at = excluded.updated_at",
+        params![username, password_hash, role, now],
+    )?;
+    let user = load_user(&tx, username)?.ok_or_else(|| ApiError::DatabaseError("User vanished after upsert".to_string()))?;
+    // Never log the password or its hash
+    audit::record(
+        &tx,
+        actor,
+        audit::USER_SET,
+        None,
+        before.and_then(|before| serde_json::to_value(before).ok()).as_ref(),
+        serde_json::to_value(&user).ok().as_ref(),
+    )?;
+    tx.commit()?;
+    Ok(user)
+}
+
+const USER_COLUMNS: &str = "username, role, created_at, updated_at";
+
+fn user_from_row(row: &rusqlite::Row) -> rusqlite::Result<User> {
+    Ok(User { username: row.get(0)?, role: row.get(1)?, created_at: row.get(2)?, updated_at: row.get(3)? })
+}
+
+pub fn load_user(conn: &Connection, username: &str) -> ApiResult<Option<User>> {
+    let sql = format!("SELECT {} FROM users WHERE username = ?1", USER_COLUMNS);
+    Ok(conn.query_row(&sql, params![username], user_from_row).optional()?)
+}
+
+/// All users by name
+pub fn load_users(conn: &Connection) -> ApiResult<Vec<User>> {
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM users ORDER BY username ASC", USER_COLUMNS))?;
+    let rows = stmt.query_map([], user_from_row)?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// Whether any user exists. Without users or API keys, guarded endpoints refuse every request.
+pub fn has_users(conn: &Connection) -> rusqlite::Result<bool> {
+    conn.query_row("SELECT EXISTS(SELECT 1 FROM users)", [], |row| row.get(0))
+}
+
+/// User `username` if `password` is theirs
+pub fn authenticate(conn: &Connection, username: &str, password: &str) -> ApiResult<Option<User>> {
+    let password_hash: Option<String> = conn
+        .query_row("SELECT password_hash FROM users WHERE username = ?1", params![username], |row| row.get(0))
+        .optional()?;
+    let Some(password_hash) = password_hash else {
+        return Ok(None);
+    };
+    let hash = PasswordHash::new(&password_hash).map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    if Argon2::default().verify_password(password.as_bytes(), &hash).is_err() {
+        return Ok(None);
+    }
+    load_user(conn, username)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+    #[test]
+    fn test_requests_without_a_key_are_anonymous_only_with_auth_disabled() {
+        let enabled = AuthConfig::default();
+        assert_eq!(enabled.actor(None), None);
+        assert_eq!(enabled.actor(Some("ops".to_string())), Some("ops".to_string()));
+
+        let disabled = AuthConfig { disabled: true };
+        assert_eq!(disabled.actor(None), Some(audit::ANONYMOUS_ACTOR.to_string()));
+        assert_eq!(disabled.actor(Some("ops".to_string())), Some("ops".to_string()));
+    }
+
+    #[test]
+    fn test_session_tokens() {
+        let jwt = JwtConfig::new(SECRET, Duration::from_secs(900)).unwrap();
+        let now = chrono::Utc::now().timestamp();
+        let session = jwt.issue("alice", Role::Trader, now).unwrap();
+        assert_eq!(session.expires_at, now + 900);
+        let claims = jwt.verify(&session.token).unwrap();
+        assert_eq!((claims.sub.as_str(), claims.role), ("alice", Role::Trader));
+
+        // Expired tokens and tokens signed with another secret are refused
+        let expired = jwt.issue("alice", Role::Trader, now - 3600).unwrap();
+        assert!(jwt.verify(&expired.token).is_err());
+        let other = JwtConfig::new(b"another secret of at least 32 bytes", Duration::from_secs(900)).unwrap();
+        assert!(other.verify(&session.token).is_err());
+        assert!(JwtConfig::new(b"short", Duration::from_secs(900)).is_err());
+        assert!(Role::Admin > Role::Trader && Role::Trader > Role::Viewer);
+    }
+
+    #[test]
+    fn test_users_authenticate_with_their_password() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init_db(&conn).unwrap();
+        assert!(!has_users(&conn).unwrap());
+
+        let user = set_user(&conn, "alice", "correct horse battery", Role::Viewer, "ops", 1).unwrap();
+        assert_eq!(user.role, Role::Viewer);
+        assert_eq!(authenticate(&conn, "alice", "correct horse battery").unwrap(), Some(user));
+        assert_eq!(authenticate(&conn, "alice", "wrong password!").unwrap(), None);
+        assert_eq!(authenticate(&conn, "bob", "correct horse battery").unwrap(), None);
+        assert!(set_user(&conn, "bob", "short", Role::Viewer, "ops", 1).is_err());
+
+        // Resetting keeps the creation time
+        let user = set_user(&conn, "alice", "another long password", Role::Admin, "ops", 2).unwrap();
+        assert_eq!((user.role, user.created_at, user.updated_at), (Role::Admin, 1, 2));
+        assert_eq!(authenticate(&conn, "alice", "correct horse battery").unwrap(), None);
+        assert!(has_users(&conn).unwrap());
+        let entries = audit::query(&conn, &audit::AuditFilter::default()).unwrap();
+        assert!(entries.iter().all(|e| e.action == audit::USER_SET && !e.post_state.as_ref().unwrap().to_string().contains("argon2")));
+    }
+}
@@ -0,0 +1,317 @@
+// Backtesting.
+// Replays historical BTC spot (and optionally IV) through the same pricing, quoting and
+// margining the live service uses, with simulated client flow: every `trade_interval`
+// a client asks for a random product of the options grid and buys a share of the largest
+// quantity the pool would sell. Contracts settle at the first spot sample at or after expiry.
+// The report covers the pool's P&L, its max drawdown and how much of the collateral the
+// book margined, so COLLATERAL_RATE, RISK_MARGIN and the quoting settings can be compared on
+// the same history before they reach production.
+// Market data comes from the BTC price_history (IV held at a default) or a CSV of timestamp,spot[,iv].
+
+use crate::margin::MarginModel;
+use crate::models::{Asset, Contract, OptionSide};
+use crate::options_grid::GridConfig;
+use crate::pricing;
+use crate::quoting::{BookExposure, QuotingConfig};
+use crate::rates::RateCurve;
+use crate::risk_manager::{aggregate_positions, RiskManager};
+use crate::utils::{duration_to_seconds, year_fraction};
+use crate::vol;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::io::Read;
+use std::sync::Arc;
+
+
+/// One observation of the market replayed by the backtest
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct MarketPoint {
+    pub timestamp: i64,
+    pub spot: f64,
+    pub iv: Option<f64>,  // Held at the default IV when missing
+}
+
+/// Spot samples recorded in price_history between `since` and `until`
+pub fn load_spot_history(conn: &Connection, since: i64, until: i64) -> rusqlite::Result<Vec<MarketPoint>> {
+    Ok(vol::load_spot_samples(conn, Asset::Btc, since)?
+        .into_iter()
+        .take_while(|sample| sample.timestamp <= until)
+        .map(|sample| MarketPoint { timestamp: sample.timestamp, spot: sample.price, iv: None })
+        .collect())
+}
+
+/// Market points from CSV with a `timestamp,spot` header and an optional `iv` column,
+/// ordered by time
+pub fn read_csv<R: Read>(reader: R) -> Result<Vec<MarketPoint>, String> {
+    let mut points = csv::Reader::from_reader(reader)
+        .deserialize::<MarketPoint>()
+        .enumerate()
+        .map(|(i, row)| row.map_err(|e| format!("row {}: {}", i + 1, e)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let valid = |value: f64| value.is_finite() && value > 0.0;
+    if let Some(point) = points.iter().find(|p| !valid(p.spot) || p.iv.is_some_and(|iv| !valid(iv))) {
+        return Err(format!("spot and iv must be positive at {}", point.timestamp));
+    }
+    points.sort_by_key(|p| p.timestamp);
+    Ok(points)
+}
+
+#[derive(Debug, Clone)]
+pub struct BacktestConfig {
+    pub pool_btc: f64,
+    pub collateral_rate: f64,
+    pub risk_margin: f64,
+    pub rates: RateCurve,
+    pub default_iv: f64,
+    pub max_contract_quantity: f64,
+    pub grid: GridConfig,
+    pub quoting: QuotingConfig,
+    pub trade_interval: i64,  // Seconds between client orders
+    pub fill_percent: f64,    // Largest share of the max quantity one order takes
+    pub seed: u64,            // Of the simulated client flow
+}
+
+impl Default for BacktestConfig {
+    fn default() -> Self {
+        Self {
+            pool_btc: 1.0,
+            collateral_rate: 0.5,
+            risk_margin: 1.2,
+            rates: RateCurve::default(),
+            default_iv: 0.5,
+            max_contract_quantity: 1000.0,
+            grid: GridConfig::default(),
+            quoting: QuotingConfig::default(),
+            trade_interval: 3600,
+            fill_percent: 10.0,
+            seed: 1,
+        }
+    }
+}
+
+impl BacktestConfig {
+    /// The production settings: COLLATERAL_RATE, RISK_MARGIN, the risk-free rate curve,
+    /// MAX_CONTRACT_QUANTITY, the OPTIONS_* grid and the QUOTE_* markups
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let parse = |name: &str, default: f64| env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default);
+        Self {
+            collateral_rate: parse("COLLATERAL_RATE", defaults.collateral_rate),
+            risk_margin: parse("RISK_MARGIN", defaults.risk_margin),
+            rates: RateCurve::from_env().unwrap_or_default(),
+            max_contract_quantity: parse("MAX_CONTRACT_QUANTITY", defaults.max_contract_quantity),
+            grid: GridConfig::from_env(),
+            quoting: QuotingConfig::from_env(),
+            ..defaults
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct BacktestReport {
+    pub collateral_rate: f64,
+    pub risk_margin: f64,
+    pub start: i64,
+    pub end: i64,
+    pub orders: usize,
+    pub trades: usize,
+    pub rejected: usize,                // Orders the pool had no collateral for
+    pub settled: usize,
+    pub premium_collected_usd: f64,
+    pub payouts_usd: f64,
+    pub realized_pnl_usd: f64,          // Premiums of settled contracts less their payouts
+    pub unrealized_pnl_usd: f64,        // Premiums of open contracts less their mark at the end
+    pub total_pnl_usd: f64,
+    pub max_drawdown_usd: f64,          // Largest fall of the marked P&L from its peak
+    pub peak_margin_usage: f64,         // Book margin over trading collateral
+    pub average_margin_usage: f64,
+}
+
+// A contract sold during the replay, with the USD premium it was sold for
+struct SoldContract {
+    contract: Contract,
+    premium_usd: f64,
+}
+
+// Unexpired book marked at one market point
+struct Mark {
+    value_usd: f64,   // What the pool would pay to buy the book back at the model mid
+    margin_usd: f64,
+}
+
+fn mark_book(book: &[SoldContract], risk_manager: &RiskManager, point: &MarketPoint, config: &BacktestConfig) -> Mark {
+    let iv = point.iv.unwrap_or(config.default_iv);
+    let (mut value_usd, mut margin_usd) = (0.0, 0.0);
+    for sold in book {
+        let c = &sold.contract;
+        let t = year_fraction(c.expires, point.timestamp);
+        let rate = config.rates.rate_to(c.expires, point.timestamp);
+        value_usd += pricing::option_price(&c.side, point.spot, c.strike_price, rate, iv, t) * c.quantity;
+        margin_usd += risk_manager
+            .calculate_position_risk(&c.side, c.strike_price, c.premium, c.quantity, point.spot, iv, t, rate)
+            .margin_required;
+    }
+    Mark { value_usd, margin_usd }
+}
+
+/// Replay `data` (ordered by time) with `config`, margining with `margin_model`
+pub fn run(data: &[MarketPoint], config: &BacktestConfig, margin_model: Arc<dyn MarginModel>) -> BacktestReport {
+    let mut report = BacktestReport {
+        collateral_rate: config.collateral_rate,
+        risk_margin: config.risk_margin,
+        start: data.first().map_or(0, |p| p.timestamp),
+        end: data.last().map_or(0, |p| p.timestamp),
+        ..Default::default()
+    };
+    let risk_manager = RiskManager::new(config.risk_margin)
+        .with_max_contract_quantity(config.max_contract_quantity)
+        .with_margin_model(margin_model);
+    let tenors: Vec<i64> = config.grid.tenors.iter().map(|t| duration_to_seconds(t)).filter(|s| *s > 0).collect();
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let mut book: Vec<SoldContract> = Vec::new();
+    let mut next_order = report.start;
+    let (mut peak_pnl, mut usage_sum) = (0.0_f64, 0.0);
+
+    for point in data {
+        // Contracts settle at the first sample at or after their expiry
+        let (expired, open): (Vec<_>, Vec<_>) = book.into_iter().partition(|sold| sold.contract.expires <= point.timestamp);
+        book = open;
+        for sold in expired {
+            let c = &sold.contract;
+            let intrinsic = match c.side {
+                OptionSide::Call => (point.spot - c.strike_price).max(0.0),
+                OptionSide::Put => (c.strike_price - point.spot).max(0.0),
+            };
+            report.payouts_usd += intrinsic * c.quantity;
+            report.realized_pnl_usd += sold.premium_usd - intrinsic * c.quantity;
+            report.settled += 1;
+        }
+
+        let collateral_usd = config.pool_btc * point.spot * config.collateral_rate;
+        let iv = point.iv.unwrap_or(config.default_iv);
+        if point.timestamp >= next_order && !tenors.is_empty() {
+            next_order = point.timestamp + config.trade_interval.max(1);
+            report.orders += 1;
+
+            let strikes = config.grid.strikes(point.spot);
+            let side = if rng.gen_bool(0.5) { OptionSide::Call } else { OptionSide::Put };
+            let strike = strikes[rng.gen_range(0..strikes.len())];
+            let tenor = tenors[rng.gen_range(0..tenors.len())];
+            let t = year_fraction(point.timestamp + tenor, point.timestamp);
+            let rate = config.rates.rate_to(point.timestamp + tenor, point.timestamp);
+
+            // Priced and sized as /optionsTable would at this point
+            let contracts: Vec<Contract> = book.iter().map(|sold| sold.contract.clone()).collect();
+            let positions = aggregate_positions(&contracts, point.timestamp);
+            let position_delta = |p: &crate::risk_manager::Position| {
+                let t = year_fraction(p.expires, point.timestamp);
+                pricing::option_delta(&p.side, point.spot, p.strike_price, config.rates.rate_to(p.expires, point.timestamp), iv, t)
+            };
+            let exposure = BookExposure::new(&positions, Asset::Btc, point.spot, collateral_usd, position_delta);
+            let mid = pricing::option_price(&side, point.spot, strike, rate, iv, t);
+            let greeks = pricing::option_greeks(&side, point.spot, strike, rate, iv, t);
+            let ask_usd = config.quoting.quote(mid, side, strike, &greeks, &exposure).ask;
+            let premium_btc = ask_usd / point.spot;
+
+            let available_usd = collateral_usd - mark_book(&book, &risk_manager, point, config).margin_usd;
+            let unit_margin = risk_manager
+                .calculate_position_risk(&side, strike, premium_btc, 1.0, point.spot, iv, t, rate)
+                .margin_required;
+            let max_quantity = match available_usd > 0.0 && unit_margin > 0.0 {
+                true => (available_usd / unit_margin).min(config.max_contract_quantity),
+                false => 0.0,
+            };
+            let quantity = max_quantity * config.fill_percent / 100.0 * rng.gen_range(0.5..=1.0);
+            if quantity < 1e-8 {
+                report.rejected += 1;
+            } else {
+                report.trades += 1;
+                report.premium_collected_usd += ask_usd * quantity;
+                book.push(SoldContract {
+                    contract: Contract {
+                        underlying: Asset::Btc,
+                        side,
+                        strike_price: strike,
+                        quantity,
+                        expires: point.timestamp + tenor,
+                        premium: premium_btc,
+                    },
+                    premium_usd: ask_usd * quantity,
+                });
+            }
+        }
+
+        let mark = mark_book(&book, &risk_manager, point, config);
+        let open_premium_usd: f64 = book.iter().map(|sold| sold.premium_usd).sum();
+        let pnl = report.realized_pnl_usd + open_premium_usd - mark.value_usd;
+        peak_pnl = peak_pnl.max(pnl);
+        report.max_drawdown_usd = report.max_drawdown_usd.max(peak_pnl - pnl);
+        report.unrealized_pnl_usd = open_premium_usd - mark.value_usd;
+        report.total_pnl_usd = pnl;
+
+        let usage = if collateral_usd > 0.0 { mark.margin_usd / collateral_usd } else { 0.0 };
+        report.peak_margin_usage = report.peak_margin_usage.max(usage);
+        usage_sum += usage;
+    }
+    if !data.is_empty() {
+        report.average_margin_usage = usage_sum / data.len() as f64;
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::margin::MaxLossMargin;
+
+    // Hourly points over `hours` with spot moving by `step` each hour
+    fn path(hours: i64, start: f64, step: f64) -> Vec<MarketPoint> {
+        (0..=hours)
+            .map(|h| MarketPoint { timestamp: 1_800_000_000 + h * 3600, spot: start + step * h as f64, iv: None })
+            .collect()
+    }
+
+    #[test]
+    fn test_read_csv() {
+        let csv = "timestamp,spot,iv\n1800003600,101000,0.55\n1800000000,100000,\n";
+        let points = read_csv(csv.as_bytes()).unwrap();
+        assert_eq!(points[0], MarketPoint { timestamp: 1_800_000_000, spot: 100_000.0, iv: None });
+        assert_eq!(points[1].iv, Some(0.55));
+        assert_eq!(read_csv("timestamp,spot\n1800000000,100000\n".as_bytes()).unwrap().len(), 1);
+        assert!(read_csv("timestamp,spot\n1800000000,-1\n".as_bytes()).is_err());
+        assert!(read_csv("timestamp,spot\nnoon,100000\n".as_bytes()).unwrap_err().starts_with("row 1"));
+    }
+
+    #[test]
+    fn test_flat_market_keeps_the_premiums() {
+        // Spot never moves, so every call above and put below spot expires worthless
+        let config = BacktestConfig::default();
+        let report = run(&path(24 * 10, 100_000.0, 0.0), &config, Arc::new(MaxLossMargin));
+        assert_eq!(report.orders, 241);
+        assert_eq!(report.trades + report.rejected, report.orders);
+        assert!(report.trades > 0 && report.settled > 0);
+        assert!(report.premium_collected_usd > report.payouts_usd);
+        assert!((report.total_pnl_usd - (report.realized_pnl_usd + report.unrealized_pnl_usd)).abs() < 1e-6);
+        assert!(report.peak_margin_usage > 0.0 && report.peak_margin_usage <= 1.0 + 1e-9);
+        assert!(report.average_margin_usage <= report.peak_margin_usage);
+
+        // The same seed replays the same flow
+        assert_eq!(run(&path(24 * 10, 100_000.0, 0.0), &config, Arc::new(MaxLossMargin)), report);
+    }
+
+    #[test]
+    fn test_crash_draws_the_pool_down() {
+        let config = BacktestConfig::default();
+        let report = run(&path(24 * 10, 100_000.0, -200.0), &config, Arc::new(MaxLossMargin));
+        assert!(report.payouts_usd > 0.0);
+        assert!(report.max_drawdown_usd > 0.0);
+
+        // A wider risk margin sells less into the same crash
+        let cautious = BacktestConfig { risk_margin: 3.0, ..config };
+        let cautious = run(&path(24 * 10, 100_000.0, -200.0), &cautious, Arc::new(MaxLossMargin));
+        assert!(cautious.premium_collected_usd < report.premium_collected_usd);
+    }
+}
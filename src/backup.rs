@@ -0,0 +1,254 @@
+// Online backups of the contracts database.
+// A background task copies the live database with the SQLite backup API every
+// BACKUP_INTERVAL_SECS into BACKUP_DIR and keeps the newest BACKUP_RETENTION copies.
+// Readers and writers carry on while a copy is taken. `optadmin backup` takes one on
+// demand and `optadmin restore` replaces the database with one, after checking it.
+
+use crate::error::{ApiError, ApiResult};
+use crate::migrations::{self, MIGRATIONS};
+use crate::repository::Repository;
+use crate::supervisor::Supervisor;
+use chrono::{TimeZone, Utc};
+use rusqlite::{Connection, DatabaseName, OpenFlags};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::time::interval;
+
+const FILE_PREFIX: &str = "contracts-";
+const FILE_SUFFIX: &str = ".db";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BackupConfig {
+    pub dir: PathBuf,          // Where backups are written
+    pub interval: Duration,    // Time between scheduled backups
+    pub retention: usize,      // Backups kept, newest first
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("backups"),
+            interval: Duration::from_secs(3600),
+            retention: 24,
+        }
+    }
+}
+
+impl BackupConfig {
+    /// BACKUP_DIR (backups), BACKUP_INTERVAL_SECS (3600, 0 = no scheduled backups) and
+    /// BACKUP_RETENTION (24). None when scheduled backups are off.
+    pub fn from_env() -> Option<Self> {
+        let interval_secs: u64 = env::var("BACKUP_INTERVAL_SECS")
+            .unwrap_or_else(|_| "3600".to_string())
+            .parse()
+            .unwrap_or(3600);
+        if interval_secs == 0 {
+            return None;
+        }
+        Some(Self { interval: Duration::from_secs(interval_secs), ..Self::manual_from_env() })
+    }
+
+    /// BACKUP_DIR and BACKUP_RETENTION, for backups taken on demand
+    pub fn manual_from_env() -> Self {
+        let default = Self::default();
+        Self {
+            dir: env::var("BACKUP_DIR").map(PathBuf::from).unwrap_or(default.dir),
+            retention: env::var("BACKUP_RETENTION")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default.retention)
+                .max(1),
+            ..default
+        }
+    }
+}
+
+fn io_error(context: &str, path: &Path, err: std::io::Error) -> ApiError {
+    ApiError::DatabaseError(format!("{} {}: {}", context, path.display(), err))
+}
+
+/// Backup file name for a backup taken at `now`; names sort in time order
+pub fn backup_file_name(now: i64) -> String {
+    let at = Utc.timestamp_opt(now, 0).single().unwrap_or_default();
+    format!("{}{}{}", FILE_PREFIX, at.format("%Y%m%dT%H%M%SZ"), FILE_SUFFIX)
+}
+
+/// Copy the database of `conn` into `config.dir` and prune old backups. Returns the backup path.
+pub fn create_backup(conn: &Connection, config: &BackupConfig, now: i64) -> ApiResult<PathBuf> {
+    fs::create_dir_all(&config.dir).map_err(|e| io_error("Cannot create backup directory", &config.dir, e))?;
+    let path = config.dir.join(backup_file_name(now));
+    // Written under a temporary name so an interrupted copy is never taken for a backup
+    let partial = path.with_extension("db.partial");
+    conn.backup(DatabaseName::Main, &partial, None)?;
+    fs::rename(&partial, &path).map_err(|e| io_error("Cannot finish backup", &path, e))?;
+    prune_backups(&config.dir, config.retention)?;
+    Ok(path)
+}
+
+/// Backups in `dir`, oldest first
+pub fn list_backups(dir: &Path) -> ApiResult<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(io_error("Cannot read backup directory", dir, e)),
+    };
+    let mut backups: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(FILE_PREFIX) && name.ends_with(FILE_SUFFIX))
+        })
+        .collect();
+    backups.sort();
+    Ok(backups)
+}
+
+/// Delete all but the newest `retention` backups in `dir`. Returns the number deleted.
+pub fn prune_backups(dir: &Path, retention: usize) -> ApiResult<usize> {
+    let backups = list_backups(dir)?;
+    let excess = backups.len().saturating_sub(retention);
+    for path in &backups[..excess] {
+        fs::remove_file(path).map_err(|e| io_error("Cannot delete old backup", path, e))?;
+    }
+    Ok(excess)
+}
+
+/// Schema version of the backup at `path` after checking its integrity. Fails for a file
+/// that is not an intact database, or one written by a newer schema than this build knows.
+pub fn verify_backup(path: &Path) -> ApiResult<i64> {
+    if !path.is_file() {
+        return Err(ApiError::NotFound(format!("No backup at {}", path.display())));
+    }
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let integrity: String = conn.query_row("PRAGMA integrity_check", [], |row| row.get(0))?;
+    if integrity != "ok" {
+        return Err(ApiError::ValidationError(format!("Backup {} is corrupt: {}", path.display(), integrity)));
+    }
+    let version: i64 = conn
+        .query_row("SELECT COALESCE(MAX(version), 0) FROM schema_version", [], |row| row.get(0))
+        .map_err(|_| ApiError::ValidationError(format!("{} is not a contracts database backup", path.display())))?;
+    let latest = MIGRATIONS.last().map_or(0, |m| m.version);
+    if version > latest {
+        return Err(ApiError::ValidationError(format!(
+            "Backup {} has schema version {}, newer than this build's {}",
+            path.display(),
+            version,
+            latest
+        )));
+    }
+    Ok(version)
+}
+
+/// Replace the database of `conn` with the backup at `path` and bring it up to the current
+/// schema. Returns the schema version of the backup.
+pub fn restore_backup(conn: &mut Connection, path: &Path) -> ApiResult<i64> {
+    let version = verify_backup(path)?;
+    conn.restore(DatabaseName::Main, path, None::<fn(rusqlite::backup::Progress)>)?;
+    migrations::run_migrations(conn)?;
+    Ok(version)
+}
+
+/// Take a backup every `config.interval`, the first one interval after start
+pub fn start_backups(supervisor: &Supervisor, repository: Repository, config: BackupConfig) {
+    supervisor.spawn("database_backup", move || {
+        let (repository, config) = (repository.clone(), config.clone());
+        async move {
+            let mut ticker = interval(config.interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let config = config.clone();
+                let result = repository
+                    .run(move |conn| create_backup(conn, &config, Utc::now().timestamp()))
+                    .await;
+                match result {
+                    Ok(path) => println!("💾 Database backed up to {}", path.display()),
+                    Err(e) => eprintln!("Error backing up the database: {}", e),
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Contract, OptionSide};
+    use crate::repository::{insert_contract, load_contract_records};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("btc-options-backup-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn database_with_contract() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init_db(&conn).unwrap();
+        let contract = Contract {
+            underlying: Default::default(),
+            side: OptionSide::Put,
+            strike_price: 95000.0,
+            quantity: 0.5,
+            expires: 1_900_000_000,
+            premium: 0.01,
+        };
+        insert_contract(&conn, &contract, None).unwrap();
+        conn
+    }
+
+    #[test]
+    fn test_backup_and_restore_roundtrip() {
+        let dir = temp_dir("roundtrip");
+        let config = BackupConfig { dir: dir.clone(), ..Default::default() };
+        let path = create_backup(&database_with_contract(), &config, 1_760_000_000).unwrap();
+        assert_eq!(path, dir.join("contracts-20251009T085320Z.db"));
+        assert_eq!(verify_backup(&path).unwrap(), MIGRATIONS.last().unwrap().version);
+
+        // Restoring replaces whatever the target database held
+        let mut target = Connection::open_in_memory().unwrap();
+        crate::db::init_db(&target).unwrap();
+        restore_backup(&mut target, &path).unwrap();
+        let records = load_contract_records(&target, None).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].quantity, 0.5);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_retention_keeps_the_newest_backups() {
+        let dir = temp_dir("retention");
+        let config = BackupConfig { dir: dir.clone(), retention: 2, ..Default::default() };
+        let conn = database_with_contract();
+        for now in [1_760_000_000, 1_760_003_600, 1_760_007_200] {
+            create_backup(&conn, &config, now).unwrap();
+        }
+        fs::write(dir.join("notes.txt"), "not a backup").unwrap();
+
+        let backups = list_backups(&dir).unwrap();
+        assert_eq!(backups, vec![dir.join(backup_file_name(1_760_003_600)), dir.join(backup_file_name(1_760_007_200))]);
+        assert!(dir.join("notes.txt").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_restore_rejects_files_that_are_not_backups() {
+        let dir = temp_dir("invalid");
+        fs::create_dir_all(&dir).unwrap();
+        let garbage = dir.join("contracts-garbage.db");
+        fs::write(&garbage, "definitely not sqlite").unwrap();
+        let empty = dir.join("empty.db");
+        Connection::open(&empty).unwrap().execute_batch("CREATE TABLE t (x INTEGER)").unwrap();
+
+        let mut target = database_with_contract();
+        assert!(restore_backup(&mut target, &garbage).is_err());
+        assert!(restore_backup(&mut target, &empty).is_err());
+        assert!(matches!(restore_backup(&mut target, &dir.join("missing.db")), Err(ApiError::NotFound(_))));
+        // The database is left as it was
+        assert_eq!(load_contract_records(&target, None).unwrap().len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,298 @@
+// Basis adjustment of pricing.
+// Crypto options are hedged with perpetuals and futures, which trade at a basis to spot, so the
+// forward an option is struck against is rarely spot grown at the risk-free rate. PRICING_BASIS
+// picks the cost of carry quotes and marks are priced at:
+//   off       carry at the risk-free rate, i.e. Black-Scholes on spot (the default)
+//   funding   the 8h funding rate of the Deribit perpetual, annualized, at every maturity
+//   futures   the annualized basis ln(F/S)/T of each listed Deribit future, from its mid and
+//             index price and its expiry in the instruments API, interpolated by maturity as
+//             the risk-free curve is (see rates.rs)
+// An option is then priced as Black-76 on the forward F = S·e^(bT), which is Black-Scholes at the
+// forward's present value S·e^((b-r)T) (pricing::forward_spot). Margin, VaR and stress tests stay
+// on spot. Carries are fetched every PRICING_BASIS_REFRESH_SECS (60); when a fetch fails the last
+// carry is kept, and an underlying never fetched is priced without basis.
+
+use crate::http_client::HttpClient;
+use crate::models::Asset;
+use crate::rates::RateCurve;
+use crate::supervisor::Supervisor;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::time::interval;
+
+const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+// Funding periods of the perpetual in a year, 8 hours each
+const FUNDING_PERIODS_PER_YEAR: f64 = 3.0 * 365.0;
+
+// Futures closer to expiry than this are left out; their basis over a few hours is mostly noise
+const MIN_FUTURE_TENOR_SECS: i64 = 24 * 60 * 60;
+
+const SECONDS_PER_YEAR: f64 = 365.0 * 24.0 * 60.0 * 60.0;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BasisMode {
+    #[default]
+    Off,
+    Funding,
+    Futures,
+}
+
+impl fmt::Display for BasisMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BasisMode::Off => write!(f, "off"),
+            BasisMode::Funding => write!(f, "funding"),
+            BasisMode::Futures => write!(f, "futures"),
+        }
+    }
+}
+
+impl FromStr for BasisMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "off" | "none" => Ok(BasisMode::Off),
+            "funding" => Ok(BasisMode::Funding),
+            "futures" => Ok(BasisMode::Futures),
+            other => Err(format!("unknown PRICING_BASIS '{}', expected off, funding or futures", other)),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct DeribitResult<T> {
+    result: T,
+}
+
+#[derive(Deserialize)]
+struct Ticker {
+    funding_8h: Option<f64>,
+}
+
+/// A listed future, from the instruments API
+#[derive(Deserialize, Clone, Debug)]
+pub struct FutureInstrument {
+    pub instrument_name: String,
+    pub expiration_timestamp: i64,  // Milliseconds
+    #[serde(default)]
+    pub settlement_period: String,  // "perpetual" for the perpetual
+}
+
+/// Book summary of a future
+#[derive(Deserialize, Clone, Debug)]
+pub struct FutureSummary {
+    pub instrument_name: String,
+    pub mid_price: Option<f64>,
+    pub estimated_delivery_price: Option<f64>,  // The index, i.e. spot
+}
+
+/// Annualized carry of an 8h perpetual funding rate
+pub fn funding_carry(funding_8h: f64) -> f64 {
+    funding_8h * FUNDING_PERIODS_PER_YEAR
+}
+
+/// Carry curve of the annualized basis of each dated future as of `now` (Unix seconds)
+pub fn futures_curve(instruments: &[FutureInstrument], summaries: &[FutureSummary], now: i64) -> Result<RateCurve, String> {
+    let summaries: HashMap<&str, &FutureSummary> = summaries.iter().map(|s| (s.instrument_name.as_str(), s)).collect();
+    let points = instruments
+        .iter()
+        .filter(|future| future.settlement_period != "perpetual")
+        .filter_map(|future| {
+            let tenor_secs = future.expiration_timestamp / 1000 - now;
+            if tenor_secs < MIN_FUTURE_TENOR_SECS {
+                return None;
+            }
+            let summary = summaries.get(future.instrument_name.as_str())?;
+            let (mid, index) = (summary.mid_price?, summary.estimated_delivery_price?);
+            if !(mid > 0.0 && index > 0.0) {
+                return None;
+            }
+            let years = tenor_secs as f64 / SECONDS_PER_YEAR;
+            Some((years, (mid / index).ln() / years))
+        })
+        .collect();
+    RateCurve::from_points(points).map_err(|_| "no quoted futures to read the basis off".to_string())
+}
+
+/// Carries of the underlyings, kept current from Deribit unless PRICING_BASIS is off
+#[derive(Debug)]
+pub struct BasisSource {
+    mode: BasisMode,
+    api_url: String,
+    refresh_interval: Duration,
+    carries: RwLock<HashMap<Asset, Arc<RateCurve>>>,
+}
+
+impl Default for BasisSource {
+    fn default() -> Self {
+        Self::new(BasisMode::Off, String::new())
+    }
+}
+
+impl BasisSource {
+    /// Carries read from the Deribit API at `api_url`
+    pub fn new(mode: BasisMode, api_url: String) -> Self {
+        Self { mode, api_url, refresh_interval: DEFAULT_REFRESH_INTERVAL, carries: RwLock::default() }
+    }
+
+    /// PRICING_BASIS (off) and PRICING_BASIS_REFRESH_SECS (60)
+    pub fn from_env(api_url: String) -> Result<Self, String> {
+        let mode = match env::var("PRICING_BASIS") {
+            Ok(mode) if !mode.trim().is_empty() => mode.parse()?,
+            _ => BasisMode::default(),
+        };
+        let refresh_interval = env::var("PRICING_BASIS_REFRESH_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .map(|secs: u64| Duration::from_secs(secs.max(1)))
+            .unwrap_or(DEFAULT_REFRESH_INTERVAL);
+        Ok(Self { refresh_interval, ..Self::new(mode, api_url) })
+    }
+
+    pub fn mode(&self) -> BasisMode {
+        self.mode
+    }
+
+    /// Carry curve of `asset`; None while options on it are priced without basis
+    pub fn carry(&self, asset: Asset) -> Option<Arc<RateCurve>> {
+        self.carries.read().unwrap().get(&asset).cloned()
+    }
+
+    pub fn set_carry(&self, asset: Asset, curve: RateCurve) {
+        self.carries.write().unwrap().insert(asset, Arc::new(curve));
+    }
+
+    async fn get<T: for<'de> Deserialize<'de>>(&self, http_client: &HttpClient, url: String) -> Result<T, String> {
+        let response = http_client.get(&url).await.map_err(|e| e.to_string())?;
+        let body: DeribitResult<T> = response.json().await.map_err(|e| e.to_string())?;
+        Ok(body.result)
+    }
+
+    // Fetch the carry of `asset` under the mode
+    async fn fetch(&self, http_client: &HttpClient, asset: Asset) -> Result<Option<RateCurve>, String> {
+        match self.mode {
+            BasisMode::Off => Ok(None),
+            BasisMode::Funding => {
+                let url = format!("{}/public/ticker?instrument_name={}-PERPETUAL", self.api_url, asset);
+                let ticker: Ticker = self.get(http_client, url).await?;
+                let funding_8h = ticker.funding_8h.filter(|f| f.is_finite()).ok_or("the perpetual has no funding rate")?;
+                Ok(Some(RateCurve::flat(funding_carry(funding_8h))))
+            }
+            BasisMode::Futures => {
+                let url = format!("{}/public/get_instruments?currency={}&kind=future&expired=false", self.api_url, asset);
+                let instruments: Vec<FutureInstrument> = self.get(http_client, url).await?;
+                let url = format!("{}/public/get_book_summary_by_currency?currency={}&kind=future", self.api_url, asset);
+                let summaries: Vec<FutureSummary> = self.get(http_client, url).await?;
+                futures_curve(&instruments, &summaries, chrono::Utc::now().timestamp()).map(Some)
+            }
+        }
+    }
+
+    /// Fetch the carries of `assets`, keeping the last one of any that fails
+    pub async fn refresh(&self, http_client: &HttpClient, assets: &[Asset]) -> Result<(), String> {
+        let mut errors = Vec::new();
+        for &asset in assets {
+            match self.fetch(http_client, asset).await {
+                Ok(Some(curve)) => self.set_carry(asset, curve),
+                Ok(None) => {}
+                Err(e) => errors.push(format!("{}: {}", asset, e)),
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("; "))
+        }
+    }
+
+    /// Keep the carries of `assets` current; nothing to do when PRICING_BASIS is off
+    pub fn start_updates(self: &Arc<Self>, supervisor: &Supervisor, http_client: HttpClient, assets: Vec<Asset>) {
+        if self.mode == BasisMode::Off {
+            return;
+        }
+        let source = self.clone();
+        supervisor.spawn("pricing_basis", move || {
+            let (source, http_client, assets) = (source.clone(), http_client.clone(), assets.clone());
+            async move {
+                let mut ticker = interval(source.refresh_interval);
+                loop {
+                    ticker.tick().await;
+                    if let Err(e) = source.refresh(&http_client, &assets).await {
+                        eprintln!("Error refreshing the {} pricing basis: {}", source.mode, e);
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_735_689_600;
+    const DAY_MS: i64 = 86_400_000;
+
+    fn future(name: &str, days: i64, settlement_period: &str) -> FutureInstrument {
+        FutureInstrument {
+            instrument_name: name.to_string(),
+            expiration_timestamp: NOW * 1000 + days * DAY_MS,
+            settlement_period: settlement_period.to_string(),
+        }
+    }
+
+    fn summary(name: &str, mid_price: Option<f64>) -> FutureSummary {
+        FutureSummary { instrument_name: name.to_string(), mid_price, estimated_delivery_price: Some(100_000.0) }
+    }
+
+    #[test]
+    fn test_futures_curve() {
+        let instruments = vec![
+            future("BTC-PERPETUAL", 0, "perpetual"),
+            future("BTC-2JAN25", 0, "day"),
+            future("BTC-31JAN25", 30, "month"),
+            future("BTC-28MAR25", 86, "month"),
+            future("BTC-27JUN25", 177, "month"),
+        ];
+        let summaries = vec![
+            summary("BTC-PERPETUAL", Some(100_050.0)),
+            summary("BTC-2JAN25", Some(100_020.0)),
+            summary("BTC-31JAN25", Some(100_500.0)),
+            summary("BTC-28MAR25", Some(102_000.0)),
+            summary("BTC-27JUN25", None),
+        ];
+        let curve = futures_curve(&instruments, &summaries, NOW).unwrap();
+        // The perpetual, the expiring future and the unquoted one are left out
+        assert_eq!(curve.points().len(), 2);
+        let (years, carry) = curve.points()[0];
+        assert_eq!(years, 30.0 / 365.0);
+        assert!((carry - 1.005f64.ln() / years).abs() < 1e-12);
+        // Forwards are recovered from the carry at each future's expiry
+        let forward = 100_000.0 * (curve.rate_to(NOW + 86 * 86_400, NOW) * 86.0 / 365.0).exp();
+        assert!((forward - 102_000.0).abs() < 1e-6);
+
+        assert!(futures_curve(&instruments[..1], &summaries, NOW).is_err());
+    }
+
+    #[test]
+    fn test_funding_carry_and_mode() {
+        assert!((funding_carry(0.0001) - 0.1095).abs() < 1e-12);
+        assert_eq!("Futures".parse(), Ok(BasisMode::Futures));
+        assert_eq!("none".parse(), Ok(BasisMode::Off));
+        assert!("spot".parse::<BasisMode>().is_err());
+
+        let source = BasisSource::default();
+        assert_eq!(source.carry(Asset::Btc), None);
+        source.set_carry(Asset::Btc, RateCurve::flat(0.1));
+        assert_eq!(source.carry(Asset::Btc).map(|c| c.rate(0.5)), Some(0.1));
+        assert_eq!(source.carry(Asset::Eth), None);
+    }
+}
@@ -0,0 +1,149 @@
+// Replay historical market data through the pricing, quoting and risk engines.
+//
+// Usage: backtest [options]
+//
+// Market data is the BTC price_history of the configured database, or --csv. Settings
+// default to the production environment (COLLATERAL_RATE, RISK_MARGIN, QUOTE_*, ...);
+// comma separated --collateral-rate and --risk-margin values run one backtest per combination.
+
+use btc_options_api::backtest::{self, BacktestConfig, BacktestReport, MarketPoint};
+use btc_options_api::db;
+use btc_options_api::margin::margin_model_from_env;
+use chrono::Utc;
+use dotenv::dotenv;
+use std::env;
+use std::error::Error;
+use std::fs::File;
+
+const USAGE: &str = "Usage: backtest [options]
+
+Options:
+  --csv <path>                    Market data as timestamp,spot[,iv] (default: price_history)
+  --since <unix>                  First price_history sample replayed (default: 30 days ago)
+  --until <unix>                  Last price_history sample replayed (default: now)
+  --iv <vol>                      IV where the data has none (default: 0.5)
+  --pool-btc <btc>                Pool balance (default: 1)
+  --collateral-rate <r>[,<r>...]  Share of the pool sold against (default: COLLATERAL_RATE)
+  --risk-margin <m>[,<m>...]      Margin multiplier (default: RISK_MARGIN)
+  --interval <secs>               Seconds between simulated client orders (default: 3600)
+  --fill-percent <pct>            Largest share of the max quantity one order takes (default: 10)
+  --seed <n>                      Seed of the simulated client flow (default: 1)
+  --json                          Print the reports as JSON";
+
+fn main() {
+    dotenv().ok();
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+    if args.iter().any(|arg| matches!(*arg, "-h" | "--help")) {
+        eprintln!("{}", USAGE);
+        std::process::exit(2);
+    }
+
+    if let Err(e) = run(&args) {
+        eprintln!("❌ {}", e);
+        std::process::exit(1);
+    }
+}
+
+// Value following `--name` in the argument list
+fn flag_value<'a>(args: &[&'a str], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| *arg == name)
+        .and_then(|i| args.get(i + 1))
+        .copied()
+}
+
+fn parse_flag<T: std::str::FromStr>(args: &[&str], name: &str) -> Result<Option<T>, Box<dyn Error>> {
+    flag_value(args, name)
+        .map(|value| value.parse::<T>().map_err(|_| format!("{} expects a number, got '{}'", name, value).into()))
+        .transpose()
+}
+
+// Comma separated numbers following `--name`, or `default` alone
+fn parse_list_flag(args: &[&str], name: &str, default: f64) -> Result<Vec<f64>, Box<dyn Error>> {
+    match flag_value(args, name) {
+        Some(list) => list
+            .split(',')
+            .map(|value| value.trim().parse::<f64>().map_err(|_| format!("{} expects numbers, got '{}'", name, value).into()))
+            .collect(),
+        None => Ok(vec![default]),
+    }
+}
+
+fn load_data(args: &[&str]) -> Result<Vec<MarketPoint>, Box<dyn Error>> {
+    if let Some(path) = flag_value(args, "--csv") {
+        return Ok(backtest::read_csv(File::open(path)?)?);
+    }
+    let until = parse_flag(args, "--until")?.unwrap_or_else(|| Utc::now().timestamp());
+    let since = parse_flag(args, "--since")?.unwrap_or(until - 30 * 86_400);
+    let pool = db::create_pool()?;
+    let conn = pool.get()?;
+    Ok(backtest::load_spot_history(&conn, since, until)?)
+}
+
+fn run(args: &[&str]) -> Result<(), Box<dyn Error>> {
+    let data = load_data(args)?;
+    if data.is_empty() {
+        return Err("no market data to replay".into());
+    }
+
+    let defaults = BacktestConfig::from_env();
+    let base = BacktestConfig {
+        default_iv: parse_flag(args, "--iv")?.unwrap_or(defaults.default_iv),
+        pool_btc: parse_flag(args, "--pool-btc")?.unwrap_or(defaults.pool_btc),
+        trade_interval: parse_flag(args, "--interval")?.unwrap_or(defaults.trade_interval),
+        fill_percent: parse_flag(args, "--fill-percent")?.unwrap_or(defaults.fill_percent),
+        seed: parse_flag(args, "--seed")?.unwrap_or(defaults.seed),
+        ..defaults.clone()
+    };
+    let margin_model = margin_model_from_env()?;
+
+    let mut reports = Vec::new();
+    for &collateral_rate in &parse_list_flag(args, "--collateral-rate", defaults.collateral_rate)? {
+        for &risk_margin in &parse_list_flag(args, "--risk-margin", defaults.risk_margin)? {
+            let config = BacktestConfig { collateral_rate, risk_margin, ..base.clone() };
+            reports.push(backtest::run(&data, &config, margin_model.clone()));
+        }
+    }
+
+    if args.contains(&"--json") {
+        println!("{}", serde_json::to_string_pretty(&reports)?);
+    } else {
+        print_reports(&data, &reports);
+    }
+    Ok(())
+}
+
+fn print_reports(data: &[MarketPoint], reports: &[BacktestReport]) {
+    let (first, last) = (data[0], data[data.len() - 1]);
+    println!(
+        "📈 Replayed {} points from {} (${:.2}) to {} (${:.2})",
+        data.len(),
+        first.timestamp,
+        first.spot,
+        last.timestamp,
+        last.spot
+    );
+    println!("{:-<120}", "-");
+    println!(
+        "{:<10} {:<8} {:>7} {:>8} {:>12} {:>12} {:>12} {:>12} {:>10} {:>10}",
+        "Coll.rate", "Margin", "Trades", "Rejected", "Premiums", "Payouts", "P&L", "Max DD", "Peak use", "Avg use"
+    );
+    println!("{:-<120}", "-");
+    for report in reports {
+        println!(
+            "{:<10.2} {:<8.2} {:>7} {:>8} {:>12.2} {:>12.2} {:>12.2} {:>12.2} {:>9.1}% {:>9.1}%",
+            report.collateral_rate,
+            report.risk_margin,
+            report.trades,
+            report.rejected,
+            report.premium_collected_usd,
+            report.payouts_usd,
+            report.total_pnl_usd,
+            report.max_drawdown_usd,
+            report.peak_margin_usage * 100.0,
+            report.average_margin_usage * 100.0
+        );
+    }
+}
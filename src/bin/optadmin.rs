@@ -0,0 +1,543 @@
+// Operator CLI for the BTC options database.
+//
+// Usage: optadmin <command> [options]
+//
+//   contracts list [--status open|expired|settled]
+//   contracts expire
+//   contracts settle [--asset BTC|ETH] [--price <usd>] [--btc-price <usd>]
+//   risk [--spot <usd>]
+//   iv dump
+//   migrate
+//   rotate-api-key <name>
+//   trading-state [open|reduce_only|halted] [--reason <text>]
+//   export [--out <path>]
+//   backup [list]
+//   pools list
+//   pools add <name> --address <address> --network mainnet|testnet|signet
+//             [--collateral-rate <rate>] [--risk-margin <margin>]
+//   restore <path>
+//   snapshot [--out <path>]
+//   snapshot restore <path> [--env-out <path>]
+//   users list
+//   users set <name> --role viewer|trader|admin
+
+use btc_options_api::api_keys;
+use btc_options_api::attestation;
+use btc_options_api::auth::{self, Role};
+use btc_options_api::backup::{self, BackupConfig};
+use btc_options_api::db::{self, DbPool};
+use btc_options_api::http_client::HttpClient;
+use btc_options_api::iv_oracle::IvOracle;
+use btc_options_api::iv_policy::DefaultIvPolicy;
+use btc_options_api::margin::margin_model_from_env;
+use btc_options_api::migrations;
+use btc_options_api::pools::{self, NewPool, Pool};
+use btc_options_api::models::{Asset, Contract, ContractRecord, ContractStatus};
+use btc_options_api::price_oracle::PriceOracle;
+use btc_options_api::rates::RateSource;
+use btc_options_api::repository;
+use btc_options_api::risk_manager::RiskManager;
+use btc_options_api::snapshot;
+use btc_options_api::trading_state::{self, TradingState, TradingStatus};
+use btc_options_api::utils::format_expires_timestamp;
+use chrono::Utc;
+use dotenv::dotenv;
+use std::env;
+use std::error::Error;
+
+type CliResult = Result<(), Box<dyn Error>>;
+
+const USAGE: &str = "Usage: optadmin <command> [options]
+
+Commands:
+  contracts list [--status open|expired|settled]   List stored contracts
+  contracts expire                                 Mark contracts past expiry as expired
+  contracts settle [--asset BTC|ETH] [--price <usd>] [--btc-price <usd>]
+                                                   Settle expired contracts on one underlying (default: BTC)
+                                                   at the attested settlement price of each maturity, or
+                                                   at --price; --btc-price converts payoffs
+  risk [--spot <usd>]                              Recompute portfolio margin and VaR
+  iv dump                                          Fetch and print the Deribit IV surface
+  migrate                                          Apply pending schema migrations
+  rotate-api-key <name>                            Revoke keys for <name> and issue a new one
+  trading-state [open|reduce_only|halted] [--reason <text>]
+                                                   Show or change the venue trading state
+  export [--out <path>]                            Export all contracts as JSON
+  backup [list]                                    Back up the database to BACKUP_DIR, or list the backups there
+  restore <path>                                   Replace the database with a backup (stop the server first);
+                                                   the current database is backed up before it is replaced
+  snapshot [--out <path>]                          Write the database, settings and IV surface file to one
+                                                   checksummed archive (default: BACKUP_DIR/snapshot-<time>.json)
+  snapshot restore <path> [--env-out <path>]       Rebuild the database from a snapshot (stop the server first),
+                                                   backing up the current one; --env-out writes its settings
+  pools list                                       List the pools contracts can be sold from
+  pools add <name> --address <address> --network mainnet|testnet|signet [--collateral-rate <rate>] [--risk-margin <margin>]
+                                                   Add a pool (default rate 0.5, margin 1.2)
+  users list                                       List the users who can log in at /auth/login
+  users set <name> --role viewer|trader|admin      Create a user or reset its role and password; the password
+                                                   is OPTADMIN_USER_PASSWORD, or generated and shown once";
+
+#[tokio::main]
+async fn main() {
+    dotenv().ok();
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+
+    let result = match args.as_slice() {
+        ["contracts", "list", rest @ ..] => list_contracts(rest),
+        ["contracts", "expire"] => expire_contracts(),
+        ["contracts", "settle", rest @ ..] => settle_contracts(rest).await,
+        ["risk", rest @ ..] => recompute_risk(rest).await,
+        ["iv", "dump"] => dump_iv_cache().await,
+        ["migrate"] => run_migrations(),
+        ["rotate-api-key", name] => rotate_api_key(name),
+        ["trading-state"] => show_trading_state(),
+        ["trading-state", state, rest @ ..] => set_trading_state(state, rest),
+        ["export", rest @ ..] => export_contracts(rest),
+        ["backup"] => backup_database(),
+        ["backup", "list"] => list_backups(),
+        ["restore", path] => restore_database(path),
+        ["snapshot", "restore", path, rest @ ..] => restore_snapshot(path, rest),
+        ["snapshot", rest @ ..] => take_snapshot(rest),
+        ["pools", "list"] => list_pools(),
+        ["pools", "add", name, rest @ ..] => add_pool(name, rest),
+        ["users", "list"] => list_users(),
+        ["users", "set", name, rest @ ..] => set_user(name, rest),
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("❌ {}", e);
+        std::process::exit(1);
+    }
+}
+
+// Value following `--name` in the argument list
+fn flag_value<'a>(args: &[&'a str], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| *arg == name)
+        .and_then(|i| args.get(i + 1))
+        .copied()
+}
+
+fn parse_f64_flag(args: &[&str], name: &str) -> Result<Option<f64>, Box<dyn Error>> {
+    flag_value(args, name)
+        .map(|value| {
+            value
+                .parse::<f64>()
+                .map_err(|_| format!("{} expects a number, got '{}'", name, value).into())
+        })
+        .transpose()
+}
+
+// Actor recorded in the audit log for changes made through this CLI
+fn audit_actor() -> String {
+    format!("optadmin:{}", env::var("USER").unwrap_or_else(|_| "unknown".to_string()))
+}
+
+fn open_pool() -> Result<DbPool, Box<dyn Error>> {
+    db::create_pool()
+}
+
+async fn spot_price(asset: Asset, override_price: Option<f64>) -> Result<f64, Box<dyn Error>> {
+    if let Some(price) = override_price {
+        return Ok(price);
+    }
+    let aggregator_url = env::var("AGGREGATOR_URL").unwrap_or_else(|_| "http://localhost:50051".to_string());
+    let oracle = PriceOracle::new(aggregator_url).await?;
+    oracle.get_price(asset).await
+}
+
+fn list_contracts(args: &[&str]) -> CliResult {
+    let status = flag_value(args, "--status")
+        .map(|s| s.parse::<ContractStatus>().map_err(|_| format!("unknown status '{}'", s)))
+        .transpose()?;
+
+    let pool = open_pool()?;
+    let conn = pool.get()?;
+    let records = repository::load_contract_records(&conn, status)?;
+
+    println!("{:>6}  {:<4}  {:>12}  {:>12}  {:>12}  {:<10}  {:<8}", "ID", "SIDE", "STRIKE", "QUANTITY", "PREMIUM", "EXPIRES", "STATUS");
+    for record in &records {
+        println!(
+            "{:>6}  {:<4}  {:>12.2}  {:>12.8}  {:>12.8}  {:<10}  {:<8}",
+            record.id,
+            record.side,
+            record.strike_price,
+            record.quantity,
+            record.premium,
+            format_expires_timestamp(record.expires),
+            record.status,
+        );
+    }
+    println!("{} contract(s)", records.len());
+    Ok(())
+}
+
+fn expire_contracts() -> CliResult {
+    let pool = open_pool()?;
+    let conn = pool.get()?;
+    let expired = repository::expire_contracts(&conn, Utc::now().timestamp(), &audit_actor())?;
+    println!("✅ Marked {} contract(s) as expired", expired);
+    Ok(())
+}
+
+async fn settle_contracts(args: &[&str]) -> CliResult {
+    let asset = flag_value(args, "--asset")
+        .map(|s| s.parse::<Asset>().map_err(|_| format!("unknown asset '{}'", s)))
+        .transpose()?
+        .unwrap_or_default();
+    let pool = open_pool()?;
+    let conn = pool.get()?;
+    let now = Utc::now().timestamp();
+    let actor = audit_actor();
+    repository::expire_contracts(&conn, now, &actor)?;
+
+    let Some(settlement_price) = parse_f64_flag(args, "--price")? else {
+        // Each maturity settles at its attested price (the TWAP or median of its window)
+        for expires in repository::expired_maturities(&conn, asset)? {
+            let Some(settlement_price) = attestation::attested_price(&conn, asset, expires)? else {
+                println!("⏳ {} maturity {} has no attested settlement price yet", asset, format_expires_timestamp(expires));
+                continue;
+            };
+            let btc_price = match (asset, parse_f64_flag(args, "--btc-price")?) {
+                (_, Some(btc_price)) => btc_price,
+                (Asset::Btc, None) => settlement_price,
+                (_, None) => match attestation::attested_price(&conn, Asset::Btc, expires)? {
+                    Some(btc_price) => btc_price,
+                    None => spot_price(Asset::Btc, None).await?,
+                },
+            };
+            let settled = repository::settle_expired_maturity(&conn, asset, expires, settlement_price, btc_price, now, &actor)?;
+            print_settled(asset, &settled, settlement_price, btc_price);
+        }
+        return Ok(());
+    };
+    // Payoffs of contracts quoted in BTC are converted at this price
+    let btc_price = match asset {
+        Asset::Btc => parse_f64_flag(args, "--btc-price")?.unwrap_or(settlement_price),
+        _ => spot_price(Asset::Btc, parse_f64_flag(args, "--btc-price")?).await?,
+    };
+    let settled = repository::settle_expired_contracts(&conn, asset, settlement_price, btc_price, now, &actor)?;
+    print_settled(asset, &settled, settlement_price, btc_price);
+    Ok(())
+}
+
+fn print_settled(asset: Asset, settled: &[ContractRecord], settlement_price: f64, btc_price: f64) {
+    let mut total_payoff = 0.0;
+    for record in settled {
+        let payoff = record.payoff_usd(settlement_price);
+        total_payoff += payoff;
+        println!(
+            "  #{} {} {:.2} x {:.8} {} -> payoff ${:.2} ({} {})",
+            record.id,
+            record.side,
+            record.strike_price,
+            record.quantity,
+            record.underlying,
+            payoff,
+            record.premium_currency.format(record.settlement_payoff().unwrap_or(0.0)),
+            record.premium_currency
+        );
+    }
+    println!(
+        "✅ Settled {} {} contract(s) at ${:.2} (BTC ${:.2}), total payoff ${:.2}",
+        settled.len(),
+        asset,
+        settlement_price,
+        btc_price,
+        total_payoff
+    );
+}
+
+async fn recompute_risk(args: &[&str]) -> CliResult {
+    let btc_price = spot_price(Asset::Btc, parse_f64_flag(args, "--spot")?).await?;
+    let risk_margin: f64 = env::var("RISK_MARGIN")
+        .unwrap_or_else(|_| "1.2".to_string())
+        .parse()
+        .unwrap_or(1.2);
+    // The curve the server prices at, fetched when it has a URL
+    let rates = RateSource::from_env()?;
+    if let Err(e) = rates.refresh(&HttpClient::default()).await {
+        eprintln!("⚠️  Could not fetch the risk-free rate curve, using the configured one: {}", e);
+    }
+    let rates = rates.curve();
+
+    let pool = open_pool()?;
+    let (contracts, realized_vol): (Vec<Contract>, Option<f64>) = {
+        let conn = pool.get()?;
+        let contracts = repository::load_active_contracts(&conn, Utc::now().timestamp())?;
+        let realized_vol = btc_options_api::vol::realized_vol(&conn, Asset::Btc, "7d")?.close_to_close;
+        (contracts, realized_vol)
+    };
+    let spot_vol = realized_vol.filter(|v| *v > 0.0).unwrap_or(0.6);
+
+    // Live IVs when Deribit is reachable; the default IV policy fills in the rest
+    let iv_policy = DefaultIvPolicy::from_env()?;
+    iv_policy.set_realized_vol(Asset::Btc, realized_vol);
+    let iv_oracle = IvOracle::new(deribit_url());
+    if let Err(e) = iv_oracle.fetch_and_update_iv().await {
+        eprintln!("⚠️  Could not fetch IV data, using the {} default IV policy: {}", iv_policy.mode(), e);
+    }
+    let iv_lookup = |side: &str, strike: f64, expire: &str| Some(iv_policy.resolve(&iv_oracle, Asset::Btc, side, strike, expire).value);
+
+    let risk_manager = RiskManager::new(risk_margin).with_margin_model(margin_model_from_env()?);
+    let margin = risk_manager.calculate_portfolio_risk(&contracts, btc_price, &rates, &iv_lookup);
+    let var = risk_manager.calculate_var(&contracts, btc_price, &rates, &iv_lookup, spot_vol, 1.0);
+
+    println!("📊 Portfolio risk at spot ${:.2}", btc_price);
+    println!("   Active contracts:  {}", contracts.len());
+    println!("   Margin required:   ${:.2} ({} model)", margin, risk_manager.margin_model_name());
+    println!("   1d VaR 95% / 99%:  ${:.2} / ${:.2}", var.var_95, var.var_99);
+    println!("   1d ES  95% / 99%:  ${:.2} / ${:.2}", var.es_95, var.es_99);
+    Ok(())
+}
+
+fn deribit_url() -> String {
+    env::var("DERIBIT_API_URL").unwrap_or_else(|_| "https://www.deribit.com/api/v2".to_string())
+}
+
+async fn dump_iv_cache() -> CliResult {
+    let iv_oracle = IvOracle::new(deribit_url());
+    iv_oracle.fetch_and_update_iv().await?;
+
+    println!("{:<8}  {:>10}  {:<4}  {:>8}", "EXPIRY", "STRIKE", "SIDE", "IV");
+    let entries = iv_oracle.snapshot();
+    for (expiry, strike, side, iv) in &entries {
+        println!("{:<8}  {:>10.0}  {:<4}  {:>7.2}%", expiry, strike, side, iv * 100.0);
+    }
+    println!("{} IV point(s)", entries.len());
+    Ok(())
+}
+
+fn run_migrations() -> CliResult {
+    // create_pool applies any pending migrations
+    let pool = open_pool()?;
+    let conn = pool.get()?;
+    let version = migrations::current_version(&conn)?;
+    println!("✅ Database schema at version {}", version);
+    Ok(())
+}
+
+fn rotate_api_key(name: &str) -> CliResult {
+    let pool = open_pool()?;
+    let conn = pool.get()?;
+    let key = api_keys::rotate_api_key(&conn, name, &audit_actor())?;
+    println!("🔑 New API key for '{}' (shown once, store it now):", name);
+    println!("{}", key);
+    Ok(())
+}
+
+fn print_trading_state(status: &TradingStatus) {
+    println!("🚦 Trading state: {}", status.state);
+    if let Some(reason) = &status.reason {
+        println!("   Reason: {}", reason);
+    }
+    if let (Some(by), Some(at)) = (&status.updated_by, status.updated_at) {
+        println!("   Set by {} at {}{}", by, at, if status.automatic { " (automatic)" } else { "" });
+    }
+}
+
+fn show_trading_state() -> CliResult {
+    let pool = open_pool()?;
+    let conn = pool.get()?;
+    print_trading_state(&trading_state::load(&conn)?);
+    Ok(())
+}
+
+fn set_trading_state(state: &str, args: &[&str]) -> CliResult {
+    let state: TradingState = state
+        .parse()
+        .map_err(|_| format!("unknown trading state '{}', expected open, reduce_only or halted", state))?;
+    let pool = open_pool()?;
+    let conn = pool.get()?;
+    let status = trading_state::set(&conn, state, flag_value(args, "--reason"), &audit_actor(), false)?;
+    print_trading_state(&status);
+    Ok(())
+}
+
+fn export_contracts(args: &[&str]) -> CliResult {
+    let pool = open_pool()?;
+    let conn = pool.get()?;
+    let records = repository::load_contract_records(&conn, None)?;
+    let json = serde_json::to_string_pretty(&records)?;
+
+    match flag_value(args, "--out") {
+        Some(path) => {
+            std::fs::write(path, json)?;
+            println!("✅ Exported {} contract(s) to {}", records.len(), path);
+        }
+        None => println!("{}", json),
+    }
+    Ok(())
+}
+
+fn backup_database() -> CliResult {
+    let pool = open_pool()?;
+    let conn = pool.get()?;
+    let path = backup::create_backup(&conn, &BackupConfig::manual_from_env(), Utc::now().timestamp())?;
+    println!("✅ Database backed up to {}", path.display());
+    Ok(())
+}
+
+fn list_backups() -> CliResult {
+    let config = BackupConfig::manual_from_env();
+    let backups = backup::list_backups(&config.dir)?;
+    for path in &backups {
+        let size = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+        println!("{}  {:>10} bytes", path.display(), size);
+    }
+    println!("{} backup(s) in {} (keeping {})", backups.len(), config.dir.display(), config.retention);
+    Ok(())
+}
+
+fn restore_database(path: &str) -> CliResult {
+    let path = std::path::Path::new(path);
+    // Check the backup before touching the live database
+    let version = backup::verify_backup(path)?;
+    let pool = open_pool()?;
+    let mut conn = pool.get()?;
+    let safety_copy = backup::create_backup(&conn, &BackupConfig::manual_from_env(), Utc::now().timestamp())?;
+    println!("💾 Current database backed up to {}", safety_copy.display());
+    backup::restore_backup(&mut conn, path)?;
+    println!(
+        "✅ Restored {} (schema version {}, now {})",
+        path.display(),
+        version,
+        migrations::current_version(&conn)?
+    );
+    Ok(())
+}
+
+fn take_snapshot(args: &[&str]) -> CliResult {
+    let now = Utc::now().timestamp();
+    let path = match flag_value(args, "--out") {
+        Some(path) => std::path::PathBuf::from(path),
+        None => BackupConfig::manual_from_env().dir.join(snapshot::snapshot_file_name(now)),
+    };
+    let config = snapshot::capture_config();
+    let files = snapshot::capture_files(&config)?;
+    let pool = open_pool()?;
+    let conn = pool.get()?;
+    let snapshot = snapshot::create_snapshot(&conn, config, files, now)?;
+    snapshot::write_snapshot(&snapshot, &path)?;
+    println!(
+        "✅ Snapshot of {} table(s), {} row(s), {} setting(s) and {} file(s) written to {}",
+        snapshot.tables.len(),
+        snapshot.row_count(),
+        snapshot.config.len(),
+        snapshot.files.len(),
+        path.display()
+    );
+    println!("   Schema version {}, checksum {}", snapshot.schema_version, snapshot.checksum);
+    if !snapshot.missing_secrets().is_empty() {
+        println!("   Secrets left out: {}", snapshot.missing_secrets().join(", "));
+    }
+    Ok(())
+}
+
+fn restore_snapshot(path: &str, args: &[&str]) -> CliResult {
+    // Check every checksum before touching the live database
+    let snapshot = snapshot::read_snapshot(std::path::Path::new(path))?;
+    let backup_config = BackupConfig::manual_from_env();
+    let pool = open_pool()?;
+    let mut conn = pool.get()?;
+    let safety_copy = backup::create_backup(&conn, &backup_config, Utc::now().timestamp())?;
+    println!("💾 Current database backed up to {}", safety_copy.display());
+    snapshot::restore_snapshot(&mut conn, &snapshot, &backup_config.dir)?;
+    println!(
+        "✅ Restored {} table(s), {} row(s) from {} (schema version {}, now {})",
+        snapshot.tables.len(),
+        snapshot.row_count(),
+        path,
+        snapshot.schema_version,
+        migrations::current_version(&conn)?
+    );
+    for written in snapshot::restore_files(&snapshot)? {
+        println!("📄 Wrote {}", written.display());
+    }
+    match flag_value(args, "--env-out") {
+        Some(env_out) => {
+            std::fs::write(env_out, snapshot.env_file())?;
+            println!("⚙️  {} setting(s) written to {}", snapshot.config.len(), env_out);
+        }
+        None => println!("⚙️  {} setting(s) in the snapshot; write them out with --env-out <path>", snapshot.config.len()),
+    }
+    if !snapshot.missing_secrets().is_empty() {
+        println!("🔑 Set these secrets again: {}", snapshot.missing_secrets().join(", "));
+    }
+    Ok(())
+}
+
+fn print_pool(pool: &Pool) {
+    println!(
+        "{:>4}  {:<12}  {:<8}  {:>6.2}  {:>6.2}  {}",
+        pool.id, pool.name, pool.network.to_string(), pool.collateral_rate, pool.risk_margin, pool.address
+    );
+}
+
+fn list_pools() -> CliResult {
+    let pool = open_pool()?;
+    let conn = pool.get()?;
+    let default_address = env::var("POOL_ADDRESS").unwrap_or_else(|_| "(POOL_ADDRESS not set)".to_string());
+    println!("{:>4}  {:<12}  {:<8}  {:>6}  {:>6}  ADDRESS", "ID", "NAME", "NETWORK", "RATE", "MARGIN");
+    print_pool(&Pool::default_from_env(&default_address));
+    for stored in pools::load_pools(&conn)? {
+        print_pool(&stored);
+    }
+    Ok(())
+}
+
+fn add_pool(name: &str, args: &[&str]) -> CliResult {
+    let address = flag_value(args, "--address").ok_or("--address is required")?;
+    let network = flag_value(args, "--network")
+        .ok_or("--network is required")?
+        .parse()?;
+    let new_pool = NewPool {
+        name: name.to_string(),
+        address: address.to_string(),
+        network,
+        collateral_rate: parse_f64_flag(args, "--collateral-rate")?.unwrap_or(0.5),
+        risk_margin: parse_f64_flag(args, "--risk-margin")?.unwrap_or(1.2),
+    };
+    let db = open_pool()?;
+    let conn = db.get()?;
+    let created = pools::add_pool(&conn, &new_pool, &audit_actor(), Utc::now().timestamp())?;
+    println!("✅ Added pool {}; sell from it with POST /pools/{}/contract", created.id, created.id);
+    print_pool(&created);
+    Ok(())
+}
+
+fn list_users() -> CliResult {
+    let pool = open_pool()?;
+    let conn = pool.get()?;
+    println!("{:<20}  {:<7}  UPDATED", "USERNAME", "ROLE");
+    for user in auth::load_users(&conn)? {
+        println!("{:<20}  {:<7}  {}", user.username, user.role, user.updated_at);
+    }
+    Ok(())
+}
+
+fn set_user(name: &str, args: &[&str]) -> CliResult {
+    let role = flag_value(args, "--role").ok_or("--role is required")?;
+    let role: Role = role
+        .parse()
+        .map_err(|_| format!("unknown role '{}', expected viewer, trader or admin", role))?;
+    let (password, generated) = match env::var("OPTADMIN_USER_PASSWORD") {
+        Ok(password) if !password.is_empty() => (password, false),
+        _ => (api_keys::generate_api_key(), true),
+    };
+    let pool = open_pool()?;
+    let conn = pool.get()?;
+    let user = auth::set_user(&conn, name, &password, role, &audit_actor(), Utc::now().timestamp())?;
+    println!("👤 User '{}' set with role {}", user.username, user.role);
+    if generated {
+        println!("Password (shown once, store it now):");
+        println!("{}", password);
+    }
+    Ok(())
+}
@@ -1,5 +1,4 @@
 use btc_options_api::iv_oracle::IvOracle;
-use tokio;
 
 #[tokio::main]
 async fn main() {
@@ -0,0 +1,265 @@
+// Product catalog.
+// A listing job keeps the catalog of listed options in step with the market: every
+// CATALOG_REFRESH_SECS (daily by default) it centers the strike grid of each enabled underlying
+// on the oracle spot price, as the options table does, and lists a call and a put at every
+// strike for each standard tenor. Tenors map to fixed maturities at CATALOG_EXPIRY_HOUR_UTC
+// (OPTIONS_EXPIRY_HOUR_UTC, 08:00 UTC, by default), so products listed on different days share expiries. Listed products are kept
+// when spot moves away; products whose maturity has passed are marked expired.
+
+use crate::error::{ApiError, ApiResult};
+use crate::models::{Asset, OptionSide};
+use crate::options_grid::{self, GridConfig};
+use crate::repository::Repository;
+use crate::sources::PriceSource;
+use crate::supervisor::Supervisor;
+use crate::utils::{cents_to_usd, tenor_expiry, usd_to_cents, DEFAULT_EXPIRY_HOUR_UTC};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use rusqlite::{params, Connection, Row};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::interval;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProductStatus {
+    #[default]
+    Active,
+    Expired,
+}
+
+impl fmt::Display for ProductStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ProductStatus::Active => write!(f, "active"),
+            ProductStatus::Expired => write!(f, "expired"),
+        }
+    }
+}
+
+impl ToSql for ProductStatus {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.to_string().into())
+    }
+}
+
+impl FromSql for ProductStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "active" => Ok(ProductStatus::Active),
+            "expired" => Ok(ProductStatus::Expired),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Product {
+    pub id: i64,
+    pub underlying: Asset,
+    pub side: OptionSide,
+    pub strike_price: f64,
+    pub expires: i64,
+    pub status: ProductStatus,
+    pub listed_at: i64,
+    pub expired_at: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CatalogConfig {
+    pub auto_list: bool,
+    pub refresh_interval: Duration,
+    pub expiry_hour: u32,  // UTC hour products mature at
+}
+
+impl Default for CatalogConfig {
+    fn default() -> Self {
+        Self {
+            auto_list: true,
+            refresh_interval: Duration::from_secs(24 * 60 * 60),
+            expiry_hour: DEFAULT_EXPIRY_HOUR_UTC,
+        }
+    }
+}
+
+impl CatalogConfig {
+    /// CATALOG_AUTO_LIST (true), CATALOG_REFRESH_SECS (86400) and CATALOG_EXPIRY_HOUR_UTC
+    /// (OPTIONS_EXPIRY_HOUR_UTC, so listings match the options table)
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            auto_list: env::var("CATALOG_AUTO_LIST")
+                .map(|v| !matches!(v.trim(), "false" | "0" | "off"))
+                .unwrap_or(defaults.auto_list),
+            refresh_interval: env::var("CATALOG_REFRESH_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .map(|secs: u64| Duration::from_secs(secs.max(60)))
+                .unwrap_or(defaults.refresh_interval),
+            expiry_hour: env::var("CATALOG_EXPIRY_HOUR_UTC")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|hour| *hour < 24)
+                .unwrap_or_else(options_grid::expiry_hour_from_env),
+        }
+    }
+
+    /// Maturity of `tenor` listed at `now`: the first expiry hour at least `tenor` away,
+    /// or the next whole minute or hour for intraday tenors
+    pub fn maturity(&self, tenor: &str, now: i64) -> Option<i64> {
+        tenor_expiry(tenor, now, self.expiry_hour)
+    }
+}
+
+fn product_from_row(row: &Row) -> rusqlite::Result<Product> {
+    Ok(Product {
+        id: row.get(0)?,
+        underlying: row.get(1)?,
+        side: row.get(2)?,
+        strike_price: cents_to_usd(row.get(3)?),
+        expires: row.get(4)?,
+        status: row.get(5)?,
+        listed_at: row.get(6)?,
+        expired_at: row.get(7)?,
+    })
+}
+
+/// List a product unless it already is. Returns whether it was added.
+pub fn list_product(conn: &Connection, underlying: Asset, side: OptionSide, strike_price: f64, expires: i64, now: i64) -> ApiResult<bool> {
+    let inserted = conn.execute(
+        "INSERT OR IGNORE INTO products (underlying, side, strike_price_cents, expires, status, listed_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![underlying, side, usd_to_cents(strike_price), expires, ProductStatus::Active, now],
+    )?;
+    Ok(inserted > 0)
+}
+
+/// Mark active products maturing by `now` expired. Returns how many were.
+pub fn expire_products(conn: &Connection, now: i64) -> ApiResult<usize> {
+    Ok(conn.execute(
+        "UPDATE products SET status = ?1, expired_at = ?2 WHERE status = ?3 AND expires <= ?2",
+        params![ProductStatus::Expired, now, ProductStatus::Active],
+    )?)
+}
+
+/// Products with `status`, optionally on one underlying, by expiry, side and strike
+pub fn load_products(conn: &Connection, status: Option<ProductStatus>, underlying: Option<Asset>) -> ApiResult<Vec<Product>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, underlying, side, strike_price_cents, expires, status, listed_at, expired_at
+         FROM products
+         WHERE (?1 IS NULL OR status = ?1) AND (?2 IS NULL OR underlying = ?2)
+         ORDER BY expires ASC, underlying ASC, side ASC, strike_price_cents ASC",
+    )?;
+    let rows = stmt.query_map(params![status, underlying], product_from_row)?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// Expire matured products and list the grid around spot for every underlying.
+/// Returns the number of products listed and expired.
+pub async fn refresh_catalog(
+    repository: &Repository,
+    price_source: &dyn PriceSource,
+    grid: &GridConfig,
+    assets: &[Asset],
+    config: &CatalogConfig,
+    now: i64,
+) -> ApiResult<(usize, usize)> {
+    let expired = repository.run(move |conn| expire_products(conn, now)).await?;
+    let mut listed = 0;
+    for &asset in assets {
+        let spot = price_source
+            .get_price(asset)
+            .await
+            .map_err(|e| ApiError::PriceOracleError(format!("no {} price to list products around: {}", asset, e)))?;
+        let grid = grid.for_asset(asset);
+        let strikes = grid.strikes(spot);
+        let maturities: Vec<i64> = grid.tenors.iter().filter_map(|tenor| config.maturity(tenor, now)).collect();
+        listed += repository
+            .run(move |conn| {
+                let tx = conn.unchecked_transaction()?;
+                let mut listed = 0;
+                for &expires in &maturities {
+                    for &strike in &strikes {
+                        for side in [OptionSide::Call, OptionSide::Put] {
+                            listed += list_product(&tx, asset, side, strike, expires, now)? as usize;
+                        }
+                    }
+                }
+                tx.commit()?;
+                Ok(listed)
+            })
+            .await?;
+    }
+    Ok((listed, expired))
+}
+
+/// Refresh the catalog at startup and every CATALOG_REFRESH_SECS
+pub fn start_listing_job(
+    supervisor: &Supervisor,
+    repository: Repository,
+    price_source: Arc<dyn PriceSource>,
+    grid: GridConfig,
+    assets: Vec<Asset>,
+    config: CatalogConfig,
+) {
+    if !config.auto_list {
+        return;
+    }
+    supervisor.spawn("product_listing", move || {
+        let (repository, price_source, grid, assets) = (repository.clone(), price_source.clone(), grid.clone(), assets.clone());
+        async move {
+            let mut ticker = interval(config.refresh_interval);
+            loop {
+                ticker.tick().await;
+                let now = chrono::Utc::now().timestamp();
+                match refresh_catalog(&repository, price_source.as_ref(), &grid, &assets, &config, now).await {
+                    Ok((0, 0)) => {}
+                    Ok((listed, expired)) => println!("📋 Product catalog: {} listed, {} expired", listed, expired),
+                    Err(e) => eprintln!("Error refreshing the product catalog: {}", e),
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_maturity() {
+        let config = CatalogConfig::default();
+        // 2025-01-01 07:00 UTC
+        let now = 1_735_714_800;
+        let day = 86_400;
+        let eight = 1_735_718_400;  // 2025-01-01 08:00 UTC
+        assert_eq!(config.maturity("1h", now), Some(eight));
+        assert_eq!(config.maturity("1d", now), Some(eight + day));
+        assert_eq!(config.maturity("7d", now), Some(eight + 7 * day));
+        // Past the expiry hour the maturity rolls to the next day
+        assert_eq!(config.maturity("1d", now + 2 * 3600), Some(eight + 2 * day));
+        assert_eq!(config.maturity("bogus", now), None);
+    }
+
+    #[test]
+    fn test_list_and_expire_products() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init_db(&conn).unwrap();
+        let now = 1_800_000_000;
+
+        assert!(list_product(&conn, Asset::Btc, OptionSide::Call, 100_000.0, now + 60, now).unwrap());
+        assert!(!list_product(&conn, Asset::Btc, OptionSide::Call, 100_000.0, now + 60, now).unwrap());
+        assert!(list_product(&conn, Asset::Btc, OptionSide::Put, 100_000.0, now + 86_400, now).unwrap());
+        assert!(list_product(&conn, Asset::Eth, OptionSide::Put, 3_500.0, now + 86_400, now).unwrap());
+
+        assert_eq!(expire_products(&conn, now + 60).unwrap(), 1);
+        let active = load_products(&conn, Some(ProductStatus::Active), Some(Asset::Btc)).unwrap();
+        assert_eq!(active.len(), 1);
+        assert_eq!((active[0].side, active[0].strike_price), (OptionSide::Put, 100_000.0));
+        let expired = load_products(&conn, Some(ProductStatus::Expired), None).unwrap();
+        assert_eq!(expired[0].expired_at, Some(now + 60));
+        assert_eq!(load_products(&conn, None, None).unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,81 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Stops calling a failing dependency for `cooldown` after `failure_threshold`
+/// consecutive failures. After the cooldown one trial call is let through;
+/// success closes the breaker, failure re-opens it.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Debug, Default)]
+struct BreakerState {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            cooldown,
+            state: Mutex::new(BreakerState::default()),
+        }
+    }
+
+    /// Whether a call may be attempted now
+    pub fn allow(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        match state.opened_at {
+            Some(opened_at) => opened_at.elapsed() >= self.cooldown,
+            None => true,
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures = 0;
+        state.opened_at = None;
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures += 1;
+        if state.consecutive_failures >= self.failure_threshold {
+            // (Re-)open; a failed trial call restarts the cooldown
+            state.opened_at = Some(Instant::now());
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        !self.allow()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opens_after_threshold_and_recovers() {
+        let breaker = CircuitBreaker::new(2, Duration::from_millis(20));
+        assert!(breaker.allow());
+
+        breaker.record_failure();
+        assert!(breaker.allow());
+        breaker.record_failure();
+        assert!(breaker.is_open());
+
+        std::thread::sleep(Duration::from_millis(25));
+        assert!(breaker.allow());
+        breaker.record_failure();
+        assert!(breaker.is_open());
+
+        std::thread::sleep(Duration::from_millis(25));
+        breaker.record_success();
+        assert!(breaker.allow());
+    }
+}
@@ -0,0 +1,244 @@
+// Contract search for the operator console.
+// GET /contracts/search narrows contracts down by a fragment of their product symbol and by
+// range filters: underlying, side, status, a moneyness band around spot, time left to expiry
+// and minimum open quantity. The range filters go through `ContractQuery`, which ANDs them
+// into one WHERE clause with positional parameters. Product symbols are derived rather than
+// stored, so the symbol fragment is matched against rows as they are read, newest first,
+// until `limit` matched.
+
+use crate::error::{ApiError, ApiResult};
+use crate::models::{legacy_product_symbol, product_symbol, Asset, ContractRecord, ContractStatus, OptionSide};
+use crate::repository::{contract_record_from_row, CONTRACT_RECORD_COLUMNS};
+use crate::utils::{btc_to_sats, usd_to_cents};
+use rusqlite::types::Value;
+use rusqlite::Connection;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+pub const DEFAULT_SEARCH_LIMIT: u32 = 100;
+pub const MAX_SEARCH_LIMIT: u32 = 1000;
+
+// Search filters; every field is optional and they combine with AND
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct ContractSearchQuery {
+    pub q: Option<String>,                  // Fragment of the product symbol, e.g. "28MAR25" or "50000-C"
+    pub underlying: Option<Asset>,
+    pub side: Option<OptionSide>,
+    pub status: Option<ContractStatus>,
+    pub moneyness_percent: Option<f64>,     // Strikes within ± this percentage of spot
+    pub expiring_within_days: Option<f64>,  // Unexpired and expiring within this many days
+    pub min_quantity: Option<f64>,          // Open quantity, in BTC
+    pub limit: Option<u32>,
+}
+
+impl ContractSearchQuery {
+    /// Reject filters that cannot match anything sensible
+    pub fn validate(&self) -> ApiResult<()> {
+        let positive = |name: &str, value: Option<f64>| match value {
+            Some(value) if !value.is_finite() || value <= 0.0 => {
+                Err(ApiError::ValidationError(format!("{} must be positive, got {}", name, value)))
+            }
+            _ => Ok(()),
+        };
+        positive("moneyness_percent", self.moneyness_percent)?;
+        positive("expiring_within_days", self.expiring_within_days)?;
+        if let Some(min_quantity) = self.min_quantity {
+            if !min_quantity.is_finite() || min_quantity < 0.0 {
+                return Err(ApiError::ValidationError(format!("min_quantity must not be negative, got {}", min_quantity)));
+            }
+        }
+        if let Some(limit) = self.limit {
+            if limit == 0 || limit > MAX_SEARCH_LIMIT {
+                return Err(ApiError::ValidationError(format!("limit must be between 1 and {}", MAX_SEARCH_LIMIT)));
+            }
+        }
+        Ok(())
+    }
+
+    // Upper-cased symbol fragment, if any is left after trimming
+    fn fragment(&self) -> Option<String> {
+        self.q.as_deref().map(str::trim).filter(|q| !q.is_empty()).map(str::to_uppercase)
+    }
+}
+
+/// WHERE clause of a contracts query, built up one condition at a time. Conditions use `?`
+/// placeholders, bound in the order the conditions were added.
+#[derive(Clone, Debug, Default)]
+pub struct ContractQuery {
+    conditions: Vec<String>,
+    params: Vec<Value>,
+}
+
+impl ContractQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// AND `condition`, whose placeholders take `params`
+    pub fn filter(mut self, condition: impl Into<String>, params: impl IntoIterator<Item = Value>) -> Self {
+        self.conditions.push(condition.into());
+        self.params.extend(params);
+        self
+    }
+
+    /// AND `condition` when `value` is set, with `value` as its one parameter
+    pub fn filter_opt<T: Into<Value>>(self, condition: &str, value: Option<T>) -> Self {
+        match value {
+            Some(value) => self.filter(condition, [value.into()]),
+            None => self,
+        }
+    }
+
+    /// SELECT of `columns` from contracts matching every condition, ordered by `order_by`
+    pub fn select(&self, columns: &str, order_by: &str) -> String {
+        let mut sql = format!("SELECT {} FROM contracts", columns);
+        if !self.conditions.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&self.conditions.join(" AND "));
+        }
+        sql.push_str(" ORDER BY ");
+        sql.push_str(order_by);
+        sql
+    }
+
+    pub fn params(&self) -> &[Value] {
+        &self.params
+    }
+}
+
+/// The range filters of `query` as a `ContractQuery`. The moneyness band is taken around
+/// `spots`, so underlyings without a spot there match no strikes.
+pub fn build_query(query: &ContractSearchQuery, spots: &HashMap<Asset, f64>, now: i64) -> ContractQuery {
+    let mut built = ContractQuery::new()
+        .filter_opt("underlying = ?", query.underlying.map(|asset| asset.to_string()))
+        .filter_opt("side = ?", query.side.map(|side| side.to_string()))
+        .filter_opt("status = ?", query.status.map(|status| status.to_string()))
+        .filter_opt("quantity_sats - closed_quantity_sats >= ?", query.min_quantity.map(btc_to_sats));
+
+    if let Some(days) = query.expiring_within_days {
+        let until = now + (days * 86_400.0).round() as i64;
+        built = built.filter("expires > ? AND expires <= ?", [Value::from(now), Value::from(until)]);
+    }
+    if let Some(percent) = query.moneyness_percent {
+        let mut bands: Vec<(Asset, f64)> = spots.iter().map(|(asset, spot)| (*asset, *spot)).collect();
+        bands.sort_by_key(|(asset, _)| asset.to_string());
+        let condition = match bands.len() {
+            0 => "0".to_string(),
+            n => format!("({})", vec!["(underlying = ? AND strike_price_cents BETWEEN ? AND ?)"; n].join(" OR ")),
+        };
+        let params = bands.into_iter().flat_map(|(asset, spot)| {
+            [
+                Value::from(asset.to_string()),
+                Value::from(usd_to_cents(spot * (1.0 - percent / 100.0))),
+                Value::from(usd_to_cents(spot * (1.0 + percent / 100.0))),
+            ]
+        });
+        built = built.filter(condition, params.collect::<Vec<_>>());
+    }
+    built
+}
+
+/// Contracts matching `query`, newest first. `spots` are the spot prices the moneyness band
+/// is taken around.
+pub fn search(conn: &Connection, query: &ContractSearchQuery, spots: &HashMap<Asset, f64>, now: i64) -> ApiResult<Vec<ContractRecord>> {
+    let limit = query.limit.unwrap_or(DEFAULT_SEARCH_LIMIT).min(MAX_SEARCH_LIMIT) as usize;
+    let fragment = query.fragment();
+    let built = build_query(query, spots, now);
+
+    let mut stmt = conn.prepare(&built.select(CONTRACT_RECORD_COLUMNS, "id DESC"))?;
+    let rows = stmt.query_map(rusqlite::params_from_iter(built.params()), contract_record_from_row)?;
+    let mut found = Vec::new();
+    for row in rows {
+        let contract = row?;
+        if fragment.as_deref().is_none_or(|fragment| matches_symbol(&contract, fragment)) {
+            found.push(contract);
+            if found.len() == limit {
+                break;
+            }
+        }
+    }
+    Ok(found)
+}
+
+// Whether either product symbol of `contract` contains the upper-cased `fragment`
+fn matches_symbol(contract: &ContractRecord, fragment: &str) -> bool {
+    let (underlying, side, strike, expires) = (contract.underlying, &contract.side, contract.strike_price, contract.expires);
+    product_symbol(underlying, side, strike, expires).to_uppercase().contains(fragment)
+        || legacy_product_symbol(underlying, side, strike, expires).to_uppercase().contains(fragment)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::params;
+
+    // 2025-03-28 08:00 UTC
+    const MATURITY: i64 = 1_743_148_800;
+
+    fn insert(conn: &Connection, underlying: &str, side: &str, strike_cents: i64, quantity_sats: i64, expires: i64, status: &str) {
+        conn.execute(
+            "INSERT INTO contracts (side, strike_price_cents, quantity_sats, expires, premium_sats, underlying, status)
+             VALUES (?1, ?2, ?3, ?4, 100000, ?5, ?6)",
+            params![side, strike_cents, quantity_sats, expires, underlying, status],
+        )
+        .unwrap();
+    }
+
+    fn ids(conn: &Connection, query: ContractSearchQuery, spots: &HashMap<Asset, f64>) -> Vec<i64> {
+        search(conn, &query, spots, MATURITY - 30 * 86_400).unwrap().iter().map(|c| c.id).collect()
+    }
+
+    #[test]
+    fn test_search_combines_symbol_and_range_filters() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init_db(&conn).unwrap();
+        insert(&conn, "BTC", "Call", 5_000_000, 100_000_000, MATURITY, "open");            // 1
+        insert(&conn, "BTC", "Put", 4_000_000, 10_000_000, MATURITY, "open");              // 2
+        insert(&conn, "BTC", "Call", 5_400_000, 50_000_000, MATURITY + 90 * 86_400, "open"); // 3
+        insert(&conn, "ETH", "Call", 350_000, 200_000_000, MATURITY, "settled");           // 4
+        let spots = HashMap::from([(Asset::Btc, 50_000.0), (Asset::Eth, 3_600.0)]);
+
+        assert_eq!(ids(&conn, ContractSearchQuery::default(), &spots), vec![4, 3, 2, 1]);
+
+        let q = |q: &str| ContractSearchQuery { q: Some(q.to_string()), ..Default::default() };
+        assert_eq!(ids(&conn, q("28mar25"), &spots), vec![4, 2, 1]);
+        assert_eq!(ids(&conn, q("btc-28MAR25-50000-C"), &spots), vec![1]);
+        assert_eq!(ids(&conn, q("-Put"), &spots), vec![2]);
+        assert_eq!(ids(&conn, q("  "), &spots), vec![4, 3, 2, 1]);
+
+        // ±10% of spot leaves out the 40000 put; the ETH 3500 call is within 10% of 3600
+        let band = ContractSearchQuery { moneyness_percent: Some(10.0), ..Default::default() };
+        assert_eq!(ids(&conn, band.clone(), &spots), vec![4, 3, 1]);
+        assert_eq!(ids(&conn, band, &HashMap::new()), Vec::<i64>::new());
+
+        let expiring = ContractSearchQuery { expiring_within_days: Some(31.0), ..Default::default() };
+        assert_eq!(ids(&conn, expiring, &spots), vec![4, 2, 1]);
+
+        let combined = ContractSearchQuery {
+            q: Some("-C".to_string()),
+            underlying: Some(Asset::Btc),
+            min_quantity: Some(0.5),
+            expiring_within_days: Some(31.0),
+            ..Default::default()
+        };
+        assert_eq!(ids(&conn, combined, &spots), vec![1]);
+
+        let settled = ContractSearchQuery { status: Some(ContractStatus::Settled), limit: Some(1), ..Default::default() };
+        assert_eq!(ids(&conn, settled, &spots), vec![4]);
+        assert_eq!(ids(&conn, ContractSearchQuery { limit: Some(2), ..Default::default() }, &spots), vec![4, 3]);
+    }
+
+    #[test]
+    fn test_search_query_validation() {
+        assert!(ContractSearchQuery::default().validate().is_ok());
+        for query in [
+            ContractSearchQuery { moneyness_percent: Some(0.0), ..Default::default() },
+            ContractSearchQuery { expiring_within_days: Some(f64::NAN), ..Default::default() },
+            ContractSearchQuery { min_quantity: Some(-1.0), ..Default::default() },
+            ContractSearchQuery { limit: Some(0), ..Default::default() },
+            ContractSearchQuery { limit: Some(MAX_SEARCH_LIMIT + 1), ..Default::default() },
+        ] {
+            assert!(query.validate().is_err(), "{:?}", query);
+        }
+    }
+}
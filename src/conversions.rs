@@ -0,0 +1,125 @@
+// Currency conversions of contract cash flows.
+// Premiums are agreed in the buyer's premium currency and stored in BTC; payouts and close
+// proceeds are worked out in USD and paid in the premium currency. Each conversion is recorded
+// with the BTC/USD rate applied, in the same transaction as the flow, so the book can be
+// reconciled against the prices that were actually used.
+
+use crate::error::ApiResult;
+use crate::models::QuoteCurrency;
+use crate::utils::{btc_to_sats, cents_to_usd, format_sats, sats_to_btc, usd_to_cents};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use rusqlite::{params, Connection, Row};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConversionKind {
+    Premium,  // Paid by the buyer at trade time
+    Payout,   // Owed to the buyer at settlement or exercise
+    Close,    // Paid to the buyer for selling back to the pool
+}
+
+impl fmt::Display for ConversionKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConversionKind::Premium => write!(f, "premium"),
+            ConversionKind::Payout => write!(f, "payout"),
+            ConversionKind::Close => write!(f, "close"),
+        }
+    }
+}
+
+impl ToSql for ConversionKind {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.to_string().into())
+    }
+}
+
+impl FromSql for ConversionKind {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "premium" => Ok(ConversionKind::Premium),
+            "payout" => Ok(ConversionKind::Payout),
+            "close" => Ok(ConversionKind::Close),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+/// One cash flow of a contract in its premium currency, BTC and USD
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Conversion {
+    pub kind: ConversionKind,
+    pub currency: QuoteCurrency,
+    pub amount: String,  // In currency
+    pub btc: String,     // 8 decimal string
+    pub usd: f64,
+    pub btc_price: f64,  // USD per BTC applied
+    pub created_at: i64,
+}
+
+fn conversion_from_row(row: &Row) -> rusqlite::Result<Conversion> {
+    let currency: QuoteCurrency = row.get(1)?;
+    Ok(Conversion {
+        kind: row.get(0)?,
+        currency,
+        amount: currency.format(currency.from_minor(row.get(2)?)),
+        btc: format_sats(row.get(3)?),
+        usd: cents_to_usd(row.get(4)?),
+        btc_price: cents_to_usd(row.get(5)?),
+        created_at: row.get(6)?,
+    })
+}
+
+/// Record `btc` of contract `contract_id` flowing as `kind`, converted at `btc_price`
+pub fn record(
+    conn: &Connection,
+    contract_id: i64,
+    kind: ConversionKind,
+    currency: QuoteCurrency,
+    btc: f64,
+    btc_price: f64,
+    now: i64,
+) -> ApiResult<()> {
+    let btc_sats = btc_to_sats(btc);
+    let btc = sats_to_btc(btc_sats);
+    conn.execute(
+        "INSERT INTO conversions (contract_id, kind, currency, amount_minor, btc_sats, usd_cents, btc_price_cents, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            contract_id,
+            kind,
+            currency,
+            currency.to_minor(currency.from_btc(btc, btc_price)),
+            btc_sats,
+            usd_to_cents(btc * btc_price),
+            usd_to_cents(btc_price),
+            now
+        ],
+    )?;
+    Ok(())
+}
+
+/// Record `usd` of contract `contract_id` flowing as `kind`, paid in BTC at `btc_price`
+pub fn record_usd(
+    conn: &Connection,
+    contract_id: i64,
+    kind: ConversionKind,
+    currency: QuoteCurrency,
+    usd: f64,
+    btc_price: f64,
+    now: i64,
+) -> ApiResult<()> {
+    record(conn, contract_id, kind, currency, usd / btc_price, btc_price, now)
+}
+
+/// Conversions of contract `contract_id`, oldest first
+pub fn load_conversions(conn: &Connection, contract_id: i64) -> ApiResult<Vec<Conversion>> {
+    let mut stmt = conn.prepare(
+        "SELECT kind, currency, amount_minor, btc_sats, usd_cents, btc_price_cents, created_at
+         FROM conversions WHERE contract_id = ?1 ORDER BY id ASC",
+    )?;
+    let rows = stmt.query_map(params![contract_id], conversion_from_row)?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
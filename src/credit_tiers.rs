@@ -0,0 +1,426 @@
+// Counterparty credit tiers.
+// A tier caps what one account may hold beyond the venue-wide position limits: its open
+// notional across underlyings, the notional of a single contract, and the products it may buy,
+// so one buyer cannot absorb the whole pool. A tier can also set the fee schedule of its
+// accounts (see fees.rs). Accounts are API key names. Each account has at
+// most one tier; accounts without one use the tier named "default" when it exists. Tiers and
+// assignments are managed through the /admin/creditTiers endpoints and every change is audited.
+
+use crate::audit;
+use crate::fees;
+use crate::error::{ApiError, ApiResult, ErrorCode};
+use crate::models::{Asset, Contract, OptionSide};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// Tier of accounts without an assignment
+pub const DEFAULT_TIER: &str = "default";
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct CreditTier {
+    pub id: i64,
+    pub name: String,
+    #[serde(flatten)]
+    pub terms: CreditTierTerms,
+    pub updated_at: i64,
+}
+
+/// Limits of a tier; None leaves that part unlimited
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct CreditTierTerms {
+    #[serde(default)]
+    pub max_open_notional_usd: Option<f64>,    // Open notional at spot, all underlyings
+    #[serde(default)]
+    pub max_trade_notional_usd: Option<f64>,   // Notional of a single contract at spot
+    #[serde(default)]
+    pub allowed_products: Option<Vec<String>>, // "BTC" for both sides, "BTC-C" or "BTC-P" for one
+    #[serde(default)]
+    pub fee_percent: Option<f64>,              // Fee on the premium; negative for a rebate, None for FEE_PERCENT
+    #[serde(default)]
+    pub min_fee_sats: Option<i64>,             // None for FEE_MIN_SATS
+}
+
+/// Tier assigned to an account
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct TierAssignment {
+    pub account: String,
+    pub tier: String,
+    pub assigned_at: i64,
+}
+
+// Product key of a contract as matched against allowed_products, e.g. "BTC-C"
+fn product_key(underlying: Asset, side: &OptionSide) -> String {
+    let side = match side {
+        OptionSide::Call => "C",
+        OptionSide::Put => "P",
+    };
+    format!("{}-{}", underlying, side)
+}
+
+impl CreditTierTerms {
+    // Check the limits and normalize product names to upper case
+    fn validated(mut self) -> ApiResult<Self> {
+        for (field, limit) in [
+            ("max_open_notional_usd", self.max_open_notional_usd),
+            ("max_trade_notional_usd", self.max_trade_notional_usd),
+        ] {
+            if let Some(limit) = limit.filter(|limit| !(limit.is_finite() && *limit > 0.0)) {
+                return Err(ApiError::ValidationError(format!("{} must be positive, got {}", field, limit)));
+            }
+        }
+        if let Some(percent) = self.fee_percent {
+            fees::validate_percent(percent)?;
+        }
+        if let Some(min_fee_sats) = self.min_fee_sats.filter(|sats| *sats < 0) {
+            return Err(ApiError::ValidationError(format!("min_fee_sats must not be negative, got {}", min_fee_sats)));
+        }
+        if let Some(products) = self.allowed_products.as_mut() {
+            for product in products.iter_mut() {
+                *product = product.trim().to_uppercase();
+                let (asset, side) = match product.split_once('-') {
+                    Some((asset, side)) => (asset, Some(side)),
+                    None => (product.as_str(), None),
+                };
+                if asset.parse::<Asset>().is_err() || !matches!(side, None | Some("C") | Some("P")) {
+                    return Err(ApiError::ValidationError(format!(
+                        "Unknown product '{}' in allowed_products, expected an underlying such as BTC or BTC-C/BTC-P",
+                        product
+                    )));
+                }
+            }
+            products.sort();
+            products.dedup();
+        }
+        Ok(self)
+    }
+}
+
+impl CreditTier {
+    /// Whether the tier lets its accounts buy `side` options on `underlying`
+    pub fn allows(&self, underlying: Asset, side: &OptionSide) -> bool {
+        match &self.terms.allowed_products {
+            None => true,
+            Some(products) => {
+                let key = product_key(underlying, side);
+                products.iter().any(|product| *product == key || *product == underlying.to_string())
+            }
+        }
+    }
+
+    /// Reject `contract` if `account` may not buy its product, or if it exceeds the single
+    /// contract or open notional limit. `account_book` holds the account's other open
+    /// contracts; `spot_prices` must cover them.
+    pub fn check(
+        &self,
+        account: &str,
+        contract: &Contract,
+        account_book: &[Contract],
+        spot_prices: &HashMap<Asset, f64>,
+    ) -> Result<(), ApiError> {
+        if !self.allows(contract.underlying, &contract.side) {
+            let product = product_key(contract.underlying, &contract.side);
+            return Err(ApiError::ValidationError(format!(
+                "Account {} in credit tier {} may not buy {} options",
+                account, self.name, product
+            ))
+            .with_code(ErrorCode::ProductNotAllowed)
+            .with_details(serde_json::json!({
+                "account": account,
+                "tier": self.name,
+                "product": product,
+                "allowed_products": self.terms.allowed_products,
+            })));
+        }
+        let notional = |c: &Contract| c.quantity * spot_prices.get(&c.underlying).copied().unwrap_or(0.0);
+        let trade_notional_usd = notional(contract);
+        if let Some(limit) = self.terms.max_trade_notional_usd.filter(|limit| trade_notional_usd > *limit) {
+            return Err(ApiError::PositionLimitExceeded(format!(
+                "contract notional of ${:.2} exceeds the ${:.2} limit of credit tier {} of account {}",
+                trade_notional_usd, limit, self.name, account
+            ))
+            .with_details(serde_json::json!({
+                "limit": "tier_max_trade_notional_usd",
+                "tier": self.name,
+                "value": trade_notional_usd,
+                "current": 0.0,
+                "max": limit,
+            })));
+        }
+        let current_notional_usd: f64 = account_book.iter().map(notional).sum();
+        let notional_usd = current_notional_usd + trade_notional_usd;
+        if let Some(limit) = self.terms.max_open_notional_usd.filter(|limit| notional_usd > *limit) {
+            return Err(ApiError::PositionLimitExceeded(format!(
+                "open notional of account {} would be ${:.2} (currently ${:.2}), limit ${:.2} of credit tier {}",
+                account, notional_usd, current_notional_usd, limit, self.name
+            ))
+            .with_details(serde_json::json!({
+                "limit": "tier_max_open_notional_usd",
+                "tier": self.name,
+                "value": notional_usd,
+                "current": current_notional_usd,
+                "max": limit,
+            })));
+        }
+        Ok(())
+    }
+}
+
+const TIER_COLUMNS: &str =
+    "id, name, max_open_notional_usd, max_trade_notional_usd, allowed_products, updated_at, fee_percent, min_fee_sats";
+
+fn tier_from_row(row: &Row) -> rusqlite::Result<CreditTier> {
+    let allowed_products: Option<String> = row.get(4)?;
+    Ok(CreditTier {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        terms: CreditTierTerms {
+            max_open_notional_usd: row.get(2)?,
+            max_trade_notional_usd: row.get(3)?,
+            allowed_products: allowed_products
+                .map(|products| serde_json::from_str(&products))
+                .transpose()
+                .map_err(|e| rusqlite::Error::FromSqlConversionFailure(4, rusqlite::types::Type::Text, e.into()))?,
+            fee_percent: row.get(6)?,
+            min_fee_sats: row.get(7)?,
+        },
+        updated_at: row.get(5)?,
+    })
+}
+
+/// Tier `name`, if it exists
+pub fn load_tier(conn: &Connection, name: &str) -> ApiResult<Option<CreditTier>> {
+    let sql = format!("SELECT {} FROM credit_tiers WHERE name = ?1", TIER_COLUMNS);
+    Ok(conn.query_row(&sql, params![name], tier_from_row).optional()?)
+}
+
+/// All tiers by name
+pub fn load_tiers(conn: &Connection) -> ApiResult<Vec<CreditTier>> {
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM credit_tiers ORDER BY name ASC", TIER_COLUMNS))?;
+    let rows = stmt.query_map([], tier_from_row)?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// All tier assignments by account
+pub fn load_assignments(conn: &Connection) -> ApiResult<Vec<TierAssignment>> {
+    let mut stmt = conn.prepare(
+        "SELECT a.account, t.name, a.assigned_at FROM account_credit_tiers a
+         JOIN credit_tiers t ON t.id = a.tier_id ORDER BY a.account ASC",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok(TierAssignment { account: row.get(0)?, tier: row.get(1)?, assigned_at: row.get(2)? })
+    })?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// Tier assigned to `account`, without the default tier fallback
+pub fn load_assignment(conn: &Connection, account: &str) -> ApiResult<Option<TierAssignment>> {
+    Ok(conn
+        .query_row(
+            "SELECT a.account, t.name, a.assigned_at FROM account_credit_tiers a
+             JOIN credit_tiers t ON t.id = a.tier_id WHERE a.account = ?1",
+            params![account],
+            |row| Ok(TierAssignment { account: row.get(0)?, tier: row.get(1)?, assigned_at: row.get(2)? }),
+        )
+        .optional()?)
+}
+
+/// Tier whose limits apply to `account`: its assigned tier, else the default tier if defined
+pub fn account_tier(conn: &Connection, account: &str) -> ApiResult<Option<CreditTier>> {
+    let sql = format!(
+        "SELECT {} FROM credit_tiers WHERE id = COALESCE(
+             (SELECT tier_id FROM account_credit_tiers WHERE account = ?1),
+             (SELECT id FROM credit_tiers WHERE name = ?2))",
+        TIER_COLUMNS
+    );
+    Ok(conn.query_row(&sql, params![account, DEFAULT_TIER], tier_from_row).optional()?)
+}
+
+/// Check `contract` against the tier of `account`, if it has one. See `CreditTier::check`.
+pub fn check_account(
+    conn: &Connection,
+    account: &str,
+    contract: &Contract,
+    account_book: &[Contract],
+    spot_prices: &HashMap<Asset, f64>,
+) -> ApiResult<()> {
+    match account_tier(conn, account)? {
+        Some(tier) => tier.check(account, contract, account_book, spot_prices),
+        None => Ok(()),
+    }
+}
+
+/// Create tier `name` or replace its limits, audited under `actor`
+pub fn set_tier(conn: &Connection, name: &str, terms: CreditTierTerms, actor: &str, now: i64) -> ApiResult<CreditTier> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(ApiError::ValidationError("Credit tier name must be set".to_string()));
+    }
+    let terms = terms.validated()?;
+    let allowed_products = terms
+        .allowed_products
+        .as_ref()
+        .map(serde_json::to_string)
+        .transpose()
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    let tx = conn.unchecked_transaction()?;
+    let before = load_tier(&tx, name)?;
+    tx.execute(
+        "INSERT INTO credit_tiers
+             (name, max_open_notional_usd, max_trade_notional_usd, allowed_products, updated_at, fee_percent, min_fee_sats)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+         ON CONFLICT(name) DO UPDATE SET max_open_notional_usd = excluded.max_open_notional_usd,
+             max_trade_notional_usd = excluded.max_trade_notional_usd,
+             allowed_products = excluded.allowed_products, updated_at = excluded.updated_at,
+             fee_percent = excluded.fee_percent, min_fee_sats = excluded.min_fee_sats",
+        params![
+            name,
+            terms.max_open_notional_usd,
+            terms.max_trade_notional_usd,
+            allowed_products,
+            now,
+            terms.fee_percent,
+            terms.min_fee_sats
+        ],
+    )?;
+    let tier = load_tier(&tx, name)?
+        .ok_or_else(|| ApiError::DatabaseError("Credit tier vanished after upsert".to_string()))?;
+    audit::record(
+        &tx,
+        actor,
+        audit::CREDIT_TIER_SET,
+        Some(tier.id),
+        before.and_then(|before| serde_json::to_value(before).ok()).as_ref(),
+        serde_json::to_value(&tier).ok().as_ref(),
+    )?;
+    tx.commit()?;
+    Ok(tier)
+}
+
+/// Delete tier `name`, audited under `actor`. Tiers still assigned to accounts are kept.
+pub fn delete_tier(conn: &Connection, name: &str, actor: &str) -> ApiResult<()> {
+    let tx = conn.unchecked_transaction()?;
+    let tier = load_tier(&tx, name)?.ok_or_else(|| ApiError::NotFound(format!("Credit tier {} not found", name)))?;
+    let accounts: i64 = tx.query_row(
+        "SELECT COUNT(*) FROM account_credit_tiers WHERE tier_id = ?1",
+        params![tier.id],
+        |row| row.get(0),
+    )?;
+    if accounts > 0 {
+        return Err(ApiError::ValidationError(format!(
+            "Credit tier {} is assigned to {} account(s); reassign them first",
+            name, accounts
+        )));
+    }
+    tx.execute("DELETE FROM credit_tiers WHERE id = ?1", params![tier.id])?;
+    audit::record(&tx, actor, audit::CREDIT_TIER_DELETE, Some(tier.id), serde_json::to_value(&tier).ok().as_ref(), None)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Assign `account` to tier `tier`, or back to the default tier when None, audited under `actor`
+pub fn assign_tier(
+    conn: &Connection,
+    account: &str,
+    tier: Option<&str>,
+    actor: &str,
+    now: i64,
+) -> ApiResult<Option<TierAssignment>> {
+    if account.trim().is_empty() {
+        return Err(ApiError::ValidationError("Account must be set".to_string()));
+    }
+    let tx = conn.unchecked_transaction()?;
+    let before = load_assignment(&tx, account)?;
+    match tier {
+        Some(name) => {
+            let tier = load_tier(&tx, name)?
+                .ok_or_else(|| ApiError::NotFound(format!("Credit tier {} not found", name)))?;
+            tx.execute(
+                "INSERT INTO account_credit_tiers (account, tier_id, assigned_at) VALUES (?1, ?2, ?3)
+                 ON CONFLICT(account) DO UPDATE SET tier_id = excluded.tier_id, assigned_at = excluded.assigned_at",
+                params![account, tier.id, now],
+            )?;
+        }
+        None => {
+            tx.execute("DELETE FROM account_credit_tiers WHERE account = ?1", params![account])?;
+        }
+    }
+    let after = load_assignment(&tx, account)?;
+    audit::record(
+        &tx,
+        actor,
+        audit::CREDIT_TIER_ASSIGN,
+        None,
+        Some(&serde_json::json!({ "account": account, "tier": before.map(|a| a.tier) })),
+        Some(&serde_json::json!({ "account": account, "tier": after.as_ref().map(|a| &a.tier) })),
+    )?;
+    tx.commit()?;
+    Ok(after)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contract(underlying: Asset, side: OptionSide, quantity: f64) -> Contract {
+        Contract { underlying, side, strike_price: 100_000.0, quantity, expires: 1_800_000_000, premium: 0.01 }
+    }
+
+    fn terms(max_open: Option<f64>, max_trade: Option<f64>, products: Option<&[&str]>) -> CreditTierTerms {
+        CreditTierTerms {
+            max_open_notional_usd: max_open,
+            max_trade_notional_usd: max_trade,
+            allowed_products: products.map(|p| p.iter().map(|s| s.to_string()).collect()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_tier_limits() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init_db(&conn).unwrap();
+        let spot_prices = HashMap::from([(Asset::Btc, 100_000.0), (Asset::Eth, 4_000.0)]);
+        let tier = set_tier(&conn, "retail", terms(Some(150_000.0), Some(100_000.0), Some(&["btc-c", "ETH"])), "ops", 1).unwrap();
+        assert_eq!(tier.terms.allowed_products, Some(vec!["BTC-C".to_string(), "ETH".to_string()]));
+
+        // Products outside the tier are refused
+        assert!(tier.allows(Asset::Eth, &OptionSide::Put));
+        let err = tier.check("alice", &contract(Asset::Btc, OptionSide::Put, 0.1), &[], &spot_prices).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::ProductNotAllowed);
+        // A single contract above $100k and open notional above $150k are refused
+        let err = tier.check("alice", &contract(Asset::Btc, OptionSide::Call, 1.5), &[], &spot_prices).unwrap_err();
+        assert!(err.to_string().contains("contract notional of $150000.00"), "{}", err);
+        let book = [contract(Asset::Btc, OptionSide::Call, 1.0)];
+        let err = tier.check("alice", &contract(Asset::Eth, OptionSide::Call, 20.0), &book, &spot_prices).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::PositionLimitExceeded);
+        assert!(tier.check("alice", &contract(Asset::Eth, OptionSide::Call, 10.0), &book, &spot_prices).is_ok());
+
+        // Unassigned accounts use the default tier once it exists
+        assert_eq!(account_tier(&conn, "alice").unwrap(), None);
+        let default = set_tier(&conn, DEFAULT_TIER, terms(Some(10_000.0), None, None), "ops", 2).unwrap();
+        assert_eq!(account_tier(&conn, "alice").unwrap(), Some(default));
+        assert!(check_account(&conn, "alice", &contract(Asset::Btc, OptionSide::Put, 0.2), &[], &spot_prices).is_err());
+        let assignment = assign_tier(&conn, "alice", Some("retail"), "ops", 3).unwrap().unwrap();
+        assert_eq!(assignment.tier, "retail");
+        assert_eq!(account_tier(&conn, "alice").unwrap().unwrap().name, "retail");
+        assert_eq!(load_assignments(&conn).unwrap(), vec![assignment]);
+
+        // Assigned tiers can't be deleted and unknown tiers can't be assigned
+        assert!(delete_tier(&conn, "retail", "ops").is_err());
+        assert!(assign_tier(&conn, "bob", Some("gold"), "ops", 3).is_err());
+        assert_eq!(assign_tier(&conn, "alice", None, "ops", 4).unwrap(), None);
+        delete_tier(&conn, "retail", "ops").unwrap();
+        assert_eq!(load_tiers(&conn).unwrap().len(), 1);
+
+        // Limits must be positive and products known
+        assert!(set_tier(&conn, "bad", terms(Some(0.0), None, None), "ops", 5).is_err());
+        assert!(set_tier(&conn, "bad", terms(None, None, Some(&["BTC-X"])), "ops", 5).is_err());
+        let actions: Vec<String> = audit::query(&conn, &audit::AuditFilter::default())
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.action)
+            .collect();
+        assert_eq!(actions.iter().filter(|a| *a == audit::CREDIT_TIER_ASSIGN).count(), 2);
+        assert!(actions.contains(&audit::CREDIT_TIER_DELETE.to_string()));
+    }
+}
@@ -0,0 +1,189 @@
+// Day-count conventions.
+// Black-Scholes times, theta and the VaR horizon are year fractions under the deployment's
+// DAY_COUNT convention. ACT/365 (the default) counts every second of the calendar, as crypto
+// trades 24/7 and Deribit quotes its IVs on that basis. ACT/252 counts only trading days,
+// Monday to Friday outside DAY_COUNT_HOLIDAYS, in a 252 day year, as TradFi desks do; options
+// then lose no time value over weekends and holidays and carry more of it per trading day.
+// The convention is set once at startup with `init` and read through `deployment`.
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Weekday};
+use serde::Serialize;
+use std::env;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+static DEPLOYMENT: OnceLock<DayCount> = OnceLock::new();
+
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DayCountConvention {
+    #[default]
+    #[serde(rename = "ACT/365")]
+    Act365,
+    #[serde(rename = "ACT/252")]
+    Act252,
+}
+
+impl DayCountConvention {
+    /// Days in a year under the convention
+    pub fn days_per_year(&self) -> f64 {
+        match self {
+            DayCountConvention::Act365 => 365.0,
+            DayCountConvention::Act252 => 252.0,
+        }
+    }
+}
+
+impl fmt::Display for DayCountConvention {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DayCountConvention::Act365 => write!(f, "ACT/365"),
+            DayCountConvention::Act252 => write!(f, "ACT/252"),
+        }
+    }
+}
+
+impl FromStr for DayCountConvention {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_uppercase().replace(['_', ' '], "/").as_str() {
+            "ACT/365" | "ACT/365F" | "ACT365" => Ok(DayCountConvention::Act365),
+            "ACT/252" | "ACT252" | "BUS/252" => Ok(DayCountConvention::Act252),
+            other => Err(format!("unknown day count '{}', expected ACT/365 or ACT/252", other)),
+        }
+    }
+}
+
+/// A day-count convention with the holidays ACT/252 skips
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DayCount {
+    pub convention: DayCountConvention,
+    pub holidays: Vec<NaiveDate>,  // Sorted; only ACT/252 skips them
+}
+
+impl DayCount {
+    /// DAY_COUNT (ACT/365) and DAY_COUNT_HOLIDAYS, comma separated YYYY-MM-DD dates
+    pub fn from_env() -> Result<Self, String> {
+        let convention = match env::var("DAY_COUNT") {
+            Ok(value) if !value.trim().is_empty() => value.parse()?,
+            _ => DayCountConvention::default(),
+        };
+        let mut holidays = env::var("DAY_COUNT_HOLIDAYS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|date| !date.is_empty())
+            .map(|date| {
+                NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                    .map_err(|_| format!("invalid holiday '{}' in DAY_COUNT_HOLIDAYS, expected YYYY-MM-DD", date))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        holidays.sort();
+        holidays.dedup();
+        Ok(Self { convention, holidays })
+    }
+
+    pub fn days_per_year(&self) -> f64 {
+        self.convention.days_per_year()
+    }
+
+    /// Whether time on `date` counts towards the year fraction
+    pub fn is_business_day(&self, date: NaiveDate) -> bool {
+        match self.convention {
+            DayCountConvention::Act365 => true,
+            DayCountConvention::Act252 => {
+                !matches!(date.weekday(), Weekday::Sat | Weekday::Sun) && self.holidays.binary_search(&date).is_err()
+            }
+        }
+    }
+
+    /// Days from `now` to `expires` that count under the convention, to the second.
+    /// Negative once expired.
+    pub fn days_between(&self, expires: i64, now: i64) -> f64 {
+        if expires < now {
+            return -self.days_between(now, expires);
+        }
+        if self.convention == DayCountConvention::Act365 {
+            return (expires - now) as f64 / SECONDS_PER_DAY as f64;
+        }
+        let (Some(start), Some(end)) = (DateTime::from_timestamp(now, 0), DateTime::from_timestamp(expires, 0)) else {
+            return 0.0;
+        };
+        let mut seconds = 0;
+        let mut date = start.date_naive();
+        while date <= end.date_naive() {
+            if self.is_business_day(date) {
+                let day_start = date.and_hms_opt(0, 0, 0).map_or(now, |d| d.and_utc().timestamp());
+                let from = day_start.max(now);
+                let to = (day_start + SECONDS_PER_DAY).min(expires);
+                seconds += (to - from).max(0);
+            }
+            date += Duration::days(1);
+        }
+        seconds as f64 / SECONDS_PER_DAY as f64
+    }
+
+    /// Time from `now` to `expires` as a year fraction under the convention
+    pub fn year_fraction(&self, expires: i64, now: i64) -> f64 {
+        self.days_between(expires, now) / self.days_per_year()
+    }
+}
+
+/// Set the deployment's convention. Fails if it was already set or read.
+pub fn init(day_count: DayCount) -> Result<(), String> {
+    DEPLOYMENT
+        .set(day_count)
+        .map_err(|_| "the day count convention is already set".to_string())
+}
+
+/// The deployment's convention; ACT/365 unless `init` set another
+pub fn deployment() -> &'static DayCount {
+    DEPLOYMENT.get_or_init(DayCount::default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_act_365_counts_every_second() {
+        let day_count = DayCount::default();
+        let now = 1_735_714_800;  // 2025-01-01 07:00 UTC, a Wednesday
+        assert_eq!(day_count.year_fraction(now + 7 * SECONDS_PER_DAY, now), 7.0 / 365.0);
+        assert_eq!(day_count.year_fraction(now + 3600, now), 1.0 / (365.0 * 24.0));
+        assert_eq!(day_count.year_fraction(now - 3600, now), -1.0 / (365.0 * 24.0));
+    }
+
+    #[test]
+    fn test_act_252_skips_weekends_and_holidays() {
+        let friday = NaiveDate::from_ymd_opt(2025, 1, 3).unwrap();
+        let friday_noon = friday.and_hms_opt(12, 0, 0).unwrap().and_utc().timestamp();
+        let day_count = DayCount { convention: DayCountConvention::Act252, holidays: Vec::new() };
+
+        // Friday noon to Monday noon is half of Friday and half of Monday
+        let monday_noon = friday_noon + 3 * SECONDS_PER_DAY;
+        assert_eq!(day_count.days_between(monday_noon, friday_noon), 1.0);
+        assert_eq!(day_count.year_fraction(monday_noon, friday_noon), 1.0 / 252.0);
+        // Time within a weekend does not count
+        let saturday = friday_noon + SECONDS_PER_DAY;
+        assert_eq!(day_count.days_between(saturday + 3600, saturday), 0.0);
+        // Two calendar weeks are ten trading days
+        assert_eq!(day_count.days_between(friday_noon + 14 * SECONDS_PER_DAY, friday_noon), 10.0);
+
+        let holidays = DayCount { holidays: vec![friday + Duration::days(3)], ..day_count };
+        assert_eq!(holidays.days_between(monday_noon, friday_noon), 0.5);
+        assert!(holidays.is_business_day(friday));
+        assert!(!holidays.is_business_day(friday + Duration::days(1)));
+    }
+
+    #[test]
+    fn test_parse_convention() {
+        assert_eq!("ACT/252".parse(), Ok(DayCountConvention::Act252));
+        assert_eq!("act_365".parse(), Ok(DayCountConvention::Act365));
+        assert!("30/360".parse::<DayCountConvention>().is_err());
+        assert_eq!(DayCountConvention::Act252.to_string(), "ACT/252");
+    }
+}
@@ -1,13 +1,67 @@
-use r2d2::Pool;
+use crate::migrations;
+use r2d2::{CustomizeConnection, Pool};
 use r2d2_sqlite::SqliteConnectionManager;
 use rusqlite::{Connection, Result};
+use std::env;
 use std::sync::Arc;
+use std::time::Duration;
 
 pub type DbPool = Arc<Pool<SqliteConnectionManager>>;
 
+const DB_PATH: &str = "contracts.db";
+
+// Applies per-connection pragmas whenever the pool opens a new connection.
+// WAL lets readers proceed while a writer holds the lock, and busy_timeout makes
+// concurrent writers wait for the lock instead of failing with "database is locked".
+// The timeout goes first: switching to WAL takes the lock too.
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectionOptions {
+    pub busy_timeout: Duration,
+}
+
+impl CustomizeConnection<Connection, rusqlite::Error> for ConnectionOptions {
+    fn on_acquire(&self, conn: &mut Connection) -> Result<()> {
+        conn.busy_timeout(self.busy_timeout)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        conn.pragma_update(None, "foreign_keys", "ON")?;
+        Ok(())
+    }
+}
+
+// Connections of the read pool. They can only query, and wait out a checkpoint like writers do.
+#[derive(Debug, Clone, Copy)]
+pub struct ReadConnectionOptions {
+    pub busy_timeout: Duration,
+}
+
+impl CustomizeConnection<Connection, rusqlite::Error> for ReadConnectionOptions {
+    fn on_acquire(&self, conn: &mut Connection) -> Result<()> {
+        conn.busy_timeout(self.busy_timeout)?;
+        conn.pragma_update(None, "query_only", "ON")?;
+        Ok(())
+    }
+}
+
+fn busy_timeout_from_env() -> Duration {
+    let busy_timeout_ms: u64 = env::var("DB_BUSY_TIMEOUT_MS")
+        .unwrap_or_else(|_| "5000".to_string())
+        .parse()
+        .unwrap_or(5000);
+    Duration::from_millis(busy_timeout_ms)
+}
+
 pub fn create_pool() -> Result<DbPool, Box<dyn std::error::Error>> {
-    let manager = SqliteConnectionManager::file("contracts.db");
-    let pool = Pool::new(manager)?;
+    let max_size: u32 = env::var("DB_POOL_MAX_SIZE")
+        .unwrap_or_else(|_| "10".to_string())
+        .parse()
+        .unwrap_or(10);
+
+    let manager = SqliteConnectionManager::file(DB_PATH);
+    let pool = Pool::builder()
+        .max_size(max_size.max(1))
+        .connection_customizer(Box::new(ConnectionOptions { busy_timeout: busy_timeout_from_env() }))
+        .build(manager)?;
     
     // Initialize database schema using a connection from the pool
     let conn = pool.get()?;
@@ -16,46 +70,197 @@ pub fn create_pool() -> Result<DbPool, Box<dyn std::error::Error>> {
     Ok(Arc::new(pool))
 }
 
-// Initialize the SQLite database and creates the tables if they don't exist.
+/// Pool of DB_READ_POOL_MAX_SIZE (4) query-only connections that analytics scans run on, so
+/// dashboard traffic cannot take the connections trades wait for. Under WAL they read the last
+/// committed state without blocking writers. None when DB_READ_POOL_MAX_SIZE is 0, which keeps
+/// reads on the main pool. Create it after `create_pool`, which sets up the database.
+pub fn create_read_pool() -> Result<Option<DbPool>, Box<dyn std::error::Error>> {
+    let max_size: u32 = env::var("DB_READ_POOL_MAX_SIZE")
+        .unwrap_or_else(|_| "4".to_string())
+        .parse()
+        .unwrap_or(4);
+    if max_size == 0 {
+        return Ok(None);
+    }
+
+    let manager = SqliteConnectionManager::file(DB_PATH);
+    let pool = Pool::builder()
+        .max_size(max_size)
+        .connection_customizer(Box::new(ReadConnectionOptions { busy_timeout: busy_timeout_from_env() }))
+        .build(manager)?;
+    Ok(Some(Arc::new(pool)))
+}
+
+/// Single-connection in-memory database with the schema applied, for tests and tooling.
+/// One connection so every query sees the same database.
+pub fn create_in_memory_pool() -> Result<DbPool, Box<dyn std::error::Error>> {
+    let pool = Pool::builder()
+        .max_size(1)
+        .build(SqliteConnectionManager::memory())?;
+    let conn = pool.get()?;
+    init_db(&conn)?;
+    drop(conn);
+    Ok(Arc::new(pool))
+}
+
+// Initialize the SQLite database by applying any pending schema migrations.
 pub fn init_db(conn: &Connection) -> Result<()> {
-    // Create contracts table with string storage for floats
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS contracts (
-            id INTEGER PRIMARY KEY,
-            side TEXT NOT NULL,
-            strike_price_cents INTEGER NOT NULL,
-            quantity_str TEXT NOT NULL,
-            expires INTEGER NOT NULL,
-            premium_str TEXT NOT NULL,
-            created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
-        )",
-        [],
-    )?;
-    
-    // Create premium history table for tracking price movements
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS premium_history (
-            id INTEGER PRIMARY KEY,
-            product_key TEXT NOT NULL,
-            side TEXT NOT NULL,
-            strike_price_cents INTEGER NOT NULL,
-            expires INTEGER NOT NULL,
-            premium_str TEXT NOT NULL,
-            timestamp INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
-            UNIQUE(product_key, timestamp)
-        )",
-        [],
-    )?;
-    
-    // Create index for efficient queries
-    conn.execute(
-        "CREATE INDEX IF NOT EXISTS idx_contracts_created_at ON contracts(created_at)",
-        [],
-    )?;
-    conn.execute(
-        "CREATE INDEX IF NOT EXISTS idx_premium_history_product ON premium_history(product_key, timestamp)",
-        [],
-    )?;
-    
+    let applied = migrations::run_migrations(conn)?;
+    if !applied.is_empty() {
+        println!("🗄️  Applied database migrations: {:?}", applied);
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Steps of SQLite's plan for `sql`, e.g. "SCAN contracts USING COVERING INDEX ...", with
+/// every parameter bound to NULL
+pub fn query_plan(conn: &Connection, sql: &str) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare(&format!("EXPLAIN QUERY PLAN {}", sql))?;
+    let nulls = (0..stmt.parameter_count()).map(|_| rusqlite::types::Null);
+    let steps = stmt.query_map(rusqlite::params_from_iter(nulls), |row| row.get(3))?;
+    steps.collect()
+}
+
+// Indexes of the contracts table the expiry-filtered queries below rely on
+pub const EXPECTED_INDEXES: &[&str] = &[
+    "idx_contracts_expires",
+    "idx_contracts_side_strike_expires",
+    "idx_contracts_status_expires",
+    "idx_contracts_underlying_expires",
+    "idx_contracts_counterparty",
+    "idx_contracts_pool_status",
+    "idx_contracts_product",
+];
+
+// Shapes of the hot expiry-filtered queries. Each must search an index rather than scan contracts.
+const EXPIRY_QUERIES: &[(&str, &str)] = &[
+    (
+        "active contracts",
+        "SELECT id FROM contracts WHERE expires > ?1 AND status IN ('open', 'pending') AND (?2 IS NULL OR pool_id = ?2)",
+    ),
+    ("open interest", "SELECT TOTAL(quantity_sats) FROM contracts WHERE expires > ?1 AND status = 'open'"),
+    ("unexpired contracts", "SELECT id FROM contracts WHERE expires > ?1"),
+    ("expiring contracts", "SELECT id FROM contracts WHERE expires > ?1 AND expires <= ?2 AND status IN (?3, ?4)"),
+    ("due for settlement", "SELECT id FROM contracts WHERE status = ?1 AND expires <= ?2"),
+    (
+        "product trades",
+        "SELECT id FROM contracts WHERE side = ?1 AND strike_price_cents = ?2 AND expires = ?3",
+    ),
+    (
+        "counterparty exposure",
+        "SELECT id FROM contracts WHERE counterparty = ?1 AND expires > ?2 AND status IN ('open', 'pending')",
+    ),
+];
+
+/// Problems with the contracts indexes: expected indexes that are missing, and expiry-filtered
+/// queries that SQLite would answer by scanning the table. Empty when all is well.
+pub fn check_indexes(conn: &Connection) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT name FROM sqlite_master WHERE type = 'index' AND tbl_name = 'contracts'")?;
+    let existing: Vec<String> = stmt.query_map([], |row| row.get(0))?.collect::<Result<_>>()?;
+
+    let mut problems: Vec<String> = EXPECTED_INDEXES
+        .iter()
+        .filter(|name| !existing.iter().any(|e| e == *name))
+        .map(|name| format!("missing index {}", name))
+        .collect();
+    for (query, sql) in EXPIRY_QUERIES {
+        let plan = query_plan(conn, sql)?;
+        // Scanning a covering index still reads every contract
+        if plan.iter().any(|step| step.starts_with("SCAN contracts")) {
+            problems.push(format!("{} query scans contracts: {}", query, plan.join("; ")));
+        }
+    }
+    Ok(problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connection_options_apply_pragmas() {
+        let dir = std::env::temp_dir().join(format!("btc_options_pragmas_{}.db", std::process::id()));
+        let manager = SqliteConnectionManager::file(&dir);
+        let pool = Pool::builder()
+            .max_size(1)
+            .connection_customizer(Box::new(ConnectionOptions {
+                busy_timeout: Duration::from_millis(1234),
+            }))
+            .build(manager)
+            .unwrap();
+        let conn = pool.get().unwrap();
+
+        let journal_mode: String = conn.pragma_query_value(None, "journal_mode", |row| row.get(0)).unwrap();
+        let busy_timeout: i64 = conn.pragma_query_value(None, "busy_timeout", |row| row.get(0)).unwrap();
+        let synchronous: i64 = conn.pragma_query_value(None, "synchronous", |row| row.get(0)).unwrap();
+        let foreign_keys: i64 = conn.pragma_query_value(None, "foreign_keys", |row| row.get(0)).unwrap();
+
+        assert_eq!(journal_mode.to_lowercase(), "wal");
+        assert_eq!(busy_timeout, 1234);
+        assert_eq!(synchronous, 1); // NORMAL
+        assert_eq!(foreign_keys, 1);
+
+        // Read connections see committed writes but cannot write themselves
+        conn.execute("CREATE TABLE t (x INTEGER)", []).unwrap();
+        conn.execute("INSERT INTO t VALUES (1)", []).unwrap();
+        let read_pool = Pool::builder()
+            .max_size(1)
+            .connection_customizer(Box::new(ReadConnectionOptions { busy_timeout: Duration::from_millis(1234) }))
+            .build(SqliteConnectionManager::file(&dir))
+            .unwrap();
+        let reader = read_pool.get().unwrap();
+        let count: i64 = reader.query_row("SELECT COUNT(*) FROM t", [], |row| row.get(0)).unwrap();
+        assert_eq!(count, 1);
+        assert!(reader.execute("INSERT INTO t VALUES (2)", []).is_err());
+
+        drop(reader);
+        drop(read_pool);
+        drop(conn);
+        drop(pool);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", dir.display(), suffix));
+        }
+    }
+
+    #[test]
+    fn test_switch_to_wal_waits_for_the_lock() {
+        let dir = std::env::temp_dir().join(format!("btc_options_wal_lock_{}.db", std::process::id()));
+        let writer = Connection::open(&dir).unwrap();
+        writer.execute_batch("CREATE TABLE t (x INTEGER); BEGIN EXCLUSIVE; INSERT INTO t VALUES (1);").unwrap();
+        let release = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            writer.execute_batch("COMMIT").unwrap();
+        });
+
+        // A new connection set up while another holds the lock waits for it
+        let mut conn = Connection::open(&dir).unwrap();
+        conn.busy_timeout(Duration::ZERO).unwrap();
+        ConnectionOptions { busy_timeout: Duration::from_secs(5) }.on_acquire(&mut conn).unwrap();
+        let journal_mode: String = conn.pragma_query_value(None, "journal_mode", |row| row.get(0)).unwrap();
+        assert_eq!(journal_mode.to_lowercase(), "wal");
+
+        release.join().unwrap();
+        drop(conn);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", dir.display(), suffix));
+        }
+    }
+
+    #[test]
+    fn test_expiry_queries_use_indexes() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn).unwrap();
+        assert_eq!(check_indexes(&conn).unwrap(), Vec::<String>::new());
+
+        let plan = query_plan(&conn, "SELECT id FROM contracts WHERE expires > ?1").unwrap();
+        assert_eq!(plan, vec!["SEARCH contracts USING COVERING INDEX idx_contracts_expires (expires>?)"]);
+        let plan = query_plan(&conn, EXPIRY_QUERIES[5].1).unwrap();
+        assert!(plan[0].contains("idx_contracts_side_strike_expires"), "{:?}", plan);
+
+        // A dropped index is reported, and so are the queries left scanning the table
+        conn.execute_batch("DROP INDEX idx_contracts_expires").unwrap();
+        let problems = check_indexes(&conn).unwrap();
+        assert_eq!(problems[0], "missing index idx_contracts_expires");
+        assert!(problems[1].starts_with("unexpired contracts query scans contracts"), "{:?}", problems);
+    }
+}
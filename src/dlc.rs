@@ -0,0 +1,362 @@
+// Discreet log contracts.
+// Instead of the pool's collateral being assumed to stand behind every written option, each
+// contract can be collateralized on-chain as a DLC between the pool (offer) and the buyer
+// (accept). This module builds its descriptor: a numeric-outcome payout curve over the
+// settlement price of the underlying, and the oracle event our price oracle attests at expiry,
+// announced with the nonces each digit of the price will be signed with and signed by the
+// oracle key.
+// Payoffs are paid in BTC at the settlement price, so only BTC contracts can be described.
+// Calls are fully collateralized by their quantity; puts by their max loss at the BTC price
+// they were written at, the payout being capped there. Funding and signing are left to the
+// parties' DLC wallets.
+
+use crate::attestation::{self, OracleSigner};
+use crate::error::{ApiError, ApiResult};
+use crate::models::{Asset, ContractRecord, OptionSide};
+use crate::utils::{btc_to_sats, sats_to_btc};
+use serde::Serialize;
+use std::env;
+
+// Points sampled along the curved part of the payout; linear interpolation between them
+// stays within 0.01% of the quantity
+const PAYOUT_SAMPLES: usize = 128;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DlcConfig {
+    pub price_digits: u32,  // Binary digits of the attested price
+}
+
+impl Default for DlcConfig {
+    fn default() -> Self {
+        Self { price_digits: 20 }
+    }
+}
+
+impl DlcConfig {
+    /// DLC_PRICE_DIGITS (20, i.e. prices up to $1,048,575)
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            price_digits: env::var("DLC_PRICE_DIGITS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|digits| (10..=32).contains(digits))
+                .unwrap_or(defaults.price_digits),
+        }
+    }
+
+    /// Largest price the oracle can attest
+    pub fn max_outcome(&self) -> u64 {
+        (1u64 << self.price_digits) - 1
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct DlcDescriptor {
+    pub contract_id: i64,
+    pub total_collateral_sats: i64,
+    pub offer_collateral_sats: i64,   // Pool, as the option writer
+    pub accept_collateral_sats: i64,  // Buyer; the premium is paid separately
+    pub contract_descriptor: NumericOutcomeDescriptor,
+    pub oracle_announcement: OracleAnnouncement,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct NumericOutcomeDescriptor {
+    pub num_digits: u32,
+    pub payout_points: Vec<PayoutPoint>,  // Linear between points; the pool gets the rest of the collateral
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct PayoutPoint {
+    pub outcome: u64,             // Settlement price in USD
+    pub accept_payout_sats: i64,  // Paid to the buyer
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct OracleAnnouncement {
+    pub announcement_signature: String,  // Of announcement_message(oracle_event), hex
+    pub oracle_public_key: String,       // Our attestation key, x-only, hex
+    pub oracle_event: OracleEvent,
+}
+
+impl OracleAnnouncement {
+    /// Whether the announcement is signed by its oracle key
+    pub fn verify(&self) -> bool {
+        attestation::verify(
+            &self.oracle_public_key,
+            &announcement_message(&self.oracle_event),
+            &self.announcement_signature,
+        )
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct OracleEvent {
+    pub oracle_nonces: Vec<String>,  // R-point of each digit's signature, most significant first
+    pub event_id: String,
+    pub event_maturity_epoch: i64,
+    pub event_descriptor: DigitDecompositionEvent,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct DigitDecompositionEvent {
+    pub base: u32,
+    pub is_signed: bool,
+    pub unit: String,
+    pub precision: i32,
+    pub nb_digits: u32,
+}
+
+/// Binary digits of `price` in whole USD, most significant first, as the oracle attests them
+/// for events of `nb_digits` digits. Prices above the largest attestable one are attested as
+/// the largest.
+pub fn outcome_digits(price: f64, nb_digits: u32) -> Vec<u8> {
+    let max_outcome = (1u64 << nb_digits) - 1;
+    let outcome = (price.round().max(0.0) as u64).min(max_outcome);
+    (0..nb_digits).rev().map(|bit| ((outcome >> bit) & 1) as u8).collect()
+}
+
+/// Text the oracle signs to announce `event`: every field, the nonces in order
+pub fn announcement_message(event: &OracleEvent) -> String {
+    let descriptor = &event.event_descriptor;
+    format!(
+        "{}:{}:{}:{}:{}:{}:{}:{}",
+        event.event_id,
+        event.event_maturity_epoch,
+        descriptor.base,
+        descriptor.is_signed,
+        descriptor.unit,
+        descriptor.precision,
+        descriptor.nb_digits,
+        event.oracle_nonces.join(",")
+    )
+}
+
+/// Event under which the oracle attests the price of `underlying` at `maturity`
+pub fn event_id(underlying: Asset, maturity: i64) -> String {
+    format!("{}usd-{}", underlying.to_string().to_lowercase(), maturity)
+}
+
+/// Collateral locked by the pool for the open quantity of `contract`
+pub fn collateral_sats(contract: &ContractRecord) -> i64 {
+    let quantity = contract.open_quantity();
+    match contract.side {
+        // An inverse call never pays more than its quantity
+        OptionSide::Call => btc_to_sats(quantity),
+        OptionSide::Put => {
+            let btc_price = contract.trade_btc_price.unwrap_or(contract.strike_price);
+            btc_to_sats(quantity * contract.strike_price / btc_price)
+        }
+    }
+}
+
+/// Buyer's payout at settlement price `outcome`, capped at the collateral
+pub fn accept_payout_sats(contract: &ContractRecord, outcome: u64, collateral_sats: i64) -> i64 {
+    if outcome == 0 {
+        return match contract.side {
+            OptionSide::Call => 0,
+            OptionSide::Put => collateral_sats,
+        };
+    }
+    let price = outcome as f64;
+    btc_to_sats(contract.payoff_usd(price) / price).min(collateral_sats)
+}
+
+/// Payout curve of `contract` over every price the oracle can attest. The payout is flat
+/// outside [low, high] and curved in between, where it is sampled at geometrically spaced prices.
+pub fn payout_points(contract: &ContractRecord, collateral_sats: i64, max_outcome: u64) -> Vec<PayoutPoint> {
+    let strike = (contract.strike_price.round() as u64).clamp(1, max_outcome);
+    let (low, high) = match contract.side {
+        OptionSide::Call => (strike, max_outcome),
+        OptionSide::Put => {
+            // Below this price the payout is capped at the collateral
+            let quantity = contract.open_quantity();
+            let collateral = sats_to_btc(collateral_sats);
+            let floor = (quantity * contract.strike_price / (quantity + collateral)).ceil() as u64;
+            (floor.clamp(1, strike), strike)
+        }
+    };
+    let mut outcomes = vec![0, low, high, max_outcome];
+    let ratio = high as f64 / low as f64;
+    for i in 1..PAYOUT_SAMPLES {
+        outcomes.push((low as f64 * ratio.powf(i as f64 / PAYOUT_SAMPLES as f64)).round() as u64);
+    }
+    outcomes.sort_unstable();
+    outcomes.dedup();
+    outcomes
+        .into_iter()
+        .map(|outcome| PayoutPoint {
+            outcome,
+            accept_payout_sats: accept_payout_sats(contract, outcome, collateral_sats),
+        })
+        .collect()
+}
+
+/// DLC descriptor of the open quantity of `contract`, settled on `signer`'s attestation
+pub fn build_descriptor(contract: &ContractRecord, config: &DlcConfig, signer: &OracleSigner) -> ApiResult<DlcDescriptor> {
+    if contract.underlying != Asset::Btc {
+        return Err(ApiError::ValidationError(format!(
+            "Contract {} is on {}; DLCs pay in BTC and need a BTC underlying",
+            contract.id, contract.underlying
+        )));
+    }
+    if btc_to_sats(contract.open_quantity()) <= 0 {
+        return Err(ApiError::ValidationError(format!("Contract {} has no open quantity", contract.id)));
+    }
+    if contract.strike_price >= config.max_outcome() as f64 {
+        return Err(ApiError::ValidationError(format!(
+            "Strike {} is above the largest attestable price {}",
+            contract.strike_price,
+            config.max_outcome()
+        )));
+    }
+
+    let event_id = event_id(contract.underlying, contract.expires);
+    let oracle_event = OracleEvent {
+        oracle_nonces: signer.digit_nonce_points(&event_id, config.price_digits),
+        event_id,
+        event_maturity_epoch: contract.expires,
+        event_descriptor: DigitDecompositionEvent {
+            base: 2,
+            is_signed: false,
+            unit: "USD".to_string(),
+            precision: 0,
+            nb_digits: config.price_digits,
+        },
+    };
+    let total_collateral_sats = collateral_sats(contract);
+    Ok(DlcDescriptor {
+        contract_id: contract.id,
+        total_collateral_sats,
+        offer_collateral_sats: total_collateral_sats,
+        accept_collateral_sats: 0,
+        contract_descriptor: NumericOutcomeDescriptor {
+            num_digits: config.price_digits,
+            payout_points: payout_points(contract, total_collateral_sats, config.max_outcome()),
+        },
+        oracle_announcement: OracleAnnouncement {
+            announcement_signature: signer.sign(&announcement_message(&oracle_event)),
+            oracle_public_key: signer.public_key_hex(),
+            oracle_event,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{ContractStatus, ExerciseStyle, QuoteCurrency};
+
+    fn record(side: OptionSide, strike_price: f64, quantity: f64) -> ContractRecord {
+        ContractRecord {
+            id: 1,
+            underlying: Asset::Btc,
+            side,
+            strike_price,
+            quantity,
+            closed_quantity: 0.0,
+            expires: 1_800_000_000,
+            premium: 0.01,
+            premium_currency: QuoteCurrency::Btc,
+            quoted_premium: None,
+            trade_btc_price: Some(100_000.0),
+            created_at: 1_799_000_000,
+            status: ContractStatus::Open,
+            settlement_price: None,
+            settlement_btc_price: None,
+            settled_at: None,
+            exercise_style: ExerciseStyle::European,
+            counterparty: None,
+            spot_at_trade: None,
+            iv_at_trade: None,
+            iv_source: None,
+            mark_premium_at_trade: None,
+            pool_id: 1,
+        }
+    }
+
+    fn signer() -> OracleSigner {
+        OracleSigner::from_secret_hex("0000000000000000000000000000000000000000000000000000000000000003").unwrap()
+    }
+
+    // Payout interpolated from the points at `outcome`
+    fn interpolate(points: &[PayoutPoint], outcome: u64) -> f64 {
+        let i = points.iter().position(|p| p.outcome >= outcome).unwrap();
+        if points[i].outcome == outcome {
+            return points[i].accept_payout_sats as f64;
+        }
+        let (a, b) = (points[i - 1], points[i]);
+        let weight = (outcome - a.outcome) as f64 / (b.outcome - a.outcome) as f64;
+        a.accept_payout_sats as f64 + weight * (b.accept_payout_sats - a.accept_payout_sats) as f64
+    }
+
+    #[test]
+    fn test_call_payout_curve() {
+        let call = record(OptionSide::Call, 100_000.0, 0.5);
+        let descriptor = build_descriptor(&call, &DlcConfig::default(), &signer()).unwrap();
+        assert_eq!(descriptor.total_collateral_sats, 50_000_000);
+        assert_eq!(descriptor.oracle_announcement.oracle_event.event_id, "btcusd-1800000000");
+
+        let points = &descriptor.contract_descriptor.payout_points;
+        assert_eq!(points.first().unwrap().outcome, 0);
+        assert_eq!(points.last().unwrap().outcome, 1_048_575);
+        assert_eq!(accept_payout_sats(&call, 100_000, 50_000_000), 0);
+        // 0.5 * (125000 - 100000) / 125000 BTC
+        assert_eq!(accept_payout_sats(&call, 125_000, 50_000_000), 10_000_000);
+        let error = (interpolate(points, 125_000) - 10_000_000.0).abs();
+        assert!(error < 5_000.0, "interpolation error {} sats", error);
+    }
+
+    #[test]
+    fn test_announcement_commits_to_the_attested_nonces() {
+        let call = record(OptionSide::Call, 100_000.0, 0.5);
+        let announcement = build_descriptor(&call, &DlcConfig::default(), &signer()).unwrap().oracle_announcement;
+        assert!(announcement.verify());
+        let nonces = &announcement.oracle_event.oracle_nonces;
+        assert_eq!(nonces.len(), 20);
+
+        let signatures = signer().sign_digits("btcusd-1800000000", &outcome_digits(101_234.0, 20));
+        for (signature, nonce) in signatures.iter().zip(nonces) {
+            assert_eq!(&signature[..64], nonce);
+        }
+
+        let mut forged = announcement.clone();
+        forged.oracle_event.oracle_nonces.swap(0, 1);
+        assert!(!forged.verify());
+        let mut forged = announcement;
+        forged.oracle_event.event_maturity_epoch += 1;
+        assert!(!forged.verify());
+    }
+
+    #[test]
+    fn test_outcome_digits_are_most_significant_first() {
+        assert_eq!(outcome_digits(5.4, 4), vec![0, 1, 0, 1]);
+        assert_eq!(outcome_digits(101_234.567, 20).len(), 20);
+        assert_eq!(outcome_digits(101_234.567, 20).iter().fold(0, |n, d| n * 2 + *d as u64), 101_235);
+        // Capped at the largest outcome
+        assert_eq!(outcome_digits(2_000_000.0, 20), vec![1; 20]);
+        assert_eq!(outcome_digits(-1.0, 3), vec![0; 3]);
+    }
+
+    #[test]
+    fn test_put_payout_is_capped_at_collateral() {
+        let put = record(OptionSide::Put, 90_000.0, 1.0);
+        let collateral = collateral_sats(&put);
+        // Max loss of $90,000 at the $100,000 trade price
+        assert_eq!(collateral, 90_000_000);
+        assert_eq!(accept_payout_sats(&put, 0, collateral), collateral);
+        assert_eq!(accept_payout_sats(&put, 30_000, collateral), collateral);
+        // 1 * (90000 - 60000) / 60000 BTC
+        assert_eq!(accept_payout_sats(&put, 60_000, collateral), 50_000_000);
+        assert_eq!(accept_payout_sats(&put, 95_000, collateral), 0);
+
+        let points = payout_points(&put, collateral, DlcConfig::default().max_outcome());
+        assert!(points.windows(2).all(|w| w[0].accept_payout_sats >= w[1].accept_payout_sats));
+        assert!((interpolate(&points, 60_000) - 50_000_000.0).abs() < 10_000.0);
+
+        let mut eth = put.clone();
+        eth.underlying = Asset::Eth;
+        assert!(build_descriptor(&eth, &DlcConfig::default(), &signer()).is_err());
+    }
+}
@@ -1,5 +1,43 @@
+use actix_web::http::StatusCode;
 use actix_web::{HttpResponse, ResponseError};
+use serde::Serialize;
+use serde_json::{Map, Value};
 use std::fmt;
+use crate::request_id;
+
+/// Machine-readable reason of an error response, for clients to branch on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    // One per ApiError kind
+    DatabaseError,
+    UpstreamUnavailable,
+    ValidationError,
+    PriceUnavailable,
+    NotFound,
+    Unauthorized,
+    Forbidden,
+    StalePrice,
+    TradingHalted,
+    PositionLimitExceeded,
+    UpstreamTimeout,
+    // Validation failures clients commonly handle on their own
+    InvalidExpiry,
+    InvalidStrike,
+    InvalidQuantity,
+    SubSatoshiQuantity,
+    QuantityBelowMinimum,
+    QuantityAboveMaximum,
+    InsufficientCollateral,
+    AssetNotEnabled,
+    ContractNotOpen,
+    PaymentMethodUnavailable,
+    ProductNotAllowed,
+    KycRequired,
+    IvUnavailable,
+    ReservationExpired,
+    OracleDisabled,
+}
 
 #[derive(Debug)]
 pub enum ApiError {
@@ -8,6 +46,83 @@ pub enum ApiError {
     ValidationError(String),
     PriceOracleError(String),
     NotFound(String),
+    Unauthorized(String),
+    Forbidden(String),  // Authenticated, but without the role the endpoint requires
+    StalePrice(String),
+    TradingHalted(String),
+    PositionLimitExceeded(String),
+    UpstreamTimeout(String),
+    // `error` with a more specific code and structured details, see `with_code`/`with_details`
+    Detailed {
+        error: Box<ApiError>,
+        code: ErrorCode,
+        details: Map<String, Value>,
+    },
+}
+
+impl ApiError {
+    /// Report this error under a more specific code than its kind's
+    pub fn with_code(self, code: ErrorCode) -> Self {
+        match self {
+            ApiError::Detailed { error, details, .. } => ApiError::Detailed { error, code, details },
+            error => ApiError::Detailed { error: Box::new(error), code, details: Map::new() },
+        }
+    }
+
+    /// Attach machine-readable details, e.g. `json!({"max_quantity": 0.5})`. Non-object values are ignored.
+    pub fn with_details(self, details: Value) -> Self {
+        let Value::Object(extra) = details else {
+            return self;
+        };
+        match self {
+            ApiError::Detailed { error, code, mut details } => {
+                details.extend(extra);
+                ApiError::Detailed { error, code, details }
+            }
+            error => ApiError::Detailed { code: error.code(), error: Box::new(error), details: extra },
+        }
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ApiError::DatabaseError(_) => ErrorCode::DatabaseError,
+            ApiError::ExternalApiError(_) => ErrorCode::UpstreamUnavailable,
+            ApiError::ValidationError(_) => ErrorCode::ValidationError,
+            ApiError::PriceOracleError(_) => ErrorCode::PriceUnavailable,
+            ApiError::NotFound(_) => ErrorCode::NotFound,
+            ApiError::Unauthorized(_) => ErrorCode::Unauthorized,
+            ApiError::Forbidden(_) => ErrorCode::Forbidden,
+            ApiError::StalePrice(_) => ErrorCode::StalePrice,
+            ApiError::TradingHalted(_) => ErrorCode::TradingHalted,
+            ApiError::PositionLimitExceeded(_) => ErrorCode::PositionLimitExceeded,
+            ApiError::UpstreamTimeout(_) => ErrorCode::UpstreamTimeout,
+            ApiError::Detailed { code, .. } => *code,
+        }
+    }
+
+    pub fn details(&self) -> Map<String, Value> {
+        match self {
+            ApiError::Detailed { details, .. } => details.clone(),
+            _ => Map::new(),
+        }
+    }
+
+    fn status_and_title(&self) -> (StatusCode, &'static str) {
+        match self {
+            ApiError::DatabaseError(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error"),
+            ApiError::ExternalApiError(_) => (StatusCode::SERVICE_UNAVAILABLE, "Service unavailable"),
+            ApiError::ValidationError(_) => (StatusCode::BAD_REQUEST, "Bad request"),
+            ApiError::PriceOracleError(_) => (StatusCode::SERVICE_UNAVAILABLE, "Price service unavailable"),
+            ApiError::NotFound(_) => (StatusCode::NOT_FOUND, "Not found"),
+            ApiError::Unauthorized(_) => (StatusCode::UNAUTHORIZED, "Unauthorized"),
+            ApiError::Forbidden(_) => (StatusCode::FORBIDDEN, "Forbidden"),
+            ApiError::StalePrice(_) => (StatusCode::SERVICE_UNAVAILABLE, "Price unreliable"),
+            ApiError::TradingHalted(_) => (StatusCode::SERVICE_UNAVAILABLE, "Trading halted"),
+            ApiError::PositionLimitExceeded(_) => (StatusCode::BAD_REQUEST, "Position limit exceeded"),
+            ApiError::UpstreamTimeout(_) => (StatusCode::GATEWAY_TIMEOUT, "Gateway timeout"),
+            ApiError::Detailed { error, .. } => error.status_and_title(),
+        }
+    }
 }
 
 impl fmt::Display for ApiError {
@@ -18,44 +133,33 @@ impl fmt::Display for ApiError {
             ApiError::ValidationError(msg) => write!(f, "Validation error: {}", msg),
             ApiError::PriceOracleError(msg) => write!(f, "Price oracle error: {}", msg),
             ApiError::NotFound(msg) => write!(f, "Not found: {}", msg),
+            ApiError::Unauthorized(msg) => write!(f, "Unauthorized: {}", msg),
+            ApiError::Forbidden(msg) => write!(f, "Forbidden: {}", msg),
+            ApiError::StalePrice(msg) => write!(f, "Stale price: {}", msg),
+            ApiError::TradingHalted(msg) => write!(f, "Trading unavailable: {}", msg),
+            ApiError::PositionLimitExceeded(msg) => write!(f, "Position limit exceeded: {}", msg),
+            ApiError::UpstreamTimeout(msg) => write!(f, "Upstream timeout: {}", msg),
+            ApiError::Detailed { error, .. } => error.fmt(f),
         }
     }
 }
 
+impl std::error::Error for ApiError {}
+
 impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.status_and_title().0
+    }
+
     fn error_response(&self) -> HttpResponse {
-        match self {
-            ApiError::DatabaseError(_) => {
-                HttpResponse::InternalServerError().json(serde_json::json!({
-                    "error": "Internal server error",
-                    "message": self.to_string()
-                }))
-            }
-            ApiError::ExternalApiError(_) => {
-                HttpResponse::ServiceUnavailable().json(serde_json::json!({
-                    "error": "Service unavailable",
-                    "message": self.to_string()
-                }))
-            }
-            ApiError::ValidationError(_) => {
-                HttpResponse::BadRequest().json(serde_json::json!({
-                    "error": "Bad request",
-                    "message": self.to_string()
-                }))
-            }
-            ApiError::PriceOracleError(_) => {
-                HttpResponse::ServiceUnavailable().json(serde_json::json!({
-                    "error": "Price service unavailable",
-                    "message": self.to_string()
-                }))
-            }
-            ApiError::NotFound(_) => {
-                HttpResponse::NotFound().json(serde_json::json!({
-                    "error": "Not found",
-                    "message": self.to_string()
-                }))
-            }
-        }
+        let (status, title) = self.status_and_title();
+        HttpResponse::build(status).json(serde_json::json!({
+            "error": title,
+            "error_code": self.code(),
+            "message": self.to_string(),
+            "details": self.details(),
+            "request_id": request_id::current()
+        }))
     }
 }
 
@@ -77,4 +181,32 @@ impl From<reqwest::Error> for ApiError {
     }
 }
 
-pub type ApiResult<T> = Result<T, ApiError>;
\ No newline at end of file
+pub type ApiResult<T> = Result<T, ApiError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::to_bytes;
+    use serde_json::json;
+
+    #[actix_web::test]
+    async fn test_error_body() {
+        let error = ApiError::ValidationError("Requested quantity exceeds maximum".to_string())
+            .with_code(ErrorCode::InsufficientCollateral)
+            .with_details(json!({"max_quantity": 0.5}))
+            .with_details(json!({"available_collateral_usd": 1250.0}));
+        assert_eq!(error.status_code(), StatusCode::BAD_REQUEST);
+        assert_eq!(error.to_string(), "Validation error: Requested quantity exceeds maximum");
+
+        let body: Value = serde_json::from_slice(&to_bytes(error.error_response().into_body()).await.unwrap()).unwrap();
+        assert_eq!(body["error"], "Bad request");
+        assert_eq!(body["error_code"], "INSUFFICIENT_COLLATERAL");
+        assert_eq!(body["details"], json!({"max_quantity": 0.5, "available_collateral_usd": 1250.0}));
+
+        // Details alone keep the code of the error's kind
+        let error = ApiError::StalePrice("old".to_string()).with_details(json!({"age_secs": 40}));
+        assert_eq!(error.code(), ErrorCode::StalePrice);
+        assert_eq!(error.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(ApiError::NotFound("x".to_string()).details(), Map::new());
+    }
+}
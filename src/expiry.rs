@@ -0,0 +1,174 @@
+// Expiring-soon notifications.
+// A background task finds open contracts expiring within the notice window and publishes a
+// contract.expiring_soon event for each. A contract is marked once its event is delivered,
+// so it is announced once even across restarts; failed deliveries are retried on the next check.
+
+use crate::error::ApiResult;
+use crate::repository::Repository;
+use crate::supervisor::Supervisor;
+use crate::webhooks::{EventSink, WebhookEvent, CONTRACT_EXPIRING_SOON};
+use chrono::Utc;
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::interval;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ExpiryNoticeConfig {
+    pub notice: Duration,          // How long before expiry contracts are announced
+    pub check_interval: Duration,  // How often the notifier looks for them
+}
+
+impl Default for ExpiryNoticeConfig {
+    fn default() -> Self {
+        Self {
+            notice: Duration::from_secs(24 * 3600),
+            check_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+impl ExpiryNoticeConfig {
+    /// EXPIRY_NOTICE_HOURS (24) and EXPIRY_CHECK_INTERVAL_SECS (60)
+    pub fn from_env() -> Self {
+        let notice_hours: f64 = env::var("EXPIRY_NOTICE_HOURS")
+            .unwrap_or_else(|_| "24".to_string())
+            .parse()
+            .unwrap_or(24.0);
+        let check_interval_secs: u64 = env::var("EXPIRY_CHECK_INTERVAL_SECS")
+            .unwrap_or_else(|_| "60".to_string())
+            .parse()
+            .unwrap_or(60);
+        Self {
+            notice: Duration::from_secs_f64(notice_hours.max(0.0) * 3600.0),
+            check_interval: Duration::from_secs(check_interval_secs.max(1)),
+        }
+    }
+
+    pub fn notice_secs(&self) -> i64 {
+        self.notice.as_secs() as i64
+    }
+
+    /// Whether a contract expiring at `expires` is inside the notice window at `now`
+    pub fn is_expiring_soon(&self, expires: i64, now: i64) -> bool {
+        expires > now && expires <= now + self.notice_secs()
+    }
+}
+
+/// Publish an expiring-soon event for every open contract expiring within `notice_secs`
+/// that has not been announced yet. Returns the number of contracts announced.
+pub async fn notify_expiring_contracts(
+    repository: &Repository,
+    sink: &dyn EventSink,
+    notice_secs: i64,
+    now: i64,
+) -> ApiResult<usize> {
+    let contracts = repository.contracts_to_announce(now, now + notice_secs).await?;
+    let mut announced = 0;
+    for contract in contracts {
+        let event = WebhookEvent::new(
+            CONTRACT_EXPIRING_SOON,
+            serde_json::json!({
+                "contract": contract,
+                "expires_in_secs": contract.expires - now,
+            }),
+        );
+        match sink.publish(&event).await {
+            Ok(()) => {
+                repository.mark_expiry_notified(contract.id, now).await?;
+                announced += 1;
+            }
+            Err(e) => eprintln!("⚠️  Expiry notice for contract {} not delivered, will retry: {}", contract.id, e),
+        }
+    }
+    Ok(announced)
+}
+
+pub fn start_expiry_notifier(
+    supervisor: &Supervisor,
+    repository: Repository,
+    sink: Arc<dyn EventSink>,
+    config: ExpiryNoticeConfig,
+) {
+    supervisor.spawn("expiry_notifier", move || {
+        let (repository, sink) = (repository.clone(), sink.clone());
+        async move {
+            let mut ticker = interval(config.check_interval);
+            loop {
+                ticker.tick().await;
+                let now = Utc::now().timestamp();
+                match notify_expiring_contracts(&repository, sink.as_ref(), config.notice_secs(), now).await {
+                    Ok(0) => {}
+                    Ok(count) => println!("🔔 Sent expiring-soon notices for {} contract(s)", count),
+                    Err(e) => eprintln!("Error checking for expiring contracts: {}", e),
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::create_in_memory_pool;
+    use crate::models::{Asset, Contract, OptionSide};
+    use crate::sources::SourceError;
+    use async_trait::async_trait;
+    use std::sync::Mutex;
+
+    // Records published events; fails while `fail` is set
+    struct RecordingSink {
+        events: Mutex<Vec<WebhookEvent>>,
+        fail: Mutex<bool>,
+    }
+
+    #[async_trait]
+    impl EventSink for RecordingSink {
+        async fn publish(&self, event: &WebhookEvent) -> Result<(), SourceError> {
+            if *self.fail.lock().unwrap() {
+                return Err("receiver down".into());
+            }
+            self.events.lock().unwrap().push(event.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_expiring_contracts_are_announced_once() {
+        let repo = Repository::new(create_in_memory_pool().unwrap());
+        let now = Utc::now().timestamp();
+        let contract = |expires_in: i64| Contract {
+            underlying: Asset::Btc,
+            side: OptionSide::Put,
+            strike_price: 95000.0,
+            quantity: 0.1,
+            expires: now + expires_in,
+            premium: 0.001,
+        };
+        repo.insert_contract(contract(3600)).await.unwrap();
+        repo.insert_contract(contract(3 * 86400)).await.unwrap();
+        repo.insert_contract(contract(-60)).await.unwrap();
+
+        let sink = RecordingSink { events: Mutex::new(Vec::new()), fail: Mutex::new(true) };
+        // Undelivered notices are retried on the next check
+        assert_eq!(notify_expiring_contracts(&repo, &sink, 86400, now).await.unwrap(), 0);
+
+        *sink.fail.lock().unwrap() = false;
+        assert_eq!(notify_expiring_contracts(&repo, &sink, 86400, now).await.unwrap(), 1);
+        assert_eq!(notify_expiring_contracts(&repo, &sink, 86400, now + 60).await.unwrap(), 0);
+
+        let events = sink.events.lock().unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event, CONTRACT_EXPIRING_SOON);
+        assert_eq!(events[0].data["contract"]["id"], 1);
+        assert_eq!(events[0].data["expires_in_secs"], 3600);
+    }
+
+    #[test]
+    fn test_notice_window() {
+        let config = ExpiryNoticeConfig { notice: Duration::from_secs(3600), ..Default::default() };
+        assert!(config.is_expiring_soon(1_000 + 3600, 1_000));
+        assert!(!config.is_expiring_soon(1_000 + 3601, 1_000));
+        assert!(!config.is_expiring_soon(1_000, 1_000));
+    }
+}
@@ -0,0 +1,461 @@
+// CSV and Parquet exports of the contract book and premium history.
+// Rows are read from SQLite on the blocking thread pool and handed to the HTTP response
+// in chunks as they are encoded, so an export is never held in memory whole.
+
+use crate::db::DbPool;
+use crate::models::{Asset, ContractRecord};
+use crate::repository::{self, CONTRACT_RECORD_COLUMNS};
+use crate::utils::{cents_to_usd, sats_to_btc};
+use actix_web::web::Bytes;
+use chrono::{NaiveDate, NaiveTime};
+use futures::Stream;
+use parquet::data_type::{ByteArray, ByteArrayType, DoubleType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use rusqlite::{params, Connection};
+use serde::Deserialize;
+use std::io::{self, Write};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+// Bytes buffered before a chunk is sent to the client
+const CHUNK_SIZE: usize = 64 * 1024;
+// Rows per Parquet row group; bounds the rows held in memory while encoding
+const ROW_GROUP_SIZE: usize = 8192;
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Parquet,
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Parquet => "parquet",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum ColumnType {
+    Int64,
+    Double,
+    Text,
+}
+
+pub struct Column {
+    name: &'static str,
+    kind: ColumnType,
+    optional: bool,
+}
+
+const fn column(name: &'static str, kind: ColumnType, optional: bool) -> Column {
+    Column { name, kind, optional }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Int(Option<i64>),
+    Float(Option<f64>),
+    Text(Option<String>),
+}
+
+impl Value {
+    fn to_csv_field(&self) -> String {
+        match self {
+            Value::Int(v) => v.map(|v| v.to_string()).unwrap_or_default(),
+            Value::Float(v) => v.map(|v| v.to_string()).unwrap_or_default(),
+            Value::Text(v) => v.clone().unwrap_or_default(),
+        }
+    }
+}
+
+// Receives each exported row in order
+type RowSink<'a> = dyn FnMut(Vec<Value>) -> io::Result<()> + 'a;
+
+// An exportable dataset: its columns and how to read its rows for a time range
+pub struct ExportTable {
+    pub name: &'static str,
+    columns: &'static [Column],
+    read: fn(&Connection, i64, i64, &mut RowSink) -> io::Result<()>,
+}
+
+pub const CONTRACTS: ExportTable = ExportTable {
+    name: "contracts",
+    columns: &[
+        column("id", ColumnType::Int64, false),
+        column("underlying", ColumnType::Text, false),
+        column("side", ColumnType::Text, false),
+        column("strike_price", ColumnType::Double, false),
+        column("quantity", ColumnType::Double, false),
+        column("expires", ColumnType::Int64, false),
+        column("premium_btc", ColumnType::Double, false),
+        column("premium_currency", ColumnType::Text, false),
+        column("quoted_premium", ColumnType::Double, true),
+        column("trade_btc_price", ColumnType::Double, true),
+        column("created_at", ColumnType::Int64, false),
+        column("status", ColumnType::Text, false),
+        column("settlement_price", ColumnType::Double, true),
+        column("settlement_btc_price", ColumnType::Double, true),
+        column("settled_at", ColumnType::Int64, true),
+        column("spot_at_trade", ColumnType::Double, true),
+        column("iv_at_trade", ColumnType::Double, true),
+        column("iv_source", ColumnType::Text, true),
+        column("mark_premium_at_trade", ColumnType::Double, true),
+    ],
+    read: read_contracts,
+};
+
+pub const PREMIUM_HISTORY: ExportTable = ExportTable {
+    name: "premium_history",
+    columns: &[
+        column("id", ColumnType::Int64, false),
+        column("product_key", ColumnType::Text, false),
+        column("underlying", ColumnType::Text, false),
+        column("side", ColumnType::Text, false),
+        column("strike_price", ColumnType::Double, false),
+        column("expires", ColumnType::Int64, false),
+        column("premium_btc", ColumnType::Double, false),
+        column("timestamp", ColumnType::Int64, false),
+    ],
+    read: read_premium_history,
+};
+
+fn sql_error(e: rusqlite::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+fn contract_row(record: ContractRecord) -> Vec<Value> {
+    vec![
+        Value::Int(Some(record.id)),
+        Value::Text(Some(record.underlying.to_string())),
+        Value::Text(Some(record.side.to_string())),
+        Value::Float(Some(record.strike_price)),
+        Value::Float(Some(record.quantity)),
+        Value::Int(Some(record.expires)),
+        Value::Float(Some(record.premium)),
+        Value::Text(Some(record.premium_currency.to_string())),
+        Value::Float(record.quoted_premium),
+        Value::Float(record.trade_btc_price),
+        Value::Int(Some(record.created_at)),
+        Value::Text(Some(record.status.to_string())),
+        Value::Float(record.settlement_price),
+        Value::Float(record.settlement_btc_price),
+        Value::Int(record.settled_at),
+        Value::Float(record.spot_at_trade),
+        Value::Float(record.iv_at_trade),
+        Value::Text(record.iv_source.map(|source| source.to_string())),
+        Value::Float(record.mark_premium_at_trade),
+    ]
+}
+
+// Contracts created in [from, to)
+fn read_contracts(conn: &Connection, from: i64, to: i64, emit: &mut RowSink) -> io::Result<()> {
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {} FROM contracts WHERE created_at >= ?1 AND created_at < ?2 ORDER BY id ASC",
+            CONTRACT_RECORD_COLUMNS
+        ))
+        .map_err(sql_error)?;
+    let rows = stmt
+        .query_map(params![from, to], repository::contract_record_from_row)
+        .map_err(sql_error)?;
+    for record in rows {
+        emit(contract_row(record.map_err(sql_error)?))?;
+    }
+    Ok(())
+}
+
+// Premium observations recorded in [from, to)
+fn read_premium_history(conn: &Connection, from: i64, to: i64, emit: &mut RowSink) -> io::Result<()> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, product_key, side, strike_price_cents, expires, premium_sats, timestamp
+             FROM premium_history WHERE timestamp >= ?1 AND timestamp < ?2 ORDER BY id ASC",
+        )
+        .map_err(sql_error)?;
+    let mut rows = stmt.query(params![from, to]).map_err(sql_error)?;
+    while let Some(row) = rows.next().map_err(sql_error)? {
+        let product_key: String = row.get(1).map_err(sql_error)?;
+        // Non-BTC product keys are prefixed with their underlying (see repository::product_key)
+        let underlying = Asset::ALL
+            .into_iter()
+            .find(|asset| product_key.starts_with(&format!("{}-", asset)))
+            .unwrap_or_default();
+        emit(vec![
+            Value::Int(Some(row.get(0).map_err(sql_error)?)),
+            Value::Text(Some(product_key)),
+            Value::Text(Some(underlying.to_string())),
+            Value::Text(Some(row.get(2).map_err(sql_error)?)),
+            Value::Float(Some(cents_to_usd(row.get(3).map_err(sql_error)?))),
+            Value::Int(Some(row.get(4).map_err(sql_error)?)),
+            Value::Float(Some(sats_to_btc(row.get(5).map_err(sql_error)?))),
+            Value::Int(Some(row.get(6).map_err(sql_error)?)),
+        ])?;
+    }
+    Ok(())
+}
+
+/// Parse a date filter: Unix seconds, or a YYYY-MM-DD day in UTC. A day used as the
+/// upper bound covers the whole day.
+pub fn parse_date_bound(value: &str, upper: bool) -> Result<i64, String> {
+    if let Ok(timestamp) = value.parse::<i64>() {
+        return Ok(timestamp);
+    }
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| format!("invalid date '{}', expected YYYY-MM-DD or Unix seconds", value))?;
+    let date = if upper { date.succ_opt().unwrap_or(date) } else { date };
+    Ok(date.and_time(NaiveTime::MIN).and_utc().timestamp())
+}
+
+/// Write `table` rows with timestamps in [from, to) to `out` in `format`
+pub fn write_export<W: Write + Send>(
+    conn: &Connection,
+    table: &ExportTable,
+    format: ExportFormat,
+    from: i64,
+    to: i64,
+    out: W,
+) -> io::Result<()> {
+    let mut out = match format {
+        ExportFormat::Csv => write_csv(conn, table, from, to, out)?,
+        ExportFormat::Parquet => write_parquet(conn, table, from, to, out)?,
+    };
+    out.flush()
+}
+
+fn write_csv<W: Write>(conn: &Connection, table: &ExportTable, from: i64, to: i64, out: W) -> io::Result<W> {
+    let mut writer = csv::Writer::from_writer(out);
+    writer.write_record(table.columns.iter().map(|c| c.name))?;
+    (table.read)(conn, from, to, &mut |row| {
+        writer.write_record(row.iter().map(Value::to_csv_field))?;
+        Ok(())
+    })?;
+    writer.into_inner().map_err(|e| e.into_error())
+}
+
+fn parquet_schema(table: &ExportTable) -> String {
+    let fields: Vec<String> = table
+        .columns
+        .iter()
+        .map(|c| {
+            let repetition = if c.optional { "OPTIONAL" } else { "REQUIRED" };
+            let physical = match c.kind {
+                ColumnType::Int64 => "INT64",
+                ColumnType::Double => "DOUBLE",
+                ColumnType::Text => "BYTE_ARRAY",
+            };
+            let logical = if matches!(c.kind, ColumnType::Text) { " (UTF8)" } else { "" };
+            format!("{} {} {}{};", repetition, physical, c.name, logical)
+        })
+        .collect();
+    format!("message {} {{ {} }}", table.name, fields.join(" "))
+}
+
+fn write_parquet<W: Write + Send>(conn: &Connection, table: &ExportTable, from: i64, to: i64, out: W) -> io::Result<W> {
+    let schema = Arc::new(parse_message_type(&parquet_schema(table)).map_err(io::Error::other)?);
+    let props = Arc::new(WriterProperties::builder().build());
+    let mut writer = SerializedFileWriter::new(out, schema, props).map_err(io::Error::other)?;
+
+    let mut rows = Vec::with_capacity(ROW_GROUP_SIZE);
+    (table.read)(conn, from, to, &mut |row| {
+        rows.push(row);
+        if rows.len() == ROW_GROUP_SIZE {
+            write_row_group(&mut writer, &rows)?;
+            rows.clear();
+        }
+        Ok(())
+    })?;
+    if !rows.is_empty() {
+        write_row_group(&mut writer, &rows)?;
+    }
+    writer.into_inner().map_err(io::Error::other)
+}
+
+fn write_row_group<W: Write + Send>(writer: &mut SerializedFileWriter<W>, rows: &[Vec<Value>]) -> io::Result<()> {
+    let mut row_group = writer.next_row_group().map_err(io::Error::other)?;
+    let mut index = 0;
+    while let Some(mut column) = row_group.next_column().map_err(io::Error::other)? {
+        // Definition level 1 = present, 0 = null; only read for optional columns
+        let mut def_levels = Vec::with_capacity(rows.len());
+        let result = match &rows[0][index] {
+            Value::Int(_) => {
+                let mut values = Vec::with_capacity(rows.len());
+                for row in rows {
+                    if let Value::Int(v) = &row[index] {
+                        def_levels.push(v.is_some() as i16);
+                        values.extend(v);
+                    }
+                }
+                column.typed::<Int64Type>().write_batch(&values, Some(&def_levels), None)
+            }
+            Value::Float(_) => {
+                let mut values = Vec::with_capacity(rows.len());
+                for row in rows {
+                    if let Value::Float(v) = &row[index] {
+                        def_levels.push(v.is_some() as i16);
+                        values.extend(v);
+                    }
+                }
+                column.typed::<DoubleType>().write_batch(&values, Some(&def_levels), None)
+            }
+            Value::Text(_) => {
+                let mut values = Vec::with_capacity(rows.len());
+                for row in rows {
+                    if let Value::Text(v) = &row[index] {
+                        def_levels.push(v.is_some() as i16);
+                        values.extend(v.as_ref().map(|s| ByteArray::from(s.as_bytes().to_vec())));
+                    }
+                }
+                column.typed::<ByteArrayType>().write_batch(&values, Some(&def_levels), None)
+            }
+        };
+        result.map_err(io::Error::other)?;
+        column.close().map_err(io::Error::other)?;
+        index += 1;
+    }
+    row_group.close().map_err(io::Error::other)?;
+    Ok(())
+}
+
+// Forwards written bytes to the response stream in CHUNK_SIZE pieces. Writes fail once
+// the client has gone away, which stops the export.
+struct ChunkWriter {
+    tx: mpsc::Sender<io::Result<Bytes>>,
+    buf: Vec<u8>,
+}
+
+impl ChunkWriter {
+    fn send_buffered(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(std::mem::replace(&mut self.buf, Vec::with_capacity(CHUNK_SIZE)));
+        self.tx
+            .blocking_send(Ok(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "export client disconnected"))
+    }
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= CHUNK_SIZE {
+            self.send_buffered()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send_buffered()
+    }
+}
+
+/// Stream an export as it is produced. A database or encoding error part way through
+/// ends the stream with that error.
+pub fn stream_export(
+    pool: DbPool,
+    table: &'static ExportTable,
+    format: ExportFormat,
+    from: i64,
+    to: i64,
+) -> impl Stream<Item = io::Result<Bytes>> {
+    let (tx, rx) = mpsc::channel(4);
+    tokio::task::spawn_blocking(move || {
+        let writer = ChunkWriter { tx: tx.clone(), buf: Vec::with_capacity(CHUNK_SIZE) };
+        let result = pool
+            .get()
+            .map_err(io::Error::other)
+            .and_then(|conn| write_export(&conn, table, format, from, to, writer));
+        if let Err(e) = result {
+            eprintln!("❌ Export of {} failed: {}", table.name, e);
+            let _ = tx.blocking_send(Err(e));
+        }
+    });
+    futures::stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|item| (item, rx)) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Contract, OptionSide};
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+
+    fn test_conn() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init_db(&conn).unwrap();
+        for (underlying, strike_price) in [(Asset::Btc, 100000.0), (Asset::Eth, 3500.0)] {
+            let contract = Contract {
+                underlying,
+                side: OptionSide::Call,
+                strike_price,
+                quantity: 0.5,
+                expires: 2_000_000_000,
+                premium: 0.01,
+            };
+            repository::insert_contract(&conn, &contract, None).unwrap();
+        }
+        conn
+    }
+
+    #[test]
+    fn test_csv_export() {
+        let conn = test_conn();
+        let mut out = Vec::new();
+        write_export(&conn, &CONTRACTS, ExportFormat::Csv, 0, i64::MAX, &mut out).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("id,underlying,side,strike_price,quantity"));
+        assert!(lines[2].starts_with("2,ETH,Call,3500,0.5,2000000000,0.01,BTC,,"));
+
+        let mut out = Vec::new();
+        write_export(&conn, &PREMIUM_HISTORY, ExportFormat::Csv, 0, i64::MAX, &mut out).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        assert!(csv.lines().nth(2).unwrap().starts_with("2,ETH-Call-350000-2000000000,ETH,Call,3500,"));
+
+        // Nothing was created before 1970-01-02
+        let mut out = Vec::new();
+        write_export(&conn, &CONTRACTS, ExportFormat::Csv, 0, 86_400, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap().lines().count(), 1);
+    }
+
+    #[test]
+    fn test_parquet_export_round_trips() {
+        let conn = test_conn();
+        let mut out = Vec::new();
+        write_export(&conn, &CONTRACTS, ExportFormat::Parquet, 0, i64::MAX, &mut out).unwrap();
+
+        let reader = SerializedFileReader::new(Bytes::from(out)).unwrap();
+        let metadata = reader.metadata();
+        assert_eq!(metadata.file_metadata().num_rows(), 2);
+        assert_eq!(metadata.file_metadata().schema_descr().num_columns(), CONTRACTS.columns.len());
+        let rows: Vec<String> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| row.unwrap().to_string())
+            .collect();
+        assert!(rows[1].contains("underlying: \"ETH\""));
+        assert!(rows[1].contains("settled_at: null"));
+    }
+
+    #[test]
+    fn test_parse_date_bound() {
+        assert_eq!(parse_date_bound("1735689600", false), Ok(1735689600));
+        assert_eq!(parse_date_bound("2025-01-01", false), Ok(1735689600));
+        assert_eq!(parse_date_bound("2025-01-01", true), Ok(1735689600 + 86_400));
+        assert!(parse_date_bound("01/01/2025", false).is_err());
+    }
+}
@@ -0,0 +1,182 @@
+// Trading fees.
+// Every trade pays a fee on top of its premium: FEE_PERCENT of the premium, but at least
+// FEE_MIN_SATS. A credit tier can give its accounts their own percentage and minimum. A negative
+// percentage is a maker rebate, paid back out of the premium, to which no minimum applies. The
+// fee of each trade is recorded in the fees table with the contract, is added to the premium
+// payment when premiums must be paid, and counts towards the pool's P&L in settlement reports.
+
+use crate::credit_tiers;
+use crate::error::{ApiError, ApiResult};
+use crate::utils::{cents_to_usd, usd_to_cents};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use std::env;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct FeeSchedule {
+    pub percent: f64,       // Of the premium; negative for a rebate
+    pub min_fee_sats: i64,  // Floor of positive fees
+}
+
+impl FeeSchedule {
+    /// FEE_PERCENT (0) and FEE_MIN_SATS (0). Invalid values fall back to the defaults.
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            percent: env::var("FEE_PERCENT")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|percent| validate_percent(*percent).is_ok())
+                .unwrap_or(default.percent),
+            min_fee_sats: env::var("FEE_MIN_SATS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|sats: &i64| *sats >= 0)
+                .unwrap_or(default.min_fee_sats),
+        }
+    }
+
+    /// Fee on a premium of `premium_sats`; negative for a rebate
+    pub fn fee_sats(&self, premium_sats: i64) -> i64 {
+        let fee = (premium_sats as f64 * self.percent / 100.0).round() as i64;
+        if self.percent < 0.0 {
+            fee
+        } else {
+            fee.max(self.min_fee_sats)
+        }
+    }
+}
+
+/// Refuse fee percentages outside (-100, 100]; a rebate cannot exceed the premium
+pub fn validate_percent(percent: f64) -> ApiResult<()> {
+    if percent.is_finite() && percent > -100.0 && percent <= 100.0 {
+        Ok(())
+    } else {
+        Err(ApiError::ValidationError(format!("fee_percent must be above -100 and at most 100, got {}", percent)))
+    }
+}
+
+/// The fee of one trade
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct TradeFee {
+    pub tier: Option<String>,  // Credit tier whose schedule applied; None for the default schedule
+    pub fee_percent: f64,
+    pub fee_sats: i64,         // Paid on top of the premium; negative for a rebate
+    pub fee_usd: f64,          // At the BTC price of the trade
+}
+
+/// Fee `account` pays on a premium of `premium_sats` traded at `btc_price`: by the schedule of
+/// its credit tier where the tier sets one, otherwise by `default`
+pub fn trade_fee(
+    conn: &Connection,
+    account: &str,
+    premium_sats: i64,
+    btc_price: f64,
+    default: &FeeSchedule,
+) -> ApiResult<TradeFee> {
+    let tier = credit_tiers::account_tier(conn, account)?
+        .filter(|tier| tier.terms.fee_percent.is_some() || tier.terms.min_fee_sats.is_some());
+    let schedule = match &tier {
+        Some(tier) => FeeSchedule {
+            percent: tier.terms.fee_percent.unwrap_or(default.percent),
+            min_fee_sats: tier.terms.min_fee_sats.unwrap_or(default.min_fee_sats),
+        },
+        None => *default,
+    };
+    let fee_sats = schedule.fee_sats(premium_sats);
+    Ok(TradeFee {
+        tier: tier.map(|tier| tier.name),
+        fee_percent: schedule.percent,
+        fee_sats,
+        fee_usd: cents_to_usd(usd_to_cents(fee_sats as f64 / 1e8 * btc_price)),
+    })
+}
+
+/// Record `fee` as paid by `account` for contract `contract_id`
+pub fn record(conn: &Connection, contract_id: i64, account: &str, fee: &TradeFee, now: i64) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO fees (contract_id, account, tier, fee_percent, fee_sats, fee_cents, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![contract_id, account, fee.tier, fee.fee_percent, fee.fee_sats, usd_to_cents(fee.fee_usd), now],
+    )?;
+    Ok(())
+}
+
+/// Fee recorded for contract `contract_id`, if any
+pub fn load_fee(conn: &Connection, contract_id: i64) -> rusqlite::Result<Option<TradeFee>> {
+    conn.query_row(
+        "SELECT tier, fee_percent, fee_sats, fee_cents FROM fees WHERE contract_id = ?1",
+        params![contract_id],
+        |row| {
+            Ok(TradeFee {
+                tier: row.get(0)?,
+                fee_percent: row.get(1)?,
+                fee_sats: row.get(2)?,
+                fee_usd: cents_to_usd(row.get(3)?),
+            })
+        },
+    )
+    .optional()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::credit_tiers::{assign_tier, set_tier, CreditTierTerms};
+    use crate::models::Contract;
+    use crate::repository::insert_contract;
+
+    #[test]
+    fn test_fee_schedule() {
+        let schedule = FeeSchedule { percent: 2.0, min_fee_sats: 500 };
+        assert_eq!(schedule.fee_sats(1_000_000), 20_000);
+        // Small premiums pay the minimum
+        assert_eq!(schedule.fee_sats(10_000), 500);
+        assert_eq!(schedule.fee_sats(0), 500);
+        // Rebates are not floored
+        let rebate = FeeSchedule { percent: -0.5, min_fee_sats: 500 };
+        assert_eq!(rebate.fee_sats(1_000_000), -5_000);
+        assert_eq!(FeeSchedule::default().fee_sats(1_000_000), 0);
+
+        assert!(validate_percent(-99.9).is_ok());
+        assert!(validate_percent(-100.0).is_err());
+        assert!(validate_percent(f64::NAN).is_err());
+    }
+
+    #[test]
+    fn test_tiers_set_the_fees_of_their_accounts() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init_db(&conn).unwrap();
+        let default = FeeSchedule { percent: 1.0, min_fee_sats: 100 };
+
+        let fee = trade_fee(&conn, "desk-a", 1_000_000, 100_000.0, &default).unwrap();
+        assert_eq!(fee, TradeFee { tier: None, fee_percent: 1.0, fee_sats: 10_000, fee_usd: 10.0 });
+
+        // A tier without fee terms leaves the default schedule in place
+        set_tier(&conn, "retail", CreditTierTerms::default(), "ops", 1).unwrap();
+        assign_tier(&conn, "desk-a", Some("retail"), "ops", 1).unwrap();
+        assert_eq!(trade_fee(&conn, "desk-a", 1_000_000, 100_000.0, &default).unwrap().tier, None);
+
+        let maker = CreditTierTerms { fee_percent: Some(-0.25), ..Default::default() };
+        set_tier(&conn, "maker", maker, "ops", 1).unwrap();
+        assign_tier(&conn, "desk-a", Some("maker"), "ops", 1).unwrap();
+        let fee = trade_fee(&conn, "desk-a", 1_000_000, 100_000.0, &default).unwrap();
+        assert_eq!(fee, TradeFee { tier: Some("maker".to_string()), fee_percent: -0.25, fee_sats: -2_500, fee_usd: -2.5 });
+
+        let bad = CreditTierTerms { fee_percent: Some(-150.0), ..Default::default() };
+        assert!(set_tier(&conn, "bad", bad, "ops", 1).is_err());
+
+        let contract = Contract {
+            underlying: Default::default(),
+            side: crate::models::OptionSide::Call,
+            strike_price: 100_000.0,
+            quantity: 1.0,
+            expires: 1_900_000_000,
+            premium: 0.01,
+        };
+        let id = insert_contract(&conn, &contract, None).unwrap();
+        record(&conn, id, "desk-a", &fee, 1).unwrap();
+        assert_eq!(load_fee(&conn, id).unwrap(), Some(fee));
+        assert_eq!(load_fee(&conn, id + 1).unwrap(), None);
+    }
+}
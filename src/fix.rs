@@ -0,0 +1,1061 @@
+// FIX 4.4 gateway for institutional takers.
+// With FIX_LISTEN_ADDR set, a FIX acceptor runs as its own supervised task next to the HTTP
+// API. Counterparties log on with their CompID as SenderCompID, ours (FIX_COMP_ID) as
+// TargetCompID and an API key as Password; a Logon without a valid key is refused unless
+// AUTH_DISABLED is set, and trades are attributed to the key like POST /contract. A
+// NewOrderSingle buys one option from the pool: it goes through the same checks as POST
+// /contract and is answered with an ExecutionReport, filled, new while the premium payment is
+// pending, or rejected with the error code and reason in Text.
+//
+// Session handling covers Logon, Heartbeat, TestRequest, ResendRequest, SequenceReset, Reject
+// and Logout. Sequence numbers of each session are persisted, so a counterparty reconnecting
+// after a drop or a restart carries on where it left off; sent ExecutionReports are stored and
+// replayed on ResendRequest, with gap fills for session messages.
+
+use crate::api::{self, AppState};
+use crate::error::ApiError;
+use crate::models::{Asset, Contract, ExerciseStyle, OptionSide, QuoteCurrency};
+use crate::payments::{PaymentMethod, PremiumPayment};
+use crate::pools;
+use crate::repository::Repository;
+use crate::request_id;
+use crate::supervisor::Supervisor;
+use chrono::{NaiveDate, NaiveTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::env;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+pub const BEGIN_STRING: &str = "FIX.4.4";
+const SOH: char = '\u{1}';
+const LOGON_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_HEARTBEAT_SECS: u64 = 300;
+const MAX_MESSAGE_LEN: usize = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FixConfig {
+    pub listen_addr: Option<String>,  // Gateway off unless set
+    pub comp_id: String,
+}
+
+impl Default for FixConfig {
+    fn default() -> Self {
+        Self {
+            listen_addr: None,
+            comp_id: "BTCOPTIONS".to_string(),
+        }
+    }
+}
+
+impl FixConfig {
+    /// FIX_LISTEN_ADDR (e.g. 0.0.0.0:9878, off when unset) and FIX_COMP_ID (BTCOPTIONS)
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            listen_addr: env::var("FIX_LISTEN_ADDR").ok().filter(|v| !v.trim().is_empty()),
+            comp_id: env::var("FIX_COMP_ID").ok().filter(|v| !v.trim().is_empty()).unwrap_or(defaults.comp_id),
+        }
+    }
+}
+
+/// Tags used by the gateway
+pub mod tag {
+    pub const AVG_PX: u32 = 6;
+    pub const BEGIN_SEQ_NO: u32 = 7;
+    pub const BEGIN_STRING: u32 = 8;
+    pub const BODY_LENGTH: u32 = 9;
+    pub const CHECK_SUM: u32 = 10;
+    pub const CL_ORD_ID: u32 = 11;
+    pub const CUM_QTY: u32 = 14;
+    pub const CURRENCY: u32 = 15;
+    pub const END_SEQ_NO: u32 = 16;
+    pub const EXEC_ID: u32 = 17;
+    pub const LAST_PX: u32 = 31;
+    pub const LAST_QTY: u32 = 32;
+    pub const MSG_SEQ_NUM: u32 = 34;
+    pub const MSG_TYPE: u32 = 35;
+    pub const NEW_SEQ_NO: u32 = 36;
+    pub const ORDER_ID: u32 = 37;
+    pub const ORDER_QTY: u32 = 38;
+    pub const ORD_STATUS: u32 = 39;
+    pub const ORD_TYPE: u32 = 40;
+    pub const POSS_DUP_FLAG: u32 = 43;
+    pub const PRICE: u32 = 44;
+    pub const REF_SEQ_NUM: u32 = 45;
+    pub const SENDER_COMP_ID: u32 = 49;
+    pub const SENDING_TIME: u32 = 52;
+    pub const SIDE: u32 = 54;
+    pub const SYMBOL: u32 = 55;
+    pub const TARGET_COMP_ID: u32 = 56;
+    pub const TEXT: u32 = 58;
+    pub const ENCRYPT_METHOD: u32 = 98;
+    pub const ORD_REJ_REASON: u32 = 103;
+    pub const HEART_BT_INT: u32 = 108;
+    pub const TEST_REQ_ID: u32 = 112;
+    pub const ORIG_SENDING_TIME: u32 = 122;
+    pub const GAP_FILL_FLAG: u32 = 123;
+    pub const RESET_SEQ_NUM_FLAG: u32 = 141;
+    pub const EXEC_TYPE: u32 = 150;
+    pub const LEAVES_QTY: u32 = 151;
+    pub const PUT_OR_CALL: u32 = 201;
+    pub const STRIKE_PRICE: u32 = 202;
+    pub const REF_MSG_TYPE: u32 = 372;
+    pub const SESSION_REJECT_REASON: u32 = 373;
+    pub const BUSINESS_REJECT_REASON: u32 = 380;
+    pub const MATURITY_DATE: u32 = 541;
+    pub const PASSWORD: u32 = 554;
+    pub const MATURITY_TIME: u32 = 1079;
+}
+
+/// Message types used by the gateway
+pub mod msg_type {
+    pub const HEARTBEAT: &str = "0";
+    pub const TEST_REQUEST: &str = "1";
+    pub const RESEND_REQUEST: &str = "2";
+    pub const REJECT: &str = "3";
+    pub const SEQUENCE_RESET: &str = "4";
+    pub const LOGOUT: &str = "5";
+    pub const EXECUTION_REPORT: &str = "8";
+    pub const LOGON: &str = "A";
+    pub const NEW_ORDER_SINGLE: &str = "D";
+    pub const BUSINESS_MESSAGE_REJECT: &str = "j";
+
+    /// Session-level messages are gap filled rather than resent
+    pub fn is_admin(msg_type: &str) -> bool {
+        matches!(msg_type, HEARTBEAT | TEST_REQUEST | RESEND_REQUEST | REJECT | SEQUENCE_RESET | LOGOUT | LOGON)
+    }
+}
+
+#[derive(Debug)]
+pub enum FixError {
+    Garbled(String),
+    Io(std::io::Error),
+    Database(ApiError),
+}
+
+impl fmt::Display for FixError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FixError::Garbled(msg) => write!(f, "Garbled message: {}", msg),
+            FixError::Io(e) => write!(f, "Connection error: {}", e),
+            FixError::Database(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for FixError {}
+
+impl From<std::io::Error> for FixError {
+    fn from(err: std::io::Error) -> Self {
+        FixError::Io(err)
+    }
+}
+
+impl From<ApiError> for FixError {
+    fn from(err: ApiError) -> Self {
+        FixError::Database(err)
+    }
+}
+
+/// A FIX message as an ordered list of fields. BeginString, BodyLength and CheckSum are not
+/// kept; they are checked by `decode` and written by `encode`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Message {
+    fields: Vec<(u32, String)>,
+}
+
+impl Message {
+    pub fn new(msg_type: &str) -> Self {
+        Self { fields: vec![(tag::MSG_TYPE, msg_type.to_string())] }
+    }
+
+    pub fn with(mut self, tag: u32, value: impl ToString) -> Self {
+        self.fields.push((tag, value.to_string()));
+        self
+    }
+
+    pub fn get(&self, tag: u32) -> Option<&str> {
+        self.fields.iter().find(|(t, _)| *t == tag).map(|(_, v)| v.as_str())
+    }
+
+    pub fn msg_type(&self) -> &str {
+        self.get(tag::MSG_TYPE).unwrap_or_default()
+    }
+
+    pub fn seq_num(&self) -> Option<u64> {
+        self.get(tag::MSG_SEQ_NUM).and_then(|v| v.parse().ok())
+    }
+
+    fn flag(&self, tag: u32) -> bool {
+        self.get(tag) == Some("Y")
+    }
+
+    // Fields after the standard header, as stored for resends
+    fn body(&self) -> String {
+        self.fields
+            .iter()
+            .filter(|(t, _)| !is_header(*t))
+            .map(|(t, v)| format!("{}={}{}", t, v, SOH))
+            .collect()
+    }
+
+    fn from_body(msg_type: &str, body: &str) -> Self {
+        let mut message = Self::new(msg_type);
+        message.fields.extend(parse_fields(body));
+        message
+    }
+}
+
+fn is_header(tag: u32) -> bool {
+    matches!(
+        tag,
+        tag::MSG_TYPE
+            | tag::SENDER_COMP_ID
+            | tag::TARGET_COMP_ID
+            | tag::MSG_SEQ_NUM
+            | tag::SENDING_TIME
+            | tag::POSS_DUP_FLAG
+            | tag::ORIG_SENDING_TIME
+    )
+}
+
+fn parse_fields(body: &str) -> Vec<(u32, String)> {
+    body.split(SOH)
+        .filter(|field| !field.is_empty())
+        .filter_map(|field| {
+            let (tag, value) = field.split_once('=')?;
+            Some((tag.parse().ok()?, value.to_string()))
+        })
+        .collect()
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+/// SendingTime format, UTC with milliseconds
+pub fn sending_time() -> String {
+    Utc::now().format("%Y%m%d-%H:%M:%S%.3f").to_string()
+}
+
+/// Standard header of an outgoing message
+pub struct Header<'a> {
+    pub sender_comp_id: &'a str,
+    pub target_comp_id: &'a str,
+    pub seq_num: u64,
+    pub sending_time: &'a str,
+    pub orig_sending_time: Option<&'a str>,  // Set on resends, which are flagged PossDupFlag=Y
+}
+
+/// Encode `message` with `header`, adding BeginString, BodyLength and CheckSum
+pub fn encode(header: &Header, message: &Message) -> Vec<u8> {
+    let mut body = format!(
+        "35={}{soh}49={}{soh}56={}{soh}34={}{soh}52={}{soh}",
+        message.msg_type(),
+        header.sender_comp_id,
+        header.target_comp_id,
+        header.seq_num,
+        header.sending_time,
+        soh = SOH
+    );
+    if let Some(orig) = header.orig_sending_time {
+        body.push_str(&format!("43=Y{soh}122={}{soh}", orig, soh = SOH));
+    }
+    body.push_str(&message.body());
+
+    let mut out = format!("8={}{soh}9={}{soh}{}", BEGIN_STRING, body.len(), body, soh = SOH).into_bytes();
+    let sum = checksum(&out);
+    out.extend_from_slice(format!("10={:03}{}", sum, SOH).as_bytes());
+    out
+}
+
+/// Decode the first message in `buf`: None until a whole message has arrived, otherwise the
+/// message and the number of bytes it took
+pub fn decode(buf: &[u8]) -> Result<Option<(Message, usize)>, FixError> {
+    let prefix = format!("8={}{}9=", BEGIN_STRING, SOH);
+    let compared = buf.len().min(prefix.len());
+    if buf[..compared] != prefix.as_bytes()[..compared] {
+        return Err(FixError::Garbled(format!("expected {} BeginString", BEGIN_STRING)));
+    }
+    let Some(length_end) = buf[compared..].iter().position(|b| *b == SOH as u8).map(|i| compared + i) else {
+        if buf.len() > prefix.len() + 10 {
+            return Err(FixError::Garbled("BodyLength too long".to_string()));
+        }
+        return Ok(None);
+    };
+    let body_length: usize = std::str::from_utf8(&buf[prefix.len()..length_end])
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|len| *len <= MAX_MESSAGE_LEN)
+        .ok_or_else(|| FixError::Garbled("invalid BodyLength".to_string()))?;
+
+    let body_end = length_end + 1 + body_length;
+    let total = body_end + 7;  // 10=nnn<SOH>
+    if buf.len() < total {
+        return Ok(None);
+    }
+    let trailer = &buf[body_end..total];
+    let expected = format!("10={:03}{}", checksum(&buf[..body_end]), SOH);
+    if trailer != expected.as_bytes() {
+        return Err(FixError::Garbled(format!(
+            "CheckSum mismatch, expected {}",
+            expected.trim_end_matches(SOH)
+        )));
+    }
+
+    let body = std::str::from_utf8(&buf[length_end + 1..body_end])
+        .map_err(|_| FixError::Garbled("body is not UTF-8".to_string()))?;
+    let fields = parse_fields(body);
+    if fields.first().map(|(t, _)| *t) != Some(tag::MSG_TYPE) {
+        return Err(FixError::Garbled("MsgType must follow BodyLength".to_string()));
+    }
+    Ok(Some((Message { fields }, total)))
+}
+
+// ---------------------------------------------------------------------------
+// Session persistence
+// ---------------------------------------------------------------------------
+
+/// Next sequence numbers of a session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionSeq {
+    pub next_in: u64,
+    pub next_out: u64,
+}
+
+impl Default for SessionSeq {
+    fn default() -> Self {
+        Self { next_in: 1, next_out: 1 }
+    }
+}
+
+pub fn load_session(conn: &Connection, sender_comp_id: &str, target_comp_id: &str) -> rusqlite::Result<SessionSeq> {
+    Ok(conn
+        .query_row(
+            "SELECT next_in_seq, next_out_seq FROM fix_sessions WHERE sender_comp_id = ?1 AND target_comp_id = ?2",
+            params![sender_comp_id, target_comp_id],
+            |row| Ok(SessionSeq { next_in: row.get::<_, i64>(0)? as u64, next_out: row.get::<_, i64>(1)? as u64 }),
+        )
+        .optional()?
+        .unwrap_or_default())
+}
+
+pub fn save_session(conn: &Connection, sender_comp_id: &str, target_comp_id: &str, seq: SessionSeq) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO fix_sessions (sender_comp_id, target_comp_id, next_in_seq, next_out_seq, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5)
+         ON CONFLICT (sender_comp_id, target_comp_id)
+         DO UPDATE SET next_in_seq = ?3, next_out_seq = ?4, updated_at = ?5",
+        params![sender_comp_id, target_comp_id, seq.next_in as i64, seq.next_out as i64, Utc::now().timestamp()],
+    )?;
+    Ok(())
+}
+
+/// Start the session over at 1/1, forgetting the messages sent on it
+pub fn reset_session(conn: &Connection, sender_comp_id: &str, target_comp_id: &str) -> rusqlite::Result<()> {
+    conn.execute(
+        "DELETE FROM fix_messages
+         WHERE (sender_comp_id = ?1 AND target_comp_id = ?2) OR (sender_comp_id = ?2 AND target_comp_id = ?1)",
+        params![sender_comp_id, target_comp_id],
+    )?;
+    save_session(conn, sender_comp_id, target_comp_id, SessionSeq::default())
+}
+
+/// Record application message `seq_num` sent on a session
+pub fn store_message(
+    conn: &Connection,
+    sender_comp_id: &str,
+    target_comp_id: &str,
+    seq_num: u64,
+    message: &Message,
+    sent_at: &str,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO fix_messages (sender_comp_id, target_comp_id, seq_num, msg_type, body, sent_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![sender_comp_id, target_comp_id, seq_num as i64, message.msg_type(), message.body(), sent_at],
+    )?;
+    Ok(())
+}
+
+/// Application messages sent on a session with sequence numbers in [from, to], in order
+pub fn stored_messages(
+    conn: &Connection,
+    sender_comp_id: &str,
+    target_comp_id: &str,
+    from: u64,
+    to: u64,
+) -> rusqlite::Result<Vec<(u64, Message, String)>> {
+    let mut stmt = conn.prepare(
+        "SELECT seq_num, msg_type, body, sent_at FROM fix_messages
+         WHERE sender_comp_id = ?1 AND target_comp_id = ?2 AND seq_num BETWEEN ?3 AND ?4
+         ORDER BY seq_num",
+    )?;
+    let rows = stmt.query_map(params![sender_comp_id, target_comp_id, from as i64, to as i64], |row| {
+        let msg_type: String = row.get(1)?;
+        let body: String = row.get(2)?;
+        Ok((row.get::<_, i64>(0)? as u64, Message::from_body(&msg_type, &body), row.get(3)?))
+    })?;
+    rows.collect()
+}
+
+// ---------------------------------------------------------------------------
+// Orders
+// ---------------------------------------------------------------------------
+
+/// The option bought by a NewOrderSingle
+#[derive(Debug, Clone)]
+pub struct Order {
+    pub cl_ord_id: String,
+    pub contract: Contract,
+    pub premium_currency: QuoteCurrency,
+}
+
+/// Read an order: Symbol is the underlying, Side must be Buy (1), OrdType Limit (2) with the
+/// premium per unit as Price in Currency (BTC by default), PutOrCall 0 or 1, StrikePrice in
+/// USD, and MaturityDate (YYYYMMDD) with MaturityTime (HH:MM:SS UTC, 08:00:00 by default)
+pub fn parse_order(message: &Message) -> Result<Order, String> {
+    let required = |tag: u32, name: &str| message.get(tag).ok_or_else(|| format!("{} ({}) is required", name, tag));
+    let number = |tag: u32, name: &str| {
+        required(tag, name)?.parse::<f64>().ok().filter(|v| v.is_finite()).ok_or_else(|| format!("{} ({}) must be a number", name, tag))
+    };
+
+    let cl_ord_id = required(tag::CL_ORD_ID, "ClOrdID")?.to_string();
+    let symbol = required(tag::SYMBOL, "Symbol")?;
+    let underlying: Asset = symbol.parse().map_err(|_| format!("unknown Symbol {}", symbol))?;
+    if required(tag::SIDE, "Side")? != "1" {
+        return Err("only Buy orders (Side=1) are accepted".to_string());
+    }
+    if required(tag::ORD_TYPE, "OrdType")? != "2" {
+        return Err("only Limit orders (OrdType=2) are accepted".to_string());
+    }
+    let side = match required(tag::PUT_OR_CALL, "PutOrCall")? {
+        "0" => OptionSide::Put,
+        "1" => OptionSide::Call,
+        other => return Err(format!("invalid PutOrCall {}", other)),
+    };
+    let quantity = number(tag::ORDER_QTY, "OrderQty")?;
+    let premium = number(tag::PRICE, "Price")?;
+    let strike_price = number(tag::STRIKE_PRICE, "StrikePrice")?;
+    if quantity <= 0.0 || premium < 0.0 || strike_price <= 0.0 {
+        return Err("OrderQty and StrikePrice must be positive and Price must not be negative".to_string());
+    }
+    let premium_currency = match message.get(tag::CURRENCY) {
+        Some(currency) => currency.parse().map_err(|_| format!("unsupported Currency {}", currency))?,
+        None => QuoteCurrency::Btc,
+    };
+
+    let date = NaiveDate::parse_from_str(required(tag::MATURITY_DATE, "MaturityDate")?, "%Y%m%d")
+        .map_err(|_| "MaturityDate must be YYYYMMDD".to_string())?;
+    let time = match message.get(tag::MATURITY_TIME) {
+        Some(time) => NaiveTime::parse_from_str(time, "%H:%M:%S").map_err(|_| "MaturityTime must be HH:MM:SS".to_string())?,
+        None => NaiveTime::from_hms_opt(8, 0, 0).unwrap_or_default(),
+    };
+    let expires = date.and_time(time).and_utc().timestamp();
+
+    Ok(Order {
+        cl_ord_id,
+        contract: Contract { underlying, side, strike_price, quantity, expires, premium },
+        premium_currency,
+    })
+}
+
+fn execution_report(message: &Message, exec_id: String, exec_type: &str, ord_status: &str) -> Message {
+    let mut report = Message::new(msg_type::EXECUTION_REPORT)
+        .with(tag::ORDER_ID, "NONE")
+        .with(tag::CL_ORD_ID, message.get(tag::CL_ORD_ID).unwrap_or("NONE"))
+        .with(tag::EXEC_ID, exec_id)
+        .with(tag::EXEC_TYPE, exec_type)
+        .with(tag::ORD_STATUS, ord_status);
+    for tag in [tag::SYMBOL, tag::SIDE, tag::ORDER_QTY, tag::PUT_OR_CALL, tag::STRIKE_PRICE, tag::MATURITY_DATE] {
+        if let Some(value) = message.get(tag) {
+            report = report.with(tag, value);
+        }
+    }
+    report
+}
+
+fn set(report: &mut Message, tag: u32, value: impl ToString) {
+    match report.fields.iter_mut().find(|(t, _)| *t == tag) {
+        Some(field) => field.1 = value.to_string(),
+        None => report.fields.push((tag, value.to_string())),
+    }
+}
+
+/// ExecutionReport for a contract created from `order`: filled, or new until its premium is paid
+pub fn order_accepted(message: &Message, order: &Order, contract_id: i64, payment: Option<&PremiumPayment>) -> Message {
+    let quantity = format!("{:.8}", order.contract.quantity);
+    let price = order.premium_currency.format(order.contract.premium);
+    let mut report = match payment {
+        None => execution_report(message, format!("{}-F", contract_id), "F", "2")
+            .with(tag::LAST_QTY, &quantity)
+            .with(tag::LAST_PX, &price)
+            .with(tag::CUM_QTY, &quantity)
+            .with(tag::LEAVES_QTY, "0")
+            .with(tag::AVG_PX, &price),
+        Some(payment) => execution_report(message, format!("{}-0", contract_id), "0", "0")
+            .with(tag::CUM_QTY, "0")
+            .with(tag::LEAVES_QTY, &quantity)
+            .with(tag::AVG_PX, "0")
+            .with(
+                tag::TEXT,
+                match (&payment.address, &payment.invoice) {
+                    (_, Some(invoice)) => format!("Awaiting premium payment of {} sats: {}", payment.amount_sats, invoice),
+                    (Some(address), _) => format!("Awaiting premium payment of {} sats to {}", payment.amount_sats, address),
+                    _ => format!("Awaiting premium payment of {} sats", payment.amount_sats),
+                },
+            ),
+    };
+    set(&mut report, tag::ORDER_ID, contract_id);
+    report
+}
+
+/// Rejection ExecutionReport, with the error code and reason in Text
+pub fn order_rejected(message: &Message, reason: &str) -> Message {
+    let quantity = message.get(tag::ORDER_QTY).unwrap_or("0").to_string();
+    execution_report(message, format!("R{:016x}", rand::random::<u64>()), "8", "8")
+        .with(tag::ORD_REJ_REASON, "99")
+        .with(tag::CUM_QTY, "0")
+        .with(tag::LEAVES_QTY, quantity)
+        .with(tag::AVG_PX, "0")
+        .with(tag::TEXT, reason)
+}
+
+fn rejection_text(error: &ApiError) -> String {
+    let code = serde_json::to_value(error.code()).ok().and_then(|v| v.as_str().map(str::to_string)).unwrap_or_default();
+    format!("{}: {}", code, error)
+}
+
+// ---------------------------------------------------------------------------
+// Acceptor
+// ---------------------------------------------------------------------------
+
+struct Gateway {
+    config: FixConfig,
+    repository: Repository,
+    state: Arc<AppState>,
+}
+
+/// Run the FIX acceptor as the supervised task "fix_gateway", if FIX_LISTEN_ADDR is set
+pub fn start_fix_gateway(supervisor: &Supervisor, config: FixConfig, repository: Repository, state: Arc<AppState>) {
+    let Some(listen_addr) = config.listen_addr.clone() else {
+        return;
+    };
+    let gateway = Arc::new(Gateway { config, repository, state });
+    supervisor.spawn("fix_gateway", move || {
+        let (gateway, listen_addr) = (gateway.clone(), listen_addr.clone());
+        async move {
+            match TcpListener::bind(&listen_addr).await {
+                Ok(listener) => {
+                    println!("📠 FIX gateway listening on {} as {}", listen_addr, gateway.config.comp_id);
+                    accept_loop(listener, gateway).await
+                }
+                Err(e) => eprintln!("Failed to bind FIX gateway to {}: {}", listen_addr, e),
+            }
+        }
+    });
+}
+
+/// Serve FIX sessions on `listener` until it fails
+pub async fn serve(listener: TcpListener, config: FixConfig, repository: Repository, state: Arc<AppState>) {
+    accept_loop(listener, Arc::new(Gateway { config, repository, state })).await
+}
+
+async fn accept_loop(listener: TcpListener, gateway: Arc<Gateway>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                let gateway = gateway.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = Session::run(stream, gateway).await {
+                        eprintln!("FIX session from {} ended: {}", peer, e);
+                    }
+                });
+            }
+            Err(e) => {
+                eprintln!("FIX gateway failed to accept a connection: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+struct Session {
+    stream: TcpStream,
+    buf: Vec<u8>,
+    gateway: Arc<Gateway>,
+    counterparty: String,  // Their CompID
+    actor: String,         // API key name the session's trades are recorded under
+    seq: SessionSeq,
+    heartbeat: Duration,
+    last_sent: Instant,
+    last_received: Instant,
+    test_request_sent: bool,
+    resend_requested: bool,
+}
+
+enum Next {
+    Continue,
+    Disconnect,
+}
+
+impl Session {
+    async fn run(mut stream: TcpStream, gateway: Arc<Gateway>) -> Result<(), FixError> {
+        let mut buf = Vec::new();
+        let logon = match tokio::time::timeout(LOGON_TIMEOUT, read_message(&mut stream, &mut buf)).await {
+            Ok(Ok(Some(message))) => message,
+            Ok(Ok(None)) | Err(_) => return Ok(()),
+            Ok(Err(e)) => return Err(e),
+        };
+        let Some(mut session) = Self::logon(stream, buf, gateway, logon).await? else {
+            return Ok(());
+        };
+        session.serve().await
+    }
+
+    // Check the Logon and answer it; None when the session is refused
+    async fn logon(stream: TcpStream, buf: Vec<u8>, gateway: Arc<Gateway>, logon: Message) -> Result<Option<Self>, FixError> {
+        let counterparty = logon.get(tag::SENDER_COMP_ID).unwrap_or_default().to_string();
+        let mut session = Session {
+            stream,
+            buf,
+            counterparty,
+            actor: String::new(),
+            seq: SessionSeq::default(),
+            heartbeat: Duration::from_secs(30),
+            last_sent: Instant::now(),
+            last_received: Instant::now(),
+            test_request_sent: false,
+            resend_requested: false,
+            gateway,
+        };
+
+        if logon.msg_type() != msg_type::LOGON {
+            eprintln!("FIX connection sent {} before Logon, closing", logon.msg_type());
+            return Ok(None);
+        }
+        if session.counterparty.is_empty() || logon.get(tag::TARGET_COMP_ID) != Some(session.gateway.config.comp_id.as_str()) {
+            eprintln!("FIX Logon for unknown CompIDs, closing");
+            return Ok(None);
+        }
+        let (sender, target) = (session.counterparty.clone(), session.gateway.config.comp_id.clone());
+        let reset = logon.flag(tag::RESET_SEQ_NUM_FLAG);
+        session.seq = session
+            .gateway
+            .repository
+            .run(move |conn| {
+                if reset {
+                    reset_session(conn, &sender, &target)?;
+                }
+                Ok(load_session(conn, &sender, &target)?)
+            })
+            .await?;
+
+        let heartbeat = logon.get(tag::HEART_BT_INT).and_then(|v| v.parse::<u64>().ok());
+        let refusal = match heartbeat {
+            _ if logon.get(tag::ENCRYPT_METHOD).unwrap_or("0") != "0" => Some("EncryptMethod must be 0".to_string()),
+            Some(secs) if (1..=MAX_HEARTBEAT_SECS).contains(&secs) => None,
+            _ => Some(format!("HeartBtInt must be between 1 and {}", MAX_HEARTBEAT_SECS)),
+        };
+        let key_actor = session
+            .gateway
+            .repository
+            .api_key_actor(logon.get(tag::PASSWORD).map(str::to_string))
+            .await?;
+        let actor = session.gateway.state.auth().actor(key_actor);
+        let refusal = refusal
+            .or_else(|| actor.is_none().then(|| "invalid Password".to_string()))
+            .or_else(|| match logon.seq_num() {
+                Some(seq) if seq >= session.seq.next_in => None,
+                _ => Some(format!("MsgSeqNum too low, expecting {}", session.seq.next_in)),
+            });
+        if let Some(text) = refusal {
+            eprintln!("FIX Logon from {} refused: {}", session.counterparty, text);
+            session.send(Message::new(msg_type::LOGOUT).with(tag::TEXT, text)).await?;
+            return Ok(None);
+        }
+
+        session.actor = actor.unwrap_or_default();
+        session.heartbeat = Duration::from_secs(heartbeat.unwrap_or(30));
+        let mut reply = Message::new(msg_type::LOGON)
+            .with(tag::ENCRYPT_METHOD, 0)
+            .with(tag::HEART_BT_INT, session.heartbeat.as_secs());
+        if reset {
+            reply = reply.with(tag::RESET_SEQ_NUM_FLAG, "Y");
+        }
+        session.send(reply).await?;
+        println!("📠 FIX session {} logged on as {}", session.counterparty, session.actor);
+        session.check_seq(&logon).await?;
+        Ok(Some(session))
+    }
+
+    async fn serve(&mut self) -> Result<(), FixError> {
+        let mut ticker = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            tokio::select! {
+                message = read_message(&mut self.stream, &mut self.buf) => {
+                    let Some(message) = message? else {
+                        return Ok(());
+                    };
+                    self.last_received = Instant::now();
+                    self.test_request_sent = false;
+                    if let Next::Disconnect = self.handle(message).await? {
+                        return Ok(());
+                    }
+                }
+                _ = ticker.tick() => {
+                    if self.last_received.elapsed() >= self.heartbeat * 2 {
+                        eprintln!("FIX session {} timed out", self.counterparty);
+                        self.send(Message::new(msg_type::LOGOUT).with(tag::TEXT, "Heartbeat timeout")).await?;
+                        return Ok(());
+                    }
+                    if !self.test_request_sent && self.last_received.elapsed() >= self.heartbeat + self.heartbeat / 5 {
+                        self.test_request_sent = true;
+                        self.send(Message::new(msg_type::TEST_REQUEST).with(tag::TEST_REQ_ID, sending_time())).await?;
+                    } else if self.last_sent.elapsed() >= self.heartbeat {
+                        self.send(Message::new(msg_type::HEARTBEAT)).await?;
+                    }
+                }
+            }
+        }
+    }
+
+    // Whether `message` is the next one expected; asks for a resend when messages are missing
+    async fn check_seq(&mut self, message: &Message) -> Result<bool, FixError> {
+        let seq = message.seq_num().unwrap_or_default();
+        if seq > self.seq.next_in {
+            if !self.resend_requested {
+                self.resend_requested = true;
+                let request = Message::new(msg_type::RESEND_REQUEST)
+                    .with(tag::BEGIN_SEQ_NO, self.seq.next_in)
+                    .with(tag::END_SEQ_NO, 0);
+                self.send(request).await?;
+            }
+            return Ok(false);
+        }
+        self.resend_requested = false;
+        self.seq.next_in = seq + 1;
+        self.save().await?;
+        Ok(true)
+    }
+
+    async fn handle(&mut self, message: Message) -> Result<Next, FixError> {
+        if message.get(tag::SENDER_COMP_ID) != Some(self.counterparty.as_str())
+            || message.get(tag::TARGET_COMP_ID) != Some(self.gateway.config.comp_id.as_str())
+        {
+            self.session_reject(&message, 9, "CompID problem").await?;
+            self.send(Message::new(msg_type::LOGOUT).with(tag::TEXT, "CompID problem")).await?;
+            return Ok(Next::Disconnect);
+        }
+        let Some(seq) = message.seq_num() else {
+            self.send(Message::new(msg_type::LOGOUT).with(tag::TEXT, "MsgSeqNum missing")).await?;
+            return Ok(Next::Disconnect);
+        };
+
+        // A SequenceReset without GapFill moves the expected number whatever it is
+        if message.msg_type() == msg_type::SEQUENCE_RESET && !message.flag(tag::GAP_FILL_FLAG) {
+            return self.sequence_reset(&message).await;
+        }
+        if seq < self.seq.next_in {
+            if message.flag(tag::POSS_DUP_FLAG) {
+                return Ok(Next::Continue);
+            }
+            let text = format!("MsgSeqNum too low, expecting {} but received {}", self.seq.next_in, seq);
+            self.send(Message::new(msg_type::LOGOUT).with(tag::TEXT, text)).await?;
+            return Ok(Next::Disconnect);
+        }
+        // Resend requests are honoured even when they arrive ahead of a gap
+        if message.msg_type() == msg_type::RESEND_REQUEST {
+            self.resend(&message).await?;
+        }
+        if !self.check_seq(&message).await? {
+            return Ok(Next::Continue);
+        }
+
+        match message.msg_type() {
+            msg_type::HEARTBEAT | msg_type::RESEND_REQUEST => {}
+            msg_type::TEST_REQUEST => {
+                let test_req_id = message.get(tag::TEST_REQ_ID).unwrap_or_default().to_string();
+                self.send(Message::new(msg_type::HEARTBEAT).with(tag::TEST_REQ_ID, test_req_id)).await?;
+            }
+            msg_type::SEQUENCE_RESET => return self.sequence_reset(&message).await,
+            msg_type::REJECT => {
+                eprintln!("FIX session {} rejected our message {}: {}",
+                    self.counterparty, message.get(tag::REF_SEQ_NUM).unwrap_or("?"), message.get(tag::TEXT).unwrap_or(""));
+            }
+            msg_type::LOGOUT => {
+                self.send(Message::new(msg_type::LOGOUT)).await?;
+                println!("📠 FIX session {} logged out", self.counterparty);
+                return Ok(Next::Disconnect);
+            }
+            msg_type::LOGON => self.session_reject(&message, 5, "Already logged on").await?,
+            msg_type::NEW_ORDER_SINGLE => {
+                let report = self.new_order(&message).await;
+                self.send(report).await?;
+            }
+            other => {
+                let reject = Message::new(msg_type::BUSINESS_MESSAGE_REJECT)
+                    .with(tag::REF_SEQ_NUM, seq)
+                    .with(tag::REF_MSG_TYPE, other)
+                    .with(tag::BUSINESS_REJECT_REASON, 3)
+                    .with(tag::TEXT, "Unsupported message type");
+                self.send(reject).await?;
+            }
+        }
+        Ok(Next::Continue)
+    }
+
+    async fn sequence_reset(&mut self, message: &Message) -> Result<Next, FixError> {
+        match message.get(tag::NEW_SEQ_NO).and_then(|v| v.parse::<u64>().ok()) {
+            Some(new_seq) if new_seq >= self.seq.next_in => {
+                self.seq.next_in = new_seq;
+                self.resend_requested = false;
+                self.save().await?;
+            }
+            _ => self.session_reject(message, 5, "NewSeqNo must not decrease").await?,
+        }
+        Ok(Next::Continue)
+    }
+
+    // Replay stored ExecutionReports in the requested range, gap filling the rest
+    async fn resend(&mut self, request: &Message) -> Result<(), FixError> {
+        let last_sent = self.seq.next_out - 1;
+        let begin = request.get(tag::BEGIN_SEQ_NO).and_then(|v| v.parse::<u64>().ok()).unwrap_or(1).max(1);
+        let end = match request.get(tag::END_SEQ_NO).and_then(|v| v.parse::<u64>().ok()) {
+            Some(0) | None => last_sent,
+            Some(end) => end.min(last_sent),
+        };
+        if begin > end {
+            return Ok(());
+        }
+        let (sender, target) = (self.gateway.config.comp_id.clone(), self.counterparty.clone());
+        let stored = self
+            .gateway
+            .repository
+            .run(move |conn| Ok(stored_messages(conn, &sender, &target, begin, end)?))
+            .await?;
+
+        let mut next = begin;
+        for (seq, message, sent_at) in stored {
+            if seq > next {
+                self.gap_fill(next, seq).await?;
+            }
+            self.write(seq, &message, Some(&sent_at)).await?;
+            next = seq + 1;
+        }
+        if next <= end {
+            self.gap_fill(next, end + 1).await?;
+        }
+        Ok(())
+    }
+
+    async fn gap_fill(&mut self, seq: u64, new_seq: u64) -> Result<(), FixError> {
+        let fill = Message::new(msg_type::SEQUENCE_RESET)
+            .with(tag::GAP_FILL_FLAG, "Y")
+            .with(tag::NEW_SEQ_NO, new_seq);
+        self.write(seq, &fill, Some(&sending_time())).await
+    }
+
+    async fn session_reject(&mut self, message: &Message, reason: u32, text: &str) -> Result<(), FixError> {
+        let reject = Message::new(msg_type::REJECT)
+            .with(tag::REF_SEQ_NUM, message.seq_num().unwrap_or_default())
+            .with(tag::REF_MSG_TYPE, message.msg_type())
+            .with(tag::SESSION_REJECT_REASON, reason)
+            .with(tag::TEXT, text);
+        self.send(reject).await
+    }
+
+    // Trade a NewOrderSingle through the POST /contract flow
+    async fn new_order(&self, message: &Message) -> Message {
+        let order = match parse_order(message) {
+            Ok(order) => order,
+            Err(reason) => return order_rejected(message, &format!("VALIDATION_ERROR: {}", reason)),
+        };
+        println!("📠 FIX order {} from {}", order.cl_ord_id, self.counterparty);
+        let accepted = request_id::scope(
+            request_id::generate(),
+            api::accept_contract(
+                &self.gateway.state,
+                pools::DEFAULT_POOL_ID,
+                self.actor.clone(),
+                order.contract.clone(),
+                order.premium_currency,
+                ExerciseStyle::default(),
+                PaymentMethod::default(),
+                None,
+                None,
+            ),
+        )
+        .await;
+        match accepted {
+            Ok(accepted) => order_accepted(message, &order, accepted.contract_id, accepted.payment.as_ref()),
+            Err(e) => order_rejected(message, &rejection_text(&e)),
+        }
+    }
+
+    // Send `message` with the next sequence number, storing it for resends
+    async fn send(&mut self, message: Message) -> Result<(), FixError> {
+        let seq = self.seq.next_out;
+        let sent_at = sending_time();
+        self.seq.next_out += 1;
+        let (sender, target, seq_state) = (self.gateway.config.comp_id.clone(), self.counterparty.clone(), self.seq);
+        let (counterparty, stored) = (self.counterparty.clone(), message.clone());
+        let stored_at = sent_at.clone();
+        self.gateway
+            .repository
+            .run(move |conn| {
+                if !msg_type::is_admin(stored.msg_type()) {
+                    store_message(conn, &sender, &target, seq, &stored, &stored_at)?;
+                }
+                Ok(save_session(conn, &counterparty, &sender, seq_state)?)
+            })
+            .await?;
+        self.write_at(seq, &message, &sent_at, None).await
+    }
+
+    async fn write(&mut self, seq: u64, message: &Message, orig_sending_time: Option<&str>) -> Result<(), FixError> {
+        self.write_at(seq, message, &sending_time(), orig_sending_time).await
+    }
+
+    async fn write_at(&mut self, seq: u64, message: &Message, sent_at: &str, orig: Option<&str>) -> Result<(), FixError> {
+        let header = Header {
+            sender_comp_id: &self.gateway.config.comp_id,
+            target_comp_id: &self.counterparty,
+            seq_num: seq,
+            sending_time: sent_at,
+            orig_sending_time: orig,
+        };
+        let bytes = encode(&header, message);
+        self.stream.write_all(&bytes).await?;
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+
+    async fn save(&self) -> Result<(), FixError> {
+        let (sender, target, seq) = (self.counterparty.clone(), self.gateway.config.comp_id.clone(), self.seq);
+        self.gateway.repository.run(move |conn| Ok(save_session(conn, &sender, &target, seq)?)).await?;
+        Ok(())
+    }
+}
+
+// Read the next message from `stream`, None once it is closed
+async fn read_message(stream: &mut TcpStream, buf: &mut Vec<u8>) -> Result<Option<Message>, FixError> {
+    loop {
+        if !buf.is_empty() {
+            if let Some((message, used)) = decode(buf)? {
+                buf.drain(..used);
+                return Ok(Some(message));
+            }
+        }
+        let mut chunk = [0u8; 4096];
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(seq_num: u64) -> Header<'static> {
+        Header {
+            sender_comp_id: "TAKER",
+            target_comp_id: "BTCOPTIONS",
+            seq_num,
+            sending_time: "20250101-08:00:00.000",
+            orig_sending_time: None,
+        }
+    }
+
+    fn order() -> Message {
+        Message::new(msg_type::NEW_ORDER_SINGLE)
+            .with(tag::CL_ORD_ID, "ord-1")
+            .with(tag::SYMBOL, "BTC")
+            .with(tag::SIDE, "1")
+            .with(tag::ORDER_QTY, "0.5")
+            .with(tag::ORD_TYPE, "2")
+            .with(tag::PRICE, "0.01")
+            .with(tag::PUT_OR_CALL, "1")
+            .with(tag::STRIKE_PRICE, "105000")
+            .with(tag::MATURITY_DATE, "20300101")
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let bytes = encode(&header(7), &Message::new(msg_type::HEARTBEAT).with(tag::TEST_REQ_ID, "ping"));
+        let text = String::from_utf8(bytes.clone()).unwrap().replace(SOH, "|");
+        assert_eq!(
+            text,
+            "8=FIX.4.4|9=67|35=0|49=TAKER|56=BTCOPTIONS|34=7|52=20250101-08:00:00.000|112=ping|10=190|"
+        );
+
+        // Partial input waits for the rest; trailing bytes are left for the next message
+        assert!(decode(&bytes[..bytes.len() - 3]).unwrap().is_none());
+        let mut two = bytes.clone();
+        two.extend_from_slice(&bytes[..10]);
+        let (message, used) = decode(&two).unwrap().unwrap();
+        assert_eq!(used, bytes.len());
+        assert_eq!(message.msg_type(), msg_type::HEARTBEAT);
+        assert_eq!(message.seq_num(), Some(7));
+        assert_eq!(message.get(tag::TEST_REQ_ID), Some("ping"));
+
+        let mut corrupted = bytes.clone();
+        corrupted[30] = b'X';
+        assert!(matches!(decode(&corrupted), Err(FixError::Garbled(_))));
+        assert!(matches!(decode(b"8=FIX.4.2\x019=5\x01"), Err(FixError::Garbled(_))));
+    }
+
+    #[test]
+    fn test_resend_keeps_original_sending_time() {
+        let header = Header { orig_sending_time: Some("20250101-07:59:00.000"), ..header(3) };
+        let (message, _) = decode(&encode(&header, &order())).unwrap().unwrap();
+        assert!(message.flag(tag::POSS_DUP_FLAG));
+        assert_eq!(message.get(tag::ORIG_SENDING_TIME), Some("20250101-07:59:00.000"));
+        assert_eq!(Message::from_body(message.msg_type(), &message.body()), order());
+    }
+
+    #[test]
+    fn test_parse_order() {
+        let parsed = parse_order(&order()).unwrap();
+        assert_eq!(parsed.cl_ord_id, "ord-1");
+        assert_eq!(parsed.premium_currency, QuoteCurrency::Btc);
+        assert_eq!(parsed.contract.side, OptionSide::Call);
+        assert_eq!(parsed.contract.quantity, 0.5);
+        assert_eq!(parsed.contract.strike_price, 105_000.0);
+        // 2030-01-01 08:00 UTC
+        assert_eq!(parsed.contract.expires, 1_893_484_800);
+
+        let mut sell = order();
+        set(&mut sell, tag::SIDE, "2");
+        assert_eq!(parse_order(&sell).unwrap_err(), "only Buy orders (Side=1) are accepted");
+        let mut no_strike = order();
+        no_strike.fields.retain(|(t, _)| *t != tag::STRIKE_PRICE);
+        assert_eq!(parse_order(&no_strike).unwrap_err(), "StrikePrice (202) is required");
+    }
+
+    #[test]
+    fn test_session_persistence() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init_db(&conn).unwrap();
+        assert_eq!(load_session(&conn, "TAKER", "BTCOPTIONS").unwrap(), SessionSeq::default());
+
+        save_session(&conn, "TAKER", "BTCOPTIONS", SessionSeq { next_in: 5, next_out: 9 }).unwrap();
+        store_message(&conn, "BTCOPTIONS", "TAKER", 4, &order(), "20250101-08:00:00.000").unwrap();
+        store_message(&conn, "BTCOPTIONS", "TAKER", 7, &order(), "20250101-08:00:01.000").unwrap();
+        assert_eq!(load_session(&conn, "TAKER", "BTCOPTIONS").unwrap(), SessionSeq { next_in: 5, next_out: 9 });
+        let stored = stored_messages(&conn, "BTCOPTIONS", "TAKER", 5, 8).unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].0, 7);
+        assert_eq!(stored[0].1, order());
+
+        reset_session(&conn, "TAKER", "BTCOPTIONS").unwrap();
+        assert_eq!(load_session(&conn, "TAKER", "BTCOPTIONS").unwrap(), SessionSeq::default());
+        assert!(stored_messages(&conn, "BTCOPTIONS", "TAKER", 1, 10).unwrap().is_empty());
+    }
+}
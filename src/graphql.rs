@@ -0,0 +1,162 @@
+// GraphQL API alongside REST.
+// POST /graphql answers queries over the same data as the REST endpoints: contracts, the
+// options table, the market analytics and the pool. Clients select the fields they need, and
+// only the selected parts are computed, so a dashboard can load in one round trip. GET
+// /graphql serves GraphiQL for exploring the schema. There are no mutations; trading stays
+// on REST. Errors carry the REST error code and details as extensions.
+
+use crate::api::{
+    self, AppState, ContractResponse, MarketHighlightItem, OptionsTableQuery, OptionsTableResponse,
+    TopBannerResponse, TopGainerItem, TopVolumeItem,
+};
+use crate::auth::Role;
+use crate::error::ApiError;
+use crate::models::{Asset, QuoteCurrency};
+use crate::rolling_metrics::MetricsWindow;
+use actix_web::{web, HttpRequest, HttpResponse};
+use async_graphql::http::GraphiQLSource;
+use async_graphql::{Context, EmptyMutation, EmptySubscription, ErrorExtensions, Object, ResultExt, Schema};
+use std::sync::{Arc, OnceLock};
+
+const MAX_DEPTH: usize = 8;
+
+pub type ApiSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+impl ErrorExtensions for ApiError {
+    fn extend(&self) -> async_graphql::Error {
+        let code = serde_json::to_value(self.code()).unwrap_or_default();
+        let details = serde_json::Value::Object(self.details());
+        async_graphql::Error::new(self.to_string()).extend_with(|_, extensions| {
+            for (key, value) in [("error_code", code), ("details", details)] {
+                if let Ok(value) = async_graphql::Value::from_json(value) {
+                    extensions.set(key, value);
+                }
+            }
+        })
+    }
+}
+
+fn state<'a>(ctx: &Context<'a>) -> &'a AppState {
+    ctx.data_unchecked::<Arc<AppState>>()
+}
+
+// Whether the request may read contracts, checked before the query runs as resolvers have
+// no access to the request
+struct ViewerAccess(Result<(), ApiError>);
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// All contracts, as GET /contracts; requires the viewer role
+    async fn contracts(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<ContractResponse>> {
+        if let Err(e) = &ctx.data_unchecked::<ViewerAccess>().0 {
+            return Err(e.extend());
+        }
+        api::list_contracts(state(ctx)).await.extend()
+    }
+
+    /// The options table of `asset` with server default strikes and tenors, as GET /optionsTable
+    async fn options_table(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default)] asset: Asset,
+        #[graphql(default)] premium_currency: QuoteCurrency,
+        tenors: Option<String>,
+    ) -> async_graphql::Result<Vec<OptionsTableResponse>> {
+        let query = OptionsTableQuery { asset, premium_currency, tenors, ..Default::default() };
+        let table = api::options_table(state(ctx), &query).await.extend()?;
+        // The table is cached and shared; each response gets its own copy
+        Ok(table.iter().cloned().collect())
+    }
+
+    /// Market analytics, for one underlying or all of them
+    async fn analytics(&self, asset: Option<Asset>) -> Analytics {
+        Analytics { asset }
+    }
+
+    async fn pool(&self) -> Pool {
+        Pool
+    }
+}
+
+pub struct Analytics {
+    asset: Option<Asset>,
+}
+
+#[Object]
+impl Analytics {
+    /// As GET /topBanner
+    async fn top_banner(&self, ctx: &Context<'_>) -> async_graphql::Result<TopBannerResponse> {
+        api::top_banner(state(ctx), self.asset).await.extend()
+    }
+
+    /// As GET /marketHighlights
+    async fn market_highlights(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<MarketHighlightItem>> {
+        api::market_highlights(state(ctx), self.asset).await.extend()
+    }
+
+    /// As GET /topGainers
+    async fn top_gainers(&self, ctx: &Context<'_>, window: Option<MetricsWindow>) -> async_graphql::Result<Vec<TopGainerItem>> {
+        api::top_gainers(state(ctx), self.asset, window.unwrap_or_default()).await.extend()
+    }
+
+    /// As GET /topLosers
+    async fn top_losers(&self, ctx: &Context<'_>, window: Option<MetricsWindow>) -> async_graphql::Result<Vec<TopGainerItem>> {
+        api::top_losers(state(ctx), self.asset, window.unwrap_or_default()).await.extend()
+    }
+
+    /// As GET /topVolume
+    async fn top_volume(&self, ctx: &Context<'_>, window: Option<MetricsWindow>) -> async_graphql::Result<Vec<TopVolumeItem>> {
+        api::top_volume(state(ctx), self.asset, window.unwrap_or_default()).await.extend()
+    }
+}
+
+pub struct Pool;
+
+#[Object]
+impl Pool {
+    /// Address holding the pool's collateral
+    async fn address<'a>(&self, ctx: &Context<'a>) -> &'a str {
+        state(ctx).pool_address()
+    }
+
+    async fn balance_btc(&self, ctx: &Context<'_>) -> async_graphql::Result<f64> {
+        state(ctx).get_pool_balance_btc().await.extend()
+    }
+
+    async fn balance_usd(&self, ctx: &Context<'_>) -> async_graphql::Result<f64> {
+        let state = state(ctx);
+        let balance_btc = state.get_pool_balance_btc().await.extend()?;
+        Ok(balance_btc * state.spot_price(Asset::Btc).await.extend()?)
+    }
+
+    async fn btc_price(&self, ctx: &Context<'_>) -> async_graphql::Result<f64> {
+        state(ctx).spot_price(Asset::Btc).await.extend()
+    }
+}
+
+/// The schema, built once. The AppState is passed with each request.
+pub fn schema() -> &'static ApiSchema {
+    static SCHEMA: OnceLock<ApiSchema> = OnceLock::new();
+    SCHEMA.get_or_init(|| Schema::build(QueryRoot, EmptyMutation, EmptySubscription).limit_depth(MAX_DEPTH).finish())
+}
+
+// POST /graphql - Execute a GraphQL query
+pub(crate) async fn post_graphql(
+    req: HttpRequest,
+    request: web::Json<async_graphql::Request>,
+    state: web::Data<Arc<AppState>>,
+) -> HttpResponse {
+    let access = ViewerAccess(api::require_role(&req, &state, Role::Viewer).await.map(|_| ()));
+    let request = request.into_inner().data(state.get_ref().clone()).data(access);
+    let response = schema().execute(request).await;
+    HttpResponse::Ok().json(response)
+}
+
+// GET /graphql - GraphiQL explorer
+pub(crate) async fn get_graphiql() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(GraphiQLSource::build().endpoint("/graphql").finish())
+}
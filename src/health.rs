@@ -0,0 +1,304 @@
+// Dependency health for GET /health.
+// Each dependency is probed with a timeout and reported as ok, degraded or down. The
+// database and the price oracle are critical: without them no contract can be priced or
+// recorded, so either being down makes the service unhealthy (503, taken out of rotation
+// by load balancers). Anything else short of ok leaves it degraded (200): reads still work,
+// and IV falls back to a default while the surface is missing. So does a background worker
+// waiting to be restarted by the supervisor. The exchange feeds behind the price are
+// reported one by one but don't change the status while the aggregate price is good.
+
+use crate::repository::Repository;
+use crate::models::Asset;
+use crate::sources::{IvSource, PriceSource, SourcePrice, WalletSource};
+use crate::supervisor::{TaskState, TaskStatus};
+use serde::Serialize;
+use chrono::Utc;
+use std::env;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::time::timeout;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HealthConfig {
+    pub probe_timeout: Duration,  // Longest a single probe may take
+    pub iv_max_age: Duration,     // Older IV surfaces are degraded
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            probe_timeout: Duration::from_secs(3),
+            iv_max_age: Duration::from_secs(120),
+        }
+    }
+}
+
+impl HealthConfig {
+    /// HEALTH_PROBE_TIMEOUT_SECS (3) and IV_MAX_AGE_SECS (120)
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let secs = |name: &str, default: Duration| {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .map(|secs: u64| Duration::from_secs(secs.max(1)))
+                .unwrap_or(default)
+        };
+        Self {
+            probe_timeout: secs("HEALTH_PROBE_TIMEOUT_SECS", defaults.probe_timeout),
+            iv_max_age: secs("IV_MAX_AGE_SECS", defaults.iv_max_age),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum DependencyStatus {
+    Ok,
+    Degraded,
+    Down,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OverallStatus {
+    Healthy,
+    Degraded,
+    Unhealthy,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct DatabaseHealth {
+    pub status: DependencyStatus,
+    pub latency_ms: u64,
+    pub connections: u32,
+    pub idle_connections: u32,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PriceOracleHealth {
+    pub status: DependencyStatus,
+    pub price_age_secs: Option<f64>,  // Since the last successful fetch
+    pub stale: bool,                  // Serving the last good price with every source down
+    pub data_points: Option<u32>,     // Behind the price
+    pub rejected_points: Option<u32>, // Dropped as too old or outlying
+    pub error: Option<String>,
+    pub sources: Vec<SourceHealth>,   // BTC price of each exchange feed
+}
+
+/// Liveness of one exchange feed behind the price
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SourceHealth {
+    #[serde(flatten)]
+    pub price: SourcePrice,
+    pub status: DependencyStatus,   // Ok when live, degraded when its latest price is older than the max age
+    pub age_secs: Option<i64>,
+}
+
+/// Judge each feed's latest price against `max_age`; feeds without a price are down
+pub fn source_health(prices: Vec<SourcePrice>, max_age: Duration, now: i64) -> Vec<SourceHealth> {
+    prices
+        .into_iter()
+        .map(|price| {
+            let age_secs = price.timestamp.filter(|_| price.price.is_some()).map(|timestamp| (now - timestamp).max(0));
+            let status = match age_secs {
+                Some(age) if age as u64 <= max_age.as_secs() => DependencyStatus::Ok,
+                Some(_) => DependencyStatus::Degraded,
+                None => DependencyStatus::Down,
+            };
+            SourceHealth { price, status, age_secs }
+        })
+        .collect()
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct IvOracleHealth {
+    pub status: DependencyStatus,
+    pub cache_size: usize,
+    pub age_secs: Option<f64>,  // Since the last refresh; None for sources that don't refresh
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct WalletHealth {
+    pub status: DependencyStatus,
+    pub latency_ms: u64,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Dependencies {
+    pub database: DatabaseHealth,
+    pub price_oracle: PriceOracleHealth,
+    pub iv_oracle: IvOracleHealth,
+    pub wallet: WalletHealth,
+}
+
+pub fn overall_status(dependencies: &Dependencies, tasks: &[TaskStatus]) -> OverallStatus {
+    let critical = dependencies.database.status.max(dependencies.price_oracle.status);
+    let other = dependencies.iv_oracle.status.max(dependencies.wallet.status);
+    if critical == DependencyStatus::Down {
+        OverallStatus::Unhealthy
+    } else if critical.max(other) != DependencyStatus::Ok || tasks.iter().any(|t| t.state != TaskState::Running) {
+        OverallStatus::Degraded
+    } else {
+        OverallStatus::Healthy
+    }
+}
+
+// Run `probe` within `limit`, timing it
+async fn timed<T, E: ToString>(
+    limit: Duration,
+    probe: impl Future<Output = Result<T, E>>,
+) -> (Result<T, String>, u64) {
+    let started = Instant::now();
+    let result = match timeout(limit, probe).await {
+        Ok(result) => result.map_err(|e| e.to_string()),
+        Err(_) => Err(format!("no response within {}s", limit.as_secs())),
+    };
+    (result, started.elapsed().as_millis() as u64)
+}
+
+pub async fn check_database(repository: &Repository, config: &HealthConfig) -> DatabaseHealth {
+    let (result, latency_ms) = timed(
+        config.probe_timeout,
+        repository.run(|conn| Ok(conn.query_row("SELECT 1", [], |row| row.get::<_, i64>(0))?)),
+    )
+    .await;
+    let pool_state = repository.pool().state();
+    DatabaseHealth {
+        status: if result.is_ok() { DependencyStatus::Ok } else { DependencyStatus::Down },
+        latency_ms,
+        connections: pool_state.connections,
+        idle_connections: pool_state.idle_connections,
+        error: result.err(),
+    }
+}
+
+/// Degraded when the price is stale or older than `max_age` (the price guard's), so trading refuses it
+pub async fn check_price_oracle(
+    price_source: &dyn PriceSource,
+    max_age: Duration,
+    config: &HealthConfig,
+) -> PriceOracleHealth {
+    let (quote, sources) = futures::join!(
+        timed(config.probe_timeout, price_source.get_price_quote()),
+        timed(config.probe_timeout, price_source.source_prices(Asset::Btc, &[])),
+    );
+    // A breakdown that can't be read leaves the list empty; the quote tells whether the price is down
+    let sources = source_health(sources.0.unwrap_or_default(), max_age, Utc::now().timestamp());
+    match quote.0 {
+        Ok(quote) => PriceOracleHealth {
+            status: if quote.stale || quote.age > max_age {
+                DependencyStatus::Degraded
+            } else {
+                DependencyStatus::Ok
+            },
+            price_age_secs: Some(quote.age.as_secs_f64()),
+            stale: quote.stale,
+            data_points: Some(quote.data_points),
+            rejected_points: Some(quote.rejected_points),
+            error: None,
+            sources,
+        },
+        Err(e) => PriceOracleHealth {
+            status: DependencyStatus::Down,
+            price_age_secs: None,
+            stale: price_source.is_stale(),
+            data_points: None,
+            rejected_points: None,
+            error: Some(e),
+            sources,
+        },
+    }
+}
+
+pub fn check_iv_oracle(iv_source: &dyn IvSource, config: &HealthConfig) -> IvOracleHealth {
+    let cache_size = iv_source.cache_size();
+    let age = iv_source.last_refresh_age();
+    IvOracleHealth {
+        status: if cache_size == 0 || age.is_some_and(|age| age > config.iv_max_age) {
+            DependencyStatus::Degraded
+        } else {
+            DependencyStatus::Ok
+        },
+        cache_size,
+        age_secs: age.map(|age| age.as_secs_f64()),
+    }
+}
+
+pub async fn check_wallet(wallet: &dyn WalletSource, address: &str, config: &HealthConfig) -> WalletHealth {
+    let (result, latency_ms) = timed(config.probe_timeout, wallet.get_wallet_balance(address)).await;
+    WalletHealth {
+        status: if result.is_ok() { DependencyStatus::Ok } else { DependencyStatus::Down },
+        latency_ms,
+        error: result.err(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dependencies(database: DependencyStatus, iv: DependencyStatus, wallet: DependencyStatus) -> Dependencies {
+        Dependencies {
+            database: DatabaseHealth { status: database, latency_ms: 1, connections: 1, idle_connections: 1, error: None },
+            price_oracle: PriceOracleHealth {
+                status: DependencyStatus::Ok,
+                price_age_secs: Some(1.0),
+                stale: false,
+                data_points: Some(3),
+                rejected_points: Some(0),
+                error: None,
+                sources: vec![],
+            },
+            iv_oracle: IvOracleHealth { status: iv, cache_size: 10, age_secs: None },
+            wallet: WalletHealth { status: wallet, latency_ms: 1, error: None },
+        }
+    }
+
+    #[test]
+    fn test_overall_status() {
+        use DependencyStatus::*;
+        assert_eq!(overall_status(&dependencies(Ok, Ok, Ok), &[]), OverallStatus::Healthy);
+        assert_eq!(overall_status(&dependencies(Ok, Degraded, Ok), &[]), OverallStatus::Degraded);
+        // Only the database and price oracle are critical
+        assert_eq!(overall_status(&dependencies(Ok, Ok, Down), &[]), OverallStatus::Degraded);
+        assert_eq!(overall_status(&dependencies(Down, Ok, Ok), &[]), OverallStatus::Unhealthy);
+
+        let restarting = TaskStatus {
+            name: "payment_watcher".to_string(),
+            state: TaskState::Restarting,
+            restarts: 1,
+            started_at: 1_800_000_000,
+            last_failure: Some("panicked: boom".to_string()),
+            last_failure_at: Some(1_800_000_010),
+        };
+        assert_eq!(overall_status(&dependencies(Ok, Ok, Ok), &[restarting]), OverallStatus::Degraded);
+    }
+
+    #[test]
+    fn test_source_health() {
+        let price = |source: &str, price: Option<f64>, timestamp: Option<i64>| SourcePrice {
+            source: source.to_string(),
+            via: "aggregator".to_string(),
+            price,
+            timestamp,
+            error: price.is_none().then(|| "timeout".to_string()),
+        };
+        let health = source_health(
+            vec![
+                price("binance", Some(100_000.0), Some(995)),
+                price("coinbase", Some(100_010.0), Some(900)),
+                price("kraken", None, None),
+            ],
+            Duration::from_secs(30),
+            1_000,
+        );
+        let statuses: Vec<_> = health.iter().map(|source| (source.status, source.age_secs)).collect();
+        assert_eq!(
+            statuses,
+            vec![(DependencyStatus::Ok, Some(5)), (DependencyStatus::Degraded, Some(100)), (DependencyStatus::Down, None)]
+        );
+    }
+}
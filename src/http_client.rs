@@ -0,0 +1,291 @@
+// Shared HTTP client for the REST dependencies (Deribit, the Mutiny/mempool API).
+// Every request has a timeout. Network errors, timeouts, 429 and 5xx responses are retried
+// with exponential backoff and jitter, honouring Retry-After. Calls that still fail count
+// against a circuit breaker per host; while it is open, requests to the host fail at once
+// instead of waiting out their timeouts. Requests made while serving an API request carry its
+// X-Request-Id.
+
+use crate::circuit_breaker::CircuitBreaker;
+use crate::request_id;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Client, Response, StatusCode, Url};
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HttpClientConfig {
+    pub timeout: Duration,          // Per attempt
+    pub max_retries: u32,
+    pub initial_backoff: Duration,  // Doubled after each attempt
+    pub max_backoff: Duration,
+    pub breaker_failures: u32,      // Failed calls in a row before a host is skipped
+    pub breaker_cooldown: Duration,
+}
+
+impl Default for HttpClientConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(250),
+            max_backoff: Duration::from_secs(5),
+            breaker_failures: 5,
+            breaker_cooldown: Duration::from_secs(30),
+        }
+    }
+}
+
+impl HttpClientConfig {
+    /// HTTP_TIMEOUT_SECS (10), HTTP_MAX_RETRIES (3), HTTP_RETRY_BACKOFF_MS (250),
+    /// HTTP_RETRY_MAX_BACKOFF_MS (5000), HTTP_BREAKER_FAILURES (5) and HTTP_BREAKER_COOLDOWN_SECS (30)
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let parse = |name: &str| env::var(name).ok().and_then(|v| v.parse::<u64>().ok());
+        Self {
+            timeout: parse("HTTP_TIMEOUT_SECS").map(|s| Duration::from_secs(s.max(1))).unwrap_or(defaults.timeout),
+            max_retries: parse("HTTP_MAX_RETRIES").map(|n| n as u32).unwrap_or(defaults.max_retries),
+            initial_backoff: parse("HTTP_RETRY_BACKOFF_MS").map(Duration::from_millis).unwrap_or(defaults.initial_backoff),
+            max_backoff: parse("HTTP_RETRY_MAX_BACKOFF_MS").map(Duration::from_millis).unwrap_or(defaults.max_backoff),
+            breaker_failures: parse("HTTP_BREAKER_FAILURES").map(|n| n as u32).unwrap_or(defaults.breaker_failures),
+            breaker_cooldown: parse("HTTP_BREAKER_COOLDOWN_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.breaker_cooldown),
+        }
+    }
+}
+
+/// Credentials sent as HTTP Basic auth, e.g. to a self-hosted explorer behind a proxy
+#[derive(Clone, PartialEq)]
+pub struct BasicAuth {
+    pub username: String,
+    pub password: Option<String>,
+}
+
+impl fmt::Debug for BasicAuth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BasicAuth").field("username", &self.username).finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug)]
+pub enum HttpError {
+    CircuitOpen(String),  // Host
+    Timeout(String),
+    Network(String),
+    Status { status: StatusCode, body: String },
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HttpError::CircuitOpen(host) => write!(f, "{} is failing, requests paused", host),
+            HttpError::Timeout(msg) => write!(f, "Request timed out: {}", msg),
+            HttpError::Network(msg) => write!(f, "Network error: {}", msg),
+            HttpError::Status { status, body } if body.is_empty() => write!(f, "API returned status: {}", status),
+            HttpError::Status { status, body } => write!(f, "API returned status: {}: {}", status, body),
+        }
+    }
+}
+
+impl Error for HttpError {}
+
+fn is_retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// Wait before retry `attempt` (0 for the first retry): exponential, with `jitter` in [0, 1]
+/// taking off up to half of it, but no shorter than the server's Retry-After
+pub fn backoff_delay(config: &HttpClientConfig, attempt: u32, jitter: f64, retry_after: Option<Duration>) -> Duration {
+    let exponential = config.initial_backoff.saturating_mul(2u32.saturating_pow(attempt)).min(config.max_backoff);
+    let delay = exponential.mul_f64(1.0 - jitter.clamp(0.0, 1.0) / 2.0);
+    delay.max(retry_after.unwrap_or_default()).min(config.max_backoff)
+}
+
+#[derive(Clone)]
+pub struct HttpClient {
+    client: Client,
+    config: HttpClientConfig,
+    breakers: Arc<Mutex<HashMap<String, Arc<CircuitBreaker>>>>,
+}
+
+impl Default for HttpClient {
+    fn default() -> Self {
+        Self::new(HttpClientConfig::default())
+    }
+}
+
+impl HttpClient {
+    pub fn new(config: HttpClientConfig) -> Self {
+        Self {
+            client: Client::new(),
+            config,
+            breakers: Arc::default(),
+        }
+    }
+
+    fn breaker(&self, host: &str) -> Arc<CircuitBreaker> {
+        let mut breakers = self.breakers.lock().unwrap();
+        breakers
+            .entry(host.to_string())
+            .or_insert_with(|| Arc::new(CircuitBreaker::new(self.config.breaker_failures, self.config.breaker_cooldown)))
+            .clone()
+    }
+
+    /// GET `url`, retrying transient failures. Only successful responses are returned.
+    pub async fn get(&self, url: &str) -> Result<Response, HttpError> {
+        self.get_with_auth(url, None).await
+    }
+
+    /// `get` with Basic auth credentials, if any
+    pub async fn get_with_auth(&self, url: &str, auth: Option<&BasicAuth>) -> Result<Response, HttpError> {
+        let host = Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+            .unwrap_or_default();
+        let breaker = self.breaker(&host);
+        if !breaker.allow() {
+            return Err(HttpError::CircuitOpen(host));
+        }
+
+        let mut attempt = 0;
+        loop {
+            let mut request = self.client.get(url).timeout(self.config.timeout);
+            if let Some(id) = request_id::current() {
+                request = request.header(request_id::HEADER, id);
+            }
+            if let Some(auth) = auth {
+                request = request.basic_auth(&auth.username, auth.password.as_ref());
+            }
+            let (error, retry_after) = match request.send().await {
+                Ok(response) if response.status().is_success() => {
+                    breaker.record_success();
+                    return Ok(response);
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    let retry_after = response
+                        .headers()
+                        .get(RETRY_AFTER)
+                        .and_then(|v| v.to_str().ok())
+                        .and_then(|v| v.trim().parse().ok())
+                        .map(Duration::from_secs);
+                    let body = response.text().await.unwrap_or_default();
+                    let error = HttpError::Status { status, body };
+                    if !is_retryable(status) {
+                        // The host answered; the request itself is at fault
+                        breaker.record_success();
+                        return Err(error);
+                    }
+                    (error, retry_after)
+                }
+                Err(e) if e.is_timeout() => (HttpError::Timeout(format!("{} after {:?}", host, self.config.timeout)), None),
+                Err(e) => (HttpError::Network(e.to_string()), None),
+            };
+
+            if attempt >= self.config.max_retries {
+                breaker.record_failure();
+                return Err(error);
+            }
+            tokio::time::sleep(backoff_delay(&self.config, attempt, rand::random(), retry_after)).await;
+            attempt += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    // Serve one canned response per connection, in order
+    fn serve(responses: Vec<&'static str>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = [0u8; 1024];
+                let _ = stream.read(&mut request);
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        format!("http://{}/", address)
+    }
+
+    const TOO_MANY: &str = "HTTP/1.1 429 Too Many Requests\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+    const UNAVAILABLE: &str = "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+    const NOT_FOUND: &str = "HTTP/1.1 404 Not Found\r\nContent-Length: 7\r\nConnection: close\r\n\r\nmissing";
+    const OK: &str = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok";
+
+    fn config() -> HttpClientConfig {
+        HttpClientConfig {
+            timeout: Duration::from_secs(2),
+            max_retries: 2,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(10),
+            breaker_failures: 2,
+            breaker_cooldown: Duration::from_secs(60),
+        }
+    }
+
+    #[test]
+    fn test_backoff_delay() {
+        let config = HttpClientConfig::default();
+        assert_eq!(backoff_delay(&config, 0, 0.0, None), Duration::from_millis(250));
+        assert_eq!(backoff_delay(&config, 2, 0.0, None), Duration::from_millis(1000));
+        assert_eq!(backoff_delay(&config, 2, 1.0, None), Duration::from_millis(500));
+        assert_eq!(backoff_delay(&config, 10, 0.0, None), Duration::from_secs(5));
+        assert_eq!(backoff_delay(&config, 0, 0.0, Some(Duration::from_secs(2))), Duration::from_secs(2));
+        assert_eq!(backoff_delay(&config, 0, 0.0, Some(Duration::from_secs(60))), Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_transient_failures_are_retried() {
+        let client = HttpClient::new(config());
+        let url = serve(vec![TOO_MANY, UNAVAILABLE, OK]);
+        let response = client.get(&url).await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "ok");
+
+        // Client errors are returned as they are
+        let url = serve(vec![NOT_FOUND]);
+        match client.get(&url).await {
+            Err(HttpError::Status { status, body }) => {
+                assert_eq!(status, StatusCode::NOT_FOUND);
+                assert_eq!(body, "missing");
+            }
+            other => panic!("expected a 404, got {:?}", other.map(|r| r.status())),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_id_is_forwarded() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let n = stream.read(&mut request).unwrap();
+            let _ = stream.write_all(OK.as_bytes());
+            String::from_utf8_lossy(&request[..n]).to_lowercase()
+        });
+
+        let client = HttpClient::new(config());
+        request_id::scope("req-42".to_string(), client.get(&url)).await.unwrap();
+        assert!(server.join().unwrap().contains("x-request-id: req-42"));
+    }
+
+    #[tokio::test]
+    async fn test_breaker_opens_for_failing_host() {
+        let client = HttpClient::new(HttpClientConfig { max_retries: 0, ..config() });
+        let url = serve(vec![UNAVAILABLE, UNAVAILABLE]);
+        assert!(matches!(client.get(&url).await, Err(HttpError::Status { .. })));
+        assert!(matches!(client.get(&url).await, Err(HttpError::Status { .. })));
+        // No third response is served: the breaker answers
+        assert!(matches!(client.get(&url).await, Err(HttpError::CircuitOpen(host)) if host == "127.0.0.1"));
+    }
+}
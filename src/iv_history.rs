@@ -0,0 +1,227 @@
+// Implied vol history of the product catalog.
+// Every IV_SNAPSHOT_INTERVAL_SECS a background job records the IV of each active listed
+// product, with where it came from, into iv_snapshots; snapshots older than
+// IV_SNAPSHOT_RETENTION_DAYS are pruned. GET /volMovers ranks products by how far their IV
+// moved over a window, next to the premium-based leaderboards. IVs the default IV policy
+// filled in are recorded but not compared, as they track no market. Each snapshot is also
+// published as a marks.snapshot event.
+
+use crate::catalog::{self, ProductStatus};
+use crate::error::ApiResult;
+use crate::iv_policy::IvResolver;
+use crate::models::{product_symbol, Asset, IvProvenance, OptionSide};
+use crate::outbox;
+use crate::repository::Repository;
+use crate::supervisor::Supervisor;
+use crate::utils::cents_to_usd;
+use crate::webhooks::MARKS_SNAPSHOT;
+use chrono::Utc;
+use rusqlite::{params, Connection};
+use std::env;
+use std::time::Duration;
+use tokio::time::interval;
+
+// Most products GET /volMovers lists
+pub const MAX_VOL_MOVERS: usize = 50;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IvHistoryConfig {
+    pub snapshot_interval: Duration,
+    pub retention: Duration,
+}
+
+impl Default for IvHistoryConfig {
+    fn default() -> Self {
+        Self {
+            snapshot_interval: Duration::from_secs(300),
+            retention: Duration::from_secs(30 * 86_400),
+        }
+    }
+}
+
+impl IvHistoryConfig {
+    /// IV_SNAPSHOT_INTERVAL_SECS (300) and IV_SNAPSHOT_RETENTION_DAYS (30, at least 7 so
+    /// every vol movers window has history)
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            snapshot_interval: env::var("IV_SNAPSHOT_INTERVAL_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .map(|secs: u64| Duration::from_secs(secs.max(60)))
+                .unwrap_or(defaults.snapshot_interval),
+            retention: env::var("IV_SNAPSHOT_RETENTION_DAYS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .map(|days: u64| Duration::from_secs(days.max(7) * 86_400))
+                .unwrap_or(defaults.retention),
+        }
+    }
+}
+
+/// Store the IV of a product observed at `timestamp`
+pub fn record(conn: &Connection, product_id: i64, timestamp: i64, iv: f64, source: IvProvenance) -> ApiResult<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO iv_snapshots (product_id, timestamp, iv, iv_source) VALUES (?1, ?2, ?3, ?4)",
+        params![product_id, timestamp, iv, source],
+    )?;
+    Ok(())
+}
+
+/// Delete the snapshots taken before `before`. Returns how many were.
+pub fn prune(conn: &Connection, before: i64) -> ApiResult<usize> {
+    Ok(conn.execute("DELETE FROM iv_snapshots WHERE timestamp < ?1", params![before])?)
+}
+
+/// Snapshot the IV of every active product at `now`. Returns the number of snapshots taken.
+pub async fn snapshot_products(repository: &Repository, ivs: &IvResolver, now: i64) -> ApiResult<usize> {
+    let products = repository
+        .run(|conn| catalog::load_products(conn, Some(ProductStatus::Active), None))
+        .await?;
+    let mut marks = Vec::new();
+    let snapshots: Vec<(i64, f64, IvProvenance)> = products
+        .iter()
+        .filter(|product| product.expires > now)
+        .map(|product| {
+            let quote = ivs.option_iv(product.underlying, product.side, product.strike_price, product.expires);
+            marks.push(serde_json::json!({
+                "product_symbol": product_symbol(product.underlying, &product.side, product.strike_price, product.expires),
+                "underlying": product.underlying,
+                "side": product.side,
+                "strike_price": product.strike_price,
+                "expires": product.expires,
+                "iv": quote.value,
+                "iv_source": quote.source,
+            }));
+            (product.id, quote.value, quote.source)
+        })
+        .collect();
+    repository
+        .run(move |conn| {
+            let tx = conn.unchecked_transaction()?;
+            for &(product_id, iv, source) in &snapshots {
+                record(&tx, product_id, now, iv, source)?;
+            }
+            if !marks.is_empty() {
+                outbox::record(&tx, MARKS_SNAPSHOT, None, &serde_json::json!({"timestamp": now, "marks": marks}))?;
+            }
+            tx.commit()?;
+            Ok(snapshots.len())
+        })
+        .await
+}
+
+/// IV move of one product over a window
+#[derive(Debug, Clone, PartialEq)]
+pub struct VolMove {
+    pub underlying: Asset,
+    pub side: OptionSide,
+    pub strike_price: f64,
+    pub expires: i64,
+    pub iv: f64,          // Latest snapshot
+    pub iv_before: f64,   // Last snapshot before the window, or the first in it
+    pub snapshot_at: i64, // Of the latest snapshot
+}
+
+impl VolMove {
+    /// Change in vol points, e.g. 0.05 for 50% to 55%
+    pub fn change(&self) -> f64 {
+        self.iv - self.iv_before
+    }
+}
+
+/// The `limit` active products whose IV moved the most, either way, between `since` and `now`
+pub fn vol_movers(conn: &Connection, now: i64, since: i64, asset: Option<Asset>, limit: usize) -> ApiResult<Vec<VolMove>> {
+    let mut stmt = conn.prepare(
+        "SELECT underlying, side, strike_price_cents, expires, iv, iv_before, snapshot_at FROM (
+             SELECT p.underlying, p.side, p.strike_price_cents, p.expires,
+                    (SELECT s.iv FROM iv_snapshots s
+                     WHERE s.product_id = p.id AND s.timestamp <= ?1 AND s.iv_source != 'default'
+                     ORDER BY s.timestamp DESC LIMIT 1) AS iv,
+                    COALESCE(
+                        (SELECT s.iv FROM iv_snapshots s
+                         WHERE s.product_id = p.id AND s.timestamp <= ?2 AND s.iv_source != 'default'
+                         ORDER BY s.timestamp DESC LIMIT 1),
+                        (SELECT s.iv FROM iv_snapshots s
+                         WHERE s.product_id = p.id AND s.timestamp > ?2 AND s.timestamp <= ?1 AND s.iv_source != 'default'
+                         ORDER BY s.timestamp ASC LIMIT 1)) AS iv_before,
+                    (SELECT MAX(s.timestamp) FROM iv_snapshots s
+                     WHERE s.product_id = p.id AND s.timestamp <= ?1 AND s.iv_source != 'default') AS snapshot_at
+             FROM products p
+             WHERE p.status = ?3 AND p.expires > ?1 AND (?4 IS NULL OR p.underlying = ?4))
+         WHERE iv IS NOT NULL AND iv_before IS NOT NULL
+         ORDER BY ABS(iv - iv_before) DESC, expires ASC, strike_price_cents ASC, side ASC",
+    )?;
+    let rows = stmt.query_map(params![now, since, ProductStatus::Active, asset], |row| {
+        Ok(VolMove {
+            underlying: row.get(0)?,
+            side: row.get(1)?,
+            strike_price: cents_to_usd(row.get(2)?),
+            expires: row.get(3)?,
+            iv: row.get(4)?,
+            iv_before: row.get(5)?,
+            snapshot_at: row.get(6)?,
+        })
+    })?;
+    Ok(rows.take(limit).collect::<Result<_, _>>()?)
+}
+
+/// Snapshot product IVs every IV_SNAPSHOT_INTERVAL_SECS, pruning past the retention
+pub fn start_snapshots(supervisor: &Supervisor, repository: Repository, ivs: IvResolver, config: IvHistoryConfig) {
+    supervisor.spawn("iv_snapshots", move || {
+        let (repository, ivs) = (repository.clone(), ivs.clone());
+        async move {
+            let mut ticker = interval(config.snapshot_interval);
+            loop {
+                ticker.tick().await;
+                let now = Utc::now().timestamp();
+                if let Err(e) = snapshot_products(&repository, &ivs, now).await {
+                    eprintln!("Error recording IV snapshots: {}", e);
+                }
+                let before = now - config.retention.as_secs() as i64;
+                if let Err(e) = repository.run(move |conn| prune(conn, before)).await {
+                    eprintln!("Error pruning IV snapshots: {}", e);
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::list_product;
+
+    #[test]
+    fn test_vol_movers_rank_by_iv_change() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init_db(&conn).unwrap();
+        let expires = 10_000;
+        for strike in [90_000.0, 100_000.0, 110_000.0] {
+            list_product(&conn, Asset::Btc, OptionSide::Call, strike, expires, 0).unwrap();
+        }
+        // Product 1 rises 5 points, 2 falls 10, 3 only has a default IV
+        for (product_id, timestamp, iv, source) in [
+            (1, 100, 0.5, IvProvenance::Deribit),
+            (1, 200, 0.55, IvProvenance::Deribit),
+            (2, 100, 0.6, IvProvenance::Interpolated),
+            (2, 200, 0.5, IvProvenance::Deribit),
+            (3, 100, 0.4, IvProvenance::Default),
+            (3, 200, 0.9, IvProvenance::Deribit),
+        ] {
+            record(&conn, product_id, timestamp, iv, source).unwrap();
+        }
+
+        let movers = vol_movers(&conn, 250, 150, None, 10).unwrap();
+        assert_eq!(movers.iter().map(|m| m.strike_price).collect::<Vec<_>>(), vec![100_000.0, 90_000.0, 110_000.0]);
+        assert_eq!((movers[0].iv, movers[0].iv_before, movers[0].snapshot_at), (0.5, 0.6, 200));
+        // Without an earlier market IV, the first one in the window is the reference
+        assert_eq!(movers[2].change(), 0.0);
+        assert_eq!(vol_movers(&conn, 250, 150, None, 1).unwrap().len(), 1);
+        assert!(vol_movers(&conn, 250, 150, Some(Asset::Eth), 10).unwrap().is_empty());
+        // Expired products drop out
+        assert!(vol_movers(&conn, expires, 150, None, 10).unwrap().is_empty());
+
+        assert_eq!(prune(&conn, 200).unwrap(), 3);
+    }
+}
@@ -1,8 +1,20 @@
-use reqwest::Client;
-use serde::Deserialize;
-use std::collections::HashMap;
+use crate::error::ApiError;
+use crate::http_client::HttpClient;
+use crate::models::{Asset, IvProvenance};
+use crate::shared_cache::SharedCache;
+use crate::sources::IvQuote;
+use crate::supervisor::Supervisor;
+use crate::svi::{self, SurfaceFit};
+use crate::timeouts::UpstreamTimeouts;
+use crate::utils::year_fraction;
+use serde::{Deserialize, Serialize};
+use rand::Rng;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
-use tokio::time::{interval, Duration};
+use std::time::Instant;
+use tokio::time::{sleep, timeout, Duration};
 use std::hash::{Hash, Hasher};
 use chrono::{DateTime, NaiveDate, Utc};
 
@@ -33,6 +45,8 @@ struct DeribitResponse {
 struct OptionSummary {
     instrument_name: String,
     mark_iv: f64,
+    #[serde(default)]
+    underlying_price: Option<f64>,  // Forward of the instrument's expiry
 }
 
 #[derive(Debug, Deserialize)]
@@ -57,112 +71,375 @@ struct ExpiryInfo {
     timestamp: i64,        // milliseconds
 }
 
+// A cached IV and when Deribit last quoted it (unix seconds)
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct IvEntry {
+    iv: f64,
+    updated_at: i64,
+}
+
+/// How often the surface is refreshed from Deribit, and how long unquoted IVs are kept
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IvRefreshConfig {
+    pub interval: Duration,       // Between refreshes
+    pub jitter: Duration,         // Up to this much is added to each interval at random
+    pub max_entry_age: Duration,  // Entries not quoted for this long are evicted
+}
+
+impl Default for IvRefreshConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(15),
+            jitter: Duration::from_secs(2),
+            max_entry_age: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+impl IvRefreshConfig {
+    /// IV_REFRESH_SECS (15, at least 1), IV_REFRESH_JITTER_SECS (2) and IV_ENTRY_MAX_AGE_SECS (3600)
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let secs = |name: &str| env::var(name).ok().and_then(|v| v.parse::<u64>().ok()).map(Duration::from_secs);
+        Self {
+            interval: secs("IV_REFRESH_SECS").map(|d| d.max(Duration::from_secs(1))).unwrap_or(defaults.interval),
+            jitter: secs("IV_REFRESH_JITTER_SECS").unwrap_or(defaults.jitter),
+            max_entry_age: secs("IV_ENTRY_MAX_AGE_SECS").unwrap_or(defaults.max_entry_age),
+        }
+    }
+
+    /// Wait before the next refresh: the interval plus a random share of the jitter, so
+    /// oracles of several underlyings and instances do not poll Deribit in lockstep
+    pub fn next_delay(&self) -> Duration {
+        let jitter_ms = self.jitter.as_millis() as u64;
+        self.interval + Duration::from_millis(rand::thread_rng().gen_range(0..=jitter_ms))
+    }
+}
+
+/// How IVs are served from the cached surface
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum SurfaceModel {
+    Raw,      // The quoted IV of the listed strike at the nearest expiry
+    #[default]
+    Svi,      // SVI smiles fitted to each expiry, for any strike and expiry
+}
+
+impl SurfaceModel {
+    /// IV_SURFACE_MODEL: svi (default) or raw
+    pub fn from_env() -> Result<Self, String> {
+        match env::var("IV_SURFACE_MODEL").as_deref() {
+            Err(_) | Ok("svi") => Ok(SurfaceModel::Svi),
+            Ok("raw") => Ok(SurfaceModel::Raw),
+            Ok(other) => Err(format!("IV_SURFACE_MODEL must be svi or raw, got '{}'", other)),
+        }
+    }
+}
+
+// IV values keyed by expiry date string -> strike -> side ("C"/"P")
+type IvCache = HashMap<String, HashMap<StrikePrice, HashMap<String, IvEntry>>>;
+
+// Instruments listed on Deribit, as (expiry, strike, side)
+type Listing = HashSet<(String, StrikePrice, String)>;
+
+// One refresh of the surface from Deribit, as stored in the shared cache for other instances
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct SurfaceUpdate {
+    fetched_at: i64,                                // Unix milliseconds
+    quotes: Vec<(String, f64, String, f64)>,        // (expiry, strike, side, iv)
+    listing: Option<Vec<(String, f64, String)>>,    // None when the instruments could not be read
+    forwards: HashMap<String, f64>,                 // Median forward of each expiry
+}
+
 #[derive(Clone)]
 pub struct IvOracle {
-    client: Client,
-    cache: Arc<RwLock<HashMap<String, HashMap<StrikePrice, HashMap<String, f64>>>>>,
+    client: HttpClient,
+    cache: Arc<RwLock<IvCache>>,
     expiry_map: Arc<RwLock<HashMap<String, i64>>>,  // Maps date strings to timestamps
+    forwards: Arc<RwLock<HashMap<String, f64>>>,    // Maps date strings to Deribit's forward
+    surface_model: SurfaceModel,
+    fits: Arc<RwLock<Vec<SurfaceFit>>>,             // Of the latest refresh, by expiry
     api_url: String,
+    currency: Asset,          // Deribit currency whose option surface is cached
+    version: Arc<AtomicU64>,  // Bumped on every successful refresh
+    refreshed_at: Arc<RwLock<Option<Instant>>>,
+    last_error: Arc<RwLock<Option<String>>>,  // Of the latest refresh, None once one succeeds
+    timeout: Duration,        // Deadline of a full refresh
+    refresh: IvRefreshConfig,
+    shared_cache: Option<Arc<dyn SharedCache>>,  // Surface shared with other instances
+    applied_at: Arc<AtomicI64>,                  // Fetch time of the latest surface merged
 }
 
 impl IvOracle {
     pub fn new(api_url: String) -> Self {
+        Self::for_asset(api_url, Asset::Btc)
+    }
+
+    /// Oracle for the option surface of another Deribit currency, e.g. ETH
+    pub fn for_asset(api_url: String, currency: Asset) -> Self {
         Self {
-            client: Client::new(),
+            client: HttpClient::default(),
             cache: Arc::new(RwLock::new(HashMap::new())),
             expiry_map: Arc::new(RwLock::new(HashMap::new())),
+            forwards: Arc::new(RwLock::new(HashMap::new())),
+            surface_model: SurfaceModel::default(),
+            fits: Arc::new(RwLock::new(Vec::new())),
             api_url,
+            currency,
+            version: Arc::new(AtomicU64::new(0)),
+            refreshed_at: Arc::new(RwLock::new(None)),
+            last_error: Arc::new(RwLock::new(None)),
+            timeout: UpstreamTimeouts::default().iv_oracle,
+            refresh: IvRefreshConfig::default(),
+            shared_cache: None,
+            applied_at: Arc::new(AtomicI64::new(0)),
         }
     }
 
+    /// Share a client configured for retries, timeouts and circuit breaking
+    pub fn with_http_client(mut self, client: HttpClient) -> Self {
+        self.client = client;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Refresh interval, jitter and eviction age (every 15-17s, evicting after an hour by default)
+    pub fn with_refresh(mut self, refresh: IvRefreshConfig) -> Self {
+        self.refresh = refresh;
+        self
+    }
+
+    /// Serve IVs from fitted SVI smiles (default) or the raw quotes
+    pub fn with_surface_model(mut self, surface_model: SurfaceModel) -> Self {
+        self.surface_model = surface_model;
+        self
+    }
+
+    /// Share refreshes with other instances: one of them fetches the surface from Deribit
+    /// while it is stale and the others load what it stored
+    pub fn with_shared_cache(mut self, cache: Arc<dyn SharedCache>) -> Self {
+        self.shared_cache = Some(cache);
+        self
+    }
+
+    pub fn refresh_config(&self) -> IvRefreshConfig {
+        self.refresh
+    }
+
+    pub fn currency(&self) -> Asset {
+        self.currency
+    }
+
     pub async fn initialize(&self) -> Result<(), Box<dyn std::error::Error>> {
-        println!("📊 Initializing IV Oracle - fetching initial data...");
+        println!("📊 Initializing {} IV Oracle - fetching initial data...", self.currency);
         self.fetch_and_update_iv().await?;
-        println!("✅ IV Oracle initialized with data");
+        println!("✅ {} IV Oracle initialized with data", self.currency);
         Ok(())
     }
 
-    pub async fn start_updates(&self) {
+    pub fn start_updates(&self, supervisor: &Supervisor) {
         let oracle = self.clone();
-        tokio::spawn(async move {
-            let mut ticker = interval(Duration::from_secs(15));
-            loop {
-                ticker.tick().await;
-                if let Err(e) = oracle.fetch_and_update_iv().await {
-                    eprintln!("Error updating IV data: {}", e);
+        let name = format!("iv_refresh_{}", self.currency.to_string().to_lowercase());
+        supervisor.spawn(&name, move || {
+            let oracle = oracle.clone();
+            async move {
+                loop {
+                    sleep(oracle.refresh.next_delay()).await;
+                    if let Err(e) = oracle.fetch_and_update_iv().await {
+                        eprintln!("Error updating {} IV data: {}", oracle.currency, e);
+                    }
                 }
             }
         });
     }
 
+    /// Merge a fresh surface from Deribit into the cache, within the refresh deadline
     pub async fn fetch_and_update_iv(&self) -> Result<(), Box<dyn std::error::Error>> {
-        // First, get all available BTC option instruments to see what's available
-        let instruments_url = format!("{}/public/get_instruments?currency=BTC&kind=option&expired=false", self.api_url);
-        match self.client.get(&instruments_url).send().await {
-            Ok(resp) => {
-                if let Ok(instruments_response) = resp.json::<InstrumentsResponse>().await {
-                    // Log the number of instruments found
-                    //println!("Found {} active BTC option instruments", instruments_response.result.len());
-                    
-                    // Collect unique expiries for logging
-                    let mut unique_expiries = std::collections::HashSet::new();
-                    for instrument in &instruments_response.result {
-                        if let Some((expiry, _, _)) = parse_instrument_name(&instrument.instrument_name) {
-                            unique_expiries.insert(expiry);
-                        }
-                    }
-                    let mut expiries_vec: Vec<String> = unique_expiries.into_iter().collect();
-                    expiries_vec.sort();
-                    //println!("Unique expiries found ({}): {:?}", expiries_vec.len(), expiries_vec);
+        let result = match timeout(self.timeout, self.fetch_surface()).await {
+            Ok(result) => result,
+            Err(_) => Err(Box::new(ApiError::UpstreamTimeout(format!(
+                "Deribit {} IV refresh did not complete within {}s",
+                self.currency,
+                self.timeout.as_secs_f64()
+            ))) as Box<dyn std::error::Error>),
+        };
+        *self.last_error.write().unwrap() = result.as_ref().err().map(|e| e.to_string());
+        result
+    }
+
+    /// Error of the latest refresh, None if it succeeded
+    pub fn last_error(&self) -> Option<String> {
+        self.last_error.read().unwrap().clone()
+    }
+
+    async fn fetch_surface(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(cache) = &self.shared_cache else {
+            let update = self.fetch_update().await?;
+            self.apply_update(update);
+            return Ok(());
+        };
+
+        let key = format!("iv_surface:{}", self.currency);
+        let stored = match cache.get(&key).await {
+            Ok(value) => value.and_then(|value| serde_json::from_str::<SurfaceUpdate>(&value).ok()),
+            Err(e) => {
+                eprintln!("Failed to read shared {} IV surface, fetching it from Deribit: {}", self.currency, e);
+                None
+            }
+        };
+        let fresh_since = Utc::now().timestamp_millis() - self.refresh.interval.as_millis() as i64;
+        if let Some(update) = stored.clone().filter(|update| update.fetched_at > fresh_since) {
+            self.apply_update(update);
+            return Ok(());
+        }
+
+        // Stale: whoever takes the lock refreshes it from Deribit for everyone, while the
+        // others keep the surface last stored
+        let lock = format!("iv_refresh:{}", self.currency);
+        match (cache.set_if_absent(&lock, "1", self.timeout).await, stored) {
+            (Ok(false), Some(update)) => {
+                self.apply_update(update);
+                return Ok(());
+            }
+            (Ok(_), _) => {}
+            (Err(e), _) => eprintln!("Failed to lock shared {} IV refresh: {}", self.currency, e),
+        }
+        let update = self.fetch_update().await?;
+        match serde_json::to_string(&update) {
+            Ok(value) => {
+                if let Err(e) = cache.set(&key, &value, self.refresh.max_entry_age).await {
+                    eprintln!("Failed to share {} IV surface: {}", self.currency, e);
                 }
             }
+            Err(e) => eprintln!("Failed to serialize {} IV surface: {}", self.currency, e),
+        }
+        self.apply_update(update);
+        Ok(())
+    }
+
+    // Fetch the quoted IVs, listed instruments and forwards of this oracle's currency
+    async fn fetch_update(&self) -> Result<SurfaceUpdate, Box<dyn std::error::Error>> {
+        // The instruments still listed, to evict delisted ones. Without the list, entries
+        // are only evicted once they expire or go unquoted for max_entry_age.
+        let instruments_url = format!(
+            "{}/public/get_instruments?currency={}&kind=option&expired=false",
+            self.api_url, self.currency
+        );
+        let listing = match self.client.get(&instruments_url).await {
+            Ok(resp) => match resp.json::<InstrumentsResponse>().await {
+                Ok(instruments) => Some(self.listed_instruments(&instruments.result)),
+                Err(e) => {
+                    eprintln!("Failed to parse {} instruments: {}", self.currency, e);
+                    None
+                }
+            },
             Err(e) => {
                 eprintln!("Failed to fetch instruments: {}", e);
+                None
             }
-        }
-        
+        };
+
         // Now fetch the book summary for all options (this includes IV data)
-        let url = format!("{}/public/get_book_summary_by_currency?currency=BTC&kind=option", self.api_url);
+        let url = format!(
+            "{}/public/get_book_summary_by_currency?currency={}&kind=option",
+            self.api_url, self.currency
+        );
         let response: DeribitResponse = self.client
             .get(&url)
-            .send()
             .await?
             .json()
             .await?;
 
-        let mut new_cache = HashMap::new();
-        let mut new_expiry_map = HashMap::new();
-
-        for option in response.result {
-            if let Some((expiry, strike, side)) = parse_instrument_name(&option.instrument_name) {
-                // Convert IV from percentage to decimal (e.g., 35.16 -> 0.3516)
-                let iv_decimal = option.mark_iv / 100.0;
-                
-                // Store IV in cache
-                new_cache
-                    .entry(expiry.clone())
-                    .or_insert_with(HashMap::new)
-                    .entry(StrikePrice(strike))
-                    .or_insert_with(HashMap::new)
-                    .insert(side, iv_decimal);
-                
-                // Parse and store expiry timestamp if not already present
-                if !new_expiry_map.contains_key(&expiry) {
-                    if let Some(timestamp) = Self::parse_expiry_to_timestamp(&expiry) {
-                        new_expiry_map.insert(expiry, timestamp);
-                    }
-                }
+        // Deribit's forward of each expiry, the median over its instruments
+        let mut forwards: HashMap<String, Vec<f64>> = HashMap::new();
+        for option in &response.result {
+            let forward = option.underlying_price.filter(|price| price.is_finite() && *price > 0.0);
+            if let (Some(forward), Some((expiry, _, _))) =
+                (forward, parse_asset_instrument_name(&option.instrument_name, self.currency))
+            {
+                forwards.entry(expiry).or_default().push(forward);
             }
         }
 
-        // Update both caches atomically
-        let mut cache = self.cache.write().unwrap();
-        *cache = new_cache;
-        
-        let mut expiry_map = self.expiry_map.write().unwrap();
-        *expiry_map = new_expiry_map;
+        // Convert IV from percentage to decimal (e.g., 35.16 -> 0.3516), skipping
+        // instruments without a usable mark so their last known IV is kept
+        let quotes: Vec<(String, f64, String, f64)> = response
+            .result
+            .into_iter()
+            .filter(|option| option.mark_iv.is_finite() && option.mark_iv > 0.0)
+            .filter_map(|option| {
+                let (expiry, strike, side) = parse_asset_instrument_name(&option.instrument_name, self.currency)?;
+                Some((expiry, strike, side, option.mark_iv / 100.0))
+            })
+            .collect();
 
-        Ok(())
+        Ok(SurfaceUpdate {
+            fetched_at: Utc::now().timestamp_millis(),
+            quotes,
+            listing,
+            forwards: forwards
+                .into_iter()
+                .map(|(expiry, mut prices)| {
+                    prices.sort_by(f64::total_cmp);
+                    (expiry, prices[prices.len() / 2])
+                })
+                .collect(),
+        })
+    }
+
+    // Merge `update` into the cache, unless a later one was merged already
+    fn apply_update(&self, update: SurfaceUpdate) {
+        if self.applied_at.fetch_max(update.fetched_at, Ordering::SeqCst) >= update.fetched_at {
+            return;
+        }
+        let listing: Option<Listing> = update.listing.map(|listing| {
+            listing.into_iter().map(|(expiry, strike, side)| (expiry, StrikePrice(strike), side)).collect()
+        });
+
+        // Merge under the write locks; the fetch ran without holding them
+        let now = update.fetched_at / 1000;
+        let max_age = self.refresh.max_entry_age.as_secs() as i64;
+        {
+            let mut cache = self.cache.write().unwrap();
+            let mut expiry_map = self.expiry_map.write().unwrap();
+            let (_, evicted) = merge_surface(&mut cache, &mut expiry_map, update.quotes, listing.as_ref(), now, max_age);
+            if evicted > 0 {
+                println!("🧹 Evicted {} delisted or stale {} IV entries", evicted, self.currency);
+            }
+
+            let mut known_forwards = self.forwards.write().unwrap();
+            known_forwards.extend(update.forwards);
+            known_forwards.retain(|expiry, _| expiry_map.contains_key(expiry));
+            let fits = fit_surface(&cache, &expiry_map, &known_forwards, self.currency, now);
+            *self.fits.write().unwrap() = fits;
+        }
+
+        self.version.fetch_add(1, Ordering::SeqCst);
+        // As old as the fetch, which may have been another instance's
+        let age = Duration::from_millis((Utc::now().timestamp_millis() - update.fetched_at).max(0) as u64);
+        *self.refreshed_at.write().unwrap() = Some(Instant::now().checked_sub(age).unwrap_or_else(Instant::now));
+    }
+
+    // Active instruments of this oracle's currency, as (expiry, strike, side)
+    fn listed_instruments(&self, instruments: &[InstrumentData]) -> Vec<(String, f64, String)> {
+        instruments
+            .iter()
+            .filter(|instrument| instrument.is_active)
+            .filter_map(|instrument| parse_asset_instrument_name(&instrument.instrument_name, self.currency))
+            .collect()
+    }
+
+    /// Time since the cache was last refreshed, None before the first refresh
+    pub fn last_refresh_age(&self) -> Option<Duration> {
+        self.refreshed_at.read().unwrap().map(|at| at.elapsed())
+    }
+
+    /// Refresh counter, incremented each time new IV data replaces the cache
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::SeqCst)
     }
 
     /// Get implied volatility for a given option.
@@ -180,8 +457,8 @@ impl IvOracle {
         
         for (_cached_expiry, strikes) in cache.iter() {
             if let Some(sides) = strikes.get(&StrikePrice(strike_price)) {
-                if let Some(iv) = sides.get(side) {
-                    return Some(*iv);
+                if let Some(entry) = sides.get(side) {
+                    return Some(entry.iv);
                 }
             }
         }
@@ -189,6 +466,34 @@ impl IvOracle {
         None
     }
     
+    /// `get_iv` with its provenance: Deribit's own quote for the option, as old as Deribit
+    /// last quoted it, or interpolated (SVI fit or nearest expiry) as of the last refresh
+    pub fn get_iv_quote(&self, side: &str, strike_price: f64, expire: &str) -> Option<IvQuote> {
+        let value = self.get_iv(side, strike_price, expire)?;
+        let listed = expire.parse::<i64>().ok().and_then(|ms| self.listed_entry(side, strike_price, ms));
+        Some(match listed {
+            Some(entry) if entry.iv == value => IvQuote {
+                value,
+                source: IvProvenance::Deribit,
+                age: Some(Duration::from_secs((Utc::now().timestamp() - entry.updated_at).max(0) as u64)),
+            },
+            _ => IvQuote { value, source: IvProvenance::Interpolated, age: self.last_refresh_age() },
+        })
+    }
+
+    // Cached quote of the instrument expiring exactly at `expire_timestamp_ms`
+    fn listed_entry(&self, side: &str, strike_price: f64, expire_timestamp_ms: i64) -> Option<IvEntry> {
+        let expiry = self
+            .expiry_map
+            .read()
+            .unwrap()
+            .iter()
+            .find(|(_, timestamp)| **timestamp == expire_timestamp_ms)
+            .map(|(expiry, _)| expiry.clone())?;
+        let cache = self.cache.read().unwrap();
+        cache.get(&expiry)?.get(&StrikePrice(strike_price))?.get(side).copied()
+    }
+
     pub fn get_cache_size(&self) -> usize {
         let cache = self.cache.read().unwrap();
         cache.values()
@@ -198,6 +503,28 @@ impl IvOracle {
             .sum()
     }
 
+    /// Flattened copy of the cache as (expiry, strike, side, iv), sorted by expiry date then strike
+    pub fn snapshot(&self) -> Vec<(String, f64, String, f64)> {
+        let cache = self.cache.read().unwrap();
+        let expiry_map = self.expiry_map.read().unwrap();
+        let mut entries: Vec<(String, f64, String, f64)> = cache
+            .iter()
+            .flat_map(|(expiry, strikes)| {
+                strikes.iter().flat_map(move |(strike, sides)| {
+                    sides.iter().map(move |(side, entry)| (expiry.clone(), strike.0, side.clone(), entry.iv))
+                })
+            })
+            .collect();
+        entries.sort_by(|a, b| {
+            let a_ts = expiry_map.get(&a.0).copied().unwrap_or(i64::MAX);
+            let b_ts = expiry_map.get(&b.0).copied().unwrap_or(i64::MAX);
+            a_ts.cmp(&b_ts)
+                .then_with(|| a.1.total_cmp(&b.1))
+                .then_with(|| a.2.cmp(&b.2))
+        });
+        entries
+    }
+
     pub fn get_cached_expiries(&self) -> Vec<String> {
         let cache = self.cache.read().unwrap();
         cache.keys().cloned().collect()
@@ -233,7 +560,7 @@ impl IvOracle {
         cache.get(expire)
             .and_then(|strikes| strikes.get(&StrikePrice(strike_price)))
             .and_then(|sides| sides.get(side))
-            .map(|iv| *iv)
+            .map(|entry| entry.iv)
     }
     /// Parse Deribit date format (e.g., "19SEP25" or "6SEP25") to timestamp
     fn parse_expiry_to_timestamp(expiry: &str) -> Option<i64> {
@@ -293,8 +620,23 @@ impl IvOracle {
         closest_expiry
     }
     
-    /// Get IV for a specific option with timestamp-based expiry matching
+    /// SVI smiles fitted at the latest refresh, by expiry
+    pub fn surface_fits(&self) -> Vec<SurfaceFit> {
+        self.fits.read().unwrap().clone()
+    }
+
+    /// Get IV for a specific option with timestamp-based expiry matching.
+    /// With the SVI model, any strike and expiry is priced off the fitted smiles; the raw
+    /// quotes are used when no expiry could be fitted.
     pub fn get_iv_by_timestamp(&self, side: &str, strike_price: f64, expire_timestamp_ms: i64) -> Option<f64> {
+        if self.surface_model == SurfaceModel::Svi {
+            let fits = self.fits.read().unwrap();
+            let iv = svi::surface_iv(&fits, strike_price, expire_timestamp_ms / 1000, Utc::now().timestamp());
+            if iv.is_some() {
+                return iv;
+            }
+        }
+
         // Find the nearest expiry
         let nearest_expiry = self.find_nearest_expiry(expire_timestamp_ms)?;
         
@@ -303,9 +645,102 @@ impl IvOracle {
     }
 }
 
+// Merge freshly quoted IVs (expiry, strike, side, iv) into the cache at `now` (unix seconds).
+// Entries missing from the quotes keep their last known IV; they are evicted once their
+// expiry has passed, once `listing` (when known) no longer has them, or after going
+// unquoted for `max_age` seconds. Returns the number of entries updated and evicted.
+fn merge_surface(
+    cache: &mut IvCache,
+    expiry_map: &mut HashMap<String, i64>,
+    quotes: Vec<(String, f64, String, f64)>,
+    listing: Option<&Listing>,
+    now: i64,
+    max_age: i64,
+) -> (usize, usize) {
+    let updated = quotes.len();
+    for (expiry, strike, side, iv) in quotes {
+        if let std::collections::hash_map::Entry::Vacant(entry) = expiry_map.entry(expiry.clone()) {
+            if let Some(timestamp) = IvOracle::parse_expiry_to_timestamp(entry.key()) {
+                entry.insert(timestamp);
+            }
+        }
+        cache
+            .entry(expiry)
+            .or_default()
+            .entry(StrikePrice(strike))
+            .or_default()
+            .insert(side, IvEntry { iv, updated_at: now });
+    }
+
+    let mut evicted = 0;
+    cache.retain(|expiry, strikes| {
+        let expired = expiry_map.get(expiry).is_some_and(|&timestamp| timestamp <= now * 1000);
+        strikes.retain(|strike, sides| {
+            sides.retain(|side, entry| {
+                let listed = listing.is_none_or(|listing| listing.contains(&(expiry.clone(), *strike, side.clone())));
+                let keep = !expired && listed && now - entry.updated_at < max_age;
+                evicted += !keep as usize;
+                keep
+            });
+            !sides.is_empty()
+        });
+        !strikes.is_empty()
+    });
+    expiry_map.retain(|expiry, _| cache.contains_key(expiry));
+    (updated, evicted)
+}
+
+// Fit an SVI smile to each unexpired expiry of the cache whose forward is known, from the
+// IV of every quoted strike (the mean of its call and put), and check them against each other
+fn fit_surface(
+    cache: &IvCache,
+    expiry_map: &HashMap<String, i64>,
+    forwards: &HashMap<String, f64>,
+    asset: Asset,
+    now: i64,
+) -> Vec<SurfaceFit> {
+    let mut fits: Vec<SurfaceFit> = cache
+        .iter()
+        .filter_map(|(expiry, strikes)| {
+            let expires = expiry_map.get(expiry)? / 1000;
+            let forward = *forwards.get(expiry)?;
+            let t = year_fraction(expires, now);
+            let quotes: Vec<(f64, f64)> = strikes
+                .iter()
+                .map(|(strike, sides)| {
+                    let iv = sides.values().map(|entry| entry.iv).sum::<f64>() / sides.len() as f64;
+                    ((strike.0 / forward).ln(), iv)
+                })
+                .collect();
+            let (params, rmse, max_error) = svi::fit_smile(&quotes, t)?;
+            Some(SurfaceFit {
+                asset,
+                expiry: expiry.clone(),
+                expires,
+                time_to_expiry: t,
+                forward,
+                params,
+                points: quotes.len(),
+                rmse,
+                max_error,
+                calendar_arbitrage_free: true,
+                fitted_at: now,
+            })
+        })
+        .collect();
+    svi::check_calendar(&mut fits);
+    fits
+}
+
 pub fn parse_instrument_name(name: &str) -> Option<(String, f64, String)> {
+    parse_asset_instrument_name(name, Asset::Btc)
+}
+
+/// Split a Deribit option name such as "ETH-19SEP25-3500-C" into (expiry, strike, side)
+/// if it belongs to `currency`
+pub fn parse_asset_instrument_name(name: &str, currency: Asset) -> Option<(String, f64, String)> {
     let parts: Vec<&str> = name.split('-').collect();
-    if parts.len() >= 4 && parts[0] == "BTC" {
+    if parts.len() >= 4 && parts[0] == currency.to_string() {
         let expiry = parts[1].to_string();
         if let Ok(strike) = parts[2].parse::<f64>() {
             let side = parts[3].to_string();
@@ -314,3 +749,157 @@ pub fn parse_instrument_name(name: &str) -> Option<(String, f64, String)> {
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quote(expiry: &str, strike: f64, side: &str, iv: f64) -> (String, f64, String, f64) {
+        (expiry.to_string(), strike, side.to_string(), iv)
+    }
+
+    fn iv(cache: &IvCache, expiry: &str, strike: f64, side: &str) -> Option<f64> {
+        cache.get(expiry)?.get(&StrikePrice(strike))?.get(side).map(|entry| entry.iv)
+    }
+
+    #[test]
+    fn test_refresh_delay_stays_within_the_jitter() {
+        let config = IvRefreshConfig { interval: Duration::from_secs(15), jitter: Duration::from_secs(2), ..Default::default() };
+        for _ in 0..100 {
+            let delay = config.next_delay();
+            assert!(delay >= Duration::from_secs(15) && delay <= Duration::from_secs(17));
+        }
+        let no_jitter = IvRefreshConfig { jitter: Duration::ZERO, ..config };
+        assert_eq!(no_jitter.next_delay(), Duration::from_secs(15));
+    }
+
+    #[test]
+    fn test_merge_surface_keeps_last_known_values() {
+        let (mut cache, mut expiry_map) = (IvCache::new(), HashMap::new());
+        let now = 1_800_000_000;
+        let quotes = vec![quote("1JAN40", 100000.0, "C", 0.5), quote("1JAN40", 100000.0, "P", 0.55), quote("1JAN20", 100000.0, "C", 0.4)];
+        // The 2020 expiry has passed and is dropped straight away
+        assert_eq!(merge_surface(&mut cache, &mut expiry_map, quotes, None, now, 3600), (3, 1));
+        assert!(!expiry_map.contains_key("1JAN20"));
+
+        // A partial response updates what it quotes and keeps the rest
+        let quotes = vec![quote("1JAN40", 100000.0, "C", 0.6)];
+        assert_eq!(merge_surface(&mut cache, &mut expiry_map, quotes, None, now + 15, 3600), (1, 0));
+        assert_eq!(iv(&cache, "1JAN40", 100000.0, "C"), Some(0.6));
+        assert_eq!(iv(&cache, "1JAN40", 100000.0, "P"), Some(0.55));
+        assert_eq!(cache["1JAN40"][&StrikePrice(100000.0)]["P"].updated_at, now);
+
+        // Entries unquoted for max_age are evicted
+        let quotes = vec![quote("1JAN40", 100000.0, "C", 0.6)];
+        assert_eq!(merge_surface(&mut cache, &mut expiry_map, quotes, None, now + 3600, 3600), (1, 1));
+        assert_eq!(iv(&cache, "1JAN40", 100000.0, "P"), None);
+    }
+
+    #[test]
+    fn test_merge_surface_evicts_delisted_instruments() {
+        let (mut cache, mut expiry_map) = (IvCache::new(), HashMap::new());
+        let now = 1_800_000_000;
+        let quotes = vec![quote("1JAN40", 100000.0, "C", 0.5), quote("1JAN40", 110000.0, "C", 0.45), quote("2JAN40", 100000.0, "C", 0.5)];
+        merge_surface(&mut cache, &mut expiry_map, quotes, None, now, 3600);
+
+        let listing: Listing = HashSet::from([("1JAN40".to_string(), StrikePrice(100000.0), "C".to_string())]);
+        assert_eq!(merge_surface(&mut cache, &mut expiry_map, Vec::new(), Some(&listing), now + 15, 3600), (0, 2));
+        assert_eq!(iv(&cache, "1JAN40", 100000.0, "C"), Some(0.5));
+        assert!(!cache["1JAN40"].contains_key(&StrikePrice(110000.0)));
+        assert_eq!(expiry_map.keys().collect::<Vec<_>>(), vec!["1JAN40"]);
+    }
+
+    #[test]
+    fn test_svi_surface_prices_unlisted_strikes() {
+        let (mut cache, mut expiry_map) = (IvCache::new(), HashMap::new());
+        let now = Utc::now().timestamp();
+        // A smile around a 100k forward, quoted every 5k; calls and puts share a strike's IV
+        let quotes: Vec<_> = (-4..=4)
+            .flat_map(|i| {
+                let strike = 100_000.0 + 5_000.0 * i as f64;
+                let iv = 0.5 + 0.4 * (strike / 100_000.0f64).ln().powi(2);
+                [quote("1JAN40", strike, "C", iv), quote("1JAN40", strike, "P", iv)]
+            })
+            .collect();
+        merge_surface(&mut cache, &mut expiry_map, quotes, None, now, 3600);
+        // No forward, no fit
+        assert!(fit_surface(&cache, &expiry_map, &HashMap::new(), Asset::Btc, now).is_empty());
+
+        let forwards = HashMap::from([("1JAN40".to_string(), 100_000.0)]);
+        let fits = fit_surface(&cache, &expiry_map, &forwards, Asset::Btc, now);
+        assert_eq!(fits.len(), 1);
+        assert_eq!((fits[0].points, fits[0].expires), (9, expiry_map["1JAN40"] / 1000));
+        assert!(fits[0].rmse < 0.01, "{:?}", fits[0]);
+
+        let oracle = IvOracle::new("http://localhost".to_string());
+        *oracle.cache.write().unwrap() = cache;
+        *oracle.expiry_map.write().unwrap() = expiry_map.clone();
+        *oracle.fits.write().unwrap() = fits;
+        let expires_ms = expiry_map["1JAN40"];
+        let iv = oracle.get_iv_by_timestamp("C", 102_500.0, expires_ms).unwrap();
+        assert!((iv - 0.5).abs() < 0.02, "{}", iv);
+        // The raw surface only knows the listed strikes
+        let raw = oracle.clone().with_surface_model(SurfaceModel::Raw);
+        assert_eq!(raw.get_iv_by_timestamp("C", 102_500.0, expires_ms), None);
+        assert_eq!(raw.get_iv_by_timestamp("C", 105_000.0, expires_ms), Some(0.5 + 0.4 * 1.05f64.ln().powi(2)));
+
+        // Only Deribit's own quote of the listed option counts as such
+        let source = |oracle: &IvOracle, strike: f64, expires_ms: i64| {
+            oracle.get_iv_quote("C", strike, &expires_ms.to_string()).map(|quote| quote.source)
+        };
+        assert_eq!(source(&oracle, 102_500.0, expires_ms), Some(IvProvenance::Interpolated));
+        assert_eq!(source(&raw, 105_000.0, expires_ms), Some(IvProvenance::Deribit));
+        assert_eq!(source(&raw, 105_000.0, expires_ms - 3_600_000), Some(IvProvenance::Interpolated));
+        assert!(raw.get_iv_quote("C", 105_000.0, &expires_ms.to_string()).unwrap().age.unwrap() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_instances_share_surface_refreshes() {
+        use crate::shared_cache::LocalCache;
+
+        // Deribit is unreachable, so everything below comes from the shared cache
+        let shared: Arc<dyn SharedCache> = Arc::new(LocalCache::new());
+        let oracle = || {
+            IvOracle::new("http://127.0.0.1:9".to_string())
+                .with_surface_model(SurfaceModel::Raw)
+                .with_shared_cache(shared.clone())
+        };
+        let update = |fetched_at: i64, iv: f64| SurfaceUpdate {
+            fetched_at,
+            quotes: vec![quote("1JAN40", 100000.0, "C", iv)],
+            listing: None,
+            forwards: HashMap::new(),
+        };
+        let store = |update: SurfaceUpdate| {
+            let shared = shared.clone();
+            async move {
+                let value = serde_json::to_string(&update).unwrap();
+                shared.set("iv_surface:BTC", &value, Duration::from_secs(3600)).await.unwrap();
+            }
+        };
+        let expires_ms = IvOracle::parse_expiry_to_timestamp("1JAN40").unwrap().to_string();
+
+        // A surface another instance fetched moments ago is loaded as is
+        let now_ms = Utc::now().timestamp_millis();
+        store(update(now_ms, 0.5)).await;
+        let first = oracle();
+        first.fetch_and_update_iv().await.unwrap();
+        assert_eq!(first.get_iv("C", 100000.0, &expires_ms), Some(0.5));
+        assert_eq!(first.version(), 1);
+        // Loading it again changes nothing
+        first.fetch_and_update_iv().await.unwrap();
+        assert_eq!(first.version(), 1);
+
+        // Stale while another instance holds the refresh lock: the stored surface is kept
+        store(update(now_ms - 60_000, 0.6)).await;
+        shared.set("iv_refresh:BTC", "1", Duration::from_secs(60)).await.unwrap();
+        let second = oracle();
+        second.fetch_and_update_iv().await.unwrap();
+        assert_eq!(second.get_iv("C", 100000.0, &expires_ms), Some(0.6));
+        assert!(second.last_refresh_age().unwrap() >= Duration::from_secs(59));
+
+        // Stale and unlocked: this instance refreshes from Deribit, which is down
+        shared.set("iv_refresh:BTC", "", Duration::ZERO).await.unwrap();
+        assert!(oracle().fetch_and_update_iv().await.is_err());
+    }
+}
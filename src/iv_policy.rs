@@ -0,0 +1,289 @@
+// Default IV policy.
+// When the IV surface has no IV for an option, one policy decides what is used instead,
+// wherever options are priced, margined or marked. IV_DEFAULT_POLICY picks it:
+//   constant      IV_DEFAULT (0.4)
+//   last_known    the last IV the surface gave for the option, else IV_DEFAULT
+//   realized_vol  7d close-to-close realized vol of the underlying, else IV_DEFAULT
+//   reject        IV_DEFAULT to value the open book, but trades on the option are refused
+// Values the policy supplies are marked IvProvenance::Default wherever they are reported.
+
+use crate::error::{ApiError, ApiResult, ErrorCode};
+use crate::models::{Asset, IvProvenance, OptionSide};
+use crate::repository::Repository;
+use crate::sources::{IvQuote, IvSource};
+use crate::supervisor::Supervisor;
+use crate::utils::usd_to_cents;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::time::interval;
+
+// IV used when nothing better is known
+pub const DEFAULT_IV: f64 = 0.4;
+
+// How often realized vol is recomputed under the realized_vol policy
+const REALIZED_VOL_REFRESH_INTERVAL: Duration = Duration::from_secs(300);
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DefaultIvMode {
+    #[default]
+    Constant,
+    LastKnown,
+    RealizedVol,
+    Reject,
+}
+
+impl fmt::Display for DefaultIvMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DefaultIvMode::Constant => write!(f, "constant"),
+            DefaultIvMode::LastKnown => write!(f, "last_known"),
+            DefaultIvMode::RealizedVol => write!(f, "realized_vol"),
+            DefaultIvMode::Reject => write!(f, "reject"),
+        }
+    }
+}
+
+impl FromStr for DefaultIvMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "constant" => Ok(DefaultIvMode::Constant),
+            "last_known" => Ok(DefaultIvMode::LastKnown),
+            "realized_vol" => Ok(DefaultIvMode::RealizedVol),
+            "reject" => Ok(DefaultIvMode::Reject),
+            other => Err(format!(
+                "unknown IV_DEFAULT_POLICY '{}', expected constant, last_known, realized_vol or reject",
+                other
+            )),
+        }
+    }
+}
+
+// An option as the IV surface is asked for it: underlying, side, strike in cents and expiry
+type OptionKey = (Asset, String, i64, String);
+
+#[derive(Debug)]
+pub struct DefaultIvPolicy {
+    mode: DefaultIvMode,
+    constant: f64,
+    last_known: RwLock<HashMap<OptionKey, f64>>,
+    realized_vol: RwLock<HashMap<Asset, f64>>,
+}
+
+impl Default for DefaultIvPolicy {
+    fn default() -> Self {
+        Self::new(DefaultIvMode::Constant, DEFAULT_IV)
+    }
+}
+
+impl DefaultIvPolicy {
+    pub fn new(mode: DefaultIvMode, constant: f64) -> Self {
+        Self { mode, constant, last_known: RwLock::default(), realized_vol: RwLock::default() }
+    }
+
+    /// IV_DEFAULT_POLICY (constant) and IV_DEFAULT (0.4), the constant every policy falls back to
+    pub fn from_env() -> Result<Self, String> {
+        let mode = match env::var("IV_DEFAULT_POLICY") {
+            Ok(mode) if !mode.trim().is_empty() => mode.parse()?,
+            _ => DefaultIvMode::default(),
+        };
+        let constant = match env::var("IV_DEFAULT") {
+            Ok(value) => value
+                .trim()
+                .parse()
+                .ok()
+                .filter(|iv: &f64| iv.is_finite() && *iv > 0.0)
+                .ok_or_else(|| format!("invalid IV_DEFAULT '{}', expected a positive decimal such as 0.4", value))?,
+            Err(_) => DEFAULT_IV,
+        };
+        Ok(Self::new(mode, constant))
+    }
+
+    pub fn mode(&self) -> DefaultIvMode {
+        self.mode
+    }
+
+    /// IV of an option from `source`, filled in by the policy when the surface has none.
+    /// `side` is "C"/"P" and `expire` a millisecond timestamp string, as for IvSource.
+    pub fn resolve(&self, source: &dyn IvSource, asset: Asset, side: &str, strike_price: f64, expire: &str) -> IvQuote {
+        let key = || (asset, side.to_string(), usd_to_cents(strike_price), expire.to_string());
+        match source.get_iv_quote(asset, side, strike_price, expire) {
+            Some(quote) => {
+                if self.mode == DefaultIvMode::LastKnown {
+                    self.last_known.write().unwrap().insert(key(), quote.value);
+                }
+                quote
+            }
+            None => {
+                let known = match self.mode {
+                    DefaultIvMode::LastKnown => self.last_known.read().unwrap().get(&key()).copied(),
+                    DefaultIvMode::RealizedVol => self.realized_vol.read().unwrap().get(&asset).copied(),
+                    DefaultIvMode::Constant | DefaultIvMode::Reject => None,
+                };
+                IvQuote::default_iv(known.unwrap_or(self.constant))
+            }
+        }
+    }
+
+    /// Whether an option may be traded at `quote`: always, unless the reject policy is on
+    /// and the IV is a default
+    pub fn allows_trade(&self, quote: &IvQuote) -> bool {
+        !(self.mode == DefaultIvMode::Reject && quote.source == IvProvenance::Default)
+    }
+
+    /// Refuse a trade on an option priced at `quote` when the policy does not allow it
+    pub fn check_trade(&self, quote: &IvQuote, asset: Asset, side: OptionSide, strike_price: f64, expires: i64) -> ApiResult<()> {
+        if self.allows_trade(quote) {
+            return Ok(());
+        }
+        Err(ApiError::PriceOracleError(format!(
+            "No implied volatility is available for {} {} {} expiring {}",
+            asset, side, strike_price, expires
+        ))
+        .with_code(ErrorCode::IvUnavailable)
+        .with_details(serde_json::json!({
+            "underlying": asset,
+            "side": side,
+            "strike_price": strike_price,
+            "expires": expires,
+            "policy": self.mode,
+        })))
+    }
+
+    /// Realized vol `asset` is priced at when it has no IV under the realized_vol policy
+    pub fn set_realized_vol(&self, asset: Asset, vol: Option<f64>) {
+        let mut realized_vol = self.realized_vol.write().unwrap();
+        match vol.filter(|vol| *vol > 0.0) {
+            Some(vol) => realized_vol.insert(asset, vol),
+            None => realized_vol.remove(&asset),
+        };
+    }
+
+    /// Recompute the 7d realized vol of each of `assets` from spot history
+    pub async fn refresh_realized_vol(&self, repository: &Repository, assets: &[Asset]) -> ApiResult<()> {
+        for &asset in assets {
+            let vol = repository.realized_vol(asset, "7d").await?.close_to_close;
+            self.set_realized_vol(asset, vol);
+        }
+        Ok(())
+    }
+
+    /// Keep realized vol current under the realized_vol policy; nothing to do under the others
+    pub fn start_updates(self: &Arc<Self>, supervisor: &Supervisor, repository: Repository, assets: Vec<Asset>) {
+        if self.mode != DefaultIvMode::RealizedVol {
+            return;
+        }
+        let policy = self.clone();
+        supervisor.spawn("default_iv", move || {
+            let (policy, repository, assets) = (policy.clone(), repository.clone(), assets.clone());
+            async move {
+                let mut ticker = interval(REALIZED_VOL_REFRESH_INTERVAL);
+                loop {
+                    ticker.tick().await;
+                    if let Err(e) = policy.refresh_realized_vol(&repository, &assets).await {
+                        eprintln!("Error refreshing realized vol for default IVs: {}", e);
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// An IV surface together with the policy filling its gaps
+#[derive(Clone)]
+pub struct IvResolver {
+    pub source: Arc<dyn IvSource>,
+    pub policy: Arc<DefaultIvPolicy>,
+}
+
+impl IvResolver {
+    pub fn new(source: Arc<dyn IvSource>, policy: Arc<DefaultIvPolicy>) -> Self {
+        Self { source, policy }
+    }
+
+    /// IV by side "C"/"P" and millisecond expiry string, as risk manager lookups ask for it
+    pub fn lookup(&self, asset: Asset, side: &str, strike_price: f64, expire: &str) -> IvQuote {
+        self.policy.resolve(self.source.as_ref(), asset, side, strike_price, expire)
+    }
+
+    /// IV of the option on `asset` expiring at `expires` (unix seconds)
+    pub fn option_iv(&self, asset: Asset, side: OptionSide, strike_price: f64, expires: i64) -> IvQuote {
+        let side = match side {
+            OptionSide::Call => "C",
+            OptionSide::Put => "P",
+        };
+        self.lookup(asset, side, strike_price, &(expires * 1000).to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Quotes one call; everything else is missing from the surface
+    struct OneCall(RwLock<Option<f64>>);
+
+    impl IvSource for OneCall {
+        fn get_iv(&self, side: &str, strike_price: f64, _expire: &str) -> Option<f64> {
+            if side == "C" && strike_price == 100_000.0 {
+                *self.0.read().unwrap()
+            } else {
+                None
+            }
+        }
+
+        fn cache_size(&self) -> usize {
+            1
+        }
+
+        fn version(&self) -> u64 {
+            0
+        }
+    }
+
+    #[test]
+    fn test_policies_fill_missing_ivs() {
+        let source = OneCall(RwLock::new(Some(0.55)));
+        let resolve = |policy: &DefaultIvPolicy, side: &str| policy.resolve(&source, Asset::Btc, side, 100_000.0, "1900000000000");
+
+        let constant = DefaultIvPolicy::new(DefaultIvMode::Constant, 0.45);
+        assert_eq!(resolve(&constant, "C").source, IvProvenance::Deribit);
+        assert_eq!(resolve(&constant, "P"), IvQuote::default_iv(0.45));
+
+        // The last quote of the option outlives its removal from the surface
+        let last_known = DefaultIvPolicy::new(DefaultIvMode::LastKnown, 0.45);
+        assert_eq!(resolve(&last_known, "C").value, 0.55);
+        *source.0.write().unwrap() = None;
+        assert_eq!(resolve(&last_known, "C"), IvQuote::default_iv(0.55));
+        assert_eq!(resolve(&last_known, "P").value, 0.45);
+
+        let realized = DefaultIvPolicy::new(DefaultIvMode::RealizedVol, 0.45);
+        assert_eq!(resolve(&realized, "P").value, 0.45);
+        realized.set_realized_vol(Asset::Btc, Some(0.62));
+        assert_eq!(resolve(&realized, "P"), IvQuote::default_iv(0.62));
+
+        // Defaults still value the book, but are not traded on
+        let reject = DefaultIvPolicy::new(DefaultIvMode::Reject, 0.45);
+        let quote = resolve(&reject, "P");
+        assert_eq!(quote.value, 0.45);
+        assert!(!reject.allows_trade(&quote));
+        let err = reject.check_trade(&quote, Asset::Btc, OptionSide::Put, 100_000.0, 1_900_000_000).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::IvUnavailable);
+        assert_eq!(err.details()["policy"], "reject");
+        assert!(constant.allows_trade(&quote));
+    }
+
+    #[test]
+    fn test_default_iv_mode_from_str() {
+        assert_eq!("Realized_Vol".parse::<DefaultIvMode>(), Ok(DefaultIvMode::RealizedVol));
+        assert_eq!(DefaultIvMode::LastKnown.to_string().parse::<DefaultIvMode>(), Ok(DefaultIvMode::LastKnown));
+        assert!("guess".parse::<DefaultIvMode>().is_err());
+    }
+}
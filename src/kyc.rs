@@ -0,0 +1,259 @@
+// KYC gating of trade size.
+// Every account (API key name) has a KYC status, `none` until an operator sets another with
+// PUT /admin/accounts/{account}/kyc. When KYC_LIMITS is set, the cumulative notional an
+// account has bought, each contract at the spot price it was traded at, is capped by status.
+// A trade past the cap is refused with KYC_REQUIRED, pointing the buyer to
+// KYC_VERIFICATION_URL. Without KYC_LIMITS statuses are kept but trades are not gated.
+
+use crate::audit;
+use crate::error::{ApiError, ApiResult, ErrorCode};
+use crate::models::ContractStatus;
+use rusqlite::types::{FromSql, FromSqlError, ToSql, ToSqlOutput, ValueRef};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum KycStatus {
+    #[default]
+    None,
+    Pending,   // Documents submitted, not yet reviewed
+    Verified,
+    Rejected,
+}
+
+impl fmt::Display for KycStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KycStatus::None => write!(f, "none"),
+            KycStatus::Pending => write!(f, "pending"),
+            KycStatus::Verified => write!(f, "verified"),
+            KycStatus::Rejected => write!(f, "rejected"),
+        }
+    }
+}
+
+impl std::str::FromStr for KycStatus {
+    type Err = FromSqlError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(KycStatus::None),
+            "pending" => Ok(KycStatus::Pending),
+            "verified" => Ok(KycStatus::Verified),
+            "rejected" => Ok(KycStatus::Rejected),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl ToSql for KycStatus {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.to_string().into())
+    }
+}
+
+impl FromSql for KycStatus {
+    fn column_result(value: ValueRef<'_>) -> std::result::Result<Self, FromSqlError> {
+        value.as_str()?.parse()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct KycConfig {
+    pub limits: HashMap<KycStatus, f64>,   // Max cumulative notional in USD; statuses not listed are unlimited
+    pub verification_url: Option<String>,  // Where buyers complete verification
+}
+
+impl Default for KycConfig {
+    // Only verified accounts may trade
+    fn default() -> Self {
+        Self {
+            limits: HashMap::from([(KycStatus::None, 0.0), (KycStatus::Pending, 0.0), (KycStatus::Rejected, 0.0)]),
+            verification_url: None,
+        }
+    }
+}
+
+impl KycConfig {
+    /// KYC_LIMITS, comma separated status=usd pairs such as "none=1000,pending=10000", and
+    /// KYC_VERIFICATION_URL. None when KYC_LIMITS is unset. Statuses not listed keep the
+    /// default: verified is unlimited, the others may not trade. "unlimited" lifts a cap.
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let limits = match env::var("KYC_LIMITS") {
+            Ok(limits) if !limits.trim().is_empty() => limits,
+            _ => return Ok(None),
+        };
+        let mut config = Self {
+            verification_url: env::var("KYC_VERIFICATION_URL").ok().filter(|url| !url.trim().is_empty()),
+            ..Self::default()
+        };
+        for pair in limits.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (status, limit) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("invalid KYC_LIMITS entry '{}', expected status=usd", pair))?;
+            let status: KycStatus = status.trim().parse().map_err(|_| {
+                format!("unknown KYC status '{}' in KYC_LIMITS, expected none, pending, verified or rejected", status.trim())
+            })?;
+            match limit.trim() {
+                "unlimited" => {
+                    config.limits.remove(&status);
+                }
+                limit => {
+                    let limit: f64 = limit
+                        .parse()
+                        .ok()
+                        .filter(|limit: &f64| limit.is_finite() && *limit >= 0.0)
+                        .ok_or_else(|| format!("invalid KYC_LIMITS cap '{}' for {}", limit, status))?;
+                    config.limits.insert(status, limit);
+                }
+            }
+        }
+        Ok(Some(config))
+    }
+
+    /// Max cumulative notional of accounts with `status`, None if unlimited
+    pub fn max_notional_usd(&self, status: KycStatus) -> Option<f64> {
+        self.limits.get(&status).copied()
+    }
+
+    /// Reject a trade of `trade_notional_usd` if it takes `account` past the cap of its status
+    pub fn check(&self, conn: &Connection, account: &str, trade_notional_usd: f64) -> ApiResult<()> {
+        let status = load_status(conn, account)?;
+        let Some(limit) = self.max_notional_usd(status) else {
+            return Ok(());
+        };
+        let current_notional_usd = cumulative_notional_usd(conn, account)?;
+        let notional_usd = current_notional_usd + trade_notional_usd;
+        if notional_usd <= limit {
+            return Ok(());
+        }
+        let next_step = match (status, &self.verification_url) {
+            (KycStatus::Pending, _) => "verification is pending review".to_string(),
+            (_, Some(url)) => format!("complete verification at {}", url),
+            (_, None) => "complete verification to trade more".to_string(),
+        };
+        Err(ApiError::ValidationError(format!(
+            "Cumulative notional of account {} would be ${:.2} (currently ${:.2}), above the ${:.2} allowed with KYC status {}; {}",
+            account, notional_usd, current_notional_usd, limit, status, next_step
+        ))
+        .with_code(ErrorCode::KycRequired)
+        .with_details(serde_json::json!({
+            "account": account,
+            "kyc_status": status,
+            "value": notional_usd,
+            "current": current_notional_usd,
+            "max": limit,
+            "verification_url": self.verification_url,
+        })))
+    }
+}
+
+/// KYC status of an account
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct AccountKyc {
+    pub account: String,
+    pub kyc_status: KycStatus,
+    pub updated_at: Option<i64>,  // None until a status is first set
+}
+
+/// KYC status of `account`, `none` for accounts never reviewed
+pub fn load_status(conn: &Connection, account: &str) -> ApiResult<KycStatus> {
+    Ok(load_account(conn, account)?.kyc_status)
+}
+
+/// KYC record of `account`
+pub fn load_account(conn: &Connection, account: &str) -> ApiResult<AccountKyc> {
+    let row = conn
+        .query_row(
+            "SELECT kyc_status, kyc_updated_at FROM accounts WHERE name = ?1",
+            params![account],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    let (kyc_status, updated_at) = row.unwrap_or_default();
+    Ok(AccountKyc { account: account.to_string(), kyc_status, updated_at })
+}
+
+/// Set the KYC status of `account`, audited under `actor`
+pub fn set_status(conn: &Connection, account: &str, status: KycStatus, actor: &str, now: i64) -> ApiResult<AccountKyc> {
+    if account.trim().is_empty() {
+        return Err(ApiError::ValidationError("Account must be set".to_string()));
+    }
+    let tx = conn.unchecked_transaction()?;
+    let before = load_account(&tx, account)?;
+    tx.execute(
+        "INSERT INTO accounts (name, kyc_status, kyc_updated_at) VALUES (?1, ?2, ?3)
+         ON CONFLICT(name) DO UPDATE SET kyc_status = excluded.kyc_status, kyc_updated_at = excluded.kyc_updated_at",
+        params![account, status, now],
+    )?;
+    let after = load_account(&tx, account)?;
+    audit::record(
+        &tx,
+        actor,
+        audit::ACCOUNT_KYC,
+        None,
+        serde_json::to_value(&before).ok().as_ref(),
+        serde_json::to_value(&after).ok().as_ref(),
+    )?;
+    tx.commit()?;
+    Ok(after)
+}
+
+/// Notional `account` has bought over all time, each contract at its spot price at trade.
+/// Closed and settled contracts count; cancelled ones, never paid for, do not.
+pub fn cumulative_notional_usd(conn: &Connection, account: &str) -> ApiResult<f64> {
+    Ok(conn.query_row(
+        "SELECT COALESCE(SUM(CAST(quantity_sats AS REAL) * COALESCE(spot_at_trade_cents, 0)), 0) / 1e10
+         FROM contracts WHERE counterparty = ?1 AND status != ?2",
+        params![account, ContractStatus::Cancelled],
+        |row| row.get(0),
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kyc_caps_cumulative_notional() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init_db(&conn).unwrap();
+        conn.execute(
+            "INSERT INTO contracts (side, strike_price_cents, quantity_sats, expires, premium_sats, counterparty, spot_at_trade_cents)
+             VALUES ('Call', 10000000, 50000000, 1800000000, 1000000, 'alice', 10000000)",
+            [],
+        )
+        .unwrap();
+        assert_eq!(cumulative_notional_usd(&conn, "alice").unwrap(), 50_000.0);
+
+        let config = KycConfig {
+            limits: HashMap::from([(KycStatus::None, 60_000.0), (KycStatus::Pending, 0.0)]),
+            verification_url: Some("https://example.com/kyc".to_string()),
+        };
+        assert!(config.check(&conn, "alice", 10_000.0).is_ok());
+        let err = config.check(&conn, "alice", 10_000.01).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::KycRequired);
+        assert!(err.to_string().contains("complete verification at https://example.com/kyc"), "{}", err);
+
+        // Verified accounts are unlimited unless capped
+        let account = set_status(&conn, "alice", KycStatus::Verified, "ops", 1).unwrap();
+        assert_eq!((account.kyc_status, account.updated_at), (KycStatus::Verified, Some(1)));
+        assert!(config.check(&conn, "alice", 1e9).is_ok());
+        assert_eq!(load_status(&conn, "bob").unwrap(), KycStatus::None);
+        let entries = audit::query(&conn, &audit::AuditFilter::default()).unwrap();
+        assert_eq!(entries[0].action, audit::ACCOUNT_KYC);
+    }
+
+    #[test]
+    fn test_default_limits() {
+        let config = KycConfig::default();
+        assert_eq!(config.max_notional_usd(KycStatus::Verified), None);
+        for status in [KycStatus::None, KycStatus::Pending, KycStatus::Rejected] {
+            assert_eq!(config.max_notional_usd(status), Some(0.0));
+        }
+    }
+}
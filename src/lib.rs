@@ -1,9 +1,76 @@
 pub mod mutiny_wallet;
 pub mod iv_oracle;
+pub mod iv_policy;
+pub mod iv_history;
 pub mod mock_apis;
 pub mod price_oracle;
+pub mod price_feeds;
+pub mod price_guards;
+pub mod circuit_breaker;
+pub mod http_client;
 pub mod db;
+pub mod backup;
+pub mod snapshot;
+pub mod api_keys;
+pub mod auth;
+pub mod audit;
+pub mod conversions;
+pub mod export;
+pub mod migrations;
 pub mod utils;
+pub mod day_count;
+pub mod rates;
+pub mod basis;
 pub mod error;
+pub mod models;
+pub mod options_grid;
+pub mod catalog;
+pub mod pricing;
+pub mod pricing_audit;
+pub mod quoting;
+pub mod quote_sanity;
+pub mod backtest;
+pub mod margin;
+pub mod risk_manager;
+pub mod repository;
+pub mod reservations;
+pub mod contract_search;
+pub mod vol;
+pub mod price_history;
+pub mod svi;
+pub mod stats;
+pub mod strikes;
+pub mod rolling_metrics;
+pub mod table_cache;
+pub mod shared_cache;
+pub mod sources;
+pub mod webhooks;
+pub mod outbox;
+pub mod message_bus;
+pub mod expiry;
+pub mod trading_state;
+pub mod utilization;
+pub mod position_limits;
+pub mod credit_tiers;
+pub mod fees;
+pub mod referrals;
+pub mod kyc;
+pub mod validation;
+pub mod orderbook;
+pub mod attestation;
+pub mod settlement;
+pub mod dlc;
+pub mod health;
+pub mod supervisor;
+pub mod timeouts;
+pub mod request_id;
+pub mod graphql;
+pub mod fix;
+pub mod lightning;
+pub mod payments;
+pub mod pools;
+pub mod api;
+pub mod api_v1;
+pub mod api_v2;
 
 pub use mutiny_wallet::{MutinyWallet, Network, WalletBalance, MutinyWalletError};
\ No newline at end of file
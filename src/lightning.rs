@@ -0,0 +1,290 @@
+// Lightning premium payments.
+// Instead of waiting for on-chain confirmations, a buyer may pay the premium with a BOLT11
+// invoice issued by the pool's Lightning node. LIGHTNING_BACKEND selects the node's REST API:
+// lnd (LND REST, authenticated with a hex invoice macaroon) or cln (Core Lightning clnrest,
+// authenticated with a rune). The payment watcher looks up pending invoices and opens the
+// contract as soon as its invoice settles. Invoices expire when the payment is due, so a late
+// payment cannot be made.
+
+use async_trait::async_trait;
+use reqwest::{Certificate, Client, RequestBuilder};
+use serde::Deserialize;
+use serde_json::json;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum LightningError {
+    NetworkError(String),
+    ParseError(String),
+    ApiError(String),
+}
+
+impl fmt::Display for LightningError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LightningError::NetworkError(msg) => write!(f, "Network error: {}", msg),
+            LightningError::ParseError(msg) => write!(f, "Parse error: {}", msg),
+            LightningError::ApiError(msg) => write!(f, "API error: {}", msg),
+        }
+    }
+}
+
+impl Error for LightningError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Invoice {
+    pub payment_hash: String,  // Hex
+    pub bolt11: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InvoiceState {
+    Open,
+    Settled { settled_at: i64 },
+    Cancelled,  // Expired or cancelled; can no longer be paid
+}
+
+#[async_trait]
+pub trait LightningNode: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Invoice for `amount_sats` that can be paid for `expiry_secs`
+    async fn create_invoice(&self, amount_sats: i64, memo: &str, expiry_secs: i64) -> Result<Invoice, LightningError>;
+
+    async fn lookup_invoice(&self, payment_hash: &str) -> Result<InvoiceState, LightningError>;
+}
+
+// Send a request and decode its JSON response
+async fn send_json<T: for<'de> Deserialize<'de>>(request: RequestBuilder) -> Result<T, LightningError> {
+    let response = request
+        .send()
+        .await
+        .map_err(|e| LightningError::NetworkError(e.to_string()))?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        return Err(LightningError::ApiError(format!("API returned status {}: {}", status, body)));
+    }
+    response
+        .json::<T>()
+        .await
+        .map_err(|e| LightningError::ParseError(e.to_string()))
+}
+
+// LND encodes 64-bit integers as JSON strings
+fn parse_lnd_int(value: &str) -> Result<i64, LightningError> {
+    value
+        .parse()
+        .map_err(|_| LightningError::ParseError(format!("invalid integer '{}'", value)))
+}
+
+// LND returns payment hashes as standard base64
+fn base64_to_hex(encoded: &str) -> Result<String, LightningError> {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut bits: u32 = 0;
+    let mut bit_count = 0;
+    let mut hex = String::new();
+    for c in encoded.trim_end_matches('=').bytes() {
+        let value = ALPHABET
+            .iter()
+            .position(|&a| a == c)
+            .ok_or_else(|| LightningError::ParseError(format!("invalid base64 '{}'", encoded)))?;
+        bits = (bits << 6) | value as u32;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            hex.push_str(&format!("{:02x}", (bits >> bit_count) & 0xff));
+        }
+    }
+    Ok(hex)
+}
+
+pub struct LndRest {
+    client: Client,
+    base_url: String,
+    macaroon_hex: String,
+}
+
+impl LndRest {
+    pub fn new(client: Client, base_url: String, macaroon_hex: String) -> Self {
+        Self { client, base_url: base_url.trim_end_matches('/').to_string(), macaroon_hex }
+    }
+}
+
+#[derive(Deserialize)]
+struct LndAddInvoiceResponse {
+    r_hash: String,
+    payment_request: String,
+}
+
+#[derive(Deserialize)]
+struct LndInvoice {
+    state: String,
+    #[serde(default)]
+    settle_date: String,
+}
+
+#[async_trait]
+impl LightningNode for LndRest {
+    fn name(&self) -> &'static str {
+        "lnd"
+    }
+
+    async fn create_invoice(&self, amount_sats: i64, memo: &str, expiry_secs: i64) -> Result<Invoice, LightningError> {
+        let request = self
+            .client
+            .post(format!("{}/v1/invoices", self.base_url))
+            .header("Grpc-Metadata-macaroon", &self.macaroon_hex)
+            .json(&json!({
+                "value": amount_sats.to_string(),
+                "memo": memo,
+                "expiry": expiry_secs.to_string(),
+            }));
+        let created: LndAddInvoiceResponse = send_json(request).await?;
+        Ok(Invoice {
+            payment_hash: base64_to_hex(&created.r_hash)?,
+            bolt11: created.payment_request,
+        })
+    }
+
+    async fn lookup_invoice(&self, payment_hash: &str) -> Result<InvoiceState, LightningError> {
+        let request = self
+            .client
+            .get(format!("{}/v1/invoice/{}", self.base_url, payment_hash))
+            .header("Grpc-Metadata-macaroon", &self.macaroon_hex);
+        let invoice: LndInvoice = send_json(request).await?;
+        match invoice.state.as_str() {
+            "SETTLED" => Ok(InvoiceState::Settled { settled_at: parse_lnd_int(&invoice.settle_date)? }),
+            "CANCELED" => Ok(InvoiceState::Cancelled),
+            // ACCEPTED is a held invoice that has not settled yet
+            "OPEN" | "ACCEPTED" => Ok(InvoiceState::Open),
+            other => Err(LightningError::ParseError(format!("unknown invoice state '{}'", other))),
+        }
+    }
+}
+
+pub struct ClnRest {
+    client: Client,
+    base_url: String,
+    rune: String,
+}
+
+impl ClnRest {
+    pub fn new(client: Client, base_url: String, rune: String) -> Self {
+        Self { client, base_url: base_url.trim_end_matches('/').to_string(), rune }
+    }
+}
+
+#[derive(Deserialize)]
+struct ClnInvoiceResponse {
+    payment_hash: String,
+    bolt11: String,
+}
+
+#[derive(Deserialize)]
+struct ClnListInvoicesResponse {
+    invoices: Vec<ClnInvoice>,
+}
+
+#[derive(Deserialize)]
+struct ClnInvoice {
+    status: String,
+    paid_at: Option<i64>,
+}
+
+#[async_trait]
+impl LightningNode for ClnRest {
+    fn name(&self) -> &'static str {
+        "cln"
+    }
+
+    async fn create_invoice(&self, amount_sats: i64, memo: &str, expiry_secs: i64) -> Result<Invoice, LightningError> {
+        // Labels must be unique per node
+        let label = format!("option-premium-{:016x}", rand::random::<u64>());
+        let request = self
+            .client
+            .post(format!("{}/v1/invoice", self.base_url))
+            .header("Rune", &self.rune)
+            .json(&json!({
+                "amount_msat": amount_sats * 1000,
+                "label": label,
+                "description": memo,
+                "expiry": expiry_secs,
+            }));
+        let created: ClnInvoiceResponse = send_json(request).await?;
+        Ok(Invoice {
+            payment_hash: created.payment_hash,
+            bolt11: created.bolt11,
+        })
+    }
+
+    async fn lookup_invoice(&self, payment_hash: &str) -> Result<InvoiceState, LightningError> {
+        let request = self
+            .client
+            .post(format!("{}/v1/listinvoices", self.base_url))
+            .header("Rune", &self.rune)
+            .json(&json!({ "payment_hash": payment_hash }));
+        let listed: ClnListInvoicesResponse = send_json(request).await?;
+        let invoice = listed
+            .invoices
+            .into_iter()
+            .next()
+            .ok_or_else(|| LightningError::ApiError(format!("no invoice with payment hash {}", payment_hash)))?;
+        match invoice.status.as_str() {
+            "paid" => Ok(InvoiceState::Settled { settled_at: invoice.paid_at.unwrap_or_default() }),
+            "expired" => Ok(InvoiceState::Cancelled),
+            "unpaid" => Ok(InvoiceState::Open),
+            other => Err(LightningError::ParseError(format!("unknown invoice status '{}'", other))),
+        }
+    }
+}
+
+/// LIGHTNING_BACKEND: unset (no Lightning payments), lnd or cln, at LIGHTNING_REST_URL.
+/// LND needs LIGHTNING_MACAROON (hex), CLN needs LIGHTNING_RUNE. LIGHTNING_TLS_CERT is the
+/// path of the node's PEM certificate, for nodes with a self-signed one.
+pub fn lightning_node_from_env() -> Result<Option<Arc<dyn LightningNode>>, String> {
+    let backend = env::var("LIGHTNING_BACKEND").unwrap_or_default();
+    if backend.trim().is_empty() {
+        return Ok(None);
+    }
+    let required = |name: &str| {
+        env::var(name)
+            .ok()
+            .filter(|v| !v.trim().is_empty())
+            .ok_or_else(|| format!("{} is required with LIGHTNING_BACKEND={}", name, backend.trim()))
+    };
+    let base_url = required("LIGHTNING_REST_URL")?;
+
+    let mut builder = Client::builder();
+    if let Ok(cert_path) = env::var("LIGHTNING_TLS_CERT") {
+        let pem = fs::read(&cert_path).map_err(|e| format!("cannot read LIGHTNING_TLS_CERT {}: {}", cert_path, e))?;
+        let cert = Certificate::from_pem(&pem).map_err(|e| format!("invalid LIGHTNING_TLS_CERT {}: {}", cert_path, e))?;
+        builder = builder.add_root_certificate(cert);
+    }
+    let client = builder.build().map_err(|e| e.to_string())?;
+
+    match backend.trim() {
+        "lnd" => Ok(Some(Arc::new(LndRest::new(client, base_url, required("LIGHTNING_MACAROON")?)))),
+        "cln" => Ok(Some(Arc::new(ClnRest::new(client, base_url, required("LIGHTNING_RUNE")?)))),
+        other => Err(format!("unknown LIGHTNING_BACKEND '{}' (expected lnd or cln)", other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lnd_payment_hash_is_hex() {
+        assert_eq!(base64_to_hex("3q2+7w==").unwrap(), "deadbeef");
+        assert_eq!(
+            base64_to_hex("AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=").unwrap(),
+            "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f"
+        );
+        assert!(base64_to_hex("not base64!").is_err());
+    }
+}
@@ -1,244 +1,610 @@
 // This is a refactored version of main.rs with all architectural improvements
 // After review, this can replace the original main.rs
 
-use actix_web::{web, App, HttpResponse, HttpServer, Responder, middleware};
-use serde::{Deserialize, Serialize};
-use serde_json;
-use chrono::Utc;
-use reqwest::Client;
-use std::fmt;
+use actix_web::{web, App, HttpServer, middleware};
 use std::env;
 use std::sync::Arc;
 use dotenv::dotenv;
-use rusqlite::{params, types::{ToSql, FromSql, ToSqlOutput, FromSqlError, ValueRef}};
 
 // Import our modules
-mod mock_apis;
-mod iv_oracle;
-mod price_oracle;
-mod db;
-mod db_migration;
-mod utils;
-mod error;
-mod risk_manager;
 
-mod mutiny_wallet;
-
-use crate::db::DbPool;
-use crate::error::ApiError;
-use crate::utils::{format_expires_timestamp, parse_duration, usd_to_cents, cents_to_usd, 
-                   float_to_db_string, db_string_to_float, format_btc, round_btc, BTC_PRECISION};
-use crate::mutiny_wallet::{MutinyWallet, Network};
-use crate::risk_manager::{RiskManager};
-
-// Represents the side of an option: Call or Put.
-#[derive(Serialize, Deserialize, Clone, Debug)]
-pub enum OptionSide {
-    Call,
-    Put,
-}
-
-impl ToSql for OptionSide {
-    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
-        Ok(self.to_string().into())
+use btc_options_api::{api, api_keys, api_v1, api_v2, attestation, auth, backup, basis, catalog, day_count, db, dlc, expiry, fix, health, iv_history, iv_oracle, iv_policy, kyc, lightning, message_bus, migrations, mock_apis, outbox, payments, price_history, price_oracle, rates, request_id, rolling_metrics, settlement, shared_cache, stats, strikes, trading_state, utilization};
+use btc_options_api::api::AppState;
+use btc_options_api::models::Asset;
+use btc_options_api::repository::Repository;
+use btc_options_api::mutiny_wallet::{EsploraConfig, MutinyWallet, Network};
+use btc_options_api::options_grid::GridConfig;
+use btc_options_api::margin::margin_model_from_env;
+use btc_options_api::position_limits::PositionLimits;
+use btc_options_api::orderbook::OrderbookConfig;
+use btc_options_api::quoting::QuotingConfig;
+use btc_options_api::reservations::ReservationConfig;
+use btc_options_api::fees::FeeSchedule;
+use btc_options_api::risk_manager::MarginCache;
+use btc_options_api::price_guards::PriceGuards;
+use btc_options_api::price_feeds::{FallbackConfig, FallbackPriceSource};
+use btc_options_api::sources::{AssetIvSources, FixedPriceSource, IvSource, PriceSource, StaticIvSource, WalletSource};
+use btc_options_api::http_client::{HttpClient, HttpClientConfig};
+use btc_options_api::supervisor::{Supervisor, SupervisorConfig};
+use btc_options_api::timeouts::UpstreamTimeouts;
+use btc_options_api::webhooks::{EventSink, WebhookSink};
+use std::collections::HashMap;
+
+// Apply pending migrations and print the resulting schema version history
+fn run_migrate_command() -> std::io::Result<()> {
+    let db_pool = db::create_pool()
+        .map_err(|e| std::io::Error::other(format!("Failed to create database pool: {}", e)))?;
+    let conn = db_pool
+        .get()
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+    let applied = migrations::applied_migrations(&conn)
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+
+    for migration in &applied {
+        println!("  {:04} {} (applied at {})", migration.version, migration.name, migration.applied_at);
     }
+    println!("✅ Database schema at version {}", applied.last().map(|m| m.version).unwrap_or(0));
+    Ok(())
 }
 
-impl FromSql for OptionSide {
-    fn column_result(value: ValueRef<'_>) -> std::result::Result<Self, FromSqlError> {
-        value.as_str()?.parse()
+// Main application entry point
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    dotenv().ok();
+    env_logger::init();
+
+    // `btc_options_api migrate` applies pending schema migrations and exits
+    if env::args().nth(1).as_deref() == Some("migrate") {
+        return run_migrate_command();
     }
-}
 
-impl std::str::FromStr for OptionSide {
-    type Err = FromSqlError;
-    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
-        match s {
-            "Call" => Ok(OptionSide::Call),
-            "Put" => Ok(OptionSide::Put),
-            _ => Err(FromSqlError::InvalidType),
+    // Initialize database pool (applies pending migrations)
+    let db_pool = db::create_pool()
+        .expect("Failed to create database pool");
+    match db_pool.get().map_err(|e| e.to_string()).and_then(|conn| db::check_indexes(&conn).map_err(|e| e.to_string())) {
+        Ok(problems) => {
+            for problem in problems {
+                eprintln!("WARNING: Database index check: {}", problem);
+            }
         }
+        Err(e) => eprintln!("WARNING: Database index check failed: {}", e),
     }
-}
 
-impl fmt::Display for OptionSide {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            OptionSide::Call => write!(f, "Call"),
-            OptionSide::Put => write!(f, "Put"),
-        }
+    // Start the mock API server first so offline mode can use it during initialization
+    let offline = mock_apis::offline_mode();
+    let mock_config = mock_apis::MockConfig::from_env();
+    if mock_apis::mocks_enabled() {
+        let mock_server = mock_apis::mock_server(mock_config.clone(), offline)?;
+        tokio::spawn(mock_server);
+        println!("🧪 Mock APIs listening on {}", mock_config.bind_address);
+    }
+    if offline {
+        println!("🔌 Offline mode: Deribit, mempool.space and the price oracle are mocked");
     }
-}
 
-// Contract structure for API input/output (uses floats for backward compatibility)
-#[derive(Serialize, Deserialize, Clone)]
-struct Contract {
-    side: OptionSide,
-    strike_price: f64,
-    quantity: f64,
-    expires: i64,
-    premium: f64,
-}
+    // Year fractions for pricing and margin, ACT/365 unless DAY_COUNT says otherwise
+    let day_count = day_count::DayCount::from_env().unwrap_or_else(|e| {
+        eprintln!("ERROR: Invalid day count configuration: {}", e);
+        std::process::exit(1);
+    });
+    println!("📆 Day count convention: {} ({} holidays)", day_count.convention, day_count.holidays.len());
+    day_count::init(day_count).expect("day count is set once at startup");
 
-// Internal contract structure for database storage (uses strings for precision)
-#[derive(Clone, Debug)]
-struct ContractDb {
-    side: OptionSide,
-    strike_price_cents: i64,
-    quantity_str: String,
-    expires: i64,
-    premium_str: String,
-}
+    // Strikes of new trades and the strike grid are on these ticks
+    let strike_ticks = strikes::StrikeTicks::from_env().unwrap_or_else(|e| {
+        eprintln!("ERROR: Invalid strike tick configuration: {}", e);
+        std::process::exit(1);
+    });
+    println!("📏 Strike ticks: BTC ${}, ETH ${}", strike_ticks.btc, strike_ticks.eth);
+    strikes::init(strike_ticks).expect("strike ticks are set once at startup");
 
-impl ContractDb {
-    // Convert from API contract to DB contract
-    fn from_contract(contract: &Contract) -> Self {
-        Self {
-            side: contract.side.clone(),
-            strike_price_cents: usd_to_cents(contract.strike_price),
-            quantity_str: float_to_db_string(round_btc(contract.quantity), BTC_PRECISION),
-            expires: contract.expires,
-            premium_str: float_to_db_string(round_btc(contract.premium), BTC_PRECISION),
-        }
-    }
-    
-    // Convert to API contract when needed for calculations
-    fn to_contract(&self) -> Contract {
-        Contract {
-            side: self.side.clone(),
-            strike_price: cents_to_usd(self.strike_price_cents),
-            quantity: db_string_to_float(&self.quantity_str).unwrap_or(0.0),
-            expires: self.expires,
-            premium: db_string_to_float(&self.premium_str).unwrap_or(0.0),
+    // Underlyings open for trading, e.g. ASSETS=BTC,ETH (BTC is always enabled)
+    let assets = Asset::parse_list(&env::var("ASSETS").unwrap_or_default()).unwrap_or_else(|e| {
+        eprintln!("ERROR: Invalid ASSETS: {}", e);
+        std::process::exit(1);
+    });
+    println!("🪙 Trading options on: {:?}", assets.iter().map(|a| a.to_string()).collect::<Vec<_>>());
+
+    // Background workers are restarted with backoff if they panic, and reported by /health
+    let supervisor = Supervisor::new(SupervisorConfig::from_env());
+
+    // Deribit and the Mutiny API share retries, timeouts and per-host circuit breakers
+    let http_client = HttpClient::new(HttpClientConfig::from_env());
+    // Deadlines of whole upstream calls, retries included
+    let upstream_timeouts = UpstreamTimeouts::from_env();
+
+    // Spot prices and IV surfaces shared with the other API instances (SHARED_CACHE_URL)
+    let shared_cache = match shared_cache::SharedCacheConfig::from_env() {
+        Ok(Some(config)) => match shared_cache::connect(&config).await {
+            Ok(cache) => {
+                println!("🗄️  Sharing prices and IV surfaces through {} under {}:*", config.url, config.key_prefix);
+                Some((cache, config))
+            }
+            Err(e) => {
+                eprintln!("ERROR: {}", e);
+                std::process::exit(1);
+            }
+        },
+        Ok(None) => None,
+        Err(e) => {
+            eprintln!("ERROR: {}", e);
+            std::process::exit(1);
         }
-    }
-}
+    };
 
-// Request/Response structures
-#[derive(Serialize)]
-struct OptionsTableResponse {
-    side: OptionSide,
-    strike_price: f64,
-    expire: String,
-    premium: String,  // BTC amount as string for precision
-    max_quantity: String,  // BTC amount as string for precision
-    iv: f64,
-    delta: f64,
-}
+    let deribit_url = if offline {
+        format!("{}/deribit", mock_config.base_url())
+    } else {
+        env::var("DERIBIT_API_URL")
+            .unwrap_or_else(|_| "https://www.deribit.com/api/v2".to_string())
+    };
 
-// Contract response with string fields for precision
-#[derive(Serialize)]
-struct ContractResponse {
-    side: OptionSide,
-    strike_price: f64,
-    quantity: String,  // BTC amount as string
-    expires: i64,
-    premium: String,   // BTC amount as string
-}
+    // Initialize the IV source: a static surface from IV_FILE, otherwise one Deribit
+    // oracle per underlying
+    let iv_source: Arc<dyn IvSource> = match env::var("IV_FILE") {
+        Ok(path) => {
+            let source = StaticIvSource::from_file(&path).unwrap_or_else(|e| {
+                eprintln!("ERROR: Failed to load IV_FILE: {}", e);
+                std::process::exit(1);
+            });
+            println!("📄 Loaded {} IV points from {}", source.cache_size(), path);
+            Arc::new(source)
+        }
+        Err(_) => {
+            let iv_refresh = iv_oracle::IvRefreshConfig::from_env();
+            let surface_model = iv_oracle::SurfaceModel::from_env().unwrap_or_else(|e| {
+                eprintln!("ERROR: {}", e);
+                std::process::exit(1);
+            });
+            let mut oracles: HashMap<Asset, Arc<dyn IvSource>> = HashMap::new();
+            for asset in &assets {
+                let mut iv_oracle = iv_oracle::IvOracle::for_asset(deribit_url.clone(), *asset)
+                    .with_http_client(http_client.clone())
+                    .with_timeout(upstream_timeouts.iv_oracle)
+                    .with_refresh(iv_refresh)
+                    .with_surface_model(surface_model);
+                if let Some((cache, _)) = &shared_cache {
+                    iv_oracle = iv_oracle.with_shared_cache(cache.clone());
+                }
+                let iv_oracle = Arc::new(iv_oracle);
 
-#[derive(Serialize)]
-struct TopBannerResponse {
-    volume_24hr: f64,
-    open_interest_usd: f64,
-    contract_count: i64,
-}
+                // Initialize IV oracle with data before starting server
+                println!("🔄 Initializing {} IV Oracle with market data...", asset);
+                if let Err(e) = iv_oracle.initialize().await {
+                    eprintln!("WARNING: Failed to initialize {} IV Oracle: {}", asset, e);
+                    eprintln!("The server will start but IV data may not be immediately available.");
+                }
 
-#[derive(Serialize)]
-struct MarketHighlightItem {
-    product_symbol: String,
-    side: OptionSide,
-    strike_price: f64,
-    expire: String,
-    volume_24hr: f64,
-    price_change_24hr_percent: f64,
-}
+                // Start background updates after initial data is loaded
+                iv_oracle.start_updates(&supervisor);
+                oracles.insert(*asset, iv_oracle);
+            }
+            Arc::new(AssetIvSources::new(oracles))
+        }
+    };
 
-#[derive(Serialize)]
-struct TopGainerItem {
-    product_symbol: String,
-    side: OptionSide,
-    strike_price: f64,
-    expire: String,
-    change_24hr_percent: f64,
-    last_price: f64,
-}
+    // Initialize the price source: a fixed price offline, otherwise the gRPC aggregator
+    // with public exchange feeds as fallback. The server starts even if the aggregator is down.
+    let aggregator_url = env::var("AGGREGATOR_URL")
+        .unwrap_or_else(|_| "http://localhost:50051".to_string());
+    let price_oracle: Arc<dyn PriceSource> = if offline {
+        Arc::new(FixedPriceSource::new(mock_config.btc_price).with_price(Asset::Eth, mock_config.eth_price))
+    } else {
+        let fallback_config = FallbackConfig::from_env();
+        println!(
+            "💱 Price sources: aggregator at {}, fallback feeds: {:?}",
+            aggregator_url,
+            fallback_config.exchanges.iter().map(|e| e.name()).collect::<Vec<_>>()
+        );
+        let price_source = Arc::new(FallbackPriceSource::from_aggregator(aggregator_url.clone(), &fallback_config));
 
-#[derive(Serialize)]
-struct TopVolumeItem {
-    product_symbol: String,
-    side: OptionSide,
-    strike_price: f64,
-    expire: String,
-    volume_usd: f64,
-    last_price: f64,
-}
+        // Keep the price current from the aggregator stream instead of polling (PRICE_STREAMING=false disables)
+        let price_streaming = env::var("PRICE_STREAMING").map(|v| v != "false" && v != "0").unwrap_or(true);
+        if price_streaming {
+            let stream_target = price_source.clone();
+            price_oracle::start_price_stream(aggregator_url, move |event| stream_target.handle_stream_event(event));
+        }
+        price_source
+    };
+    let price_oracle: Arc<dyn PriceSource> = match &shared_cache {
+        Some((cache, config)) => Arc::new(shared_cache::SharedPriceSource::new(price_oracle, cache.clone(), config.price_ttl)),
+        None => price_oracle,
+    };
 
-// Application state
-pub struct AppState {
-    db_pool: DbPool,
-    iv_oracle: Arc<iv_oracle::IvOracle>,
-    price_oracle: Arc<price_oracle::PriceOracle>,
-    mutiny_wallet: Arc<MutinyWallet>,
-    pool_address: String,
-}
+    // Sample spot of every underlying into price_history for realized vol, settlement and charts
+    let price_history_config = price_history::PriceHistoryConfig::from_env();
+    price_history::start_sampler(
+        &supervisor,
+        Repository::new(db_pool.clone()),
+        price_oracle.clone(),
+        assets.clone(),
+        price_history_config,
+    );
 
-// Main application entry point
-#[tokio::main]
-async fn main() -> std::io::Result<()> {
-    dotenv().ok();
-    env_logger::init();
+    // What options the IV surface has no IV for are priced at
+    let iv_policy = Arc::new(iv_policy::DefaultIvPolicy::from_env().unwrap_or_else(|e| {
+        eprintln!("ERROR: Invalid default IV policy: {}", e);
+        std::process::exit(1);
+    }));
+    iv_policy.start_updates(&supervisor, Repository::new(db_pool.clone()), assets.clone());
+
+    // Risk-free rates by maturity: RISK_FREE_RATE_CURVE, or flat at RISK_FREE_RATE, unless
+    // RISK_FREE_RATE_CURVE_URL serves the curve
+    let rates = Arc::new(rates::RateSource::from_env().unwrap_or_else(|e| {
+        eprintln!("ERROR: Invalid risk-free rate configuration: {}", e);
+        std::process::exit(1);
+    }));
+    if let Some(url) = rates.url() {
+        println!("📈 Fetching the risk-free rate curve from {}", url);
+        if let Err(e) = rates.refresh(&http_client).await {
+            eprintln!("WARNING: Failed to fetch the risk-free rate curve, using the configured one: {}", e);
+        }
+    }
+    rates.start_updates(&supervisor, http_client.clone());
+    let curve = rates.curve();
+    println!(
+        "📈 Risk-free rates: {}",
+        curve.points().iter().map(|(tenor, rate)| format!("{:.3}y {:.2}%", tenor, rate * 100.0)).collect::<Vec<_>>().join(", ")
+    );
 
-    // Initialize database pool
-    let db_pool = db::create_pool()
-        .expect("Failed to create database pool");
+    // Quotes and marks priced with the perpetual funding or futures basis (PRICING_BASIS)
+    let basis = Arc::new(basis::BasisSource::from_env(deribit_url.clone()).unwrap_or_else(|e| {
+        eprintln!("ERROR: Invalid pricing basis configuration: {}", e);
+        std::process::exit(1);
+    }));
+    basis.start_updates(&supervisor, http_client.clone(), assets.clone());
+    println!("📐 Pricing basis: {}", basis.mode());
+
+    // Snapshot the IV of every listed product for GET /volMovers
+    iv_history::start_snapshots(
+        &supervisor,
+        Repository::new(db_pool.clone()),
+        iv_policy::IvResolver::new(iv_source.clone(), iv_policy.clone()),
+        iv_history::IvHistoryConfig::from_env(),
+    );
+    println!("📐 Options without an IV are priced under the {} default IV policy", iv_policy.mode());
+
+    // Back the database up on a schedule so an operator mishap can be undone
+    match backup::BackupConfig::from_env() {
+        Some(config) => {
+            println!(
+                "💾 Database backups every {}s into {} (keeping {})",
+                config.interval.as_secs(),
+                config.dir.display(),
+                config.retention
+            );
+            backup::start_backups(&supervisor, Repository::new(db_pool.clone()), config);
+        }
+        None => println!("💾 BACKUP_INTERVAL_SECS=0, scheduled database backups are disabled"),
+    }
 
-    // Initialize the IV Oracle
-    let deribit_url = env::var("DERIBIT_API_URL")
-        .unwrap_or_else(|_| "https://www.deribit.com/api/v2".to_string());
-    let iv_oracle = Arc::new(iv_oracle::IvOracle::new(deribit_url));
-    
-    // Initialize IV oracle with data before starting server
-    println!("🔄 Initializing IV Oracle with market data...");
-    if let Err(e) = iv_oracle.initialize().await {
-        eprintln!("WARNING: Failed to initialize IV Oracle: {}", e);
-        eprintln!("The server will start but IV data may not be immediately available.");
+    // Snapshot hourly volume and open interest into market_stats for GET /stats/history
+    stats::start_stats_aggregation(&supervisor, Repository::new(db_pool.clone()), price_oracle.clone(), assets.clone());
+
+    // Contracts stored with float noise in their strike group with their product
+    match Repository::new(db_pool.clone()).run(move |conn| strike_ticks.normalize_stored(conn)).await {
+        Ok(0) => {}
+        Ok(normalized) => println!("📏 Snapped the strikes of {} contracts to their tick", normalized),
+        Err(e) => {
+            eprintln!("ERROR: Failed to normalize stored strikes: {}", e);
+            std::process::exit(1);
+        }
     }
-    
-    // Start background updates after initial data is loaded
-    iv_oracle.start_updates().await;
 
-    // Initialize the Price Oracle with gRPC
-    let aggregator_url = env::var("AGGREGATOR_URL")
-        .unwrap_or_else(|_| "http://localhost:50051".to_string());
-    let price_oracle = Arc::new(
-        price_oracle::PriceOracle::new(aggregator_url)
-            .await
-            .unwrap_or_else(|e| {
-                eprintln!("ERROR: {}", e);
-                std::process::exit(1);
-            })
+    // 24h volume and premium changes of /topBanner and /marketHighlights, kept current by trades
+    let now = chrono::Utc::now().timestamp();
+    let rolling_metrics = Repository::new(db_pool.clone())
+        .run(move |conn| rolling_metrics::RollingMetrics::load(conn, now))
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("ERROR: Failed to load 24h market metrics: {}", e);
+            std::process::exit(1);
+        });
+
+    // List the product catalog around spot daily and expire matured products
+    catalog::start_listing_job(
+        &supervisor,
+        Repository::new(db_pool.clone()),
+        price_oracle.clone(),
+        GridConfig::from_env(),
+        assets.clone(),
+        catalog::CatalogConfig::from_env(),
     );
 
     // Initialize Mutiny Wallet
-    let pool_network = match env::var("POOL_NETWORK").unwrap_or_else(|_| "signet".to_string()).as_str() {
-        "mainnet" => Network::Mainnet,
-        "testnet" => Network::Testnet,
-        _ => Network::Signet,
+    let pool_network: Network = env::var("POOL_NETWORK").ok().and_then(|v| v.parse().ok()).unwrap_or(Network::Signet);
+    let wallet_for = |network: Network| {
+        let wallet = if offline {
+            MutinyWallet::with_custom_url(format!("{}/mempool", mock_config.base_url()), network)
+        } else {
+            let esplora = EsploraConfig::from_env(network).unwrap_or_else(|e| {
+                eprintln!("ERROR: Invalid Esplora configuration: {}", e);
+                std::process::exit(1);
+            });
+            MutinyWallet::with_esplora(esplora, network)
+        };
+        Arc::new(wallet.with_http_client(http_client.clone()))
     };
-    let mutiny_wallet = Arc::new(MutinyWallet::new(pool_network));
+    let mutiny_wallet = wallet_for(pool_network);
+    if !offline && mutiny_wallet.esplora() != &EsploraConfig::public(pool_network) {
+        println!("🔗 Esplora API for {}: {}", pool_network, mutiny_wallet.esplora().base_url);
+    }
+    // Balances of pools added with `optadmin pools add` are read on their own network
+    let network_wallets: HashMap<Network, Arc<dyn WalletSource>> = [Network::Mainnet, Network::Testnet, Network::Signet]
+        .into_iter()
+        .map(|network| (network, wallet_for(network) as Arc<dyn WalletSource>))
+        .collect();
     
     // Get pool address from environment
-    let pool_address = env::var("POOL_ADDRESS")
-        .expect("POOL_ADDRESS must be set in environment");
+    let pool_address = if offline {
+        env::var("POOL_ADDRESS").unwrap_or_else(|_| match pool_network {
+            Network::Mainnet => "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4".to_string(),
+            Network::Testnet | Network::Signet => "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx".to_string(),
+        })
+    } else {
+        env::var("POOL_ADDRESS").expect("POOL_ADDRESS must be set in environment")
+    };
+    if let Err(e) = mutiny_wallet.validate_address(&pool_address) {
+        eprintln!("ERROR: POOL_ADDRESS is not a {} address. {}", pool_network, e);
+        std::process::exit(1);
+    }
 
     // Create app state
-    let app_state = Arc::new(AppState {
-        db_pool: db_pool.clone(),
-        iv_oracle: iv_oracle.clone(),
-        price_oracle: price_oracle.clone(),
-        mutiny_wallet: mutiny_wallet.clone(),
-        pool_address: pool_address.clone(),
+    // How long a priced options table may be served before re-pricing
+    let options_table_cache_secs: u64 = env::var("OPTIONS_TABLE_CACHE_SECS")
+        .unwrap_or_else(|_| "5".to_string())
+        .parse()
+        .unwrap_or(5);
+
+    // Announce contracts approaching expiry to the configured webhooks
+    let expiry_notice = expiry::ExpiryNoticeConfig::from_env();
+    let event_sink: Option<Arc<dyn EventSink>> = match WebhookSink::from_env() {
+        Some(sink) => {
+            println!(
+                "🔔 Expiry notices {:.1}h before expiry to {} webhook(s)",
+                expiry_notice.notice.as_secs_f64() / 3600.0,
+                sink.urls().len()
+            );
+            let sink: Arc<dyn EventSink> = Arc::new(sink);
+            expiry::start_expiry_notifier(&supervisor, Repository::new(db_pool.clone()), sink.clone(), expiry_notice);
+            Some(sink)
+        }
+        None => {
+            println!("🔕 WEBHOOK_URLS not set, contract notifications are disabled");
+            None
+        }
+    };
+
+    // Deliver outbox events (trades, settlements, marks, risk alerts) to the webhooks, the
+    // message bus and /ws/events
+    let mut outbox_sinks: Vec<Arc<dyn EventSink>> = event_sink.into_iter().collect();
+    match message_bus::MessageBusConfig::from_env() {
+        Ok(Some(config)) => match message_bus::connect(&config).await {
+            Ok(sink) => {
+                println!("📨 Publishing events to {} at {} under {}.*", config.backend, config.url, config.topic_prefix);
+                outbox_sinks.push(sink);
+            }
+            Err(e) => {
+                eprintln!("ERROR: {}", e);
+                std::process::exit(1);
+            }
+        },
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("ERROR: {}", e);
+            std::process::exit(1);
+        }
+    }
+    let outbox_sink: Option<Arc<dyn EventSink>> = match outbox_sinks.len() {
+        0 => None,
+        1 => outbox_sinks.pop(),
+        _ => Some(Arc::new(outbox::Fanout::new(outbox_sinks))),
+    };
+    let events = outbox::EventBroadcast::default();
+    outbox::start_outbox_publisher(
+        &supervisor,
+        Repository::new(db_pool.clone()),
+        outbox_sink,
+        events.clone(),
+        outbox::OutboxConfig::from_env(),
+    );
+
+    // Go reduce-only while the price oracle fails its health checks
+    let price_guards = PriceGuards::from_env();
+    trading_state::start_oracle_monitor(
+        &supervisor,
+        Repository::new(db_pool.clone()),
+        price_oracle.clone(),
+        price_guards.clone(),
+        assets.clone(),
+        trading_state::OracleMonitorConfig::from_env(),
+    );
+
+    // Contracts open once their premium is paid on-chain or over Lightning, when required
+    let payment_config = payments::PaymentConfig::from_env();
+    let lightning_node = lightning::lightning_node_from_env().unwrap_or_else(|e| {
+        eprintln!("ERROR: Invalid Lightning configuration: {}", e);
+        std::process::exit(1);
+    });
+    if payment_config.required {
+        println!(
+            "💸 Premiums paid on-chain: {} confirmation(s) within {}s",
+            payment_config.confirmations,
+            payment_config.timeout.as_secs()
+        );
+        if let Some(node) = &lightning_node {
+            println!("⚡ Lightning invoices issued by {}", node.name());
+        }
+        payments::start_payment_watcher(
+            &supervisor,
+            Repository::new(db_pool.clone()),
+            mutiny_wallet.clone(),
+            lightning_node.clone(),
+            payment_config,
+        );
+    }
+
+    // Sign the price of every maturity, for GET /attestations/{date} and DLC settlement
+    let oracle_signer = attestation::OracleSigner::from_env()
+        .unwrap_or_else(|e| {
+            eprintln!("ERROR: Invalid ORACLE_SIGNING_KEY: {}", e);
+            std::process::exit(1);
+        })
+        .map(Arc::new);
+    match &oracle_signer {
+        Some(signer) => println!("🔏 Oracle attestation key: {}", signer.public_key_hex()),
+        None => eprintln!(
+            "⚠️  WARNING: ORACLE_SIGNING_KEY is not set, settlement prices are not attested and \
+             /attestations and DLC descriptors are unavailable"
+        ),
+    }
+    // Settlement prices are averaged over samples taken before each maturity
+    let settlement_config = settlement::SettlementConfig::from_env().unwrap_or_else(|e| {
+        eprintln!("ERROR: Invalid settlement configuration: {}", e);
+        std::process::exit(1);
+    });
+    println!(
+        "⚖️  Settlement price: {} over {}s before expiry",
+        settlement_config.method,
+        settlement_config.window.as_secs()
+    );
+    settlement::start_settlement_sampler(
+        &supervisor,
+        Repository::new(db_pool.clone()),
+        price_oracle.clone(),
+        settlement_config,
+    );
+    // Prices are attested in as many digits as DLC descriptors announce
+    let dlc_config = dlc::DlcConfig::from_env();
+    if let Some(signer) = &oracle_signer {
+        attestation::start_attestation_job(
+            &supervisor,
+            Repository::new(db_pool.clone()),
+            price_oracle.clone(),
+            signer.clone(),
+            settlement_config,
+            dlc_config.price_digits,
+        );
+    }
+
+    // Margin model for position risk: max_loss (default) or scenario_grid
+    let margin_model = margin_model_from_env().unwrap_or_else(|e| {
+        eprintln!("ERROR: Invalid margin model: {}", e);
+        std::process::exit(1);
     });
+    println!("🧮 Margin model: {}", margin_model.name());
+
+    // Go reduce-only while the margin of the book uses up most of the pools' collateral
+    let utilization_monitor = utilization::UtilizationMonitorConfig::from_env();
+    match &utilization_monitor {
+        Some(config) => println!(
+            "📈 Reduce-only at {:.0}% collateral utilization, reopening under {:.0}%",
+            config.reduce_only_percent, config.resume_percent
+        ),
+        None => println!("🔕 UTILIZATION_REDUCE_ONLY_PERCENT is 0, the utilization monitor is disabled"),
+    }
+
+    // Cap the cumulative notional of accounts by KYC status
+    let kyc = kyc::KycConfig::from_env().unwrap_or_else(|e| {
+        eprintln!("ERROR: Invalid KYC configuration: {}", e);
+        std::process::exit(1);
+    });
+    if kyc.is_none() {
+        println!("🪪 KYC_LIMITS is not set, trade size is not gated by KYC status");
+    }
+
+    // Session tokens for frontend users logging in at /auth/login
+    let jwt = auth::JwtConfig::from_env().unwrap_or_else(|e| {
+        eprintln!("ERROR: Invalid JWT_SECRET: {}", e);
+        std::process::exit(1);
+    });
+    match &jwt {
+        Some(jwt) => println!("🔐 Session login enabled, tokens valid for {}s", jwt.ttl.as_secs()),
+        None => println!("🔐 JWT_SECRET is not set, session login is disabled"),
+    }
+
+    // Guarded routes and FIX logons need an API key or a session unless auth is disabled
+    let auth_config = auth::AuthConfig::from_env();
+    if auth_config.disabled {
+        eprintln!("⚠️  WARNING: AUTH_DISABLED is set, anyone reaching this server can trade and administer the pool");
+        eprintln!("⚠️  WARNING: Never set AUTH_DISABLED outside local development");
+    } else {
+        let has_credentials = db_pool.get().map_err(|e| e.to_string()).and_then(|conn| {
+            Ok(api_keys::has_active_keys(&conn).map_err(|e| e.to_string())?
+                || auth::has_users(&conn).map_err(|e| e.to_string())?)
+        });
+        match has_credentials {
+            Ok(true) => {}
+            Ok(false) => eprintln!(
+                "WARNING: No API key or user exists, guarded routes refuse every request until one is \
+                 created with `optadmin rotate-api-key` or `optadmin users set`"
+            ),
+            Err(e) => eprintln!("WARNING: Failed to check for API keys and users: {}", e),
+        }
+    }
+
+    // Analytics and listings read from their own connections, so they cannot hold up trades
+    let mut repository = Repository::new(db_pool.clone());
+    match db::create_read_pool() {
+        Ok(Some(read_pool)) => {
+            println!("📖 Analytics reads use {} separate read connections", read_pool.max_size());
+            repository = repository.with_read_pool(read_pool);
+        }
+        Ok(None) => println!("📖 DB_READ_POOL_MAX_SIZE=0, analytics reads share the main pool"),
+        Err(e) => {
+            eprintln!("ERROR: Failed to open the read pool: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    let app_state = Arc::new(AppState::new(
+        repository,
+        iv_source,
+        price_oracle.clone(),
+        mutiny_wallet.clone(),
+        pool_address.clone(),
+        GridConfig::from_env(),
+        std::time::Duration::from_secs(options_table_cache_secs),
+    )
+    .with_price_guards(price_guards)
+    .with_assets(assets)
+    .with_expiry_notice(expiry_notice)
+    .with_position_limits(PositionLimits::from_env())
+    .with_kyc(kyc)
+    .with_iv_policy(iv_policy)
+    .with_rates(rates)
+    .with_basis(basis)
+    .with_rolling_metrics(Arc::new(rolling_metrics))
+    .with_auth(auth_config)
+    .with_jwt(jwt)
+    .with_orderbook(OrderbookConfig::from_env())
+    .with_quoting(QuotingConfig::from_env())
+    .with_payments(payment_config)
+    .with_reservations(ReservationConfig::from_env())
+    .with_fees(FeeSchedule::from_env())
+    .with_api_v1(api_v1::DeprecationConfig::from_env())
+    .with_lightning(lightning_node)
+    .with_dlc(dlc_config)
+    .with_oracle_signer(oracle_signer)
+    .with_health(health::HealthConfig::from_env())
+    .with_supervisor(supervisor.clone())
+    .with_upstream_timeouts(upstream_timeouts)
+    .with_margin_model(margin_model)
+    .with_margin_cache(MarginCache::from_env().map(Arc::new))
+    .with_network_wallets(network_wallets)
+    .with_utilization_monitor(utilization_monitor)
+    .with_events(events));
+
+    if let Some(config) = utilization_monitor {
+        utilization::start_utilization_monitor(&supervisor, app_state.clone(), config);
+    }
+
+    // FIX 4.4 acceptor for institutional takers, trading through the same flow as POST /contract
+    let fix_config = fix::FixConfig::from_env();
+    if fix_config.listen_addr.is_none() {
+        println!("🔕 FIX_LISTEN_ADDR not set, the FIX gateway is disabled");
+    }
+    fix::start_fix_gateway(&supervisor, fix_config, Repository::new(db_pool.clone()), app_state.clone());
     
     // Check pool wallet balance at initialization
     println!("🔍 Checking pool wallet balance at startup...");
@@ -289,954 +655,20 @@ async fn main() -> std::io::Result<()> {
     let server1 = HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(app_state.clone()))
-            .wrap(middleware::Logger::default())
-            // Health check endpoints
-            .route("/", web::get().to(health_check))
-            .route("/health", web::get().to(health_check))
-            // Register API endpoints
-            .service(web::resource("/contract").route(web::post().to(post_contract)))
-            .service(web::resource("/contracts").route(web::get().to(get_contracts)))
-            .service(web::resource("/optionsTable").route(web::get().to(get_options_table)))
-            .service(web::resource("/delta").route(web::get().to(get_delta)))
-            // Analytics endpoints
-            .service(web::resource("/topBanner").route(web::get().to(get_top_banner)))
-            .service(web::resource("/marketHighlights").route(web::get().to(get_market_highlights)))
-            .service(web::resource("/topGainers").route(web::get().to(get_top_gainers)))
-            .service(web::resource("/topVolume").route(web::get().to(get_top_volume)))
+            .wrap(middleware::from_fn(auth::middleware))
+            .wrap(middleware::from_fn(api_v1::middleware))
+            .wrap(middleware::from_fn(request_id::middleware))
+            .wrap(middleware::Logger::new(
+                r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T request_id=%{x-request-id}o"#,
+            ))
+            // v1 is served unprefixed as well, for clients from before versioning
+            .service(web::scope(api_v1::PREFIX).configure(api::configure))
+            .service(web::scope(api_v2::PREFIX).wrap(middleware::from_fn(api_v2::middleware)).configure(api::configure))
+            .configure(api::configure)
     })
     .bind("0.0.0.0:8080")?
     .run();
 
-    // Start the mock API server
-    let server2 = mock_apis::mock_server();
-
-    // Run both servers concurrently
-    let _ = tokio::join!(server1, server2);
-
-    Ok(())
-}
-
-impl AppState {
-    // Helper method to get pool balance in BTC
-    async fn get_pool_balance_btc(&self) -> Result<f64, ApiError> {
-        let wallet_balance = self.mutiny_wallet
-            .get_wallet_balance(&self.pool_address)
-            .await
-            .map_err(|e| ApiError::ExternalApiError(format!("Failed to get pool balance: {}", e)))?;
-        
-        // Convert satoshis to BTC
-        Ok(MutinyWallet::satoshis_to_btc(wallet_balance.total_balance))
-    }
-}
-
-// Helper function to convert duration strings to seconds
-fn duration_to_seconds(duration: &str) -> i64 {
-    let d = duration.trim();
-    let (num_str, unit) = d.split_at(d.len() - 1);
-    let num: i64 = num_str.parse().unwrap_or(0);
-    
-    match unit {
-        "m" => num * 60,           // minutes to seconds
-        "h" => num * 60 * 60,      // hours to seconds  
-        "d" => num * 24 * 60 * 60, // days to seconds
-        _ => 0,
-    }
-}
-
-// GET / - Health check endpoint
-async fn health_check() -> Result<impl Responder, ApiError> {
-    Ok(HttpResponse::Ok().json(serde_json::json!({
-        "status": "healthy",
-        "service": "BTC Options API",
-        "version": "1.0.0"
-    })))
+    server1.await
 }
 
-// POST /contract - Create new contract
-async fn post_contract(
-    contract: web::Json<Contract>,
-    state: web::Data<Arc<AppState>>,
-) -> Result<impl Responder, ApiError> {
-    // Log incoming contract request
-    println!("📥 POST /contract request:");
-    println!("   Side: {:?}", contract.side);
-    println!("   Strike: ${:.2}", contract.strike_price);
-    println!("   Quantity: {:.8} BTC", contract.quantity);
-    println!("   Premium: {:.8} BTC", contract.premium);
-    println!("   Expires: {}", contract.expires);
-    
-    // Validation
-    let now = Utc::now().timestamp();
-    if contract.expires <= now {
-        eprintln!("❌ Contract validation failed: expiration date ({}) is not in the future (now: {})", contract.expires, now);
-        return Err(ApiError::ValidationError(
-            "Contract expiration date must be in the future.".to_string(),
-        ));
-    }
-
-    // Get collateral parameters
-    let collateral_rate: f64 = env::var("COLLATERAL_RATE")
-        .unwrap_or_else(|_| "0.5".to_string())
-        .parse()
-        .unwrap_or(0.5);
-
-    // Get real pool balance from Mutiny wallet (actual BTC balance from blockchain)
-    let pool_qty: f64 = state.get_pool_balance_btc().await?;
-
-    // Get BTC price from oracle
-    let btc_price = state
-        .price_oracle
-        .get_btc_price()
-        .await
-        .map_err(|e| ApiError::PriceOracleError(e.to_string()))?;
-
-    // Initialize risk manager
-    let risk_margin = env::var("RISK_MARGIN")
-        .unwrap_or_else(|_| "1.2".to_string())
-        .parse()
-        .unwrap_or(1.2);
-    let risk_free_rate: f64 = env::var("RISK_FREE_RATE")
-        .unwrap_or_else(|_| "0.0".to_string())
-        .parse()
-        .unwrap_or(0.0);
-    
-    let risk_manager = RiskManager::new(risk_margin);
-    
-    // Get existing contracts to calculate current risk exposure
-    let conn = state.db_pool.get()?;
-    let mut stmt = conn.prepare(
-        "SELECT side, strike_price_cents, quantity_str, expires, premium_str FROM contracts WHERE expires > ?1"
-    )?;
-    
-    let contracts_iter = stmt.query_map(params![now], |row| {
-        let quantity_str: String = row.get(2)?;
-        let premium_str: String = row.get(4)?;
-        
-        Ok(Contract {
-            side: row.get(0)?,
-            strike_price: cents_to_usd(row.get(1)?),
-            quantity: db_string_to_float(&quantity_str).unwrap_or(0.0),
-            expires: row.get(3)?,
-            premium: db_string_to_float(&premium_str).unwrap_or(0.0),
-        })
-    })?;
-    
-    let mut existing_contracts: Vec<Contract> = contracts_iter
-        .collect::<Result<Vec<_>, _>>()
-        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
-    
-    // Calculate current risk exposure WITHOUT the new contract
-    let iv_oracle_closure = |side_str: &str, strike: f64, expire: &str| {
-        state.iv_oracle.get_iv(side_str, strike, expire)
-    };
-    
-    let total_existing_risk = risk_manager.calculate_portfolio_risk(
-        &existing_contracts,
-        btc_price,
-        risk_free_rate,
-        &iv_oracle_closure,
-    );
-    
-    // Calculate available collateral
-    let total_collateral_usd = pool_qty * btc_price * collateral_rate;
-    let available_collateral_usd = total_collateral_usd - total_existing_risk;
-    
-    // Get IV for the new contract
-    let time_to_expiry = (contract.expires - now) as f64 / (365.0 * 24.0 * 60.0 * 60.0);
-    let side_str = match contract.side {
-        OptionSide::Call => "C",
-        OptionSide::Put => "P",
-    };
-    let expire_timestamp_ms = (contract.expires * 1000).to_string();
-    let iv = state.iv_oracle.get_iv(side_str, contract.strike_price, &expire_timestamp_ms)
-        .unwrap_or(0.4);
-    
-    // Calculate maximum allowed quantity for this specific contract
-    let max_quantity = risk_manager.calculate_max_quantity(
-        &contract.side,
-        contract.strike_price,
-        contract.premium,
-        btc_price,
-        iv,
-        time_to_expiry,
-        risk_free_rate,
-        available_collateral_usd,
-        total_existing_risk,
-    );
-    
-    // Log risk calculation details
-    println!("📊 Contract Risk Analysis:");
-    println!("   Contract: {} expires {} @ ${} for {} qty", 
-        contract.side, contract.expires, contract.strike_price, contract.quantity);
-    println!("   Max allowed quantity: {:.2}", max_quantity);
-    println!("   Available collateral: ${:.2}", available_collateral_usd);
-    println!("   Existing portfolio risk: ${:.2}", total_existing_risk);
-    
-    // Check if requested quantity exceeds maximum allowed
-    if contract.quantity > max_quantity {
-        eprintln!("❌ Contract validation failed: requested quantity ({:.8}) exceeds maximum allowed ({:.8})", 
-            contract.quantity, max_quantity);
-        eprintln!("   Available collateral: ${:.2}", available_collateral_usd);
-        eprintln!("   Existing risk exposure: ${:.2}", total_existing_risk);
-        eprintln!("   Total collateral pool: ${:.2}", total_collateral_usd);
-        return Err(ApiError::ValidationError(
-            format!(
-                "Requested quantity ({:.8}) exceeds maximum allowed quantity ({:.8}). \
-                Available collateral: ${:.2}, \
-                Existing risk exposure: ${:.2}, \
-                Total collateral pool: ${:.2}",
-                contract.quantity,
-                max_quantity,
-                available_collateral_usd,
-                total_existing_risk,
-                total_collateral_usd
-            ),
-        ));
-    }
-    
-    // Now check total risk with the new contract
-    existing_contracts.push((*contract).clone());
-    let total_risk_with_new = risk_manager.calculate_portfolio_risk(
-        &existing_contracts,
-        btc_price,
-        risk_free_rate,
-        &iv_oracle_closure,
-    );
-    
-    if total_risk_with_new > total_collateral_usd {
-        // This should not happen if max_quantity check above is working correctly
-        // But we keep it as a safety check
-        let position_risk = risk_manager.calculate_position_risk(
-            &contract.side,
-            contract.strike_price,
-            contract.premium,
-            contract.quantity,
-            btc_price,
-            iv,
-            time_to_expiry,
-            risk_free_rate,
-        );
-        
-        eprintln!("❌ Contract validation failed: risk exceeds available collateral");
-        eprintln!("   New position margin required: ${:.2}", position_risk.margin_required);
-        eprintln!("   Total portfolio margin would be: ${:.2}", total_risk_with_new);
-        eprintln!("   Available collateral: ${:.2}", total_collateral_usd);
-        
-        return Err(ApiError::ValidationError(
-            format!(
-                "Contract risk exceeds available collateral. \
-                New position margin required: ${:.2}, \
-                Total portfolio margin would be: ${:.2}, \
-                Available collateral: ${:.2}",
-                position_risk.margin_required,
-                total_risk_with_new,
-                total_collateral_usd
-            ),
-        ));
-    }
-
-    // Save to database with proper conversions
-    let rounded_quantity = round_btc(contract.quantity);
-    let rounded_premium = round_btc(contract.premium);
-    
-    conn.execute(
-        "INSERT INTO contracts (side, strike_price_cents, quantity_str, expires, premium_str) 
-         VALUES (?1, ?2, ?3, ?4, ?5)",
-        params![
-            contract.side,
-            usd_to_cents(contract.strike_price),
-            float_to_db_string(rounded_quantity, BTC_PRECISION),
-            contract.expires,
-            float_to_db_string(rounded_premium, BTC_PRECISION)
-        ],
-    )?;
-
-    // Save to premium history
-    let product_key = format!("{}-{}-{}", contract.side, usd_to_cents(contract.strike_price), contract.expires);
-    let _ = conn.execute(
-        "INSERT OR REPLACE INTO premium_history (product_key, side, strike_price_cents, expires, premium_str) 
-         VALUES (?1, ?2, ?3, ?4, ?5)",
-        params![
-            product_key,
-            contract.side,
-            usd_to_cents(contract.strike_price),
-            contract.expires,
-            float_to_db_string(rounded_premium, BTC_PRECISION)
-        ],
-    );
-
-    Ok(HttpResponse::Ok().finish())
-}
-
-// GET /contracts - List all contracts
-async fn get_contracts(state: web::Data<Arc<AppState>>) -> Result<impl Responder, ApiError> {
-    let conn = state.db_pool.get()?;
-    let mut stmt = conn.prepare(
-        "SELECT side, strike_price_cents, quantity_str, expires, premium_str FROM contracts"
-    )?;
-
-    let contracts_iter = stmt.query_map([], |row| {
-        Ok(ContractResponse {
-            side: row.get(0)?,
-            strike_price: cents_to_usd(row.get(1)?),
-            quantity: row.get(2)?,  // Keep as string
-            expires: row.get(3)?,
-            premium: row.get(4)?,   // Keep as string
-        })
-    })?;
-
-    let contracts: Vec<ContractResponse> = contracts_iter
-        .collect::<Result<Vec<_>, _>>()
-        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
-
-    Ok(HttpResponse::Ok().json(contracts))
-}
-
-// GET /optionsTable - Generate options table with automatic parameters
-async fn get_options_table(
-    state: web::Data<Arc<AppState>>,
-) -> Result<impl Responder, ApiError> {
-    // Get current BTC price from gRPC oracle
-    let btc_price = state
-        .price_oracle
-        .get_btc_price()
-        .await
-        .map_err(|e| ApiError::PriceOracleError(e.to_string()))?;
-    
-    println!("📊 Generating options table for BTC price: ${:.2}", btc_price);
-    
-    // Check IV cache status
-    let cache_size = state.iv_oracle.get_cache_size();
-    if cache_size > 0 {
-        println!("✅ IV cache populated with {} entries", cache_size);
-    } else {
-        println!("⚠️ IV cache is empty - fetching may be slower");
-    }
-    
-    // Generate strike prices: round to nearest 1000, then ±5 strikes
-    let center_strike = (btc_price / 1000.0).round() * 1000.0;
-    let mut strike_prices: Vec<f64> = Vec::new();
-    
-    // Generate strikes from -5000 to +5000 relative to center
-    for i in -5..=5 {
-        let strike = center_strike + (i as f64 * 1000.0);
-        if strike > 0.0 { // Ensure positive strikes only
-            strike_prices.push(strike);
-        }
-    }
-    
-    // Sort strikes in ascending order
-    strike_prices.sort_by(|a, b| a.partial_cmp(b).unwrap());
-    
-    println!("🎯 Generated {} strike prices: {:?}", strike_prices.len(), strike_prices);
-    
-    // Generate expiries: 1d, 2d, 3d, 5d, 7d
-    let expires: Vec<String> = vec![
-        "1d".to_string(),
-        "2d".to_string(), 
-        "3d".to_string(),
-        "5d".to_string(),
-        "7d".to_string(),
-    ];
-    
-    println!("⏰ Generated expiries: {:?}", expires);
-
-    // Get financial parameters
-    let risk_free_rate: f64 = env::var("RISK_FREE_RATE")
-        .unwrap_or_else(|_| "0.0".to_string())
-        .parse()
-        .unwrap_or(0.0);
-    let collateral_rate: f64 = env::var("COLLATERAL_RATE")
-        .unwrap_or_else(|_| "0.5".to_string())
-        .parse()
-        .unwrap_or(0.5);
-
-    // Get real pool balance from Mutiny wallet (actual BTC balance from blockchain)
-    let pool_qty: f64 = state.get_pool_balance_btc().await?;
-
-    // Initialize client for IV fallback
-    let client = Client::new();
-
-    let btc_price = state
-        .price_oracle
-        .get_btc_price()
-        .await
-        .map_err(|e| ApiError::PriceOracleError(e.to_string()))?;
-
-    // Initialize risk manager with 20% safety margin
-    let risk_margin = env::var("RISK_MARGIN")
-        .unwrap_or_else(|_| "1.2".to_string())
-        .parse()
-        .unwrap_or(1.2);
-    let risk_manager = RiskManager::new(risk_margin);
-    
-    // Get existing contracts to calculate current risk exposure
-    let conn = state.db_pool.get()?;
-    let mut stmt = conn.prepare(
-        "SELECT side, strike_price_cents, quantity_str, expires, premium_str FROM contracts WHERE expires > ?1"
-    )?;
-    let now = Utc::now().timestamp();
-    
-    let contracts_iter = stmt.query_map(params![now], |row| {
-        let quantity_str: String = row.get(2)?;
-        let premium_str: String = row.get(4)?;
-        
-        Ok(Contract {
-            side: row.get(0)?,
-            strike_price: cents_to_usd(row.get(1)?),
-            quantity: db_string_to_float(&quantity_str).unwrap_or(0.0),
-            expires: row.get(3)?,
-            premium: db_string_to_float(&premium_str).unwrap_or(0.0),
-        })
-    })?;
-    
-    let existing_contracts: Vec<Contract> = contracts_iter
-        .collect::<Result<Vec<_>, _>>()
-        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
-    
-    // Calculate total existing risk exposure
-    let iv_oracle_closure = |side_str: &str, strike: f64, expire: &str| {
-        state.iv_oracle.get_iv(side_str, strike, expire)
-    };
-    
-    let total_existing_risk = risk_manager.calculate_portfolio_risk(
-        &existing_contracts,
-        btc_price,
-        risk_free_rate,
-        &iv_oracle_closure,
-    );
-    
-    // Calculate available collateral
-    let total_collateral_usd = pool_qty * btc_price * collateral_rate;
-    let available_collateral_usd = total_collateral_usd - total_existing_risk;
-    
-    println!("💰 Risk Analysis:");
-    println!("   Total Collateral: ${:.2}", total_collateral_usd);
-    println!("   Existing Risk Exposure: ${:.2}", total_existing_risk);
-    println!("   Available Collateral: ${:.2}", available_collateral_usd);
-    println!("   Risk Margin: {:.0}%", (risk_margin - 1.0) * 100.0);
-
-    let mut table = Vec::new();
-    let sides = [OptionSide::Call, OptionSide::Put];
-
-    // Generate options table
-    for strike_price in &strike_prices {
-        for expire in &expires {
-            for side in &sides {
-                // Get IV from oracle
-                let side_str = match side {
-                    OptionSide::Call => "C",
-                    OptionSide::Put => "P",
-                };
-
-                // Convert expire string to timestamp for IV oracle
-                let expire_for_iv = if expire.ends_with('d') || expire.ends_with('h') || expire.ends_with('m') {
-                    // For durations, calculate future timestamp in milliseconds
-                    let duration_seconds = duration_to_seconds(expire);
-                    let future_timestamp_ms = (Utc::now().timestamp() + duration_seconds) * 1000;
-                    future_timestamp_ms.to_string()
-                } else {
-                    // Assume it's already a timestamp or other format
-                    expire.clone()
-                };
-                
-                // Get IV from cache (should be pre-populated)
-                let iv = state.iv_oracle.get_iv(side_str, *strike_price, &expire_for_iv)
-                    .unwrap_or(0.3); // Default IV if not found in cache
-
-                let t = parse_duration(expire);
-
-                // Calculate premium using Black-Scholes (returns USD value)
-                let premium_usd = match side {
-                    OptionSide::Call => black_scholes::call(
-                        btc_price,
-                        *strike_price,
-                        risk_free_rate,
-                        iv,
-                        t,
-                    ),
-                    OptionSide::Put => black_scholes::put(
-                        btc_price,
-                        *strike_price,
-                        risk_free_rate,
-                        iv,
-                        t,
-                    ),
-                };
-                
-                // Convert premium from USD to BTC
-                let premium_btc = premium_usd / btc_price;
-
-                // Calculate delta using Black-Scholes
-                let delta = match side {
-                    OptionSide::Call => black_scholes::call_delta(
-                        btc_price,
-                        *strike_price,
-                        risk_free_rate,
-                        iv,
-                        t,
-                    ),
-                    OptionSide::Put => black_scholes::put_delta(
-                        btc_price,
-                        *strike_price,
-                        risk_free_rate,
-                        iv,
-                        t,
-                    ),
-                };
-
-                // Calculate risk-based max_quantity considering:
-                // 1. Option-specific risk (max loss potential)
-                // 2. Existing portfolio risk exposure
-                // 3. Available collateral after risk margin
-                let max_quantity = risk_manager.calculate_max_quantity(
-                    side,
-                    *strike_price,
-                    premium_btc,
-                    btc_price,
-                    iv,
-                    t,
-                    risk_free_rate,
-                    available_collateral_usd,
-                    total_existing_risk,
-                );
-
-                table.push(OptionsTableResponse {
-                    side: side.clone(),
-                    strike_price: *strike_price,
-                    expire: expire.clone(),
-                    premium: format_btc(premium_btc),  // Format as string with 8 decimals
-                    max_quantity: format_btc(max_quantity),  // Format as string with 8 decimals
-                    iv,
-                    delta,
-                });
-            }
-        }
-    }
-
-    // Display formatted options table
-    println!("\n📊 Generated Options Table Summary:");
-    println!("   Total Options: {}", table.len());
-    println!("   Strike Prices: {} (from ${} to ${})", 
-        strike_prices.len(), 
-        strike_prices.first().unwrap_or(&0.0),
-        strike_prices.last().unwrap_or(&0.0)
-    );
-    println!("   Expiries: {}", expires.len());
-    println!("   Current BTC Price: ${:.2}", btc_price);
-    
-    // Create formatted table display
-    println!("\n🎯 Options Table:");
-    println!("{:-<140}", "-");
-    println!("{:<6} {:<10} {:<10} {:<12} {:<12} {:<10} {:<10} {:<12}", 
-        "Type", "Strike", "Expiry", "Premium(BTC)", "Max Qty", "IV", "Delta", "Value(USD)");
-    println!("{:-<140}", "-");
-    
-    // Group by expiry for better display
-    for expire in &expires {
-        println!("\n📅 Expiry: {}", expire);
-        
-        // Sort options for this expiry by strike price
-        let mut expiry_options: Vec<&OptionsTableResponse> = table.iter()
-            .filter(|opt| opt.expire == *expire)
-            .collect();
-        expiry_options.sort_by(|a, b| {
-            a.strike_price.partial_cmp(&b.strike_price).unwrap()
-                .then(a.side.to_string().cmp(&b.side.to_string()))
-        });
-        
-        for opt in expiry_options {
-            // Convert string premium to float only for calculation
-            let premium_f64 = db_string_to_float(&opt.premium).unwrap_or(0.0);
-            let option_value = premium_f64 * btc_price;
-            println!("{:<6} ${:<9.0} {:<10} ₿{:<11} {:<11} {:<9.4} {:<9.4} ${:<11.2}", 
-                format!("{}", opt.side),
-                opt.strike_price,
-                opt.expire,
-                opt.premium,     // Already formatted string
-                opt.max_quantity, // Already formatted string
-                opt.iv,
-                opt.delta,
-                option_value
-            );
-        }
-    }
-    
-    println!("{:-<140}", "-");
-    println!("\n💰 Pool Information:");
-    println!("   Pool Balance: {} BTC (${:.2} USD)", pool_qty, pool_qty * btc_price);
-    println!("   Collateral Rate: {:.0}%", collateral_rate * 100.0);
-    
-    Ok(HttpResponse::Ok().json(table))
-}
-
-// GET /delta - Calculate portfolio delta
-async fn get_delta(state: web::Data<Arc<AppState>>) -> Result<impl Responder, ApiError> {
-    let now = Utc::now().timestamp();
-    let conn = state.db_pool.get()?;
-
-    let mut stmt = conn.prepare(
-        "SELECT side, strike_price_cents, quantity_str, expires, premium_str FROM contracts WHERE expires > ?1"
-    )?;
-
-    let contracts_iter = stmt.query_map(params![now], |row| {
-        let quantity_str: String = row.get(2)?;
-        let premium_str: String = row.get(4)?;
-        Ok(Contract {
-            side: row.get(0)?,
-            strike_price: cents_to_usd(row.get(1)?),
-            quantity: db_string_to_float(&quantity_str).unwrap_or(0.0),
-            expires: row.get(3)?,
-            premium: db_string_to_float(&premium_str).unwrap_or(0.0),
-        })
-    })?;
-
-    let contracts: Vec<Contract> = contracts_iter
-        .collect::<Result<Vec<_>, _>>()
-        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
-
-    if contracts.is_empty() {
-        return Ok(HttpResponse::Ok().json(0.0));
-    }
-
-    let btc_price = state
-        .price_oracle
-        .get_btc_price()
-        .await
-        .map_err(|e| ApiError::PriceOracleError(e.to_string()))?;
-
-    let risk_free_rate: f64 = env::var("RISK_FREE_RATE")
-        .unwrap_or_else(|_| "0.0".to_string())
-        .parse()
-        .unwrap_or(0.0);
-
-    let mut total_delta = 0.0;
-
-    for contract in contracts.iter() {
-        let t = (contract.expires - now) as f64 / (365.0 * 24.0 * 60.0 * 60.0);
-        
-        // Convert contract.expires (seconds) to milliseconds for IV oracle
-        let expire_timestamp_ms = (contract.expires * 1000).to_string();
-        
-        // Try to get IV from oracle first
-        let side_str = match contract.side {
-            OptionSide::Call => "C",
-            OptionSide::Put => "P",
-        };
-        
-        let iv: f64 = state.iv_oracle.get_iv(side_str, contract.strike_price, &expire_timestamp_ms)
-            .unwrap_or(0.3); // Default IV if not found in cache
-
-        let delta = match contract.side {
-            OptionSide::Call => {
-                black_scholes::call_delta(btc_price, contract.strike_price, risk_free_rate, iv, t)
-            }
-            OptionSide::Put => {
-                black_scholes::put_delta(btc_price, contract.strike_price, risk_free_rate, iv, t)
-            }
-        };
-
-        total_delta += delta * contract.quantity;
-    }
-
-    Ok(HttpResponse::Ok().json(total_delta))
-}
-
-// GET /topBanner - Market statistics
-async fn get_top_banner(state: web::Data<Arc<AppState>>) -> Result<impl Responder, ApiError> {
-    let now = Utc::now().timestamp();
-    let twenty_four_hours_ago = now - (24 * 60 * 60);
-    
-    // Validate time range
-    if twenty_four_hours_ago >= now {
-        return Err(ApiError::ValidationError(
-            "Invalid time range".to_string(),
-        ));
-    }
-
-    let conn = state.db_pool.get()?;
-
-    // Get 24hr volume
-    let volume_24hr: f64 = conn
-        .query_row(
-            "SELECT COALESCE(SUM(CAST(quantity_str AS REAL)), 0.0) FROM contracts WHERE created_at >= ?1",
-            params![twenty_four_hours_ago],
-            |row| row.get(0),
-        )?;
-
-    // Get open interest
-    let mut stmt = conn.prepare(
-        "SELECT quantity_str, premium_str FROM contracts WHERE expires > ?1"
-    )?;
-    
-    let contracts_iter = stmt.query_map(params![now], |row| {
-        let quantity_str: String = row.get(0)?;
-        let premium_str: String = row.get(1)?;
-        Ok((
-            db_string_to_float(&quantity_str).unwrap_or(0.0),
-            db_string_to_float(&premium_str).unwrap_or(0.0)
-        ))
-    })?;
-
-    let mut open_interest_btc = 0.0;
-    for contract in contracts_iter {
-        if let Ok((quantity, premium)) = contract {
-            open_interest_btc += quantity * premium;
-        }
-    }
-
-    let btc_price = state
-        .price_oracle
-        .get_btc_price()
-        .await
-        .map_err(|e| ApiError::PriceOracleError(e.to_string()))?;
-    
-    let open_interest_usd = open_interest_btc * btc_price;
-
-    // Get contract count
-    let contract_count: i64 = conn
-        .query_row(
-            "SELECT COUNT(*) FROM contracts WHERE expires > ?1",
-            params![now],
-            |row| row.get(0),
-        )?;
-
-    Ok(HttpResponse::Ok().json(TopBannerResponse {
-        volume_24hr,
-        open_interest_usd,
-        contract_count,
-    }))
-}
-
-// GET /marketHighlights - Top products by volume
-async fn get_market_highlights(state: web::Data<Arc<AppState>>) -> Result<impl Responder, ApiError> {
-    let now = Utc::now().timestamp();
-    let twenty_four_hours_ago = now - (24 * 60 * 60);
-
-    let conn = state.db_pool.get()?;
-
-    let mut stmt = conn.prepare(
-        "SELECT side, strike_price_cents, expires, 
-                SUM(CAST(quantity_str AS REAL)) as total_volume, 
-                AVG(CAST(premium_str AS REAL)) as avg_premium
-         FROM contracts 
-         WHERE created_at >= ?1
-         GROUP BY side, strike_price_cents, expires
-         ORDER BY total_volume DESC
-         LIMIT 6"
-    )?;
-
-    let products_iter = stmt.query_map(params![twenty_four_hours_ago], |row| {
-        Ok((
-            row.get::<_, OptionSide>(0)?,
-            row.get::<_, i64>(1)?,  // strike_price_cents
-            row.get::<_, i64>(2)?,
-            row.get::<_, f64>(3)?,
-            row.get::<_, f64>(4)?,
-        ))
-    })?;
-
-    let mut highlights = Vec::new();
-
-    for product in products_iter {
-        if let Ok((side, strike_price_cents, expires, volume, current_premium)) = product {
-            let product_key = format!("{}-{}-{}", side, strike_price_cents, expires);
-            let strike_price = cents_to_usd(strike_price_cents);
-
-            // Get premium from 24 hours ago
-            let premium_24hr_ago_str: Option<String> = conn
-                .query_row(
-                    "SELECT premium_str FROM premium_history 
-                     WHERE product_key = ?1 AND timestamp <= ?2 
-                     ORDER BY timestamp DESC LIMIT 1",
-                    params![&product_key, twenty_four_hours_ago],
-                    |row| row.get(0),
-                )
-                .ok();
-
-            let premium_24hr_ago = premium_24hr_ago_str
-                .and_then(|s| db_string_to_float(&s).ok())
-                .unwrap_or(0.0);
-
-            let price_change_percent = if premium_24hr_ago > 0.0 {
-                ((current_premium - premium_24hr_ago) / premium_24hr_ago) * 100.0
-            } else {
-                0.0
-            };
-
-            let expire_string = format_expires_timestamp(expires);
-
-            highlights.push(MarketHighlightItem {
-                product_symbol: format!("BTC-{}-{}-{}", expire_string, strike_price, side),
-                side,
-                strike_price,
-                expire: expire_string,
-                volume_24hr: volume,
-                price_change_24hr_percent: price_change_percent,
-            });
-        }
-    }
-
-    Ok(HttpResponse::Ok().json(highlights))
-}
-
-// GET /topGainers - Top gainers by percentage
-async fn get_top_gainers(state: web::Data<Arc<AppState>>) -> Result<impl Responder, ApiError> {
-    let now = Utc::now().timestamp();
-    let twenty_four_hours_ago = now - (24 * 60 * 60);
-
-    let conn = state.db_pool.get()?;
-
-    let mut stmt = conn.prepare(
-        "SELECT DISTINCT side, strike_price_cents, expires FROM contracts WHERE expires > ?1"
-    )?;
-
-    let products_iter = stmt.query_map(params![now], |row| {
-        Ok((
-            row.get::<_, OptionSide>(0)?,
-            row.get::<_, i64>(1)?,  // strike_price_cents
-            row.get::<_, i64>(2)?,
-        ))
-    })?;
-
-    let mut gainers = Vec::new();
-
-    for product in products_iter {
-        if let Ok((side, strike_price_cents, expires)) = product {
-            // Get current premium
-            let current_premium_str: Option<String> = conn
-                .query_row(
-                    "SELECT premium_str FROM contracts 
-                     WHERE side = ?1 AND strike_price_cents = ?2 AND expires = ?3 
-                     ORDER BY id DESC LIMIT 1",
-                    params![&side, strike_price_cents, expires],
-                    |row| row.get(0),
-                )
-                .ok();
-
-            if let Some(current_str) = current_premium_str {
-                let current = db_string_to_float(&current_str).unwrap_or(0.0);
-                let product_key = format!("{}-{}-{}", side, strike_price_cents, expires);
-
-                // For new contracts (< 24hr old), use creation premium as baseline
-                // For older contracts, try to get premium from 24 hours ago
-                let baseline_premium_str: Option<String> = conn
-                    .query_row(
-                        "SELECT premium_str FROM premium_history 
-                         WHERE product_key = ?1 AND timestamp <= ?2 
-                         ORDER BY timestamp DESC LIMIT 1",
-                        params![&product_key, twenty_four_hours_ago],
-                        |row| row.get(0),
-                    )
-                    .ok()
-                    .or_else(|| {
-                        // If no data from 24hr ago, get the earliest premium for this product from history
-                        conn.query_row(
-                            "SELECT premium_str FROM premium_history 
-                             WHERE product_key = ?1 
-                             ORDER BY timestamp ASC LIMIT 1",
-                            params![&product_key],
-                            |row| row.get(0),
-                        )
-                        .ok()
-                    })
-                    .or_else(|| {
-                        // If no premium history at all, use the earliest contract premium as baseline
-                        conn.query_row(
-                            "SELECT premium_str FROM contracts 
-                             WHERE side = ?1 AND strike_price_cents = ?2 AND expires = ?3 
-                             ORDER BY id ASC LIMIT 1",
-                            params![&side, strike_price_cents, expires],
-                            |row| row.get(0),
-                        )
-                        .ok()
-                    });
-
-                if let Some(baseline_str) = baseline_premium_str {
-                    let baseline_premium = db_string_to_float(&baseline_str).unwrap_or(0.0);
-                    if baseline_premium > 0.0 {
-                        let change_percent = if current != baseline_premium {
-                            ((current - baseline_premium) / baseline_premium) * 100.0
-                        } else {
-                            // For new contracts with no price change, show 0% change
-                            // This ensures they appear in the list
-                            0.0
-                        };
-                        let expire_string = format_expires_timestamp(expires);
-                        let strike_price = cents_to_usd(strike_price_cents);
-
-                        gainers.push(TopGainerItem {
-                            product_symbol: format!("BTC-{}-{}-{}", expire_string, strike_price, side),
-                            side,
-                            strike_price,
-                            expire: expire_string,
-                            change_24hr_percent: change_percent,
-                            last_price: current,
-                        });
-                    }
-                }
-            }
-        }
-    }
-
-    // Sort and take top 5
-    gainers.sort_by(|a, b| b.change_24hr_percent.partial_cmp(&a.change_24hr_percent).unwrap());
-    gainers.truncate(5);
-
-    Ok(HttpResponse::Ok().json(gainers))
-}
-
-// GET /topVolume - Top products by volume
-async fn get_top_volume(state: web::Data<Arc<AppState>>) -> Result<impl Responder, ApiError> {
-    let now = Utc::now().timestamp();
-    let twenty_four_hours_ago = now - (24 * 60 * 60);
-
-    let conn = state.db_pool.get()?;
-
-    let btc_price = state
-        .price_oracle
-        .get_btc_price()
-        .await
-        .map_err(|e| ApiError::PriceOracleError(e.to_string()))?;
-
-    let mut stmt = conn.prepare(
-        "SELECT side, strike_price_cents, expires, 
-                SUM(CAST(quantity_str AS REAL) * CAST(premium_str AS REAL)) as total_volume_btc, 
-                AVG(CAST(premium_str AS REAL)) as avg_premium
-         FROM contracts 
-         WHERE created_at >= ?1
-         GROUP BY side, strike_price_cents, expires
-         ORDER BY total_volume_btc DESC
-         LIMIT 5"
-    )?;
-
-    let products_iter = stmt.query_map(params![twenty_four_hours_ago], |row| {
-        Ok((
-            row.get::<_, OptionSide>(0)?,
-            row.get::<_, i64>(1)?,  // strike_price_cents
-            row.get::<_, i64>(2)?,
-            row.get::<_, f64>(3)?,
-            row.get::<_, f64>(4)?,
-        ))
-    })?;
-
-    let mut top_volume = Vec::new();
-
-    for product in products_iter {
-        if let Ok((side, strike_price_cents, expires, volume_btc, last_premium)) = product {
-            let expire_string = format_expires_timestamp(expires);
-            let strike_price = cents_to_usd(strike_price_cents);
-
-            top_volume.push(TopVolumeItem {
-                product_symbol: format!("BTC-{}-{}-{}", expire_string, strike_price, side),
-                side,
-                strike_price,
-                expire: expire_string,
-                volume_usd: volume_btc * btc_price,
-                last_price: last_premium,
-            });
-        }
-    }
-
-    Ok(HttpResponse::Ok().json(top_volume))
-}
\ No newline at end of file
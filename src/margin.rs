@@ -0,0 +1,189 @@
+// Margin models for short option positions.
+// MaxLoss margins the loss if the underlying goes to zero (puts) or triples (calls).
+// ScenarioGrid is SPAN-like: it reprices the option across a grid of spot and vol shocks
+// and margins the worst loss, with a minimum charge for far out-of-the-money options.
+// It is usually much lower than MaxLoss for short-dated options.
+// MARGIN_MODEL selects the model; the risk manager applies RISK_MARGIN on top of either.
+
+use crate::models::OptionSide;
+use crate::pricing::option_price;
+use std::env;
+use std::fmt;
+use std::sync::Arc;
+
+/// One short option as seen by a margin model
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShortOption<'a> {
+    pub side: &'a OptionSide,
+    pub strike: f64,
+    pub premium: f64,         // Premium received per unit
+    pub spot_price: f64,
+    pub iv: f64,
+    pub time_to_expiry: f64,  // Years
+    pub risk_free_rate: f64,
+}
+
+pub trait MarginModel: Send + Sync + fmt::Debug {
+    fn name(&self) -> &'static str;
+
+    /// Loss in USD of one unit of `option` that margin must cover, before the risk margin
+    fn unit_loss(&self, option: &ShortOption) -> f64;
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MaxLossMargin;
+
+impl MarginModel for MaxLossMargin {
+    fn name(&self) -> &'static str {
+        "max_loss"
+    }
+
+    fn unit_loss(&self, option: &ShortOption) -> f64 {
+        match option.side {
+            // For put seller: max loss = strike - premium received (if the underlying goes to 0)
+            OptionSide::Put => option.strike - option.premium,
+            // For call seller: theoretically unlimited loss, but we cap it
+            // Use 3x current price as reasonable worst case
+            OptionSide::Call => (option.spot_price * 3.0 - option.strike).max(0.0) - option.premium,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScenarioGridMargin {
+    pub spot_shock_percent: f64,     // Largest spot move, up and down
+    pub spot_steps: usize,           // Spot moves on each side of unchanged spot
+    pub vol_shock_percent: f64,      // Relative IV move, up and down
+    pub min_charge_percent: f64,     // Floor as % of spot, for far out-of-the-money options
+}
+
+impl Default for ScenarioGridMargin {
+    fn default() -> Self {
+        Self {
+            spot_shock_percent: 15.0,
+            spot_steps: 3,
+            vol_shock_percent: 30.0,
+            min_charge_percent: 1.0,
+        }
+    }
+}
+
+impl ScenarioGridMargin {
+    /// (spot multiplier, IV multiplier) of every scenario, unchanged market included
+    pub fn scenarios(&self) -> Vec<(f64, f64)> {
+        let steps = self.spot_steps.max(1) as i64;
+        let vol_shock = self.vol_shock_percent / 100.0;
+        let mut scenarios = Vec::new();
+        for step in -steps..=steps {
+            let spot_multiplier = 1.0 + self.spot_shock_percent / 100.0 * step as f64 / steps as f64;
+            for vol_multiplier in [1.0 - vol_shock, 1.0, 1.0 + vol_shock] {
+                scenarios.push((spot_multiplier.max(0.0), vol_multiplier.max(0.01)));
+            }
+        }
+        scenarios
+    }
+}
+
+impl MarginModel for ScenarioGridMargin {
+    fn name(&self) -> &'static str {
+        "scenario_grid"
+    }
+
+    fn unit_loss(&self, option: &ShortOption) -> f64 {
+        // As the option seller we lose the option's value less the premium received
+        let worst_value = self
+            .scenarios()
+            .into_iter()
+            .map(|(spot_multiplier, vol_multiplier)| {
+                option_price(
+                    option.side,
+                    option.spot_price * spot_multiplier,
+                    option.strike,
+                    option.risk_free_rate,
+                    option.iv * vol_multiplier,
+                    option.time_to_expiry,
+                )
+            })
+            .fold(0.0, f64::max);
+        let min_charge = option.spot_price * self.min_charge_percent / 100.0;
+        (worst_value - option.premium).max(min_charge)
+    }
+}
+
+/// MARGIN_MODEL: max_loss (default) or scenario_grid, the latter shaped by
+/// MARGIN_SPOT_SHOCK_PERCENT (15), MARGIN_SPOT_STEPS (3), MARGIN_VOL_SHOCK_PERCENT (30)
+/// and MARGIN_MIN_CHARGE_PERCENT (1)
+pub fn margin_model_from_env() -> Result<Arc<dyn MarginModel>, String> {
+    match env::var("MARGIN_MODEL").unwrap_or_default().trim() {
+        "" | "max_loss" => Ok(Arc::new(MaxLossMargin)),
+        "scenario_grid" => {
+            let defaults = ScenarioGridMargin::default();
+            let percent = |name: &str, default: f64| {
+                env::var(name)
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .filter(|v: &f64| *v >= 0.0)
+                    .unwrap_or(default)
+            };
+            Ok(Arc::new(ScenarioGridMargin {
+                spot_shock_percent: percent("MARGIN_SPOT_SHOCK_PERCENT", defaults.spot_shock_percent),
+                spot_steps: env::var("MARGIN_SPOT_STEPS")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(defaults.spot_steps)
+                    .max(1),
+                vol_shock_percent: percent("MARGIN_VOL_SHOCK_PERCENT", defaults.vol_shock_percent),
+                min_charge_percent: percent("MARGIN_MIN_CHARGE_PERCENT", defaults.min_charge_percent),
+            }))
+        }
+        other => Err(format!("unknown MARGIN_MODEL '{}' (expected max_loss or scenario_grid)", other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn short_option(side: &OptionSide, strike: f64, time_to_expiry: f64) -> ShortOption<'_> {
+        ShortOption {
+            side,
+            strike,
+            premium: 0.0,
+            spot_price: 100_000.0,
+            iv: 0.5,
+            time_to_expiry,
+            risk_free_rate: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_scenario_grid_covers_shocks() {
+        let grid = ScenarioGridMargin::default();
+        let scenarios = grid.scenarios();
+        assert_eq!(scenarios.len(), 21);
+        let has = |spot: f64, vol: f64| scenarios.iter().any(|(s, v)| (s - spot).abs() < 1e-9 && (v - vol).abs() < 1e-9);
+        assert!(has(0.85, 0.7));
+        assert!(has(1.0, 1.0));
+        assert!(has(1.15, 1.3));
+
+        // A 1-day ATM put loses roughly the 15% down move
+        let put = short_option(&OptionSide::Put, 100_000.0, 1.0 / 365.0);
+        let loss = grid.unit_loss(&put);
+        assert!(loss > 15_000.0 && loss < 16_000.0, "loss {}", loss);
+        // and far less than the max-loss model
+        assert!(loss < MaxLossMargin.unit_loss(&put) / 5.0);
+    }
+
+    #[test]
+    fn test_scenario_grid_minimum_charge() {
+        let grid = ScenarioGridMargin::default();
+        let far_otm_call = short_option(&OptionSide::Call, 200_000.0, 1.0 / 365.0);
+        assert_eq!(grid.unit_loss(&far_otm_call), 1_000.0);
+    }
+
+    #[test]
+    fn test_max_loss_model() {
+        assert_eq!(MaxLossMargin.unit_loss(&short_option(&OptionSide::Put, 90_000.0, 0.1)), 90_000.0);
+        assert_eq!(MaxLossMargin.unit_loss(&short_option(&OptionSide::Call, 110_000.0, 0.1)), 190_000.0);
+    }
+}
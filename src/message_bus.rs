@@ -0,0 +1,227 @@
+// Message bus publisher for larger deployments.
+// With MESSAGE_BUS=nats or kafka the outbox poller also publishes every event to a topic of
+// the bus, so data pipelines and hedging bots consume them in real time: trade lifecycle
+// events to <prefix>.trades, IV marks to <prefix>.marks and trading state changes and risk
+// alerts to <prefix>.risk. The clients are behind the `nats` and `kafka` cargo features; a
+// server built without the one configured refuses to start. Events are the JSON sent to the
+// webhooks; Kafka messages are keyed by contract id, or by event name, to keep each
+// contract's events in order within its partition.
+
+use crate::webhooks::{
+    EventSink, WebhookEvent, CONTRACT_EXERCISED, CONTRACT_SETTLED, MARKS_SNAPSHOT, RISK_ALERT, TRADE_CREATED,
+    TRADING_STATE_CHANGED,
+};
+use std::env;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const TRADES_TOPIC: &str = "trades";
+pub const MARKS_TOPIC: &str = "marks";
+pub const RISK_TOPIC: &str = "risk";
+pub const EVENTS_TOPIC: &str = "events";  // Anything else
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BusBackend {
+    Nats,
+    Kafka,
+}
+
+impl fmt::Display for BusBackend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BusBackend::Nats => write!(f, "nats"),
+            BusBackend::Kafka => write!(f, "kafka"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MessageBusConfig {
+    pub backend: BusBackend,
+    pub url: String,           // NATS server URL, or Kafka bootstrap servers
+    pub topic_prefix: String,  // Topics are <prefix>.trades, <prefix>.marks, ...
+    pub timeout: Duration,     // For connecting and for each publish
+}
+
+impl MessageBusConfig {
+    /// MESSAGE_BUS (nats or kafka, off when unset), MESSAGE_BUS_URL (nats://localhost:4222 or
+    /// localhost:9092), MESSAGE_BUS_TOPIC_PREFIX (options) and MESSAGE_BUS_TIMEOUT_SECS (5)
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let backend = match env::var("MESSAGE_BUS").unwrap_or_default().trim().to_lowercase().as_str() {
+            "" | "none" => return Ok(None),
+            "nats" => BusBackend::Nats,
+            "kafka" => BusBackend::Kafka,
+            other => return Err(format!("invalid MESSAGE_BUS '{}', expected nats or kafka", other)),
+        };
+        let default_url = match backend {
+            BusBackend::Nats => "nats://localhost:4222",
+            BusBackend::Kafka => "localhost:9092",
+        };
+        let topic_prefix = env::var("MESSAGE_BUS_TOPIC_PREFIX").unwrap_or_else(|_| "options".to_string());
+        if topic_prefix.is_empty() || !topic_prefix.chars().all(|c| c.is_ascii_alphanumeric() || "._-".contains(c)) {
+            return Err(format!(
+                "invalid MESSAGE_BUS_TOPIC_PREFIX '{}', expected letters, digits, '.', '_' or '-'",
+                topic_prefix
+            ));
+        }
+        let timeout_secs: u64 = env::var("MESSAGE_BUS_TIMEOUT_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|secs| *secs > 0)
+            .unwrap_or(5);
+        Ok(Some(Self {
+            backend,
+            url: env::var("MESSAGE_BUS_URL").unwrap_or_else(|_| default_url.to_string()),
+            topic_prefix,
+            timeout: Duration::from_secs(timeout_secs),
+        }))
+    }
+
+    /// Topic, or NATS subject, `event` is published to
+    pub fn topic_for(&self, event: &str) -> String {
+        format!("{}.{}", self.topic_prefix, topic(event))
+    }
+}
+
+/// Topic of `event` below the prefix
+pub fn topic(event: &str) -> &'static str {
+    match event {
+        TRADE_CREATED | CONTRACT_SETTLED | CONTRACT_EXERCISED => TRADES_TOPIC,
+        MARKS_SNAPSHOT => MARKS_TOPIC,
+        TRADING_STATE_CHANGED | RISK_ALERT => RISK_TOPIC,
+        _ => EVENTS_TOPIC,
+    }
+}
+
+/// Partition key of `event`: the id of the contract it is about, or else its name
+pub fn key(event: &WebhookEvent) -> String {
+    match event.data["contract"]["id"].as_i64() {
+        Some(id) => id.to_string(),
+        None => event.event.clone(),
+    }
+}
+
+/// Connect to the bus `config` names. Fails when this build lacks its cargo feature.
+pub async fn connect(config: &MessageBusConfig) -> Result<Arc<dyn EventSink>, String> {
+    match config.backend {
+        #[cfg(feature = "nats")]
+        BusBackend::Nats => Ok(Arc::new(nats::NatsSink::connect(config).await?)),
+        #[cfg(feature = "kafka")]
+        BusBackend::Kafka => Ok(Arc::new(kafka::KafkaSink::connect(config)?)),
+        #[allow(unreachable_patterns)]
+        backend => Err(format!(
+            "MESSAGE_BUS={} needs a server built with the '{}' feature (cargo build --features {})",
+            backend, backend, backend
+        )),
+    }
+}
+
+#[cfg(any(feature = "nats", feature = "kafka"))]
+fn payload(event: &WebhookEvent) -> Result<Vec<u8>, crate::sources::SourceError> {
+    serde_json::to_vec(event).map_err(|e| e.to_string().into())
+}
+
+#[cfg(feature = "nats")]
+mod nats {
+    use super::*;
+    use crate::sources::SourceError;
+    use async_trait::async_trait;
+
+    pub struct NatsSink {
+        client: async_nats::Client,
+        config: MessageBusConfig,
+    }
+
+    impl NatsSink {
+        pub async fn connect(config: &MessageBusConfig) -> Result<Self, String> {
+            let client = tokio::time::timeout(config.timeout, async_nats::connect(config.url.as_str()))
+                .await
+                .map_err(|_| format!("timed out connecting to NATS at {}", config.url))?
+                .map_err(|e| format!("failed to connect to NATS at {}: {}", config.url, e))?;
+            Ok(Self { client, config: config.clone() })
+        }
+    }
+
+    #[async_trait]
+    impl EventSink for NatsSink {
+        async fn publish(&self, event: &WebhookEvent) -> Result<(), SourceError> {
+            let subject = self.config.topic_for(&event.event);
+            let publish = async {
+                self.client.publish(subject, payload(event)?.into()).await.map_err(|e| e.to_string())?;
+                // Published only once the server has it
+                self.client.flush().await.map_err(|e| e.to_string())?;
+                Ok::<(), SourceError>(())
+            };
+            tokio::time::timeout(self.config.timeout, publish)
+                .await
+                .map_err(|_| SourceError::from("NATS publish timed out".to_string()))?
+        }
+    }
+}
+
+#[cfg(feature = "kafka")]
+mod kafka {
+    use super::*;
+    use crate::sources::SourceError;
+    use async_trait::async_trait;
+    use rdkafka::producer::{FutureProducer, FutureRecord};
+    use rdkafka::ClientConfig;
+
+    pub struct KafkaSink {
+        producer: FutureProducer,
+        config: MessageBusConfig,
+    }
+
+    impl KafkaSink {
+        pub fn connect(config: &MessageBusConfig) -> Result<Self, String> {
+            let producer = ClientConfig::new()
+                .set("bootstrap.servers", &config.url)
+                .set("message.timeout.ms", config.timeout.as_millis().to_string())
+                .set("enable.idempotence", "true")
+                .create()
+                .map_err(|e| format!("failed to create Kafka producer for {}: {}", config.url, e))?;
+            Ok(Self { producer, config: config.clone() })
+        }
+    }
+
+    #[async_trait]
+    impl EventSink for KafkaSink {
+        async fn publish(&self, event: &WebhookEvent) -> Result<(), SourceError> {
+            let topic = self.config.topic_for(&event.event);
+            let (key, payload) = (key(event), payload(event)?);
+            let record = FutureRecord::to(&topic).key(&key).payload(&payload);
+            self.producer
+                .send(record, self.config.timeout)
+                .await
+                .map(|_| ())
+                .map_err(|(e, _)| SourceError::from(format!("Kafka delivery to {} failed: {}", topic, e)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_events_map_to_topics_and_keys() {
+        let config = MessageBusConfig {
+            backend: BusBackend::Kafka,
+            url: "localhost:9092".to_string(),
+            topic_prefix: "options".to_string(),
+            timeout: Duration::from_secs(5),
+        };
+        assert_eq!(config.topic_for(TRADE_CREATED), "options.trades");
+        assert_eq!(config.topic_for(CONTRACT_SETTLED), "options.trades");
+        assert_eq!(config.topic_for(MARKS_SNAPSHOT), "options.marks");
+        assert_eq!(config.topic_for(RISK_ALERT), "options.risk");
+        assert_eq!(config.topic_for(TRADING_STATE_CHANGED), "options.risk");
+        assert_eq!(config.topic_for("contract.expiring_soon"), "options.events");
+
+        let trade = WebhookEvent::new(TRADE_CREATED, json!({"contract": {"id": 7}}));
+        assert_eq!(key(&trade), "7");
+        assert_eq!(key(&WebhookEvent::new(RISK_ALERT, json!({"reason": "oracle down"}))), RISK_ALERT);
+    }
+}
@@ -0,0 +1,25 @@
+-- Contracts with string storage for BTC amounts and integer cents for USD prices
+CREATE TABLE IF NOT EXISTS contracts (
+    id INTEGER PRIMARY KEY,
+    side TEXT NOT NULL,
+    strike_price_cents INTEGER NOT NULL,
+    quantity_str TEXT NOT NULL,
+    expires INTEGER NOT NULL,
+    premium_str TEXT NOT NULL,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
+
+-- Premium history for tracking price movements
+CREATE TABLE IF NOT EXISTS premium_history (
+    id INTEGER PRIMARY KEY,
+    product_key TEXT NOT NULL,
+    side TEXT NOT NULL,
+    strike_price_cents INTEGER NOT NULL,
+    expires INTEGER NOT NULL,
+    premium_str TEXT NOT NULL,
+    timestamp INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    UNIQUE(product_key, timestamp)
+);
+
+CREATE INDEX IF NOT EXISTS idx_contracts_created_at ON contracts(created_at);
+CREATE INDEX IF NOT EXISTS idx_premium_history_product ON premium_history(product_key, timestamp);
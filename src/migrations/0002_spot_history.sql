@@ -0,0 +1,7 @@
+-- Sampled BTC spot prices for realized volatility calculations
+CREATE TABLE IF NOT EXISTS spot_history (
+    id INTEGER PRIMARY KEY,
+    price_cents INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    UNIQUE(timestamp)
+);
@@ -0,0 +1,6 @@
+-- Contract lifecycle: open -> expired -> settled
+ALTER TABLE contracts ADD COLUMN status TEXT NOT NULL DEFAULT 'open';
+ALTER TABLE contracts ADD COLUMN settlement_price_cents INTEGER;
+ALTER TABLE contracts ADD COLUMN settled_at INTEGER;
+
+CREATE INDEX IF NOT EXISTS idx_contracts_status_expires ON contracts(status, expires);
@@ -0,0 +1,10 @@
+-- API keys for machine clients. Only the SHA-256 hash of each key is stored.
+CREATE TABLE IF NOT EXISTS api_keys (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    key_hash TEXT NOT NULL UNIQUE,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    revoked_at INTEGER
+);
+
+CREATE INDEX IF NOT EXISTS idx_api_keys_name ON api_keys(name);
@@ -0,0 +1,4 @@
+-- Underlying asset of each contract; everything written before multi-asset support is BTC
+ALTER TABLE contracts ADD COLUMN underlying TEXT NOT NULL DEFAULT 'BTC';
+
+CREATE INDEX IF NOT EXISTS idx_contracts_underlying_expires ON contracts(underlying, expires);
@@ -0,0 +1,6 @@
+-- Premium quote currency. premium_str stays the BTC premium; USD/USDT quotes keep the
+-- amount agreed with the buyer and the BTC prices used to convert at trade and settlement time.
+ALTER TABLE contracts ADD COLUMN premium_currency TEXT NOT NULL DEFAULT 'BTC';
+ALTER TABLE contracts ADD COLUMN quoted_premium_str TEXT;
+ALTER TABLE contracts ADD COLUMN trade_btc_price_cents INTEGER;
+ALTER TABLE contracts ADD COLUMN settlement_btc_price_cents INTEGER;
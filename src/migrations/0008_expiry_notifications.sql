@@ -0,0 +1,2 @@
+-- When the expiring-soon notice for a contract was delivered (NULL = not yet)
+ALTER TABLE contracts ADD COLUMN expiry_notified_at INTEGER;
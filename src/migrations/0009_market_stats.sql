@@ -0,0 +1,14 @@
+-- Hourly market statistics per underlying, written by the stats aggregation job.
+-- bucket is the start of the hour; volume covers the hour, the other columns its end.
+CREATE TABLE IF NOT EXISTS market_stats (
+    bucket INTEGER NOT NULL,
+    underlying TEXT NOT NULL,
+    volume_str TEXT NOT NULL,
+    open_interest_str TEXT NOT NULL,
+    open_interest_btc_str TEXT NOT NULL,
+    contract_count INTEGER NOT NULL,
+    notional_cents INTEGER NOT NULL,
+    spot_price_cents INTEGER NOT NULL,
+    recorded_at INTEGER NOT NULL,
+    PRIMARY KEY (bucket, underlying)
+);
@@ -0,0 +1,30 @@
+-- Quantities and BTC premiums as integer satoshis (1e-8 units) instead of decimal strings,
+-- so analytics aggregate them in SQL without CAST. Quoted premiums are kept in the minor
+-- unit of premium_currency (satoshis for BTC, cents for USD and USDT). Decimal strings are
+-- only produced at the JSON boundary.
+ALTER TABLE contracts ADD COLUMN quantity_sats INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE contracts ADD COLUMN premium_sats INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE contracts ADD COLUMN quoted_premium_minor INTEGER;
+UPDATE contracts SET
+    quantity_sats = CAST(ROUND(CAST(quantity_str AS REAL) * 100000000) AS INTEGER),
+    premium_sats = CAST(ROUND(CAST(premium_str AS REAL) * 100000000) AS INTEGER),
+    quoted_premium_minor = CAST(ROUND(CAST(quoted_premium_str AS REAL)
+        * CASE premium_currency WHEN 'BTC' THEN 100000000 ELSE 100 END) AS INTEGER);
+ALTER TABLE contracts DROP COLUMN quantity_str;
+ALTER TABLE contracts DROP COLUMN premium_str;
+ALTER TABLE contracts DROP COLUMN quoted_premium_str;
+
+ALTER TABLE premium_history ADD COLUMN premium_sats INTEGER NOT NULL DEFAULT 0;
+UPDATE premium_history SET premium_sats = CAST(ROUND(CAST(premium_str AS REAL) * 100000000) AS INTEGER);
+ALTER TABLE premium_history DROP COLUMN premium_str;
+
+ALTER TABLE market_stats ADD COLUMN volume_sats INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE market_stats ADD COLUMN open_interest_sats INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE market_stats ADD COLUMN open_interest_btc_sats INTEGER NOT NULL DEFAULT 0;
+UPDATE market_stats SET
+    volume_sats = CAST(ROUND(CAST(volume_str AS REAL) * 100000000) AS INTEGER),
+    open_interest_sats = CAST(ROUND(CAST(open_interest_str AS REAL) * 100000000) AS INTEGER),
+    open_interest_btc_sats = CAST(ROUND(CAST(open_interest_btc_str AS REAL) * 100000000) AS INTEGER);
+ALTER TABLE market_stats DROP COLUMN volume_str;
+ALTER TABLE market_stats DROP COLUMN open_interest_str;
+ALTER TABLE market_stats DROP COLUMN open_interest_btc_str;
@@ -0,0 +1,10 @@
+-- Venue-wide trading state (open, reduce_only or halted). A single row, id = 1;
+-- no row means open. automatic = 1 when the oracle monitor set the state.
+CREATE TABLE IF NOT EXISTS trading_state (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    state TEXT NOT NULL,
+    reason TEXT,
+    updated_by TEXT NOT NULL,
+    updated_at INTEGER NOT NULL,
+    automatic INTEGER NOT NULL DEFAULT 0
+);
@@ -0,0 +1,4 @@
+-- Counterparty a contract was sold to, for per-counterparty position limits.
+-- NULL for contracts written before counterparties were recorded.
+ALTER TABLE contracts ADD COLUMN counterparty TEXT;
+CREATE INDEX IF NOT EXISTS idx_contracts_counterparty ON contracts (counterparty, expires);
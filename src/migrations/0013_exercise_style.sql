@@ -0,0 +1,2 @@
+-- Whether the buyer may exercise before expiry (american) or only at it (european)
+ALTER TABLE contracts ADD COLUMN exercise_style TEXT NOT NULL DEFAULT 'european';
@@ -0,0 +1,15 @@
+-- Partial and full closes: the buyer sells part of a contract back to the pool at the mark.
+-- closed_quantity_sats is the running total; contract_closes keeps every close and its price.
+ALTER TABLE contracts ADD COLUMN closed_quantity_sats INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE IF NOT EXISTS contract_closes (
+    id INTEGER PRIMARY KEY,
+    contract_id INTEGER NOT NULL REFERENCES contracts(id),
+    quantity_sats INTEGER NOT NULL,
+    price_cents INTEGER NOT NULL,      -- USD per unit paid to the buyer
+    btc_price_cents INTEGER NOT NULL,  -- BTC price the proceeds are converted at
+    actor TEXT NOT NULL,
+    closed_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_contract_closes_contract ON contract_closes(contract_id, closed_at);
@@ -0,0 +1,18 @@
+-- Resting quotes: the pool's standing offers per product, reposted when they expire.
+-- A quote is live until valid_until unless it was withdrawn by a repost or fully filled.
+CREATE TABLE IF NOT EXISTS resting_quotes (
+    id INTEGER PRIMARY KEY,
+    underlying TEXT NOT NULL,
+    side TEXT NOT NULL,
+    strike_price_cents INTEGER NOT NULL,
+    expires INTEGER NOT NULL,
+    price_sats INTEGER NOT NULL,        -- Premium per unit, in BTC
+    size_sats INTEGER NOT NULL,
+    filled_sats INTEGER NOT NULL DEFAULT 0,
+    iv REAL NOT NULL,
+    posted_at INTEGER NOT NULL,
+    valid_until INTEGER NOT NULL,
+    withdrawn_at INTEGER
+);
+
+CREATE INDEX IF NOT EXISTS idx_resting_quotes_live ON resting_quotes(underlying, valid_until);
@@ -0,0 +1,15 @@
+-- On-chain payment of premiums: a contract sold while payments are required starts out
+-- pending and opens once a transaction paying amount_sats to address has enough
+-- confirmations. Unpaid contracts are cancelled at due_at.
+CREATE TABLE IF NOT EXISTS premium_payments (
+    contract_id INTEGER PRIMARY KEY REFERENCES contracts(id),
+    address TEXT NOT NULL,
+    amount_sats INTEGER NOT NULL,           -- Unique among pending payments to the address
+    confirmations_required INTEGER NOT NULL,
+    requested_at INTEGER NOT NULL,
+    due_at INTEGER NOT NULL,
+    txid TEXT UNIQUE,                        -- Set once the payment is confirmed
+    confirmed_at INTEGER
+);
+
+CREATE INDEX IF NOT EXISTS idx_premium_payments_pending ON premium_payments(confirmed_at, due_at);
@@ -0,0 +1,26 @@
+-- Premiums paid over Lightning: a payment is either on-chain, to address, or a BOLT11
+-- invoice identified by its payment hash. SQLite cannot drop NOT NULL from address, so
+-- the table is rebuilt.
+CREATE TABLE premium_payments_new (
+    contract_id INTEGER PRIMARY KEY REFERENCES contracts(id),
+    method TEXT NOT NULL DEFAULT 'onchain',  -- onchain or lightning
+    address TEXT,                            -- On-chain deposit address
+    amount_sats INTEGER NOT NULL,            -- Unique among pending on-chain payments to the address
+    confirmations_required INTEGER NOT NULL, -- 0 for Lightning
+    requested_at INTEGER NOT NULL,
+    due_at INTEGER NOT NULL,
+    txid TEXT UNIQUE,                        -- Set once an on-chain payment is confirmed
+    payment_hash TEXT UNIQUE,                -- Hex, for Lightning invoices
+    bolt11 TEXT,
+    confirmed_at INTEGER
+);
+
+INSERT INTO premium_payments_new (contract_id, method, address, amount_sats, confirmations_required, requested_at,
+                                  due_at, txid, confirmed_at)
+SELECT contract_id, 'onchain', address, amount_sats, confirmations_required, requested_at, due_at, txid, confirmed_at
+FROM premium_payments;
+
+DROP TABLE premium_payments;
+ALTER TABLE premium_payments_new RENAME TO premium_payments;
+
+CREATE INDEX IF NOT EXISTS idx_premium_payments_pending ON premium_payments(confirmed_at, due_at);
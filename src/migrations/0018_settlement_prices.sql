@@ -0,0 +1,15 @@
+-- Settlement prices attested by the oracle: the price of an underlying read once contracts
+-- expiring at timestamp matured, signed with the service's key (BIP-340 Schnorr over the
+-- SHA-256 of "<event_id>:<price>").
+CREATE TABLE IF NOT EXISTS settlement_prices (
+    underlying TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,       -- Maturity the price settles
+    price_cents INTEGER NOT NULL,
+    event_id TEXT NOT NULL,
+    public_key TEXT NOT NULL,         -- x-only, hex
+    signature TEXT NOT NULL,          -- hex
+    observed_at INTEGER NOT NULL,     -- When the price was read
+    PRIMARY KEY (underlying, timestamp)
+);
+
+CREATE INDEX IF NOT EXISTS idx_settlement_prices_timestamp ON settlement_prices(timestamp);
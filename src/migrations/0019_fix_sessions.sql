@@ -0,0 +1,22 @@
+-- FIX sessions: the next sequence numbers of each counterparty session, kept across
+-- reconnects and restarts, and the application messages sent on it so that resend
+-- requests can be answered. A session is reset to 1/1 when the counterparty logs on
+-- with ResetSeqNumFlag=Y.
+CREATE TABLE IF NOT EXISTS fix_sessions (
+    sender_comp_id TEXT NOT NULL,     -- Counterparty's CompID
+    target_comp_id TEXT NOT NULL,     -- Ours
+    next_in_seq INTEGER NOT NULL,
+    next_out_seq INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL,
+    PRIMARY KEY (sender_comp_id, target_comp_id)
+);
+
+CREATE TABLE IF NOT EXISTS fix_messages (
+    sender_comp_id TEXT NOT NULL,
+    target_comp_id TEXT NOT NULL,
+    seq_num INTEGER NOT NULL,
+    msg_type TEXT NOT NULL,
+    body TEXT NOT NULL,               -- Fields after the standard header, SOH separated
+    sent_at TEXT NOT NULL,            -- SendingTime
+    PRIMARY KEY (sender_comp_id, target_comp_id, seq_num)
+);
@@ -0,0 +1,12 @@
+-- Settlement pricing: spot samples of each underlying taken in the window before a
+-- maturity, and how the attested settlement price was computed from them.
+CREATE TABLE IF NOT EXISTS settlement_samples (
+    underlying TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    price_cents INTEGER NOT NULL,
+    PRIMARY KEY (underlying, timestamp)
+);
+
+ALTER TABLE settlement_prices ADD COLUMN method TEXT NOT NULL DEFAULT 'spot';  -- twap, median or spot
+ALTER TABLE settlement_prices ADD COLUMN sample_count INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE settlement_prices ADD COLUMN window_start INTEGER;                  -- First second averaged over
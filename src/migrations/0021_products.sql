@@ -0,0 +1,15 @@
+-- Product catalog: the listed underlying/side/strike/expiry combinations, generated from
+-- spot by the listing job and expired once their maturity passes.
+CREATE TABLE IF NOT EXISTS products (
+    id INTEGER PRIMARY KEY,
+    underlying TEXT NOT NULL,
+    side TEXT NOT NULL,
+    strike_price_cents INTEGER NOT NULL,
+    expires INTEGER NOT NULL,
+    status TEXT NOT NULL DEFAULT 'active',  -- active or expired
+    listed_at INTEGER NOT NULL,
+    expired_at INTEGER,
+    UNIQUE(underlying, side, strike_price_cents, expires)
+);
+
+CREATE INDEX IF NOT EXISTS idx_products_status_expires ON products(status, expires);
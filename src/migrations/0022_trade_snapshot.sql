@@ -0,0 +1,6 @@
+-- Market at trade time: spot of the underlying, the IV the contract was priced and margined
+-- at, and the Black-Scholes value of one option then, for slippage and edge analysis.
+-- NULL for contracts traded before they were recorded.
+ALTER TABLE contracts ADD COLUMN spot_at_trade_cents INTEGER;
+ALTER TABLE contracts ADD COLUMN iv_at_trade REAL;
+ALTER TABLE contracts ADD COLUMN mark_premium_sats INTEGER;  -- Per unit
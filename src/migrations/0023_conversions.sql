@@ -0,0 +1,57 @@
+-- Currency conversions of contract cash flows, each with the BTC/USD rate applied: premiums
+-- at trade time, payouts at settlement or exercise, and the proceeds of closes. Amounts are
+-- in the minor unit of the contract's premium currency (satoshis for BTC, cents for USD and USDT).
+CREATE TABLE IF NOT EXISTS conversions (
+    id INTEGER PRIMARY KEY,
+    contract_id INTEGER NOT NULL,
+    kind TEXT NOT NULL,                -- premium, payout or close
+    currency TEXT NOT NULL,
+    amount_minor INTEGER NOT NULL,     -- In currency
+    btc_sats INTEGER NOT NULL,
+    usd_cents INTEGER NOT NULL,
+    btc_price_cents INTEGER NOT NULL,  -- USD per BTC applied
+    created_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_conversions_contract ON conversions(contract_id);
+
+-- Backfill from the BTC prices already recorded with contracts and closes
+INSERT INTO conversions (contract_id, kind, currency, amount_minor, btc_sats, usd_cents, btc_price_cents, created_at)
+SELECT id, 'premium', premium_currency, CASE premium_currency WHEN 'BTC' THEN btc ELSE usd END,
+       btc, usd, trade_btc_price_cents, created_at
+FROM (
+    SELECT id, premium_currency, trade_btc_price_cents, created_at,
+           CAST(ROUND(premium_sats * (quantity_sats / 1e8)) AS INTEGER) AS btc,
+           CAST(ROUND(premium_sats * (quantity_sats / 1e8) * (trade_btc_price_cents / 1e8)) AS INTEGER) AS usd
+    FROM contracts
+    WHERE trade_btc_price_cents IS NOT NULL
+);
+
+INSERT INTO conversions (contract_id, kind, currency, amount_minor, btc_sats, usd_cents, btc_price_cents, created_at)
+SELECT id, 'payout', premium_currency, CASE premium_currency WHEN 'BTC' THEN btc ELSE usd END,
+       btc, usd, settlement_btc_price_cents, settled_at
+FROM (
+    SELECT id, premium_currency, settlement_btc_price_cents, settled_at,
+           CAST(ROUND(intrinsic_cents * open_btc / settlement_btc_price_cents * 1e8) AS INTEGER) AS btc,
+           CAST(ROUND(intrinsic_cents * open_btc) AS INTEGER) AS usd
+    FROM (
+        SELECT *,
+               MAX(CASE side WHEN 'Call' THEN settlement_price_cents - strike_price_cents
+                             ELSE strike_price_cents - settlement_price_cents END, 0) AS intrinsic_cents,
+               (quantity_sats - closed_quantity_sats) / 1e8 AS open_btc
+        FROM contracts
+        WHERE status IN ('settled', 'exercised') AND settlement_btc_price_cents > 0
+    )
+)
+WHERE usd > 0;
+
+INSERT INTO conversions (contract_id, kind, currency, amount_minor, btc_sats, usd_cents, btc_price_cents, created_at)
+SELECT contract_id, 'close', premium_currency, CASE premium_currency WHEN 'BTC' THEN btc ELSE usd END,
+       btc, usd, btc_price_cents, closed_at
+FROM (
+    SELECT cc.contract_id, c.premium_currency, cc.btc_price_cents, cc.closed_at,
+           CAST(ROUND(cc.price_cents * (cc.quantity_sats / 1e8) / cc.btc_price_cents * 1e8) AS INTEGER) AS btc,
+           CAST(ROUND(cc.price_cents * (cc.quantity_sats / 1e8)) AS INTEGER) AS usd
+    FROM contract_closes cc JOIN contracts c ON c.id = cc.contract_id
+    WHERE cc.btc_price_cents > 0
+);
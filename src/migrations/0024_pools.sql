@@ -0,0 +1,17 @@
+-- Collateral pools besides the default one configured by POOL_ADDRESS, POOL_NETWORK,
+-- COLLATERAL_RATE and RISK_MARGIN, which keeps id 1 and is not stored here.
+-- Every contract sells from one pool; existing contracts belong to the default pool.
+CREATE TABLE pools (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE,
+    address TEXT NOT NULL,
+    network TEXT NOT NULL,
+    collateral_rate REAL NOT NULL,  -- Share of the pool balance that may back margin
+    risk_margin REAL NOT NULL,      -- Safety multiplier on margin
+    created_at INTEGER NOT NULL
+);
+-- Stored pools are numbered from 2
+INSERT INTO sqlite_sequence (name, seq) VALUES ('pools', 1);
+
+ALTER TABLE contracts ADD COLUMN pool_id INTEGER NOT NULL DEFAULT 1;
+CREATE INDEX idx_contracts_pool_status ON contracts(pool_id, status, expires);
@@ -0,0 +1,18 @@
+-- Counterparty credit tiers. A tier caps the open notional of an account, the notional of a
+-- single contract and the products it may buy; NULL columns leave that part unlimited.
+-- Accounts are API key names. Accounts without an assignment use the tier named 'default'.
+CREATE TABLE credit_tiers (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE,
+    max_open_notional_usd REAL,   -- Open notional at spot, all underlyings
+    max_trade_notional_usd REAL,  -- Notional of a single contract at spot
+    allowed_products TEXT,        -- JSON array such as ["BTC", "ETH-C"]; NULL allows every product
+    updated_at INTEGER NOT NULL
+);
+
+CREATE TABLE account_credit_tiers (
+    account TEXT PRIMARY KEY,
+    tier_id INTEGER NOT NULL REFERENCES credit_tiers(id),
+    assigned_at INTEGER NOT NULL
+);
+CREATE INDEX idx_account_credit_tiers_tier ON account_credit_tiers(tier_id);
@@ -0,0 +1,7 @@
+-- Accounts, named after their API key, with their KYC status. Accounts without a row have
+-- status 'none'; rows are created when an operator first sets a status.
+CREATE TABLE accounts (
+    name TEXT PRIMARY KEY,
+    kyc_status TEXT NOT NULL DEFAULT 'none',  -- none, pending, verified or rejected
+    kyc_updated_at INTEGER
+);
@@ -0,0 +1,9 @@
+-- Frontend users, who log in with a password for a short-lived session token.
+-- Only the Argon2 hash of each password is stored.
+CREATE TABLE users (
+    username TEXT PRIMARY KEY,
+    password_hash TEXT NOT NULL,  -- PHC string
+    role TEXT NOT NULL,           -- viewer, trader or admin
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL
+);
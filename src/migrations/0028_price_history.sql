@@ -0,0 +1,17 @@
+-- Spot price history of every underlying, one table for realized vol, settlement TWAPs and
+-- charting. Replaces spot_history (BTC only) and settlement_samples, whose rows are carried over.
+CREATE TABLE IF NOT EXISTS price_history (
+    asset TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    price_cents INTEGER NOT NULL,
+    PRIMARY KEY (asset, timestamp)
+);
+
+INSERT OR IGNORE INTO price_history (asset, timestamp, price_cents)
+SELECT 'BTC', timestamp, price_cents FROM spot_history;
+
+INSERT OR IGNORE INTO price_history (asset, timestamp, price_cents)
+SELECT underlying, timestamp, price_cents FROM settlement_samples;
+
+DROP TABLE spot_history;
+DROP TABLE settlement_samples;
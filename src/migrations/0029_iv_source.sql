@@ -0,0 +1,3 @@
+-- Where the IV a contract was priced at came from: deribit, interpolated or default.
+-- NULL for contracts from before it was recorded.
+ALTER TABLE contracts ADD COLUMN iv_source TEXT;
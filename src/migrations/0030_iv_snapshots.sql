@@ -0,0 +1,11 @@
+-- Periodic IV of every listed product, for tracking how implied vol moves (GET /volMovers).
+-- iv_source is where the IV came from: deribit, interpolated or default.
+CREATE TABLE IF NOT EXISTS iv_snapshots (
+    product_id INTEGER NOT NULL REFERENCES products(id),
+    timestamp INTEGER NOT NULL,
+    iv REAL NOT NULL,
+    iv_source TEXT NOT NULL,
+    PRIMARY KEY (product_id, timestamp)
+);
+
+CREATE INDEX IF NOT EXISTS idx_iv_snapshots_timestamp ON iv_snapshots(timestamp);
@@ -0,0 +1,4 @@
+-- Contracts by product, covering the per-product premium change and baseline queries of the
+-- leaderboards. Every index also holds the rowid (id), so the latest, last-before and first-in
+-- window trade of each product are found without reading the table, in GROUP BY order.
+CREATE INDEX IF NOT EXISTS idx_contracts_product ON contracts(underlying, side, strike_price_cents, expires, created_at);
@@ -0,0 +1,5 @@
+-- Most handlers only want contracts that have not expired yet (expires > now), and product
+-- lookups match on side, strike and expiry without the underlying. Status filters are served
+-- by idx_contracts_status_expires.
+CREATE INDEX IF NOT EXISTS idx_contracts_expires ON contracts(expires);
+CREATE INDEX IF NOT EXISTS idx_contracts_side_strike_expires ON contracts(side, strike_price_cents, expires);
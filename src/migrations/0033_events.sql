@@ -0,0 +1,15 @@
+-- Outbox of events for downstream consumers (webhooks and /ws/events), written in the same
+-- transaction as the change they describe. published_at stays NULL until the poller has
+-- delivered the event; ids are never reused, so consumers can drop events they have seen.
+CREATE TABLE IF NOT EXISTS events (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    event TEXT NOT NULL,
+    entity_id INTEGER,
+    data TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    published_at INTEGER,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_events_pending ON events(id) WHERE published_at IS NULL;
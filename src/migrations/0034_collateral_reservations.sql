@@ -0,0 +1,40 @@
+-- Collateral held for trades between their risk check and their insert. A trade reserves the
+-- margin it adds to its pool's book, then confirms the reservation in the transaction writing
+-- the contract, or releases it if the trade falls through. Reservations left neither confirmed
+-- nor released lapse at expires_at, so an instance dying mid-trade frees its collateral.
+-- Every instance sharing the database checks trades against the reservations of the others,
+-- and the trigger refuses a reservation taking the active reservations of a pool past the
+-- collateral its open contracts leave available.
+CREATE TABLE IF NOT EXISTS collateral_reservations (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    pool_id INTEGER NOT NULL,
+    counterparty TEXT NOT NULL,
+    underlying TEXT NOT NULL,
+    side TEXT NOT NULL,
+    strike_price_cents INTEGER NOT NULL,
+    quantity_sats INTEGER NOT NULL CHECK (quantity_sats > 0),
+    expires INTEGER NOT NULL,
+    premium_sats INTEGER NOT NULL,
+    margin_cents INTEGER NOT NULL CHECK (margin_cents >= 0),  -- Margin the trade adds to the book
+    available_cents INTEGER NOT NULL,                         -- Collateral left by the open contracts
+    status TEXT NOT NULL DEFAULT 'reserved' CHECK (status IN ('reserved', 'confirmed', 'released')),
+    contract_id INTEGER UNIQUE REFERENCES contracts(id),
+    created_at INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL CHECK (expires_at > created_at),
+    CHECK ((status = 'confirmed') = (contract_id IS NOT NULL))
+);
+
+CREATE INDEX IF NOT EXISTS idx_collateral_reservations_active
+    ON collateral_reservations(pool_id, expires_at) WHERE status = 'reserved';
+CREATE INDEX IF NOT EXISTS idx_collateral_reservations_counterparty
+    ON collateral_reservations(counterparty, expires_at) WHERE status = 'reserved';
+
+CREATE TRIGGER IF NOT EXISTS collateral_reservations_within_pool
+BEFORE INSERT ON collateral_reservations
+WHEN NEW.margin_cents + (
+    SELECT COALESCE(SUM(margin_cents), 0) FROM collateral_reservations
+    WHERE pool_id = NEW.pool_id AND status = 'reserved' AND expires_at > NEW.created_at
+) > NEW.available_cents
+BEGIN
+    SELECT RAISE(ABORT, 'pool collateral oversubscribed');
+END;
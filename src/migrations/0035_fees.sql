@@ -0,0 +1,17 @@
+-- Trading fees. Credit tiers can set the fee schedule of their accounts; NULL columns fall back
+-- to FEE_PERCENT and FEE_MIN_SATS. A negative fee_percent is a maker rebate.
+ALTER TABLE credit_tiers ADD COLUMN fee_percent REAL;
+ALTER TABLE credit_tiers ADD COLUMN min_fee_sats INTEGER;
+
+-- The fee each trade paid on top of its premium, or the rebate it received (negative amounts)
+CREATE TABLE IF NOT EXISTS fees (
+    contract_id INTEGER PRIMARY KEY REFERENCES contracts(id),
+    account TEXT NOT NULL,
+    tier TEXT,                         -- Credit tier whose schedule applied; NULL for the default
+    fee_percent REAL NOT NULL,         -- Of the premium
+    fee_sats INTEGER NOT NULL,
+    fee_cents INTEGER NOT NULL,        -- At the BTC price of the trade
+    created_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_fees_account ON fees(account, created_at);
@@ -0,0 +1,9 @@
+-- Referral codes trades were placed with, for attributing volume and fees to growth campaigns
+CREATE TABLE IF NOT EXISTS referrals (
+    contract_id INTEGER PRIMARY KEY REFERENCES contracts(id),
+    code TEXT NOT NULL,                -- Upper case
+    account TEXT NOT NULL,             -- Referred account (API key name)
+    created_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_referrals_code ON referrals(code, created_at);
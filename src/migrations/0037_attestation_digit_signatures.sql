@@ -0,0 +1,4 @@
+-- Signatures of each binary digit of attested prices, most significant first, space-separated,
+-- each with the nonce the DLC announcement of the event commits to. Empty for attestations
+-- made before digits were signed.
+ALTER TABLE settlement_prices ADD COLUMN digit_signatures TEXT NOT NULL DEFAULT '';
@@ -0,0 +1,426 @@
+// Versioned schema migrations.
+// Each migration is an embedded SQL file applied once, in order, and recorded in
+// the schema_version table. To change the schema, add a new file with the next
+// version number to MIGRATIONS - never edit a migration that has already shipped.
+
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension, Result};
+
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial_schema",
+        sql: include_str!("0001_initial_schema.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "spot_history",
+        sql: include_str!("0002_spot_history.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "contract_status",
+        sql: include_str!("0003_contract_status.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "api_keys",
+        sql: include_str!("0004_api_keys.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "contract_underlying",
+        sql: include_str!("0005_contract_underlying.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "premium_currency",
+        sql: include_str!("0006_premium_currency.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "audit_log",
+        sql: include_str!("0007_audit_log.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "expiry_notifications",
+        sql: include_str!("0008_expiry_notifications.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "market_stats",
+        sql: include_str!("0009_market_stats.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "satoshi_amounts",
+        sql: include_str!("0010_satoshi_amounts.sql"),
+    },
+    Migration {
+        version: 11,
+        name: "trading_state",
+        sql: include_str!("0011_trading_state.sql"),
+    },
+    Migration {
+        version: 12,
+        name: "contract_counterparty",
+        sql: include_str!("0012_contract_counterparty.sql"),
+    },
+    Migration {
+        version: 13,
+        name: "exercise_style",
+        sql: include_str!("0013_exercise_style.sql"),
+    },
+    Migration {
+        version: 14,
+        name: "contract_closes",
+        sql: include_str!("0014_contract_closes.sql"),
+    },
+    Migration {
+        version: 15,
+        name: "resting_quotes",
+        sql: include_str!("0015_resting_quotes.sql"),
+    },
+    Migration {
+        version: 16,
+        name: "premium_payments",
+        sql: include_str!("0016_premium_payments.sql"),
+    },
+    Migration {
+        version: 17,
+        name: "lightning_payments",
+        sql: include_str!("0017_lightning_payments.sql"),
+    },
+    Migration {
+        version: 18,
+        name: "settlement_prices",
+        sql: include_str!("0018_settlement_prices.sql"),
+    },
+    Migration {
+        version: 19,
+        name: "fix_sessions",
+        sql: include_str!("0019_fix_sessions.sql"),
+    },
+    Migration {
+        version: 20,
+        name: "settlement_samples",
+        sql: include_str!("0020_settlement_samples.sql"),
+    },
+    Migration {
+        version: 21,
+        name: "products",
+        sql: include_str!("0021_products.sql"),
+    },
+    Migration {
+        version: 22,
+        name: "trade_snapshot",
+        sql: include_str!("0022_trade_snapshot.sql"),
+    },
+    Migration {
+        version: 23,
+        name: "conversions",
+        sql: include_str!("0023_conversions.sql"),
+    },
+    Migration {
+        version: 24,
+        name: "pools",
+        sql: include_str!("0024_pools.sql"),
+    },
+    Migration {
+        version: 25,
+        name: "credit_tiers",
+        sql: include_str!("0025_credit_tiers.sql"),
+    },
+    Migration {
+        version: 26,
+        name: "accounts",
+        sql: include_str!("0026_accounts.sql"),
+    },
+    Migration {
+        version: 27,
+        name: "users",
+        sql: include_str!("0027_users.sql"),
+    },
+    Migration {
+        version: 28,
+        name: "price_history",
+        sql: include_str!("0028_price_history.sql"),
+    },
+    Migration {
+        version: 29,
+        name: "iv_source",
+        sql: include_str!("0029_iv_source.sql"),
+    },
+    Migration {
+        version: 30,
+        name: "iv_snapshots",
+        sql: include_str!("0030_iv_snapshots.sql"),
+    },
+    Migration {
+        version: 31,
+        name: "contract_product_index",
+        sql: include_str!("0031_contract_product_index.sql"),
+    },
+    Migration {
+        version: 32,
+        name: "expiry_indexes",
+        sql: include_str!("0032_expiry_indexes.sql"),
+    },
+    Migration {
+        version: 33,
+        name: "events",
+        sql: include_str!("0033_events.sql"),
+    },
+    Migration {
+        version: 34,
+        name: "collateral_reservations",
+        sql: include_str!("0034_collateral_reservations.sql"),
+    },
+    Migration {
+        version: 35,
+        name: "fees",
+        sql: include_str!("0035_fees.sql"),
+    },
+    Migration {
+        version: 36,
+        name: "referrals",
+        sql: include_str!("0036_referrals.sql"),
+    },
+    Migration {
+        version: 37,
+        name: "attestation_digit_signatures",
+        sql: include_str!("0037_attestation_digit_signatures.sql"),
+    },
+];
+
+#[derive(Debug, Clone)]
+pub struct AppliedMigration {
+    pub version: i64,
+    pub name: String,
+    pub applied_at: i64,
+}
+
+fn ensure_schema_version_table(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            applied_at INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Highest applied migration version, or 0 for a fresh database
+pub fn current_version(conn: &Connection) -> Result<i64> {
+    ensure_schema_version_table(conn)?;
+    conn.query_row("SELECT COALESCE(MAX(version), 0) FROM schema_version", [], |row| row.get(0))
+}
+
+/// All migrations recorded in schema_version, oldest first
+pub fn applied_migrations(conn: &Connection) -> Result<Vec<AppliedMigration>> {
+    ensure_schema_version_table(conn)?;
+    let mut stmt = conn.prepare("SELECT version, name, applied_at FROM schema_version ORDER BY version ASC")?;
+    let applied = stmt
+        .query_map([], |row| {
+            Ok(AppliedMigration {
+                version: row.get(0)?,
+                name: row.get(1)?,
+                applied_at: row.get(2)?,
+            })
+        })?
+        .collect::<Result<Vec<_>>>()?;
+    Ok(applied)
+}
+
+/// Apply every pending migration. Returns the versions that were applied.
+pub fn run_migrations(conn: &Connection) -> Result<Vec<i64>> {
+    run_migrations_to(conn, i64::MAX)
+}
+
+/// Apply the pending migrations up to and including `target`, e.g. to rebuild a database at
+/// the schema a snapshot was taken with. Returns the versions that were applied.
+pub fn run_migrations_to(conn: &Connection, target: i64) -> Result<Vec<i64>> {
+    ensure_schema_version_table(conn)?;
+    upgrade_legacy_float_schema(conn)?;
+
+    let current = current_version(conn)?;
+    let mut applied = Vec::new();
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current && m.version <= target) {
+        // Each migration and its schema_version row commit together
+        let tx = conn.unchecked_transaction()?;
+        tx.execute_batch(migration.sql)?;
+        tx.execute(
+            "INSERT INTO schema_version (version, name, applied_at) VALUES (?1, ?2, ?3)",
+            params![migration.version, migration.name, Utc::now().timestamp()],
+        )?;
+        tx.commit()?;
+        applied.push(migration.version);
+    }
+
+    Ok(applied)
+}
+
+// Databases created before string storage kept strike/quantity/premium as REAL columns.
+// Convert them in place before the versioned migrations run, so migration 1 adopts them.
+fn upgrade_legacy_float_schema(conn: &Connection) -> Result<()> {
+    let has_legacy_column: Option<i64> = conn
+        .query_row(
+            "SELECT 1 FROM pragma_table_info('contracts') WHERE name = 'strike_price'",
+            [],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if has_legacy_column.is_none() {
+        return Ok(());
+    }
+
+    let tx = conn.unchecked_transaction()?;
+    tx.execute_batch(
+        "CREATE TABLE contracts_new (
+            id INTEGER PRIMARY KEY,
+            side TEXT NOT NULL,
+            strike_price_cents INTEGER NOT NULL,
+            quantity_str TEXT NOT NULL,
+            expires INTEGER NOT NULL,
+            premium_str TEXT NOT NULL,
+            created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+        );
+        INSERT INTO contracts_new (id, side, strike_price_cents, quantity_str, expires, premium_str, created_at)
+            SELECT id, side, CAST(strike_price * 100 AS INTEGER),
+                   printf('%.8f', quantity), expires, printf('%.8f', premium), created_at
+            FROM contracts;
+        DROP TABLE contracts;
+        ALTER TABLE contracts_new RENAME TO contracts;",
+    )?;
+
+    let has_legacy_history: Option<i64> = tx
+        .query_row(
+            "SELECT 1 FROM pragma_table_info('premium_history') WHERE name = 'strike_price'",
+            [],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if has_legacy_history.is_some() {
+        tx.execute_batch(
+            "CREATE TABLE premium_history_new (
+                id INTEGER PRIMARY KEY,
+                product_key TEXT NOT NULL,
+                side TEXT NOT NULL,
+                strike_price_cents INTEGER NOT NULL,
+                expires INTEGER NOT NULL,
+                premium_str TEXT NOT NULL,
+                timestamp INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+                UNIQUE(product_key, timestamp)
+            );
+            INSERT INTO premium_history_new (id, product_key, side, strike_price_cents, expires, premium_str, timestamp)
+                SELECT id, product_key, side, CAST(strike_price * 100 AS INTEGER),
+                       expires, printf('%.8f', premium), timestamp
+                FROM premium_history;
+            DROP TABLE premium_history;
+            ALTER TABLE premium_history_new RENAME TO premium_history;",
+        )?;
+    }
+
+    tx.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrations_are_ordered() {
+        for pair in MIGRATIONS.windows(2) {
+            assert_eq!(pair[1].version, pair[0].version + 1);
+        }
+    }
+
+    #[test]
+    fn test_run_migrations_is_idempotent() {
+        let conn = Connection::open_in_memory().unwrap();
+
+        let applied = run_migrations(&conn).unwrap();
+        assert_eq!(applied, MIGRATIONS.iter().map(|m| m.version).collect::<Vec<_>>());
+        assert_eq!(current_version(&conn).unwrap(), MIGRATIONS.last().unwrap().version);
+
+        assert!(run_migrations(&conn).unwrap().is_empty());
+        assert_eq!(applied_migrations(&conn).unwrap().len(), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn test_conversions_are_backfilled() {
+        let conn = Connection::open_in_memory().unwrap();
+        for migration in MIGRATIONS.iter().filter(|m| m.version < 23) {
+            conn.execute_batch(migration.sql).unwrap();
+        }
+        // A USD-quoted put settled $5k in the money, and a BTC-quoted call partly closed
+        conn.execute_batch(
+            "INSERT INTO contracts (id, side, strike_price_cents, quantity_sats, expires, premium_sats, created_at,
+                                    status, settlement_price_cents, settlement_btc_price_cents, settled_at,
+                                    premium_currency, quoted_premium_minor, trade_btc_price_cents)
+             VALUES (1, 'Put', 10000000, 50000000, 2000, 1000000, 1000, 'settled', 9500000, 9500000, 2000, 'USD', 100000, 10000000),
+                    (2, 'Call', 11000000, 100000000, 3000, 500000, 1000, 'open', NULL, NULL, NULL, 'BTC', 500000, 10000000);
+             INSERT INTO contract_closes (contract_id, quantity_sats, price_cents, btc_price_cents, actor, closed_at)
+             VALUES (2, 50000000, 80000, 10000000, 'desk', 1500);",
+        )
+        .unwrap();
+
+        conn.execute_batch(MIGRATIONS[22].sql).unwrap();
+
+        let rows: Vec<(i64, String, i64, i64, i64, i64)> = conn
+            .prepare("SELECT contract_id, kind, amount_minor, btc_sats, usd_cents, created_at FROM conversions ORDER BY kind, contract_id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?)))
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                (2, "close".to_string(), 400_000, 400_000, 40_000, 1500),
+                (1, "payout".to_string(), 250_000, 2_631_579, 250_000, 2000),
+                (1, "premium".to_string(), 50_000, 500_000, 50_000, 1000),
+                (2, "premium".to_string(), 500_000, 500_000, 50_000, 1000),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_legacy_float_schema_is_upgraded() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE contracts (
+                id INTEGER PRIMARY KEY,
+                side TEXT NOT NULL,
+                strike_price REAL NOT NULL,
+                quantity REAL NOT NULL,
+                expires INTEGER NOT NULL,
+                premium REAL NOT NULL,
+                created_at INTEGER NOT NULL
+            );
+            INSERT INTO contracts VALUES (1, 'Call', 100000.5, 0.25, 2000000000, 0.0125, 1000);",
+        )
+        .unwrap();
+
+        run_migrations(&conn).unwrap();
+
+        let (strike_cents, quantity_sats, premium_sats): (i64, i64, i64) = conn
+            .query_row("SELECT strike_price_cents, quantity_sats, premium_sats FROM contracts WHERE id = 1", [], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .unwrap();
+        assert_eq!(strike_cents, 10000050);
+        assert_eq!(quantity_sats, 25_000_000);
+        assert_eq!(premium_sats, 1_250_000);
+    }
+}
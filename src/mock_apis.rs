@@ -1,5 +1,71 @@
 // Actix-web for web server functionality.
+use crate::models::Asset;
+use actix_web::dev::Server;
 use actix_web::{web, App, HttpResponse, HttpServer, Responder};
+use chrono::{Duration, Utc};
+use serde_json::json;
+use std::env;
+
+// Settings for the mock server. In offline mode it also stands in for Deribit
+// and mempool.space so the API can run with no external dependencies.
+#[derive(Debug, Clone)]
+pub struct MockConfig {
+    pub bind_address: String,
+    pub btc_price: f64,          // USD, centre of the mock IV surface
+    pub eth_price: f64,          // USD, centre of the mock ETH IV surface
+    pub pool_balance_sats: u64,  // Balance reported for every address
+}
+
+impl MockConfig {
+    pub fn from_env() -> Self {
+        Self {
+            bind_address: env::var("MOCK_BIND_ADDRESS").unwrap_or_else(|_| "0.0.0.0:8081".to_string()),
+            btc_price: env::var("MOCK_BTC_PRICE")
+                .unwrap_or_else(|_| "100000".to_string())
+                .parse()
+                .unwrap_or(100000.0),
+            eth_price: env::var("MOCK_ETH_PRICE")
+                .unwrap_or_else(|_| "3500".to_string())
+                .parse()
+                .unwrap_or(3500.0),
+            pool_balance_sats: env::var("MOCK_POOL_BALANCE_SATS")
+                .unwrap_or_else(|_| "100000000".to_string())
+                .parse()
+                .unwrap_or(100_000_000),
+        }
+    }
+
+    /// Mock spot price of an underlying
+    pub fn price(&self, asset: Asset) -> f64 {
+        match asset {
+            Asset::Btc => self.btc_price,
+            Asset::Eth => self.eth_price,
+        }
+    }
+
+    /// Base URL clients should use to reach this server locally
+    pub fn base_url(&self) -> String {
+        let port = self.bind_address.rsplit(':').next().unwrap_or("8081");
+        format!("http://127.0.0.1:{}", port)
+    }
+}
+
+/// Whether the mock server should run at all (ENABLE_MOCK_APIS or OFFLINE_MODE)
+pub fn mocks_enabled() -> bool {
+    env_flag("ENABLE_MOCK_APIS") || offline_mode()
+}
+
+/// Whether Deribit, mempool.space and the price aggregator are replaced by mocks
+pub fn offline_mode() -> bool {
+    env_flag("OFFLINE_MODE")
+}
+
+fn env_flag(name: &str) -> bool {
+    matches!(
+        env::var(name).unwrap_or_default().to_lowercase().as_str(),
+        "1" | "true" | "yes"
+    )
+}
 
 // Defines the request structure for the '/iv' endpoint.
 #[derive(serde::Deserialize)]
@@ -11,26 +77,181 @@ struct IvRequest {
     expire: String,
 }
 
+// Simulate a "volatility smile" where IV increases based on distance from the given price.
+fn smile_iv(strike_price: f64, base_price: f64) -> f64 {
+    0.5 + (strike_price - base_price).abs() / base_price * 0.1
+}
+
 // Mock endpoint for calculating Implied Volatility (IV).
-// This is the only remaining mock endpoint as IV data comes from Deribit,
-// but we keep this as a fallback when Deribit data is unavailable.
-async fn get_iv(req: web::Query<IvRequest>) -> impl Responder {
-    // Simulate a "volatility smile" where IV increases based on distance from the current price.
-    // Using a base price of 50000 as a reasonable BTC price estimate
-    let base_price = 50000.0;
-    let iv = 0.5 + (req.strike_price - base_price).abs() / base_price * 0.1;
-    HttpResponse::Ok().json(iv)
-}
-
-// Main function for the mock server.
-// Runs a separate server on port 8081 to provide fallback IV data.
-// Pool data now comes from Mutiny wallet and price data from gRPC oracle.
-pub async fn mock_server() -> std::io::Result<()> {
-    HttpServer::new(|| {
-        App::new()
-            .service(web::resource("/iv").route(web::get().to(get_iv)))
+// Kept as a fallback when Deribit data is unavailable.
+async fn get_iv(req: web::Query<IvRequest>, config: web::Data<MockConfig>) -> impl Responder {
+    HttpResponse::Ok().json(smile_iv(req.strike_price, config.btc_price))
+}
+
+// Query string of the Deribit endpoints; currency defaults to BTC like before
+#[derive(serde::Deserialize)]
+struct DeribitQuery {
+    currency: Option<String>,
+}
+
+impl DeribitQuery {
+    fn asset(&self) -> Asset {
+        self.currency.as_deref().and_then(|c| c.parse().ok()).unwrap_or_default()
+    }
+}
+
+// Deribit-style instrument names for daily expiries over the next week, with 41
+// strikes around the mock price ($1000 apart for BTC, $50 for ETH)
+fn mock_instruments(config: &MockConfig, asset: Asset) -> Vec<(String, i64, f64, &'static str)> {
+    let step = match asset {
+        Asset::Btc => 1000.0,
+        Asset::Eth => 50.0,
+    };
+    let center = (config.price(asset) / step).round() * step;
+    let today_expiry = Utc::now().date_naive().and_hms_opt(8, 0, 0).unwrap().and_utc();
+
+    let mut instruments = Vec::new();
+    for day in 1..=8 {
+        let expiry = today_expiry + Duration::days(day);
+        let expiry_str = expiry.format("%-d%b%y").to_string().to_uppercase();
+        for i in -20..=20 {
+            let strike = center + i as f64 * step;
+            if strike <= 0.0 {
+                continue;
+            }
+            for side in ["C", "P"] {
+                let name = format!("{}-{}-{}-{}", asset, expiry_str, strike, side);
+                instruments.push((name, expiry.timestamp_millis(), strike, side));
+            }
+        }
+    }
+    instruments
+}
+
+// Mock of Deribit /public/get_book_summary_by_currency (mark_iv in percent)
+async fn deribit_book_summary(query: web::Query<DeribitQuery>, config: web::Data<MockConfig>) -> impl Responder {
+    let asset = query.asset();
+    let result: Vec<_> = mock_instruments(&config, asset)
+        .into_iter()
+        .map(|(name, _, strike, _)| {
+            json!({
+                "instrument_name": name,
+                "mark_iv": smile_iv(strike, config.price(asset)) * 100.0,
+                "underlying_price": config.price(asset),
+            })
+        })
+        .collect();
+    HttpResponse::Ok().json(json!({ "result": result }))
+}
+
+// Mock of Deribit /public/get_instruments
+async fn deribit_instruments(query: web::Query<DeribitQuery>, config: web::Data<MockConfig>) -> impl Responder {
+    let result: Vec<_> = mock_instruments(&config, query.asset())
+        .into_iter()
+        .map(|(name, expiration_timestamp, strike, side)| {
+            json!({
+                "instrument_name": name,
+                "is_active": true,
+                "expiration_timestamp": expiration_timestamp,
+                "strike": strike,
+                "option_type": if side == "C" { "call" } else { "put" },
+            })
+        })
+        .collect();
+    HttpResponse::Ok().json(json!({ "result": result }))
+}
+
+// Mock of mempool.space /address/{address}: a single confirmed funding output
+async fn mempool_address(path: web::Path<String>, config: web::Data<MockConfig>) -> impl Responder {
+    HttpResponse::Ok().json(json!({
+        "address": path.into_inner(),
+        "chain_stats": {
+            "funded_txo_count": 1,
+            "funded_txo_sum": config.pool_balance_sats,
+            "spent_txo_count": 0,
+            "spent_txo_sum": 0,
+            "tx_count": 1
+        },
+        "mempool_stats": {
+            "funded_txo_count": 0,
+            "funded_txo_sum": 0,
+            "spent_txo_count": 0,
+            "spent_txo_sum": 0,
+            "tx_count": 0
+        }
+    }))
+}
+
+// Mock of mempool.space /address/{address}/utxo
+async fn mempool_utxos(config: web::Data<MockConfig>) -> impl Responder {
+    HttpResponse::Ok().json(json!([{
+        "txid": "0".repeat(64),
+        "vout": 0,
+        "status": { "confirmed": true, "block_height": 1, "block_hash": null, "block_time": null },
+        "value": config.pool_balance_sats
+    }]))
+}
+
+// Mock of mempool.space /address/{address}/txs
+async fn mempool_transactions() -> impl Responder {
+    HttpResponse::Ok().json(json!([]))
+}
+
+// Mock of mempool.space /blocks/tip/height
+async fn mempool_tip_height() -> impl Responder {
+    HttpResponse::Ok().content_type("text/plain").body("1")
+}
+
+// Builds and binds the mock server; the caller drives the returned Server.
+// Always serves the fallback /iv endpoint; in offline mode it also mocks the
+// Deribit and mempool.space endpoints used by IvOracle and MutinyWallet.
+pub fn mock_server(config: MockConfig, offline: bool) -> std::io::Result<Server> {
+    let bind_address = config.bind_address.clone();
+    HttpServer::new(move || {
+        let app = App::new()
+            .app_data(web::Data::new(config.clone()))
+            .service(web::resource("/iv").route(web::get().to(get_iv)));
+        if offline {
+            app.service(web::resource("/deribit/public/get_book_summary_by_currency").route(web::get().to(deribit_book_summary)))
+                .service(web::resource("/deribit/public/get_instruments").route(web::get().to(deribit_instruments)))
+                .service(web::resource("/mempool/address/{address}").route(web::get().to(mempool_address)))
+                .service(web::resource("/mempool/address/{address}/utxo").route(web::get().to(mempool_utxos)))
+                .service(web::resource("/mempool/address/{address}/txs").route(web::get().to(mempool_transactions)))
+                .service(web::resource("/mempool/blocks/tip/height").route(web::get().to(mempool_tip_height)))
+        } else {
+            app
+        }
     })
-    .bind("0.0.0.0:8081")?
-    .run()
-    .await
-} 
\ No newline at end of file
+    .bind(bind_address)
+    .map(|server| server.run())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::iv_oracle::{parse_asset_instrument_name, parse_instrument_name};
+
+    #[test]
+    fn test_mock_instruments_parse_like_deribit() {
+        let config = MockConfig {
+            bind_address: "127.0.0.1:9999".to_string(),
+            btc_price: 100400.0,
+            eth_price: 3520.0,
+            pool_balance_sats: 1,
+        };
+        let instruments = mock_instruments(&config, Asset::Btc);
+        assert_eq!(instruments.len(), 8 * 41 * 2);
+
+        let (name, _, strike, side) = &instruments[0];
+        let (_, parsed_strike, parsed_side) = parse_instrument_name(name).unwrap();
+        assert_eq!(parsed_strike, *strike);
+        assert_eq!(parsed_side, *side);
+        assert_eq!(config.base_url(), "http://127.0.0.1:9999");
+
+        let eth = mock_instruments(&config, Asset::Eth);
+        let (name, _, strike, _) = &eth[0];
+        assert_eq!(parse_asset_instrument_name(name, Asset::Eth).unwrap().1, *strike);
+        assert!(parse_instrument_name(name).is_none());
+        assert!(eth.iter().all(|(_, _, strike, _)| *strike >= 2500.0 && *strike <= 4500.0));
+    }
+}
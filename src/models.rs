@@ -0,0 +1,515 @@
+use crate::utils::{format_expires_timestamp, usd_to_cents, cents_to_usd, btc_to_sats, sats_to_btc, float_to_db_string, round_btc, BTC_PRECISION, USD_PRECISION};
+use rusqlite::types::{ToSql, FromSql, ToSqlOutput, FromSqlError, ValueRef};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// Represents the side of an option: Call or Put.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, async_graphql::Enum)]
+pub enum OptionSide {
+    #[serde(alias = "call")]
+    Call,
+    #[serde(alias = "put")]
+    Put,
+}
+
+impl ToSql for OptionSide {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.to_string().into())
+    }
+}
+
+impl FromSql for OptionSide {
+    fn column_result(value: ValueRef<'_>) -> std::result::Result<Self, FromSqlError> {
+        value.as_str()?.parse()
+    }
+}
+
+impl std::str::FromStr for OptionSide {
+    type Err = FromSqlError;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "Call" => Ok(OptionSide::Call),
+            "Put" => Ok(OptionSide::Put),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl fmt::Display for OptionSide {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OptionSide::Call => write!(f, "Call"),
+            OptionSide::Put => write!(f, "Put"),
+        }
+    }
+}
+
+// Underlying asset an option is written on. Premiums and pool collateral stay in BTC
+// whatever the underlying; strikes are quoted in USD per unit of the underlying.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord, async_graphql::Enum)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Asset {
+    #[default]
+    #[serde(alias = "btc")]
+    Btc,
+    #[serde(alias = "eth")]
+    Eth,
+}
+
+impl Asset {
+    pub const ALL: [Asset; 2] = [Asset::Btc, Asset::Eth];
+
+    /// Parse a comma separated list such as "BTC,ETH". BTC is always included,
+    /// since pool collateral and premiums are valued in it.
+    pub fn parse_list(list: &str) -> Result<Vec<Asset>, String> {
+        let mut assets = vec![Asset::Btc];
+        for name in list.split(',').map(|name| name.trim()).filter(|name| !name.is_empty()) {
+            let asset: Asset = name.parse().map_err(|_| format!("unknown asset '{}'", name))?;
+            if !assets.contains(&asset) {
+                assets.push(asset);
+            }
+        }
+        Ok(assets)
+    }
+}
+
+impl ToSql for Asset {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.to_string().into())
+    }
+}
+
+impl FromSql for Asset {
+    fn column_result(value: ValueRef<'_>) -> std::result::Result<Self, FromSqlError> {
+        value.as_str()?.parse()
+    }
+}
+
+impl std::str::FromStr for Asset {
+    type Err = FromSqlError;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "BTC" => Ok(Asset::Btc),
+            "ETH" => Ok(Asset::Eth),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl fmt::Display for Asset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Asset::Btc => write!(f, "BTC"),
+            Asset::Eth => write!(f, "ETH"),
+        }
+    }
+}
+
+// Deribit style instrument name of a product, e.g. BTC-28MAR25-50000-C: underlying, UTC
+// expiry date, strike and C/P. It never changes over the product's life, so clients can key on it.
+pub fn product_symbol(underlying: Asset, side: &OptionSide, strike_price: f64, expires: i64) -> String {
+    let date = chrono::DateTime::from_timestamp(expires, 0)
+        .map(|at| at.format("%-d%b%y").to_string().to_uppercase())
+        .unwrap_or_default();
+    let side = match side {
+        OptionSide::Call => "C",
+        OptionSide::Put => "P",
+    };
+    format!("{}-{}-{}-{}", underlying, date, strike_price, side)
+}
+
+// The former symbol with the time left instead of the expiry date (e.g. BTC-2d-50000-Put).
+// It changes as expiry approaches and is kept for clients that still parse it.
+pub fn legacy_product_symbol(underlying: Asset, side: &OptionSide, strike_price: f64, expires: i64) -> String {
+    format!("{}-{}-{}-{}", underlying, format_expires_timestamp(expires), strike_price, side)
+}
+
+// Currency a premium is quoted and settled in. Stored premiums are always BTC; USD and
+// USDT quotes are converted at the BTC price of the moment, with USDT taken at par with USD.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash, async_graphql::Enum)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum QuoteCurrency {
+    #[default]
+    #[serde(alias = "btc")]
+    Btc,
+    #[serde(alias = "usd")]
+    Usd,
+    #[serde(alias = "usdt")]
+    Usdt,
+}
+
+impl QuoteCurrency {
+    /// Decimal places amounts in this currency are kept to
+    pub fn precision(self) -> u32 {
+        match self {
+            QuoteCurrency::Btc => BTC_PRECISION,
+            QuoteCurrency::Usd | QuoteCurrency::Usdt => USD_PRECISION,
+        }
+    }
+
+    /// Convert an amount in this currency to BTC at `btc_price` (USD per BTC)
+    pub fn to_btc(self, amount: f64, btc_price: f64) -> f64 {
+        match self {
+            QuoteCurrency::Btc => amount,
+            QuoteCurrency::Usd | QuoteCurrency::Usdt => amount / btc_price,
+        }
+    }
+
+    /// Convert a BTC amount to this currency at `btc_price` (USD per BTC)
+    pub fn from_btc(self, amount_btc: f64, btc_price: f64) -> f64 {
+        match self {
+            QuoteCurrency::Btc => amount_btc,
+            QuoteCurrency::Usd | QuoteCurrency::Usdt => amount_btc * btc_price,
+        }
+    }
+
+    /// Convert a USD amount to this currency at `btc_price` (USD per BTC)
+    pub fn from_usd(self, amount_usd: f64, btc_price: f64) -> f64 {
+        match self {
+            QuoteCurrency::Btc => amount_usd / btc_price,
+            QuoteCurrency::Usd | QuoteCurrency::Usdt => amount_usd,
+        }
+    }
+
+    /// Format an amount with this currency's precision
+    pub fn format(self, amount: f64) -> String {
+        float_to_db_string(amount, self.precision())
+    }
+
+    /// Amount in this currency's minor unit (satoshis or cents), as stored
+    pub fn to_minor(self, amount: f64) -> i64 {
+        (amount * 10f64.powi(self.precision() as i32)).round() as i64
+    }
+
+    pub fn from_minor(self, minor: i64) -> f64 {
+        minor as f64 / 10f64.powi(self.precision() as i32)
+    }
+}
+
+impl ToSql for QuoteCurrency {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.to_string().into())
+    }
+}
+
+impl FromSql for QuoteCurrency {
+    fn column_result(value: ValueRef<'_>) -> std::result::Result<Self, FromSqlError> {
+        value.as_str()?.parse()
+    }
+}
+
+impl std::str::FromStr for QuoteCurrency {
+    type Err = FromSqlError;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "BTC" => Ok(QuoteCurrency::Btc),
+            "USD" => Ok(QuoteCurrency::Usd),
+            "USDT" => Ok(QuoteCurrency::Usdt),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl fmt::Display for QuoteCurrency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            QuoteCurrency::Btc => write!(f, "BTC"),
+            QuoteCurrency::Usd => write!(f, "USD"),
+            QuoteCurrency::Usdt => write!(f, "USDT"),
+        }
+    }
+}
+
+// Premium as agreed with the buyer, kept with the BTC price it was converted at
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct PremiumQuote {
+    pub currency: QuoteCurrency,
+    pub amount: f64,
+    pub btc_price: f64,  // USD per BTC at trade time
+}
+
+impl PremiumQuote {
+    pub fn new(currency: QuoteCurrency, amount: f64, btc_price: f64) -> Self {
+        Self { currency, amount, btc_price }
+    }
+
+    /// Premium in BTC, as stored on the contract
+    pub fn premium_btc(&self) -> f64 {
+        round_btc(self.currency.to_btc(self.amount, self.btc_price))
+    }
+}
+
+// New terms of an open contract. Amendments only extend a contract: a later expiry, a larger
+// quantity and a premium per unit no lower than before.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ContractAmendment {
+    pub expires: i64,
+    pub quantity_sats: i64,  // As traded, including any quantity closed since
+    pub premium_sats: i64,   // Per unit, in BTC
+}
+
+// Market a contract was traded in, recorded with it
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct TradeSnapshot {
+    pub spot_price: f64,    // Of the underlying
+    pub iv: f64,            // The contract was priced and margined at
+    pub iv_source: IvProvenance,
+    pub mark_premium: f64,  // Black-Scholes value of one option, in BTC
+}
+
+// Where an IV came from
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum IvProvenance {
+    Deribit,       // Quoted by Deribit for this very option
+    Interpolated,  // Derived from quotes of other strikes or expiries, e.g. by the SVI fit
+    Default,       // No IV was available; a fallback value was used
+}
+
+impl ToSql for IvProvenance {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.to_string().into())
+    }
+}
+
+impl FromSql for IvProvenance {
+    fn column_result(value: ValueRef<'_>) -> std::result::Result<Self, FromSqlError> {
+        match value.as_str()? {
+            "deribit" => Ok(IvProvenance::Deribit),
+            "interpolated" => Ok(IvProvenance::Interpolated),
+            "default" => Ok(IvProvenance::Default),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl fmt::Display for IvProvenance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            IvProvenance::Deribit => write!(f, "deribit"),
+            IvProvenance::Interpolated => write!(f, "interpolated"),
+            IvProvenance::Default => write!(f, "default"),
+        }
+    }
+}
+
+// Lifecycle state of a stored contract
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ContractStatus {
+    Pending,    // Awaiting on-chain payment of the premium
+    Open,
+    Expired,
+    Settled,
+    Exercised,  // Exercised early by the buyer and paid out at that time's price
+    Closed,     // Sold back to the pool in full before expiry
+    Cancelled,  // Premium not paid in time
+}
+
+impl ToSql for ContractStatus {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.to_string().into())
+    }
+}
+
+impl FromSql for ContractStatus {
+    fn column_result(value: ValueRef<'_>) -> std::result::Result<Self, FromSqlError> {
+        value.as_str()?.parse()
+    }
+}
+
+impl std::str::FromStr for ContractStatus {
+    type Err = FromSqlError;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(ContractStatus::Pending),
+            "open" => Ok(ContractStatus::Open),
+            "expired" => Ok(ContractStatus::Expired),
+            "settled" => Ok(ContractStatus::Settled),
+            "exercised" => Ok(ContractStatus::Exercised),
+            "closed" => Ok(ContractStatus::Closed),
+            "cancelled" => Ok(ContractStatus::Cancelled),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl fmt::Display for ContractStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ContractStatus::Pending => write!(f, "pending"),
+            ContractStatus::Open => write!(f, "open"),
+            ContractStatus::Expired => write!(f, "expired"),
+            ContractStatus::Settled => write!(f, "settled"),
+            ContractStatus::Exercised => write!(f, "exercised"),
+            ContractStatus::Closed => write!(f, "closed"),
+            ContractStatus::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+// When the buyer may exercise: only at expiry, or at any time before it
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExerciseStyle {
+    #[default]
+    European,
+    American,
+}
+
+impl ToSql for ExerciseStyle {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.to_string().into())
+    }
+}
+
+impl FromSql for ExerciseStyle {
+    fn column_result(value: ValueRef<'_>) -> std::result::Result<Self, FromSqlError> {
+        match value.as_str()? {
+            "european" => Ok(ExerciseStyle::European),
+            "american" => Ok(ExerciseStyle::American),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl fmt::Display for ExerciseStyle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ExerciseStyle::European => write!(f, "european"),
+            ExerciseStyle::American => write!(f, "american"),
+        }
+    }
+}
+
+// Contract structure for API input/output (uses floats for backward compatibility).
+// Loaded contracts carry no id, so equal contracts are those with the same terms.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Contract {
+    #[serde(default)]
+    pub underlying: Asset,
+    pub side: OptionSide,
+    pub strike_price: f64,
+    pub quantity: f64,
+    pub expires: i64,
+    pub premium: f64,
+}
+
+// Internal contract structure for database storage (integer units for precision)
+#[derive(Clone, Debug)]
+pub struct ContractDb {
+    pub underlying: Asset,
+    pub side: OptionSide,
+    pub strike_price_cents: i64,
+    pub quantity_sats: i64,
+    pub closed_quantity_sats: i64,           // Sold back to the pool so far
+    pub expires: i64,
+    pub premium_sats: i64,
+    pub premium_currency: QuoteCurrency,
+    pub quoted_premium_minor: Option<i64>,   // In premium_currency's minor unit
+    pub trade_btc_price_cents: Option<i64>,  // BTC price the premium was converted at
+}
+
+impl ContractDb {
+    // Convert from API contract to DB contract
+    pub fn from_contract(contract: &Contract) -> Self {
+        Self {
+            underlying: contract.underlying,
+            side: contract.side,
+            strike_price_cents: usd_to_cents(contract.strike_price),
+            quantity_sats: btc_to_sats(contract.quantity),
+            closed_quantity_sats: 0,
+            expires: contract.expires,
+            premium_sats: btc_to_sats(contract.premium),
+            premium_currency: QuoteCurrency::Btc,
+            quoted_premium_minor: None,
+            trade_btc_price_cents: None,
+        }
+    }
+    
+    pub fn open_quantity_sats(&self) -> i64 {
+        self.quantity_sats - self.closed_quantity_sats
+    }
+
+    // Convert to API contract (with the quantity still open) when needed for calculations
+    pub fn to_contract(&self) -> Contract {
+        Contract {
+            underlying: self.underlying,
+            side: self.side,
+            strike_price: cents_to_usd(self.strike_price_cents),
+            quantity: sats_to_btc(self.open_quantity_sats()),
+            expires: self.expires,
+            premium: sats_to_btc(self.premium_sats),
+        }
+    }
+}
+
+// Stored contract together with its id and lifecycle fields
+#[derive(Serialize, Clone, Debug)]
+pub struct ContractRecord {
+    pub id: i64,
+    pub underlying: Asset,
+    pub side: OptionSide,
+    pub strike_price: f64,
+    pub quantity: f64,                      // As traded
+    pub closed_quantity: f64,               // Sold back to the pool so far
+    pub expires: i64,
+    pub premium: f64,
+    pub premium_currency: QuoteCurrency,
+    pub quoted_premium: Option<f64>,        // In premium_currency
+    pub trade_btc_price: Option<f64>,       // BTC price the premium was converted at
+    pub created_at: i64,
+    pub status: ContractStatus,
+    pub settlement_price: Option<f64>,      // Of the underlying
+    pub settlement_btc_price: Option<f64>,  // BTC price the payoff is converted at
+    pub settled_at: Option<i64>,            // Settlement or exercise time
+    pub exercise_style: ExerciseStyle,
+    pub counterparty: Option<String>,       // Buyer; None for contracts from before buyers were recorded
+    pub spot_at_trade: Option<f64>,         // Of the underlying; None for contracts from before snapshots
+    pub iv_at_trade: Option<f64>,
+    pub iv_source: Option<IvProvenance>,    // Where iv_at_trade came from
+    pub mark_premium_at_trade: Option<f64>, // Black-Scholes value of one option then, in BTC
+    pub pool_id: i64,                       // Pool the option was sold from
+}
+
+impl ContractRecord {
+    /// Quantity not yet sold back to the pool
+    pub fn open_quantity(&self) -> f64 {
+        sats_to_btc(btc_to_sats(self.quantity) - btc_to_sats(self.closed_quantity))
+    }
+
+    /// The open part of the contract
+    pub fn to_contract(&self) -> Contract {
+        Contract {
+            underlying: self.underlying,
+            side: self.side,
+            strike_price: self.strike_price,
+            quantity: self.open_quantity(),
+            expires: self.expires,
+            premium: self.premium,
+        }
+    }
+
+    /// Premium over the Black-Scholes value at trade time, in BTC per unit. Positive when the
+    /// buyer paid above the model.
+    pub fn edge_at_trade(&self) -> Option<f64> {
+        self.mark_premium_at_trade.map(|mark| self.premium - mark)
+    }
+
+    /// Intrinsic value of the open quantity owed to the buyer in USD at the given settlement price
+    pub fn payoff_usd(&self, settlement_price: f64) -> f64 {
+        let intrinsic = match self.side {
+            OptionSide::Call => (settlement_price - self.strike_price).max(0.0),
+            OptionSide::Put => (self.strike_price - settlement_price).max(0.0),
+        };
+        intrinsic * self.open_quantity()
+    }
+
+    /// Payoff in the contract's premium currency, converted at the recorded settlement BTC price.
+    /// None until the contract is settled.
+    pub fn settlement_payoff(&self) -> Option<f64> {
+        let settlement_price = self.settlement_price?;
+        let btc_price = self.settlement_btc_price?;
+        Some(self.premium_currency.from_usd(self.payoff_usd(settlement_price), btc_price))
+    }
+}
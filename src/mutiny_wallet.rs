@@ -1,5 +1,8 @@
-use reqwest::Client;
+use crate::http_client::{BasicAuth, HttpClient, HttpError};
+use bech32::{hrp, segwit};
+use reqwest::Url;
 use serde::{Deserialize, Serialize};
+use std::env;
 use std::error::Error;
 use std::fmt;
 
@@ -8,6 +11,7 @@ pub enum MutinyWalletError {
     NetworkError(String),
     ParseError(String),
     ApiError(String),
+    InvalidAddress(String),
 }
 
 impl fmt::Display for MutinyWalletError {
@@ -16,12 +20,23 @@ impl fmt::Display for MutinyWalletError {
             MutinyWalletError::NetworkError(msg) => write!(f, "Network error: {}", msg),
             MutinyWalletError::ParseError(msg) => write!(f, "Parse error: {}", msg),
             MutinyWalletError::ApiError(msg) => write!(f, "API error: {}", msg),
+            MutinyWalletError::InvalidAddress(msg) => write!(f, "Invalid address: {}", msg),
         }
     }
 }
 
 impl Error for MutinyWalletError {}
 
+impl From<HttpError> for MutinyWalletError {
+    fn from(e: HttpError) -> Self {
+        match e {
+            HttpError::Network(msg) => MutinyWalletError::NetworkError(msg),
+            HttpError::Status { .. } => MutinyWalletError::ApiError(e.to_string()),
+            _ => MutinyWalletError::NetworkError(e.to_string()),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AddressInfo {
     pub address: String,
@@ -125,20 +140,70 @@ pub struct WalletBalance {
     pub total_utxo_count: u64,
 }
 
+/// An Esplora-compatible REST API: the public mempool.space / mutinynet explorers, or a
+/// self-hosted esplora or electrs instance, optionally behind Basic auth
+#[derive(Clone, Debug, PartialEq)]
+pub struct EsploraConfig {
+    pub base_url: String,  // Without a trailing slash, e.g. https://mempool.example.com/api
+    pub auth: Option<BasicAuth>,
+}
+
+impl EsploraConfig {
+    /// The public explorer of `network`
+    pub fn public(network: Network) -> Self {
+        let base_url = match network {
+            Network::Mainnet => "https://mutiny.mempool.space/api",
+            Network::Testnet | Network::Signet => "https://mutinynet.com/api",
+        };
+        Self { base_url: base_url.to_string(), auth: None }
+    }
+
+    /// ESPLORA_<NETWORK>_URL (the public explorer), with Basic auth when
+    /// ESPLORA_<NETWORK>_USERNAME and optionally ESPLORA_<NETWORK>_PASSWORD are set,
+    /// e.g. ESPLORA_MAINNET_URL=http://electrs.internal:3000
+    pub fn from_env(network: Network) -> Result<Self, String> {
+        let prefix = format!("ESPLORA_{}", network.to_string().to_uppercase());
+        let Ok(url) = env::var(format!("{}_URL", prefix)) else {
+            return Ok(Self::public(network));
+        };
+        match Url::parse(&url) {
+            Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => {}
+            _ => return Err(format!("{}_URL must be an http(s) URL, got '{}'", prefix, url)),
+        }
+        let auth = env::var(format!("{}_USERNAME", prefix)).ok().map(|username| BasicAuth {
+            username,
+            password: env::var(format!("{}_PASSWORD", prefix)).ok(),
+        });
+        Ok(Self { base_url: url.trim_end_matches('/').to_string(), auth })
+    }
+}
+
 pub struct MutinyWallet {
-    client: Client,
-    base_url: String,
-    #[allow(dead_code)]
+    client: HttpClient,
+    esplora: EsploraConfig,
     network: Network,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Network {
     Mainnet,
     Testnet,
     Signet,
 }
 
+impl std::str::FromStr for Network {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "mainnet" => Ok(Network::Mainnet),
+            "testnet" => Ok(Network::Testnet),
+            "signet" => Ok(Network::Signet),
+            _ => Err(format!("unknown network '{}', expected mainnet, testnet or signet", s)),
+        }
+    }
+}
+
 impl std::fmt::Display for Network {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -149,42 +214,79 @@ impl std::fmt::Display for Network {
     }
 }
 
-impl MutinyWallet {
-    pub fn new(network: Network) -> Self {
-        let base_url = match network {
-            Network::Mainnet => "https://mutiny.mempool.space/api".to_string(),
-            Network::Testnet | Network::Signet => "https://mutinynet.com/api".to_string(),
+impl Network {
+    /// Check that `address` is a well-formed address of this network: segwit (bech32/bech32m)
+    /// with the network's prefix, or P2PKH/P2SH (base58check) with its version byte.
+    /// Testnet and signet share prefixes, so their addresses are accepted on either.
+    pub fn validate_address(self, address: &str) -> Result<(), MutinyWalletError> {
+        let invalid = |reason: String| MutinyWalletError::InvalidAddress(format!("{}: {}", address, reason));
+        let lowercase = address.to_ascii_lowercase();
+        let is_mainnet = match segwit::decode(address) {
+            Ok((prefix, _, _)) if prefix == hrp::BC => true,
+            Ok((prefix, _, _)) if prefix == hrp::TB => false,
+            Ok((prefix, _, _)) => return Err(invalid(format!("unsupported prefix '{}'", prefix))),
+            Err(e) if lowercase.starts_with("bc1") || lowercase.starts_with("tb1") => {
+                return Err(invalid(e.to_string()))
+            }
+            Err(_) => match bs58::decode(address).with_check(None).into_vec() {
+                Ok(payload) if payload.len() == 21 => match payload[0] {
+                    0x00 | 0x05 => true,
+                    0x6f | 0xc4 => false,
+                    version => return Err(invalid(format!("unsupported version byte {:#04x}", version))),
+                },
+                Ok(_) => return Err(invalid("unexpected payload length".to_string())),
+                Err(_) => return Err(invalid("not a bech32 or base58check address".to_string())),
+            },
         };
-
-        Self {
-            client: Client::new(),
-            base_url,
-            network,
+        if is_mainnet != (self == Network::Mainnet) {
+            let kind = if is_mainnet { "a mainnet" } else { "a testnet/signet" };
+            return Err(invalid(format!("{} address cannot be used on {}", kind, self)));
         }
+        Ok(())
+    }
+}
+
+impl MutinyWallet {
+    pub fn new(network: Network) -> Self {
+        Self::with_esplora(EsploraConfig::public(network), network)
     }
 
     pub fn with_custom_url(url: String, network: Network) -> Self {
+        Self::with_esplora(EsploraConfig { base_url: url, auth: None }, network)
+    }
+
+    pub fn with_esplora(esplora: EsploraConfig, network: Network) -> Self {
         Self {
-            client: Client::new(),
-            base_url: url,
+            client: HttpClient::default(),
+            esplora,
             network,
         }
     }
 
-    pub async fn get_address_info(&self, address: &str) -> Result<AddressInfo, MutinyWalletError> {
-        let url = format!("{}/address/{}", self.base_url, address);
-        
-        let response = self.client
-            .get(&url)
-            .send()
-            .await
-            .map_err(|e| MutinyWalletError::NetworkError(e.to_string()))?;
+    pub fn esplora(&self) -> &EsploraConfig {
+        &self.esplora
+    }
 
-        if !response.status().is_success() {
-            return Err(MutinyWalletError::ApiError(
-                format!("API returned status: {}", response.status())
-            ));
-        }
+    async fn get(&self, path: &str) -> Result<reqwest::Response, MutinyWalletError> {
+        let url = format!("{}{}", self.esplora.base_url, path);
+        Ok(self.client.get_with_auth(&url, self.esplora.auth.as_ref()).await?)
+    }
+
+    /// Share a client configured for retries, timeouts and circuit breaking
+    pub fn with_http_client(mut self, client: HttpClient) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Check that `address` belongs to this wallet's network, so a mainnet address is never
+    /// looked up on mutinynet or the other way round
+    pub fn validate_address(&self, address: &str) -> Result<(), MutinyWalletError> {
+        self.network.validate_address(address)
+    }
+
+    pub async fn get_address_info(&self, address: &str) -> Result<AddressInfo, MutinyWalletError> {
+        self.validate_address(address)?;
+        let response = self.get(&format!("/address/{}", address)).await?;
 
         let address_info = response
             .json::<AddressInfo>()
@@ -195,19 +297,8 @@ impl MutinyWallet {
     }
 
     pub async fn get_address_utxos(&self, address: &str) -> Result<Vec<Utxo>, MutinyWalletError> {
-        let url = format!("{}/address/{}/utxo", self.base_url, address);
-        
-        let response = self.client
-            .get(&url)
-            .send()
-            .await
-            .map_err(|e| MutinyWalletError::NetworkError(e.to_string()))?;
-
-        if !response.status().is_success() {
-            return Err(MutinyWalletError::ApiError(
-                format!("API returned status: {}", response.status())
-            ));
-        }
+        self.validate_address(address)?;
+        let response = self.get(&format!("/address/{}/utxo", address)).await?;
 
         let utxos = response
             .json::<Vec<Utxo>>()
@@ -218,19 +309,8 @@ impl MutinyWallet {
     }
 
     pub async fn get_address_transactions(&self, address: &str) -> Result<Vec<Transaction>, MutinyWalletError> {
-        let url = format!("{}/address/{}/txs", self.base_url, address);
-        
-        let response = self.client
-            .get(&url)
-            .send()
-            .await
-            .map_err(|e| MutinyWalletError::NetworkError(e.to_string()))?;
-
-        if !response.status().is_success() {
-            return Err(MutinyWalletError::ApiError(
-                format!("API returned status: {}", response.status())
-            ));
-        }
+        self.validate_address(address)?;
+        let response = self.get(&format!("/address/{}/txs", address)).await?;
 
         let transactions = response
             .json::<Vec<Transaction>>()
@@ -263,20 +343,19 @@ impl MutinyWallet {
         })
     }
 
-    pub async fn get_transaction(&self, txid: &str) -> Result<Transaction, MutinyWalletError> {
-        let url = format!("{}/tx/{}", self.base_url, txid);
-        
-        let response = self.client
-            .get(&url)
-            .send()
+    pub async fn get_tip_height(&self) -> Result<u64, MutinyWalletError> {
+        let response = self.get("/blocks/tip/height").await?;
+
+        let body = response
+            .text()
             .await
             .map_err(|e| MutinyWalletError::NetworkError(e.to_string()))?;
 
-        if !response.status().is_success() {
-            return Err(MutinyWalletError::ApiError(
-                format!("API returned status: {}", response.status())
-            ));
-        }
+        body.trim().parse().map_err(|e: std::num::ParseIntError| MutinyWalletError::ParseError(e.to_string()))
+    }
+
+    pub async fn get_transaction(&self, txid: &str) -> Result<Transaction, MutinyWalletError> {
+        let response = self.get(&format!("/tx/{}", txid)).await?;
 
         let transaction = response
             .json::<Transaction>()
@@ -298,6 +377,73 @@ impl MutinyWallet {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_self_hosted_esplora_with_basic_auth() {
+        env::set_var("ESPLORA_TESTNET_URL", "ftp://electrs.internal");
+        assert!(EsploraConfig::from_env(Network::Testnet).is_err());
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/esplora/", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let n = stream.read(&mut request).unwrap();
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 6\r\nConnection: close\r\n\r\n840000");
+            String::from_utf8_lossy(&request[..n]).to_lowercase()
+        });
+        env::set_var("ESPLORA_TESTNET_URL", &url);
+        env::set_var("ESPLORA_TESTNET_USERNAME", "user");
+        env::set_var("ESPLORA_TESTNET_PASSWORD", "pass");
+        let esplora = EsploraConfig::from_env(Network::Testnet).unwrap();
+        for name in ["ESPLORA_TESTNET_URL", "ESPLORA_TESTNET_USERNAME", "ESPLORA_TESTNET_PASSWORD"] {
+            env::remove_var(name);
+        }
+        assert_eq!(esplora.base_url, url.trim_end_matches('/'));
+        assert_eq!(EsploraConfig::from_env(Network::Testnet).unwrap(), EsploraConfig::public(Network::Testnet));
+
+        let wallet = MutinyWallet::with_esplora(esplora, Network::Testnet);
+        assert_eq!(wallet.get_tip_height().await.unwrap(), 840_000);
+        let request = server.join().unwrap();
+        assert!(request.starts_with("get /esplora/blocks/tip/height "), "{}", request);
+        // base64("user:pass")
+        assert!(request.contains("authorization: basic dxnlcjpwyxnz"), "{}", request);
+    }
+
+    #[test]
+    fn test_addresses_are_validated_against_the_network() {
+        for address in [
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4",
+            "BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4",
+            "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0",
+            "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2",
+            "3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy",
+        ] {
+            assert!(Network::Mainnet.validate_address(address).is_ok(), "{}", address);
+            assert!(matches!(Network::Signet.validate_address(address), Err(MutinyWalletError::InvalidAddress(_))));
+        }
+        for address in [
+            "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx",
+            "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7",
+            "mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn",
+        ] {
+            assert!(Network::Signet.validate_address(address).is_ok(), "{}", address);
+            assert!(Network::Testnet.validate_address(address).is_ok(), "{}", address);
+            assert!(Network::Mainnet.validate_address(address).is_err(), "{}", address);
+        }
+        for address in [
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t5",  // Bad checksum
+            "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN3",          // Bad checksum
+            "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080", // Regtest
+            "offline-pool-address",
+            "",
+        ] {
+            assert!(Network::Mainnet.validate_address(address).is_err(), "{}", address);
+            assert!(Network::Signet.validate_address(address).is_err(), "{}", address);
+        }
+    }
 
     #[tokio::test]
     async fn test_mutiny_testnet_balance_query() {
@@ -0,0 +1,240 @@
+use crate::models::Asset;
+use crate::strikes;
+use crate::utils::{duration_to_seconds, tenor_expiry, DEFAULT_EXPIRY_HOUR_UTC};
+use std::env;
+
+// Upper bounds so a single /optionsTable request can't ask for an unbounded grid
+pub const MAX_STRIKES_PER_SIDE: u32 = 50;
+pub const MAX_TENORS: usize = 20;
+
+const DEFAULT_STRIKE_STEP: f64 = 1000.0;
+const DEFAULT_STRIKES_PER_SIDE: u32 = 5;
+const DEFAULT_TENORS: &str = "1d,2d,3d,5d,7d";
+
+// Percent-based strikes are rounded to this increment (USD)
+const PERCENT_STRIKE_ROUNDING: f64 = 100.0;
+
+// ETH trades around 1/30th of BTC, so its default grid is correspondingly finer
+const ETH_STRIKE_STEP: f64 = 50.0;
+const ETH_PERCENT_STRIKE_ROUNDING: f64 = 10.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StrikeSpacing {
+    Absolute(f64), // USD between strikes, centered on spot rounded to the step
+    Percent(f64),  // Percent of spot between strikes (2.5 = 2.5%)
+}
+
+/// Strike grid and tenor list used to build the options table
+#[derive(Debug, Clone, PartialEq)]
+pub struct GridConfig {
+    pub spacing: StrikeSpacing,
+    pub strikes_per_side: u32,
+    pub tenors: Vec<String>,
+    pub strike_rounding: f64,  // USD increment percent-based strikes are rounded to
+    pub strike_tick: f64,      // Strike tick of the underlying, every strike is on it
+    pub expiry_hour: u32,      // UTC hour day tenors expire at
+}
+
+impl Default for GridConfig {
+    fn default() -> Self {
+        Self {
+            spacing: StrikeSpacing::Absolute(DEFAULT_STRIKE_STEP),
+            strikes_per_side: DEFAULT_STRIKES_PER_SIDE,
+            tenors: parse_tenors(DEFAULT_TENORS).unwrap(),
+            strike_rounding: PERCENT_STRIKE_ROUNDING,
+            strike_tick: strikes::DEFAULT_BTC_TICK,
+            expiry_hour: DEFAULT_EXPIRY_HOUR_UTC,
+        }
+    }
+}
+
+impl GridConfig {
+    /// Server defaults from OPTIONS_STRIKE_STEP, OPTIONS_STRIKE_PERCENT,
+    /// OPTIONS_STRIKES_PER_SIDE, OPTIONS_TENORS and OPTIONS_EXPIRY_HOUR_UTC.
+    /// Invalid values fall back to the defaults.
+    pub fn from_env() -> Self {
+        let defaults = Self { expiry_hour: expiry_hour_from_env(), ..Self::default() };
+        let step = env::var("OPTIONS_STRIKE_STEP").ok().and_then(|v| v.parse().ok());
+        let percent = env::var("OPTIONS_STRIKE_PERCENT").ok().and_then(|v| v.parse().ok());
+        let strikes_per_side = env::var("OPTIONS_STRIKES_PER_SIDE").ok().and_then(|v| v.parse().ok());
+        let tenors = env::var("OPTIONS_TENORS").ok();
+
+        defaults
+            .clone()
+            .with_overrides(step, percent, strikes_per_side, tenors.as_deref())
+            .unwrap_or_else(|e| {
+                eprintln!("⚠️  Invalid options grid configuration ({}), using defaults", e);
+                defaults
+            })
+    }
+
+    /// The grid for another underlying. The configured (BTC) grid is kept as is
+    /// for BTC; other underlyings keep the tenors and strike count but use their
+    /// own USD strike step, unless strikes are percent-based. Strikes are on the
+    /// deployment's tick of the underlying.
+    pub fn for_asset(&self, asset: Asset) -> Self {
+        let mut grid = self.clone();
+        grid.strike_tick = strikes::deployment().tick(asset);
+        if asset == Asset::Eth {
+            if let StrikeSpacing::Absolute(_) = grid.spacing {
+                grid.spacing = StrikeSpacing::Absolute(ETH_STRIKE_STEP);
+            }
+            grid.strike_rounding = ETH_PERCENT_STRIKE_ROUNDING;
+        }
+        grid
+    }
+
+    /// Apply per-request overrides. `percent` takes precedence over `step` when both are set.
+    pub fn with_overrides(
+        mut self,
+        step: Option<f64>,
+        percent: Option<f64>,
+        strikes_per_side: Option<u32>,
+        tenors: Option<&str>,
+    ) -> Result<Self, String> {
+        if let Some(step) = step {
+            if !(step.is_finite() && step > 0.0) {
+                return Err("strike step must be a positive number".to_string());
+            }
+            self.spacing = StrikeSpacing::Absolute(step);
+        }
+        if let Some(percent) = percent {
+            if !(percent.is_finite() && percent > 0.0 && percent < 100.0) {
+                return Err("strike percent must be between 0 and 100".to_string());
+            }
+            self.spacing = StrikeSpacing::Percent(percent);
+        }
+        if let Some(count) = strikes_per_side {
+            if count > MAX_STRIKES_PER_SIDE {
+                return Err(format!("at most {} strikes per side are allowed", MAX_STRIKES_PER_SIDE));
+            }
+            self.strikes_per_side = count;
+        }
+        if let Some(tenors) = tenors {
+            self.tenors = parse_tenors(tenors)?;
+        }
+        Ok(self)
+    }
+
+    /// Expiry timestamp of `tenor` quoted at `now`, see `tenor_expiry`
+    pub fn expiry(&self, tenor: &str, now: i64) -> Option<i64> {
+        tenor_expiry(tenor, now, self.expiry_hour)
+    }
+
+    /// Strikes around `spot`, ascending, positive and without duplicates
+    pub fn strikes(&self, spot: f64) -> Vec<f64> {
+        let n = self.strikes_per_side as i64;
+        let mut strikes: Vec<f64> = match self.spacing {
+            StrikeSpacing::Absolute(step) => {
+                let center = (spot / step).round() * step;
+                (-n..=n).map(|i| center + i as f64 * step).collect()
+            }
+            StrikeSpacing::Percent(percent) => (-n..=n)
+                .map(|i| {
+                    let strike = spot * (1.0 + i as f64 * percent / 100.0);
+                    (strike / self.strike_rounding).round() * self.strike_rounding
+                })
+                .collect(),
+        };
+
+        // A step or rounding finer than the tick would list strikes that cannot be traded
+        for strike in strikes.iter_mut() {
+            *strike = (*strike / self.strike_tick).round() * self.strike_tick;
+        }
+        strikes.retain(|strike| *strike > 0.0);
+        strikes.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        strikes.dedup();
+        strikes
+    }
+}
+
+/// OPTIONS_EXPIRY_HOUR_UTC (8), the UTC hour day tenors expire at
+pub fn expiry_hour_from_env() -> u32 {
+    env::var("OPTIONS_EXPIRY_HOUR_UTC")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|hour| *hour < 24)
+        .unwrap_or(DEFAULT_EXPIRY_HOUR_UTC)
+}
+
+/// Parse a comma separated tenor list such as "1d,2d,12h"
+pub fn parse_tenors(list: &str) -> Result<Vec<String>, String> {
+    let tenors: Vec<String> = list
+        .split(',')
+        .map(|t| t.trim())
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_string())
+        .collect();
+
+    if tenors.is_empty() {
+        return Err("at least one tenor is required".to_string());
+    }
+    if tenors.len() > MAX_TENORS {
+        return Err(format!("at most {} tenors are allowed", MAX_TENORS));
+    }
+    if let Some(bad) = tenors.iter().find(|t| duration_to_seconds(t) <= 0) {
+        return Err(format!("invalid tenor '{}', expected e.g. 30m, 12h or 7d", bad));
+    }
+    Ok(tenors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_grid_matches_legacy_table() {
+        let grid = GridConfig::default();
+        let strikes = grid.strikes(100_400.0);
+        assert_eq!(strikes.len(), 11);
+        assert_eq!(strikes.first(), Some(&95_000.0));
+        assert_eq!(strikes.last(), Some(&105_000.0));
+        assert_eq!(grid.tenors, vec!["1d", "2d", "3d", "5d", "7d"]);
+    }
+
+    #[test]
+    fn test_percent_strikes() {
+        let grid = GridConfig::default()
+            .with_overrides(None, Some(5.0), Some(2), None)
+            .unwrap();
+        assert_eq!(grid.strikes(100_000.0), vec![90_000.0, 95_000.0, 100_000.0, 105_000.0, 110_000.0]);
+    }
+
+    #[test]
+    fn test_eth_grid() {
+        let eth = GridConfig::default().for_asset(Asset::Eth);
+        let strikes = eth.strikes(3_520.0);
+        assert_eq!(strikes.first(), Some(&3_250.0));
+        assert_eq!(strikes.last(), Some(&3_750.0));
+        assert_eq!(eth.tenors, GridConfig::default().tenors);
+
+        let percent = eth.with_overrides(None, Some(2.0), Some(1), None).unwrap();
+        assert_eq!(percent.strikes(3_500.0), vec![3_430.0, 3_500.0, 3_570.0]);
+        assert_eq!(GridConfig::default().for_asset(Asset::Btc), GridConfig::default());
+    }
+
+    #[test]
+    fn test_strikes_are_on_the_tick() {
+        let grid = GridConfig::default()
+            .with_overrides(Some(150.0), None, Some(2), None)
+            .unwrap();
+        assert_eq!(grid.strikes(100_000.0), vec![99_800.0, 99_900.0, 100_100.0, 100_200.0, 100_400.0]);
+    }
+
+    #[test]
+    fn test_strikes_stay_positive() {
+        let grid = GridConfig::default()
+            .with_overrides(Some(1000.0), None, Some(5), None)
+            .unwrap();
+        assert!(grid.strikes(2_000.0).iter().all(|s| *s > 0.0));
+    }
+
+    #[test]
+    fn test_invalid_overrides_rejected() {
+        assert!(GridConfig::default().with_overrides(Some(-1.0), None, None, None).is_err());
+        assert!(GridConfig::default().with_overrides(None, None, Some(MAX_STRIKES_PER_SIDE + 1), None).is_err());
+        assert!(parse_tenors("1d,abc").is_err());
+        assert!(parse_tenors(" , ").is_err());
+        assert_eq!(parse_tenors("12h, 1d").unwrap(), vec!["12h", "1d"]);
+    }
+}
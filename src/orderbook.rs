@@ -0,0 +1,251 @@
+// Resting quotes.
+// Instead of pricing every request for quote, the pool posts standing offers to sell each
+// product of the options grid: a price from the pricing engine, a size from the collateral
+// left over by the open book, and a time to live. GET /orderbook serves the live offers and
+// reposts the book of an underlying once they have all expired or filled. POST /orderbook/take
+// fills an offer at its posted price. A fill is still checked like any POST /contract, since
+// the book and the collateral can move between posting and taking.
+
+use crate::error::{ApiError, ApiResult};
+use crate::models::{Asset, OptionSide};
+use crate::utils::{btc_to_sats, cents_to_usd, format_sats, sats_to_btc, usd_to_cents};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::Serialize;
+use std::env;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OrderbookConfig {
+    pub quote_ttl_secs: i64,   // How long a posted offer stays live
+    pub size_percent: f64,     // Offer size as % of the grid's max quantity
+    pub spread_percent: f64,   // Offer price above the model premium
+}
+
+impl Default for OrderbookConfig {
+    fn default() -> Self {
+        Self {
+            quote_ttl_secs: 30,
+            size_percent: 50.0,
+            spread_percent: 0.0,
+        }
+    }
+}
+
+impl OrderbookConfig {
+    /// ORDERBOOK_QUOTE_TTL_SECS (30), ORDERBOOK_SIZE_PERCENT (50) and ORDERBOOK_SPREAD_PERCENT (0)
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let parse = |name: &str| env::var(name).ok().and_then(|v| v.parse::<f64>().ok());
+        Self {
+            quote_ttl_secs: env::var("ORDERBOOK_QUOTE_TTL_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.quote_ttl_secs)
+                .max(1),
+            size_percent: parse("ORDERBOOK_SIZE_PERCENT")
+                .filter(|percent| *percent > 0.0 && *percent <= 100.0)
+                .unwrap_or(defaults.size_percent),
+            spread_percent: parse("ORDERBOOK_SPREAD_PERCENT")
+                .filter(|percent| *percent >= 0.0)
+                .unwrap_or(defaults.spread_percent),
+        }
+    }
+
+    /// Offer price and size for a product with model premium `premium_btc` and `max_quantity`
+    pub fn offer(&self, premium_btc: f64, max_quantity: f64) -> (f64, f64) {
+        let price = premium_btc * (1.0 + self.spread_percent / 100.0);
+        let size = max_quantity * self.size_percent / 100.0;
+        (sats_to_btc(btc_to_sats(price)), sats_to_btc(btc_to_sats(size)))
+    }
+}
+
+/// A product the pool is about to offer
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewQuote {
+    pub underlying: Asset,
+    pub side: OptionSide,
+    pub strike_price: f64,
+    pub expires: i64,
+    pub price: f64,  // BTC per unit
+    pub size: f64,
+    pub iv: f64,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct RestingQuote {
+    pub id: i64,
+    pub underlying: Asset,
+    pub side: OptionSide,
+    pub strike_price: f64,
+    pub expires: i64,
+    pub price: f64,        // BTC per unit
+    pub size: f64,         // As posted
+    pub remaining: f64,    // Size not yet taken
+    pub iv: f64,           // Priced at
+    pub posted_at: i64,
+    pub valid_until: i64,
+}
+
+const QUOTE_COLUMNS: &str = "id, underlying, side, strike_price_cents, expires, price_sats, size_sats,
+                             filled_sats, iv, posted_at, valid_until";
+
+fn quote_from_row(row: &Row) -> rusqlite::Result<RestingQuote> {
+    let size_sats: i64 = row.get(6)?;
+    let filled_sats: i64 = row.get(7)?;
+    Ok(RestingQuote {
+        id: row.get(0)?,
+        underlying: row.get(1)?,
+        side: row.get(2)?,
+        strike_price: cents_to_usd(row.get(3)?),
+        expires: row.get(4)?,
+        price: sats_to_btc(row.get(5)?),
+        size: sats_to_btc(size_sats),
+        remaining: sats_to_btc(size_sats - filled_sats),
+        iv: row.get(8)?,
+        posted_at: row.get(9)?,
+        valid_until: row.get(10)?,
+    })
+}
+
+/// Withdraw the live quotes of `underlying` and post `quotes` in their place, valid for
+/// `ttl_secs`. Quotes without size are not posted. Returns the new live quotes.
+pub fn post_quotes(
+    conn: &Connection,
+    underlying: Asset,
+    quotes: &[NewQuote],
+    now: i64,
+    ttl_secs: i64,
+) -> ApiResult<Vec<RestingQuote>> {
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "UPDATE resting_quotes SET withdrawn_at = ?1
+         WHERE underlying = ?2 AND withdrawn_at IS NULL AND valid_until > ?1",
+        params![now, underlying],
+    )?;
+    for quote in quotes.iter().filter(|q| btc_to_sats(q.size) > 0 && q.expires > now) {
+        tx.execute(
+            "INSERT INTO resting_quotes (underlying, side, strike_price_cents, expires, price_sats, size_sats,
+                                         iv, posted_at, valid_until)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                quote.underlying,
+                quote.side,
+                usd_to_cents(quote.strike_price),
+                quote.expires,
+                btc_to_sats(quote.price),
+                btc_to_sats(quote.size),
+                quote.iv,
+                now,
+                now + ttl_secs
+            ],
+        )?;
+    }
+    let live = load_live_quotes(&tx, underlying, now)?;
+    tx.commit()?;
+    Ok(live)
+}
+
+/// Quotes of `underlying` that can still be taken, by expiry, strike and side
+pub fn load_live_quotes(conn: &Connection, underlying: Asset, now: i64) -> ApiResult<Vec<RestingQuote>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM resting_quotes
+         WHERE underlying = ?1 AND withdrawn_at IS NULL AND valid_until > ?2 AND filled_sats < size_sats
+         ORDER BY expires ASC, strike_price_cents ASC, side ASC",
+        QUOTE_COLUMNS
+    ))?;
+    let rows = stmt.query_map(params![underlying, now], quote_from_row)?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
+
+pub fn load_quote(conn: &Connection, id: i64) -> ApiResult<RestingQuote> {
+    conn.query_row(
+        &format!("SELECT {} FROM resting_quotes WHERE id = ?1", QUOTE_COLUMNS),
+        params![id],
+        quote_from_row,
+    )
+    .optional()?
+    .ok_or_else(|| ApiError::NotFound(format!("quote {} not found", id)))
+}
+
+/// Take `quantity_sats` of quote `id`. Fails unless the quote is live and has that much left.
+pub fn fill_quote(conn: &Connection, id: i64, quantity_sats: i64, now: i64) -> ApiResult<RestingQuote> {
+    let live: Option<(i64, i64)> = conn
+        .query_row(
+            "SELECT size_sats, filled_sats FROM resting_quotes
+             WHERE id = ?1 AND withdrawn_at IS NULL AND valid_until > ?2",
+            params![id, now],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    let Some((size_sats, filled_sats)) = live else {
+        load_quote(conn, id)?;
+        return Err(ApiError::ValidationError(format!(
+            "Quote {} is no longer live, fetch a fresh orderbook",
+            id
+        )));
+    };
+    let remaining_sats = size_sats - filled_sats;
+    if quantity_sats <= 0 || quantity_sats > remaining_sats {
+        return Err(ApiError::ValidationError(format!(
+            "Quantity {} must be positive and at most the {} left on quote {}",
+            format_sats(quantity_sats),
+            format_sats(remaining_sats),
+            id
+        )));
+    }
+    conn.execute(
+        "UPDATE resting_quotes SET filled_sats = filled_sats + ?1 WHERE id = ?2",
+        params![quantity_sats, id],
+    )?;
+    load_quote(conn, id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_quote(strike_price: f64, size: f64, expires: i64) -> NewQuote {
+        NewQuote {
+            underlying: Asset::Btc,
+            side: OptionSide::Call,
+            strike_price,
+            expires,
+            price: 0.0125,
+            size,
+            iv: 0.5,
+        }
+    }
+
+    #[test]
+    fn test_post_and_fill_quotes() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init_db(&conn).unwrap();
+        let now = 1_800_000_000;
+
+        let quotes = [new_quote(100000.0, 0.5, now + 86400), new_quote(110000.0, 0.0, now + 86400)];
+        let live = post_quotes(&conn, Asset::Btc, &quotes, now, 30).unwrap();
+        // Quotes without size are not posted
+        assert_eq!(live.len(), 1);
+        assert_eq!(live[0].valid_until, now + 30);
+
+        let filled = fill_quote(&conn, live[0].id, 20_000_000, now + 1).unwrap();
+        assert_eq!(filled.remaining, 0.3);
+        assert!(fill_quote(&conn, live[0].id, 30_000_001, now + 1).is_err());
+        fill_quote(&conn, live[0].id, 30_000_000, now + 1).unwrap();
+        // Fully filled quotes leave the book
+        assert!(load_live_quotes(&conn, Asset::Btc, now + 1).unwrap().is_empty());
+
+        // Reposting withdraws the previous quotes, and expired quotes cannot be taken
+        let first = post_quotes(&conn, Asset::Btc, &quotes[..1], now + 10, 30).unwrap();
+        let second = post_quotes(&conn, Asset::Btc, &quotes[..1], now + 20, 30).unwrap();
+        assert_eq!(load_live_quotes(&conn, Asset::Btc, now + 20).unwrap(), second);
+        assert!(fill_quote(&conn, first[0].id, 1, now + 20).is_err());
+        assert!(fill_quote(&conn, second[0].id, 1, now + 50).is_err());
+        assert!(matches!(fill_quote(&conn, 99, 1, now), Err(ApiError::NotFound(_))));
+    }
+
+    #[test]
+    fn test_offer_applies_spread_and_size() {
+        let config = OrderbookConfig { spread_percent: 10.0, size_percent: 50.0, ..Default::default() };
+        assert_eq!(config.offer(0.01, 3.0), (0.011, 1.5));
+    }
+}
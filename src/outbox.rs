@@ -0,0 +1,290 @@
+// Transactional outbox of events for downstream consumers.
+// Events such as trade.created and contract.settled are written to the events table in the
+// same transaction as the change they describe, so an event exists if and only if its change
+// was committed. A poller delivers pending events in id order: to the webhooks and message
+// bus first, then to /ws/events subscribers. An event stays pending, and everything after it
+// waits, until every webhook and the bus have accepted it, so a consumer that was down catches
+// up in order. Delivery is at least once; events carry their outbox id for consumers to drop
+// the ones they have seen.
+
+use crate::error::ApiResult;
+use crate::repository::Repository;
+use crate::sources::SourceError;
+use crate::supervisor::Supervisor;
+use crate::webhooks::{EventSink, WebhookEvent};
+use async_trait::async_trait;
+use chrono::Utc;
+use rusqlite::{params, Connection, Result};
+use serde_json::Value;
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::time::interval;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutboxConfig {
+    pub poll_interval: Duration,  // How often the poller looks for pending events
+    pub batch_size: u32,          // Most events delivered per poll
+}
+
+impl Default for OutboxConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_millis(1000),
+            batch_size: 100,
+        }
+    }
+}
+
+impl OutboxConfig {
+    /// OUTBOX_POLL_INTERVAL_MS (1000) and OUTBOX_BATCH_SIZE (100)
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            poll_interval: env::var("OUTBOX_POLL_INTERVAL_MS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|ms: &u64| *ms > 0)
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.poll_interval),
+            batch_size: env::var("OUTBOX_BATCH_SIZE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|size: &u32| *size > 0)
+                .unwrap_or(defaults.batch_size),
+        }
+    }
+}
+
+/// Write an event about `entity_id` to the outbox. Call it with the transaction making the
+/// change, so the event commits or rolls back with it.
+pub fn record(conn: &Connection, event: &str, entity_id: Option<i64>, data: &Value) -> Result<i64> {
+    conn.execute(
+        "INSERT INTO events (event, entity_id, data, created_at) VALUES (?1, ?2, ?3, ?4)",
+        params![event, entity_id, data.to_string(), Utc::now().timestamp()],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// Up to `limit` undelivered events, oldest first
+pub fn pending(conn: &Connection, limit: u32) -> Result<Vec<WebhookEvent>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, event, data, created_at FROM events WHERE published_at IS NULL ORDER BY id ASC LIMIT ?1",
+    )?;
+    let rows = stmt.query_map(params![limit], |row| {
+        let data: String = row.get(2)?;
+        Ok(WebhookEvent {
+            id: Some(row.get(0)?),
+            event: row.get(1)?,
+            timestamp: row.get(3)?,
+            data: serde_json::from_str(&data).unwrap_or(Value::Null),
+        })
+    })?;
+    rows.collect()
+}
+
+pub fn mark_published(conn: &Connection, id: i64, now: i64) -> Result<()> {
+    conn.execute(
+        "UPDATE events SET published_at = ?1, attempts = attempts + 1, last_error = NULL WHERE id = ?2",
+        params![now, id],
+    )?;
+    Ok(())
+}
+
+pub fn mark_failed(conn: &Connection, id: i64, error: &str) -> Result<()> {
+    conn.execute("UPDATE events SET attempts = attempts + 1, last_error = ?1 WHERE id = ?2", params![error, id])?;
+    Ok(())
+}
+
+/// Number of events not delivered yet
+pub fn pending_count(conn: &Connection) -> Result<i64> {
+    conn.query_row("SELECT COUNT(*) FROM events WHERE published_at IS NULL", [], |row| row.get(0))
+}
+
+/// Live feed of delivered events for WebSocket subscribers. Publishing never fails: events
+/// sent while nobody listens are simply not seen.
+#[derive(Clone)]
+pub struct EventBroadcast {
+    sender: broadcast::Sender<WebhookEvent>,
+}
+
+impl Default for EventBroadcast {
+    fn default() -> Self {
+        Self { sender: broadcast::channel(256).0 }
+    }
+}
+
+impl EventBroadcast {
+    pub fn subscribe(&self) -> broadcast::Receiver<WebhookEvent> {
+        self.sender.subscribe()
+    }
+}
+
+#[async_trait]
+impl EventSink for EventBroadcast {
+    async fn publish(&self, event: &WebhookEvent) -> std::result::Result<(), SourceError> {
+        let _ = self.sender.send(event.clone());
+        Ok(())
+    }
+}
+
+/// Publishes each event to every sink, failing if any of them did. Sinks that had accepted
+/// an event get it again when it is retried.
+pub struct Fanout {
+    sinks: Vec<Arc<dyn EventSink>>,
+}
+
+impl Fanout {
+    pub fn new(sinks: Vec<Arc<dyn EventSink>>) -> Self {
+        Self { sinks }
+    }
+}
+
+#[async_trait]
+impl EventSink for Fanout {
+    async fn publish(&self, event: &WebhookEvent) -> std::result::Result<(), SourceError> {
+        let mut failures = Vec::new();
+        for sink in &self.sinks {
+            if let Err(e) = sink.publish(event).await {
+                failures.push(e.to_string());
+            }
+        }
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(failures.join("; ").into())
+        }
+    }
+}
+
+/// Deliver up to `batch_size` pending events in order, to `sink` (the webhooks and message
+/// bus) and then `broadcast`. Stops at the first event `sink` refuses, which is retried on the
+/// next call. Returns the number delivered.
+pub async fn publish_pending(
+    repository: &Repository,
+    sink: Option<&dyn EventSink>,
+    broadcast: &EventBroadcast,
+    batch_size: u32,
+) -> ApiResult<usize> {
+    let events = repository.run(move |conn| Ok(pending(conn, batch_size)?)).await?;
+    let mut delivered = 0;
+    for event in events {
+        let Some(id) = event.id else { continue };
+        if let Some(sink) = sink {
+            if let Err(e) = sink.publish(&event).await {
+                let error = e.to_string();
+                eprintln!("⚠️  {} event {} not delivered, will retry: {}", event.event, id, error);
+                repository.run(move |conn| Ok(mark_failed(conn, id, &error)?)).await?;
+                break;
+            }
+        }
+        let now = Utc::now().timestamp();
+        repository.run(move |conn| Ok(mark_published(conn, id, now)?)).await?;
+        let _ = broadcast.publish(&event).await;
+        delivered += 1;
+    }
+    Ok(delivered)
+}
+
+pub fn start_outbox_publisher(
+    supervisor: &Supervisor,
+    repository: Repository,
+    sink: Option<Arc<dyn EventSink>>,
+    broadcast: EventBroadcast,
+    config: OutboxConfig,
+) {
+    supervisor.spawn("outbox_publisher", move || {
+        let (repository, sink, broadcast) = (repository.clone(), sink.clone(), broadcast.clone());
+        async move {
+            let mut ticker = interval(config.poll_interval);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = publish_pending(&repository, sink.as_deref(), &broadcast, config.batch_size).await {
+                    eprintln!("Error publishing outbox events: {}", e);
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::create_in_memory_pool;
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    // Records published events; refuses them while `fail` is set
+    struct RecordingSink {
+        events: Mutex<Vec<WebhookEvent>>,
+        fail: Mutex<bool>,
+    }
+
+    #[async_trait]
+    impl EventSink for RecordingSink {
+        async fn publish(&self, event: &WebhookEvent) -> std::result::Result<(), SourceError> {
+            if *self.fail.lock().unwrap() {
+                return Err("receiver down".into());
+            }
+            self.events.lock().unwrap().push(event.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_events_are_delivered_in_order_after_failures() {
+        let repository = Repository::new(create_in_memory_pool().unwrap());
+        repository
+            .run(|conn| {
+                let tx = conn.unchecked_transaction()?;
+                record(&tx, "trade.created", Some(1), &json!({"id": 1}))?;
+                record(&tx, "contract.settled", Some(1), &json!({"id": 1}))?;
+                tx.commit()?;
+                // Rolled back with its change, so never delivered
+                let tx = conn.unchecked_transaction()?;
+                record(&tx, "trade.created", Some(2), &json!({"id": 2}))?;
+                drop(tx);
+                Ok(())
+            })
+            .await
+            .unwrap();
+
+        let sink = RecordingSink { events: Mutex::new(Vec::new()), fail: Mutex::new(true) };
+        let broadcast = EventBroadcast::default();
+        let mut live = broadcast.subscribe();
+
+        assert_eq!(publish_pending(&repository, Some(&sink), &broadcast, 100).await.unwrap(), 0);
+        let (count, last_error) = repository
+            .run(|conn| {
+                let last_error: Option<String> =
+                    conn.query_row("SELECT last_error FROM events WHERE id = 1", [], |row| row.get(0))?;
+                Ok((pending_count(conn)?, last_error))
+            })
+            .await
+            .unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(last_error.as_deref(), Some("receiver down"));
+        assert!(live.try_recv().is_err());
+
+        // Back up: both go out in order, once, to the webhooks and then the live feed
+        *sink.fail.lock().unwrap() = false;
+        assert_eq!(publish_pending(&repository, Some(&sink), &broadcast, 1).await.unwrap(), 1);
+        assert_eq!(publish_pending(&repository, Some(&sink), &broadcast, 100).await.unwrap(), 1);
+        assert_eq!(publish_pending(&repository, Some(&sink), &broadcast, 100).await.unwrap(), 0);
+
+        let events = sink.events.lock().unwrap().clone();
+        assert_eq!(events.iter().map(|e| (e.id, e.event.as_str())).collect::<Vec<_>>(), vec![
+            (Some(1), "trade.created"),
+            (Some(2), "contract.settled"),
+        ]);
+        assert_eq!(events[0].data, json!({"id": 1}));
+        assert_eq!(live.try_recv().unwrap().id, Some(1));
+        assert_eq!(live.try_recv().unwrap().id, Some(2));
+
+        // Without webhooks events only go to the live feed
+        repository.run(|conn| Ok(record(conn, "risk.alert", None, &json!({}))?)).await.unwrap();
+        assert_eq!(publish_pending(&repository, None, &broadcast, 100).await.unwrap(), 1);
+        assert_eq!(live.try_recv().unwrap().event, "risk.alert");
+    }
+}
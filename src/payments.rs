@@ -0,0 +1,595 @@
+// On-chain payment of premiums.
+// While PREMIUM_PAYMENT_REQUIRED is set, a new contract starts out pending and the buyer is
+// given a deposit address and amount. Deposits go to the pool address, so each pending
+// payment is asked for an amount no other pending payment has: the premium, plus a few sats
+// when it collides. A background watcher looks for a transaction paying exactly that amount
+// to the address and opens the contract once it has PAYMENT_CONFIRMATIONS confirmations.
+// Buyers may pay over Lightning instead (see lightning.rs): the contract opens as soon as its
+// invoice settles. Contracts still unpaid when their payment is due are cancelled and release
+// their collateral.
+
+use crate::audit;
+use crate::error::{ApiError, ApiResult};
+use crate::lightning::{Invoice, InvoiceState, LightningNode};
+use crate::models::{ContractRecord, ContractStatus};
+use crate::mutiny_wallet::Transaction;
+use crate::repository::{audit_transitions, contract_record_from_row, Repository, CONTRACT_RECORD_COLUMNS};
+use crate::sources::WalletSource;
+use crate::supervisor::Supervisor;
+use crate::utils::format_sats;
+use chrono::Utc;
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet};
+use std::env;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::interval;
+
+// Actor recorded for activations and cancellations made by the watcher
+pub const PAYMENT_WATCHER_ACTOR: &str = "system:payment-watcher";
+
+// Block timestamps may run up to two hours ahead of real time, so a payment confirmed in a
+// block stamped this long before the request still counts
+const BLOCK_TIME_SLACK_SECS: i64 = 2 * 3600;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PaymentConfig {
+    pub required: bool,            // Contracts stay pending until their premium is paid
+    pub confirmations: u32,        // Confirmations a payment needs
+    pub timeout: Duration,         // How long the buyer has to pay
+    pub check_interval: Duration,  // How often the watcher looks for payments
+}
+
+impl Default for PaymentConfig {
+    fn default() -> Self {
+        Self {
+            required: false,
+            confirmations: 1,
+            timeout: Duration::from_secs(3600),
+            check_interval: Duration::from_secs(30),
+        }
+    }
+}
+
+impl PaymentConfig {
+    /// PREMIUM_PAYMENT_REQUIRED (false), PAYMENT_CONFIRMATIONS (1), PAYMENT_TIMEOUT_SECS (3600)
+    /// and PAYMENT_CHECK_INTERVAL_SECS (30)
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let secs = |name: &str, default: Duration| {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .map(|secs: u64| Duration::from_secs(secs.max(1)))
+                .unwrap_or(default)
+        };
+        Self {
+            required: env::var("PREMIUM_PAYMENT_REQUIRED")
+                .map(|v| v == "true" || v == "1")
+                .unwrap_or(defaults.required),
+            confirmations: env::var("PAYMENT_CONFIRMATIONS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.confirmations)
+                .max(1),
+            timeout: secs("PAYMENT_TIMEOUT_SECS", defaults.timeout),
+            check_interval: secs("PAYMENT_CHECK_INTERVAL_SECS", defaults.check_interval),
+        }
+    }
+}
+
+// How the buyer pays the premium
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PaymentMethod {
+    #[default]
+    Onchain,
+    Lightning,
+}
+
+impl ToSql for PaymentMethod {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.to_string().into())
+    }
+}
+
+impl FromSql for PaymentMethod {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "onchain" => Ok(PaymentMethod::Onchain),
+            "lightning" => Ok(PaymentMethod::Lightning),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl fmt::Display for PaymentMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PaymentMethod::Onchain => write!(f, "onchain"),
+            PaymentMethod::Lightning => write!(f, "lightning"),
+        }
+    }
+}
+
+/// Where the premium is to be paid
+#[derive(Debug, Clone, PartialEq)]
+pub enum PaymentTarget {
+    Onchain { address: String, confirmations_required: u32 },
+    Lightning(Invoice),
+}
+
+/// Payment to ask for when recording a contract
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaymentRequest {
+    pub target: PaymentTarget,
+    pub amount_sats: i64,  // Premium owed; an on-chain amount asked for may be a few sats more
+    pub due_at: i64,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PaymentStatus {
+    Pending,
+    Confirmed,
+    Expired,  // Not paid in time; the contract was cancelled
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct PremiumPayment {
+    pub contract_id: i64,
+    pub status: PaymentStatus,
+    pub method: PaymentMethod,
+    pub address: Option<String>,       // On-chain deposit address
+    pub amount_sats: i64,
+    pub amount: String,                // BTC amount as string
+    pub confirmations_required: u32,   // 0 for Lightning
+    pub invoice: Option<String>,       // BOLT11 invoice to pay over Lightning
+    pub payment_hash: Option<String>,
+    pub requested_at: i64,
+    pub due_at: i64,
+    pub txid: Option<String>,
+    pub confirmed_at: Option<i64>,
+}
+
+const PAYMENT_COLUMNS: &str = "p.contract_id, p.address, p.amount_sats, p.confirmations_required, p.requested_at,
+                               p.due_at, p.txid, p.confirmed_at, c.status, p.method, p.bolt11, p.payment_hash";
+
+fn payment_from_row(row: &Row) -> rusqlite::Result<PremiumPayment> {
+    let amount_sats: i64 = row.get(2)?;
+    let confirmed_at: Option<i64> = row.get(7)?;
+    let contract_status: ContractStatus = row.get(8)?;
+    let status = if confirmed_at.is_some() {
+        PaymentStatus::Confirmed
+    } else if contract_status == ContractStatus::Cancelled {
+        PaymentStatus::Expired
+    } else {
+        PaymentStatus::Pending
+    };
+    Ok(PremiumPayment {
+        contract_id: row.get(0)?,
+        status,
+        method: row.get(9)?,
+        address: row.get(1)?,
+        amount_sats,
+        amount: format_sats(amount_sats),
+        confirmations_required: row.get(3)?,
+        invoice: row.get(10)?,
+        payment_hash: row.get(11)?,
+        requested_at: row.get(4)?,
+        due_at: row.get(5)?,
+        txid: row.get(6)?,
+        confirmed_at,
+    })
+}
+
+/// Move freshly inserted contract `contract_id` to pending and ask for its premium
+pub fn request_payment(conn: &Connection, contract_id: i64, request: &PaymentRequest, now: i64) -> ApiResult<PremiumPayment> {
+    match &request.target {
+        PaymentTarget::Onchain { address, confirmations_required } => {
+            let taken: HashSet<i64> = {
+                let mut stmt = conn.prepare(
+                    "SELECT p.amount_sats FROM premium_payments p JOIN contracts c ON c.id = p.contract_id
+                     WHERE p.address = ?1 AND p.confirmed_at IS NULL AND c.status = ?2",
+                )?;
+                let rows = stmt.query_map(params![address, ContractStatus::Pending], |row| row.get(0))?;
+                rows.collect::<Result<_, _>>()?
+            };
+            let mut amount_sats = request.amount_sats;
+            while taken.contains(&amount_sats) {
+                amount_sats += 1;
+            }
+
+            conn.execute(
+                "INSERT INTO premium_payments (contract_id, method, address, amount_sats, confirmations_required,
+                                               requested_at, due_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![contract_id, PaymentMethod::Onchain, address, amount_sats, confirmations_required, now, request.due_at],
+            )?;
+        }
+        // The invoice is for the exact premium and identifies the payment by itself
+        PaymentTarget::Lightning(invoice) => {
+            conn.execute(
+                "INSERT INTO premium_payments (contract_id, method, amount_sats, confirmations_required, requested_at,
+                                               due_at, payment_hash, bolt11)
+                 VALUES (?1, ?2, ?3, 0, ?4, ?5, ?6, ?7)",
+                params![
+                    contract_id,
+                    PaymentMethod::Lightning,
+                    request.amount_sats,
+                    now,
+                    request.due_at,
+                    invoice.payment_hash,
+                    invoice.bolt11
+                ],
+            )?;
+        }
+    }
+    conn.execute(
+        "UPDATE contracts SET status = ?1 WHERE id = ?2",
+        params![ContractStatus::Pending, contract_id],
+    )?;
+    load_payment(conn, contract_id)
+}
+
+pub fn load_payment(conn: &Connection, contract_id: i64) -> ApiResult<PremiumPayment> {
+    conn.query_row(
+        &format!(
+            "SELECT {} FROM premium_payments p JOIN contracts c ON c.id = p.contract_id WHERE p.contract_id = ?1",
+            PAYMENT_COLUMNS
+        ),
+        params![contract_id],
+        payment_from_row,
+    )
+    .optional()?
+    .ok_or_else(|| ApiError::NotFound(format!("no premium payment for contract {}", contract_id)))
+}
+
+/// Payments of contracts still pending, oldest request first
+pub fn load_pending_payments(conn: &Connection) -> ApiResult<Vec<PremiumPayment>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM premium_payments p JOIN contracts c ON c.id = p.contract_id
+         WHERE p.confirmed_at IS NULL AND c.status = ?1
+         ORDER BY p.requested_at ASC, p.contract_id ASC",
+        PAYMENT_COLUMNS
+    ))?;
+    let rows = stmt.query_map(params![ContractStatus::Pending], payment_from_row)?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
+
+/// Transactions already credited to a payment
+pub fn load_used_txids(conn: &Connection) -> ApiResult<HashSet<String>> {
+    let mut stmt = conn.prepare("SELECT txid FROM premium_payments WHERE txid IS NOT NULL")?;
+    let rows = stmt.query_map([], |row| row.get(0))?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+fn confirmations(tx: &Transaction, tip_height: u64) -> u64 {
+    match (tx.status.confirmed, tx.status.block_height) {
+        (true, Some(height)) if height <= tip_height => tip_height - height + 1,
+        _ => 0,
+    }
+}
+
+/// Pair pending payments with the transactions that pay them: exactly the amount asked for,
+/// to the deposit address, with enough confirmations, in a block no older than the request
+/// and not already credited. Each transaction pays at most one contract.
+/// Returns (contract id, txid) pairs.
+pub fn match_payments(
+    pending: &[PremiumPayment],
+    transactions: &[Transaction],
+    tip_height: u64,
+    used_txids: &HashSet<String>,
+) -> Vec<(i64, String)> {
+    let mut claimed: HashSet<&str> = HashSet::new();
+    let mut matches = Vec::new();
+    for payment in pending {
+        let Some(address) = payment.address.as_deref().filter(|_| payment.method == PaymentMethod::Onchain) else {
+            continue;
+        };
+        let paying = transactions.iter().find(|tx| {
+            !used_txids.contains(&tx.txid)
+                && !claimed.contains(tx.txid.as_str())
+                && confirmations(tx, tip_height) >= payment.confirmations_required as u64
+                && tx
+                    .status
+                    .block_time
+                    .is_none_or(|time| time as i64 >= payment.requested_at - BLOCK_TIME_SLACK_SECS)
+                && tx.vout.iter().any(|out| {
+                    out.scriptpubkey_address.as_deref() == Some(address)
+                        && out.value as i64 == payment.amount_sats
+                })
+        });
+        if let Some(tx) = paying {
+            claimed.insert(tx.txid.as_str());
+            matches.push((payment.contract_id, tx.txid.clone()));
+        }
+    }
+    matches
+}
+
+/// Record the payment of pending contract `contract_id`, on-chain by `txid` or over Lightning,
+/// and open the contract, auditing the activation under `actor`
+pub fn confirm_payment(
+    conn: &Connection,
+    contract_id: i64,
+    txid: Option<&str>,
+    now: i64,
+    actor: &str,
+) -> ApiResult<ContractRecord> {
+    let tx = conn.unchecked_transaction()?;
+    let before = crate::repository::load_contract_record(&tx, contract_id)?;
+    if before.status != ContractStatus::Pending {
+        return Err(ApiError::ValidationError(format!(
+            "Contract {} is {}, not pending",
+            contract_id, before.status
+        )));
+    }
+    tx.execute(
+        "UPDATE premium_payments SET txid = ?1, confirmed_at = ?2 WHERE contract_id = ?3",
+        params![txid, now, contract_id],
+    )?;
+    tx.execute(
+        "UPDATE contracts SET status = ?1 WHERE id = ?2",
+        params![ContractStatus::Open, contract_id],
+    )?;
+    audit_transitions(&tx, actor, audit::CONTRACT_ACTIVATE, std::slice::from_ref(&before))?;
+    let opened = crate::repository::load_contract_record(&tx, contract_id)?;
+    tx.commit()?;
+    Ok(opened)
+}
+
+/// Cancel pending contracts whose payment was due by `now`, auditing each under `actor`
+pub fn cancel_overdue_payments(conn: &Connection, now: i64, actor: &str) -> ApiResult<Vec<ContractRecord>> {
+    let tx = conn.unchecked_transaction()?;
+    let before = {
+        let mut stmt = tx.prepare(&format!(
+            "SELECT {} FROM contracts
+             WHERE status = ?1 AND id IN (SELECT contract_id FROM premium_payments WHERE confirmed_at IS NULL AND due_at <= ?2)
+             ORDER BY id ASC",
+            CONTRACT_RECORD_COLUMNS
+        ))?;
+        let rows = stmt.query_map(params![ContractStatus::Pending, now], contract_record_from_row)?;
+        rows.collect::<Result<Vec<_>, _>>()?
+    };
+    for contract in &before {
+        tx.execute(
+            "UPDATE contracts SET status = ?1 WHERE id = ?2",
+            params![ContractStatus::Cancelled, contract.id],
+        )?;
+    }
+    audit_transitions(&tx, actor, audit::CONTRACT_CANCEL, &before)?;
+    let cancelled = before
+        .iter()
+        .map(|contract| crate::repository::load_contract_record(&tx, contract.id))
+        .collect::<ApiResult<Vec<_>>>()?;
+    tx.commit()?;
+    Ok(cancelled)
+}
+
+/// Open every pending contract whose payment has confirmed or whose invoice has settled,
+/// then cancel those overdue. Nothing is cancelled when a payment could not be checked.
+/// Returns the number of contracts opened and cancelled.
+pub async fn check_payments(
+    repository: &Repository,
+    wallet: &dyn WalletSource,
+    lightning: Option<&dyn LightningNode>,
+    now: i64,
+) -> ApiResult<(usize, usize)> {
+    let (pending, pending_invoices): (Vec<_>, Vec<_>) = repository
+        .run(|conn| load_pending_payments(conn))
+        .await?
+        .into_iter()
+        .partition(|payment| payment.method == PaymentMethod::Onchain);
+    let mut opened = 0;
+    if !pending.is_empty() {
+        let tip_height = wallet
+            .get_tip_height()
+            .await
+            .map_err(|e| ApiError::ExternalApiError(format!("chain tip unavailable: {}", e)))?;
+        let used_txids = repository.run(|conn| load_used_txids(conn)).await?;
+        let addresses: BTreeSet<&str> = pending.iter().filter_map(|p| p.address.as_deref()).collect();
+        let mut transactions = Vec::new();
+        for address in addresses {
+            transactions.extend(
+                wallet
+                    .get_address_transactions(address)
+                    .await
+                    .map_err(|e| ApiError::ExternalApiError(format!("transactions of {} unavailable: {}", address, e)))?,
+            );
+        }
+
+        for (contract_id, txid) in match_payments(&pending, &transactions, tip_height, &used_txids) {
+            let contract = repository
+                .run(move |conn| confirm_payment(conn, contract_id, Some(&txid), now, PAYMENT_WATCHER_ACTOR))
+                .await?;
+            println!("💸 Premium of contract {} paid, contract is now {}", contract.id, contract.status);
+            opened += 1;
+        }
+    }
+
+    if !pending_invoices.is_empty() {
+        let lightning = lightning
+            .ok_or_else(|| ApiError::ExternalApiError("Lightning invoices pending but no node configured".to_string()))?;
+        for payment in pending_invoices {
+            let payment_hash = payment.payment_hash.unwrap_or_default();
+            let state = lightning
+                .lookup_invoice(&payment_hash)
+                .await
+                .map_err(|e| ApiError::ExternalApiError(format!("invoice {} unavailable: {}", payment_hash, e)))?;
+            if let InvoiceState::Settled { .. } = state {
+                let contract_id = payment.contract_id;
+                let contract = repository
+                    .run(move |conn| confirm_payment(conn, contract_id, None, now, PAYMENT_WATCHER_ACTOR))
+                    .await?;
+                println!("⚡ Premium of contract {} paid over Lightning, contract is now {}", contract.id, contract.status);
+                opened += 1;
+            }
+        }
+    }
+
+    let cancelled = repository.run(move |conn| cancel_overdue_payments(conn, now, PAYMENT_WATCHER_ACTOR)).await?;
+    for contract in &cancelled {
+        println!("🚫 Contract {} cancelled, premium not paid in time", contract.id);
+    }
+    Ok((opened, cancelled.len()))
+}
+
+pub fn start_payment_watcher(
+    supervisor: &Supervisor,
+    repository: Repository,
+    wallet: Arc<dyn WalletSource>,
+    lightning: Option<Arc<dyn LightningNode>>,
+    config: PaymentConfig,
+) {
+    supervisor.spawn("payment_watcher", move || {
+        let (repository, wallet, lightning) = (repository.clone(), wallet.clone(), lightning.clone());
+        async move {
+            let mut ticker = interval(config.check_interval);
+            loop {
+                ticker.tick().await;
+                let now = Utc::now().timestamp();
+                if let Err(e) = check_payments(&repository, wallet.as_ref(), lightning.as_deref(), now).await {
+                    eprintln!("Error checking premium payments: {}", e);
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Asset, Contract, OptionSide};
+    use crate::mutiny_wallet::{TxStatus, Vout};
+    use crate::repository::insert_contract;
+
+    fn payment(contract_id: i64, amount_sats: i64, requested_at: i64) -> PremiumPayment {
+        PremiumPayment {
+            contract_id,
+            status: PaymentStatus::Pending,
+            method: PaymentMethod::Onchain,
+            address: Some("pool".to_string()),
+            amount_sats,
+            amount: format_sats(amount_sats),
+            confirmations_required: 2,
+            invoice: None,
+            payment_hash: None,
+            requested_at,
+            due_at: requested_at + 3600,
+            txid: None,
+            confirmed_at: None,
+        }
+    }
+
+    fn transaction(txid: &str, address: &str, value: u64, block_height: Option<u64>) -> Transaction {
+        Transaction {
+            txid: txid.to_string(),
+            version: 2,
+            locktime: 0,
+            vin: Vec::new(),
+            vout: vec![Vout {
+                scriptpubkey: String::new(),
+                scriptpubkey_asm: String::new(),
+                scriptpubkey_type: "v0_p2wpkh".to_string(),
+                scriptpubkey_address: Some(address.to_string()),
+                value,
+            }],
+            size: 200,
+            weight: 800,
+            fee: 300,
+            status: TxStatus {
+                confirmed: block_height.is_some(),
+                block_height,
+                block_hash: None,
+                block_time: block_height.map(|_| 1_800_000_000),
+            },
+        }
+    }
+
+    #[test]
+    fn test_match_payments() {
+        let pending = [payment(1, 100_000, 1_800_000_000), payment(2, 100_001, 1_800_000_000), payment(3, 5_000, 1_800_000_000)];
+        let transactions = [
+            transaction("a", "pool", 100_000, Some(100)),      // 1 confirmation: not enough
+            transaction("b", "pool", 100_001, Some(99)),       // pays contract 2
+            transaction("c", "elsewhere", 5_000, Some(90)),    // wrong address
+            transaction("d", "pool", 5_000, None),             // unconfirmed
+            transaction("e", "pool", 100_001, Some(95)),       // already credited
+        ];
+        let used = HashSet::from(["e".to_string()]);
+        assert_eq!(match_payments(&pending, &transactions, 100, &used), vec![(2, "b".to_string())]);
+        // Lightning payments are never settled by a transaction
+        let mut invoice = payment(4, 100_001, 1_800_000_000);
+        invoice.method = PaymentMethod::Lightning;
+        invoice.address = None;
+        assert!(match_payments(&[invoice], &transactions, 100, &HashSet::new()).is_empty());
+        assert_eq!(
+            match_payments(&pending, &transactions, 101, &used),
+            vec![(1, "a".to_string()), (2, "b".to_string())]
+        );
+
+        // A block from well before the request cannot hold its payment
+        let late = [payment(2, 100_001, 1_800_000_000 + BLOCK_TIME_SLACK_SECS + 1)];
+        assert!(match_payments(&late, &transactions, 100, &used).is_empty());
+    }
+
+    #[test]
+    fn test_payment_lifecycle() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init_db(&conn).unwrap();
+        let now = 1_800_000_000;
+        let contract = Contract {
+            underlying: Asset::Btc,
+            side: OptionSide::Put,
+            strike_price: 100000.0,
+            quantity: 0.1,
+            expires: now + 86400,
+            premium: 0.01,
+        };
+        let request = PaymentRequest {
+            target: PaymentTarget::Onchain { address: "pool".to_string(), confirmations_required: 1 },
+            amount_sats: 100_000,
+            due_at: now + 3600,
+        };
+        let first = insert_contract(&conn, &contract, None).unwrap();
+        let second = insert_contract(&conn, &contract, None).unwrap();
+        assert_eq!(request_payment(&conn, first, &request, now).unwrap().amount_sats, 100_000);
+        // Pending payments to the same address never share an amount
+        let payment = request_payment(&conn, second, &request, now).unwrap();
+        assert_eq!(payment.amount_sats, 100_001);
+        assert_eq!(payment.amount, "0.00100001");
+        // An invoice is for the exact premium, whatever else is pending
+        let third = insert_contract(&conn, &contract, None).unwrap();
+        let invoice = Invoice { payment_hash: "ab".repeat(32), bolt11: "lnbc1m1p...".to_string() };
+        let lightning_request = PaymentRequest { target: PaymentTarget::Lightning(invoice), ..request.clone() };
+        let lightning_payment = request_payment(&conn, third, &lightning_request, now).unwrap();
+        assert_eq!(lightning_payment.method, PaymentMethod::Lightning);
+        assert_eq!(lightning_payment.amount_sats, 100_000);
+        assert_eq!(lightning_payment.address, None);
+        assert_eq!(lightning_payment.invoice.as_deref(), Some("lnbc1m1p..."));
+        assert_eq!(load_pending_payments(&conn).unwrap().len(), 3);
+        confirm_payment(&conn, third, None, now + 5, "test").unwrap();
+
+        let opened = confirm_payment(&conn, first, Some("a"), now + 60, "test").unwrap();
+        assert_eq!(opened.status, ContractStatus::Open);
+        assert_eq!(load_payment(&conn, first).unwrap().status, PaymentStatus::Confirmed);
+        assert!(confirm_payment(&conn, first, Some("b"), now + 60, "test").is_err());
+        assert_eq!(load_used_txids(&conn).unwrap(), HashSet::from(["a".to_string()]));
+
+        assert!(cancel_overdue_payments(&conn, now + 3599, "test").unwrap().is_empty());
+        let cancelled = cancel_overdue_payments(&conn, now + 3600, "test").unwrap();
+        assert_eq!(cancelled.len(), 1);
+        assert_eq!(cancelled[0].status, ContractStatus::Cancelled);
+        assert_eq!(load_payment(&conn, second).unwrap().status, PaymentStatus::Expired);
+
+        let entries = audit::query(&conn, &audit::AuditFilter::default()).unwrap();
+        assert_eq!(entries[0].action, audit::CONTRACT_CANCEL);
+        assert_eq!(entries[1].action, audit::CONTRACT_ACTIVATE);
+        assert_eq!(entries[1].pre_state.as_ref().unwrap()["status"], "pending");
+        assert_eq!(entries[1].entity_id, Some(first));
+    }
+}
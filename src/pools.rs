@@ -0,0 +1,182 @@
+// Collateral pools.
+// Each pool is a wallet address on one network whose balance backs the options sold from it,
+// with its own collateral rate and risk margin. Contracts are margined against the open book
+// of their own pool only. The default pool (id 1) is configured by POOL_ADDRESS,
+// POOL_NETWORK, COLLATERAL_RATE and RISK_MARGIN; others are added with `optadmin pools add`.
+
+use crate::audit;
+use crate::error::{ApiError, ApiResult};
+use crate::models::ContractStatus;
+use crate::mutiny_wallet::Network;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::Serialize;
+use std::env;
+
+pub const DEFAULT_POOL_ID: i64 = 1;
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Pool {
+    pub id: i64,
+    pub name: String,
+    pub address: String,
+    pub network: Network,
+    pub collateral_rate: f64,  // Share of the balance that may back margin
+    pub risk_margin: f64,      // Safety multiplier on margin
+}
+
+impl Pool {
+    /// The default pool at `address`, with POOL_NETWORK (signet), COLLATERAL_RATE (0.5)
+    /// and RISK_MARGIN (1.2)
+    pub fn default_from_env(address: &str) -> Self {
+        Self {
+            id: DEFAULT_POOL_ID,
+            name: "default".to_string(),
+            address: address.to_string(),
+            network: env::var("POOL_NETWORK").ok().and_then(|v| v.parse().ok()).unwrap_or(Network::Signet),
+            collateral_rate: env::var("COLLATERAL_RATE").ok().and_then(|v| v.parse().ok()).unwrap_or(0.5),
+            risk_margin: env::var("RISK_MARGIN").ok().and_then(|v| v.parse().ok()).unwrap_or(1.2),
+        }
+    }
+}
+
+/// A pool to add. Ids are assigned on insert.
+#[derive(Clone, Debug)]
+pub struct NewPool {
+    pub name: String,
+    pub address: String,
+    pub network: Network,
+    pub collateral_rate: f64,
+    pub risk_margin: f64,
+}
+
+impl NewPool {
+    fn validate(&self) -> ApiResult<()> {
+        if self.name.trim().is_empty() || self.name == "default" {
+            return Err(ApiError::ValidationError("Pool name must be set and not 'default'".to_string()));
+        }
+        self.network
+            .validate_address(&self.address)
+            .map_err(|e| ApiError::ValidationError(e.to_string()))?;
+        if !(self.collateral_rate > 0.0 && self.collateral_rate <= 1.0) {
+            return Err(ApiError::ValidationError(format!(
+                "collateral_rate must be in (0, 1], got {}",
+                self.collateral_rate
+            )));
+        }
+        if !(self.risk_margin >= 1.0 && self.risk_margin.is_finite()) {
+            return Err(ApiError::ValidationError(format!("risk_margin must be at least 1, got {}", self.risk_margin)));
+        }
+        Ok(())
+    }
+}
+
+const POOL_COLUMNS: &str = "id, name, address, network, collateral_rate, risk_margin";
+
+fn pool_from_row(row: &Row) -> rusqlite::Result<Pool> {
+    let network: String = row.get(3)?;
+    Ok(Pool {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        address: row.get(2)?,
+        network: network
+            .parse()
+            .map_err(|e: String| rusqlite::Error::FromSqlConversionFailure(3, rusqlite::types::Type::Text, e.into()))?,
+        collateral_rate: row.get(4)?,
+        risk_margin: row.get(5)?,
+    })
+}
+
+/// Add `pool`, audited under `actor`, returning it with its id
+pub fn add_pool(conn: &Connection, pool: &NewPool, actor: &str, now: i64) -> ApiResult<Pool> {
+    pool.validate()?;
+    let tx = conn.unchecked_transaction()?;
+    let exists: bool = tx.query_row(
+        "SELECT EXISTS(SELECT 1 FROM pools WHERE name = ?1 OR (address = ?2 AND network = ?3))",
+        params![pool.name, pool.address, pool.network.to_string()],
+        |row| row.get(0),
+    )?;
+    if exists {
+        return Err(ApiError::ValidationError(format!(
+            "A pool named '{}' or at {} on {} already exists",
+            pool.name, pool.address, pool.network
+        )));
+    }
+    tx.execute(
+        "INSERT INTO pools (name, address, network, collateral_rate, risk_margin, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![pool.name, pool.address, pool.network.to_string(), pool.collateral_rate, pool.risk_margin, now],
+    )?;
+    let created = load_pool(&tx, tx.last_insert_rowid())?
+        .ok_or_else(|| ApiError::DatabaseError("Pool vanished after insert".to_string()))?;
+    audit::record(&tx, actor, audit::POOL_CREATE, Some(created.id), None, serde_json::to_value(&created).ok().as_ref())?;
+    tx.commit()?;
+    Ok(created)
+}
+
+/// Stored pool `id`; None for the default pool and unknown ids
+pub fn load_pool(conn: &Connection, id: i64) -> ApiResult<Option<Pool>> {
+    let sql = format!("SELECT {} FROM pools WHERE id = ?1", POOL_COLUMNS);
+    Ok(conn.query_row(&sql, params![id], pool_from_row).optional()?)
+}
+
+/// Number of contracts sold from pool `pool_id` since `since`, and the premium they were
+/// sold for in BTC. Cancelled contracts, whose premium was never paid, are left out.
+pub fn pool_sales_since(conn: &Connection, pool_id: i64, since: i64) -> ApiResult<(i64, f64)> {
+    Ok(conn.query_row(
+        "SELECT COUNT(*), COALESCE(SUM(CAST(premium_sats AS REAL) * quantity_sats), 0) / 1e16 FROM contracts
+         WHERE pool_id = ?1 AND created_at >= ?2 AND status != ?3",
+        params![pool_id, since, ContractStatus::Cancelled],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?)
+}
+
+/// Stored pools in id order, without the default pool
+pub fn load_pools(conn: &Connection) -> ApiResult<Vec<Pool>> {
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM pools ORDER BY id ASC", POOL_COLUMNS))?;
+    let rows = stmt.query_map([], pool_from_row)?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POOL: &str = "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4";
+    const OTHER: &str = "3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy";
+
+    fn new_pool(name: &str, address: &str) -> NewPool {
+        NewPool {
+            name: name.to_string(),
+            address: address.to_string(),
+            network: Network::Mainnet,
+            collateral_rate: 0.3,
+            risk_margin: 1.5,
+        }
+    }
+
+    #[test]
+    fn test_pools_are_numbered_after_the_default_pool() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init_db(&conn).unwrap();
+
+        let pool = add_pool(&conn, &new_pool("mainnet", POOL), "ops", 1_700_000_000).unwrap();
+        assert_eq!(pool.id, DEFAULT_POOL_ID + 1);
+        assert_eq!(pool.network, Network::Mainnet);
+        assert_eq!(load_pool(&conn, pool.id).unwrap(), Some(pool.clone()));
+        assert_eq!(load_pool(&conn, DEFAULT_POOL_ID).unwrap(), None);
+        assert_eq!(load_pools(&conn).unwrap(), vec![pool.clone()]);
+        let entries = audit::query(&conn, &audit::AuditFilter::default()).unwrap();
+        assert_eq!((entries[0].action.as_str(), entries[0].entity_id), (audit::POOL_CREATE, Some(pool.id)));
+
+        // Names and addresses are unique, and parameters must make sense
+        assert!(add_pool(&conn, &new_pool("mainnet", OTHER), "ops", 0).is_err());
+        assert!(add_pool(&conn, &new_pool("other", POOL), "ops", 0).is_err());
+        assert!(add_pool(&conn, &new_pool("default", OTHER), "ops", 0).is_err());
+        assert!(add_pool(&conn, &NewPool { collateral_rate: 1.5, ..new_pool("other", OTHER) }, "ops", 0).is_err());
+        assert!(add_pool(&conn, &NewPool { risk_margin: 0.8, ..new_pool("other", OTHER) }, "ops", 0).is_err());
+        // The address must belong to the pool's network
+        let err = add_pool(&conn, &NewPool { network: Network::Signet, ..new_pool("other", OTHER) }, "ops", 0).unwrap_err();
+        assert!(err.to_string().contains("cannot be used on signet"), "{}", err);
+        assert_eq!(load_pools(&conn).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,338 @@
+// Position limits and concentration checks.
+// A product is one underlying, side, strike and expiry. Limits cap the open quantity and
+// notional of each product, the share of pool collateral its margin may use, and the open
+// quantity and notional of each counterparty. Every limit except the single contract cap
+// is off unless configured. Until buyer accounts exist, the counterparty of a trade is the
+// API key it was placed with. Portfolio limits cap the net delta, gamma and vega of the
+// pool's book, short every contract it sold, across all underlyings.
+
+use crate::error::ApiError;
+use crate::models::{Asset, Contract};
+use crate::pricing::Greeks;
+use crate::strikes;
+use crate::utils::btc_to_sats;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::env;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PositionLimits {
+    pub min_contract_quantity: f64,                   // Smallest single contract, also the smallest partial close
+    pub max_contract_quantity: f64,                   // Largest single contract
+    pub max_product_quantity: Option<f64>,            // Open quantity per product
+    pub max_product_notional_usd: Option<f64>,        // Open quantity per product at spot
+    pub max_product_collateral_percent: Option<f64>,  // Product margin as % of pool collateral
+    pub max_counterparty_quantity: Option<f64>,       // Open quantity per counterparty and underlying
+    pub max_counterparty_notional_usd: Option<f64>,   // Open notional per counterparty, all underlyings
+    pub max_net_delta_usd: Option<f64>,               // Absolute net delta of the book, see BookGreeks
+    pub max_net_gamma_usd: Option<f64>,
+    pub max_net_vega_usd: Option<f64>,
+}
+
+impl Default for PositionLimits {
+    fn default() -> Self {
+        Self {
+            min_contract_quantity: 0.00001,
+            max_contract_quantity: 1000.0,
+            max_product_quantity: None,
+            max_product_notional_usd: None,
+            max_product_collateral_percent: None,
+            max_counterparty_quantity: None,
+            max_counterparty_notional_usd: None,
+            max_net_delta_usd: None,
+            max_net_gamma_usd: None,
+            max_net_vega_usd: None,
+        }
+    }
+}
+
+/// Net Greeks of the pool's book in USD, so underlyings add up. Contracts are sold, so each
+/// counts short. Delta is the USD value of the underlying the book is equivalent to, gamma
+/// the change of that delta on a 1% move of spot, and vega the USD change on a 1 vol point rise.
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct BookGreeks {
+    pub delta_usd: f64,
+    pub gamma_usd: f64,
+    pub vega_usd: f64,
+}
+
+impl BookGreeks {
+    /// Add `contract`, whose Greeks per option are `greeks` at `spot_price`
+    pub fn add(&mut self, contract: &Contract, greeks: &Greeks, spot_price: f64) {
+        let short = greeks.scaled(-contract.quantity);
+        self.delta_usd += short.delta * spot_price;
+        self.gamma_usd += short.gamma * spot_price * spot_price / 100.0;
+        self.vega_usd += short.vega;
+    }
+}
+
+// Unset, unparsable or non-positive values disable a limit
+fn optional_limit(name: &str) -> Option<f64> {
+    env::var(name).ok().and_then(|v| v.parse().ok()).filter(|limit: &f64| *limit > 0.0)
+}
+
+impl PositionLimits {
+    /// MIN_CONTRACT_QUANTITY (0.00001), MAX_CONTRACT_QUANTITY (1000), PRODUCT_MAX_QUANTITY, PRODUCT_MAX_NOTIONAL_USD,
+    /// PRODUCT_MAX_COLLATERAL_PERCENT, COUNTERPARTY_MAX_QUANTITY, COUNTERPARTY_MAX_NOTIONAL_USD,
+    /// PORTFOLIO_MAX_NET_DELTA_USD, PORTFOLIO_MAX_NET_GAMMA_USD and PORTFOLIO_MAX_NET_VEGA_USD
+    pub fn from_env() -> Self {
+        Self {
+            min_contract_quantity: optional_limit("MIN_CONTRACT_QUANTITY")
+                .unwrap_or(Self::default().min_contract_quantity),
+            max_contract_quantity: optional_limit("MAX_CONTRACT_QUANTITY")
+                .unwrap_or(Self::default().max_contract_quantity),
+            max_product_quantity: optional_limit("PRODUCT_MAX_QUANTITY"),
+            max_product_notional_usd: optional_limit("PRODUCT_MAX_NOTIONAL_USD"),
+            max_product_collateral_percent: optional_limit("PRODUCT_MAX_COLLATERAL_PERCENT"),
+            max_counterparty_quantity: optional_limit("COUNTERPARTY_MAX_QUANTITY"),
+            max_counterparty_notional_usd: optional_limit("COUNTERPARTY_MAX_NOTIONAL_USD"),
+            max_net_delta_usd: optional_limit("PORTFOLIO_MAX_NET_DELTA_USD"),
+            max_net_gamma_usd: optional_limit("PORTFOLIO_MAX_NET_GAMMA_USD"),
+            max_net_vega_usd: optional_limit("PORTFOLIO_MAX_NET_VEGA_USD"),
+        }
+    }
+
+    /// Whether any net Greek of the book is limited
+    pub fn limits_greeks(&self) -> bool {
+        self.max_net_delta_usd.is_some() || self.max_net_gamma_usd.is_some() || self.max_net_vega_usd.is_some()
+    }
+
+    /// MIN_CONTRACT_QUANTITY in satoshis (1e-8 units of the underlying)
+    pub fn min_contract_quantity_sats(&self) -> i64 {
+        btc_to_sats(self.min_contract_quantity)
+    }
+
+    /// Reject `contract` if it takes its product past a quantity, notional or collateral limit.
+    /// `product_margin_usd` is the margin of the whole product position including `contract`.
+    pub fn check_product(
+        &self,
+        contract: &Contract,
+        book: &[Contract],
+        spot_price: f64,
+        product_margin_usd: f64,
+        collateral_usd: f64,
+    ) -> Result<(), ApiError> {
+        let current_quantity = product_quantity(book, contract);
+        let quantity = current_quantity + contract.quantity;
+        if let Some(limit) = self.max_product_quantity.filter(|limit| quantity > *limit) {
+            return Err(ApiError::PositionLimitExceeded(format!(
+                "open quantity of {} would be {:.8} (currently {:.8}), limit {:.8}",
+                product_name(contract), quantity, current_quantity, limit
+            ))
+            .with_details(serde_json::json!({
+                "limit": "max_product_quantity",
+                "value": quantity,
+                "current": current_quantity,
+                "max": limit,
+            })));
+        }
+        let notional_usd = quantity * spot_price;
+        if let Some(limit) = self.max_product_notional_usd.filter(|limit| notional_usd > *limit) {
+            return Err(ApiError::PositionLimitExceeded(format!(
+                "notional of {} would be ${:.2} (currently ${:.2}), limit ${:.2}",
+                product_name(contract), notional_usd, current_quantity * spot_price, limit
+            ))
+            .with_details(serde_json::json!({
+                "limit": "max_product_notional_usd",
+                "value": notional_usd,
+                "current": current_quantity * spot_price,
+                "max": limit,
+            })));
+        }
+        if let Some(limit) = self.max_product_collateral_percent {
+            let collateral_percent = if collateral_usd > 0.0 {
+                product_margin_usd / collateral_usd * 100.0
+            } else {
+                f64::INFINITY
+            };
+            if collateral_percent > limit {
+                return Err(ApiError::PositionLimitExceeded(format!(
+                    "margin of {} would be ${:.2}, {:.2}% of pool collateral (${:.2}), limit {:.2}%",
+                    product_name(contract), product_margin_usd, collateral_percent, collateral_usd, limit
+                ))
+                .with_details(serde_json::json!({
+                    "limit": "max_product_collateral_percent",
+                    "value": collateral_percent,
+                    "margin_usd": product_margin_usd,
+                    "available_collateral_usd": collateral_usd,
+                    "max": limit,
+                })));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reject `contract` if it takes `counterparty` past a quantity or notional limit.
+    /// `counterparty_book` holds the counterparty's open contracts; `spot_prices` must cover them.
+    pub fn check_counterparty(
+        &self,
+        counterparty: &str,
+        contract: &Contract,
+        counterparty_book: &[Contract],
+        spot_prices: &HashMap<Asset, f64>,
+    ) -> Result<(), ApiError> {
+        let current_quantity: f64 = counterparty_book
+            .iter()
+            .filter(|c| c.underlying == contract.underlying)
+            .map(|c| c.quantity)
+            .sum();
+        let quantity = current_quantity + contract.quantity;
+        if let Some(limit) = self.max_counterparty_quantity.filter(|limit| quantity > *limit) {
+            return Err(ApiError::PositionLimitExceeded(format!(
+                "open {} quantity of counterparty {} would be {:.8} (currently {:.8}), limit {:.8}",
+                contract.underlying, counterparty, quantity, current_quantity, limit
+            ))
+            .with_details(serde_json::json!({
+                "limit": "max_counterparty_quantity",
+                "value": quantity,
+                "current": current_quantity,
+                "max": limit,
+            })));
+        }
+        let notional = |c: &Contract| c.quantity * spot_prices.get(&c.underlying).copied().unwrap_or(0.0);
+        let current_notional_usd: f64 = counterparty_book.iter().map(notional).sum();
+        let notional_usd = current_notional_usd + notional(contract);
+        if let Some(limit) = self.max_counterparty_notional_usd.filter(|limit| notional_usd > *limit) {
+            return Err(ApiError::PositionLimitExceeded(format!(
+                "open notional of counterparty {} would be ${:.2} (currently ${:.2}), limit ${:.2}",
+                counterparty, notional_usd, current_notional_usd, limit
+            ))
+            .with_details(serde_json::json!({
+                "limit": "max_counterparty_notional_usd",
+                "value": notional_usd,
+                "current": current_notional_usd,
+                "max": limit,
+            })));
+        }
+        Ok(())
+    }
+
+    /// Reject a trade that takes the book from `current` to `greeks` if a net Greek ends up
+    /// past its limit. A trade that brings a Greek closer to zero is accepted even past the
+    /// limit, so the book can always be hedged back in.
+    pub fn check_greeks(&self, current: &BookGreeks, greeks: &BookGreeks) -> Result<(), ApiError> {
+        let checks = [
+            ("delta", "max_net_delta_usd", self.max_net_delta_usd, current.delta_usd, greeks.delta_usd),
+            ("gamma", "max_net_gamma_usd", self.max_net_gamma_usd, current.gamma_usd, greeks.gamma_usd),
+            ("vega", "max_net_vega_usd", self.max_net_vega_usd, current.vega_usd, greeks.vega_usd),
+        ];
+        for (greek, name, limit, current, value) in checks {
+            let Some(limit) = limit else { continue };
+            if value.abs() > limit && value.abs() > current.abs() {
+                return Err(ApiError::PositionLimitExceeded(format!(
+                    "net {} of the pool would be ${:.2} (currently ${:.2}), limit ±${:.2}",
+                    greek, value, current, limit
+                ))
+                .with_details(serde_json::json!({
+                    "limit": name,
+                    "value": value,
+                    "current": current,
+                    "max": limit,
+                })));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether two contracts are the same product (underlying, side, strike and expiry)
+pub fn is_same_product(a: &Contract, b: &Contract) -> bool {
+    a.underlying == b.underlying
+        && a.side == b.side
+        && a.expires == b.expires
+        && strikes::deployment().normalize(a.underlying, a.strike_price) == strikes::deployment().normalize(b.underlying, b.strike_price)
+}
+
+/// Open quantity of the product of `contract` in `book`
+pub fn product_quantity(book: &[Contract], contract: &Contract) -> f64 {
+    book.iter().filter(|c| is_same_product(c, contract)).map(|c| c.quantity).sum()
+}
+
+fn product_name(contract: &Contract) -> String {
+    format!("{} {} {} expiring {}", contract.underlying, contract.side, contract.strike_price, contract.expires)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorCode;
+    use crate::models::OptionSide;
+
+    fn contract(strike_price: f64, quantity: f64) -> Contract {
+        Contract {
+            underlying: Asset::Btc,
+            side: OptionSide::Call,
+            strike_price,
+            quantity,
+            expires: 1_900_000_000,
+            premium: 0.01,
+        }
+    }
+
+    #[test]
+    fn test_product_limits() {
+        let book = vec![contract(100000.0, 2.0), contract(110000.0, 5.0)];
+        let limits = PositionLimits {
+            max_product_quantity: Some(3.0),
+            max_product_notional_usd: Some(250_000.0),
+            max_product_collateral_percent: Some(25.0),
+            ..Default::default()
+        };
+
+        assert!(limits.check_product(&contract(100000.0, 0.5), &book, 100_000.0, 1000.0, 10_000.0).is_ok());
+        // Other strikes don't count towards the product
+        let err = limits.check_product(&contract(100000.0, 1.5), &book, 50_000.0, 1000.0, 10_000.0).unwrap_err();
+        assert!(err.to_string().contains("would be 3.50000000 (currently 2.00000000), limit 3.00000000"));
+        let err = limits.check_product(&contract(100000.0, 0.75), &book, 100_000.0, 1000.0, 10_000.0).unwrap_err();
+        assert!(err.to_string().contains("notional"));
+        let err = limits.check_product(&contract(100000.0, 0.5), &book, 100_000.0, 3000.0, 10_000.0).unwrap_err();
+        assert!(err.to_string().contains("30.00% of pool collateral"));
+        assert_eq!(err.code(), ErrorCode::PositionLimitExceeded);
+        assert_eq!(err.details()["limit"], "max_product_collateral_percent");
+        assert_eq!(err.details()["value"], 30.0);
+
+        assert!(PositionLimits::default()
+            .check_product(&contract(110000.0, 100.0), &book, 100_000.0, 1e9, 1.0)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_counterparty_limits() {
+        let mut eth = contract(4000.0, 10.0);
+        eth.underlying = Asset::Eth;
+        let book = vec![contract(100000.0, 1.0), eth];
+        let spot_prices = HashMap::from([(Asset::Btc, 100_000.0), (Asset::Eth, 4_000.0)]);
+        let limits = PositionLimits {
+            max_counterparty_quantity: Some(2.0),
+            max_counterparty_notional_usd: Some(200_000.0),
+            ..Default::default()
+        };
+
+        // ETH quantity doesn't count towards the BTC quantity limit, but its notional does
+        assert!(limits.check_counterparty("desk", &contract(90000.0, 0.5), &book, &spot_prices).is_ok());
+        let err = limits.check_counterparty("desk", &contract(90000.0, 1.5), &book, &spot_prices).unwrap_err();
+        assert!(err.to_string().contains("counterparty desk would be 2.50000000"));
+        let err = limits.check_counterparty("desk", &contract(90000.0, 0.7), &book, &spot_prices).unwrap_err();
+        assert!(err.to_string().contains("$210000.00 (currently $140000.00), limit $200000.00"));
+    }
+
+    #[test]
+    fn test_greek_limits() {
+        let mut book = BookGreeks::default();
+        let greeks = Greeks { delta: 0.5, gamma: 0.000030517578125, vega: 40.0, theta: -30.0 };
+        book.add(&contract(100000.0, 2.0), &greeks, 100_000.0);
+        assert_eq!(book, BookGreeks { delta_usd: -100_000.0, gamma_usd: -6_103.515625, vega_usd: -80.0 });
+
+        let limits = PositionLimits { max_net_vega_usd: Some(100.0), max_net_gamma_usd: Some(10_000.0), ..Default::default() };
+        assert!(limits.limits_greeks());
+        assert!(limits.check_greeks(&BookGreeks::default(), &book).is_ok());
+        let mut more = book;
+        more.add(&contract(100000.0, 1.0), &greeks, 100_000.0);
+        let err = limits.check_greeks(&book, &more).unwrap_err();
+        assert!(err.to_string().contains("net vega of the pool would be $-120.00 (currently $-80.00), limit ±$100.00"), "{}", err);
+        assert_eq!(err.details()["limit"], "max_net_vega_usd");
+        // Reducing a Greek already past its limit is allowed
+        assert!(limits.check_greeks(&more, &book).is_ok());
+        assert!(!PositionLimits::default().limits_greeks());
+    }
+}
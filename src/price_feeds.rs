@@ -0,0 +1,695 @@
+// Price sources used when the gRPC aggregator is unavailable.
+// FallbackPriceSource tries the aggregator first, then the median of the
+// public exchange REST feeds, and finally serves the last good price flagged
+// as stale rather than failing.
+
+use crate::circuit_breaker::CircuitBreaker;
+use crate::models::Asset;
+use crate::price_oracle::{OutlierFilter, PriceOracle, StreamEvent, AGGREGATOR};
+use crate::sources::{median, PriceQuote, PriceSource, PriceUpdate, SourceError, SourcePrice};
+use crate::timeouts::UpstreamTimeouts;
+use async_trait::async_trait;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use chrono::Utc;
+use tokio::sync::{broadcast, Mutex};
+
+// Streamed prices are trusted without polling for at most this long, in case
+// the stream stays connected but stops delivering updates
+const STREAM_MAX_AGE: Duration = Duration::from_secs(60);
+
+/// Public exchange ticker used as a secondary USD spot price
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Exchange {
+    Coinbase,
+    Binance,
+    Kraken,
+}
+
+impl Exchange {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "coinbase" => Some(Exchange::Coinbase),
+            "binance" => Some(Exchange::Binance),
+            "kraken" => Some(Exchange::Kraken),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Exchange::Coinbase => "coinbase",
+            Exchange::Binance => "binance",
+            Exchange::Kraken => "kraken",
+        }
+    }
+
+    fn url(&self, asset: Asset) -> String {
+        match self {
+            Exchange::Coinbase => format!("https://api.coinbase.com/v2/prices/{}-USD/spot", asset),
+            Exchange::Binance => format!("https://api.binance.com/api/v3/ticker/price?symbol={}USDT", asset),
+            Exchange::Kraken => {
+                // Kraken still lists bitcoin under its legacy XBT code
+                let pair = match asset {
+                    Asset::Btc => "XBT",
+                    Asset::Eth => "ETH",
+                };
+                format!("https://api.kraken.com/0/public/Ticker?pair={}USD", pair)
+            }
+        }
+    }
+
+    /// Extract the last trade price from the exchange's ticker response
+    pub fn parse_price(&self, body: &Value) -> Option<f64> {
+        let price = match self {
+            // {"data": {"amount": "100000.00", ...}}
+            Exchange::Coinbase => body["data"]["amount"].as_str()?,
+            // {"symbol": "BTCUSDT", "price": "100000.00"}
+            Exchange::Binance => body["price"].as_str()?,
+            // {"error": [], "result": {"XXBTZUSD": {"c": ["100000.0", "0.01"], ...}}}
+            Exchange::Kraken => body["result"]
+                .as_object()?
+                .values()
+                .next()?["c"][0]
+                .as_str()?,
+        };
+        price.parse::<f64>().ok().filter(|p| p.is_finite() && *p > 0.0)
+    }
+}
+
+/// Spot price from a single exchange REST ticker
+pub struct RestPriceFeed {
+    exchange: Exchange,
+    client: reqwest::Client,
+}
+
+impl RestPriceFeed {
+    pub fn new(exchange: Exchange, timeout: Duration) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .unwrap_or_default();
+        Self { exchange, client }
+    }
+}
+
+#[async_trait]
+impl PriceSource for RestPriceFeed {
+    async fn get_btc_price(&self) -> Result<f64, SourceError> {
+        self.get_price(Asset::Btc).await
+    }
+
+    async fn get_price(&self, asset: Asset) -> Result<f64, SourceError> {
+        let body: Value = self
+            .client
+            .get(self.exchange.url(asset))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        self.exchange
+            .parse_price(&body)
+            .ok_or_else(|| format!("unexpected {} {} ticker response", self.exchange.name(), asset).into())
+    }
+
+    fn version(&self) -> u64 {
+        0
+    }
+}
+
+/// gRPC aggregator that connects on first use and reconnects after failures,
+/// so the server can start while the aggregator is down
+pub struct LazyAggregator {
+    url: String,
+    timeout: Duration,
+    outlier_filter: OutlierFilter,
+    oracle: Mutex<Option<PriceOracle>>,
+}
+
+impl LazyAggregator {
+    pub fn new(url: String, timeout: Duration, outlier_filter: OutlierFilter) -> Self {
+        Self { url, timeout, outlier_filter, oracle: Mutex::new(None) }
+    }
+
+    // Run `call` against the connected aggregator, connecting first if needed
+    async fn with_oracle<T, F, Fut>(&self, call: F) -> Result<T, SourceError>
+    where
+        F: FnOnce(PriceOracle) -> Fut,
+        Fut: std::future::Future<Output = Result<T, Box<dyn std::error::Error>>>,
+    {
+        let mut oracle = self.oracle.lock().await;
+        if oracle.is_none() {
+            let connected = PriceOracle::connect(self.url.clone(), self.timeout).await.map_err(|e| e.to_string())?;
+            *oracle = Some(connected.with_outlier_filter(self.outlier_filter));
+        }
+        let result = call(oracle.clone().unwrap()).await.map_err(|e| e.to_string());
+        if result.is_err() {
+            // Drop the client so the next attempt reconnects and re-runs the health check
+            *oracle = None;
+        }
+        Ok(result?)
+    }
+}
+
+#[async_trait]
+impl PriceSource for LazyAggregator {
+    async fn get_btc_price(&self) -> Result<f64, SourceError> {
+        Ok(self.get_price_quote().await?.price)
+    }
+
+    async fn get_price(&self, asset: Asset) -> Result<f64, SourceError> {
+        Ok(self.get_asset_price_quote(asset).await?.price)
+    }
+
+    async fn get_price_quote(&self) -> Result<PriceQuote, SourceError> {
+        self.get_asset_price_quote(Asset::Btc).await
+    }
+
+    async fn get_asset_price_quote(&self, asset: Asset) -> Result<PriceQuote, SourceError> {
+        let checked = self.with_oracle(|oracle| async move { oracle.get_checked_price(asset).await }).await?;
+        Ok(PriceQuote {
+            price: checked.price,
+            age: Duration::ZERO,
+            data_points: checked.used_points,
+            rejected_points: checked.rejected_points,
+            previous_price: None,
+            stale: false,
+        })
+    }
+
+    async fn source_prices(&self, asset: Asset, sources: &[String]) -> Result<Vec<SourcePrice>, SourceError> {
+        self.with_oracle(|oracle| async move { oracle.get_source_prices(asset, sources).await }).await
+    }
+
+    fn version(&self) -> u64 {
+        0
+    }
+}
+
+// A price source guarded by one circuit breaker per asset, so a source that
+// can't price ETH keeps serving BTC
+struct GuardedSource {
+    name: String,
+    source: Arc<dyn PriceSource>,
+    breakers: HashMap<Asset, CircuitBreaker>,
+}
+
+impl GuardedSource {
+    async fn fetch(&self, asset: Asset) -> Result<PriceQuote, String> {
+        let breaker = self.breakers.get(&asset).ok_or_else(|| format!("{} is not priced", asset))?;
+        if !breaker.allow() {
+            return Err("skipped while its circuit breaker is open".to_string());
+        }
+        match self.source.get_asset_price_quote(asset).await {
+            Ok(quote) => {
+                breaker.record_success();
+                Ok(quote)
+            }
+            Err(e) => {
+                breaker.record_failure();
+                eprintln!("⚠️  Price source {} failed for {}: {}", self.name, asset, e);
+                Err(e.to_string())
+            }
+        }
+    }
+
+    // This feed's latest price, as a fallback polled directly
+    async fn source_price(&self, asset: Asset) -> SourcePrice {
+        let quote = self.fetch(asset).await;
+        SourcePrice {
+            source: self.name.clone(),
+            via: REST.to_string(),
+            price: quote.as_ref().ok().map(|quote| quote.price),
+            timestamp: quote.as_ref().ok().map(|quote| Utc::now().timestamp() - quote.age.as_secs() as i64),
+            error: quote.err(),
+        }
+    }
+}
+
+/// `SourcePrice::via` of exchange tickers polled directly
+pub const REST: &str = "rest";
+
+/// Settings for FallbackPriceSource
+#[derive(Debug, Clone)]
+pub struct FallbackConfig {
+    pub exchanges: Vec<Exchange>,
+    pub breaker_failures: u32,
+    pub breaker_cooldown: Duration,
+    pub feed_timeout: Duration,
+    pub aggregator_timeout: Duration,
+    pub cache_duration: Duration,
+    pub outlier_filter: OutlierFilter,  // Applied to the points behind the aggregator's price
+}
+
+impl FallbackConfig {
+    /// From PRICE_FALLBACK_SOURCES (default "coinbase,binance,kraken", "none" disables),
+    /// PRICE_BREAKER_FAILURES (3), PRICE_BREAKER_COOLDOWN_SECS (30), PRICE_FEED_TIMEOUT_SECS (5)
+    /// and PRICE_ORACLE_TIMEOUT_SECS (5) for the aggregator, whose points are checked as
+    /// in OutlierFilter::from_env
+    pub fn from_env() -> Self {
+        let sources = env::var("PRICE_FALLBACK_SOURCES").unwrap_or_else(|_| "coinbase,binance,kraken".to_string());
+        let exchanges = sources
+            .split(',')
+            .filter(|name| !name.trim().is_empty() && name.trim() != "none")
+            .filter_map(|name| {
+                let exchange = Exchange::parse(name);
+                if exchange.is_none() {
+                    eprintln!("⚠️  Unknown price source '{}' in PRICE_FALLBACK_SOURCES, ignoring", name.trim());
+                }
+                exchange
+            })
+            .collect();
+
+        Self {
+            exchanges,
+            breaker_failures: env::var("PRICE_BREAKER_FAILURES")
+                .unwrap_or_else(|_| "3".to_string())
+                .parse()
+                .unwrap_or(3),
+            breaker_cooldown: Duration::from_secs(
+                env::var("PRICE_BREAKER_COOLDOWN_SECS")
+                    .unwrap_or_else(|_| "30".to_string())
+                    .parse()
+                    .unwrap_or(30),
+            ),
+            feed_timeout: Duration::from_secs(
+                env::var("PRICE_FEED_TIMEOUT_SECS")
+                    .unwrap_or_else(|_| "5".to_string())
+                    .parse()
+                    .unwrap_or(5),
+            ),
+            aggregator_timeout: UpstreamTimeouts::from_env().price_oracle,
+            cache_duration: Duration::from_secs(10),
+            outlier_filter: OutlierFilter::from_env(),
+        }
+    }
+}
+
+// Most recent fresh price and the one before it
+#[derive(Debug, Clone, Copy)]
+struct Observation {
+    price: f64,
+    at: Instant,
+    data_points: u32,
+    rejected_points: u32,
+    previous_price: Option<f64>,
+}
+
+impl Observation {
+    fn quote(&self, stale: bool) -> PriceQuote {
+        PriceQuote {
+            price: self.price,
+            age: self.at.elapsed(),
+            data_points: self.data_points,
+            rejected_points: self.rejected_points,
+            previous_price: self.previous_price,
+            stale,
+        }
+    }
+}
+
+/// Aggregator first, then the median of the available fallback feeds, then the
+/// last good price flagged as stale. While the aggregator price stream is
+/// connected, streamed prices are served directly and nothing is polled.
+/// The stream, staleness flag and /ws/price updates cover BTC only; other
+/// underlyings are polled through the same sources with their own cache.
+pub struct FallbackPriceSource {
+    primary: Option<GuardedSource>,
+    fallbacks: Vec<GuardedSource>,
+    cache_duration: Duration,
+    last_price: RwLock<Option<Observation>>,
+    asset_prices: RwLock<HashMap<Asset, Observation>>,
+    stale: AtomicBool,
+    streaming: AtomicBool,
+    version: AtomicU64,
+    updates: broadcast::Sender<PriceUpdate>,
+}
+
+impl FallbackPriceSource {
+    pub fn new(
+        primary: Option<(String, Arc<dyn PriceSource>)>,
+        fallbacks: Vec<(String, Arc<dyn PriceSource>)>,
+        config: &FallbackConfig,
+    ) -> Self {
+        let guard = |(name, source): (String, Arc<dyn PriceSource>)| GuardedSource {
+            name,
+            source,
+            breakers: Asset::ALL
+                .iter()
+                .map(|asset| (*asset, CircuitBreaker::new(config.breaker_failures, config.breaker_cooldown)))
+                .collect(),
+        };
+        Self {
+            primary: primary.map(guard),
+            fallbacks: fallbacks.into_iter().map(guard).collect(),
+            cache_duration: config.cache_duration,
+            last_price: RwLock::new(None),
+            asset_prices: RwLock::new(HashMap::new()),
+            stale: AtomicBool::new(false),
+            streaming: AtomicBool::new(false),
+            version: AtomicU64::new(0),
+            updates: broadcast::channel(64).0,
+        }
+    }
+
+    /// Feed events from price_oracle::start_price_stream into the cache
+    pub fn handle_stream_event(&self, event: StreamEvent) {
+        match event {
+            StreamEvent::Connected => {
+                println!("📡 Subscribed to aggregator price stream");
+                self.streaming.store(true, Ordering::SeqCst);
+            }
+            StreamEvent::Price { price, data_points, .. } => self.record_price(price, data_points, 0),
+            StreamEvent::Disconnected(reason) => {
+                if self.streaming.swap(false, Ordering::SeqCst) {
+                    eprintln!("⚠️  Aggregator price stream disconnected ({}), polling until it reconnects", reason);
+                }
+            }
+        }
+    }
+
+    fn record_price(&self, price: f64, data_points: u32, rejected_points: u32) {
+        {
+            let mut last_price = self.last_price.write().unwrap();
+            let previous_price = last_price.map(|observation| observation.price);
+            *last_price = Some(Observation { price, at: Instant::now(), data_points, rejected_points, previous_price });
+        }
+        self.stale.store(false, Ordering::SeqCst);
+        self.version.fetch_add(1, Ordering::SeqCst);
+        self.publish(price, false);
+    }
+
+    fn publish(&self, price: f64, stale: bool) {
+        // No receivers is fine: nobody is subscribed to /ws/price
+        let _ = self.updates.send(PriceUpdate { price, timestamp: Utc::now().timestamp(), stale });
+    }
+
+    /// The gRPC aggregator backed by the exchange REST feeds in `config`
+    pub fn from_aggregator(aggregator_url: String, config: &FallbackConfig) -> Self {
+        let fallbacks = config
+            .exchanges
+            .iter()
+            .map(|exchange| {
+                let feed: Arc<dyn PriceSource> = Arc::new(RestPriceFeed::new(*exchange, config.feed_timeout));
+                (exchange.name().to_string(), feed)
+            })
+            .collect();
+        let aggregator: Arc<dyn PriceSource> = Arc::new(LazyAggregator::new(
+            aggregator_url,
+            config.aggregator_timeout,
+            config.outlier_filter,
+        ));
+        Self::new(Some(("aggregator".to_string(), aggregator)), fallbacks, config)
+    }
+
+    // (price, data points, rejected points): the aggregator's own counts, or the number of
+    // feeds in the median
+    async fn fetch_fresh(&self, asset: Asset) -> Option<(f64, u32, u32)> {
+        if let Some(primary) = &self.primary {
+            if let Ok(quote) = primary.fetch(asset).await {
+                return Some((quote.price, quote.data_points, quote.rejected_points));
+            }
+        }
+        let prices: Vec<f64> = futures::future::join_all(self.fallbacks.iter().map(|source| source.fetch(asset)))
+            .await
+            .into_iter()
+            .filter_map(Result::ok)
+            .map(|quote| quote.price)
+            .collect();
+        let count = prices.len() as u32;
+        median(prices).map(|price| (price, count, 0))
+    }
+
+    // Non-BTC underlyings: fresh within cache_duration, else refetched, else the
+    // last good observation flagged as stale
+    async fn other_asset_quote(&self, asset: Asset) -> Result<PriceQuote, SourceError> {
+        let cached = self.asset_prices.read().unwrap().get(&asset).copied();
+        if let Some(observation) = cached {
+            if observation.at.elapsed() < self.cache_duration {
+                return Ok(observation.quote(false));
+            }
+        }
+
+        match self.fetch_fresh(asset).await {
+            Some((price, data_points, rejected_points)) => {
+                let observation = Observation {
+                    price,
+                    at: Instant::now(),
+                    data_points,
+                    rejected_points,
+                    previous_price: cached.map(|observation| observation.price),
+                };
+                self.asset_prices.write().unwrap().insert(asset, observation);
+                Ok(observation.quote(false))
+            }
+            None => cached
+                .map(|observation| observation.quote(true))
+                .ok_or_else(|| format!("no {} price source is available", asset).into()),
+        }
+    }
+}
+
+#[async_trait]
+impl PriceSource for FallbackPriceSource {
+    async fn get_btc_price(&self) -> Result<f64, SourceError> {
+        let cached = *self.last_price.read().unwrap();
+        if let Some(Observation { price, at: fetched_at, .. }) = cached {
+            let max_age = if self.streaming.load(Ordering::SeqCst) {
+                STREAM_MAX_AGE
+            } else {
+                self.cache_duration
+            };
+            if fetched_at.elapsed() < max_age && !self.stale.load(Ordering::SeqCst) {
+                return Ok(price);
+            }
+        }
+
+        match self.fetch_fresh(Asset::Btc).await {
+            Some((price, data_points, rejected_points)) => {
+                self.record_price(price, data_points, rejected_points);
+                Ok(price)
+            }
+            None => match cached {
+                Some(Observation { price, at: fetched_at, .. }) => {
+                    if !self.stale.swap(true, Ordering::SeqCst) {
+                        eprintln!(
+                            "⚠️  All price sources unavailable, serving cached price ${:.2} from {}s ago",
+                            price,
+                            fetched_at.elapsed().as_secs()
+                        );
+                        self.publish(price, true);
+                    }
+                    Ok(price)
+                }
+                None => Err("no price source is available and no price has been cached yet".into()),
+            },
+        }
+    }
+
+    fn version(&self) -> u64 {
+        self.version.load(Ordering::SeqCst)
+    }
+
+    fn is_stale(&self) -> bool {
+        self.stale.load(Ordering::SeqCst)
+    }
+
+    fn subscribe(&self) -> Option<broadcast::Receiver<PriceUpdate>> {
+        Some(self.updates.subscribe())
+    }
+
+    async fn get_price(&self, asset: Asset) -> Result<f64, SourceError> {
+        match asset {
+            Asset::Btc => self.get_btc_price().await,
+            _ => Ok(self.other_asset_quote(asset).await?.price),
+        }
+    }
+
+    async fn get_price_quote(&self) -> Result<PriceQuote, SourceError> {
+        let price = self.get_btc_price().await?;
+        let observation = (*self.last_price.read().unwrap()).ok_or("no price observed")?;
+        Ok(PriceQuote { price, ..observation.quote(self.is_stale()) })
+    }
+
+    async fn get_asset_price_quote(&self, asset: Asset) -> Result<PriceQuote, SourceError> {
+        match asset {
+            Asset::Btc => self.get_price_quote().await,
+            _ => self.other_asset_quote(asset).await,
+        }
+    }
+
+    /// The aggregator's per-exchange breakdown, then each fallback feed polled directly
+    async fn source_prices(&self, asset: Asset, sources: &[String]) -> Result<Vec<SourcePrice>, SourceError> {
+        let mut prices = match &self.primary {
+            Some(primary) => primary.source.source_prices(asset, sources).await.unwrap_or_else(|e| {
+                vec![SourcePrice {
+                    source: primary.name.clone(),
+                    via: AGGREGATOR.to_string(),
+                    price: None,
+                    timestamp: None,
+                    error: Some(e.to_string()),
+                }]
+            }),
+            None => Vec::new(),
+        };
+        let polled = self
+            .fallbacks
+            .iter()
+            .filter(|feed| sources.is_empty() || sources.iter().any(|source| source.eq_ignore_ascii_case(&feed.name)));
+        prices.extend(futures::future::join_all(polled.map(|feed| feed.source_price(asset))).await);
+        Ok(prices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::FixedPriceSource;
+    use serde_json::json;
+
+    // Returns a fixed price, or fails while `up` is false
+    struct ToggleSource {
+        price: f64,
+        up: AtomicBool,
+    }
+
+    impl ToggleSource {
+        fn new(price: f64, up: bool) -> Arc<Self> {
+            Arc::new(Self { price, up: AtomicBool::new(up) })
+        }
+    }
+
+    #[async_trait]
+    impl PriceSource for ToggleSource {
+        async fn get_btc_price(&self) -> Result<f64, SourceError> {
+            if self.up.load(Ordering::SeqCst) {
+                Ok(self.price)
+            } else {
+                Err("down".into())
+            }
+        }
+
+        fn version(&self) -> u64 {
+            0
+        }
+    }
+
+    fn config() -> FallbackConfig {
+        FallbackConfig {
+            exchanges: vec![],
+            breaker_failures: 1,
+            breaker_cooldown: Duration::from_secs(60),
+            feed_timeout: Duration::from_secs(1),
+            aggregator_timeout: Duration::from_secs(1),
+            cache_duration: Duration::ZERO,
+            outlier_filter: OutlierFilter::default(),
+        }
+    }
+
+    #[test]
+    fn test_parse_exchange_tickers() {
+        let coinbase = json!({"data": {"base": "BTC", "currency": "USD", "amount": "100123.45"}});
+        let binance = json!({"symbol": "BTCUSDT", "price": "100100.00000000"});
+        let kraken = json!({"error": [], "result": {"XXBTZUSD": {"c": ["100050.10000", "0.001"]}}});
+
+        assert_eq!(Exchange::Coinbase.parse_price(&coinbase), Some(100123.45));
+        assert_eq!(Exchange::Binance.parse_price(&binance), Some(100100.0));
+        assert_eq!(Exchange::Kraken.parse_price(&kraken), Some(100050.1));
+        assert_eq!(Exchange::Binance.parse_price(&json!({"code": -1121})), None);
+        assert_eq!(Exchange::parse(" Kraken"), Some(Exchange::Kraken));
+    }
+
+    #[test]
+    fn test_median() {
+        assert_eq!(median(vec![]), None);
+        assert_eq!(median(vec![3.0, 1.0, 2.0]), Some(2.0));
+        assert_eq!(median(vec![4.0, 1.0, 2.0, 3.0]), Some(2.5));
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_to_median_then_stale_cache() {
+        let primary = ToggleSource::new(100_000.0, true);
+        let feed_a = ToggleSource::new(99_000.0, true);
+        let feed_b = ToggleSource::new(101_000.0, true);
+        let source = FallbackPriceSource::new(
+            Some(("aggregator".to_string(), primary.clone() as Arc<dyn PriceSource>)),
+            vec![
+                ("a".to_string(), feed_a.clone() as Arc<dyn PriceSource>),
+                ("b".to_string(), feed_b.clone() as Arc<dyn PriceSource>),
+            ],
+            &config(),
+        );
+
+        assert_eq!(source.get_btc_price().await.unwrap(), 100_000.0);
+        assert!(!source.is_stale());
+
+        // Aggregator down: median of the fallback feeds
+        primary.up.store(false, Ordering::SeqCst);
+        assert_eq!(source.get_btc_price().await.unwrap(), 100_000.0);
+        // Median of whichever feeds answered
+        feed_b.up.store(false, Ordering::SeqCst);
+        assert_eq!(source.get_btc_price().await.unwrap(), 99_000.0);
+
+        // Everything down: last good price, flagged stale
+        feed_a.up.store(false, Ordering::SeqCst);
+        let version = source.version();
+        assert_eq!(source.get_btc_price().await.unwrap(), 99_000.0);
+        assert!(source.is_stale());
+        assert_eq!(source.version(), version);
+    }
+
+    #[tokio::test]
+    async fn test_streamed_prices_are_served_without_polling() {
+        let primary = ToggleSource::new(100_000.0, false);
+        let source = FallbackPriceSource::new(
+            Some(("aggregator".to_string(), primary.clone() as Arc<dyn PriceSource>)),
+            vec![],
+            &config(),
+        );
+        let mut updates = source.subscribe().unwrap();
+
+        source.handle_stream_event(StreamEvent::Connected);
+        source.handle_stream_event(StreamEvent::Price { price: 101_500.0, data_points: 3, timestamp: 0 });
+        assert_eq!(updates.recv().await.unwrap().price, 101_500.0);
+
+        // cache_duration is zero, yet the streamed price is served and the failing aggregator is not called
+        assert_eq!(source.get_btc_price().await.unwrap(), 101_500.0);
+        assert!(!source.is_stale());
+
+        // Disconnected: back to polling, which fails, so the streamed price is served as stale
+        source.handle_stream_event(StreamEvent::Disconnected("test".to_string()));
+        assert_eq!(source.get_btc_price().await.unwrap(), 101_500.0);
+        assert!(source.is_stale());
+        assert!(updates.recv().await.unwrap().stale);
+    }
+
+    #[tokio::test]
+    async fn test_other_assets_skip_sources_that_cannot_price_them() {
+        // The aggregator only knows BTC; the exchange feed also prices ETH
+        let primary = ToggleSource::new(100_000.0, true);
+        let feed = Arc::new(FixedPriceSource::new(99_000.0).with_price(Asset::Eth, 3_500.0));
+        let source = FallbackPriceSource::new(
+            Some(("aggregator".to_string(), primary as Arc<dyn PriceSource>)),
+            vec![("feed".to_string(), feed as Arc<dyn PriceSource>)],
+            &config(),
+        );
+
+        let quote = source.get_asset_price_quote(Asset::Eth).await.unwrap();
+        assert_eq!(quote.price, 3_500.0);
+        assert_eq!(quote.data_points, 1);
+        // The aggregator's ETH breaker is open, its BTC breaker is not
+        assert_eq!(source.get_price(Asset::Btc).await.unwrap(), 100_000.0);
+        assert_eq!(source.get_price(Asset::Eth).await.unwrap(), 3_500.0);
+    }
+
+    #[tokio::test]
+    async fn test_no_sources_and_no_cache_is_an_error() {
+        let source = FallbackPriceSource::new(None, vec![], &config());
+        assert!(source.get_btc_price().await.is_err());
+    }
+}
@@ -0,0 +1,129 @@
+use crate::error::ApiError;
+use crate::models::Asset;
+use crate::sources::PriceQuote;
+use std::env;
+use std::time::Duration;
+
+/// Checks a price quote is trustworthy enough to accept a trade against it
+#[derive(Debug, Clone, PartialEq)]
+pub struct PriceGuards {
+    pub max_age: Duration,
+    pub min_data_points: u32,
+    pub max_deviation_percent: f64,  // vs the previous observation
+}
+
+impl Default for PriceGuards {
+    fn default() -> Self {
+        Self {
+            max_age: Duration::from_secs(30),
+            min_data_points: 1,
+            max_deviation_percent: 10.0,
+        }
+    }
+}
+
+impl PriceGuards {
+    /// From PRICE_MAX_AGE_SECS (30), PRICE_MIN_DATA_POINTS (1) and PRICE_MAX_DEVIATION_PERCENT (10)
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            max_age: env::var("PRICE_MAX_AGE_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.max_age),
+            min_data_points: env::var("PRICE_MIN_DATA_POINTS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.min_data_points),
+            max_deviation_percent: env::var("PRICE_MAX_DEVIATION_PERCENT")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.max_deviation_percent),
+        }
+    }
+
+    pub fn check(&self, asset: Asset, quote: &PriceQuote) -> Result<(), ApiError> {
+        if quote.stale || quote.age > self.max_age {
+            return Err(ApiError::StalePrice(format!(
+                "{} price is {}s old (max {}s)",
+                asset,
+                quote.age.as_secs(),
+                self.max_age.as_secs()
+            ))
+            .with_details(serde_json::json!({
+                "asset": asset,
+                "age_secs": quote.age.as_secs(),
+                "max_age_secs": self.max_age.as_secs(),
+            })));
+        }
+        if quote.data_points < self.min_data_points {
+            return Err(ApiError::StalePrice(format!(
+                "{} price is backed by {} data point(s), at least {} required",
+                asset, quote.data_points, self.min_data_points
+            ))
+            .with_details(serde_json::json!({
+                "asset": asset,
+                "data_points": quote.data_points,
+                "min_data_points": self.min_data_points,
+            })));
+        }
+        if let Some(previous) = quote.previous_price.filter(|p| *p > 0.0) {
+            let deviation_percent = (quote.price - previous).abs() / previous * 100.0;
+            if deviation_percent > self.max_deviation_percent {
+                return Err(ApiError::StalePrice(format!(
+                    "{} price moved {:.2}% since the previous observation (${:.2} -> ${:.2}), max {:.2}%",
+                    asset, deviation_percent, previous, quote.price, self.max_deviation_percent
+                ))
+                .with_details(serde_json::json!({
+                    "asset": asset,
+                    "price": quote.price,
+                    "previous_price": previous,
+                    "deviation_percent": deviation_percent,
+                    "max_deviation_percent": self.max_deviation_percent,
+                })));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorCode;
+
+    fn quote() -> PriceQuote {
+        PriceQuote {
+            price: 100_000.0,
+            age: Duration::from_secs(2),
+            data_points: 3,
+            rejected_points: 0,
+            previous_price: Some(99_500.0),
+            stale: false,
+        }
+    }
+
+    #[test]
+    fn test_guards() {
+        let guards = PriceGuards { min_data_points: 2, ..PriceGuards::default() };
+        assert!(guards.check(Asset::Btc, &quote()).is_ok());
+
+        let old = PriceQuote { age: Duration::from_secs(31), ..quote() };
+        assert!(matches!(guards.check(Asset::Btc, &old), Err(e) if e.code() == ErrorCode::StalePrice));
+
+        let stale = PriceQuote { stale: true, ..quote() };
+        assert!(matches!(guards.check(Asset::Btc, &stale), Err(e) if e.code() == ErrorCode::StalePrice));
+
+        let thin = PriceQuote { data_points: 1, ..quote() };
+        assert!(matches!(guards.check(Asset::Btc, &thin), Err(e) if e.code() == ErrorCode::StalePrice));
+
+        let jump = PriceQuote { previous_price: Some(80_000.0), ..quote() };
+        let error = guards.check(Asset::Btc, &jump).unwrap_err();
+        assert_eq!(error.code(), ErrorCode::StalePrice);
+        assert_eq!(error.details()["previous_price"], 80_000.0);
+
+        let first = PriceQuote { previous_price: None, ..quote() };
+        assert!(guards.check(Asset::Btc, &first).is_ok());
+    }
+}
@@ -0,0 +1,248 @@
+// Spot price history of every underlying.
+// A background task samples each enabled underlying every SPOT_SAMPLE_INTERVAL_SECS into
+// price_history, where realized vol, settlement TWAPs and GET /spot/history read it. The
+// settlement sampler adds samples of its own in the window before each maturity. Samples
+// older than SPOT_HISTORY_RAW_DAYS are downsampled to the lowest, highest and last price of
+// each hour, which keeps hourly realized vol exact, and dropped after SPOT_HISTORY_RETENTION_DAYS.
+
+use crate::error::{ApiError, ApiResult};
+use crate::models::Asset;
+use crate::repository::Repository;
+use crate::sources::PriceSource;
+use crate::supervisor::Supervisor;
+use crate::utils::{cents_to_usd, usd_to_cents};
+use crate::vol::BAR_SECONDS;
+use chrono::Utc;
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use std::env;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::time::interval;
+
+// Most candles GET /spot/history returns
+pub const MAX_CANDLES: i64 = 5_000;
+
+// How often old samples are downsampled and pruned
+const COMPACTION_INTERVAL: Duration = Duration::from_secs(3600);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PriceHistoryConfig {
+    pub sample_interval: Duration,
+    pub raw_retention: Duration,      // Kept at full resolution
+    pub retention: Option<Duration>,  // None keeps history forever
+}
+
+impl Default for PriceHistoryConfig {
+    fn default() -> Self {
+        Self {
+            sample_interval: Duration::from_secs(60),
+            raw_retention: Duration::from_secs(7 * 86_400),
+            retention: Some(Duration::from_secs(365 * 86_400)),
+        }
+    }
+}
+
+impl PriceHistoryConfig {
+    /// SPOT_SAMPLE_INTERVAL_SECS (60), SPOT_HISTORY_RAW_DAYS (7, at least 1 so settlement
+    /// windows stay at full resolution) and SPOT_HISTORY_RETENTION_DAYS (365, 0 = forever)
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let days = |name: &str| env::var(name).ok().and_then(|v| v.parse::<u64>().ok()).map(|days| Duration::from_secs(days * 86_400));
+        Self {
+            sample_interval: env::var("SPOT_SAMPLE_INTERVAL_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .map(|secs: u64| Duration::from_secs(secs.max(1)))
+                .unwrap_or(defaults.sample_interval),
+            raw_retention: days("SPOT_HISTORY_RAW_DAYS")
+                .map(|raw| raw.max(Duration::from_secs(86_400)))
+                .unwrap_or(defaults.raw_retention),
+            retention: match days("SPOT_HISTORY_RETENTION_DAYS") {
+                Some(Duration::ZERO) => None,
+                Some(retention) => Some(retention),
+                None => defaults.retention,
+            },
+        }
+    }
+}
+
+/// Store a spot price of `asset` observed at `timestamp`
+pub fn record(conn: &Connection, asset: Asset, price: f64, timestamp: i64) -> ApiResult<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO price_history (asset, timestamp, price_cents) VALUES (?1, ?2, ?3)",
+        params![asset, timestamp, usd_to_cents(price)],
+    )?;
+    Ok(())
+}
+
+/// Downsample the samples recorded before `now - raw_retention` to the lowest, highest and
+/// last price of each hour, and delete those older than the retention. Returns the rows removed.
+pub fn compact(conn: &Connection, config: &PriceHistoryConfig, now: i64) -> ApiResult<usize> {
+    let cutoff = now - config.raw_retention.as_secs() as i64;
+    // Whole hours only, so a bar is never left half downsampled
+    let cutoff = cutoff - cutoff.rem_euclid(BAR_SECONDS);
+    let mut removed = conn.execute(
+        "DELETE FROM price_history WHERE timestamp < ?1 AND (asset, timestamp) NOT IN (
+             SELECT asset, timestamp FROM (
+                 SELECT asset, timestamp,
+                        ROW_NUMBER() OVER (PARTITION BY asset, timestamp / ?2 ORDER BY price_cents, timestamp) AS low,
+                        ROW_NUMBER() OVER (PARTITION BY asset, timestamp / ?2 ORDER BY price_cents DESC, timestamp) AS high,
+                        ROW_NUMBER() OVER (PARTITION BY asset, timestamp / ?2 ORDER BY timestamp DESC) AS last
+                 FROM price_history WHERE timestamp < ?1
+             )
+             WHERE low = 1 OR high = 1 OR last = 1
+         )",
+        params![cutoff, BAR_SECONDS],
+    )?;
+    if let Some(retention) = config.retention {
+        removed += conn.execute(
+            "DELETE FROM price_history WHERE timestamp < ?1",
+            params![now - retention.as_secs() as i64],
+        )?;
+    }
+    Ok(removed)
+}
+
+/// OHLC of the samples falling in one interval
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Candle {
+    pub timestamp: i64,  // Start of the interval
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub sample_count: usize,
+}
+
+/// Candles of `asset` over [from, to) at `interval_secs`, oldest first. Intervals without
+/// samples are left out.
+pub fn candles(conn: &Connection, asset: Asset, from: i64, to: i64, interval_secs: i64) -> ApiResult<Vec<Candle>> {
+    if interval_secs <= 0 || to <= from {
+        return Err(ApiError::ValidationError("from must be before to and the interval positive".to_string()));
+    }
+    let mut stmt = conn.prepare(
+        "SELECT timestamp, price_cents FROM price_history
+         WHERE asset = ?1 AND timestamp >= ?2 AND timestamp < ?3
+         ORDER BY timestamp ASC",
+    )?;
+    let rows = stmt.query_map(params![asset, from, to], |row| Ok((row.get::<_, i64>(0)?, cents_to_usd(row.get(1)?))))?;
+
+    let mut candles: Vec<Candle> = Vec::new();
+    for row in rows {
+        let (timestamp, price) = row?;
+        let start = timestamp - timestamp.rem_euclid(interval_secs);
+        match candles.last_mut() {
+            Some(candle) if candle.timestamp == start => {
+                candle.high = candle.high.max(price);
+                candle.low = candle.low.min(price);
+                candle.close = price;
+                candle.sample_count += 1;
+            }
+            _ => candles.push(Candle { timestamp: start, open: price, high: price, low: price, close: price, sample_count: 1 }),
+        }
+    }
+    Ok(candles)
+}
+
+/// Sample the spot price of each of `assets` now. Returns the number of samples recorded.
+pub async fn sample_prices(repository: &Repository, price_source: &dyn PriceSource, assets: &[Asset], now: i64) -> ApiResult<usize> {
+    let mut recorded = 0;
+    for &asset in assets {
+        match price_source.get_price(asset).await {
+            Ok(price) => {
+                repository.run(move |conn| record(conn, asset, price, now)).await?;
+                recorded += 1;
+            }
+            Err(e) => eprintln!("Error sampling {} spot price: {}", asset, e),
+        }
+    }
+    Ok(recorded)
+}
+
+/// Sample every enabled underlying into price_history, compacting old samples hourly
+pub fn start_sampler(
+    supervisor: &Supervisor,
+    repository: Repository,
+    price_source: Arc<dyn PriceSource>,
+    assets: Vec<Asset>,
+    config: PriceHistoryConfig,
+) {
+    supervisor.spawn("spot_sampling", move || {
+        let (repository, price_source, assets) = (repository.clone(), price_source.clone(), assets.clone());
+        async move {
+            let mut ticker = interval(config.sample_interval);
+            let mut last_compaction: Option<Instant> = None;
+            loop {
+                ticker.tick().await;
+                let now = Utc::now().timestamp();
+                if let Err(e) = sample_prices(&repository, price_source.as_ref(), &assets, now).await {
+                    eprintln!("Error storing spot samples: {}", e);
+                }
+                if last_compaction.is_none_or(|at| at.elapsed() >= COMPACTION_INTERVAL) {
+                    last_compaction = Some(Instant::now());
+                    match repository.run(move |conn| compact(conn, &config, now)).await {
+                        Ok(0) => {}
+                        Ok(removed) => println!("🗜️  Downsampled price history, {} old samples removed", removed),
+                        Err(e) => eprintln!("Error compacting price history: {}", e),
+                    }
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prices(conn: &Connection) -> Vec<(i64, f64)> {
+        let mut stmt = conn.prepare("SELECT timestamp, price_cents FROM price_history ORDER BY timestamp").unwrap();
+        stmt.query_map([], |row| Ok((row.get(0)?, cents_to_usd(row.get(1)?))))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_compact_keeps_hourly_low_high_and_last() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init_db(&conn).unwrap();
+        // An old hour sampled every ten minutes, and a recent sample
+        for (i, price) in [100.0, 103.0, 99.0, 101.0, 102.0, 100.5].iter().enumerate() {
+            record(&conn, Asset::Btc, *price, 3_600 + i as i64 * 600).unwrap();
+        }
+        record(&conn, Asset::Btc, 110.0, 30 * 86_400).unwrap();
+
+        let config = PriceHistoryConfig { retention: None, ..PriceHistoryConfig::default() };
+        let now = 30 * 86_400;
+        assert_eq!(compact(&conn, &config, now).unwrap(), 3);
+        assert_eq!(prices(&conn), vec![(4_200, 103.0), (4_800, 99.0), (6_600, 100.5), (30 * 86_400, 110.0)]);
+        // Already compacted
+        assert_eq!(compact(&conn, &config, now).unwrap(), 0);
+
+        let config = PriceHistoryConfig { retention: Some(Duration::from_secs(86_400)), ..config };
+        assert_eq!(compact(&conn, &config, now).unwrap(), 3);
+        assert_eq!(prices(&conn), vec![(30 * 86_400, 110.0)]);
+    }
+
+    #[test]
+    fn test_candles() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init_db(&conn).unwrap();
+        for (timestamp, price) in [(0, 100.0), (60, 104.0), (120, 98.0), (3_660, 101.0)] {
+            record(&conn, Asset::Btc, price, timestamp).unwrap();
+        }
+        record(&conn, Asset::Eth, 3_500.0, 0).unwrap();
+
+        let candles = candles(&conn, Asset::Btc, 0, 7_200, 3_600).unwrap();
+        assert_eq!(
+            candles,
+            vec![
+                Candle { timestamp: 0, open: 100.0, high: 104.0, low: 98.0, close: 98.0, sample_count: 3 },
+                Candle { timestamp: 3_600, open: 101.0, high: 101.0, low: 101.0, close: 101.0, sample_count: 1 },
+            ]
+        );
+        assert!(super::candles(&conn, Asset::Btc, 10, 10, 60).is_err());
+    }
+}
@@ -1,6 +1,16 @@
+use crate::error::ApiError;
+use crate::models::Asset;
+use crate::request_id;
+use crate::sources::{median, SourcePrice};
+use crate::timeouts::UpstreamTimeouts;
+use std::future::Future;
+use futures::future::join_all;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use tokio::sync::RwLock;
-use std::time::{Duration, SystemTime};
+use std::env;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tonic::transport::Channel;
 
 // Include the generated proto code
@@ -9,47 +19,153 @@ pub mod oracle {
 }
 
 use oracle::oracle_service_client::OracleServiceClient;
-use oracle::{GetPriceRequest, GetPriceResponse, HealthRequest};
+use oracle::{GetPriceRequest, GetPriceResponse, HealthRequest, PriceDataPoint, PriceRequest};
 
 #[derive(Clone)]
 pub struct PriceOracle {
-    cached_price: Arc<RwLock<Option<(f64, SystemTime)>>>,
+    cached_prices: Arc<RwLock<HashMap<Asset, (f64, SystemTime)>>>,
     grpc_client: OracleServiceClient<Channel>,
     cache_duration: Duration,
+    version: Arc<AtomicU64>,  // Bumped whenever a fresh price replaces the cached one
+    timeout: Duration,        // Deadline of each aggregator call
+    outlier_filter: OutlierFilter,
+}
+
+/// Drops points of the aggregator's recent prices that are too old, or too far from the
+/// median of the others, before its price is used
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutlierFilter {
+    pub max_point_age: Option<Duration>,     // None keeps points of any age
+    pub max_deviation_percent: Option<f64>,  // From the median of the fresh points; None keeps any
+}
+
+impl Default for OutlierFilter {
+    fn default() -> Self {
+        Self {
+            max_point_age: Some(Duration::from_secs(120)),
+            max_deviation_percent: Some(5.0),
+        }
+    }
+}
+
+/// Aggregated price once outlying points are dropped
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CheckedPrice {
+    pub price: f64,
+    pub used_points: u32,
+    pub rejected_points: u32,
+}
+
+impl OutlierFilter {
+    /// From ORACLE_POINT_MAX_AGE_SECS (120) and ORACLE_POINT_MAX_DEVIATION_PERCENT (5); 0 turns either check off
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            max_point_age: match env::var("ORACLE_POINT_MAX_AGE_SECS").ok().and_then(|v| v.parse::<u64>().ok()) {
+                Some(0) => None,
+                Some(secs) => Some(Duration::from_secs(secs)),
+                None => defaults.max_point_age,
+            },
+            max_deviation_percent: match env::var("ORACLE_POINT_MAX_DEVIATION_PERCENT").ok().and_then(|v| v.parse::<f64>().ok()) {
+                Some(percent) if percent <= 0.0 => None,
+                Some(percent) => Some(percent),
+                None => defaults.max_deviation_percent,
+            },
+        }
+    }
+
+    /// Check the points behind `response` at unix time `now`. With nothing dropped the aggregator's
+    /// price stands; otherwise the price is the median of the points kept.
+    pub fn apply(&self, response: &GetPriceResponse, now: u64) -> Result<CheckedPrice, String> {
+        // Aggregators that don't return the points behind the price can't be checked
+        if response.recent_prices.is_empty() {
+            return Ok(CheckedPrice {
+                price: response.aggregated_price,
+                used_points: response.data_points,
+                rejected_points: 0,
+            });
+        }
+        let fresh: Vec<f64> = response
+            .recent_prices
+            .iter()
+            .filter(|point| point.price.is_finite() && point.price > 0.0)
+            .filter(|point| self.max_point_age.is_none_or(|max_age| now.saturating_sub(point.timestamp) <= max_age.as_secs()))
+            .map(|point| point.price)
+            .collect();
+        let center = median(fresh.clone()).ok_or_else(|| {
+            format!("none of the {} oracle price points is recent enough", response.recent_prices.len())
+        })?;
+        let used: Vec<f64> = fresh
+            .into_iter()
+            .filter(|price| self.max_deviation_percent.is_none_or(|max| ((price - center) / center).abs() * 100.0 <= max))
+            .collect();
+        let rejected_points = (response.recent_prices.len() - used.len()) as u32;
+        let price = match rejected_points {
+            0 => response.aggregated_price,
+            _ => median(used.clone()).ok_or_else(|| {
+                format!("oracle price points disagree by more than {}% around ${:.2}", self.max_deviation_percent.unwrap_or_default(), center)
+            })?,
+        };
+        Ok(CheckedPrice { price, used_points: used.len() as u32, rejected_points })
+    }
+}
+
+// Fail `call` with UpstreamTimeout once `limit` has passed
+async fn within<T>(
+    limit: Duration,
+    call: impl Future<Output = Result<T, Box<dyn std::error::Error>>>,
+) -> Result<T, Box<dyn std::error::Error>> {
+    tokio::time::timeout(limit, call).await.unwrap_or_else(|_| {
+        Err(Box::new(ApiError::UpstreamTimeout(format!(
+            "Oracle Aggregator did not respond within {}s",
+            limit.as_secs_f64()
+        ))))
+    })
 }
 
 impl PriceOracle {
     pub async fn new(aggregator_url: String) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::connect(aggregator_url, UpstreamTimeouts::default().price_oracle).await
+    }
+
+    /// Connect to the aggregator, giving it `timeout` to answer each call
+    pub async fn connect(aggregator_url: String, timeout: Duration) -> Result<Self, Box<dyn std::error::Error>> {
         // Connect to the gRPC server
-        let client = OracleServiceClient::connect(aggregator_url.clone()).await
-            .map_err(|e| {
-                format!(
-                    "Failed to connect to Oracle Aggregator at {}. \n\n\
-                    Please ensure the Oracle Aggregator service is running.\n\n\
-                    To start the oracle system:\n\
-                    1. Start aggregator: cd /home/zeno/projects/oracle-node/aggregator-server && nix-shell && cargo run\n\
-                    2. Start oracle nodes: cd /home/zeno/projects/oracle-node && nix-shell && cargo run -- --node-id node1 --aggregator-url {}\n\n\
-                    For detailed setup instructions, see: docs/ORACLE_SETUP.md\n\n\
-                    Error: {}",
-                    aggregator_url, aggregator_url, e
-                )
-            })?;
+        let client = within(timeout, async {
+            Ok(OracleServiceClient::connect(aggregator_url.clone()).await?)
+        })
+        .await
+        .map_err(|e| {
+            format!(
+                "Failed to connect to Oracle Aggregator at {}. \n\n\
+                Please ensure the Oracle Aggregator service is running.\n\n\
+                To start the oracle system:\n\
+                1. Start aggregator: cd /home/zeno/projects/oracle-node/aggregator-server && nix-shell && cargo run\n\
+                2. Start oracle nodes: cd /home/zeno/projects/oracle-node && nix-shell && cargo run -- --node-id node1 --aggregator-url {}\n\n\
+                For detailed setup instructions, see: docs/ORACLE_SETUP.md\n\n\
+                Error: {}",
+                aggregator_url, aggregator_url, e
+            )
+        })?;
         
         // Perform health check
         let mut client_clone = client.clone();
-        let health_response = client_clone
-            .health_check(HealthRequest { 
-                node_id: "btc-option-manager".to_string() 
-            })
-            .await
-            .map_err(|e| {
-                format!(
-                    "Oracle Aggregator health check failed. \n\n\
-                    The service may not be fully initialized.\n\
-                    Error: {}",
-                    e
-                )
-            })?;
+        let health_response = within(timeout, async {
+            Ok(client_clone
+                .health_check(request_id::grpc_request(HealthRequest {
+                    node_id: "btc-option-manager".to_string()
+                }))
+                .await?)
+        })
+        .await
+        .map_err(|e| {
+            format!(
+                "Oracle Aggregator health check failed. \n\n\
+                The service may not be fully initialized.\n\
+                Error: {}",
+                e
+            )
+        })?;
         
         let health = health_response.into_inner();
         if !health.healthy {
@@ -65,59 +181,289 @@ impl PriceOracle {
         );
         
         Ok(Self {
-            cached_price: Arc::new(RwLock::new(None)),
+            cached_prices: Arc::new(RwLock::new(HashMap::new())),
             grpc_client: client,
             cache_duration: Duration::from_secs(10), // Cache for 10 seconds
+            version: Arc::new(AtomicU64::new(0)),
+            timeout,
+            outlier_filter: OutlierFilter::default(),
         })
     }
+
+    /// Check the aggregator's recent prices with `outlier_filter` instead of the defaults
+    pub fn with_outlier_filter(mut self, outlier_filter: OutlierFilter) -> Self {
+        self.outlier_filter = outlier_filter;
+        self
+    }
     
     pub async fn get_btc_price(&self) -> Result<f64, Box<dyn std::error::Error>> {
+        self.get_price(Asset::Btc).await
+    }
+
+    /// USD price of `asset`, cached per asset for `cache_duration`
+    pub async fn get_price(&self, asset: Asset) -> Result<f64, Box<dyn std::error::Error>> {
         // Check cache first
         {
-            let cache = self.cached_price.read().await;
-            if let Some((price, timestamp)) = *cache {
+            let cache = self.cached_prices.read().await;
+            if let Some((price, timestamp)) = cache.get(&asset) {
                 if timestamp.elapsed().unwrap_or(Duration::from_secs(u64::MAX)) < self.cache_duration {
-                    return Ok(price);
+                    return Ok(*price);
                 }
             }
         }
         
         // Fetch new price
-        let price = self.fetch_price_from_oracle().await?;
+        let price = self.get_checked_price(asset).await?.price;
         
         // Update cache
         {
-            let mut cache = self.cached_price.write().await;
-            *cache = Some((price, SystemTime::now()));
+            let mut cache = self.cached_prices.write().await;
+            cache.insert(asset, (price, SystemTime::now()));
         }
+        self.version.fetch_add(1, Ordering::SeqCst);
         
         Ok(price)
     }
-    
-    async fn fetch_price_from_oracle(&self) -> Result<f64, Box<dyn std::error::Error>> {
-        let response = self.get_detailed_price().await?;
-        Ok(response.aggregated_price)
+
+    /// Refresh counter, incremented each time a new price is fetched from the aggregator
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::SeqCst)
     }
     
-    /// Get detailed price information including individual exchange prices
+    /// Aggregated price of `asset` once outlying points are dropped
+    pub async fn get_checked_price(&self, asset: Asset) -> Result<CheckedPrice, Box<dyn std::error::Error>> {
+        let response = self.get_detailed_price_for(asset).await?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        Ok(self.outlier_filter.apply(&response, now)?)
+    }
+
+    /// Get detailed BTC price information including individual exchange prices
     pub async fn get_detailed_price(&self) -> Result<GetPriceResponse, Box<dyn std::error::Error>> {
+        self.get_detailed_price_for(Asset::Btc).await
+    }
+
+    /// Get detailed price information for one underlying
+    pub async fn get_detailed_price_for(&self, asset: Asset) -> Result<GetPriceResponse, Box<dyn std::error::Error>> {
+        self.get_detailed_price_from(asset, None).await
+    }
+
+    /// Get detailed price information for one underlying, aggregated over `source` only when set
+    pub async fn get_detailed_price_from(
+        &self,
+        asset: Asset,
+        source: Option<&str>,
+    ) -> Result<GetPriceResponse, Box<dyn std::error::Error>> {
         let mut client = self.grpc_client.clone();
         
-        let request = tonic::Request::new(GetPriceRequest {
-            source_filter: None, // No specific source filter
+        // BTC requests leave the asset unset so aggregators without asset support keep working
+        let request = request_id::grpc_request(GetPriceRequest {
+            source_filter: source.map(str::to_string),
+            asset: (asset != Asset::Btc).then(|| asset.to_string()),
         });
         
-        let response = client.get_aggregated_price(request).await?;
+        let response = within(self.timeout, async { Ok(client.get_aggregated_price(request).await?) }).await?;
         let price_data = response.into_inner();
         
         if !price_data.success {
             return Err("Failed to get aggregated price from oracle service".into());
         }
+
+        // An aggregator that ignores the asset filter answers with the BTC price
+        if asset != Asset::Btc && price_data.asset.as_deref() != Some(asset.to_string().as_str()) {
+            return Err(format!("Oracle aggregator does not provide {} prices", asset).into());
+        }
         
         if price_data.data_points == 0 {
-            return Err("No oracle sources available for price data".into());
+            return Err(match source {
+                Some(source) => format!("No oracle node reports {} prices", source).into(),
+                None => "No oracle sources available for price data".into(),
+            });
         }
         
         Ok(price_data)
     }
-}
\ No newline at end of file
+
+    /// Latest price of `asset` from each exchange feed behind the aggregate. All feeds come from
+    /// one request's recent prices; feeds named in `sources` are each requested through the
+    /// aggregator's source filter instead, so a feed that is down is reported with its error.
+    pub async fn get_source_prices(
+        &self,
+        asset: Asset,
+        sources: &[String],
+    ) -> Result<Vec<SourcePrice>, Box<dyn std::error::Error>> {
+        if sources.is_empty() {
+            return Ok(latest_by_source(&self.get_detailed_price_for(asset).await?));
+        }
+        // Errors as strings, as the boxed ones can't be held across the join
+        let responses = join_all(sources.iter().map(|source| async move {
+            self.get_detailed_price_from(asset, Some(source)).await.map_err(|e| e.to_string())
+        }))
+        .await;
+        Ok(sources
+            .iter()
+            .zip(responses)
+            .map(|(source, response)| match response {
+                Ok(response) => latest_by_source(&response)
+                    .into_iter()
+                    .find(|price| price.source.eq_ignore_ascii_case(source))
+                    // Aggregators that filter without returning the points behind the price
+                    .unwrap_or_else(|| SourcePrice {
+                        source: source.clone(),
+                        via: AGGREGATOR.to_string(),
+                        price: Some(response.aggregated_price),
+                        timestamp: Some(response.last_update as i64),
+                        error: None,
+                    }),
+                Err(e) => SourcePrice {
+                    source: source.clone(),
+                    via: AGGREGATOR.to_string(),
+                    price: None,
+                    timestamp: None,
+                    error: Some(e),
+                },
+            })
+            .collect())
+    }
+}
+
+/// `SourcePrice::via` of feeds reported by the aggregator's oracle nodes
+pub const AGGREGATOR: &str = "aggregator";
+
+// Newest point of each source among the response's recent prices, by source name
+fn latest_by_source(response: &GetPriceResponse) -> Vec<SourcePrice> {
+    let mut latest: BTreeMap<&str, &PriceDataPoint> = BTreeMap::new();
+    for point in &response.recent_prices {
+        let newest = latest.entry(point.source.as_str()).or_insert(point);
+        if point.timestamp > newest.timestamp {
+            *newest = point;
+        }
+    }
+    latest
+        .into_values()
+        .map(|point| SourcePrice {
+            source: point.source.clone(),
+            via: AGGREGATOR.to_string(),
+            price: Some(point.price),
+            timestamp: Some(point.timestamp as i64),
+            error: None,
+        })
+        .collect()
+}
+
+/// Event from the aggregator price stream
+#[derive(Debug, Clone)]
+pub enum StreamEvent {
+    Connected,
+    Price { price: f64, data_points: u32, timestamp: u64 },
+    Disconnected(String),
+}
+
+/// Subscribe to the aggregator's StreamPrices RPC in the background, reconnecting
+/// with backoff when the stream drops. Stops for good if the aggregator does not
+/// implement streaming, leaving callers on polling.
+pub fn start_price_stream<F>(aggregator_url: String, on_event: F)
+where
+    F: Fn(StreamEvent) + Send + Sync + 'static,
+{
+    tokio::spawn(async move {
+        let mut backoff = Duration::from_secs(1);
+        loop {
+            match run_price_stream(&aggregator_url, &on_event).await {
+                Err(status) if status.code() == tonic::Code::Unimplemented => {
+                    println!("ℹ️  Aggregator does not support price streaming, using polling");
+                    return;
+                }
+                Err(status) => on_event(StreamEvent::Disconnected(status.message().to_string())),
+                Ok(()) => {
+                    on_event(StreamEvent::Disconnected("stream closed by aggregator".to_string()));
+                    backoff = Duration::from_secs(1);
+                }
+            }
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(Duration::from_secs(60));
+        }
+    });
+}
+
+async fn run_price_stream<F>(aggregator_url: &str, on_event: &F) -> Result<(), tonic::Status>
+where
+    F: Fn(StreamEvent),
+{
+    let mut client = OracleServiceClient::connect(aggregator_url.to_string())
+        .await
+        .map_err(|e| tonic::Status::unavailable(e.to_string()))?;
+
+    // We only listen, so the outbound half never yields a PriceRequest
+    let mut stream = client
+        .stream_prices(futures::stream::pending::<PriceRequest>())
+        .await?
+        .into_inner();
+    on_event(StreamEvent::Connected);
+
+    while let Some(update) = stream.message().await? {
+        if update.data_points == 0 || !(update.aggregated_price.is_finite() && update.aggregated_price > 0.0) {
+            continue;
+        }
+        on_event(StreamEvent::Price {
+            price: update.aggregated_price,
+            data_points: update.data_points,
+            timestamp: update.timestamp,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(aggregated_price: f64, points: &[(f64, u64)]) -> GetPriceResponse {
+        GetPriceResponse {
+            success: true,
+            aggregated_price,
+            data_points: points.len() as u32,
+            last_update: 1_000,
+            recent_prices: points
+                .iter()
+                .enumerate()
+                .map(|(i, &(price, timestamp))| PriceDataPoint {
+                    price,
+                    timestamp,
+                    source: format!("exchange{}", i),
+                    node_id: "node1".to_string(),
+                })
+                .collect(),
+            asset: None,
+        }
+    }
+
+    #[test]
+    fn test_outlier_filter_drops_old_and_outlying_points() {
+        let filter = OutlierFilter::default();
+
+        // All points agree: the aggregator's price stands
+        let agreeing = response(100_050.0, &[(100_000.0, 990), (100_100.0, 995), (100_050.0, 1_000)]);
+        assert_eq!(
+            filter.apply(&agreeing, 1_000).unwrap(),
+            CheckedPrice { price: 100_050.0, used_points: 3, rejected_points: 0 }
+        );
+
+        // A stale point and a bad print are dropped and the price is the median of the rest
+        let noisy = response(
+            96_000.0,
+            &[(100_000.0, 990), (100_200.0, 995), (100_100.0, 1_000), (90_000.0, 600), (80_000.0, 1_000)],
+        );
+        assert_eq!(
+            filter.apply(&noisy, 1_000).unwrap(),
+            CheckedPrice { price: 100_100.0, used_points: 3, rejected_points: 2 }
+        );
+
+        // Nothing recent enough
+        assert!(filter.apply(&response(100_000.0, &[(100_000.0, 500)]), 1_000).is_err());
+        let unchecked = OutlierFilter { max_point_age: None, max_deviation_percent: None };
+        assert_eq!(unchecked.apply(&noisy, 1_000).unwrap().rejected_points, 0);
+
+        // No points to check
+        assert_eq!(filter.apply(&response(100_000.0, &[]), 1_000).unwrap().price, 100_000.0);
+    }
+}
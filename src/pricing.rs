@@ -0,0 +1,139 @@
+use crate::day_count;
+use crate::models::OptionSide;
+use serde::Serialize;
+
+/// Black-Scholes sensitivities of one option held long.
+/// Vega is per 1 vol point (0.01) and theta per day of the DAY_COUNT convention
+/// (a calendar day under ACT/365, a trading day under ACT/252).
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct Greeks {
+    pub delta: f64,
+    pub gamma: f64,
+    pub vega: f64,
+    pub theta: f64,
+}
+
+impl Greeks {
+    /// Greeks of `quantity` options
+    pub fn scaled(&self, quantity: f64) -> Greeks {
+        Greeks {
+            delta: self.delta * quantity,
+            gamma: self.gamma * quantity,
+            vega: self.vega * quantity,
+            theta: self.theta * quantity,
+        }
+    }
+}
+
+/// Black-Scholes value of one option in USD.
+/// Falls back to intrinsic value at or past expiry, or with non-positive vol.
+pub fn option_price(side: &OptionSide, spot: f64, strike: f64, r: f64, iv: f64, t: f64) -> f64 {
+    if t <= 0.0 || iv <= 0.0 {
+        return match side {
+            OptionSide::Call => (spot - strike).max(0.0),
+            OptionSide::Put => (strike - spot).max(0.0),
+        };
+    }
+    match side {
+        OptionSide::Call => black_scholes::call(spot, strike, r, iv, t),
+        OptionSide::Put => black_scholes::put(spot, strike, r, iv, t),
+    }
+}
+
+/// Spot that prices an option as Black-76 on the forward `spot`·e^(`carry`·t) when passed to
+/// the Black-Scholes functions with rate `r`: the present value of that forward. Equal to
+/// `spot` when the carry is the risk-free rate.
+pub fn forward_spot(spot: f64, r: f64, carry: f64, t: f64) -> f64 {
+    spot * ((carry - r) * t.max(0.0)).exp()
+}
+
+/// Black-Scholes delta of one option
+pub fn option_delta(side: &OptionSide, spot: f64, strike: f64, r: f64, iv: f64, t: f64) -> f64 {
+    match side {
+        OptionSide::Call => black_scholes::call_delta(spot, strike, r, iv, t),
+        OptionSide::Put => black_scholes::put_delta(spot, strike, r, iv, t),
+    }
+}
+
+/// Black-Scholes Greeks of one option. At or past expiry, or with non-positive vol,
+/// only delta is set (1 / -1 in the money, 0 otherwise).
+pub fn option_greeks(side: &OptionSide, spot: f64, strike: f64, r: f64, iv: f64, t: f64) -> Greeks {
+    if t <= 0.0 || iv <= 0.0 {
+        let delta = match side {
+            OptionSide::Call if spot > strike => 1.0,
+            OptionSide::Put if spot < strike => -1.0,
+            _ => 0.0,
+        };
+        return Greeks { delta, ..Greeks::default() };
+    }
+    let (gamma, vega, theta) = match side {
+        OptionSide::Call => (
+            black_scholes::call_gamma(spot, strike, r, iv, t),
+            black_scholes::call_vega(spot, strike, r, iv, t),
+            black_scholes::call_theta(spot, strike, r, iv, t),
+        ),
+        OptionSide::Put => (
+            black_scholes::put_gamma(spot, strike, r, iv, t),
+            black_scholes::put_vega(spot, strike, r, iv, t),
+            black_scholes::put_theta(spot, strike, r, iv, t),
+        ),
+    };
+    Greeks {
+        delta: option_delta(side, spot, strike, r, iv, t),
+        gamma,
+        vega: vega / 100.0,
+        theta: theta / day_count::deployment().days_per_year(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_option_price_at_expiry_is_intrinsic() {
+        assert_eq!(option_price(&OptionSide::Call, 110000.0, 100000.0, 0.0, 0.5, 0.0), 10000.0);
+        assert_eq!(option_price(&OptionSide::Put, 110000.0, 100000.0, 0.0, 0.5, -0.01), 0.0);
+        assert_eq!(option_price(&OptionSide::Put, 90000.0, 100000.0, 0.0, 0.5, 0.0), 10000.0);
+    }
+
+    #[test]
+    fn test_put_call_parity() {
+        let (s, k, r, iv, t) = (100000.0, 105000.0, 0.05, 0.6, 30.0 / 365.0);
+        let call = option_price(&OptionSide::Call, s, k, r, iv, t);
+        let put = option_price(&OptionSide::Put, s, k, r, iv, t);
+        let parity = s - k * (-r * t).exp();
+        assert!((call - put - parity).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_forward_spot_prices_on_the_forward() {
+        let (s, k, r, carry, iv, t) = (100000.0, 105000.0, 0.05, 0.12f64, 0.6, 90.0 / 365.0);
+        let forward = s * (carry * t).exp();
+        let spot = forward_spot(s, r, carry, t);
+        let call = option_price(&OptionSide::Call, spot, k, r, iv, t);
+        let put = option_price(&OptionSide::Put, spot, k, r, iv, t);
+        // Black-76 parity: C - P = e^(-rT)(F - K)
+        assert!((call - put - (-r * t).exp() * (forward - k)).abs() < 1e-6);
+        assert_eq!(forward_spot(s, r, r, t), s);
+        assert_eq!(forward_spot(s, r, carry, 0.0), s);
+    }
+
+    #[test]
+    fn test_greeks() {
+        let (s, k, r, iv, t) = (100000.0, 100000.0, 0.0, 0.5, 30.0 / 365.0);
+        let call = option_greeks(&OptionSide::Call, s, k, r, iv, t);
+        let put = option_greeks(&OptionSide::Put, s, k, r, iv, t);
+        assert!((call.delta - put.delta - 1.0).abs() < 1e-9);
+        assert!((call.gamma - put.gamma).abs() < 1e-12);
+        assert!(call.vega > 0.0 && call.theta < 0.0);
+
+        // Vega per vol point matches a bump of the price
+        let bumped = option_price(&OptionSide::Call, s, k, r, iv + 0.01, t) - option_price(&OptionSide::Call, s, k, r, iv, t);
+        assert!((bumped - call.vega).abs() / call.vega < 0.01);
+
+        let expired = option_greeks(&OptionSide::Put, 90000.0, k, r, iv, 0.0);
+        assert_eq!(expired, Greeks { delta: -1.0, ..Greeks::default() });
+        assert_eq!(expired.scaled(2.0).delta, -2.0);
+    }
+}
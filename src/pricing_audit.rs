@@ -0,0 +1,207 @@
+// Pricing audit of past trades.
+// GET /contract/{id}/pricing-audit replays a trade through Black-Scholes with the spot, IV and
+// time to expiry recorded when it was executed, and compares the premium the buyer paid with
+// the model premium. Trades further than PRICING_AUDIT_THRESHOLD_PERCENT from fair value are
+// flagged, which catches fat-fingered quotes and manipulated inputs. The risk-free rate is not
+// recorded with trades, so the rate the current curve gives the trade's time to expiry is used.
+
+use crate::error::{ApiError, ApiResult};
+use crate::models::{Asset, ContractRecord, IvProvenance, OptionSide};
+use crate::pricing;
+use crate::rates::RateCurve;
+use crate::utils::year_fraction;
+use serde::Serialize;
+use std::env;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PricingAuditConfig {
+    pub threshold_percent: f64,  // Deviation from the model premium at which a trade is flagged
+}
+
+impl Default for PricingAuditConfig {
+    fn default() -> Self {
+        Self { threshold_percent: 20.0 }
+    }
+}
+
+impl PricingAuditConfig {
+    /// PRICING_AUDIT_THRESHOLD_PERCENT (20)
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            threshold_percent: env::var("PRICING_AUDIT_THRESHOLD_PERCENT")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|percent: &f64| percent.is_finite() && *percent > 0.0)
+                .unwrap_or(defaults.threshold_percent),
+        }
+    }
+}
+
+// Which side of fair value a trade was executed on
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FairValueSide {
+    Above,  // Buyer paid more than the model premium
+    Below,
+    AtFairValue,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct PricingAudit {
+    pub contract_id: i64,
+    pub underlying: Asset,
+    pub side: OptionSide,
+    pub strike_price: f64,
+    pub quantity: f64,
+    pub expires: i64,
+    pub created_at: i64,
+    pub spot_at_trade: f64,
+    pub iv_at_trade: f64,
+    pub iv_source: Option<IvProvenance>,
+    pub btc_price_at_trade: f64,
+    pub time_to_expiry_years: f64,          // From the trade to expiry
+    pub risk_free_rate: f64,
+    pub premium: f64,                       // Paid, in BTC per unit
+    pub model_premium_usd: f64,             // Replayed Black-Scholes value of one option
+    pub model_premium: f64,                 // In BTC per unit
+    pub recorded_mark_premium: Option<f64>, // Model premium stored at trade time, in BTC per unit
+    pub deviation: f64,                     // premium - model_premium, in BTC per unit
+    pub deviation_percent: f64,             // Of model_premium
+    pub deviation_usd: f64,                 // Of the whole contract
+    pub fair_value_side: FairValueSide,
+    pub threshold_percent: f64,
+    pub flagged: bool,
+}
+
+/// Replay the pricing of `contract` from its trade-time snapshot. Fails for contracts traded
+/// before snapshots were recorded.
+pub fn audit(contract: &ContractRecord, rates: &RateCurve, config: &PricingAuditConfig) -> ApiResult<PricingAudit> {
+    let (Some(spot_at_trade), Some(iv_at_trade)) = (contract.spot_at_trade, contract.iv_at_trade) else {
+        return Err(ApiError::ValidationError(format!(
+            "Contract {} has no trade-time market snapshot to replay",
+            contract.id
+        )));
+    };
+    // BTC contracts recorded their spot, which is the BTC price
+    let btc_price_at_trade = match (contract.underlying, contract.trade_btc_price) {
+        (_, Some(btc_price)) => btc_price,
+        (Asset::Btc, None) => spot_at_trade,
+        (_, None) => {
+            return Err(ApiError::ValidationError(format!(
+                "Contract {} has no BTC price recorded at trade time",
+                contract.id
+            )))
+        }
+    };
+
+    let t = year_fraction(contract.expires, contract.created_at);
+    let risk_free_rate = rates.rate_to(contract.expires, contract.created_at);
+    let model_premium_usd =
+        pricing::option_price(&contract.side, spot_at_trade, contract.strike_price, risk_free_rate, iv_at_trade, t);
+    let model_premium = model_premium_usd / btc_price_at_trade;
+    let deviation = contract.premium - model_premium;
+    // A worthless option traded at any price is as far from fair value as it gets
+    let deviation_percent = if model_premium > 0.0 {
+        deviation / model_premium * 100.0
+    } else if deviation == 0.0 {
+        0.0
+    } else {
+        f64::INFINITY.copysign(deviation)
+    };
+    let fair_value_side = if deviation > 0.0 {
+        FairValueSide::Above
+    } else if deviation < 0.0 {
+        FairValueSide::Below
+    } else {
+        FairValueSide::AtFairValue
+    };
+
+    Ok(PricingAudit {
+        contract_id: contract.id,
+        underlying: contract.underlying,
+        side: contract.side,
+        strike_price: contract.strike_price,
+        quantity: contract.quantity,
+        expires: contract.expires,
+        created_at: contract.created_at,
+        spot_at_trade,
+        iv_at_trade,
+        iv_source: contract.iv_source,
+        btc_price_at_trade,
+        time_to_expiry_years: t,
+        risk_free_rate,
+        premium: contract.premium,
+        model_premium_usd,
+        model_premium,
+        recorded_mark_premium: contract.mark_premium_at_trade,
+        deviation,
+        deviation_percent,
+        deviation_usd: deviation * btc_price_at_trade * contract.quantity,
+        fair_value_side,
+        threshold_percent: config.threshold_percent,
+        flagged: deviation_percent.abs() > config.threshold_percent,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{ContractStatus, ExerciseStyle, QuoteCurrency};
+
+    fn record(premium: f64) -> ContractRecord {
+        ContractRecord {
+            id: 7,
+            underlying: Asset::Btc,
+            side: OptionSide::Call,
+            strike_price: 100_000.0,
+            quantity: 2.0,
+            closed_quantity: 0.0,
+            expires: 30 * 86_400,
+            premium,
+            premium_currency: QuoteCurrency::Btc,
+            quoted_premium: None,
+            trade_btc_price: None,
+            created_at: 0,
+            status: ContractStatus::Open,
+            settlement_price: None,
+            settlement_btc_price: None,
+            settled_at: None,
+            exercise_style: ExerciseStyle::European,
+            counterparty: None,
+            spot_at_trade: Some(100_000.0),
+            iv_at_trade: Some(0.5),
+            iv_source: Some(IvProvenance::Deribit),
+            mark_premium_at_trade: None,
+            pool_id: 1,
+        }
+    }
+
+    #[test]
+    fn test_audit_flags_trades_far_from_fair_value() {
+        let config = PricingAuditConfig::default();
+        let fair = audit(&record(0.0), &RateCurve::default(), &config).unwrap().model_premium;
+        assert!(fair > 0.0);
+
+        let at_fair = audit(&record(fair), &RateCurve::default(), &config).unwrap();
+        assert_eq!((at_fair.fair_value_side, at_fair.flagged), (FairValueSide::AtFairValue, false));
+        assert_eq!(at_fair.btc_price_at_trade, 100_000.0);
+
+        let within = audit(&record(fair * 1.125), &RateCurve::default(), &config).unwrap();
+        assert_eq!((within.fair_value_side, within.flagged), (FairValueSide::Above, false));
+        assert!((within.deviation_percent - 12.5).abs() < 1e-9);
+        assert!((within.deviation_usd - fair * 0.125 * 100_000.0 * 2.0).abs() < 1e-6);
+
+        let fat_finger = audit(&record(fair * 0.5), &RateCurve::default(), &config).unwrap();
+        assert_eq!((fat_finger.fair_value_side, fat_finger.flagged), (FairValueSide::Below, true));
+        assert!((fat_finger.deviation_percent + 50.0).abs() < 1e-9);
+
+        // Not replayable without the market it traded in
+        let mut old = record(fair);
+        old.iv_at_trade = None;
+        assert!(audit(&old, &RateCurve::default(), &config).is_err());
+        let mut eth = record(fair);
+        eth.underlying = Asset::Eth;
+        assert!(audit(&eth, &RateCurve::default(), &config).is_err());
+    }
+}
@@ -0,0 +1,259 @@
+// Arbitrage sanity checks of the quoted grid.
+// IV data can be patchy: a product missing from the surface is priced at the default IV, and the
+// call and put of one strike may be read off points of different age. Quotes built on such data
+// can hand buyers free money. Each time the grid is priced, for the options table and the
+// orderbook alike, the quotes are checked for:
+// - asks below intrinsic value: S - K·e^(-rT) for calls, K·e^(-rT) - S for puts, r the rate of
+//   the expiry on the risk-free curve and S, under a pricing basis, the forward's present value
+// - put-call parity breaches: the call bought at its ask and the put sold at its bid, or the
+//   put bought and the call sold, for less than the forward value S - K·e^(-rT)
+// - negative calendar spreads: an ask below the ask of the same side and strike at an earlier
+//   expiry
+// With QUOTE_SANITY=adjust (the default) offending asks are raised just enough to remove the
+// arbitrage and the rows are flagged; `flag` only flags them and `off` skips the checks. Asks
+// only ever go up, and every check is a floor on asks, so one pass in this order fixes all.
+
+use crate::models::OptionSide;
+use crate::pricing::forward_spot;
+use crate::quoting::Quote;
+use crate::rates::RateCurve;
+use crate::utils::year_fraction;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::sync::Arc;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SanityMode {
+    Off,
+    Flag,
+    #[default]
+    Adjust,
+}
+
+impl SanityMode {
+    /// QUOTE_SANITY: off, flag or adjust (default)
+    pub fn from_env() -> Self {
+        match env::var("QUOTE_SANITY").ok().as_deref().map(str::trim) {
+            Some("off") => SanityMode::Off,
+            Some("flag") => SanityMode::Flag,
+            _ => SanityMode::Adjust,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Violation {
+    BelowIntrinsic,
+    ParityBreach,
+    NegativeCalendarSpread,
+}
+
+/// One quoted option of the grid
+#[derive(Clone, Debug, PartialEq)]
+pub struct QuotedOption {
+    pub side: OptionSide,
+    pub strike_price: f64,
+    pub expires: i64,
+    pub quote: Quote,
+    pub violations: Vec<Violation>,
+}
+
+/// Market the grid was quoted in
+#[derive(Clone, Debug)]
+pub struct Market {
+    pub spot_price: f64,      // USD per unit of the underlying
+    pub rates: Arc<RateCurve>,
+    pub carry: Option<Arc<RateCurve>>,  // Basis the grid was priced with (see basis.rs)
+    pub scale: f64,           // Quote units per USD, e.g. 1 / BTC price for BTC premiums
+    pub now: i64,
+}
+
+impl Market {
+    // Present value of the forward, S - K·e^(-rT), in quote units
+    fn forward(&self, strike_price: f64, expires: i64) -> f64 {
+        let rate = self.rates.rate_to(expires, self.now);
+        let t = year_fraction(expires, self.now).max(0.0);
+        let spot_price = match &self.carry {
+            Some(carry) => forward_spot(self.spot_price, rate, carry.rate_to(expires, self.now), t),
+            None => self.spot_price,
+        };
+        (spot_price - strike_price * (-rate * t).exp()) * self.scale
+    }
+
+    // Below this, differences are float noise
+    fn tolerance(&self) -> f64 {
+        self.spot_price * self.scale * 1e-9
+    }
+}
+
+/// Check `options` for arbitrage in `market`, flagging offending rows and, in adjust mode,
+/// raising their asks; see the module comment
+pub fn check(mode: SanityMode, options: &mut [QuotedOption], market: &Market) {
+    if mode == SanityMode::Off {
+        return;
+    }
+    let adjust = mode == SanityMode::Adjust;
+    let tolerance = market.tolerance();
+
+    for option in options.iter_mut() {
+        let forward = market.forward(option.strike_price, option.expires);
+        let intrinsic = match option.side {
+            OptionSide::Call => forward.max(0.0),
+            OptionSide::Put => (-forward).max(0.0),
+        };
+        if option.quote.ask < intrinsic - tolerance {
+            option.violations.push(Violation::BelowIntrinsic);
+            if adjust {
+                option.quote.ask = intrinsic;
+            }
+        }
+    }
+
+    // Calls and puts of each strike and expiry
+    let mut pairs: BTreeMap<(i64, i64), (Option<usize>, Option<usize>)> = BTreeMap::new();
+    for (i, option) in options.iter().enumerate() {
+        let pair = pairs.entry((option.expires, (option.strike_price * 100.0).round() as i64)).or_default();
+        match option.side {
+            OptionSide::Call => pair.0 = Some(i),
+            OptionSide::Put => pair.1 = Some(i),
+        }
+    }
+    for (call, put) in pairs.into_values() {
+        let (Some(call), Some(put)) = (call, put) else { continue };
+        let forward = market.forward(options[call].strike_price, options[call].expires);
+        // A synthetic long forward bought from the pool, then a synthetic short
+        let long_shortfall = forward - (options[call].quote.ask - options[put].quote.bid);
+        let short_shortfall = -forward - (options[put].quote.ask - options[call].quote.bid);
+        for (shortfall, raised) in [(long_shortfall, call), (short_shortfall, put)] {
+            if shortfall > tolerance {
+                for i in [call, put] {
+                    if !options[i].violations.contains(&Violation::ParityBreach) {
+                        options[i].violations.push(Violation::ParityBreach);
+                    }
+                }
+                if adjust {
+                    options[raised].quote.ask += shortfall;
+                }
+            }
+        }
+    }
+
+    // Each side and strike through its expiries
+    let mut series: BTreeMap<(bool, i64), Vec<usize>> = BTreeMap::new();
+    for (i, option) in options.iter().enumerate() {
+        let key = (option.side == OptionSide::Call, (option.strike_price * 100.0).round() as i64);
+        series.entry(key).or_default().push(i);
+    }
+    for mut indices in series.into_values() {
+        indices.sort_by_key(|&i| options[i].expires);
+        let mut floor = f64::NEG_INFINITY;
+        for i in indices {
+            if options[i].quote.ask < floor - tolerance {
+                options[i].violations.push(Violation::NegativeCalendarSpread);
+                if adjust {
+                    options[i].quote.ask = floor;
+                }
+            }
+            floor = floor.max(options[i].quote.ask);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_735_689_600;
+    const DAY: i64 = 86_400;
+
+    fn option(side: OptionSide, strike_price: f64, expires: i64, bid: f64, ask: f64) -> QuotedOption {
+        QuotedOption {
+            side,
+            strike_price,
+            expires,
+            quote: Quote { bid, mid: (bid + ask) / 2.0, ask },
+            violations: Vec::new(),
+        }
+    }
+
+    fn market() -> Market {
+        Market { spot_price: 100_000.0, rates: Arc::default(), carry: None, scale: 1.0, now: NOW }
+    }
+
+    #[test]
+    fn test_asks_below_intrinsic_are_raised() {
+        let mut options = vec![
+            option(OptionSide::Call, 90_000.0, NOW + DAY, 9_000.0, 9_500.0),
+            option(OptionSide::Put, 120_000.0, NOW + 7 * DAY, 20_500.0, 21_000.0),
+        ];
+        check(SanityMode::Adjust, &mut options, &market());
+        assert_eq!(options[0].quote.ask, 10_000.0);
+        assert_eq!(options[0].violations, vec![Violation::BelowIntrinsic]);
+        assert_eq!(options[1].quote.ask, 21_000.0);
+        assert!(options[1].violations.is_empty());
+    }
+
+    #[test]
+    fn test_parity_breaches() {
+        // At the money the forward is worth 0, so the call must cost at least the put's bid
+        let mut options = vec![
+            option(OptionSide::Call, 100_000.0, NOW + DAY, 1_000.0, 1_100.0),
+            option(OptionSide::Put, 100_000.0, NOW + DAY, 1_500.0, 1_600.0),
+        ];
+        let mut flagged = options.clone();
+        check(SanityMode::Flag, &mut flagged, &market());
+        assert_eq!(flagged[0].quote.ask, 1_100.0);
+        assert_eq!(flagged[0].violations, vec![Violation::ParityBreach]);
+        assert_eq!(flagged[1].violations, vec![Violation::ParityBreach]);
+
+        check(SanityMode::Adjust, &mut options, &market());
+        assert_eq!(options[0].quote.ask, 1_500.0);
+        assert_eq!(options[1].quote.ask, 1_600.0);
+
+        let mut off = flagged.clone();
+        off.iter_mut().for_each(|o| o.violations.clear());
+        check(SanityMode::Off, &mut off, &market());
+        assert!(off.iter().all(|o| o.violations.is_empty()));
+    }
+
+    #[test]
+    fn test_intrinsic_on_the_forward_under_a_basis() {
+        // With a 10% carry the 1y forward is 110,517, so a 105,000 call is in the money
+        let carry = Market { carry: Some(Arc::new(RateCurve::flat(0.1))), ..market() };
+        let mut options = vec![option(OptionSide::Call, 105_000.0, NOW + 365 * DAY, 4_000.0, 5_000.0)];
+        check(SanityMode::Adjust, &mut options, &market());
+        assert!(options[0].violations.is_empty());
+        check(SanityMode::Adjust, &mut options, &carry);
+        assert_eq!(options[0].violations, vec![Violation::BelowIntrinsic]);
+        assert!((options[0].quote.ask - 100_000.0 * (0.1f64.exp() - 1.05)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_negative_calendar_spreads() {
+        let mut options = vec![
+            option(OptionSide::Call, 110_000.0, NOW + 7 * DAY, 700.0, 800.0),
+            option(OptionSide::Call, 110_000.0, NOW + DAY, 300.0, 900.0),
+            option(OptionSide::Call, 110_000.0, NOW + 30 * DAY, 2_000.0, 2_100.0),
+        ];
+        check(SanityMode::Adjust, &mut options, &market());
+        assert_eq!(options[0].quote.ask, 900.0);
+        assert_eq!(options[0].violations, vec![Violation::NegativeCalendarSpread]);
+        assert!(options[1].violations.is_empty() && options[2].violations.is_empty());
+    }
+
+    #[test]
+    fn test_consistent_quotes_pass() {
+        let mut options = vec![
+            option(OptionSide::Call, 100_000.0, NOW + DAY, 1_000.0, 1_100.0),
+            option(OptionSide::Put, 100_000.0, NOW + DAY, 1_000.0, 1_100.0),
+            option(OptionSide::Call, 100_000.0, NOW + 7 * DAY, 2_500.0, 2_700.0),
+            option(OptionSide::Put, 100_000.0, NOW + 7 * DAY, 2_500.0, 2_700.0),
+        ];
+        let before = options.clone();
+        check(SanityMode::Adjust, &mut options, &market());
+        assert_eq!(options, before);
+    }
+}
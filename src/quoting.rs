@@ -0,0 +1,236 @@
+// Quoting: the pool's edge over the Black-Scholes mid.
+// The options table and the orderbook quote an ask (what buyers pay) and a bid around the
+// model mid. Each side is moved away from the mid by half of QUOTE_SPREAD_PERCENT, by a vega
+// markup worth QUOTE_VEGA_MARKUP_VOL_POINTS of implied vol and by a delta markup of
+// QUOTE_DELTA_MARKUP_BPS of the option's delta notional, so options carrying more risk are
+// quoted wider. On top, the ask is skewed up by QUOTE_INVENTORY_SKEW_PERCENT of the mid per
+// unit of collateral already sold on the same underlying and side, so the pool quotes wider
+// on the side it is already heavy. All markups default to 0, which quotes the mid.
+//
+// Before the markups, the mid itself is shaded with the open book: raised by
+// QUOTE_CONCENTRATION_PERCENT per unit of collateral the pool has already sold at the same
+// strike and side, and discounted by up to QUOTE_HEDGE_DISCOUNT_PERCENT for options whose sale
+// offsets the book's net delta. The book is read from the positions aggregation each time the
+// grid is priced, so quotes move as soon as a contract is written.
+//
+// The quoted grid is then checked for arbitrage, QUOTE_SANITY (see quote_sanity.rs).
+
+use crate::models::{Asset, OptionSide};
+use crate::pricing::Greeks;
+use crate::quote_sanity::SanityMode;
+use crate::risk_manager::Position;
+use crate::utils::usd_to_cents;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::env;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct QuotingConfig {
+    pub spread_percent: f64,          // Bid to ask, as % of the mid
+    pub vega_markup_vol_points: f64,  // Each side, in vol points of vega
+    pub delta_markup_bps: f64,        // Each side, in bps of |delta| x spot
+    pub inventory_skew_percent: f64,  // Ask markup as % of the mid at full utilization
+    pub concentration_percent: f64,   // Mid markup as % per unit of collateral sold at the strike
+    pub hedge_discount_percent: f64,  // Largest mid discount for options offsetting the book delta
+    pub sanity: SanityMode,           // What to do about arbitrage in the quoted grid
+}
+
+impl QuotingConfig {
+    /// QUOTE_SPREAD_PERCENT, QUOTE_VEGA_MARKUP_VOL_POINTS, QUOTE_DELTA_MARKUP_BPS,
+    /// QUOTE_INVENTORY_SKEW_PERCENT, QUOTE_CONCENTRATION_PERCENT and QUOTE_HEDGE_DISCOUNT_PERCENT,
+    /// all 0 by default. Negative values are ignored. QUOTE_SANITY as in SanityMode::from_env.
+    pub fn from_env() -> Self {
+        let parse = |name: &str| {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.parse::<f64>().ok())
+                .filter(|v| v.is_finite() && *v >= 0.0)
+                .unwrap_or(0.0)
+        };
+        Self {
+            spread_percent: parse("QUOTE_SPREAD_PERCENT"),
+            vega_markup_vol_points: parse("QUOTE_VEGA_MARKUP_VOL_POINTS"),
+            delta_markup_bps: parse("QUOTE_DELTA_MARKUP_BPS"),
+            inventory_skew_percent: parse("QUOTE_INVENTORY_SKEW_PERCENT"),
+            concentration_percent: parse("QUOTE_CONCENTRATION_PERCENT"),
+            hedge_discount_percent: parse("QUOTE_HEDGE_DISCOUNT_PERCENT"),
+            sanity: SanityMode::from_env(),
+        }
+    }
+
+    /// Factor the mid of `side` at `strike` with `delta` is shaded by given the open `book`
+    pub fn shade(&self, book: &BookExposure, side: OptionSide, strike: f64, delta: f64) -> f64 {
+        let markup = self.concentration_percent / 100.0 * book.strike_utilization(side, strike);
+        let discount = self.hedge_discount_percent / 100.0 * book.hedge(delta);
+        (1.0 + markup - discount).max(0.0)
+    }
+
+    /// Quote around the model `mid` (USD per unit) of `side` at `strike` with `greeks`, given
+    /// the pool's open `book` on its underlying. The returned mid is the unshaded model value.
+    pub fn quote(&self, mid: f64, side: OptionSide, strike: f64, greeks: &Greeks, book: &BookExposure) -> Quote {
+        let shaded = mid * self.shade(book, side, strike, greeks.delta);
+        let half_spread = shaded * self.spread_percent / 200.0;
+        let vega_markup = greeks.vega.abs() * self.vega_markup_vol_points;
+        let delta_markup = greeks.delta.abs() * book.spot * self.delta_markup_bps / 10_000.0;
+        let markup = half_spread + vega_markup + delta_markup;
+        let skew = shaded * self.inventory_skew_percent / 100.0 * book.side_utilization(side);
+        Quote {
+            bid: (shaded - markup).max(0.0),
+            mid,
+            ask: shaded + markup + skew,
+        }
+    }
+}
+
+/// Bid, mid and ask of one option, in the unit of the mid they were quoted from
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Quote {
+    pub bid: f64,
+    pub mid: f64,
+    pub ask: f64,
+}
+
+impl Quote {
+    pub fn scaled(&self, factor: f64) -> Quote {
+        Quote { bid: self.bid * factor, mid: self.mid * factor, ask: self.ask * factor }
+    }
+}
+
+/// The pool's open book on one underlying, as the quoting engine sees it
+#[derive(Debug, Clone, Default)]
+pub struct BookExposure {
+    spot: f64,
+    collateral_usd: f64,
+    sides: HashMap<OptionSide, f64>,          // Net quantity sold per side
+    strikes: HashMap<(OptionSide, i64), f64>, // Net quantity sold per side and strike in cents
+    delta: f64,                               // The pool's net delta, in units of the underlying
+}
+
+impl BookExposure {
+    /// Exposure of the `positions` on `underlying` at `spot`, against `collateral_usd`.
+    /// `delta` gives the delta of one unit of a position held long.
+    pub fn new(
+        positions: &[Position],
+        underlying: Asset,
+        spot: f64,
+        collateral_usd: f64,
+        delta: impl Fn(&Position) -> f64,
+    ) -> Self {
+        let mut book = Self { spot, collateral_usd, ..Self::default() };
+        for position in positions.iter().filter(|p| p.underlying == underlying) {
+            *book.sides.entry(position.side).or_default() += position.net_quantity;
+            *book.strikes.entry((position.side, usd_to_cents(position.strike_price))).or_default() += position.net_quantity;
+            // The pool is short what it has sold
+            book.delta -= position.net_quantity * delta(position);
+        }
+        book
+    }
+
+    // Notional `quantity` at spot as a share of the collateral
+    fn utilization(&self, quantity: f64) -> f64 {
+        match self.collateral_usd > 0.0 {
+            true => (quantity * self.spot / self.collateral_usd).max(0.0),
+            false => 0.0,
+        }
+    }
+
+    /// Notional sold on `side`, as a share of the collateral
+    pub fn side_utilization(&self, side: OptionSide) -> f64 {
+        self.utilization(self.sides.get(&side).copied().unwrap_or(0.0))
+    }
+
+    /// Notional sold on `side` at `strike` across expiries, as a share of the collateral
+    pub fn strike_utilization(&self, side: OptionSide, strike: f64) -> f64 {
+        self.utilization(self.strikes.get(&(side, usd_to_cents(strike))).copied().unwrap_or(0.0))
+    }
+
+    /// How much selling an option with `delta` hedges the book, from 0 to 1: its |delta| times
+    /// the book's delta notional as a share of the collateral (capped at 1), when selling it
+    /// moves the book's delta towards zero
+    pub fn hedge(&self, delta: f64) -> f64 {
+        // Selling one unit moves the pool's delta by -delta
+        if self.delta * delta <= 0.0 {
+            return 0.0;
+        }
+        delta.abs().min(1.0) * self.utilization(self.delta.abs()).min(1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn greeks(delta: f64, vega: f64) -> Greeks {
+        Greeks { delta, vega, ..Greeks::default() }
+    }
+
+    fn position(underlying: Asset, side: OptionSide, strike_price: f64, expires: i64, net_quantity: f64) -> Position {
+        Position { underlying, side, strike_price, expires, net_quantity, average_premium: 0.0, contract_count: 1 }
+    }
+
+    // 0.5 BTC of 100k calls over two expiries and 0.1 BTC of 100k puts, against $400k of
+    // collateral. Calls have delta 0.5 and puts -0.5, so the pool is short 0.2 BTC of delta.
+    fn book(collateral_usd: f64) -> BookExposure {
+        let positions = [
+            position(Asset::Btc, OptionSide::Call, 100_000.0, 1, 0.25),
+            position(Asset::Btc, OptionSide::Call, 100_000.0, 2, 0.25),
+            position(Asset::Btc, OptionSide::Put, 100_000.0, 1, 0.1),
+            position(Asset::Eth, OptionSide::Call, 4_000.0, 1, 10.0),
+        ];
+        let delta = |p: &Position| match p.side {
+            OptionSide::Call => 0.5,
+            OptionSide::Put => -0.5,
+        };
+        BookExposure::new(&positions, Asset::Btc, 100_000.0, collateral_usd, delta)
+    }
+
+    #[test]
+    fn test_quote_markups() {
+        let g = greeks(-0.5, 40.0);
+        let (empty, put) = (BookExposure::new(&[], Asset::Btc, 100_000.0, 400_000.0, |_| 0.0), OptionSide::Put);
+        let quote = |config: &QuotingConfig, mid: f64, book: &BookExposure| config.quote(mid, put, 100_000.0, &g, book);
+        assert_eq!(quote(&QuotingConfig::default(), 1000.0, &book(400_000.0)), Quote { bid: 1000.0, mid: 1000.0, ask: 1000.0 });
+
+        let spread = QuotingConfig { spread_percent: 4.0, ..Default::default() };
+        assert_eq!(quote(&spread, 1000.0, &empty), Quote { bid: 980.0, mid: 1000.0, ask: 1020.0 });
+
+        // 1 vol point of 40 USD vega, and 1 bp of 50k delta notional
+        let greek_markups = QuotingConfig { vega_markup_vol_points: 1.0, delta_markup_bps: 1.0, ..Default::default() };
+        assert_eq!(quote(&greek_markups, 1000.0, &empty), Quote { bid: 955.0, mid: 1000.0, ask: 1045.0 });
+        // The bid never goes below zero
+        assert_eq!(quote(&greek_markups, 10.0, &empty).bid, 0.0);
+
+        // Only the ask moves with inventory: 0.1 BTC of puts is 2.5% of the collateral
+        let skew = QuotingConfig { inventory_skew_percent: 100.0, ..Default::default() };
+        assert_eq!(quote(&skew, 1000.0, &book(400_000.0)), Quote { bid: 1000.0, mid: 1000.0, ask: 1025.0 });
+    }
+
+    #[test]
+    fn test_book_exposure() {
+        let book = book(400_000.0);
+        assert_eq!(book.side_utilization(OptionSide::Call), 0.125);
+        assert_eq!(book.side_utilization(OptionSide::Put), 0.025);
+        assert_eq!(book.strike_utilization(OptionSide::Call, 100_000.0), 0.125);
+        assert_eq!(book.strike_utilization(OptionSide::Call, 105_000.0), 0.0);
+        assert_eq!(BookExposure::default().side_utilization(OptionSide::Call), 0.0);
+
+        // Selling puts (delta -0.5) buys back delta the short book lacks; selling calls adds to it
+        assert!((book.hedge(-0.5) - 0.5 * 0.05).abs() < 1e-12);
+        assert_eq!(book.hedge(0.5), 0.0);
+    }
+
+    #[test]
+    fn test_shade_with_the_book() {
+        let config = QuotingConfig { concentration_percent: 100.0, hedge_discount_percent: 100.0, ..Default::default() };
+        let book = book(400_000.0);
+        // Calls at the concentrated strike are marked up, calls elsewhere are not
+        assert!((config.shade(&book, OptionSide::Call, 100_000.0, 0.5) - 1.125).abs() < 1e-12);
+        assert_eq!(config.shade(&book, OptionSide::Call, 110_000.0, 0.3), 1.0);
+        // Puts away from the concentrated strike reduce the book's delta and are discounted
+        assert!((config.shade(&book, OptionSide::Put, 90_000.0, -0.2) - 0.99).abs() < 1e-12);
+
+        let quote = config.quote(1000.0, OptionSide::Call, 100_000.0, &greeks(0.5, 0.0), &book);
+        assert!((quote.ask - 1125.0).abs() < 1e-9 && (quote.bid - 1125.0).abs() < 1e-9);
+        assert_eq!(quote.mid, 1000.0);
+    }
+}
@@ -0,0 +1,269 @@
+// Risk-free rate term structure.
+// Options are priced, margined and marked at the risk-free rate for their maturity, read off a
+// curve of (tenor, rate) points: linear between points, flat before the first and past the last.
+// RISK_FREE_RATE_CURVE gives the points as comma separated tenor=rate pairs, tenors in days,
+// weeks, months or years, e.g. "1w=0.040,1m=0.045,3m=0.048,1y=0.050". Without it the curve is
+// flat at RISK_FREE_RATE (0). With RISK_FREE_RATE_CURVE_URL the curve is fetched from there
+// instead, at startup and every RISK_FREE_RATE_CURVE_REFRESH_SECS (3600), as a JSON object of
+// tenor to rate or a body in the RISK_FREE_RATE_CURVE format. Until a fetch succeeds, and
+// whenever one fails, the last curve is kept.
+// Tenors are calendar time; maturities are measured in calendar time to match.
+
+use crate::http_client::HttpClient;
+use crate::supervisor::Supervisor;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::time::{interval_at, Instant};
+
+const SECONDS_PER_YEAR: f64 = 365.0 * 24.0 * 60.0 * 60.0;
+
+const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Risk-free rates by maturity
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct RateCurve {
+    points: Vec<(f64, f64)>,  // (tenor in years, rate), sorted by tenor; empty is flat at 0
+}
+
+impl RateCurve {
+    /// The same `rate` at every maturity
+    pub fn flat(rate: f64) -> Self {
+        Self { points: vec![(0.0, rate)] }
+    }
+
+    /// Points in the RISK_FREE_RATE_CURVE format, e.g. "1w=0.04,1m=0.045,1y=0.05"
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let points = spec
+            .split(',')
+            .map(str::trim)
+            .filter(|point| !point.is_empty())
+            .map(|point| {
+                let (tenor, rate) = point
+                    .split_once('=')
+                    .ok_or_else(|| format!("invalid rate curve point '{}', expected tenor=rate such as 1m=0.045", point))?;
+                let rate = rate
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("invalid rate '{}' in rate curve point '{}'", rate.trim(), point))?;
+                Ok((parse_tenor(tenor)?, rate))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Self::from_points(points)
+    }
+
+    /// Curve through `points` of (tenor in years, rate) in any order
+    pub fn from_points(mut points: Vec<(f64, f64)>) -> Result<Self, String> {
+        if points.is_empty() {
+            return Err("a rate curve needs at least one point".to_string());
+        }
+        if let Some((_, rate)) = points.iter().find(|(_, rate)| !rate.is_finite()) {
+            return Err(format!("invalid rate {} in rate curve", rate));
+        }
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        if points.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+            return Err("rate curve has two points at the same tenor".to_string());
+        }
+        Ok(Self { points })
+    }
+
+    /// RISK_FREE_RATE_CURVE, else flat at RISK_FREE_RATE (0)
+    pub fn from_env() -> Result<Self, String> {
+        match env::var("RISK_FREE_RATE_CURVE") {
+            Ok(spec) if !spec.trim().is_empty() => Self::parse(&spec),
+            _ => match env::var("RISK_FREE_RATE") {
+                Ok(rate) if !rate.trim().is_empty() => rate
+                    .trim()
+                    .parse()
+                    .ok()
+                    .filter(|rate: &f64| rate.is_finite())
+                    .map(Self::flat)
+                    .ok_or_else(|| format!("invalid RISK_FREE_RATE '{}', expected a decimal such as 0.05", rate)),
+                _ => Ok(Self::flat(0.0)),
+            },
+        }
+    }
+
+    /// Curve from a RISK_FREE_RATE_CURVE_URL response body; see the module comment
+    pub fn parse_body(body: &str) -> Result<Self, String> {
+        match serde_json::from_str::<BTreeMap<String, f64>>(body) {
+            Ok(rates) => Self::from_points(
+                rates
+                    .into_iter()
+                    .map(|(tenor, rate)| Ok((parse_tenor(&tenor)?, rate)))
+                    .collect::<Result<_, String>>()?,
+            ),
+            Err(_) => Self::parse(body),
+        }
+    }
+
+    pub fn points(&self) -> &[(f64, f64)] {
+        &self.points
+    }
+
+    /// Rate for a maturity `years` away
+    pub fn rate(&self, years: f64) -> f64 {
+        let Some(&(first_tenor, first_rate)) = self.points.first() else {
+            return 0.0;
+        };
+        if years <= first_tenor {
+            return first_rate;
+        }
+        for pair in self.points.windows(2) {
+            let ((t0, r0), (t1, r1)) = (pair[0], pair[1]);
+            if years <= t1 {
+                return r0 + (r1 - r0) * (years - t0) / (t1 - t0);
+            }
+        }
+        self.points[self.points.len() - 1].1
+    }
+
+    /// Rate for an option expiring at `expires` as of `now` (Unix seconds)
+    pub fn rate_to(&self, expires: i64, now: i64) -> f64 {
+        self.rate((expires - now).max(0) as f64 / SECONDS_PER_YEAR)
+    }
+}
+
+// "3d", "2w", "6m" or "1y" in years
+fn parse_tenor(tenor: &str) -> Result<f64, String> {
+    let tenor = tenor.trim().to_ascii_lowercase();
+    let invalid = || format!("invalid tenor '{}', expected a count of d, w, m or y such as 3m", tenor);
+    let unit = tenor.chars().last().ok_or_else(invalid)?;
+    let count: f64 = tenor[..tenor.len() - unit.len_utf8()].trim().parse().map_err(|_| invalid())?;
+    if !count.is_finite() || count < 0.0 {
+        return Err(invalid());
+    }
+    match unit {
+        'd' => Ok(count / 365.0),
+        'w' => Ok(count * 7.0 / 365.0),
+        'm' => Ok(count / 12.0),
+        'y' => Ok(count),
+        _ => Err(invalid()),
+    }
+}
+
+/// The deployment's curve, kept current from RISK_FREE_RATE_CURVE_URL when that is set
+#[derive(Debug)]
+pub struct RateSource {
+    curve: RwLock<Arc<RateCurve>>,
+    url: Option<String>,
+    refresh_interval: Duration,
+}
+
+impl Default for RateSource {
+    fn default() -> Self {
+        Self::new(RateCurve::default())
+    }
+}
+
+impl RateSource {
+    pub fn new(curve: RateCurve) -> Self {
+        Self { curve: RwLock::new(Arc::new(curve)), url: None, refresh_interval: DEFAULT_REFRESH_INTERVAL }
+    }
+
+    /// The curve of RateCurve::from_env, fetched from RISK_FREE_RATE_CURVE_URL when set
+    pub fn from_env() -> Result<Self, String> {
+        let refresh_interval = env::var("RISK_FREE_RATE_CURVE_REFRESH_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .map(|secs: u64| Duration::from_secs(secs.max(1)))
+            .unwrap_or(DEFAULT_REFRESH_INTERVAL);
+        Ok(Self {
+            url: env::var("RISK_FREE_RATE_CURVE_URL").ok().filter(|url| !url.trim().is_empty()),
+            refresh_interval,
+            ..Self::new(RateCurve::from_env()?)
+        })
+    }
+
+    pub fn url(&self) -> Option<&str> {
+        self.url.as_deref()
+    }
+
+    pub fn curve(&self) -> Arc<RateCurve> {
+        self.curve.read().unwrap().clone()
+    }
+
+    pub fn set(&self, curve: RateCurve) {
+        *self.curve.write().unwrap() = Arc::new(curve);
+    }
+
+    /// Fetch the curve from the URL, if there is one
+    pub async fn refresh(&self, http_client: &HttpClient) -> Result<(), String> {
+        let Some(url) = &self.url else {
+            return Ok(());
+        };
+        let response = http_client.get(url).await.map_err(|e| e.to_string())?;
+        let body = response.text().await.map_err(|e| e.to_string())?;
+        self.set(RateCurve::parse_body(&body)?);
+        Ok(())
+    }
+
+    /// Keep the curve current from the URL, refreshing it an interval after the fetch at
+    /// startup; nothing to do without one
+    pub fn start_updates(self: &Arc<Self>, supervisor: &Supervisor, http_client: HttpClient) {
+        if self.url.is_none() {
+            return;
+        }
+        let source = self.clone();
+        supervisor.spawn("rate_curve", move || {
+            let (source, http_client) = (source.clone(), http_client.clone());
+            async move {
+                let mut ticker = interval_at(Instant::now() + source.refresh_interval, source.refresh_interval);
+                loop {
+                    ticker.tick().await;
+                    if let Err(e) = source.refresh(&http_client).await {
+                        eprintln!("Error refreshing the risk-free rate curve: {}", e);
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interpolation() {
+        let curve = RateCurve::parse("1y=0.05, 1m=0.04, 3m=0.045").unwrap();
+        assert_eq!(curve.points(), &[(1.0 / 12.0, 0.04), (0.25, 0.045), (1.0, 0.05)]);
+        // Flat before the first point and past the last
+        assert_eq!(curve.rate(0.0), 0.04);
+        assert_eq!(curve.rate(5.0), 0.05);
+        assert!((curve.rate(1.0 / 6.0) - 0.0425).abs() < 1e-12);
+        assert!((curve.rate(0.625) - 0.0475).abs() < 1e-12);
+
+        let now = 1_735_689_600;
+        assert_eq!(curve.rate_to(now + 365 * 86_400, now), 0.05);
+        assert_eq!(curve.rate_to(now - 86_400, now), 0.04);
+        assert_eq!(RateCurve::flat(0.03).rate(2.0), 0.03);
+        assert_eq!(RateCurve::default().rate(1.0), 0.0);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse_tenor("2W"), Ok(14.0 / 365.0));
+        assert_eq!(parse_tenor("30d"), Ok(30.0 / 365.0));
+        assert!(RateCurve::parse("1q=0.05").is_err());
+        assert!(RateCurve::parse("1m").is_err());
+        assert!(RateCurve::parse("1m=abc").is_err());
+        assert!(RateCurve::parse("12m=0.04,1y=0.05").is_err());
+        assert!(RateCurve::parse("").is_err());
+
+        let json = RateCurve::parse_body(r#"{"1m": 0.04, "1y": 0.05}"#).unwrap();
+        assert_eq!(json, RateCurve::parse("1m=0.04,1y=0.05").unwrap());
+        assert_eq!(RateCurve::parse_body("1y=0.05\n").unwrap(), RateCurve::parse("1y=0.05").unwrap());
+    }
+
+    #[test]
+    fn test_source_keeps_last_curve() {
+        let source = RateSource::new(RateCurve::flat(0.05));
+        let before = source.curve();
+        source.set(RateCurve::parse("1y=0.04").unwrap());
+        assert_eq!(before.rate(1.0), 0.05);
+        assert_eq!(source.curve().rate(1.0), 0.04);
+    }
+}
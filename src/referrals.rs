@@ -0,0 +1,147 @@
+// Referral attribution.
+// A trade may carry a referral code, e.g. from a growth campaign or an affiliate's link. Codes
+// are free form, up to 32 letters, digits, '-' or '_', and compared case-insensitively. The code
+// of each trade is recorded in the referrals table with the contract, and GET /admin/referrals
+// sums the volume and fees of the trades placed with each code.
+
+use crate::error::{ApiError, ApiResult};
+use crate::models::ContractStatus;
+use crate::utils::{cents_to_usd, format_sats};
+use rusqlite::{params, Connection};
+use serde::Serialize;
+
+const MAX_CODE_LEN: usize = 32;
+
+/// `code` trimmed and upper-cased; refused unless it is 1 to 32 letters, digits, '-' or '_'
+pub fn normalize_code(code: &str) -> ApiResult<String> {
+    let code = code.trim().to_uppercase();
+    let valid = !code.is_empty()
+        && code.len() <= MAX_CODE_LEN
+        && code.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(code)
+    } else {
+        Err(ApiError::ValidationError(format!(
+            "referral_code must be 1 to {} letters, digits, '-' or '_', got '{}'",
+            MAX_CODE_LEN, code
+        )))
+    }
+}
+
+/// Record that `account` placed contract `contract_id` with referral code `code`
+pub fn record(conn: &Connection, contract_id: i64, code: &str, account: &str, now: i64) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO referrals (contract_id, code, account, created_at) VALUES (?1, ?2, ?3, ?4)",
+        params![contract_id, code, account, now],
+    )?;
+    Ok(())
+}
+
+/// Trades placed with one referral code
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ReferralSummary {
+    pub code: String,
+    pub trades: i64,
+    pub accounts: i64,         // Distinct accounts referred
+    pub notional_usd: f64,     // Each contract at its spot price at trade
+    pub premium_btc: String,
+    pub fee_btc: String,       // Fees charged less maker rebates paid
+    pub fee_usd: f64,
+}
+
+/// Referral codes of the trades placed in [from, to), by notional traded. Cancelled contracts,
+/// never paid for, do not count.
+pub fn summarize(conn: &Connection, from: i64, to: i64) -> ApiResult<Vec<ReferralSummary>> {
+    let mut stmt = conn.prepare(
+        "SELECT r.code, COUNT(*), COUNT(DISTINCT r.account),
+                COALESCE(SUM(CAST(c.quantity_sats AS REAL) * COALESCE(c.spot_at_trade_cents, 0)), 0) / 1e10,
+                SUM(CAST(ROUND(c.premium_sats * (c.quantity_sats / 1e8)) AS INTEGER)),
+                SUM(COALESCE(f.fee_sats, 0)), SUM(COALESCE(f.fee_cents, 0))
+         FROM referrals r
+         JOIN contracts c ON c.id = r.contract_id
+         LEFT JOIN fees f ON f.contract_id = r.contract_id
+         WHERE c.status != ?1 AND r.created_at >= ?2 AND r.created_at < ?3
+         GROUP BY r.code
+         ORDER BY 4 DESC, r.code",
+    )?;
+    let summaries = stmt
+        .query_map(params![ContractStatus::Cancelled, from, to], |row| {
+            let notional_usd: f64 = row.get(3)?;
+            Ok(ReferralSummary {
+                code: row.get(0)?,
+                trades: row.get(1)?,
+                accounts: row.get(2)?,
+                notional_usd: (notional_usd * 100.0).round() / 100.0,
+                premium_btc: format_sats(row.get(4)?),
+                fee_btc: format_sats(row.get(5)?),
+                fee_usd: cents_to_usd(row.get(6)?),
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(summaries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fees::{self, TradeFee};
+
+    #[test]
+    fn test_normalize_code() {
+        assert_eq!(normalize_code(" spring-24_a ").unwrap(), "SPRING-24_A");
+        assert!(normalize_code("").is_err());
+        assert!(normalize_code("two words").is_err());
+        assert!(normalize_code(&"A".repeat(33)).is_err());
+    }
+
+    #[test]
+    fn test_summarize_by_code() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init_db(&conn).unwrap();
+        // 0.5 BTC at $100,000 with a 0.01 BTC premium per unit
+        let insert = |account: &str, status: &str| {
+            conn.execute(
+                "INSERT INTO contracts (side, strike_price_cents, quantity_sats, expires, premium_sats, counterparty, spot_at_trade_cents, status)
+                 VALUES ('Call', 10000000, 50000000, 1800000000, 1000000, ?1, 10000000, ?2)",
+                params![account, status],
+            )
+            .unwrap();
+            conn.last_insert_rowid()
+        };
+        for (account, code, fee_sats) in [("alice", "SPRING", 500), ("bob", "SPRING", -100), ("alice", "AFF-1", 0)] {
+            let id = insert(account, "open");
+            record(&conn, id, code, account, 100).unwrap();
+            let fee = TradeFee { tier: None, fee_percent: 0.0, fee_sats, fee_usd: fee_sats as f64 / 1000.0 };
+            fees::record(&conn, id, account, &fee, 100).unwrap();
+        }
+        let cancelled = insert("carol", "cancelled");
+        record(&conn, cancelled, "AFF-1", "carol", 100).unwrap();
+
+        let summaries = summarize(&conn, 0, 200).unwrap();
+        assert_eq!(
+            summaries,
+            vec![
+                ReferralSummary {
+                    code: "SPRING".to_string(),
+                    trades: 2,
+                    accounts: 2,
+                    notional_usd: 100_000.0,
+                    premium_btc: "0.01000000".to_string(),
+                    fee_btc: "0.00000400".to_string(),
+                    fee_usd: 0.4,
+                },
+                ReferralSummary {
+                    code: "AFF-1".to_string(),
+                    trades: 1,
+                    accounts: 1,
+                    notional_usd: 50_000.0,
+                    premium_btc: "0.00500000".to_string(),
+                    fee_btc: "0.00000000".to_string(),
+                    fee_usd: 0.0,
+                },
+            ]
+        );
+        assert!(summarize(&conn, 100, 100).unwrap().is_empty());
+        assert!(summarize(&conn, 101, 200).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,1405 @@
+// Repository layer for all SQLite access.
+// rusqlite is blocking, so every query runs on tokio's blocking thread pool
+// via Repository::run instead of stalling the HTTP worker threads.
+
+use crate::api_keys;
+use crate::audit::{self, AuditEntry, AuditFilter};
+use crate::contract_search::{self, ContractSearchQuery};
+use crate::conversions::{self, ConversionKind};
+use crate::db::DbPool;
+use crate::error::{ApiError, ApiResult, ErrorCode};
+use crate::models::{Asset, Contract, ContractAmendment, ContractDb, ContractRecord, ContractStatus, ExerciseStyle, OptionSide, PremiumQuote, QuoteCurrency, TradeSnapshot};
+use crate::utils::{btc_to_sats, cents_to_usd, format_sats, sats_to_btc, usd_to_cents, SATS_PER_BTC};
+use crate::outbox;
+use crate::payments::{self, PaymentRequest, PremiumPayment};
+use crate::reservations::{self, PoolBook, ReservedMargin};
+use crate::vol::{self, RealizedVol};
+use crate::webhooks::{CONTRACT_EXERCISED, CONTRACT_SETTLED, TRADE_CREATED};
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+#[derive(Clone)]
+pub struct Repository {
+    pool: DbPool,
+    read_pool: DbPool,  // Analytics reads; the main pool unless a read pool is given
+    // Serializes exercises, closes and amendments within this process; new trades are
+    // serialized by collateral reservations in the database
+    write_lock: Arc<Mutex<()>>,
+}
+
+// Aggregated trading activity for one (underlying, side, strike, expiry) product
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProductVolume {
+    pub underlying: Asset,
+    pub side: OptionSide,
+    pub strike_price_cents: i64,
+    pub expires: i64,
+    pub volume: f64,
+    pub avg_premium: f64,
+}
+
+// Latest premium for an active product together with the baseline it is compared to
+#[derive(Debug, Clone)]
+pub struct ProductPremiumChange {
+    pub underlying: Asset,
+    pub side: OptionSide,
+    pub strike_price_cents: i64,
+    pub expires: i64,
+    pub current_premium: f64,
+    pub baseline_premium: f64,
+}
+
+impl Repository {
+    pub fn new(pool: DbPool) -> Self {
+        Self {
+            read_pool: pool.clone(),
+            pool,
+            write_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Run `read` work on `read_pool`, leaving the main pool to trades and other writes
+    pub fn with_read_pool(mut self, read_pool: DbPool) -> Self {
+        self.read_pool = read_pool;
+        self
+    }
+
+    pub fn pool(&self) -> &DbPool {
+        &self.pool
+    }
+
+    pub fn read_pool(&self) -> &DbPool {
+        &self.read_pool
+    }
+
+    /// Run blocking database work on the blocking thread pool
+    pub async fn run<T, F>(&self, f: F) -> ApiResult<T>
+    where
+        F: FnOnce(&mut Connection) -> ApiResult<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let pool = self.pool.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = pool.get()?;
+            f(&mut conn)
+        })
+        .await
+        .map_err(|e| ApiError::DatabaseError(format!("Database task failed: {}", e)))?
+    }
+
+    /// Run a read-only query on the read pool, for analytics and listings that trades do not
+    /// wait on. It sees the last committed state.
+    pub async fn read<T, F>(&self, f: F) -> ApiResult<T>
+    where
+        F: FnOnce(&Connection) -> ApiResult<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let pool = self.read_pool.clone();
+        tokio::task::spawn_blocking(move || {
+            let conn = pool.get()?;
+            f(&conn)
+        })
+        .await
+        .map_err(|e| ApiError::DatabaseError(format!("Database task failed: {}", e)))?
+    }
+
+    pub async fn active_contracts(&self, now: i64) -> ApiResult<Vec<Contract>> {
+        self.run(move |conn| load_active_contracts(conn, now)).await
+    }
+
+    pub async fn pool_active_contracts(&self, pool_id: i64, now: i64) -> ApiResult<Vec<Contract>> {
+        self.run(move |conn| load_pool_active_contracts(conn, pool_id, now)).await
+    }
+
+    /// Trades of pool `pool_id` holding an active collateral reservation
+    pub async fn pool_reserved_contracts(&self, pool_id: i64, now: i64) -> ApiResult<Vec<Contract>> {
+        self.run(move |conn| Ok(reservations::pool_reserved_contracts(conn, pool_id, now)?)).await
+    }
+
+    pub async fn all_contracts(&self) -> ApiResult<Vec<ContractDb>> {
+        self.read(load_all_contracts).await
+    }
+
+    pub async fn insert_contract(&self, contract: Contract) -> ApiResult<i64> {
+        self.run(move |conn| insert_contract(conn, &contract, None)).await
+    }
+
+    /// Reserve collateral in pool `pool_id` for `contract` of `counterparty` if `check` accepts
+    /// it given the active book of the pool and of the counterparty, reserved trades included,
+    /// and returns the margin it adds, along with any detail of the check the caller wants back.
+    /// Loading, checking and reserving happen in one IMMEDIATE transaction, which SQLite
+    /// serializes across every instance using the database; the reservation lapses after `ttl`
+    /// unless `insert_reserved_contract` confirms it. Returns the reservation id and the detail.
+    pub async fn reserve_collateral<F, T>(
+        &self,
+        pool_id: i64,
+        contract: Contract,
+        counterparty: String,
+        now: i64,
+        ttl: Duration,
+        check: F,
+    ) -> ApiResult<(i64, T)>
+    where
+        F: FnOnce(&Connection, &PoolBook) -> ApiResult<(ReservedMargin, T)> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.run(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let mut counterparty_contracts = load_counterparty_contracts(&tx, &counterparty, now)?;
+            counterparty_contracts.extend(reservations::counterparty_reserved_contracts(&tx, &counterparty, now)?);
+            let book = PoolBook {
+                open: load_pool_active_contracts(&tx, pool_id, now)?,
+                reserved: reservations::pool_reserved_contracts(&tx, pool_id, now)?,
+                counterparty: counterparty_contracts,
+            };
+            let (margin, detail) = check(&tx, &book)?;
+            let id = reservations::reserve(&tx, pool_id, &counterparty, &contract, margin, now, ttl)?;
+            tx.commit()?;
+            Ok((id, detail))
+        })
+        .await
+    }
+
+    /// Release collateral reservation `id` of a trade that fell through
+    pub async fn release_reservation(&self, id: i64) -> ApiResult<()> {
+        self.run(move |conn| Ok(reservations::release(conn, id)?)).await
+    }
+
+    /// Insert a contract into pool `pool_id` holding collateral reservation `reservation_id`,
+    /// confirming the reservation in the same transaction. `check` runs in that transaction with
+    /// the new contract's id, e.g. to fill a resting quote or record the fee. Fails without
+    /// inserting anything if the reservation lapsed or `check` refuses, e.g. when the quote is gone.
+    /// `quote` records the premium as agreed with the buyer; the insert is audited under `actor`.
+    /// With a `payment` request the contract is recorded as pending until its premium is paid.
+    /// `snapshot` is the market the contract was priced in.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn insert_reserved_contract<F>(
+        &self,
+        reservation_id: i64,
+        pool_id: i64,
+        contract: Contract,
+        quote: PremiumQuote,
+        snapshot: TradeSnapshot,
+        exercise_style: ExerciseStyle,
+        payment: Option<PaymentRequest>,
+        actor: String,
+        now: i64,
+        check: F,
+    ) -> ApiResult<(i64, Option<PremiumPayment>)>
+    where
+        F: FnOnce(&Connection, i64) -> ApiResult<()> + Send + 'static,
+    {
+        self.run(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let id = insert_contract(&tx, &contract, Some(&quote))?;
+            reservations::confirm(&tx, reservation_id, id, now)?;
+            check(&tx, id)?;
+            tx.execute(
+                "UPDATE contracts SET counterparty = ?1, exercise_style = ?2, spot_at_trade_cents = ?3,
+                                      iv_at_trade = ?4, mark_premium_sats = ?5, pool_id = ?6, iv_source = ?7
+                 WHERE id = ?8",
+                params![
+                    actor,
+                    exercise_style,
+                    usd_to_cents(snapshot.spot_price),
+                    snapshot.iv,
+                    btc_to_sats(snapshot.mark_premium),
+                    pool_id,
+                    snapshot.iv_source,
+                    id
+                ],
+            )?;
+            let payment = payment.map(|request| payments::request_payment(&tx, id, &request, now)).transpose()?;
+            let record = load_contract_record(&tx, id)?;
+            conversions::record(
+                &tx,
+                id,
+                ConversionKind::Premium,
+                quote.currency,
+                record.premium * record.quantity,
+                quote.btc_price,
+                now,
+            )?;
+            audit::record(&tx, &actor, audit::CONTRACT_CREATE, Some(id), None, Some(&to_json(&record)?))?;
+            outbox::record(&tx, TRADE_CREATED, Some(id), &serde_json::json!({"contract": record}))?;
+            tx.commit()?;
+            Ok((id, payment))
+        })
+        .await
+    }
+
+    /// Name of the unrevoked API key `key`, the actor of requests presenting it. None without
+    /// a key or for an unknown or revoked one.
+    pub async fn api_key_actor(&self, key: Option<String>) -> ApiResult<Option<String>> {
+        let Some(key) = key else {
+            return Ok(None);
+        };
+        self.run(move |conn| Ok(api_keys::api_key_name(conn, &key)?)).await
+    }
+
+    /// Exercise open contract `id` early at `settlement_price`, recording `btc_price` for
+    /// converting the payoff. Audited under `actor`; fails if the contract is no longer open.
+    pub async fn exercise_contract(
+        &self,
+        id: i64,
+        settlement_price: f64,
+        btc_price: f64,
+        now: i64,
+        actor: String,
+    ) -> ApiResult<ContractRecord> {
+        let _guard = self.write_lock.lock().await;
+        self.run(move |conn| exercise_contract(conn, id, settlement_price, btc_price, now, &actor)).await
+    }
+
+    /// Sell `quantity_sats` of open contract `id` back to the pool at `price` (USD per unit),
+    /// recording `btc_price` for converting the proceeds. Audited under `actor`.
+    pub async fn close_contract(
+        &self,
+        id: i64,
+        quantity_sats: i64,
+        price: f64,
+        btc_price: f64,
+        now: i64,
+        actor: String,
+    ) -> ApiResult<ContractRecord> {
+        let _guard = self.write_lock.lock().await;
+        self.run(move |conn| close_contract(conn, id, quantity_sats, price, btc_price, now, &actor)).await
+    }
+
+    /// Amend open contract `id` to `amendment` if `check` accepts the amended open contract
+    /// given the active contracts of its pool and of its buyer, both without this contract and
+    /// with the trades holding collateral reservations. Loading, checking and updating happen in
+    /// one IMMEDIATE transaction under the write lock. The extra premium is recorded at `btc_price`; the change is audited
+    /// under `actor`.
+    pub async fn amend_contract_checked<F>(
+        &self,
+        id: i64,
+        amendment: ContractAmendment,
+        btc_price: f64,
+        actor: String,
+        now: i64,
+        check: F,
+    ) -> ApiResult<ContractRecord>
+    where
+        F: FnOnce(&Connection, &Contract, &[Contract], &[Contract]) -> ApiResult<()> + Send + 'static,
+    {
+        let _guard = self.write_lock.lock().await;
+        self.run(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let before = load_contract_record(&tx, id)?;
+            if before.status != ContractStatus::Open {
+                return Err(contract_not_open(id, before.status));
+            }
+            let (quantity_sats, premium_sats) = (btc_to_sats(before.quantity), btc_to_sats(before.premium));
+            if amendment.expires < before.expires
+                || amendment.quantity_sats < quantity_sats
+                || amendment.premium_sats < premium_sats
+            {
+                return Err(ApiError::ValidationError(format!(
+                    "Contract {} may only be extended: expiry from {}, quantity from {}, premium from {}",
+                    id,
+                    before.expires,
+                    format_sats(quantity_sats),
+                    format_sats(premium_sats)
+                )));
+            }
+
+            let current = before.to_contract();
+            let amended = Contract {
+                quantity: sats_to_btc(amendment.quantity_sats - btc_to_sats(before.closed_quantity)),
+                expires: amendment.expires,
+                premium: sats_to_btc(amendment.premium_sats),
+                ..current.clone()
+            };
+            let without_current = |mut contracts: Vec<Contract>| {
+                if let Some(index) = contracts.iter().position(|c| *c == current) {
+                    contracts.remove(index);
+                }
+                contracts
+            };
+            // Trades holding a reservation count as part of the book
+            let mut book = without_current(load_pool_active_contracts(&tx, before.pool_id, now)?);
+            book.extend(reservations::pool_reserved_contracts(&tx, before.pool_id, now)?);
+            let counterparty_contracts = match &before.counterparty {
+                Some(counterparty) => {
+                    let mut contracts = without_current(load_counterparty_contracts(&tx, counterparty, now)?);
+                    contracts.extend(reservations::counterparty_reserved_contracts(&tx, counterparty, now)?);
+                    contracts
+                }
+                None => Vec::new(),
+            };
+            check(&tx, &amended, &book, &counterparty_contracts)?;
+
+            // A new expiry gets its own expiring-soon notice
+            tx.execute(
+                "UPDATE contracts SET expires = ?1, quantity_sats = ?2, premium_sats = ?3,
+                                      expiry_notified_at = CASE WHEN expires = ?1 THEN expiry_notified_at END
+                 WHERE id = ?4",
+                params![amendment.expires, amendment.quantity_sats, amendment.premium_sats, id],
+            )?;
+            let extra_premium =
+                sats_to_btc(amendment.premium_sats) * sats_to_btc(amendment.quantity_sats) - before.premium * before.quantity;
+            if extra_premium > 0.0 {
+                conversions::record(&tx, id, ConversionKind::Premium, before.premium_currency, extra_premium, btc_price, now)?;
+            }
+            audit_transitions(&tx, &actor, audit::CONTRACT_AMEND, std::slice::from_ref(&before))?;
+            let amended = load_contract_record(&tx, id)?;
+            tx.commit()?;
+            Ok(amended)
+        })
+        .await
+    }
+
+    pub async fn contract_record(&self, id: i64) -> ApiResult<ContractRecord> {
+        self.run(move |conn| load_contract_record(conn, id)).await
+    }
+
+    /// Open contracts expiring in (now, until] whose expiring-soon notice has not been delivered
+    pub async fn contracts_to_announce(&self, now: i64, until: i64) -> ApiResult<Vec<ContractRecord>> {
+        self.run(move |conn| load_contracts_to_announce(conn, now, until)).await
+    }
+
+    pub async fn mark_expiry_notified(&self, id: i64, now: i64) -> ApiResult<()> {
+        self.run(move |conn| {
+            conn.execute("UPDATE contracts SET expiry_notified_at = ?1 WHERE id = ?2", params![now, id])?;
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn audit_entries(&self, filter: AuditFilter) -> ApiResult<Vec<AuditEntry>> {
+        self.read(move |conn| Ok(audit::query(conn, &filter)?)).await
+    }
+
+    /// Contracts matching `query`, newest first, with the moneyness band taken around `spots`
+    pub async fn search_contracts(&self, query: ContractSearchQuery, spots: HashMap<Asset, f64>, now: i64) -> ApiResult<Vec<ContractRecord>> {
+        self.read(move |conn| contract_search::search(conn, &query, &spots, now)).await
+    }
+
+    pub async fn realized_vol(&self, asset: Asset, window: &str) -> ApiResult<RealizedVol> {
+        let window = window.to_string();
+        self.run(move |conn| Ok(vol::realized_vol(conn, asset, &window)?)).await
+    }
+}
+
+/// Load all contracts that have not yet expired or been exercised. Contracts awaiting
+/// payment of their premium are included, as they hold on to collateral until cancelled.
+/// Every one is a short of the pool: rows with a negative quantity, booked before quantities
+/// were validated, are left out so they cannot pass for longs offsetting the book's margin.
+pub fn load_active_contracts(conn: &Connection, now: i64) -> ApiResult<Vec<Contract>> {
+    load_active(conn, now, None)
+}
+
+/// Active contracts of pool `pool_id`, as `load_active_contracts`
+pub fn load_pool_active_contracts(conn: &Connection, pool_id: i64, now: i64) -> ApiResult<Vec<Contract>> {
+    load_active(conn, now, Some(pool_id))
+}
+
+fn load_active(conn: &Connection, now: i64, pool_id: Option<i64>) -> ApiResult<Vec<Contract>> {
+    let mut stmt = conn.prepare(
+        "SELECT side, strike_price_cents, quantity_sats - closed_quantity_sats, expires, premium_sats, underlying FROM contracts
+         WHERE expires > ?1 AND status IN ('open', 'pending') AND (?2 IS NULL OR pool_id = ?2)
+           AND quantity_sats - closed_quantity_sats > 0"
+    )?;
+
+    let contracts_iter = stmt.query_map(params![now, pool_id], |row| {
+        Ok(Contract {
+            underlying: row.get(5)?,
+            side: row.get(0)?,
+            strike_price: cents_to_usd(row.get(1)?),
+            quantity: sats_to_btc(row.get(2)?),
+            expires: row.get(3)?,
+            premium: sats_to_btc(row.get(4)?),
+        })
+    })?;
+
+    contracts_iter
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))
+}
+
+/// Load the contracts of `counterparty` that have not yet expired or been exercised,
+/// including those awaiting payment, as `load_active_contracts`
+pub fn load_counterparty_contracts(conn: &Connection, counterparty: &str, now: i64) -> ApiResult<Vec<Contract>> {
+    let mut stmt = conn.prepare(
+        "SELECT side, strike_price_cents, quantity_sats - closed_quantity_sats, expires, premium_sats, underlying FROM contracts
+         WHERE counterparty = ?1 AND expires > ?2 AND status IN ('open', 'pending')
+           AND quantity_sats - closed_quantity_sats > 0"
+    )?;
+
+    let contracts_iter = stmt.query_map(params![counterparty, now], |row| {
+        Ok(Contract {
+            underlying: row.get(5)?,
+            side: row.get(0)?,
+            strike_price: cents_to_usd(row.get(1)?),
+            quantity: sats_to_btc(row.get(2)?),
+            expires: row.get(3)?,
+            premium: sats_to_btc(row.get(4)?),
+        })
+    })?;
+
+    contracts_iter
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))
+}
+
+/// Load every contract in storage format (integer units kept for precision)
+pub fn load_all_contracts(conn: &Connection) -> ApiResult<Vec<ContractDb>> {
+    let mut stmt = conn.prepare(
+        "SELECT side, strike_price_cents, quantity_sats, expires, premium_sats, underlying,
+                premium_currency, quoted_premium_minor, trade_btc_price_cents, closed_quantity_sats
+         FROM contracts"
+    )?;
+
+    let contracts_iter = stmt.query_map([], |row| {
+        Ok(ContractDb {
+            underlying: row.get(5)?,
+            side: row.get(0)?,
+            strike_price_cents: row.get(1)?,
+            quantity_sats: row.get(2)?,
+            expires: row.get(3)?,
+            premium_sats: row.get(4)?,
+            premium_currency: row.get(6)?,
+            quoted_premium_minor: row.get(7)?,
+            trade_btc_price_cents: row.get(8)?,
+            closed_quantity_sats: row.get(9)?,
+        })
+    })?;
+
+    contracts_iter
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))
+}
+
+pub(crate) const CONTRACT_RECORD_COLUMNS: &str = "id, side, strike_price_cents, quantity_sats, expires, premium_sats, \
+     created_at, status, settlement_price_cents, settled_at, underlying, \
+     premium_currency, quoted_premium_minor, trade_btc_price_cents, settlement_btc_price_cents, \
+     exercise_style, counterparty, closed_quantity_sats, spot_at_trade_cents, iv_at_trade, mark_premium_sats, pool_id, \
+     iv_source";
+
+pub(crate) fn contract_record_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<ContractRecord> {
+    let premium_currency: QuoteCurrency = row.get(11)?;
+    let settlement_price_cents: Option<i64> = row.get(8)?;
+    let quoted_premium_minor: Option<i64> = row.get(12)?;
+    let trade_btc_price_cents: Option<i64> = row.get(13)?;
+    let settlement_btc_price_cents: Option<i64> = row.get(14)?;
+
+    Ok(ContractRecord {
+        id: row.get(0)?,
+        underlying: row.get(10)?,
+        side: row.get(1)?,
+        strike_price: cents_to_usd(row.get(2)?),
+        quantity: sats_to_btc(row.get(3)?),
+        expires: row.get(4)?,
+        premium: sats_to_btc(row.get(5)?),
+        premium_currency,
+        quoted_premium: quoted_premium_minor.map(|minor| premium_currency.from_minor(minor)),
+        trade_btc_price: trade_btc_price_cents.map(cents_to_usd),
+        created_at: row.get(6)?,
+        status: row.get(7)?,
+        settlement_price: settlement_price_cents.map(cents_to_usd),
+        settlement_btc_price: settlement_btc_price_cents.map(cents_to_usd),
+        settled_at: row.get(9)?,
+        exercise_style: row.get(15)?,
+        counterparty: row.get(16)?,
+        closed_quantity: sats_to_btc(row.get(17)?),
+        spot_at_trade: row.get::<_, Option<i64>>(18)?.map(cents_to_usd),
+        iv_at_trade: row.get(19)?,
+        mark_premium_at_trade: row.get::<_, Option<i64>>(20)?.map(sats_to_btc),
+        pool_id: row.get(21)?,
+        iv_source: row.get(22)?,
+    })
+}
+
+/// Load stored contracts with their ids and lifecycle state, optionally filtered by status
+pub fn load_contract_records(conn: &Connection, status: Option<ContractStatus>) -> ApiResult<Vec<ContractRecord>> {
+    let records = match status {
+        Some(status) => {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {} FROM contracts WHERE status = ?1 ORDER BY id ASC",
+                CONTRACT_RECORD_COLUMNS
+            ))?;
+            let rows = stmt.query_map(params![status], contract_record_from_row)?;
+            rows.collect::<Result<Vec<_>, _>>()?
+        }
+        None => {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {} FROM contracts ORDER BY id ASC",
+                CONTRACT_RECORD_COLUMNS
+            ))?;
+            let rows = stmt.query_map([], contract_record_from_row)?;
+            rows.collect::<Result<Vec<_>, _>>()?
+        }
+    };
+    Ok(records)
+}
+
+/// Load one stored contract by id
+pub fn load_contract_record(conn: &Connection, id: i64) -> ApiResult<ContractRecord> {
+    conn.query_row(
+        &format!("SELECT {} FROM contracts WHERE id = ?1", CONTRACT_RECORD_COLUMNS),
+        params![id],
+        contract_record_from_row,
+    )
+    .optional()?
+    .ok_or_else(|| ApiError::NotFound(format!("contract {} not found", id)))
+}
+
+pub fn load_contracts_to_announce(conn: &Connection, now: i64, until: i64) -> ApiResult<Vec<ContractRecord>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM contracts
+         WHERE status = ?1 AND expiry_notified_at IS NULL AND expires > ?2 AND expires <= ?3
+         ORDER BY expires ASC",
+        CONTRACT_RECORD_COLUMNS
+    ))?;
+    let rows = stmt.query_map(params![ContractStatus::Open, now, until], contract_record_from_row)?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
+
+fn to_json(record: &ContractRecord) -> ApiResult<serde_json::Value> {
+    serde_json::to_value(record).map_err(|e| ApiError::DatabaseError(e.to_string()))
+}
+
+// Records a status change of each contract, from its snapshot in `before` to its current row
+pub(crate) fn audit_transitions(conn: &Connection, actor: &str, action: &str, before: &[ContractRecord]) -> ApiResult<()> {
+    for pre in before {
+        let post = load_contract_record(conn, pre.id)?;
+        audit::record(conn, actor, action, Some(pre.id), Some(&to_json(pre)?), Some(&to_json(&post)?))?;
+    }
+    Ok(())
+}
+
+/// Mark open contracts whose expiry has passed as expired, auditing each under `actor`.
+/// Returns the number updated.
+pub fn expire_contracts(conn: &Connection, now: i64, actor: &str) -> ApiResult<usize> {
+    let tx = conn.unchecked_transaction()?;
+    let before = {
+        let mut stmt = tx.prepare(&format!(
+            "SELECT {} FROM contracts WHERE status = ?1 AND expires <= ?2 ORDER BY id ASC",
+            CONTRACT_RECORD_COLUMNS
+        ))?;
+        let rows = stmt.query_map(params![ContractStatus::Open, now], contract_record_from_row)?;
+        rows.collect::<Result<Vec<_>, _>>()?
+    };
+    let updated = tx.execute(
+        "UPDATE contracts SET status = ?1 WHERE status = ?2 AND expires <= ?3",
+        params![ContractStatus::Expired, ContractStatus::Open, now],
+    )?;
+    audit_transitions(&tx, actor, audit::CONTRACT_EXPIRE, &before)?;
+    tx.commit()?;
+    Ok(updated)
+}
+
+/// Settle every expired contract on `underlying` at `settlement_price`, recording `btc_price`
+/// for converting payoffs into each contract's premium currency. Each settlement is audited
+/// under `actor`. Returns the settled contracts.
+pub fn settle_expired_contracts(
+    conn: &Connection,
+    underlying: Asset,
+    settlement_price: f64,
+    btc_price: f64,
+    now: i64,
+    actor: &str,
+) -> ApiResult<Vec<ContractRecord>> {
+    settle_expired(conn, underlying, None, settlement_price, btc_price, now, actor)
+}
+
+/// Settle the expired contracts on `underlying` maturing at `expires`, as
+/// `settle_expired_contracts`
+pub fn settle_expired_maturity(
+    conn: &Connection,
+    underlying: Asset,
+    expires: i64,
+    settlement_price: f64,
+    btc_price: f64,
+    now: i64,
+    actor: &str,
+) -> ApiResult<Vec<ContractRecord>> {
+    settle_expired(conn, underlying, Some(expires), settlement_price, btc_price, now, actor)
+}
+
+/// Maturities of contracts on `underlying` that have expired and await settlement, oldest first
+pub fn expired_maturities(conn: &Connection, underlying: Asset) -> ApiResult<Vec<i64>> {
+    let mut stmt = conn.prepare(
+        "SELECT DISTINCT expires FROM contracts WHERE status = ?1 AND underlying = ?2 ORDER BY expires ASC",
+    )?;
+    let rows = stmt.query_map(params![ContractStatus::Expired, underlying], |row| row.get(0))?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+fn settle_expired(
+    conn: &Connection,
+    underlying: Asset,
+    expires: Option<i64>,
+    settlement_price: f64,
+    btc_price: f64,
+    now: i64,
+    actor: &str,
+) -> ApiResult<Vec<ContractRecord>> {
+    let tx = conn.unchecked_transaction()?;
+    let before = {
+        let mut stmt = tx.prepare(&format!(
+            "SELECT {} FROM contracts WHERE status = ?1 AND underlying = ?2 AND (?3 IS NULL OR expires = ?3) ORDER BY id ASC",
+            CONTRACT_RECORD_COLUMNS
+        ))?;
+        let rows = stmt.query_map(params![ContractStatus::Expired, underlying, expires], contract_record_from_row)?;
+        rows.collect::<Result<Vec<_>, _>>()?
+    };
+    // The ids this update settled, not whatever else was settled in the same second
+    let mut ids = {
+        let mut stmt = tx.prepare(
+            "UPDATE contracts SET status = ?1, settlement_price_cents = ?2, settlement_btc_price_cents = ?3, settled_at = ?4
+             WHERE status = ?5 AND underlying = ?6 AND (?7 IS NULL OR expires = ?7)
+             RETURNING id",
+        )?;
+        let rows = stmt.query_map(
+            params![
+                ContractStatus::Settled,
+                usd_to_cents(settlement_price),
+                usd_to_cents(btc_price),
+                now,
+                ContractStatus::Expired,
+                underlying,
+                expires
+            ],
+            |row| row.get::<_, i64>(0),
+        )?;
+        rows.collect::<Result<Vec<_>, _>>()?
+    };
+    ids.sort_unstable();
+    let before: Vec<ContractRecord> = before.into_iter().filter(|contract| ids.binary_search(&contract.id).is_ok()).collect();
+    let settled = ids.iter().map(|&id| load_contract_record(&tx, id)).collect::<ApiResult<Vec<_>>>()?;
+    for contract in &settled {
+        record_payout(&tx, contract, settlement_price, btc_price, now)?;
+        let event = serde_json::json!({
+            "contract": contract,
+            "payoff_usd": contract.payoff_usd(settlement_price),
+            "payoff": contract.settlement_payoff().unwrap_or_default(),
+        });
+        outbox::record(&tx, CONTRACT_SETTLED, Some(contract.id), &event)?;
+    }
+    audit_transitions(&tx, actor, audit::CONTRACT_SETTLE, &before)?;
+    tx.commit()?;
+    Ok(settled)
+}
+
+// Record the payoff owed on `contract` at `settlement_price`, if any
+fn record_payout(conn: &Connection, contract: &ContractRecord, settlement_price: f64, btc_price: f64, now: i64) -> ApiResult<()> {
+    let payoff_usd = contract.payoff_usd(settlement_price);
+    if payoff_usd <= 0.0 {
+        return Ok(());
+    }
+    conversions::record_usd(conn, contract.id, ConversionKind::Payout, contract.premium_currency, payoff_usd, btc_price, now)
+}
+
+fn contract_not_open(id: i64, status: ContractStatus) -> ApiError {
+    ApiError::ValidationError(format!("Contract {} is {}, not open", id, status))
+        .with_code(ErrorCode::ContractNotOpen)
+        .with_details(serde_json::json!({"contract_id": id, "status": status}))
+}
+
+/// Exercise open contract `id` at `settlement_price`, auditing the change under `actor`
+pub fn exercise_contract(
+    conn: &Connection,
+    id: i64,
+    settlement_price: f64,
+    btc_price: f64,
+    now: i64,
+    actor: &str,
+) -> ApiResult<ContractRecord> {
+    let tx = conn.unchecked_transaction()?;
+    let before = load_contract_record(&tx, id)?;
+    let updated = tx.execute(
+        "UPDATE contracts SET status = ?1, settlement_price_cents = ?2, settlement_btc_price_cents = ?3, settled_at = ?4
+         WHERE id = ?5 AND status = ?6",
+        params![
+            ContractStatus::Exercised,
+            usd_to_cents(settlement_price),
+            usd_to_cents(btc_price),
+            now,
+            id,
+            ContractStatus::Open
+        ],
+    )?;
+    if updated == 0 {
+        return Err(contract_not_open(id, before.status));
+    }
+    audit_transitions(&tx, actor, audit::CONTRACT_EXERCISE, std::slice::from_ref(&before))?;
+    let exercised = load_contract_record(&tx, id)?;
+    record_payout(&tx, &exercised, settlement_price, btc_price, now)?;
+    let event = serde_json::json!({
+        "contract": exercised,
+        "payoff_usd": exercised.payoff_usd(settlement_price),
+        "payoff": exercised.settlement_payoff().unwrap_or_default(),
+    });
+    outbox::record(&tx, CONTRACT_EXERCISED, Some(id), &event)?;
+    tx.commit()?;
+    Ok(exercised)
+}
+
+/// Close `quantity_sats` of open contract `id` at `price` (USD per unit), recording the close
+/// and auditing it under `actor`. A contract closed in full moves to Closed.
+#[allow(clippy::too_many_arguments)]
+pub fn close_contract(
+    conn: &Connection,
+    id: i64,
+    quantity_sats: i64,
+    price: f64,
+    btc_price: f64,
+    now: i64,
+    actor: &str,
+) -> ApiResult<ContractRecord> {
+    let tx = conn.unchecked_transaction()?;
+    let before = load_contract_record(&tx, id)?;
+    let open_sats = btc_to_sats(before.open_quantity());
+    if before.status != ContractStatus::Open {
+        return Err(contract_not_open(id, before.status));
+    }
+    if quantity_sats <= 0 || quantity_sats > open_sats {
+        return Err(ApiError::ValidationError(format!(
+            "Close quantity {} must be positive and at most the open quantity {}",
+            format_sats(quantity_sats),
+            format_sats(open_sats)
+        )));
+    }
+    tx.execute(
+        "UPDATE contracts SET closed_quantity_sats = closed_quantity_sats + ?1,
+                              status = CASE WHEN closed_quantity_sats + ?1 >= quantity_sats THEN ?2 ELSE status END
+         WHERE id = ?3",
+        params![quantity_sats, ContractStatus::Closed, id],
+    )?;
+    tx.execute(
+        "INSERT INTO contract_closes (contract_id, quantity_sats, price_cents, btc_price_cents, actor, closed_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![id, quantity_sats, usd_to_cents(price), usd_to_cents(btc_price), actor, now],
+    )?;
+    let proceeds_usd = price * sats_to_btc(quantity_sats);
+    conversions::record_usd(&tx, id, ConversionKind::Close, before.premium_currency, proceeds_usd, btc_price, now)?;
+    audit_transitions(&tx, actor, audit::CONTRACT_CLOSE, std::slice::from_ref(&before))?;
+    let closed = load_contract_record(&tx, id)?;
+    tx.commit()?;
+    Ok(closed)
+}
+
+/// Insert a contract and record its premium in premium_history. Returns the contract id.
+/// `quote` is the premium as agreed with the buyer; without one the premium was quoted in BTC.
+pub fn insert_contract(conn: &Connection, contract: &Contract, quote: Option<&PremiumQuote>) -> ApiResult<i64> {
+    let premium_sats = btc_to_sats(contract.premium);
+    let premium_currency = quote.map(|q| q.currency).unwrap_or_default();
+
+    conn.execute(
+        "INSERT INTO contracts (side, strike_price_cents, quantity_sats, expires, premium_sats, underlying,
+                                premium_currency, quoted_premium_minor, trade_btc_price_cents)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            contract.side,
+            usd_to_cents(contract.strike_price),
+            btc_to_sats(contract.quantity),
+            contract.expires,
+            premium_sats,
+            contract.underlying,
+            premium_currency,
+            quote.map(|q| q.currency.to_minor(q.amount)),
+            quote.map(|q| usd_to_cents(q.btc_price))
+        ],
+    )?;
+    let id = conn.last_insert_rowid();
+
+    // Save to premium history
+    let product_key = product_key(contract.underlying, &contract.side, usd_to_cents(contract.strike_price), contract.expires);
+    let _ = conn.execute(
+        "INSERT OR REPLACE INTO premium_history (product_key, side, strike_price_cents, expires, premium_sats)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            product_key,
+            contract.side,
+            usd_to_cents(contract.strike_price),
+            contract.expires,
+            premium_sats
+        ],
+    );
+
+    Ok(id)
+}
+
+/// premium_history key of a product. BTC products keep the pre multi-asset
+/// "side-strike-expires" form so their existing history stays reachable.
+pub fn product_key(underlying: Asset, side: &OptionSide, strike_price_cents: i64, expires: i64) -> String {
+    match underlying {
+        Asset::Btc => format!("{}-{}-{}", side, strike_price_cents, expires),
+        _ => format!("{}-{}-{}-{}", underlying, side, strike_price_cents, expires),
+    }
+}
+
+// The analytics queries below take an optional underlying; None covers every asset.
+
+/// Total quantity of contracts created since `since`
+pub fn volume_since(conn: &Connection, since: i64, asset: Option<Asset>) -> ApiResult<f64> {
+    let volume_sats: i64 = conn.query_row(
+        "SELECT COALESCE(SUM(quantity_sats), 0) FROM contracts
+         WHERE created_at >= ?1 AND (?2 IS NULL OR underlying = ?2)",
+        params![since, asset],
+        |row| row.get(0),
+    )?;
+    Ok(sats_to_btc(volume_sats))
+}
+
+/// Open interest in BTC (open quantity * premium) of open contracts expiring after `now`
+pub fn open_interest_btc(conn: &Connection, now: i64, asset: Option<Asset>) -> ApiResult<f64> {
+    // TOTAL is floating point, so sat * sat products cannot overflow the sum
+    let open_interest_sats_squared: f64 = conn.query_row(
+        "SELECT TOTAL((quantity_sats - closed_quantity_sats) * premium_sats) FROM contracts
+         WHERE expires > ?1 AND status = 'open' AND (?2 IS NULL OR underlying = ?2)",
+        params![now, asset],
+        |row| row.get(0),
+    )?;
+    Ok(open_interest_sats_squared / (SATS_PER_BTC as f64 * SATS_PER_BTC as f64))
+}
+
+/// Number of open contracts expiring after `now`
+pub fn active_contract_count(conn: &Connection, now: i64, asset: Option<Asset>) -> ApiResult<i64> {
+    Ok(conn.query_row(
+        "SELECT COUNT(*) FROM contracts WHERE expires > ?1 AND status = 'open' AND (?2 IS NULL OR underlying = ?2)",
+        params![now, asset],
+        |row| row.get(0),
+    )?)
+}
+
+/// Products traded since `since`, ranked by total quantity
+pub fn product_volume_by_quantity(conn: &Connection, since: i64, limit: i64, asset: Option<Asset>) -> ApiResult<Vec<ProductVolume>> {
+    query_product_volume(
+        conn,
+        "SELECT side, strike_price_cents, expires,
+                SUM(quantity_sats) / 1e8 as total_volume,
+                AVG(premium_sats) / 1e8 as avg_premium,
+                underlying
+         FROM contracts
+         WHERE created_at >= ?1 AND (?3 IS NULL OR underlying = ?3)
+         GROUP BY underlying, side, strike_price_cents, expires
+         ORDER BY total_volume DESC
+         LIMIT ?2",
+        since,
+        limit,
+        asset,
+    )
+}
+
+/// Products traded since `since`, ranked by BTC notional (quantity * premium)
+pub fn product_volume_by_notional(conn: &Connection, since: i64, limit: i64, asset: Option<Asset>) -> ApiResult<Vec<ProductVolume>> {
+    query_product_volume(
+        conn,
+        "SELECT side, strike_price_cents, expires,
+                TOTAL(quantity_sats * premium_sats) / 1e16 as total_volume_btc,
+                AVG(premium_sats) / 1e8 as avg_premium,
+                underlying
+         FROM contracts
+         WHERE created_at >= ?1 AND (?3 IS NULL OR underlying = ?3)
+         GROUP BY underlying, side, strike_price_cents, expires
+         ORDER BY total_volume_btc DESC
+         LIMIT ?2",
+        since,
+        limit,
+        asset,
+    )
+}
+
+fn query_product_volume(conn: &Connection, sql: &str, since: i64, limit: i64, asset: Option<Asset>) -> ApiResult<Vec<ProductVolume>> {
+    let mut stmt = conn.prepare(sql)?;
+
+    let products_iter = stmt.query_map(params![since, limit, asset], |row| {
+        Ok(ProductVolume {
+            underlying: row.get(5)?,
+            side: row.get(0)?,
+            strike_price_cents: row.get(1)?,
+            expires: row.get(2)?,
+            volume: row.get(3)?,
+            avg_premium: row.get(4)?,
+        })
+    })?;
+
+    Ok(products_iter.flatten().collect())
+}
+
+/// Most recent premium recorded for a product at or before `timestamp`
+pub fn premium_at_or_before(conn: &Connection, product_key: &str, timestamp: i64) -> Option<f64> {
+    conn.query_row(
+        "SELECT premium_sats FROM premium_history
+         WHERE product_key = ?1 AND timestamp <= ?2
+         ORDER BY timestamp DESC LIMIT 1",
+        params![product_key, timestamp],
+        |row| row.get(0),
+    )
+    .ok()
+    .map(sats_to_btc)
+}
+
+// Grouped over idx_contracts_product, which covers it
+const PRODUCT_PREMIUM_CHANGES_SQL: &str = "SELECT p.underlying, p.side, p.strike_price_cents, p.expires, latest.premium_sats,
+            COALESCE(before.premium_sats, first.premium_sats)
+     FROM (SELECT underlying, side, strike_price_cents, expires,
+                  MAX(id) AS latest_id,
+                  MAX(CASE WHEN created_at < ?2 THEN id END) AS before_id,
+                  MIN(CASE WHEN created_at >= ?2 THEN id END) AS first_id
+           FROM contracts
+           WHERE expires > ?1 AND (?3 IS NULL OR underlying = ?3)
+           GROUP BY underlying, side, strike_price_cents, expires) p
+     JOIN contracts latest ON latest.id = p.latest_id
+     LEFT JOIN contracts before ON before.id = p.before_id
+     LEFT JOIN contracts first ON first.id = p.first_id
+     ORDER BY p.underlying, p.expires, p.strike_price_cents, p.side";
+
+/// Latest premium of every product expiring after `now`, with the premium its change over the
+/// window starting at `since` is measured from: the last one traded before the window, or for
+/// products first traded in it, the first one. One pass over contracts, joined back by id.
+pub fn product_premium_changes(conn: &Connection, now: i64, since: i64, asset: Option<Asset>) -> ApiResult<Vec<ProductPremiumChange>> {
+    let mut stmt = conn.prepare(PRODUCT_PREMIUM_CHANGES_SQL)?;
+    let changes = stmt.query_map(params![now, since, asset], |row| {
+        Ok(ProductPremiumChange {
+            underlying: row.get(0)?,
+            side: row.get(1)?,
+            strike_price_cents: row.get(2)?,
+            expires: row.get(3)?,
+            current_premium: sats_to_btc(row.get(4)?),
+            baseline_premium: sats_to_btc(row.get(5)?),
+        })
+    })?;
+    Ok(changes.collect::<Result<_, _>>()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::create_in_memory_pool;
+    use crate::models::{IvProvenance, QuoteCurrency};
+    use chrono::Utc;
+
+    fn test_repository() -> Repository {
+        Repository::new(create_in_memory_pool().unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_insert_and_load_contracts() {
+        let repo = test_repository();
+        let now = Utc::now().timestamp();
+
+        let contract = Contract {
+            underlying: Asset::Btc,
+            side: OptionSide::Put,
+            strike_price: 100000.0,
+            quantity: 0.123456789,
+            expires: now + 86400,
+            premium: 0.001,
+        };
+        repo.insert_contract(contract.clone()).await.unwrap();
+        repo.insert_contract(Contract { expires: now - 10, ..contract }).await.unwrap();
+
+        let active = repo.active_contracts(now).await.unwrap();
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0].quantity, 0.12345679);
+
+        let all = repo.all_contracts().await.unwrap();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].strike_price_cents, 10000000);
+
+        // A negative quantity booked before quantities were validated is not a long of the pool
+        repo.insert_contract(Contract { quantity: -0.5, ..contract }).await.unwrap();
+        assert_eq!(repo.active_contracts(now).await.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_product_premium_changes() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init_db(&conn).unwrap();
+        let call = Contract {
+            underlying: Asset::Btc,
+            side: OptionSide::Call,
+            strike_price: 100_000.0,
+            quantity: 1.0,
+            expires: 10_000,
+            premium: 0.01,
+        };
+        let put = Contract { side: OptionSide::Put, ..call.clone() };
+        let expired = Contract { expires: 1_000, ..call.clone() };
+        // The call trades before and in the window, the put only in it
+        for (contract, premium, created_at) in [
+            (&call, 0.01, 100),
+            (&call, 0.0125, 200),
+            (&put, 0.02, 600),
+            (&call, 0.015, 700),
+            (&put, 0.025, 800),
+            (&call, 0.0175, 900),
+            (&expired, 0.03, 600),
+        ] {
+            let id = insert_contract(&conn, &Contract { premium, ..contract.clone() }, None).unwrap();
+            conn.execute("UPDATE contracts SET created_at = ?1 WHERE id = ?2", params![created_at, id]).unwrap();
+        }
+
+        let changes = product_premium_changes(&conn, 1_500, 500, None).unwrap();
+        let summary: Vec<_> = changes.iter().map(|c| (c.side, c.current_premium, c.baseline_premium)).collect();
+        assert_eq!(summary, vec![(OptionSide::Call, 0.0175, 0.0125), (OptionSide::Put, 0.025, 0.02)]);
+        assert!(product_premium_changes(&conn, 1_500, 500, Some(Asset::Eth)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_product_premium_changes_use_the_product_index() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init_db(&conn).unwrap();
+        let plan = crate::db::query_plan(&conn, PRODUCT_PREMIUM_CHANGES_SQL).unwrap();
+        assert!(plan.contains(&"SCAN contracts USING COVERING INDEX idx_contracts_product".to_string()), "{:?}", plan);
+        // Grouped in index order, and joined back by id
+        assert!(!plan.iter().any(|step| step.contains("GROUP BY")), "{:?}", plan);
+        assert_eq!(plan.iter().filter(|step| step.contains("USING INTEGER PRIMARY KEY")).count(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_analytics_filter_by_underlying() {
+        let repo = test_repository();
+        let now = Utc::now().timestamp();
+
+        let btc = Contract {
+            underlying: Asset::Btc,
+            side: OptionSide::Call,
+            strike_price: 100000.0,
+            quantity: 1.0,
+            expires: now + 86400,
+            premium: 0.01,
+        };
+        let eth = Contract { underlying: Asset::Eth, strike_price: 3500.0, quantity: 5.0, ..btc.clone() };
+        repo.insert_contract(btc).await.unwrap();
+        repo.insert_contract(eth).await.unwrap();
+
+        let (all, eth_volume, eth_count, products, changes) = repo
+            .run(move |conn| {
+                Ok((
+                    volume_since(conn, now - 60, None)?,
+                    volume_since(conn, now - 60, Some(Asset::Eth))?,
+                    active_contract_count(conn, now, Some(Asset::Eth))?,
+                    product_volume_by_quantity(conn, now - 60, 10, None)?,
+                    product_premium_changes(conn, now, now - 60, Some(Asset::Btc))?,
+                ))
+            })
+            .await
+            .unwrap();
+        assert_eq!(all, 6.0);
+        assert_eq!(eth_volume, 5.0);
+        assert_eq!(eth_count, 1);
+        assert_eq!(products[0].underlying, Asset::Eth);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].underlying, Asset::Btc);
+
+        let loaded = repo.active_contracts(now).await.unwrap();
+        assert_eq!(loaded[1].underlying, Asset::Eth);
+        assert_eq!(product_key(Asset::Btc, &OptionSide::Put, 100, 5), "Put-100-5");
+        assert_eq!(product_key(Asset::Eth, &OptionSide::Put, 100, 5), "ETH-Put-100-5");
+    }
+
+    #[tokio::test]
+    async fn test_reservations_serialize_trades_across_instances() {
+        let repo = test_repository();
+        let now = Utc::now().timestamp();
+
+        let contract = Contract {
+            underlying: Asset::Btc,
+            side: OptionSide::Call,
+            strike_price: 120000.0,
+            quantity: 1.0,
+            expires: now + 86400,
+            premium: 0.01,
+        };
+
+        // Capacity for a single contract: only one of the racing trades may succeed. Each
+        // races through its own repository, as separate instances sharing the database would.
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let repo = Repository::new(repo.pool().clone());
+                let contract = contract.clone();
+                tokio::spawn(async move {
+                    let (reservation, ()) = repo
+                        .reserve_collateral(1, contract.clone(), "test".to_string(), now, Duration::from_secs(30), |_, book| {
+                            if book.contracts().is_empty() {
+                                Ok((ReservedMargin { margin_usd: 1000.0, available_usd: 1000.0 }, ()))
+                            } else {
+                                Err(ApiError::ValidationError("pool is full".to_string()))
+                            }
+                        })
+                        .await?;
+                    // Work between the check and the insert, e.g. issuing an invoice
+                    tokio::task::yield_now().await;
+                    let quote = PremiumQuote::new(QuoteCurrency::Btc, contract.premium, 100000.0);
+                    let snapshot = TradeSnapshot {
+                        spot_price: 100000.0,
+                        iv: 0.5,
+                        iv_source: IvProvenance::Deribit,
+                        mark_premium: 0.01,
+                    };
+                    repo.insert_reserved_contract(reservation, 1, contract, quote, snapshot, ExerciseStyle::European, None, "test".to_string(), now, |_, _| Ok(()))
+                        .await
+                })
+            })
+            .collect();
+
+        let mut succeeded = 0;
+        for handle in handles {
+            if handle.await.unwrap().is_ok() {
+                succeeded += 1;
+            }
+        }
+
+        assert_eq!(succeeded, 1);
+        assert_eq!(repo.active_contracts(now).await.unwrap().len(), 1);
+        let confirmed: i64 = repo
+            .run(|conn| Ok(conn.query_row("SELECT COUNT(*) FROM collateral_reservations WHERE status = 'confirmed'", [], |row| row.get(0))?))
+            .await
+            .unwrap();
+        assert_eq!(confirmed, 1);
+        // Only the committed trade has an event
+        let events = repo.run(|conn| Ok(outbox::pending(conn, 10)?)).await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!((events[0].event.as_str(), events[0].data["contract"]["status"].as_str()), (TRADE_CREATED, Some("open")));
+    }
+
+    #[tokio::test]
+    async fn test_expire_and_settle_contracts() {
+        let repo = test_repository();
+        let now = Utc::now().timestamp();
+
+        let contract = Contract {
+            underlying: Asset::Btc,
+            side: OptionSide::Put,
+            strike_price: 100000.0,
+            quantity: 0.5,
+            expires: now - 60,
+            premium: 0.002,
+        };
+        repo.insert_contract(contract.clone()).await.unwrap();
+        repo.insert_contract(Contract { expires: now + 3600, ..contract }).await.unwrap();
+
+        let settled = repo
+            .run(move |conn| {
+                assert_eq!(expire_contracts(conn, now, "test")?, 1);
+                settle_expired_contracts(conn, Asset::Btc, 95000.0, 95000.0, now, "test")
+            })
+            .await
+            .unwrap();
+        assert_eq!(settled.len(), 1);
+        assert_eq!(settled[0].status, ContractStatus::Settled);
+        assert_eq!(settled[0].settlement_price, Some(95000.0));
+        assert_eq!(settled[0].payoff_usd(95000.0), 2500.0);
+        assert_eq!(settled[0].settlement_payoff(), Some(2500.0 / 95000.0));
+
+        let open = repo
+            .run(|conn| load_contract_records(conn, Some(ContractStatus::Open)))
+            .await
+            .unwrap();
+        assert_eq!(open.len(), 1);
+
+        // Both transitions are in the audit log with before and after snapshots
+        let entries = repo.audit_entries(AuditFilter::default()).await.unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].action, audit::CONTRACT_SETTLE);
+        assert_eq!(entries[0].pre_state.as_ref().unwrap()["status"], "expired");
+        assert_eq!(entries[0].post_state.as_ref().unwrap()["status"], "settled");
+        assert_eq!(entries[1].action, audit::CONTRACT_EXPIRE);
+        assert_eq!(entries[1].pre_state.as_ref().unwrap()["status"], "open");
+
+        // The settlement is in the outbox with its payoff
+        let events = repo.run(|conn| Ok(outbox::pending(conn, 10)?)).await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event, CONTRACT_SETTLED);
+        assert_eq!(events[0].data["contract"]["id"], settled[0].id);
+        assert_eq!(events[0].data["payoff_usd"], 2500.0);
+    }
+
+    #[tokio::test]
+    async fn test_settle_by_maturity() {
+        let repo = test_repository();
+        let now = Utc::now().timestamp();
+
+        let contract = Contract {
+            underlying: Asset::Btc,
+            side: OptionSide::Call,
+            strike_price: 100000.0,
+            quantity: 0.5,
+            expires: now - 7200,
+            premium: 0.002,
+        };
+        repo.insert_contract(contract.clone()).await.unwrap();
+        repo.insert_contract(Contract { expires: now - 60, ..contract }).await.unwrap();
+
+        // Each maturity settles at its own price
+        let (maturities, settled) = repo
+            .run(move |conn| {
+                expire_contracts(conn, now, "test")?;
+                let maturities = expired_maturities(conn, Asset::Btc)?;
+                let settled = settle_expired_maturity(conn, Asset::Btc, now - 7200, 101000.0, 101000.0, now, "test")?;
+                Ok((maturities, settled))
+            })
+            .await
+            .unwrap();
+        assert_eq!(maturities, vec![now - 7200, now - 60]);
+        assert_eq!(settled.len(), 1);
+        assert_eq!(settled[0].settlement_price, Some(101000.0));
+        let remaining = repo.run(|conn| expired_maturities(conn, Asset::Btc)).await.unwrap();
+        assert_eq!(remaining, vec![now - 60]);
+
+        // Settling the next maturity in the same second reports only its own contract
+        let settled = repo
+            .run(move |conn| settle_expired_maturity(conn, Asset::Btc, now - 60, 99000.0, 99000.0, now, "test"))
+            .await
+            .unwrap();
+        assert_eq!(settled.len(), 1);
+        assert_eq!((settled[0].expires, settled[0].settlement_price), (now - 60, Some(99000.0)));
+    }
+
+    #[tokio::test]
+    async fn test_close_contract_in_parts() {
+        let repo = test_repository();
+        let now = Utc::now().timestamp();
+
+        let contract = Contract {
+            underlying: Asset::Btc,
+            side: OptionSide::Call,
+            strike_price: 110000.0,
+            quantity: 1.0,
+            expires: now + 86400,
+            premium: 0.01,
+        };
+        repo.insert_contract(contract).await.unwrap();
+
+        let closed = repo.close_contract(1, 25_000_000, 800.0, 100000.0, now, "desk".to_string()).await.unwrap();
+        assert_eq!(closed.status, ContractStatus::Open);
+        assert_eq!(closed.closed_quantity, 0.25);
+        assert_eq!(closed.open_quantity(), 0.75);
+        assert_eq!(repo.active_contracts(now).await.unwrap()[0].quantity, 0.75);
+        let open_interest = repo.run(move |conn| open_interest_btc(conn, now, None)).await.unwrap();
+        assert!((open_interest - 0.0075).abs() < 1e-12);
+        // Open interest in the stats snapshots is net of the close once it has happened
+        let bucket = crate::stats::bucket_start(now);
+        let stats = repo
+            .run(move |conn| crate::stats::compute_market_stats(conn, Asset::Btc, bucket, 100000.0))
+            .await
+            .unwrap();
+        assert_eq!(stats.open_interest, 0.75);
+        assert_eq!(stats.volume, 1.0);
+
+        assert!(repo.close_contract(1, 75_000_001, 800.0, 100000.0, now, "desk".to_string()).await.is_err());
+        let closed = repo.close_contract(1, 75_000_000, 900.0, 100000.0, now, "desk".to_string()).await.unwrap();
+        assert_eq!(closed.status, ContractStatus::Closed);
+        assert!(repo.active_contracts(now).await.unwrap().is_empty());
+        assert!(repo.close_contract(1, 1, 900.0, 100000.0, now, "desk".to_string()).await.is_err());
+
+        let entries = repo.audit_entries(AuditFilter::default()).await.unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].action, audit::CONTRACT_CLOSE);
+        assert_eq!(entries[0].post_state.as_ref().unwrap()["status"], "closed");
+    }
+
+    #[tokio::test]
+    async fn test_usd_quoted_contract_records_conversions() {
+        let repo = test_repository();
+        let now = Utc::now().timestamp();
+
+        // $500 premium on an ETH put, converted at $100k BTC
+        let quote = PremiumQuote::new(QuoteCurrency::Usd, 500.0, 100000.0);
+        let contract = Contract {
+            underlying: Asset::Eth,
+            side: OptionSide::Put,
+            strike_price: 3500.0,
+            quantity: 2.0,
+            expires: now - 60,
+            premium: quote.premium_btc(),
+        };
+        let snapshot = TradeSnapshot { spot_price: 3400.0, iv: 0.6, iv_source: IvProvenance::Interpolated, mark_premium: 0.0048 };
+        let margin = ReservedMargin { margin_usd: 0.0, available_usd: 0.0 };
+        let (reservation, ()) = repo
+            .reserve_collateral(1, contract.clone(), "test".to_string(), now - 120, Duration::from_secs(30), move |_, _| Ok((margin, ())))
+            .await
+            .unwrap();
+        repo.insert_reserved_contract(reservation, 1, contract, quote, snapshot, ExerciseStyle::European, None, "test".to_string(), now - 120, |_, _| Ok(()))
+            .await
+            .unwrap();
+
+        let stored = repo.all_contracts().await.unwrap();
+        assert_eq!(stored[0].premium_sats, 500_000);
+        assert_eq!(stored[0].premium_currency, QuoteCurrency::Usd);
+        assert_eq!(stored[0].quoted_premium_minor, Some(50_000));
+        assert_eq!(stored[0].trade_btc_price_cents, Some(10000000));
+        // The inserting actor is recorded as the counterparty
+        let counterparty_contracts = repo
+            .run(move |conn| load_counterparty_contracts(conn, "test", now - 120))
+            .await
+            .unwrap();
+        assert_eq!(counterparty_contracts.len(), 1);
+
+        let (btc_settled, eth_settled) = repo
+            .run(move |conn| {
+                expire_contracts(conn, now, "test")?;
+                Ok((
+                    settle_expired_contracts(conn, Asset::Btc, 90000.0, 90000.0, now, "test")?,
+                    settle_expired_contracts(conn, Asset::Eth, 3000.0, 90000.0, now, "test")?,
+                ))
+            })
+            .await
+            .unwrap();
+        assert!(btc_settled.is_empty());
+        assert_eq!(eth_settled.len(), 1);
+        assert_eq!(eth_settled[0].quoted_premium, Some(500.0));
+        assert_eq!(eth_settled[0].trade_btc_price, Some(100000.0));
+        // The market at trade time stays with the contract
+        assert_eq!(eth_settled[0].spot_at_trade, Some(3400.0));
+        assert_eq!(eth_settled[0].iv_at_trade, Some(0.6));
+        assert_eq!(eth_settled[0].iv_source, Some(IvProvenance::Interpolated));
+        assert_eq!(eth_settled[0].mark_premium_at_trade, Some(0.0048));
+        assert!((eth_settled[0].edge_at_trade().unwrap() - 0.0002).abs() < 1e-12);
+        assert_eq!(eth_settled[0].settlement_btc_price, Some(90000.0));
+        // USD-quoted payoffs stay in USD whatever BTC did
+        assert_eq!(eth_settled[0].settlement_payoff(), Some(1000.0));
+
+        // The premium and the payout are recorded with the BTC price each was converted at
+        let conversions = repo.run(|conn| conversions::load_conversions(conn, 1)).await.unwrap();
+        assert_eq!(conversions.len(), 2);
+        assert_eq!(conversions[0].kind, ConversionKind::Premium);
+        assert_eq!(conversions[0].currency, QuoteCurrency::Usd);
+        assert_eq!((conversions[0].amount.as_str(), conversions[0].btc.as_str()), ("1000.00", "0.01000000"));
+        assert_eq!((conversions[0].usd, conversions[0].btc_price, conversions[0].created_at), (1000.0, 100000.0, now - 120));
+        assert_eq!(conversions[1].kind, ConversionKind::Payout);
+        assert_eq!((conversions[1].amount.as_str(), conversions[1].btc.as_str()), ("1000.00", "0.01111111"));
+        assert_eq!((conversions[1].btc_price, conversions[1].created_at), (90000.0, now));
+    }
+
+    #[tokio::test]
+    async fn test_exercise_and_close_record_conversions() {
+        let repo = test_repository();
+        let now = Utc::now().timestamp();
+
+        let contract = Contract {
+            underlying: Asset::Btc,
+            side: OptionSide::Call,
+            strike_price: 100000.0,
+            quantity: 1.0,
+            expires: now + 86400,
+            premium: 0.01,
+        };
+        repo.insert_contract(contract).await.unwrap();
+
+        // $800 for a quarter, paid in BTC at $100k; the rest exercised at $110k with BTC at $110k
+        repo.close_contract(1, 25_000_000, 800.0, 100000.0, now, "desk".to_string()).await.unwrap();
+        repo.exercise_contract(1, 110000.0, 110000.0, now + 60, "buyer".to_string()).await.unwrap();
+        let events = repo.run(|conn| Ok(outbox::pending(conn, 10)?)).await.unwrap();
+        assert_eq!(events.iter().map(|e| e.event.as_str()).collect::<Vec<_>>(), vec![CONTRACT_EXERCISED]);
+        assert_eq!(events[0].data["payoff_usd"], 7500.0);
+
+        let conversions = repo.run(|conn| conversions::load_conversions(conn, 1)).await.unwrap();
+        let flows: Vec<_> = conversions.iter().map(|c| (c.kind, c.btc.as_str(), c.usd, c.btc_price)).collect();
+        assert_eq!(
+            flows,
+            vec![
+                (ConversionKind::Close, "0.00200000", 200.0, 100000.0),
+                (ConversionKind::Payout, "0.06818182", 7500.0, 110000.0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,100 @@
+// Request ID correlation.
+// Every request gets an ID: the caller's X-Request-Id when it sends a usable one, otherwise a
+// fresh random one. The ID is echoed in the X-Request-Id response header and in error bodies,
+// recorded on the request's tracing span and access log line, and forwarded on the calls made
+// while serving the request: as the X-Request-Id header to Deribit and the Mutiny API, and as
+// x-request-id metadata to the price aggregator. Background work has no request ID.
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::Error;
+use std::future::Future;
+use tracing::Instrument;
+
+pub const HEADER: &str = "x-request-id";
+
+const MAX_LEN: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// ID of the request being served, if any
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Run `future` as part of the request `id`
+pub async fn scope<F: Future>(id: String, future: F) -> F::Output {
+    REQUEST_ID.scope(id, future).await
+}
+
+/// A random 128-bit ID, hex encoded
+pub fn generate() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
+
+// IDs from callers are kept if they are short and printable, so they are safe to log and echo
+fn accept(value: &str) -> Option<String> {
+    let valid = !value.is_empty() && value.len() <= MAX_LEN && value.bytes().all(|b| b.is_ascii_graphic());
+    valid.then(|| value.to_string())
+}
+
+/// Wrap a gRPC message, tagging it with the current request ID
+pub fn grpc_request<T>(message: T) -> tonic::Request<T> {
+    let mut request = tonic::Request::new(message);
+    if let Some(value) = current().and_then(|id| id.parse().ok()) {
+        request.metadata_mut().insert(HEADER, value);
+    }
+    request
+}
+
+/// Middleware assigning the request ID; see the module comment
+pub async fn middleware(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let id = req
+        .headers()
+        .get(HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(accept)
+        .unwrap_or_else(generate);
+    let span = tracing::info_span!("request", request_id = %id, method = %req.method(), path = %req.path());
+
+    let mut res = scope(id.clone(), next.call(req)).instrument(span).await?;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        res.headers_mut().insert(HeaderName::from_static(HEADER), value);
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accept() {
+        assert_eq!(accept("abc-123").as_deref(), Some("abc-123"));
+        assert_eq!(accept(""), None);
+        assert_eq!(accept("has space"), None);
+        assert_eq!(accept(&"x".repeat(MAX_LEN + 1)), None);
+        assert_eq!(generate().len(), 32);
+        assert_ne!(generate(), generate());
+    }
+
+    #[tokio::test]
+    async fn test_scope() {
+        assert_eq!(current(), None);
+        let id = scope("req-1".to_string(), async {
+            let request = grpc_request(());
+            assert_eq!(request.metadata().get(HEADER).unwrap(), "req-1");
+            current()
+        })
+        .await;
+        assert_eq!(id.as_deref(), Some("req-1"));
+        assert!(grpc_request(()).metadata().get(HEADER).is_none());
+    }
+}
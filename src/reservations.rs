@@ -0,0 +1,238 @@
+// Collateral reservations, which serialize trade risk checks through the database.
+// A trade first reserves the margin it adds to its pool's book, checked against the open
+// contracts of the pool and every active reservation, then confirms the reservation in the
+// transaction writing its contract. The work in between, such as issuing a Lightning invoice,
+// runs without holding any lock. Reservations are rows every API instance sharing the database
+// sees, and a trigger refuses any reservation that would take a pool's reservations past the
+// collateral its open contracts leave, so concurrent instances cannot jointly oversubscribe the
+// pool. A reservation lapses at its expires_at unless confirmed or released first.
+
+use crate::error::{ApiError, ApiResult, ErrorCode};
+use crate::models::Contract;
+use crate::utils::{btc_to_sats, cents_to_usd, sats_to_btc, usd_to_cents};
+use rusqlite::{params, Connection, ErrorCode as SqliteErrorCode, OptionalExtension};
+use serde_json::json;
+use std::env;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReservationConfig {
+    pub ttl: Duration,  // How long a trade may take from its risk check to its insert
+}
+
+impl Default for ReservationConfig {
+    fn default() -> Self {
+        Self { ttl: Duration::from_secs(30) }
+    }
+}
+
+impl ReservationConfig {
+    /// COLLATERAL_RESERVATION_SECS (30)
+    pub fn from_env() -> Self {
+        Self {
+            ttl: env::var("COLLATERAL_RESERVATION_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|secs: &u64| *secs > 0)
+                .map(Duration::from_secs)
+                .unwrap_or(Self::default().ttl),
+        }
+    }
+}
+
+/// The active book of a pool and of a counterparty, as a new trade is checked against it
+#[derive(Clone, Debug, Default)]
+pub struct PoolBook {
+    pub open: Vec<Contract>,          // Open and pending contracts of the pool
+    pub reserved: Vec<Contract>,      // Trades of the pool holding an active reservation
+    pub counterparty: Vec<Contract>,  // Contracts and reserved trades of the counterparty
+}
+
+impl PoolBook {
+    /// Open contracts and reserved trades of the pool together
+    pub fn contracts(&self) -> Vec<Contract> {
+        self.open.iter().chain(&self.reserved).cloned().collect()
+    }
+}
+
+/// Collateral a checked trade reserves
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReservedMargin {
+    pub margin_usd: f64,     // Margin the trade adds to the book of the pool
+    pub available_usd: f64,  // Pool collateral less the margin of its open contracts
+}
+
+/// Reserve `margin` for `contract` of `counterparty` in `pool_id` until `now + ttl`. Fails
+/// with INSUFFICIENT_COLLATERAL when the pool's active reservations would exceed the
+/// available collateral.
+pub fn reserve(
+    conn: &Connection,
+    pool_id: i64,
+    counterparty: &str,
+    contract: &Contract,
+    margin: ReservedMargin,
+    now: i64,
+    ttl: Duration,
+) -> ApiResult<i64> {
+    let margin_cents = usd_to_cents(margin.margin_usd.max(0.0));
+    let inserted = conn.execute(
+        "INSERT INTO collateral_reservations
+            (pool_id, counterparty, underlying, side, strike_price_cents, quantity_sats, expires, premium_sats,
+             margin_cents, available_cents, created_at, expires_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+        params![
+            pool_id,
+            counterparty,
+            contract.underlying,
+            contract.side,
+            usd_to_cents(contract.strike_price),
+            btc_to_sats(contract.quantity),
+            contract.expires,
+            btc_to_sats(contract.premium),
+            margin_cents,
+            usd_to_cents(margin.available_usd),
+            now,
+            now + ttl.as_secs().max(1) as i64,
+        ],
+    );
+    match inserted {
+        Ok(_) => Ok(conn.last_insert_rowid()),
+        Err(rusqlite::Error::SqliteFailure(error, Some(message)))
+            if error.code == SqliteErrorCode::ConstraintViolation && message.contains("oversubscribed") =>
+        {
+            let reserved_cents = reserved_margin_cents(conn, pool_id, now)?;
+            Err(ApiError::ValidationError(format!(
+                "Margin required (${:.2}) exceeds the collateral not yet reserved (${:.2})",
+                margin.margin_usd,
+                margin.available_usd - cents_to_usd(reserved_cents)
+            ))
+            .with_code(ErrorCode::InsufficientCollateral)
+            .with_details(json!({
+                "margin_required_usd": margin.margin_usd,
+                "reserved_margin_usd": cents_to_usd(reserved_cents),
+                "available_collateral_usd": margin.available_usd,
+            })))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Margin held by the active reservations of `pool_id`, in cents
+pub fn reserved_margin_cents(conn: &Connection, pool_id: i64, now: i64) -> rusqlite::Result<i64> {
+    conn.query_row(
+        "SELECT COALESCE(SUM(margin_cents), 0) FROM collateral_reservations
+         WHERE pool_id = ?1 AND status = 'reserved' AND expires_at > ?2",
+        params![pool_id, now],
+        |row| row.get(0),
+    )
+}
+
+/// Trades of `pool_id` holding an active reservation
+pub fn pool_reserved_contracts(conn: &Connection, pool_id: i64, now: i64) -> rusqlite::Result<Vec<Contract>> {
+    load_reserved(conn, "pool_id = ?1", pool_id, now)
+}
+
+/// Trades of `counterparty` holding an active reservation
+pub fn counterparty_reserved_contracts(conn: &Connection, counterparty: &str, now: i64) -> rusqlite::Result<Vec<Contract>> {
+    load_reserved(conn, "counterparty = ?1", counterparty, now)
+}
+
+fn load_reserved(conn: &Connection, condition: &str, value: impl rusqlite::ToSql, now: i64) -> rusqlite::Result<Vec<Contract>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT side, strike_price_cents, quantity_sats, expires, premium_sats, underlying FROM collateral_reservations
+         WHERE {} AND status = 'reserved' AND expires_at > ?2 AND expires > ?2",
+        condition
+    ))?;
+    let rows = stmt.query_map(params![value, now], |row| {
+        Ok(Contract {
+            underlying: row.get(5)?,
+            side: row.get(0)?,
+            strike_price: cents_to_usd(row.get(1)?),
+            quantity: sats_to_btc(row.get(2)?),
+            expires: row.get(3)?,
+            premium: sats_to_btc(row.get(4)?),
+        })
+    })?;
+    rows.collect()
+}
+
+/// Confirm reservation `id` as held by `contract_id`. Call it with the transaction inserting
+/// the contract; fails if the reservation lapsed or was released meanwhile.
+pub fn confirm(conn: &Connection, id: i64, contract_id: i64, now: i64) -> ApiResult<()> {
+    let updated = conn.execute(
+        "UPDATE collateral_reservations SET status = 'confirmed', contract_id = ?1
+         WHERE id = ?2 AND status = 'reserved' AND expires_at > ?3",
+        params![contract_id, id, now],
+    )?;
+    if updated == 1 {
+        return Ok(());
+    }
+    let status: Option<String> = conn
+        .query_row("SELECT status FROM collateral_reservations WHERE id = ?1", params![id], |row| row.get(0))
+        .optional()?;
+    Err(ApiError::ValidationError(format!(
+        "Collateral reservation {} is no longer held; retry the trade",
+        id
+    ))
+    .with_code(ErrorCode::ReservationExpired)
+    .with_details(json!({"reservation_id": id, "status": status})))
+}
+
+/// Release reservation `id` of a trade that fell through. Confirmed reservations are kept.
+pub fn release(conn: &Connection, id: i64) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE collateral_reservations SET status = 'released' WHERE id = ?1 AND status = 'reserved'",
+        params![id],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Asset, OptionSide};
+
+    fn call(quantity: f64) -> Contract {
+        Contract {
+            underlying: Asset::Btc,
+            side: OptionSide::Call,
+            strike_price: 60_000.0,
+            quantity,
+            expires: 2_000_000_000,
+            premium: 0.01,
+        }
+    }
+
+    fn margin(margin_usd: f64) -> ReservedMargin {
+        ReservedMargin { margin_usd, available_usd: 10_000.0 }
+    }
+
+    #[test]
+    fn test_reservations_cannot_oversubscribe_the_pool() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init_db(&conn).unwrap();
+        let (now, ttl) = (1_700_000_000, Duration::from_secs(30));
+
+        let first = reserve(&conn, 1, "alice", &call(0.1), margin(6_000.0), now, ttl).unwrap();
+        assert_eq!(pool_reserved_contracts(&conn, 1, now).unwrap(), vec![call(0.1)]);
+        assert_eq!(counterparty_reserved_contracts(&conn, "alice", now).unwrap().len(), 1);
+
+        // Each fits on its own, but not next to the first, whichever instance made it
+        let error = reserve(&conn, 1, "bob", &call(0.1), margin(5_000.0), now, ttl).unwrap_err();
+        assert_eq!(error.code(), ErrorCode::InsufficientCollateral);
+        assert_eq!(error.details()["reserved_margin_usd"], 6_000.0);
+        // Other pools are unaffected
+        reserve(&conn, 2, "bob", &call(0.1), margin(5_000.0), now, ttl).unwrap();
+
+        // Released or lapsed reservations free their collateral
+        release(&conn, first).unwrap();
+        let second = reserve(&conn, 1, "bob", &call(0.1), margin(5_000.0), now, ttl).unwrap();
+        assert!(reserve(&conn, 1, "carol", &call(0.1), margin(5_000.0), now + 30, ttl).is_ok());
+        assert_eq!(pool_reserved_contracts(&conn, 1, now + 30).unwrap().len(), 1);
+
+        // Only held reservations confirm
+        let error = confirm(&conn, second, 1, now + 30).unwrap_err();
+        assert_eq!(error.code(), ErrorCode::ReservationExpired);
+        assert_eq!(confirm(&conn, first, 1, now).unwrap_err().details()["status"], "released");
+    }
+}
@@ -1,7 +1,98 @@
-use crate::{OptionSide, Contract};
+use crate::margin::{MarginModel, MaxLossMargin, ShortOption};
+use crate::models::{Asset, OptionSide, Contract};
+use crate::iv_policy::DEFAULT_IV;
+use crate::pricing::option_price;
+use crate::rates::RateCurve;
+use crate::day_count;
+use crate::strikes;
+use crate::utils::{usd_to_cents, year_fraction};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 
+// Implied vol below this fraction of realized vol is treated as suspicious
+const MIN_IV_TO_REALIZED_VOL_RATIO: f64 = 0.5;
+
+// VaR simulation grid: stratified spot shocks x stratified vol shocks
+const VAR_SPOT_SCENARIOS: usize = 500;
+const VAR_VOL_SCENARIOS: usize = 5;
+// Annualized volatility of implied vol used for the vol shock dimension
+const VAR_VOL_OF_VOL: f64 = 1.0;
+
+// Position groups margined per rayon task; smaller books are margined on one thread
+const PARALLEL_MIN_GROUPS: usize = 16;
+
+// IV lookup by side ("C"/"P"), strike and expiry in milliseconds. Shared across the
+// threads the book is margined on.
+pub type IvLookup<'a> = dyn Fn(&str, f64, &str) -> Option<f64> + Sync + 'a;
+
+#[derive(Clone)]
 pub struct RiskManager {
     risk_margin: f64,  // Safety margin (e.g., 1.2 = 20% extra margin)
+    max_contract_quantity: f64,  // Cap on calculate_max_quantity
+    margin_model: Arc<dyn MarginModel>,
+    margin_cache: Option<Arc<MarginCache>>,
+}
+
+/// Margins of the position groups of earlier portfolio margin runs. A group, the net
+/// positions of one (underlying, side, expiry), is only margined again once its positions,
+/// spot, IVs or margin settings change, or after `max_age_secs` for the time decay of
+/// time-sensitive margin models.
+#[derive(Debug)]
+pub struct MarginCache {
+    max_age_secs: i64,
+    entries: Mutex<HashMap<(Asset, String, i64), CachedMargin>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CachedMargin {
+    fingerprint: u64,  // Of everything the group's margin was computed from
+    margin: f64,
+    computed_at: i64,
+}
+
+impl MarginCache {
+    pub fn new(max_age_secs: i64) -> Self {
+        Self { max_age_secs, entries: Mutex::new(HashMap::new()), hits: AtomicU64::new(0), misses: AtomicU64::new(0) }
+    }
+
+    /// RISK_MARGIN_CACHE_SECS (60); 0 turns the cache off
+    pub fn from_env() -> Option<Self> {
+        let max_age_secs = env::var("RISK_MARGIN_CACHE_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(60);
+        (max_age_secs > 0).then(|| Self::new(max_age_secs))
+    }
+
+    /// Groups reused and margined since the cache was created
+    pub fn stats(&self) -> (u64, u64) {
+        (self.hits.load(Ordering::Relaxed), self.misses.load(Ordering::Relaxed))
+    }
+
+    fn get(&self, key: &(Asset, String, i64), fingerprint: u64, now: i64) -> Option<f64> {
+        let entries = self.entries.lock().unwrap();
+        let hit = entries
+            .get(key)
+            .filter(|cached| cached.fingerprint == fingerprint && now - cached.computed_at < self.max_age_secs)
+            .map(|cached| cached.margin);
+        match hit {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        hit
+    }
+
+    fn insert(&self, key: (Asset, String, i64), fingerprint: u64, margin: f64, now: i64) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.insert(key, CachedMargin { fingerprint, margin, computed_at: now });
+        // Drop groups that expired or stopped being margined
+        entries.retain(|_, cached| now - cached.computed_at < self.max_age_secs);
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -12,6 +103,25 @@ pub struct RiskMetrics {
     pub max_quantity: f64,        // Maximum quantity for new position
 }
 
+/// Portfolio Value-at-Risk and Expected Shortfall in USD (losses are positive)
+#[derive(Debug, Clone, Serialize)]
+pub struct VarMetrics {
+    pub horizon_days: f64,
+    pub var_95: f64,
+    pub es_95: f64,
+    pub var_99: f64,
+    pub es_99: f64,
+    pub scenario_count: usize,
+}
+
+/// Book revaluation under a single spot/IV shock
+#[derive(Debug, Clone)]
+pub struct ScenarioImpact {
+    pub shocked_spot_price: f64,
+    pub pnl_usd: f64,                   // Seller P&L from repricing (negative = loss)
+    pub margin_required_usd: f64,       // Portfolio margin at the shocked spot/IV
+}
+
 #[derive(Debug, Clone)]
 pub struct PositionRisk {
     pub max_loss: f64,           // Maximum possible loss
@@ -21,10 +131,33 @@ pub struct PositionRisk {
 
 impl RiskManager {
     pub fn new(risk_margin: f64) -> Self {
-        Self { risk_margin }
+        Self { risk_margin, max_contract_quantity: 1000.0, margin_model: Arc::new(MaxLossMargin), margin_cache: None }
+    }
+
+    /// Reuse the margin of unchanged position groups from `margin_cache`
+    pub fn with_margin_cache(mut self, margin_cache: Option<Arc<MarginCache>>) -> Self {
+        self.margin_cache = margin_cache;
+        self
+    }
+
+    /// Margin positions with `margin_model` instead of the max-loss model
+    pub fn with_margin_model(mut self, margin_model: Arc<dyn MarginModel>) -> Self {
+        self.margin_model = margin_model;
+        self
+    }
+
+    pub fn margin_model_name(&self) -> &'static str {
+        self.margin_model.name()
+    }
+
+    /// Cap the quantity of a single contract (1000 by default)
+    pub fn with_max_contract_quantity(mut self, max_contract_quantity: f64) -> Self {
+        self.max_contract_quantity = max_contract_quantity;
+        self
     }
     
     /// Calculate risk for a single option position
+    #[allow(clippy::too_many_arguments)]
     pub fn calculate_position_risk(
         &self,
         side: &OptionSide,
@@ -36,12 +169,21 @@ impl RiskManager {
         time_to_expiry: f64,
         risk_free_rate: f64,
     ) -> PositionRisk {
+        let option = ShortOption {
+            side,
+            strike,
+            premium,
+            spot_price,
+            iv,
+            time_to_expiry,
+            risk_free_rate,
+        };
+        let max_loss = MaxLossMargin.unit_loss(&option) * quantity;
+        // Margin required with safety factor
+        let margin_required = self.margin_model.unit_loss(&option) * quantity * self.risk_margin;
+
         match side {
             OptionSide::Put => {
-                // For put seller: max loss = strike - premium received (if BTC goes to 0)
-                let max_loss_per_contract = strike - premium;
-                let max_loss = max_loss_per_contract * quantity;
-                
                 // Calculate probability of being in the money using Black-Scholes N(d2)
                 let d2 = calculate_d2(spot_price, strike, risk_free_rate, iv, time_to_expiry);
                 let prob_itm = normal_cdf(-d2); // Probability put is ITM
@@ -50,9 +192,6 @@ impl RiskManager {
                 let moneyness = (strike - spot_price).max(0.0);
                 let expected_loss = prob_itm * moneyness * quantity;
                 
-                // Margin required with safety factor
-                let margin_required = max_loss * self.risk_margin;
-                
                 PositionRisk {
                     max_loss,
                     expected_loss,
@@ -60,12 +199,6 @@ impl RiskManager {
                 }
             }
             OptionSide::Call => {
-                // For call seller: theoretically unlimited loss, but we cap it
-                // Use 3x current price as reasonable worst case
-                let max_price_move = spot_price * 3.0;
-                let max_loss_per_contract = (max_price_move - strike).max(0.0) - premium;
-                let max_loss = max_loss_per_contract * quantity;
-                
                 // Calculate probability of being in the money
                 let d2 = calculate_d2(spot_price, strike, risk_free_rate, iv, time_to_expiry);
                 let prob_itm = normal_cdf(d2); // Probability call is ITM
@@ -74,9 +207,6 @@ impl RiskManager {
                 let moneyness = (spot_price - strike).max(0.0);
                 let expected_loss = prob_itm * moneyness * quantity * 1.5; // 1.5x for upside risk
                 
-                // Margin required with safety factor
-                let margin_required = max_loss * self.risk_margin;
-                
                 PositionRisk {
                     max_loss,
                     expected_loss,
@@ -86,53 +216,155 @@ impl RiskManager {
         }
     }
     
-    /// Calculate total portfolio risk from existing contracts
+    /// Calculate total portfolio risk from existing contracts.
+    ///
+    /// Positions are netted per (side, strike, expiry) before margining, so
+    /// offsetting contracts don't double-count. A positive net quantity means the
+    /// pool is short; a negative one means it is long. Net long options cover net
+    /// short options of the same side and expiry as vertical spreads, whose margin
+    /// is capped at the strike width instead of the naked max loss.
+    ///
+    /// The pool only sells options, and books loaded from the database hold its
+    /// shorts alone, so until long legs are recorded every short is margined naked.
+    ///
+    /// Groups are margined in parallel, and with a margin cache only the groups whose
+    /// positions, spot or IVs changed are margined again.
     pub fn calculate_portfolio_risk(
         &self,
         contracts: &[Contract],
         spot_price: f64,
-        risk_free_rate: f64,
-        iv_oracle: &dyn Fn(&str, f64, &str) -> Option<f64>,
+        rates: &RateCurve,
+        iv_oracle: &IvLookup,
+    ) -> f64 {
+        self.portfolio_risk(contracts, spot_price, rates, iv_oracle, self.margin_cache.as_deref())
+    }
+
+    fn portfolio_risk(
+        &self,
+        contracts: &[Contract],
+        spot_price: f64,
+        rates: &RateCurve,
+        iv_oracle: &IvLookup,
+        cache: Option<&MarginCache>,
     ) -> f64 {
-        let mut total_margin_required = 0.0;
         let current_time = chrono::Utc::now().timestamp();
-        
-        for contract in contracts {
-            // Skip expired contracts
-            if contract.expires <= current_time {
-                continue;
+        let groups: Vec<_> = net_positions(contracts, current_time).into_iter().collect();
+
+        let margins: Vec<f64> = groups
+            .into_par_iter()
+            .with_min_len(PARALLEL_MIN_GROUPS)
+            .map(|(key, (shorts, longs))| {
+                let ivs: Vec<f64> = shorts.iter().map(|short| contract_iv(&short.to_contract(short.quantity), iv_oracle)).collect();
+                let Some(cache) = cache else {
+                    return self.group_margin(shorts, longs, &ivs, spot_price, rates, current_time);
+                };
+                let fingerprint = self.group_fingerprint(&shorts, &longs, &ivs, spot_price, rates);
+                if let Some(margin) = cache.get(&key, fingerprint, current_time) {
+                    return margin;
+                }
+                let margin = self.group_margin(shorts, longs, &ivs, spot_price, rates, current_time);
+                cache.insert(key, fingerprint, margin, current_time);
+                margin
+            })
+            .collect();
+        // Summed in group order so the total does not depend on how the work was split
+        margins.iter().sum()
+    }
+
+    // Margin of the net shorts of one (underlying, side, expiry) after covering them with
+    // the group's net longs. `ivs` are those of the shorts.
+    fn group_margin(
+        &self,
+        shorts: Vec<NetPosition>,
+        mut longs: Vec<NetPosition>,
+        ivs: &[f64],
+        spot_price: f64,
+        rates: &RateCurve,
+        current_time: i64,
+    ) -> f64 {
+        let mut margin_required = 0.0;
+        for (short, &iv) in shorts.iter().zip(ivs) {
+            let mut uncovered = short.quantity;
+
+            // Cover with the longs that leave the smallest spread loss first
+            longs.sort_by(|a, b| {
+                spread_width(short, a)
+                    .partial_cmp(&spread_width(short, b))
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+            for long in longs.iter_mut() {
+                if uncovered <= 0.0 {
+                    break;
+                }
+                let covered = uncovered.min(long.quantity);
+                if covered <= 0.0 {
+                    continue;
+                }
+                let spread_loss = (spread_width(short, long) - short.premium).max(0.0);
+                margin_required += spread_loss * covered * self.risk_margin;
+                long.quantity -= covered;
+                uncovered -= covered;
+            }
+
+            if uncovered > 0.0 {
+                let time_to_expiry = year_fraction(short.expires, current_time);
+                let position_risk = self.calculate_position_risk(
+                    &short.side,
+                    short.strike_price,
+                    short.premium,
+                    uncovered,
+                    spot_price,
+                    iv,
+                    time_to_expiry,
+                    rates.rate_to(short.expires, current_time),
+                );
+                margin_required += position_risk.margin_required;
             }
-            
-            let time_to_expiry = (contract.expires - current_time) as f64 / (365.0 * 24.0 * 60.0 * 60.0);
-            
-            // Get IV for this specific contract
-            let side_str = match contract.side {
-                OptionSide::Call => "C",
-                OptionSide::Put => "P",
-            };
-            let expire_timestamp_ms = (contract.expires * 1000).to_string();
-            let iv = iv_oracle(side_str, contract.strike_price, &expire_timestamp_ms)
-                .unwrap_or(0.4); // Default IV if not found
-            
-            let position_risk = self.calculate_position_risk(
-                &contract.side,
-                contract.strike_price,
-                contract.premium,
-                contract.quantity,
-                spot_price,
-                iv,
-                time_to_expiry,
-                risk_free_rate,
-            );
-            
-            total_margin_required += position_risk.margin_required;
         }
-        
-        total_margin_required
+        margin_required
+    }
+
+    // Hash of everything a group's margin is computed from, bar the time to expiry
+    fn group_fingerprint(&self, shorts: &[NetPosition], longs: &[NetPosition], ivs: &[f64], spot_price: f64, rates: &RateCurve) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.margin_model.name().hash(&mut hasher);
+        let curve = rates.points().iter().flat_map(|&(tenor, rate)| [tenor, rate]);
+        for value in [self.risk_margin, spot_price].into_iter().chain(curve).chain(ivs.iter().copied()) {
+            value.to_bits().hash(&mut hasher);
+        }
+        shorts.len().hash(&mut hasher);
+        for position in shorts.iter().chain(longs) {
+            for value in [position.strike_price, position.quantity, position.premium] {
+                value.to_bits().hash(&mut hasher);
+            }
+        }
+        hasher.finish()
+    }
+    
+    /// Portfolio margin for a book that may mix underlyings. Each underlying is margined
+    /// separately at its own spot and IV surface (`iv_oracle` also receives the asset)
+    /// and the results are summed. Returns None if `spot_prices` lacks an underlying
+    /// that has open contracts.
+    pub fn calculate_multi_asset_portfolio_risk(
+        &self,
+        contracts: &[Contract],
+        spot_prices: &HashMap<Asset, f64>,
+        rates: &RateCurve,
+        iv_oracle: &(dyn Fn(Asset, &str, f64, &str) -> Option<f64> + Sync),
+    ) -> Option<f64> {
+        let mut total_margin_required = 0.0;
+        for (asset, asset_contracts) in contracts_by_asset(contracts) {
+            let spot_price = *spot_prices.get(&asset)?;
+            let asset_iv = |side: &str, strike: f64, expire: &str| iv_oracle(asset, side, strike, expire);
+            total_margin_required +=
+                self.calculate_portfolio_risk(&asset_contracts, spot_price, rates, &asset_iv);
+        }
+        Some(total_margin_required)
     }
     
     /// Calculate maximum quantity for a new position considering risk
     /// available_collateral_usd is already net of existing risk exposure
+    #[allow(clippy::too_many_arguments)]
     pub fn calculate_max_quantity(
         &self,
         side: &OptionSide,
@@ -172,8 +404,375 @@ impl RiskManager {
         let max_quantity = available_collateral_usd / unit_risk.margin_required;
         println!("   Calculated max quantity: {:.8}", max_quantity);
         
-        // Ensure we don't exceed reasonable position limits
-        max_quantity.min(1000.0) // Cap at 1000 contracts per position
+        // Ensure we don't exceed the configured single contract limit
+        max_quantity.min(self.max_contract_quantity)
+    }
+    
+    /// Calculate portfolio VaR and Expected Shortfall by fully revaluing the book
+    /// (short option positions) across a grid of spot and implied vol shocks.
+    ///
+    /// Spot log-returns are drawn from stratified normal quantiles scaled by
+    /// `spot_vol` over the horizon; implied vols are shocked independently by
+    /// VAR_VOL_OF_VOL. Every scenario is equally weighted.
+    pub fn calculate_var(
+        &self,
+        contracts: &[Contract],
+        spot_price: f64,
+        rates: &RateCurve,
+        iv_oracle: &IvLookup,
+        spot_vol: f64,
+        horizon_days: f64,
+    ) -> VarMetrics {
+        let current_time = chrono::Utc::now().timestamp();
+        let horizon = horizon_days / day_count::deployment().days_per_year();
+        
+        // Pre-compute current value and IV of every open position
+        let positions: Vec<(&Contract, f64, f64, f64, f64)> = contracts
+            .iter()
+            .filter(|c| c.expires > current_time)
+            .map(|c| {
+                let t = year_fraction(c.expires, current_time);
+                let rate = rates.rate_to(c.expires, current_time);
+                let iv = contract_iv(c, iv_oracle);
+                let value = option_price(&c.side, spot_price, c.strike_price, rate, iv, t);
+                (c, t, rate, iv, value)
+            })
+            .collect();
+        
+        let spot_sigma = spot_vol * horizon.sqrt();
+        let vol_sigma = VAR_VOL_OF_VOL * horizon.sqrt();
+        let mut losses = Vec::with_capacity(VAR_SPOT_SCENARIOS * VAR_VOL_SCENARIOS);
+        
+        for i in 0..VAR_SPOT_SCENARIOS {
+            let z_spot = inverse_normal_cdf((i as f64 + 0.5) / VAR_SPOT_SCENARIOS as f64);
+            let shocked_spot = spot_price * (spot_sigma * z_spot - 0.5 * spot_sigma * spot_sigma).exp();
+            
+            for j in 0..VAR_VOL_SCENARIOS {
+                let z_vol = inverse_normal_cdf((j as f64 + 0.5) / VAR_VOL_SCENARIOS as f64);
+                let vol_multiplier = (vol_sigma * z_vol - 0.5 * vol_sigma * vol_sigma).exp();
+                
+                // As the option seller we lose when the option value rises
+                let loss: f64 = positions
+                    .iter()
+                    .map(|(c, t, rate, iv, value)| {
+                        let shocked_value = option_price(
+                            &c.side,
+                            shocked_spot,
+                            c.strike_price,
+                            *rate,
+                            iv * vol_multiplier,
+                            t - horizon,
+                        );
+                        (shocked_value - value) * c.quantity
+                    })
+                    .sum();
+                losses.push(loss);
+            }
+        }
+        
+        losses.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let (var_95, es_95) = var_and_es(&losses, 0.95);
+        let (var_99, es_99) = var_and_es(&losses, 0.99);
+        
+        VarMetrics {
+            horizon_days,
+            var_95,
+            es_95,
+            var_99,
+            es_99,
+            scenario_count: losses.len(),
+        }
+    }
+    
+    /// Reprice the open book after moving spot by `spot_move_percent` and shifting
+    /// every contract's IV by `iv_shift` (absolute, e.g. 0.10 = +10 vol points)
+    pub fn evaluate_scenario(
+        &self,
+        contracts: &[Contract],
+        spot_price: f64,
+        rates: &RateCurve,
+        iv_oracle: &IvLookup,
+        spot_move_percent: f64,
+        iv_shift: f64,
+    ) -> ScenarioImpact {
+        let current_time = chrono::Utc::now().timestamp();
+        let shocked_spot_price = spot_price * (1.0 + spot_move_percent / 100.0);
+        
+        // IVs are floored so a large negative shift can't produce a degenerate price
+        let shifted_iv = |c: &Contract| (contract_iv(c, iv_oracle) + iv_shift).max(0.01);
+        
+        let mut pnl_usd = 0.0;
+        for contract in contracts.iter().filter(|c| c.expires > current_time) {
+            let t = year_fraction(contract.expires, current_time);
+            let rate = rates.rate_to(contract.expires, current_time);
+            let iv = contract_iv(contract, iv_oracle);
+            let value = option_price(&contract.side, spot_price, contract.strike_price, rate, iv, t);
+            let shocked_value = option_price(
+                &contract.side,
+                shocked_spot_price,
+                contract.strike_price,
+                rate,
+                shifted_iv(contract),
+                t,
+            );
+            // Short options: we lose when the option value rises
+            pnl_usd -= (shocked_value - value) * contract.quantity;
+        }
+        
+        let shocked_iv_oracle = |side: &str, strike: f64, expire: &str| {
+            Some((iv_oracle(side, strike, expire).unwrap_or(DEFAULT_IV) + iv_shift).max(0.01))
+        };
+        // Shocked margins are one-offs, kept out of the margin cache
+        let margin_required_usd = self.portfolio_risk(
+            contracts,
+            shocked_spot_price,
+            rates,
+            &shocked_iv_oracle,
+            None,
+        );
+        
+        ScenarioImpact {
+            shocked_spot_price,
+            pnl_usd,
+            margin_required_usd,
+        }
+    }
+    
+    /// Sanity check implied vol against recently realized vol.
+    /// Returns false when IV is implausibly low compared to realized vol,
+    /// which usually means a stale or broken IV feed. Missing realized vol passes.
+    pub fn is_iv_consistent_with_realized(&self, iv: f64, realized_vol: Option<f64>) -> bool {
+        match realized_vol {
+            Some(rv) if rv > 0.0 => iv / rv >= MIN_IV_TO_REALIZED_VOL_RATIO,
+            _ => true,
+        }
+    }
+}
+
+// Net position for one (underlying, side, strike, expiry) product
+#[derive(Debug, Clone)]
+struct NetPosition {
+    underlying: Asset,
+    side: OptionSide,
+    strike_price: f64,
+    expires: i64,
+    quantity: f64, // Absolute net quantity
+    premium: f64,  // Quantity-weighted premium of the contracts on the net side
+}
+
+impl NetPosition {
+    fn to_contract(&self, quantity: f64) -> Contract {
+        Contract {
+            underlying: self.underlying,
+            side: self.side,
+            strike_price: self.strike_price,
+            quantity,
+            expires: self.expires,
+            premium: self.premium,
+        }
+    }
+}
+
+// Contracts of one product before netting, quantities unsigned
+#[derive(Debug, Default)]
+struct GrossPosition {
+    short_quantity: f64,
+    short_premium: f64,  // Sum of quantity * premium
+    long_quantity: f64,
+    long_premium: f64,
+}
+
+// Net shorts and net longs per (underlying, side, expiry)
+type NetPositionGroups = BTreeMap<(Asset, String, i64), (Vec<NetPosition>, Vec<NetPosition>)>;
+
+// Contracts split per underlying
+pub fn contracts_by_asset(contracts: &[Contract]) -> BTreeMap<Asset, Vec<Contract>> {
+    let mut by_asset: BTreeMap<Asset, Vec<Contract>> = BTreeMap::new();
+    for contract in contracts {
+        by_asset.entry(contract.underlying).or_default().push(contract.clone());
+    }
+    by_asset
+}
+
+// Net unexpired contracts per (underlying, side, strike, expiry) and split them into
+// net shorts and net longs grouped by (underlying, side, expiry), the unit within
+// which spreads can offset. Negative quantities are longs the pool holds. The pool
+// records no longs yet, so books loaded with repository::load_active_contracts hold
+// shorts only and nothing nets or offsets until long legs are recorded.
+// A net position keeps the premium of the contracts on its own side: netting a short
+// against a long must not let the long's premium stand in for premium collected.
+fn net_positions(
+    contracts: &[Contract],
+    current_time: i64,
+) -> NetPositionGroups {
+    // (underlying, side, strike in cents, expiry) -> gross short and long quantities and premiums
+    let mut products: BTreeMap<(Asset, String, i64, i64), GrossPosition> = BTreeMap::new();
+    for contract in contracts.iter().filter(|c| c.expires > current_time) {
+        let key = (
+            contract.underlying,
+            contract.side.to_string(),
+            strikes::deployment().normalize_cents(contract.underlying, usd_to_cents(contract.strike_price)),
+            contract.expires,
+        );
+        let entry = products.entry(key).or_default();
+        if contract.quantity > 0.0 {
+            entry.short_quantity += contract.quantity;
+            entry.short_premium += contract.quantity * contract.premium;
+        } else {
+            entry.long_quantity -= contract.quantity;
+            entry.long_premium -= contract.quantity * contract.premium;
+        }
+    }
+    
+    let mut groups = NetPositionGroups::new();
+    for ((underlying, side_str, strike_cents, expires), gross) in products {
+        let net_quantity = gross.short_quantity - gross.long_quantity;
+        // Ignore rounding dust left over after netting
+        if net_quantity.abs() < 1e-8 {
+            continue;
+        }
+        let side = match side_str.parse::<OptionSide>() {
+            Ok(side) => side,
+            Err(_) => continue,
+        };
+        let position = NetPosition {
+            underlying,
+            side,
+            strike_price: strike_cents as f64 / 100.0,
+            expires,
+            quantity: net_quantity.abs(),
+            premium: if net_quantity > 0.0 {
+                gross.short_premium / gross.short_quantity
+            } else {
+                gross.long_premium / gross.long_quantity
+            },
+        };
+        let group = groups.entry((underlying, side_str, expires)).or_default();
+        if net_quantity > 0.0 {
+            group.0.push(position);
+        } else {
+            group.1.push(position);
+        }
+    }
+    
+    groups
+}
+
+/// Open contracts aggregated per (underlying, side, strike, expiry) product, the way an
+/// exchange position blotter shows a book
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Position {
+    pub underlying: Asset,
+    pub side: OptionSide,
+    pub strike_price: f64,
+    pub expires: i64,
+    pub net_quantity: f64,     // Positive: the pool is short
+    pub average_premium: f64,  // BTC, weighted by contract quantity
+    pub contract_count: usize,
+}
+
+// (underlying, side, expiry, strike in cents) -> (net quantity, gross quantity, sum of |quantity| * premium, count)
+type ProductTotals = BTreeMap<(Asset, String, i64, i64), (f64, f64, f64, usize)>;
+
+/// Unexpired contracts per product, ordered by underlying, side, expiry and strike.
+/// Products that net to zero are left out.
+pub fn aggregate_positions(contracts: &[Contract], current_time: i64) -> Vec<Position> {
+    let mut products = ProductTotals::new();
+    for contract in contracts.iter().filter(|c| c.expires > current_time) {
+        let key = (
+            contract.underlying,
+            contract.side.to_string(),
+            contract.expires,
+            (contract.strike_price * 100.0).round() as i64,
+        );
+        let entry = products.entry(key).or_insert((0.0, 0.0, 0.0, 0));
+        entry.0 += contract.quantity;
+        entry.1 += contract.quantity.abs();
+        entry.2 += contract.quantity.abs() * contract.premium;
+        entry.3 += 1;
+    }
+
+    products
+        .into_iter()
+        .filter(|(_, (net_quantity, ..))| net_quantity.abs() >= 1e-8)
+        .filter_map(|((underlying, side_str, expires, strike_cents), (net_quantity, gross_quantity, premium_sum, count))| {
+            Some(Position {
+                underlying,
+                side: side_str.parse().ok()?,
+                strike_price: strike_cents as f64 / 100.0,
+                expires,
+                net_quantity,
+                average_premium: premium_sum / gross_quantity,
+                contract_count: count,
+            })
+        })
+        .collect()
+}
+
+// Max payout per unit of a short option hedged by a long option of the same side and expiry
+fn spread_width(short: &NetPosition, long: &NetPosition) -> f64 {
+    match short.side {
+        OptionSide::Call => (long.strike_price - short.strike_price).max(0.0),
+        OptionSide::Put => (short.strike_price - long.strike_price).max(0.0),
+    }
+}
+
+// IV for an existing contract from the oracle, with the same default as margin calculations
+fn contract_iv(contract: &Contract, iv_oracle: &IvLookup) -> f64 {
+    let side_str = match contract.side {
+        OptionSide::Call => "C",
+        OptionSide::Put => "P",
+    };
+    let expire_timestamp_ms = (contract.expires * 1000).to_string();
+    iv_oracle(side_str, contract.strike_price, &expire_timestamp_ms)
+        .unwrap_or(DEFAULT_IV) // Lookups without a default IV policy
+}
+
+// VaR and Expected Shortfall at `confidence` from losses sorted ascending
+fn var_and_es(sorted_losses: &[f64], confidence: f64) -> (f64, f64) {
+    if sorted_losses.is_empty() {
+        return (0.0, 0.0);
+    }
+    let index = ((sorted_losses.len() as f64 * confidence).ceil() as usize)
+        .saturating_sub(1)
+        .min(sorted_losses.len() - 1);
+    let var = sorted_losses[index].max(0.0);
+    let tail = &sorted_losses[index..];
+    let es = (tail.iter().sum::<f64>() / tail.len() as f64).max(0.0);
+    (var, es)
+}
+
+// Inverse of the standard normal CDF (Acklam's rational approximation)
+fn inverse_normal_cdf(p: f64) -> f64 {
+    const A: [f64; 6] = [
+        -3.969683028665376e+01, 2.209460984245205e+02, -2.759285104469687e+02,
+        1.38357751867269e+02, -3.066479806614716e+01, 2.506628277459239e+00,
+    ];
+    const B: [f64; 5] = [
+        -5.447609879822406e+01, 1.615858368580409e+02, -1.556989798598866e+02,
+        6.680131188771972e+01, -1.328068155288572e+01,
+    ];
+    const C: [f64; 6] = [
+        -7.784894002430293e-03, -3.223964580411365e-01, -2.400758277161838e+00,
+        -2.549732539343734e+00, 4.374664141464968e+00, 2.938163982698783e+00,
+    ];
+    const D: [f64; 4] = [
+        7.784695709041462e-03, 3.224671290700398e-01, 2.445134137142996e+00,
+        3.754408661907416e+00,
+    ];
+    const P_LOW: f64 = 0.02425;
+    
+    if p <= P_LOW {
+        let q = (-2.0 * p.ln()).sqrt();
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    } else if p < 1.0 - P_LOW {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    } else {
+        -inverse_normal_cdf(1.0 - p)
     }
 }
 
@@ -234,4 +833,329 @@ mod tests {
         assert!(risk.expected_loss < risk.max_loss);
         assert_eq!(risk.margin_required, 99000.0 * 1.2);
     }
+
+    #[test]
+    fn test_scenario_grid_margin_model() {
+        let risk_manager = RiskManager::new(1.2).with_margin_model(Arc::new(crate::margin::ScenarioGridMargin::default()));
+        let risk = risk_manager.calculate_position_risk(
+            &OptionSide::Put, 100000.0, 0.0, 2.0, 100000.0, 0.5, 1.0 / 365.0, 0.0,
+        );
+
+        // Max loss is still reported, but margin covers the worst grid scenario
+        assert_eq!(risk.max_loss, 200000.0);
+        assert!(risk.margin_required < risk.max_loss * 1.2 / 5.0);
+        assert_eq!(risk_manager.margin_model_name(), "scenario_grid");
+    }
+    
+    fn contract(side: OptionSide, strike_price: f64, quantity: f64) -> Contract {
+        Contract {
+            underlying: Asset::Btc,
+            side,
+            strike_price,
+            quantity,
+            expires: chrono::Utc::now().timestamp() + 7 * 86400,
+            premium: 0.0,
+        }
+    }
+    
+    #[test]
+    fn test_portfolio_risk_nets_offsetting_positions() {
+        let risk_manager = RiskManager::new(1.2);
+        let iv = |_: &str, _: f64, _: &str| Some(0.5);
+        
+        let offsetting = vec![
+            contract(OptionSide::Put, 100000.0, 1.0),
+            contract(OptionSide::Put, 100000.0, -1.0),
+        ];
+        assert_eq!(risk_manager.calculate_portfolio_risk(&offsetting, 100000.0, &RateCurve::default(), &iv), 0.0);
+        
+        let partial = vec![
+            contract(OptionSide::Put, 100000.0, 2.0),
+            contract(OptionSide::Put, 100000.0, -1.5),
+        ];
+        let naked = vec![contract(OptionSide::Put, 100000.0, 0.5)];
+        assert_eq!(
+            risk_manager.calculate_portfolio_risk(&partial, 100000.0, &RateCurve::default(), &iv),
+            risk_manager.calculate_portfolio_risk(&naked, 100000.0, &RateCurve::default(), &iv),
+        );
+    }
+    
+    #[test]
+    fn test_near_offsetting_pair_keeps_the_short_premium() {
+        let risk_manager = RiskManager::new(1.2);
+        let iv = |_: &str, _: f64, _: &str| Some(0.5);
+        
+        // Net short 0.01 of the 100k put, covered by a long 95k put. The net short was sold
+        // at 500, so the spread loses 4,500 per contract; the cheap long at the same strike
+        // must not make it look like 40,100 was collected
+        let book = vec![
+            Contract { premium: 500.0, ..contract(OptionSide::Put, 100000.0, 1.0) },
+            Contract { premium: 100.0, ..contract(OptionSide::Put, 100000.0, -0.99) },
+            contract(OptionSide::Put, 95000.0, -0.01),
+        ];
+        let margin = risk_manager.calculate_portfolio_risk(&book, 100000.0, &RateCurve::default(), &iv);
+        assert!((margin - 4500.0 * 0.01 * 1.2).abs() < 1e-6);
+    }
+    
+    #[test]
+    fn test_books_loaded_from_the_database_have_no_longs_to_net() {
+        let risk_manager = RiskManager::new(1.2);
+        let iv = |_: &str, _: f64, _: &str| Some(0.5);
+        let pool = crate::db::create_in_memory_pool().unwrap();
+        let conn = pool.get().unwrap();
+
+        // The pool sold a 100k and a 95k put; a legacy row with a negative quantity looks like
+        // the long leg of a put spread but is not one
+        let short_100k = contract(OptionSide::Put, 100000.0, 1.0);
+        let short_95k = contract(OptionSide::Put, 95000.0, 1.0);
+        for leg in [&short_100k, &short_95k, &contract(OptionSide::Put, 95000.0, -1.0)] {
+            crate::repository::insert_contract(&conn, leg, None).unwrap();
+        }
+        let book = crate::repository::load_active_contracts(&conn, chrono::Utc::now().timestamp()).unwrap();
+        assert_eq!(book.len(), 2);
+        assert!(book.iter().all(|c| c.quantity > 0.0));
+
+        // Both shorts are margined naked, nothing offsets them
+        let margin = |contracts: &[Contract]| risk_manager.calculate_portfolio_risk(contracts, 100000.0, &RateCurve::default(), &iv);
+        assert!((margin(&book) - (margin(&[short_100k]) + margin(&[short_95k]))).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_portfolio_risk_caps_covered_spreads() {
+        let risk_manager = RiskManager::new(1.2);
+        let iv = |_: &str, _: f64, _: &str| Some(0.5);
+        
+        // Short 100k put covered by long 95k put: max loss is the 5k strike width
+        let put_spread = vec![
+            contract(OptionSide::Put, 100000.0, 1.0),
+            contract(OptionSide::Put, 95000.0, -1.0),
+        ];
+        let margin = risk_manager.calculate_portfolio_risk(&put_spread, 100000.0, &RateCurve::default(), &iv);
+        assert!((margin - 5000.0 * 1.2).abs() < 1e-6);
+        
+        // Short 100k call covered by long 110k call
+        let call_spread = vec![
+            contract(OptionSide::Call, 100000.0, 1.0),
+            contract(OptionSide::Call, 110000.0, -1.0),
+        ];
+        let margin = risk_manager.calculate_portfolio_risk(&call_spread, 100000.0, &RateCurve::default(), &iv);
+        assert!((margin - 10000.0 * 1.2).abs() < 1e-6);
+        
+        // A long put doesn't cover a short call
+        let mixed = vec![
+            contract(OptionSide::Call, 100000.0, 1.0),
+            contract(OptionSide::Put, 100000.0, -1.0),
+        ];
+        let naked = vec![contract(OptionSide::Call, 100000.0, 1.0)];
+        assert_eq!(
+            risk_manager.calculate_portfolio_risk(&mixed, 100000.0, &RateCurve::default(), &iv),
+            risk_manager.calculate_portfolio_risk(&naked, 100000.0, &RateCurve::default(), &iv),
+        );
+    }
+    
+    // 40 expiries of a short put and a short call each, more groups than one rayon task takes
+    fn large_book() -> Vec<Contract> {
+        (0..40)
+            .flat_map(|day| {
+                let expires = chrono::Utc::now().timestamp() + (day + 1) * 86400;
+                [
+                    Contract { expires, ..contract(OptionSide::Put, 90000.0 + day as f64 * 500.0, 0.1) },
+                    Contract { expires, ..contract(OptionSide::Call, 110000.0 - day as f64 * 500.0, 0.2) },
+                ]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parallel_portfolio_risk_matches_the_sum_of_groups() {
+        let risk_manager = RiskManager::new(1.2);
+        let iv = |_: &str, _: f64, _: &str| Some(0.5);
+        let book = large_book();
+        let expected: f64 = book
+            .iter()
+            .map(|c| risk_manager.calculate_portfolio_risk(std::slice::from_ref(c), 100000.0, &RateCurve::default(), &iv))
+            .sum();
+        let margin = risk_manager.calculate_portfolio_risk(&book, 100000.0, &RateCurve::default(), &iv);
+        assert!(margin > 0.0);
+        assert!((margin - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_margin_cache_reuses_unchanged_groups() {
+        let cache = Arc::new(MarginCache::new(60));
+        let cached = RiskManager::new(1.2).with_margin_cache(Some(cache.clone()));
+        let uncached = RiskManager::new(1.2);
+        let iv = |_: &str, _: f64, _: &str| Some(0.5);
+        let mut book = large_book();
+
+        let margin = cached.calculate_portfolio_risk(&book, 100000.0, &RateCurve::default(), &iv);
+        assert_eq!(margin, uncached.calculate_portfolio_risk(&book, 100000.0, &RateCurve::default(), &iv));
+        assert_eq!(cache.stats(), (0, 80));
+        assert_eq!(cached.calculate_portfolio_risk(&book, 100000.0, &RateCurve::default(), &iv), margin);
+        assert_eq!(cache.stats(), (80, 80));
+
+        // A new trade only margins its own group again
+        book.push(Contract { quantity: 0.3, ..book[0].clone() });
+        let margin = cached.calculate_portfolio_risk(&book, 100000.0, &RateCurve::default(), &iv);
+        assert_eq!(cache.stats(), (159, 81));
+        assert!((margin - uncached.calculate_portfolio_risk(&book, 100000.0, &RateCurve::default(), &iv)).abs() < 1e-9);
+
+        // A spot or IV move margins every group again
+        cached.calculate_portfolio_risk(&book, 101000.0, &RateCurve::default(), &iv);
+        assert_eq!(cache.stats(), (159, 161));
+        let higher_iv = |_: &str, _: f64, _: &str| Some(0.6);
+        cached.calculate_portfolio_risk(&book, 101000.0, &RateCurve::default(), &higher_iv);
+        assert_eq!(cache.stats(), (159, 241));
+    }
+
+    #[test]
+    fn test_multi_asset_risk_margins_each_underlying_at_its_spot() {
+        let risk_manager = RiskManager::new(1.2);
+        let btc_put = contract(OptionSide::Put, 100000.0, 1.0);
+        let eth_put = Contract { underlying: Asset::Eth, strike_price: 3000.0, ..btc_put.clone() };
+        // An ETH long doesn't offset a BTC short at the same strike
+        let eth_long = Contract { underlying: Asset::Eth, quantity: -1.0, ..btc_put.clone() };
+        let iv = |_: Asset, _: &str, _: f64, _: &str| Some(0.5);
+        let single_iv = |_: &str, _: f64, _: &str| Some(0.5);
+
+        let spots = HashMap::from([(Asset::Btc, 100000.0), (Asset::Eth, 3500.0)]);
+        let book = vec![btc_put.clone(), eth_put.clone(), eth_long.clone()];
+        let expected = risk_manager.calculate_portfolio_risk(&[btc_put], 100000.0, &RateCurve::default(), &single_iv)
+            + risk_manager.calculate_portfolio_risk(&[eth_put, eth_long], 3500.0, &RateCurve::default(), &single_iv);
+        assert!(expected > 0.0);
+        let margin = risk_manager.calculate_multi_asset_portfolio_risk(&book, &spots, &RateCurve::default(), &iv).unwrap();
+        assert!((margin - expected).abs() < 1e-6);
+
+        let btc_only = HashMap::from([(Asset::Btc, 100000.0)]);
+        assert!(risk_manager.calculate_multi_asset_portfolio_risk(&book, &btc_only, &RateCurve::default(), &iv).is_none());
+    }
+    
+    #[test]
+    fn test_aggregate_positions_groups_by_product() {
+        let now = chrono::Utc::now().timestamp();
+        let contract = |side: OptionSide, strike_price: f64, quantity: f64, expires: i64, premium: f64| Contract {
+            underlying: Asset::Btc,
+            side,
+            strike_price,
+            quantity,
+            expires,
+            premium,
+        };
+        let contracts = vec![
+            contract(OptionSide::Put, 95000.0, 1.0, now + 86400, 0.01),
+            contract(OptionSide::Put, 95000.0, 3.0, now + 86400, 0.02),
+            contract(OptionSide::Put, 95000.0, 1.0, now + 2 * 86400, 0.03),
+            contract(OptionSide::Call, 105000.0, 1.0, now + 86400, 0.01),
+            contract(OptionSide::Call, 105000.0, -1.0, now + 86400, 0.01),
+            contract(OptionSide::Call, 110000.0, 1.0, now - 60, 0.01),
+        ];
+
+        let positions = aggregate_positions(&contracts, now);
+        // The netted-out call and the expired call are left out
+        assert_eq!(positions.len(), 2);
+        assert_eq!(positions[0].expires, now + 86400);
+        assert_eq!(positions[0].net_quantity, 4.0);
+        assert_eq!(positions[0].contract_count, 2);
+        assert!((positions[0].average_premium - 0.0175).abs() < 1e-12);
+        assert_eq!(positions[1].expires, now + 2 * 86400);
+        assert_eq!(positions[1].net_quantity, 1.0);
+    }
+
+    #[test]
+    fn test_var_empty_book() {
+        let risk_manager = RiskManager::new(1.2);
+        let var = risk_manager.calculate_var(&[], 100000.0, &RateCurve::default(), &|_, _, _| Some(0.5), 0.6, 1.0);
+        
+        assert_eq!(var.var_95, 0.0);
+        assert_eq!(var.es_99, 0.0);
+        assert_eq!(var.scenario_count, VAR_SPOT_SCENARIOS * VAR_VOL_SCENARIOS);
+    }
+    
+    #[test]
+    fn test_var_short_put_book() {
+        let risk_manager = RiskManager::new(1.2);
+        let contracts = vec![Contract {
+            underlying: Asset::Btc,
+            side: OptionSide::Put,
+            strike_price: 95000.0,
+            quantity: 2.0,
+            expires: chrono::Utc::now().timestamp() + 7 * 86400,
+            premium: 0.01,
+        }];
+        
+        let var = risk_manager.calculate_var(&contracts, 100000.0, &RateCurve::default(), &|_, _, _| Some(0.5), 0.6, 1.0);
+        
+        assert!(var.var_95 > 0.0);
+        assert!(var.var_99 >= var.var_95);
+        assert!(var.es_95 >= var.var_95);
+        assert!(var.es_99 >= var.var_99);
+        // A 1-day loss on 2 puts can't exceed twice the strike
+        assert!(var.es_99 < 2.0 * 95000.0);
+    }
+    
+    #[test]
+    fn test_scenario_short_call_loses_on_rally() {
+        let risk_manager = RiskManager::new(1.2);
+        let contracts = vec![Contract {
+            underlying: Asset::Btc,
+            side: OptionSide::Call,
+            strike_price: 105000.0,
+            quantity: 1.0,
+            expires: chrono::Utc::now().timestamp() + 7 * 86400,
+            premium: 0.01,
+        }];
+        let iv = |_: &str, _: f64, _: &str| Some(0.5);
+        
+        let unchanged = risk_manager.evaluate_scenario(&contracts, 100000.0, &RateCurve::default(), &iv, 0.0, 0.0);
+        assert!(unchanged.pnl_usd.abs() < 1e-9);
+        assert_eq!(unchanged.shocked_spot_price, 100000.0);
+        
+        let rally = risk_manager.evaluate_scenario(&contracts, 100000.0, &RateCurve::default(), &iv, 20.0, 0.1);
+        assert!((rally.shocked_spot_price - 120000.0).abs() < 1e-6);
+        assert!(rally.pnl_usd < 0.0);
+        assert!(rally.margin_required_usd > unchanged.margin_required_usd);
+    }
+
+    #[test]
+    fn test_scenario_values_each_maturity_at_its_rate() {
+        let risk_manager = RiskManager::new(1.2);
+        let now = chrono::Utc::now().timestamp();
+        let call = |days: i64| Contract {
+            underlying: Asset::Btc,
+            side: OptionSide::Call,
+            strike_price: 105000.0,
+            quantity: 1.0,
+            expires: now + days * 86400,
+            premium: 0.01,
+        };
+        let (week, year) = (call(7), call(365));
+        let curve = RateCurve::parse("1w=0.02,1y=0.08").unwrap();
+        let iv = |_: &str, _: f64, _: &str| Some(0.5);
+        let pnl = |contracts: &[Contract], rates: &RateCurve| {
+            risk_manager.evaluate_scenario(contracts, 100000.0, rates, &iv, 10.0, 0.0).pnl_usd
+        };
+
+        let book = pnl(&[week.clone(), year.clone()], &curve);
+        let legs = pnl(std::slice::from_ref(&week), &RateCurve::flat(0.02))
+            + pnl(std::slice::from_ref(&year), &RateCurve::flat(0.08));
+        assert!((book - legs).abs() < 1e-3);
+        assert!((book - pnl(&[week, year], &RateCurve::flat(0.02))).abs() > 1.0);
+    }
+
+    #[test]
+    fn test_inverse_normal_cdf() {
+        assert!(inverse_normal_cdf(0.5).abs() < 1e-9);
+        assert!((inverse_normal_cdf(0.975) - 1.959964).abs() < 1e-4);
+        assert!((inverse_normal_cdf(0.01) + 2.326348).abs() < 1e-4);
+    }
+    
+    #[test]
+    fn test_iv_realized_vol_sanity_check() {
+        let risk_manager = RiskManager::new(1.2);
+        
+        assert!(risk_manager.is_iv_consistent_with_realized(0.5, Some(0.6)));
+        assert!(!risk_manager.is_iv_consistent_with_realized(0.2, Some(0.6)));
+        assert!(risk_manager.is_iv_consistent_with_realized(0.2, None));
+        assert!(risk_manager.is_iv_consistent_with_realized(0.2, Some(0.0)));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,499 @@
+// Rolling market metrics.
+// Volume, notional and premiums of each product over the last hour, 4 hours, 24 hours and
+// 7 days are kept in memory, each window sliding by one-minute buckets. They are rebuilt
+// from contracts at startup and updated with every trade, so /topBanner, /marketHighlights
+// and the leaderboards read them without scanning the tables. Windows are exact to the minute.
+
+use crate::error::ApiResult;
+use crate::models::{Asset, Contract, OptionSide};
+use crate::strikes;
+use crate::utils::{btc_to_sats, sats_to_btc, usd_to_cents, SATS_PER_BTC};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::RwLock;
+
+pub const BUCKET_SECS: i64 = 60;
+
+// How often products that expired with nothing left in a window are dropped from it
+const SWEEP_INTERVAL_SECS: i64 = 60 * 60;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash, async_graphql::Enum)]
+pub enum MetricsWindow {
+    #[serde(rename = "1h")]
+    OneHour,
+    #[serde(rename = "4h")]
+    FourHours,
+    #[default]
+    #[serde(rename = "24h")]
+    TwentyFourHours,
+    #[serde(rename = "7d")]
+    SevenDays,
+}
+
+impl MetricsWindow {
+    pub const ALL: [MetricsWindow; 4] =
+        [MetricsWindow::OneHour, MetricsWindow::FourHours, MetricsWindow::TwentyFourHours, MetricsWindow::SevenDays];
+
+    pub fn secs(self) -> i64 {
+        match self {
+            MetricsWindow::OneHour => 60 * 60,
+            MetricsWindow::FourHours => 4 * 60 * 60,
+            MetricsWindow::TwentyFourHours => 24 * 60 * 60,
+            MetricsWindow::SevenDays => 7 * 24 * 60 * 60,
+        }
+    }
+
+    /// Start of the window ending at `now`: the oldest of the minutes it spans
+    pub fn start(self, now: i64) -> i64 {
+        now - now.rem_euclid(BUCKET_SECS) + BUCKET_SECS - self.secs()
+    }
+}
+
+/// A product as traded: underlying, side, strike and expiry
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ProductId {
+    pub underlying: Asset,
+    pub side: OptionSide,
+    pub strike_price_cents: i64,
+    pub expires: i64,
+}
+
+impl ProductId {
+    pub fn of(contract: &Contract) -> Self {
+        Self {
+            underlying: contract.underlying,
+            side: contract.side,
+            strike_price_cents: strikes::deployment().normalize_cents(contract.underlying, usd_to_cents(contract.strike_price)),
+            expires: contract.expires,
+        }
+    }
+}
+
+/// Trading of one product over a window
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProductMetrics {
+    pub product: ProductId,
+    pub volume: f64,                    // Quantity traded in the window
+    pub notional_btc: f64,              // Sum of quantity * premium over the window
+    pub trades: i64,
+    pub avg_premium: Option<f64>,       // Per trade; None when not traded in the window
+    pub first_premium: Option<f64>,     // First traded in the window
+    pub last_premium: Option<f64>,      // Latest traded, in the window or before it
+    pub baseline_premium: Option<f64>,  // Last traded before the window
+}
+
+impl ProductMetrics {
+    /// % change of the premium over the window, from the last premium before it (or the first
+    /// in it for products new to the window) to the latest. None without a positive reference.
+    pub fn change_percent(&self) -> Option<f64> {
+        let reference = self.baseline_premium.or(self.first_premium).filter(|premium| *premium > 0.0)?;
+        let last = self.last_premium?;
+        Some((last - reference) / reference * 100.0)
+    }
+}
+
+// Trades of one product in one minute
+#[derive(Clone, Copy, Debug)]
+struct Bucket {
+    start: i64,
+    volume_sats: i64,
+    notional_sats: i128,      // Sum of quantity_sats * premium_sats
+    premium_sats_total: i64,  // Summed over trades, for the average premium
+    trades: i64,
+    first_premium_sats: i64,
+    last_premium_sats: i64,
+}
+
+#[derive(Debug, Default)]
+struct ProductWindow {
+    buckets: VecDeque<Bucket>,  // Oldest first
+    volume_sats: i64,           // Totals of the buckets
+    notional_sats: i128,
+    premium_sats_total: i64,
+    trades: i64,
+    baseline_premium_sats: Option<i64>,
+}
+
+impl ProductWindow {
+    fn metrics(&self, product: ProductId) -> ProductMetrics {
+        let last_premium_sats = self.buckets.back().map(|bucket| bucket.last_premium_sats).or(self.baseline_premium_sats);
+        ProductMetrics {
+            product,
+            volume: sats_to_btc(self.volume_sats),
+            notional_btc: self.notional_sats as f64 / (SATS_PER_BTC as f64 * SATS_PER_BTC as f64),
+            trades: self.trades,
+            avg_premium: (self.trades > 0).then(|| sats_to_btc(self.premium_sats_total) / self.trades as f64),
+            first_premium: self.buckets.front().map(|bucket| sats_to_btc(bucket.first_premium_sats)),
+            last_premium: last_premium_sats.map(sats_to_btc),
+            baseline_premium: self.baseline_premium_sats.map(sats_to_btc),
+        }
+    }
+}
+
+// The products of one window
+#[derive(Debug)]
+struct Windows {
+    window: MetricsWindow,
+    products: HashMap<ProductId, ProductWindow>,
+    // Every bucket of every product in the order it was opened, so expired ones are found
+    // without visiting all products
+    opened: VecDeque<(i64, ProductId)>,
+    volume_sats: HashMap<Asset, i64>,
+    swept_at: i64,
+}
+
+impl Windows {
+    fn new(window: MetricsWindow) -> Self {
+        Self { window, products: HashMap::new(), opened: VecDeque::new(), volume_sats: HashMap::new(), swept_at: 0 }
+    }
+
+    fn record(&mut self, product: ProductId, quantity_sats: i64, premium_sats: i64, timestamp: i64) {
+        // A trade stamped before the latest minute counts in it, keeping buckets in order
+        let start = timestamp - timestamp.rem_euclid(BUCKET_SECS);
+        let start = self.opened.back().map_or(start, |&(latest, _)| start.max(latest));
+        let window = self.products.entry(product).or_default();
+        let bucket = match window.buckets.back_mut() {
+            Some(bucket) if bucket.start == start => bucket,
+            _ => {
+                window.buckets.push_back(Bucket {
+                    start,
+                    volume_sats: 0,
+                    notional_sats: 0,
+                    premium_sats_total: 0,
+                    trades: 0,
+                    first_premium_sats: premium_sats,
+                    last_premium_sats: premium_sats,
+                });
+                self.opened.push_back((start, product));
+                window.buckets.back_mut().unwrap()
+            }
+        };
+        let notional_sats = quantity_sats as i128 * premium_sats as i128;
+        bucket.volume_sats += quantity_sats;
+        bucket.notional_sats += notional_sats;
+        bucket.premium_sats_total += premium_sats;
+        bucket.trades += 1;
+        bucket.last_premium_sats = premium_sats;
+        window.volume_sats += quantity_sats;
+        window.notional_sats += notional_sats;
+        window.premium_sats_total += premium_sats;
+        window.trades += 1;
+        *self.volume_sats.entry(product.underlying).or_default() += quantity_sats;
+    }
+
+    // Drop the buckets that have left the window by `now`, and now and then the products that
+    // expired before it with nothing left in it
+    fn advance(&mut self, now: i64) {
+        let window_start = self.window.start(now);
+        while let Some(&(start, product)) = self.opened.front() {
+            if start >= window_start {
+                break;
+            }
+            self.opened.pop_front();
+            let Some(window) = self.products.get_mut(&product) else {
+                continue;
+            };
+            let Some(bucket) = window.buckets.pop_front() else {
+                continue;
+            };
+            window.volume_sats -= bucket.volume_sats;
+            window.notional_sats -= bucket.notional_sats;
+            window.premium_sats_total -= bucket.premium_sats_total;
+            window.trades -= bucket.trades;
+            window.baseline_premium_sats = Some(bucket.last_premium_sats);
+            *self.volume_sats.entry(product.underlying).or_default() -= bucket.volume_sats;
+        }
+        if now - self.swept_at >= SWEEP_INTERVAL_SECS {
+            self.products.retain(|product, window| is_listed(product, window, window_start));
+            self.swept_at = now;
+        }
+    }
+}
+
+// Last premium of each product traded before ?1 and expiring no earlier, over idx_contracts_product
+const BASELINES_SQL: &str = "SELECT underlying, side, strike_price_cents, expires, premium_sats FROM contracts
+     WHERE id IN (SELECT MAX(id) FROM contracts WHERE created_at < ?1 AND expires >= ?1
+                  GROUP BY underlying, side, strike_price_cents, expires)";
+
+#[derive(Debug)]
+pub struct RollingMetrics {
+    windows: RwLock<Vec<Windows>>,  // One per MetricsWindow, in ALL order
+}
+
+impl Default for RollingMetrics {
+    fn default() -> Self {
+        Self { windows: RwLock::new(MetricsWindow::ALL.into_iter().map(Windows::new).collect()) }
+    }
+}
+
+impl RollingMetrics {
+    /// Rebuild the windows ending at `now` from the contracts traded in them, with the last
+    /// premium traded before each window as the baseline of the products still live in it
+    pub fn load(conn: &Connection, now: i64) -> ApiResult<Self> {
+        let metrics = Self::default();
+        let mut all = metrics.windows.write().unwrap();
+        for windows in all.iter_mut() {
+            let window_start = windows.window.start(now);
+            let mut stmt = conn.prepare(BASELINES_SQL)?;
+            let baselines = stmt
+                .query_map(params![window_start], |row| Ok((product_from_row(row)?, row.get::<_, i64>(4)?)))?
+                .collect::<Result<Vec<_>, _>>()?;
+            for (product, premium_sats) in baselines {
+                windows.products.entry(product).or_default().baseline_premium_sats = Some(premium_sats);
+            }
+
+            let mut stmt = conn.prepare(
+                "SELECT underlying, side, strike_price_cents, expires, quantity_sats, premium_sats, created_at
+                 FROM contracts WHERE created_at >= ?1 ORDER BY created_at ASC, id ASC",
+            )?;
+            let trades = stmt
+                .query_map(params![window_start], |row| {
+                    Ok((product_from_row(row)?, row.get::<_, i64>(4)?, row.get::<_, i64>(5)?, row.get::<_, i64>(6)?))
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            for (product, quantity_sats, premium_sats, created_at) in trades {
+                windows.record(product, quantity_sats, premium_sats, created_at);
+            }
+        }
+        drop(all);
+        Ok(metrics)
+    }
+
+    /// Count a contract traded at `timestamp`
+    pub fn record_trade(&self, contract: &Contract, timestamp: i64) {
+        let product = ProductId::of(contract);
+        let (quantity_sats, premium_sats) = (btc_to_sats(contract.quantity), btc_to_sats(contract.premium));
+        for windows in self.windows.write().unwrap().iter_mut() {
+            windows.record(product, quantity_sats, premium_sats, timestamp);
+        }
+    }
+
+    /// Quantity traded over `window` ending at `now`, of one underlying or all of them
+    pub fn volume(&self, now: i64, window: MetricsWindow, asset: Option<Asset>) -> f64 {
+        self.with_window(now, window, |windows| {
+            let volume_sats = match asset {
+                Some(asset) => windows.volume_sats.get(&asset).copied().unwrap_or(0),
+                None => windows.volume_sats.values().sum(),
+            };
+            sats_to_btc(volume_sats)
+        })
+    }
+
+    /// Every product traded in `window` ending at `now` or live through it with an earlier
+    /// trade, of one underlying or all of them, ordered by underlying, expiry, strike and side
+    pub fn products(&self, now: i64, window: MetricsWindow, asset: Option<Asset>) -> Vec<ProductMetrics> {
+        let window_start = window.start(now);
+        let mut products: Vec<ProductMetrics> = self.with_window(now, window, |windows| {
+            windows
+                .products
+                .iter()
+                .filter(|(product, window)| is_listed(product, window, window_start))
+                .filter(|(product, _)| asset.is_none_or(|asset| product.underlying == asset))
+                .map(|(product, window)| window.metrics(*product))
+                .collect()
+        });
+        products.sort_by_key(|metrics| {
+            let product = metrics.product;
+            (product.underlying, product.expires, product.strike_price_cents, product.side == OptionSide::Put)
+        });
+        products
+    }
+
+    fn with_window<T>(&self, now: i64, window: MetricsWindow, read: impl FnOnce(&Windows) -> T) -> T {
+        let mut all = self.windows.write().unwrap();
+        let windows = all.iter_mut().find(|windows| windows.window == window).expect("every window is kept");
+        windows.advance(now);
+        read(windows)
+    }
+}
+
+// Whether a product belongs in the window starting at `window_start`
+fn is_listed(product: &ProductId, window: &ProductWindow, window_start: i64) -> bool {
+    !window.buckets.is_empty() || product.expires >= window_start
+}
+
+fn product_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<ProductId> {
+    Ok(ProductId { underlying: row.get(0)?, side: row.get(1)?, strike_price_cents: row.get(2)?, expires: row.get(3)? })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::insert_contract;
+
+    const DAY: i64 = 24 * 60 * 60;
+
+    fn contract(side: OptionSide, quantity: f64, premium: f64) -> Contract {
+        Contract { underlying: Asset::Btc, side, strike_price: 100_000.0, quantity, expires: 10 * DAY, premium }
+    }
+
+    #[test]
+    fn test_window_slides_by_the_minute() {
+        let metrics = RollingMetrics::default();
+        let day = MetricsWindow::TwentyFourHours;
+        let t0 = 1_000 * BUCKET_SECS;
+        metrics.record_trade(&contract(OptionSide::Call, 0.5, 0.125), t0);
+        metrics.record_trade(&contract(OptionSide::Call, 0.25, 0.375), t0 + 30);
+        metrics.record_trade(&Contract { underlying: Asset::Eth, ..contract(OptionSide::Put, 2.0, 0.001) }, t0);
+        metrics.record_trade(&contract(OptionSide::Put, 1.0, 0.02), t0 + 2 * BUCKET_SECS);
+
+        assert_eq!(metrics.volume(t0 + 90, day, Some(Asset::Btc)), 1.75);
+        assert_eq!(metrics.volume(t0 + 90, day, None), 3.75);
+        let products = metrics.products(t0 + 90, day, Some(Asset::Btc));
+        assert_eq!(products.iter().map(|p| p.product.side).collect::<Vec<_>>(), vec![OptionSide::Call, OptionSide::Put]);
+        let call = &products[0];
+        assert_eq!((call.volume, call.avg_premium, call.trades), (0.75, Some(0.25), 2));
+        assert_eq!((call.first_premium, call.last_premium, call.baseline_premium), (Some(0.125), Some(0.375), None));
+        assert_eq!(call.notional_btc, 0.5 * 0.125 + 0.25 * 0.375);
+        assert_eq!(call.change_percent(), Some(200.0));
+
+        // The first minute leaves the window a day after it started
+        assert_eq!(metrics.volume(t0 + DAY - 1, day, Some(Asset::Btc)), 1.75);
+        assert_eq!(metrics.volume(t0 + DAY, day, Some(Asset::Btc)), 1.0);
+        metrics.record_trade(&contract(OptionSide::Call, 0.1, 0.1875), t0 + DAY);
+        let call = metrics.products(t0 + DAY, day, Some(Asset::Btc)).remove(0);
+        assert_eq!((call.volume, call.baseline_premium), (0.1, Some(0.375)));
+        assert_eq!(call.change_percent(), Some(-50.0));
+        // Products outlive their trades while live, with the last premium as it stands
+        let call = metrics.products(t0 + 2 * DAY, day, Some(Asset::Btc)).remove(0);
+        assert_eq!((call.trades, call.last_premium, call.change_percent()), (0, Some(0.1875), Some(0.0)));
+        assert!(metrics.products(t0 + 12 * DAY, day, None).is_empty());
+    }
+
+    #[test]
+    fn test_windows_are_kept_apart() {
+        let metrics = RollingMetrics::default();
+        let t0 = 1_000 * BUCKET_SECS;
+        metrics.record_trade(&contract(OptionSide::Call, 0.5, 0.01), t0);
+        let now = t0 + 2 * 60 * 60;
+        let volumes: Vec<f64> = MetricsWindow::ALL.iter().map(|window| metrics.volume(now, *window, None)).collect();
+        assert_eq!(volumes, vec![0.0, 0.5, 0.5, 0.5]);
+        assert_eq!(metrics.volume(t0 + 3 * DAY, MetricsWindow::SevenDays, None), 0.5);
+    }
+
+    // The premium changes of `since` to `now` as the leaderboards used to compute them, with a
+    // few queries per product
+    fn premium_changes_per_product(conn: &Connection, now: i64, since: i64) -> Vec<(ProductId, i64, i64)> {
+        let mut stmt = conn
+            .prepare("SELECT DISTINCT underlying, side, strike_price_cents, expires FROM contracts WHERE expires > ?1")
+            .unwrap();
+        let products: Vec<ProductId> = stmt.query_map(params![now], product_from_row).unwrap().map(Result::unwrap).collect();
+        let premium = |product: &ProductId, filter: &str, order: &str| -> Option<i64> {
+            conn.query_row(
+                &format!(
+                    "SELECT premium_sats FROM contracts
+                     WHERE underlying = ?1 AND side = ?2 AND strike_price_cents = ?3 AND expires = ?4 AND {}
+                     ORDER BY id {} LIMIT 1",
+                    filter, order
+                ),
+                params![product.underlying, product.side, product.strike_price_cents, product.expires, since],
+                |row| row.get(0),
+            )
+            .ok()
+        };
+        products
+            .into_iter()
+            .map(|product| {
+                let latest = premium(&product, "?5 = ?5", "DESC").unwrap();
+                let baseline = premium(&product, "created_at < ?5", "DESC").or_else(|| premium(&product, "created_at >= ?5", "ASC")).unwrap();
+                (product, latest, baseline)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_premium_changes_agree_across_implementations() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init_db(&conn).unwrap();
+        let now = 1_000 * DAY + 1_234;
+        let metrics = RollingMetrics::default();
+
+        // Three days of trades on 24 products, some expired, at pseudo-random times and premiums
+        let mut seed: u64 = 42;
+        let mut next = |n: u64| {
+            seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+            (seed >> 33) % n
+        };
+        let mut trades: Vec<(Contract, i64)> = (0..400)
+            .map(|_| {
+                let contract = Contract {
+                    underlying: if next(4) == 0 { Asset::Eth } else { Asset::Btc },
+                    side: if next(2) == 0 { OptionSide::Call } else { OptionSide::Put },
+                    strike_price: [90_000.0, 100_000.0, 110_000.0][next(3) as usize],
+                    quantity: (1 + next(100)) as f64 / 100.0,
+                    expires: [now - 3_600, now + DAY, now + 3 * DAY][next(3) as usize],
+                    premium: (1 + next(500)) as f64 / 10_000.0,
+                };
+                (contract, now - 3 * DAY + next(3 * DAY as u64) as i64)
+            })
+            .collect();
+        trades.sort_by_key(|(_, created_at)| *created_at);
+        for (contract, created_at) in &trades {
+            let id = insert_contract(&conn, contract, None).unwrap();
+            conn.execute("UPDATE contracts SET created_at = ?1 WHERE id = ?2", params![created_at, id]).unwrap();
+            metrics.record_trade(contract, *created_at);
+        }
+
+        for window in MetricsWindow::ALL {
+            let since = window.start(now);
+            let mut expected = premium_changes_per_product(&conn, now, since);
+            expected.sort_by_key(|(product, ..)| (product.underlying, product.expires, product.strike_price_cents, product.side == OptionSide::Put));
+
+            let set_based: Vec<(ProductId, i64, i64)> = crate::repository::product_premium_changes(&conn, now, since, None)
+                .unwrap()
+                .into_iter()
+                .map(|change| {
+                    let product = ProductId {
+                        underlying: change.underlying,
+                        side: change.side,
+                        strike_price_cents: change.strike_price_cents,
+                        expires: change.expires,
+                    };
+                    (product, btc_to_sats(change.current_premium), btc_to_sats(change.baseline_premium))
+                })
+                .collect();
+            assert_eq!(set_based, expected, "{:?}", window);
+
+            let in_memory: Vec<(ProductId, i64, i64)> = metrics
+                .products(now, window, None)
+                .into_iter()
+                .filter(|metrics| metrics.product.expires > now)
+                .map(|metrics| {
+                    let reference = metrics.baseline_premium.or(metrics.first_premium).unwrap();
+                    (metrics.product, btc_to_sats(metrics.last_premium.unwrap()), btc_to_sats(reference))
+                })
+                .collect();
+            assert_eq!(in_memory, expected, "{:?}", window);
+        }
+    }
+
+    #[test]
+    fn test_baselines_use_the_product_index() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init_db(&conn).unwrap();
+        let plan = crate::db::query_plan(&conn, BASELINES_SQL).unwrap();
+        assert!(plan.contains(&"SCAN contracts USING COVERING INDEX idx_contracts_product".to_string()), "{:?}", plan);
+    }
+
+    #[test]
+    fn test_load_matches_recorded_trades() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init_db(&conn).unwrap();
+        let now = chrono::Utc::now().timestamp();
+        let contract = |side, quantity, premium| Contract { expires: now + 10 * DAY, ..contract(side, quantity, premium) };
+        // The call traded the day before and again now, the put only the day before
+        insert_contract(&conn, &contract(OptionSide::Call, 1.0, 0.01), None).unwrap();
+        insert_contract(&conn, &contract(OptionSide::Put, 1.0, 0.02), None).unwrap();
+        conn.execute("UPDATE contracts SET created_at = ?1", params![now - DAY - BUCKET_SECS]).unwrap();
+        insert_contract(&conn, &contract(OptionSide::Call, 0.5, 0.02), None).unwrap();
+
+        let metrics = RollingMetrics::load(&conn, now).unwrap();
+        let day = MetricsWindow::TwentyFourHours;
+        assert_eq!(metrics.volume(now, day, None), 0.5);
+        assert_eq!(metrics.volume(now, MetricsWindow::SevenDays, None), 2.5);
+        let products = metrics.products(now, day, None);
+        assert_eq!((products[0].avg_premium, products[0].baseline_premium), (Some(0.02), Some(0.01)));
+        assert_eq!((products[1].trades, products[1].last_premium), (0, Some(0.02)));
+    }
+}
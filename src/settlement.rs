@@ -0,0 +1,593 @@
+// Settlement price methodology.
+// Settling at the single oracle price read at expiry lets anyone who can move spot for a
+// moment move every payoff. Instead, the spot price of each underlying with open contracts is
+// sampled during the window before their maturity (SETTLEMENT_WINDOW_SECS, 30 minutes before
+// 08:00 UTC by default) into price_history, next to the regular spot samples, and the settlement price is the time-weighted average (twap) or the
+// median of those samples. The attestation job signs and stores it in settlement_prices along
+// with the method and sample count, and settlement uses the attested price. A maturity with
+// too few samples, e.g. because the service was down during the window, falls back to the
+// spot price at attestation time, recorded as method spot.
+// The settlement report summarizes what each settlement meant for the pool: premium kept,
+// payoffs owed and the resulting P&L per product.
+
+use crate::error::ApiResult;
+use crate::price_history;
+use crate::models::{product_symbol, Asset, ContractStatus, OptionSide};
+use crate::repository::Repository;
+use crate::sources::PriceSource;
+use crate::supervisor::Supervisor;
+use crate::utils::{cents_to_usd, format_sats};
+use chrono::Utc;
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use std::env;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::interval;
+
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SettlementMethod {
+    #[default]
+    Twap,    // Each sample weighted by the time until the next one (or the maturity)
+    Median,
+    Spot,    // The price when the maturity was attested
+}
+
+impl std::str::FromStr for SettlementMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "twap" => Ok(SettlementMethod::Twap),
+            "median" => Ok(SettlementMethod::Median),
+            "spot" => Ok(SettlementMethod::Spot),
+            other => Err(format!("unknown settlement method '{}' (twap, median or spot)", other)),
+        }
+    }
+}
+
+impl fmt::Display for SettlementMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SettlementMethod::Twap => write!(f, "twap"),
+            SettlementMethod::Median => write!(f, "median"),
+            SettlementMethod::Spot => write!(f, "spot"),
+        }
+    }
+}
+
+impl ToSql for SettlementMethod {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.to_string().into())
+    }
+}
+
+impl FromSql for SettlementMethod {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value.as_str()?.parse().map_err(|_| FromSqlError::InvalidType)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SettlementConfig {
+    pub method: SettlementMethod,
+    pub window: Duration,           // Sampled before each maturity
+    pub sample_interval: Duration,
+    pub min_samples: usize,         // Fewer falls back to spot
+}
+
+impl Default for SettlementConfig {
+    fn default() -> Self {
+        Self {
+            method: SettlementMethod::Twap,
+            window: Duration::from_secs(30 * 60),
+            sample_interval: Duration::from_secs(60),
+            min_samples: 10,
+        }
+    }
+}
+
+impl SettlementConfig {
+    /// SETTLEMENT_METHOD (twap), SETTLEMENT_WINDOW_SECS (1800),
+    /// SETTLEMENT_SAMPLE_INTERVAL_SECS (60) and SETTLEMENT_MIN_SAMPLES (10)
+    pub fn from_env() -> Result<Self, String> {
+        let defaults = Self::default();
+        let method = match env::var("SETTLEMENT_METHOD") {
+            Ok(method) if !method.trim().is_empty() => method.parse()?,
+            _ => defaults.method,
+        };
+        let secs = |name: &str, default: Duration| {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .map(Duration::from_secs)
+                .unwrap_or(default)
+        };
+        Ok(Self {
+            method,
+            window: secs("SETTLEMENT_WINDOW_SECS", defaults.window),
+            sample_interval: secs("SETTLEMENT_SAMPLE_INTERVAL_SECS", defaults.sample_interval).max(Duration::from_secs(1)),
+            min_samples: env::var("SETTLEMENT_MIN_SAMPLES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.min_samples)
+                .max(1),
+        })
+    }
+
+    fn window_secs(&self) -> i64 {
+        self.window.as_secs() as i64
+    }
+}
+
+/// A settlement price and how it was arrived at
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct SettlementPrice {
+    pub price: f64,                 // USD
+    pub method: SettlementMethod,
+    pub sample_count: usize,
+    pub window_start: Option<i64>,  // First second averaged over; None for spot
+}
+
+impl SettlementPrice {
+    pub fn spot(price: f64) -> Self {
+        Self { price, method: SettlementMethod::Spot, sample_count: 1, window_start: None }
+    }
+}
+
+/// A spot price sampled for settlement
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sample {
+    pub timestamp: i64,
+    pub price: f64,
+}
+
+/// Samples of `underlying` in price_history taken in [from, to), oldest first
+pub fn load_samples(conn: &Connection, underlying: Asset, from: i64, to: i64) -> ApiResult<Vec<Sample>> {
+    let mut stmt = conn.prepare(
+        "SELECT timestamp, price_cents FROM price_history
+         WHERE asset = ?1 AND timestamp >= ?2 AND timestamp < ?3
+         ORDER BY timestamp ASC",
+    )?;
+    let rows = stmt.query_map(params![underlying, from, to], |row| {
+        Ok(Sample { timestamp: row.get(0)?, price: cents_to_usd(row.get(1)?) })
+    })?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// Underlyings with open or pending contracts maturing within `window_secs` after `now`
+pub fn underlyings_in_window(conn: &Connection, now: i64, window_secs: i64) -> ApiResult<Vec<Asset>> {
+    let mut stmt = conn.prepare(
+        "SELECT DISTINCT underlying FROM contracts
+         WHERE expires > ?1 AND expires <= ?2 AND status IN (?3, ?4)
+         ORDER BY underlying",
+    )?;
+    let rows = stmt.query_map(
+        params![now, now + window_secs, ContractStatus::Open, ContractStatus::Pending],
+        |row| row.get(0),
+    )?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// Time-weighted average of `samples` (oldest first) up to `end`
+pub fn twap(samples: &[Sample], end: i64) -> Option<f64> {
+    let (mut weighted, mut total) = (0.0, 0.0);
+    for (i, sample) in samples.iter().enumerate() {
+        let until = samples.get(i + 1).map_or(end, |next| next.timestamp);
+        let weight = (until - sample.timestamp).max(0) as f64;
+        weighted += sample.price * weight;
+        total += weight;
+    }
+    match total > 0.0 {
+        true => Some(weighted / total),
+        // All samples at the maturity itself: plain average
+        false if !samples.is_empty() => Some(samples.iter().map(|s| s.price).sum::<f64>() / samples.len() as f64),
+        false => None,
+    }
+}
+
+pub fn median(samples: &[Sample]) -> Option<f64> {
+    let mut prices: Vec<f64> = samples.iter().map(|s| s.price).collect();
+    if prices.is_empty() {
+        return None;
+    }
+    prices.sort_by(|a, b| a.total_cmp(b));
+    let mid = prices.len() / 2;
+    Some(match prices.len() % 2 {
+        0 => (prices[mid - 1] + prices[mid]) / 2.0,
+        _ => prices[mid],
+    })
+}
+
+/// Settlement price of `underlying` at `maturity` from the samples taken in its window, or
+/// None when there are fewer than `min_samples` of them (or the method is spot)
+pub fn sampled_price(conn: &Connection, config: &SettlementConfig, underlying: Asset, maturity: i64) -> ApiResult<Option<SettlementPrice>> {
+    if config.method == SettlementMethod::Spot {
+        return Ok(None);
+    }
+    let window_start = maturity - config.window_secs();
+    let samples = load_samples(conn, underlying, window_start, maturity + 1)?;
+    if samples.len() < config.min_samples {
+        return Ok(None);
+    }
+    let price = match config.method {
+        SettlementMethod::Median => median(&samples),
+        _ => twap(&samples, maturity),
+    };
+    Ok(price.map(|price| SettlementPrice {
+        price,
+        method: config.method,
+        sample_count: samples.len(),
+        window_start: Some(window_start),
+    }))
+}
+
+/// The contracts of one product settled together at expiry, or exercised at one time.
+/// BTC amounts are 8 decimal strings; USD amounts use the BTC price recorded with each flow.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SettlementSummary {
+    pub product_symbol: String,
+    pub underlying: Asset,
+    pub side: OptionSide,
+    pub strike_price: f64,
+    pub expires: i64,
+    pub status: ContractStatus,                       // settled, or exercised before expiry
+    pub settled_at: i64,
+    pub settlement_price: f64,                        // Of the underlying
+    pub settlement_method: Option<SettlementMethod>,  // As attested; None for exercises
+    pub settlement_btc_price: f64,                    // BTC price payoffs were converted at
+    pub contracts: i64,
+    pub quantity: String,             // Open at settlement, in units of the underlying
+    pub premium_btc: String,          // Received when the contracts were sold
+    pub close_btc: String,            // Paid buying quantity back before settlement
+    pub payoff_btc: String,           // Owed to buyers at settlement
+    pub fee_btc: String,              // Fees charged less rebates paid when the contracts were sold
+    pub premium_retained_btc: String, // premium_btc - close_btc
+    pub net_pnl_btc: String,          // premium_retained_btc + fee_btc - payoff_btc
+    pub premium_usd: f64,
+    pub close_usd: f64,
+    pub payoff_usd: f64,
+    pub fee_usd: f64,
+    pub net_pnl_usd: f64,
+}
+
+/// Totals of a settlement report
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct SettlementTotals {
+    pub settlements: usize,
+    pub contracts: i64,
+    pub premium_btc: String,
+    pub close_btc: String,
+    pub payoff_btc: String,
+    pub fee_btc: String,
+    pub premium_retained_btc: String,
+    pub net_pnl_btc: String,
+    pub premium_usd: f64,
+    pub close_usd: f64,
+    pub payoff_usd: f64,
+    pub fee_usd: f64,
+    pub net_pnl_usd: f64,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SettlementReport {
+    pub from: i64,
+    pub to: i64,
+    pub settlements: Vec<SettlementSummary>,
+    pub totals: SettlementTotals,
+}
+
+// Cash flows of a group of contracts in satoshis and cents
+#[derive(Default, Clone, Copy)]
+struct Flows {
+    premium_sats: i64,
+    close_sats: i64,
+    payoff_sats: i64,
+    fee_sats: i64,
+    premium_cents: i64,
+    close_cents: i64,
+    payoff_cents: i64,
+    fee_cents: i64,
+}
+
+impl Flows {
+    fn add(&mut self, other: &Flows) {
+        self.premium_sats += other.premium_sats;
+        self.close_sats += other.close_sats;
+        self.payoff_sats += other.payoff_sats;
+        self.fee_sats += other.fee_sats;
+        self.premium_cents += other.premium_cents;
+        self.close_cents += other.close_cents;
+        self.payoff_cents += other.payoff_cents;
+        self.fee_cents += other.fee_cents;
+    }
+
+    fn retained_sats(&self) -> i64 {
+        self.premium_sats - self.close_sats
+    }
+
+    fn net_pnl_sats(&self) -> i64 {
+        self.retained_sats() + self.fee_sats - self.payoff_sats
+    }
+
+    fn net_pnl_usd(&self) -> f64 {
+        cents_to_usd(self.premium_cents - self.close_cents + self.fee_cents - self.payoff_cents)
+    }
+}
+
+/// Settlements and exercises in [from, to), optionally on one underlying, oldest first,
+/// one row per product and settlement time
+pub fn settlement_report(conn: &Connection, from: i64, to: i64, underlying: Option<Asset>) -> ApiResult<SettlementReport> {
+    let mut stmt = conn.prepare(
+        "SELECT c.underlying, c.side, c.strike_price_cents, c.expires, c.status, c.settled_at,
+                c.settlement_price_cents, c.settlement_btc_price_cents, sp.method,
+                COUNT(*), SUM(c.quantity_sats - c.closed_quantity_sats),
+                SUM(CAST(ROUND(c.premium_sats * (c.quantity_sats / 1e8)) AS INTEGER)),
+                SUM(COALESCE(f.close_sats, 0)), SUM(COALESCE(f.payout_sats, 0)),
+                SUM(COALESCE(f.premium_cents, 0)), SUM(COALESCE(f.close_cents, 0)), SUM(COALESCE(f.payout_cents, 0)),
+                SUM(COALESCE(fe.fee_sats, 0)), SUM(COALESCE(fe.fee_cents, 0))
+         FROM contracts c
+         LEFT JOIN (
+             SELECT contract_id,
+                    SUM(CASE kind WHEN 'close' THEN btc_sats END) AS close_sats,
+                    SUM(CASE kind WHEN 'payout' THEN btc_sats END) AS payout_sats,
+                    SUM(CASE kind WHEN 'premium' THEN usd_cents END) AS premium_cents,
+                    SUM(CASE kind WHEN 'close' THEN usd_cents END) AS close_cents,
+                    SUM(CASE kind WHEN 'payout' THEN usd_cents END) AS payout_cents
+             FROM conversions GROUP BY contract_id
+         ) f ON f.contract_id = c.id
+         LEFT JOIN fees fe ON fe.contract_id = c.id
+         LEFT JOIN settlement_prices sp
+             ON c.status = ?1 AND sp.underlying = c.underlying AND sp.timestamp = c.expires
+         WHERE c.status IN (?1, ?2) AND c.settled_at >= ?3 AND c.settled_at < ?4
+               AND (?5 IS NULL OR c.underlying = ?5)
+         GROUP BY c.underlying, c.side, c.strike_price_cents, c.expires, c.status, c.settled_at,
+                  c.settlement_price_cents, c.settlement_btc_price_cents
+         ORDER BY c.settled_at ASC, c.underlying ASC, c.expires ASC, c.side ASC, c.strike_price_cents ASC",
+    )?;
+    let rows = stmt.query_map(
+        params![ContractStatus::Settled, ContractStatus::Exercised, from, to, underlying],
+        |row| {
+            let flows = Flows {
+                premium_sats: row.get(11)?,
+                close_sats: row.get(12)?,
+                payoff_sats: row.get(13)?,
+                fee_sats: row.get(17)?,
+                premium_cents: row.get(14)?,
+                close_cents: row.get(15)?,
+                payoff_cents: row.get(16)?,
+                fee_cents: row.get(18)?,
+            };
+            let underlying: Asset = row.get(0)?;
+            let side: OptionSide = row.get(1)?;
+            let strike_price = cents_to_usd(row.get(2)?);
+            let expires: i64 = row.get(3)?;
+            let summary = SettlementSummary {
+                product_symbol: product_symbol(underlying, &side, strike_price, expires),
+                underlying,
+                side,
+                strike_price,
+                expires,
+                status: row.get(4)?,
+                settled_at: row.get(5)?,
+                settlement_price: cents_to_usd(row.get(6)?),
+                settlement_method: row.get(8)?,
+                settlement_btc_price: cents_to_usd(row.get(7)?),
+                contracts: row.get(9)?,
+                quantity: format_sats(row.get(10)?),
+                premium_btc: format_sats(flows.premium_sats),
+                close_btc: format_sats(flows.close_sats),
+                payoff_btc: format_sats(flows.payoff_sats),
+                fee_btc: format_sats(flows.fee_sats),
+                premium_retained_btc: format_sats(flows.retained_sats()),
+                net_pnl_btc: format_sats(flows.net_pnl_sats()),
+                premium_usd: cents_to_usd(flows.premium_cents),
+                close_usd: cents_to_usd(flows.close_cents),
+                payoff_usd: cents_to_usd(flows.payoff_cents),
+                fee_usd: cents_to_usd(flows.fee_cents),
+                net_pnl_usd: flows.net_pnl_usd(),
+            };
+            Ok((summary, flows))
+        },
+    )?;
+
+    let mut settlements = Vec::new();
+    let mut total = Flows::default();
+    let mut contracts = 0;
+    for row in rows {
+        let (summary, flows) = row?;
+        total.add(&flows);
+        contracts += summary.contracts;
+        settlements.push(summary);
+    }
+    let totals = SettlementTotals {
+        settlements: settlements.len(),
+        contracts,
+        premium_btc: format_sats(total.premium_sats),
+        close_btc: format_sats(total.close_sats),
+        payoff_btc: format_sats(total.payoff_sats),
+        fee_btc: format_sats(total.fee_sats),
+        premium_retained_btc: format_sats(total.retained_sats()),
+        net_pnl_btc: format_sats(total.net_pnl_sats()),
+        premium_usd: cents_to_usd(total.premium_cents),
+        close_usd: cents_to_usd(total.close_cents),
+        payoff_usd: cents_to_usd(total.payoff_cents),
+        fee_usd: cents_to_usd(total.fee_cents),
+        net_pnl_usd: total.net_pnl_usd(),
+    };
+    Ok(SettlementReport { from, to, settlements, totals })
+}
+
+/// Sample the spot price of every underlying with a maturity in its settlement window.
+/// Returns the number of samples recorded.
+pub async fn sample_prices(
+    repository: &Repository,
+    price_source: &dyn PriceSource,
+    config: &SettlementConfig,
+    now: i64,
+) -> ApiResult<usize> {
+    let window_secs = config.window_secs();
+    let underlyings = repository.run(move |conn| underlyings_in_window(conn, now, window_secs)).await?;
+    let mut recorded = 0;
+    for underlying in underlyings {
+        match price_source.get_price(underlying).await {
+            Ok(price) => {
+                repository.run(move |conn| price_history::record(conn, underlying, price, now)).await?;
+                recorded += 1;
+            }
+            Err(e) => eprintln!("⚠️  No {} price to sample for settlement: {}", underlying, e),
+        }
+    }
+    Ok(recorded)
+}
+
+/// Sample prices every SETTLEMENT_SAMPLE_INTERVAL_SECS while maturities are in their window
+pub fn start_settlement_sampler(
+    supervisor: &Supervisor,
+    repository: Repository,
+    price_source: Arc<dyn PriceSource>,
+    config: SettlementConfig,
+) {
+    if config.method == SettlementMethod::Spot {
+        return;
+    }
+    supervisor.spawn("settlement_sampler", move || {
+        let (repository, price_source) = (repository.clone(), price_source.clone());
+        async move {
+            let mut ticker = interval(config.sample_interval);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = sample_prices(&repository, price_source.as_ref(), &config, Utc::now().timestamp()).await {
+                    eprintln!("Error sampling settlement prices: {}", e);
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fees::{self, TradeFee};
+    use crate::conversions::{self, ConversionKind};
+    use crate::models::{Contract, QuoteCurrency};
+    use crate::repository::{close_contract, expire_contracts, insert_contract, settle_expired_maturity};
+
+    fn sample(timestamp: i64, price: f64) -> Sample {
+        Sample { timestamp, price }
+    }
+
+    #[test]
+    fn test_twap_and_median() {
+        // 100 for 10 minutes, then 110 for 20 minutes
+        let samples = [sample(0, 100.0), sample(600, 110.0), sample(1200, 110.0)];
+        assert!((twap(&samples, 1800).unwrap() - (100.0 * 600.0 + 110.0 * 1200.0) / 1800.0).abs() < 1e-9);
+        assert_eq!(median(&samples), Some(110.0));
+        assert_eq!(median(&samples[..2]), Some(105.0));
+        // A spike just before maturity barely moves either
+        let spiked = [sample(0, 100.0), sample(1790, 200.0)];
+        assert!(twap(&spiked, 1800).unwrap() < 101.0);
+        assert_eq!(twap(&[sample(1800, 100.0)], 1800), Some(100.0));
+        assert_eq!(twap(&[], 1800), None);
+        assert_eq!(median(&[]), None);
+        assert_eq!("Median".parse::<SettlementMethod>(), Ok(SettlementMethod::Median));
+        assert!("vwap".parse::<SettlementMethod>().is_err());
+    }
+
+    #[test]
+    fn test_sampled_price() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init_db(&conn).unwrap();
+        let maturity = 1_800_000_000;
+        insert_contract(
+            &conn,
+            &Contract {
+                underlying: Asset::Btc,
+                side: OptionSide::Call,
+                strike_price: 100_000.0,
+                quantity: 0.1,
+                expires: maturity,
+                premium: 0.01,
+            },
+            None,
+        )
+        .unwrap();
+        let config = SettlementConfig { min_samples: 3, ..Default::default() };
+
+        // Only maturities inside the window are sampled
+        assert!(underlyings_in_window(&conn, maturity - 1801, 1800).unwrap().is_empty());
+        assert_eq!(underlyings_in_window(&conn, maturity - 1800, 1800).unwrap(), vec![Asset::Btc]);
+        assert!(underlyings_in_window(&conn, maturity, 1800).unwrap().is_empty());
+
+        price_history::record(&conn, Asset::Btc, 99_000.0, maturity - 1900).unwrap();  // Before the window
+        price_history::record(&conn, Asset::Btc, 100_000.0, maturity - 1800).unwrap();
+        price_history::record(&conn, Asset::Btc, 101_000.0, maturity - 900).unwrap();
+        assert_eq!(sampled_price(&conn, &config, Asset::Btc, maturity).unwrap(), None);
+
+        price_history::record(&conn, Asset::Btc, 150_000.0, maturity - 60).unwrap();
+        let settlement = sampled_price(&conn, &config, Asset::Btc, maturity).unwrap().unwrap();
+        assert_eq!(settlement.method, SettlementMethod::Twap);
+        assert_eq!(settlement.sample_count, 3);
+        assert_eq!(settlement.window_start, Some(maturity - 1800));
+        assert!((settlement.price - (100_000.0 * 900.0 + 101_000.0 * 840.0 + 150_000.0 * 60.0) / 1800.0).abs() < 0.01);
+
+        let median_config = SettlementConfig { method: SettlementMethod::Median, ..config };
+        assert_eq!(sampled_price(&conn, &median_config, Asset::Btc, maturity).unwrap().unwrap().price, 101_000.0);
+        assert_eq!(sampled_price(&conn, &median_config, Asset::Eth, maturity).unwrap(), None);
+    }
+
+    #[test]
+    fn test_settlement_report() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init_db(&conn).unwrap();
+        let maturity = 1_800_000_000;
+        let now = maturity + 600;
+        let contract = Contract {
+            underlying: Asset::Btc,
+            side: OptionSide::Call,
+            strike_price: 100_000.0,
+            quantity: 1.0,
+            expires: maturity,
+            premium: 0.01,
+        };
+        // The first buyer paid a fee, the second received a maker rebate
+        for (quantity, fee_sats) in [(1.0, 10_000), (0.5, -2_500)] {
+            let id = insert_contract(&conn, &Contract { quantity, ..contract.clone() }, None).unwrap();
+            conversions::record(&conn, id, ConversionKind::Premium, QuoteCurrency::Btc, 0.01 * quantity, 100_000.0, maturity - 86_400).unwrap();
+            let fee = TradeFee { tier: None, fee_percent: 0.0, fee_sats, fee_usd: fee_sats as f64 / 1000.0 };
+            fees::record(&conn, id, "desk-a", &fee, maturity - 86_400).unwrap();
+        }
+        // Half of the first contract is bought back for $500 per unit before expiry
+        close_contract(&conn, 1, 50_000_000, 500.0, 100_000.0, maturity - 3600, "ops").unwrap();
+        expire_contracts(&conn, maturity, "test").unwrap();
+        settle_expired_maturity(&conn, Asset::Btc, maturity, 102_000.0, 100_000.0, now, "test").unwrap();
+
+        let report = settlement_report(&conn, maturity, now + 1, None).unwrap();
+        assert_eq!(report.settlements.len(), 1);
+        let settlement = &report.settlements[0];
+        assert_eq!(settlement.product_symbol, product_symbol(Asset::Btc, &OptionSide::Call, 100_000.0, maturity));
+        assert_eq!((settlement.status, settlement.settled_at, settlement.contracts), (ContractStatus::Settled, now, 2));
+        assert_eq!(settlement.settlement_method, None);
+        assert_eq!(settlement.quantity, "1.00000000");
+        // 0.015 BTC of premium, 0.0025 paid back on the close and $2000 of payoff on the open unit
+        assert_eq!(settlement.premium_btc, "0.01500000");
+        assert_eq!(settlement.close_btc, "0.00250000");
+        assert_eq!(settlement.payoff_btc, "0.02000000");
+        assert_eq!(settlement.premium_retained_btc, "0.01250000");
+        assert_eq!((settlement.fee_btc.as_str(), settlement.fee_usd), ("0.00007500", 7.5));
+        assert_eq!(settlement.net_pnl_btc, "-0.00742500");
+        assert_eq!((settlement.premium_usd, settlement.close_usd, settlement.payoff_usd), (1500.0, 250.0, 2000.0));
+        assert_eq!(settlement.net_pnl_usd, -742.5);
+        assert_eq!(report.totals.net_pnl_btc, "-0.00742500");
+        assert_eq!(report.totals.fee_btc, "0.00007500");
+        assert_eq!(report.totals.contracts, 2);
+
+        // Outside the range, or on another underlying, there is nothing to report
+        assert!(settlement_report(&conn, now + 1, now + 2, None).unwrap().settlements.is_empty());
+        let eth = settlement_report(&conn, maturity, now + 1, Some(Asset::Eth)).unwrap();
+        assert_eq!((eth.settlements.len(), eth.totals.net_pnl_btc.as_str()), (0, "0.00000000"));
+    }
+}
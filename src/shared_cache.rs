@@ -0,0 +1,412 @@
+// Cache shared between API instances.
+// Each instance otherwise keeps its own spot prices and IV surface, so instances behind a load
+// balancer quote off different prices and each polls Deribit on its own. With SHARED_CACHE_URL
+// set they read through a Redis cache instead: a spot price or quote observed by one instance
+// is served to all of them for SHARED_PRICE_TTL_MS, and one instance at a time refreshes each
+// IV surface from Deribit while the others load the surface it stored, keeping the whole
+// deployment within one instance's Deribit request budget. Redis is behind the `redis` cargo
+// feature; without SHARED_CACHE_URL the caches stay per process. The options table is computed
+// from these shared inputs, so its short per-process cache needs no sharing.
+
+use crate::models::Asset;
+use crate::sources::{PriceQuote, PriceSource, PriceUpdate, SourceError, SourcePrice};
+use async_trait::async_trait;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+/// String values with a time to live, shared by every instance using the same store
+#[async_trait]
+pub trait SharedCache: Send + Sync {
+    async fn get(&self, key: &str) -> Result<Option<String>, SourceError>;
+
+    async fn set(&self, key: &str, value: &str, ttl: Duration) -> Result<(), SourceError>;
+
+    /// Set `key` unless it is already set, returning whether it was. Taken as a lock that
+    /// expires after `ttl`.
+    async fn set_if_absent(&self, key: &str, value: &str, ttl: Duration) -> Result<bool, SourceError>;
+}
+
+/// In-process `SharedCache`, shared only by the clones of one source
+#[derive(Default)]
+pub struct LocalCache {
+    entries: Mutex<HashMap<String, (String, Instant)>>,  // Value and when it expires
+}
+
+impl LocalCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl SharedCache for LocalCache {
+    async fn get(&self, key: &str) -> Result<Option<String>, SourceError> {
+        let entries = self.entries.lock().unwrap();
+        Ok(entries.get(key).filter(|(_, expires)| *expires > Instant::now()).map(|(value, _)| value.clone()))
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl: Duration) -> Result<(), SourceError> {
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        entries.retain(|_, (_, expires)| *expires > now);
+        entries.insert(key.to_string(), (value.to_string(), now + ttl));
+        Ok(())
+    }
+
+    async fn set_if_absent(&self, key: &str, value: &str, ttl: Duration) -> Result<bool, SourceError> {
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        if entries.get(key).is_some_and(|(_, expires)| *expires > now) {
+            return Ok(false);
+        }
+        entries.insert(key.to_string(), (value.to_string(), now + ttl));
+        Ok(true)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SharedCacheConfig {
+    pub url: String,         // redis:// or rediss:// URL
+    pub key_prefix: String,  // Keys are <prefix>:<key>, so deployments can share a Redis
+    pub price_ttl: Duration, // How long a price observed by one instance is served to all
+    pub timeout: Duration,   // For connecting and for each command
+}
+
+impl SharedCacheConfig {
+    /// SHARED_CACHE_URL (off when unset), SHARED_CACHE_PREFIX (options), SHARED_PRICE_TTL_MS
+    /// (1000) and SHARED_CACHE_TIMEOUT_MS (500)
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let url = match env::var("SHARED_CACHE_URL") {
+            Ok(url) if !url.trim().is_empty() => url.trim().to_string(),
+            _ => return Ok(None),
+        };
+        if !url.starts_with("redis://") && !url.starts_with("rediss://") {
+            return Err(format!("invalid SHARED_CACHE_URL '{}', expected a redis:// or rediss:// URL", url));
+        }
+        let millis = |name: &str, default: u64| {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|ms: &u64| *ms > 0)
+                .map(Duration::from_millis)
+                .unwrap_or(Duration::from_millis(default))
+        };
+        Ok(Some(Self {
+            url,
+            key_prefix: env::var("SHARED_CACHE_PREFIX").unwrap_or_else(|_| "options".to_string()),
+            price_ttl: millis("SHARED_PRICE_TTL_MS", 1000),
+            timeout: millis("SHARED_CACHE_TIMEOUT_MS", 500),
+        }))
+    }
+}
+
+/// Connect to the cache `config` names. Fails when this build lacks the `redis` feature.
+pub async fn connect(config: &SharedCacheConfig) -> Result<Arc<dyn SharedCache>, String> {
+    #[cfg(feature = "redis")]
+    {
+        Ok(Arc::new(redis_cache::RedisCache::connect(config).await?))
+    }
+    #[cfg(not(feature = "redis"))]
+    {
+        Err(format!(
+            "SHARED_CACHE_URL={} needs a server built with the 'redis' feature (cargo build --features redis)",
+            config.url
+        ))
+    }
+}
+
+#[cfg(feature = "redis")]
+mod redis_cache {
+    use super::*;
+    use redis::aio::ConnectionManager;
+    use redis::AsyncCommands;
+
+    pub struct RedisCache {
+        connection: ConnectionManager,
+        key_prefix: String,
+        timeout: Duration,
+    }
+
+    impl RedisCache {
+        pub async fn connect(config: &SharedCacheConfig) -> Result<Self, String> {
+            let client = redis::Client::open(config.url.as_str())
+                .map_err(|e| format!("invalid SHARED_CACHE_URL {}: {}", config.url, e))?;
+            let connection = tokio::time::timeout(config.timeout, ConnectionManager::new(client))
+                .await
+                .map_err(|_| format!("timed out connecting to Redis at {}", config.url))?
+                .map_err(|e| format!("failed to connect to Redis at {}: {}", config.url, e))?;
+            Ok(Self { connection, key_prefix: config.key_prefix.clone(), timeout: config.timeout })
+        }
+
+        fn key(&self, key: &str) -> String {
+            format!("{}:{}", self.key_prefix, key)
+        }
+
+        async fn command<T>(&self, command: impl std::future::Future<Output = redis::RedisResult<T>>) -> Result<T, SourceError> {
+            match tokio::time::timeout(self.timeout, command).await {
+                Ok(result) => result.map_err(|e| format!("Redis: {}", e).into()),
+                Err(_) => Err("Redis command timed out".into()),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl SharedCache for RedisCache {
+        async fn get(&self, key: &str) -> Result<Option<String>, SourceError> {
+            let mut connection = self.connection.clone();
+            self.command(connection.get(self.key(key))).await
+        }
+
+        async fn set(&self, key: &str, value: &str, ttl: Duration) -> Result<(), SourceError> {
+            let mut connection = self.connection.clone();
+            let millis = ttl.as_millis().max(1) as u64;
+            self.command(redis::cmd("SET").arg(self.key(key)).arg(value).arg("PX").arg(millis).query_async(&mut connection))
+                .await
+        }
+
+        async fn set_if_absent(&self, key: &str, value: &str, ttl: Duration) -> Result<bool, SourceError> {
+            let mut connection = self.connection.clone();
+            let millis = ttl.as_millis().max(1) as u64;
+            let set: Option<String> = self
+                .command(
+                    redis::cmd("SET")
+                        .arg(self.key(key))
+                        .arg(value)
+                        .arg("NX")
+                        .arg("PX")
+                        .arg(millis)
+                        .query_async(&mut connection),
+                )
+                .await?;
+            Ok(set.is_some())
+        }
+    }
+}
+
+// A price quote as stored in the cache, aged by when it was observed
+#[derive(Serialize, Deserialize)]
+struct CachedQuote {
+    price: f64,
+    observed_at_ms: i64,
+    data_points: u32,
+    rejected_points: u32,
+    previous_price: Option<f64>,
+    stale: bool,
+}
+
+impl CachedQuote {
+    fn new(quote: &PriceQuote, now_ms: i64) -> Self {
+        Self {
+            price: quote.price,
+            observed_at_ms: now_ms - quote.age.as_millis() as i64,
+            data_points: quote.data_points,
+            rejected_points: quote.rejected_points,
+            previous_price: quote.previous_price,
+            stale: quote.stale,
+        }
+    }
+
+    fn quote(&self, now_ms: i64) -> PriceQuote {
+        PriceQuote {
+            price: self.price,
+            age: Duration::from_millis((now_ms - self.observed_at_ms).max(0) as u64),
+            data_points: self.data_points,
+            rejected_points: self.rejected_points,
+            previous_price: self.previous_price,
+            stale: self.stale,
+        }
+    }
+}
+
+/// Spot prices and quotes read through a `SharedCache`: a value another instance observed
+/// less than `ttl` ago is served as is, otherwise `inner` is asked and its answer stored. Cache
+/// errors fall back to `inner`.
+pub struct SharedPriceSource {
+    inner: Arc<dyn PriceSource>,
+    cache: Arc<dyn SharedCache>,
+    ttl: Duration,
+    version: AtomicU64,               // Bumped whenever a served price differs from the last one
+    last_prices: Mutex<HashMap<Asset, f64>>,
+    degraded: AtomicBool,             // The last cache command failed
+}
+
+impl SharedPriceSource {
+    pub fn new(inner: Arc<dyn PriceSource>, cache: Arc<dyn SharedCache>, ttl: Duration) -> Self {
+        Self {
+            inner,
+            cache,
+            ttl,
+            version: AtomicU64::new(0),
+            last_prices: Mutex::new(HashMap::new()),
+            degraded: AtomicBool::new(false),
+        }
+    }
+
+    // Value of `key` cached by any instance; None when missing or the cache is unreachable
+    async fn cached(&self, key: &str) -> Option<String> {
+        let result = self.cache.get(key).await;
+        self.record(result.as_ref().err());
+        result.ok().flatten()
+    }
+
+    async fn store(&self, key: &str, value: &str) {
+        let result = self.cache.set(key, value, self.ttl).await;
+        self.record(result.as_ref().err());
+    }
+
+    // Logs when the cache goes down and when it is back, not on every failed command
+    fn record(&self, error: Option<&SourceError>) {
+        match error {
+            Some(e) if !self.degraded.swap(true, Ordering::SeqCst) => {
+                eprintln!("⚠️  Shared cache unavailable, reading prices directly: {}", e);
+            }
+            None if self.degraded.swap(false, Ordering::SeqCst) => println!("✅ Shared cache available again"),
+            _ => {}
+        }
+    }
+
+    fn observe(&self, asset: Asset, price: f64) {
+        if self.last_prices.lock().unwrap().insert(asset, price) != Some(price) {
+            self.version.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    async fn quote(&self, asset: Asset) -> Result<PriceQuote, SourceError> {
+        let key = format!("price_quote:{}", asset);
+        let now_ms = Utc::now().timestamp_millis();
+        let cached = self.cached(&key).await.and_then(|value| serde_json::from_str::<CachedQuote>(&value).ok());
+        let quote = match cached {
+            Some(cached) => cached.quote(now_ms),
+            None => {
+                let quote = self.inner.get_asset_price_quote(asset).await?;
+                if let Ok(value) = serde_json::to_string(&CachedQuote::new(&quote, now_ms)) {
+                    self.store(&key, &value).await;
+                }
+                quote
+            }
+        };
+        self.observe(asset, quote.price);
+        Ok(quote)
+    }
+}
+
+#[async_trait]
+impl PriceSource for SharedPriceSource {
+    async fn get_btc_price(&self) -> Result<f64, SourceError> {
+        self.get_price(Asset::Btc).await
+    }
+
+    async fn get_price(&self, asset: Asset) -> Result<f64, SourceError> {
+        let key = format!("price:{}", asset);
+        let price = match self.cached(&key).await.and_then(|value| value.parse::<f64>().ok()) {
+            Some(price) => price,
+            None => {
+                let price = self.inner.get_price(asset).await?;
+                self.store(&key, &price.to_string()).await;
+                price
+            }
+        };
+        self.observe(asset, price);
+        Ok(price)
+    }
+
+    fn version(&self) -> u64 {
+        self.version.load(Ordering::SeqCst)
+    }
+
+    fn is_stale(&self) -> bool {
+        self.inner.is_stale()
+    }
+
+    fn subscribe(&self) -> Option<broadcast::Receiver<PriceUpdate>> {
+        self.inner.subscribe()
+    }
+
+    async fn get_price_quote(&self) -> Result<PriceQuote, SourceError> {
+        self.quote(Asset::Btc).await
+    }
+
+    async fn source_prices(&self, asset: Asset, sources: &[String]) -> Result<Vec<SourcePrice>, SourceError> {
+        self.inner.source_prices(asset, sources).await
+    }
+
+    async fn get_price_from(&self, asset: Asset, sources: &[String]) -> Result<f64, SourceError> {
+        if sources.is_empty() {
+            return self.get_price(asset).await;
+        }
+        self.inner.get_price_from(asset, sources).await
+    }
+
+    async fn get_asset_price_quote(&self, asset: Asset) -> Result<PriceQuote, SourceError> {
+        self.quote(asset).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::FixedPriceSource;
+
+    // Fails every command, like an unreachable Redis
+    struct DownCache;
+
+    #[async_trait]
+    impl SharedCache for DownCache {
+        async fn get(&self, _key: &str) -> Result<Option<String>, SourceError> {
+            Err("connection refused".into())
+        }
+
+        async fn set(&self, _key: &str, _value: &str, _ttl: Duration) -> Result<(), SourceError> {
+            Err("connection refused".into())
+        }
+
+        async fn set_if_absent(&self, _key: &str, _value: &str, _ttl: Duration) -> Result<bool, SourceError> {
+            Err("connection refused".into())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_local_cache_expiry_and_locks() {
+        let cache = LocalCache::new();
+        cache.set("a", "1", Duration::from_secs(60)).await.unwrap();
+        cache.set("b", "2", Duration::ZERO).await.unwrap();
+        assert_eq!(cache.get("a").await.unwrap().as_deref(), Some("1"));
+        assert_eq!(cache.get("b").await.unwrap(), None);
+
+        assert!(cache.set_if_absent("lock", "x", Duration::from_secs(60)).await.unwrap());
+        assert!(!cache.set_if_absent("lock", "y", Duration::from_secs(60)).await.unwrap());
+        assert_eq!(cache.get("lock").await.unwrap().as_deref(), Some("x"));
+        assert!(cache.set_if_absent("b", "3", Duration::from_secs(60)).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_instances_share_prices_within_ttl() {
+        let cache: Arc<dyn SharedCache> = Arc::new(LocalCache::new());
+        let first = SharedPriceSource::new(Arc::new(FixedPriceSource::new(50_000.0)), cache.clone(), Duration::from_secs(60));
+        let second = SharedPriceSource::new(Arc::new(FixedPriceSource::new(51_000.0)), cache.clone(), Duration::from_secs(60));
+
+        // The second instance serves what the first observed, quotes included
+        assert_eq!(first.get_btc_price().await.unwrap(), 50_000.0);
+        assert_eq!(second.get_btc_price().await.unwrap(), 50_000.0);
+        assert_eq!(first.get_price_quote().await.unwrap().price, 50_000.0);
+        assert_eq!(second.get_asset_price_quote(Asset::Btc).await.unwrap().price, 50_000.0);
+        assert_eq!(second.version(), 1);
+
+        // Once it expires, whoever asks next refreshes it for everyone
+        cache.set("price:BTC", "", Duration::ZERO).await.unwrap();
+        assert_eq!(second.get_price(Asset::Btc).await.unwrap(), 51_000.0);
+        assert_eq!(first.get_price(Asset::Btc).await.unwrap(), 51_000.0);
+        assert_eq!(first.version(), 2);
+
+        // An unreachable cache falls back to the source itself
+        let direct = SharedPriceSource::new(Arc::new(FixedPriceSource::new(49_000.0)), Arc::new(DownCache), Duration::from_secs(60));
+        assert_eq!(direct.get_btc_price().await.unwrap(), 49_000.0);
+        assert_eq!(direct.get_price_quote().await.unwrap().price, 49_000.0);
+    }
+}
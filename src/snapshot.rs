@@ -0,0 +1,532 @@
+// Snapshots of the full application state, for rebuilding an instance after a disaster.
+// A database backup restores the tables but not the settings the instance ran with. A snapshot
+// is one versioned JSON archive holding every table (contracts, pools, settlements and their
+// attestations, IV snapshots, conversions, the audit log and the derived stats), the
+// settings documented in .env.example and the static IV surface file, with a SHA-256 checksum
+// per table and one over the whole archive. `optadmin snapshot` writes one and
+// `optadmin snapshot restore` rebuilds the database from it at the schema it was taken with,
+// applies the later migrations, then swaps it in like a backup restore.
+// Secrets (JWT_SECRET, Lightning credentials, the signing key, credentials in URLs) are left
+// out and have to be supplied again. API key and password hashes are kept, so the archive
+// should be stored like a backup.
+
+use crate::backup;
+use crate::error::{ApiError, ApiResult};
+use crate::migrations::{self, MIGRATIONS};
+use chrono::{TimeZone, Utc};
+use rusqlite::types::Value as SqlValue;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Layout version of the archive, raised whenever the layout changes
+pub const SNAPSHOT_FORMAT: u32 = 1;
+
+const FILE_PREFIX: &str = "snapshot-";
+const FILE_SUFFIX: &str = ".json";
+
+// Settings captured with a snapshot are the ones documented in .env.example
+const ENV_EXAMPLE: &str = include_str!("../.env.example");
+
+// Settings holding credentials, which a snapshot leaves out
+const SECRET_MARKERS: &[&str] = &["SECRET", "PASSWORD", "MACAROON", "RUNE", "SIGNING_KEY"];
+
+// Settings naming files a snapshot carries along
+const FILE_SETTINGS: &[&str] = &["IV_FILE"];
+
+// Tables the migrations maintain themselves
+const SKIPPED_TABLES: &[&str] = &["schema_version", "sqlite_sequence"];
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub format: u32,
+    pub schema_version: i64,                      // Migration the database was at
+    pub created_at: i64,
+    pub config: BTreeMap<String, Option<String>>, // Settings that were set; None for a left-out secret
+    pub files: BTreeMap<String, String>,          // Contents of the files settings point at, by path
+    pub tables: Vec<TableSnapshot>,
+    pub checksum: String,                         // SHA-256 over everything above
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TableSnapshot {
+    pub name: String,
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,  // Blobs as {"blob": "<hex>"}
+    pub checksum: String,       // SHA-256 over the name, columns and rows
+}
+
+fn io_error(context: &str, path: &Path, err: std::io::Error) -> ApiError {
+    ApiError::DatabaseError(format!("{} {}: {}", context, path.display(), err))
+}
+
+fn sha256_hex(value: &impl Serialize) -> String {
+    let bytes = serde_json::to_vec(value).unwrap_or_default();
+    Sha256::digest(&bytes).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl TableSnapshot {
+    fn compute_checksum(&self) -> String {
+        sha256_hex(&(&self.name, &self.columns, &self.rows))
+    }
+}
+
+impl Snapshot {
+    fn compute_checksum(&self) -> String {
+        let tables: Vec<(&str, &str)> = self.tables.iter().map(|t| (t.name.as_str(), t.checksum.as_str())).collect();
+        sha256_hex(&(self.format, self.schema_version, self.created_at, &self.config, &self.files, tables))
+    }
+
+    /// Rows across all tables
+    pub fn row_count(&self) -> usize {
+        self.tables.iter().map(|t| t.rows.len()).sum()
+    }
+
+    /// Check the format, the schema version and every checksum
+    pub fn verify(&self) -> ApiResult<()> {
+        if self.format != SNAPSHOT_FORMAT {
+            return Err(ApiError::ValidationError(format!(
+                "Snapshot format {} is not supported (expected {})",
+                self.format, SNAPSHOT_FORMAT
+            )));
+        }
+        let latest = MIGRATIONS.last().map_or(0, |m| m.version);
+        if self.schema_version < 1 || self.schema_version > latest {
+            return Err(ApiError::ValidationError(format!(
+                "Snapshot has schema version {}, this build knows 1 to {}",
+                self.schema_version, latest
+            )));
+        }
+        for table in &self.tables {
+            if table.checksum != table.compute_checksum() {
+                return Err(ApiError::ValidationError(format!("Snapshot table {} fails its checksum", table.name)));
+            }
+        }
+        if self.checksum != self.compute_checksum() {
+            return Err(ApiError::ValidationError("Snapshot fails its checksum".to_string()));
+        }
+        Ok(())
+    }
+
+    /// The captured settings as a .env file; left-out secrets are listed commented out
+    pub fn env_file(&self) -> String {
+        let at = Utc.timestamp_opt(self.created_at, 0).single().unwrap_or_default();
+        let mut out = format!("# Settings from the snapshot taken {}\n", at.to_rfc3339());
+        for (name, value) in &self.config {
+            match value {
+                Some(value) => out.push_str(&format!("{}={}\n", name, env_value(value))),
+                None => out.push_str(&format!("# {}=    # Secret, not kept in the snapshot\n", name)),
+            }
+        }
+        out
+    }
+
+    /// Settings left out of the snapshot as secrets
+    pub fn missing_secrets(&self) -> Vec<&str> {
+        self.config
+            .iter()
+            .filter(|(_, value)| value.is_none())
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+}
+
+fn env_value(value: &str) -> String {
+    if value.chars().any(|c| c.is_whitespace() || c == '#' || c == '"' || c == '\'') {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Names of the settings documented in .env.example, commented-out ones included
+pub fn documented_settings() -> Vec<&'static str> {
+    let mut names: Vec<&str> = ENV_EXAMPLE
+        .lines()
+        .filter_map(|line| line.trim_start_matches('#').trim_start().split_once('='))
+        .map(|(name, _)| name)
+        .filter(|name| !name.is_empty() && name.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_'))
+        .collect();
+    names.sort_unstable();
+    names.dedup();
+    names
+}
+
+fn is_secret(name: &str, value: &str) -> bool {
+    // Credentials in URLs, e.g. redis://:password@host
+    let url_credentials = value
+        .split_once("://")
+        .is_some_and(|(_, rest)| rest.split('/').next().is_some_and(|authority| authority.contains('@')));
+    url_credentials || SECRET_MARKERS.iter().any(|marker| name.contains(marker))
+}
+
+/// The documented settings set in the environment, with secrets left out
+pub fn capture_config() -> BTreeMap<String, Option<String>> {
+    documented_settings()
+        .into_iter()
+        .filter_map(|name| env::var(name).ok().map(|value| (name, value)))
+        .map(|(name, value)| {
+            let kept = (!is_secret(name, &value)).then_some(value);
+            (name.to_string(), kept)
+        })
+        .collect()
+}
+
+/// Contents of the files the captured settings point at
+pub fn capture_files(config: &BTreeMap<String, Option<String>>) -> ApiResult<BTreeMap<String, String>> {
+    let mut files = BTreeMap::new();
+    for setting in FILE_SETTINGS {
+        if let Some(Some(path)) = config.get(*setting) {
+            let contents = fs::read_to_string(path).map_err(|e| io_error("Cannot read", Path::new(path), e))?;
+            files.insert(path.clone(), contents);
+        }
+    }
+    Ok(files)
+}
+
+fn to_json(value: SqlValue) -> Value {
+    match value {
+        SqlValue::Null => Value::Null,
+        SqlValue::Integer(i) => json!(i),
+        SqlValue::Real(f) => json!(f),
+        SqlValue::Text(s) => Value::String(s),
+        SqlValue::Blob(bytes) => json!({"blob": bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>()}),
+    }
+}
+
+fn from_json(value: &Value) -> ApiResult<SqlValue> {
+    let invalid = || ApiError::ValidationError(format!("Snapshot holds a value no column can take: {}", value));
+    Ok(match value {
+        Value::Null => SqlValue::Null,
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => SqlValue::Integer(i),
+            None => SqlValue::Real(n.as_f64().ok_or_else(invalid)?),
+        },
+        Value::String(s) => SqlValue::Text(s.clone()),
+        Value::Object(object) => {
+            let hex = object.get("blob").and_then(Value::as_str).ok_or_else(invalid)?;
+            if hex.len() % 2 != 0 {
+                return Err(invalid());
+            }
+            let bytes = (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+                .collect::<Result<Vec<u8>, _>>()
+                .map_err(|_| invalid())?;
+            SqlValue::Blob(bytes)
+        }
+        Value::Bool(_) | Value::Array(_) => return Err(invalid()),
+    })
+}
+
+fn table_names(conn: &Connection) -> rusqlite::Result<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name")?;
+    let names = stmt.query_map([], |row| row.get::<_, String>(0))?.collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(names.into_iter().filter(|name| !SKIPPED_TABLES.contains(&name.as_str())).collect())
+}
+
+fn dump_table(conn: &Connection, name: &str) -> rusqlite::Result<TableSnapshot> {
+    let mut stmt = conn.prepare(&format!("SELECT * FROM \"{}\" ORDER BY rowid", name))?;
+    let columns: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+    let rows = stmt
+        .query_map([], |row| {
+            (0..columns.len()).map(|i| row.get::<_, SqlValue>(i).map(to_json)).collect()
+        })?
+        .collect::<rusqlite::Result<Vec<Vec<Value>>>>()?;
+    let mut table = TableSnapshot { name: name.to_string(), columns, rows, checksum: String::new() };
+    table.checksum = table.compute_checksum();
+    Ok(table)
+}
+
+/// Snapshot of the database of `conn` together with `config` and `files`, read in one
+/// transaction so the tables are consistent with each other
+pub fn create_snapshot(
+    conn: &Connection,
+    config: BTreeMap<String, Option<String>>,
+    files: BTreeMap<String, String>,
+    now: i64,
+) -> ApiResult<Snapshot> {
+    let tx = conn.unchecked_transaction()?;
+    let schema_version = migrations::current_version(&tx)?;
+    let tables = table_names(&tx)?
+        .iter()
+        .map(|name| dump_table(&tx, name))
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    drop(tx);
+    let mut snapshot = Snapshot {
+        format: SNAPSHOT_FORMAT,
+        schema_version,
+        created_at: now,
+        config,
+        files,
+        tables,
+        checksum: String::new(),
+    };
+    snapshot.checksum = snapshot.compute_checksum();
+    Ok(snapshot)
+}
+
+/// Snapshot file name for a snapshot taken at `now`; names sort in time order
+pub fn snapshot_file_name(now: i64) -> String {
+    let at = Utc.timestamp_opt(now, 0).single().unwrap_or_default();
+    format!("{}{}{}", FILE_PREFIX, at.format("%Y%m%dT%H%M%SZ"), FILE_SUFFIX)
+}
+
+/// Write `snapshot` to `path`
+pub fn write_snapshot(snapshot: &Snapshot, path: &Path) -> ApiResult<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).map_err(|e| io_error("Cannot create snapshot directory", dir, e))?;
+    }
+    let json = serde_json::to_vec(snapshot)
+        .map_err(|e| ApiError::DatabaseError(format!("Cannot serialize snapshot: {}", e)))?;
+    // Written under a temporary name so an interrupted write is never taken for a snapshot
+    let partial = path.with_extension("json.partial");
+    fs::write(&partial, json).map_err(|e| io_error("Cannot write snapshot", &partial, e))?;
+    fs::rename(&partial, path).map_err(|e| io_error("Cannot finish snapshot", path, e))
+}
+
+/// Read and verify the snapshot at `path`
+pub fn read_snapshot(path: &Path) -> ApiResult<Snapshot> {
+    if !path.is_file() {
+        return Err(ApiError::NotFound(format!("No snapshot at {}", path.display())));
+    }
+    let bytes = fs::read(path).map_err(|e| io_error("Cannot read snapshot", path, e))?;
+    let snapshot: Snapshot = serde_json::from_slice(&bytes)
+        .map_err(|e| ApiError::ValidationError(format!("{} is not a snapshot: {}", path.display(), e)))?;
+    snapshot.verify()?;
+    Ok(snapshot)
+}
+
+/// Build a new database at `path` holding the tables of `snapshot`: the schema is migrated to
+/// the snapshot's version, the rows loaded, then the later migrations applied. Fails if
+/// `path` exists.
+pub fn rebuild_database(snapshot: &Snapshot, path: &Path) -> ApiResult<()> {
+    snapshot.verify()?;
+    if path.exists() {
+        return Err(ApiError::ValidationError(format!("{} already exists", path.display())));
+    }
+    let mut conn = Connection::open(path)?;
+    // Rows load in table order, so references are only checked once all are in
+    conn.pragma_update(None, "foreign_keys", "OFF")?;
+    migrations::run_migrations_to(&conn, snapshot.schema_version)?;
+
+    let tx = conn.transaction()?;
+    let known = table_names(&tx)?;
+    for table in &snapshot.tables {
+        if !known.contains(&table.name) {
+            return Err(ApiError::ValidationError(format!(
+                "Snapshot table {} does not exist at schema version {}",
+                table.name, snapshot.schema_version
+            )));
+        }
+        // Rows some migrations seed give way to the snapshot's
+        tx.execute(&format!("DELETE FROM \"{}\"", table.name), [])?;
+        let columns: Vec<String> = table.columns.iter().map(|c| format!("\"{}\"", c)).collect();
+        let placeholders: Vec<String> = (1..=columns.len()).map(|i| format!("?{}", i)).collect();
+        let mut insert = tx.prepare(&format!(
+            "INSERT INTO \"{}\" ({}) VALUES ({})",
+            table.name,
+            columns.join(", "),
+            placeholders.join(", ")
+        ))?;
+        for row in &table.rows {
+            let values = row.iter().map(from_json).collect::<ApiResult<Vec<_>>>()?;
+            insert.execute(rusqlite::params_from_iter(values))?;
+        }
+    }
+    tx.commit()?;
+
+    migrations::run_migrations(&conn)?;
+    let dangling: Option<String> = conn
+        .prepare("PRAGMA foreign_key_check")?
+        .query_map([], |row| row.get::<_, String>(0))?
+        .next()
+        .transpose()?;
+    if let Some(table) = dangling {
+        return Err(ApiError::ValidationError(format!("Snapshot table {} references missing rows", table)));
+    }
+    Ok(())
+}
+
+/// Replace the database of `conn` with the one rebuilt from `snapshot`, using `work_dir`
+/// for the rebuilt copy
+pub fn restore_snapshot(conn: &mut Connection, snapshot: &Snapshot, work_dir: &Path) -> ApiResult<()> {
+    fs::create_dir_all(work_dir).map_err(|e| io_error("Cannot create directory", work_dir, e))?;
+    let rebuilt = work_dir.join(format!("{}restore-{}.db", FILE_PREFIX, std::process::id()));
+    let _ = fs::remove_file(&rebuilt);
+    let result = rebuild_database(snapshot, &rebuilt).and_then(|()| backup::restore_backup(conn, &rebuilt));
+    let _ = fs::remove_file(&rebuilt);
+    result.map(|_| ())
+}
+
+/// Write the files of `snapshot` back where they were, leaving existing files alone. Returns
+/// the paths written.
+pub fn restore_files(snapshot: &Snapshot) -> ApiResult<Vec<PathBuf>> {
+    let mut written = Vec::new();
+    for (path, contents) in &snapshot.files {
+        let path = PathBuf::from(path);
+        if path.exists() {
+            continue;
+        }
+        fs::write(&path, contents).map_err(|e| io_error("Cannot write", &path, e))?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Contract, OptionSide};
+    use crate::repository::{insert_contract, load_contract_records};
+    use crate::trading_state::{self, TradingState};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("btc-options-snapshot-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn database_with_state() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init_db(&conn).unwrap();
+        let contract = Contract {
+            underlying: Default::default(),
+            side: OptionSide::Call,
+            strike_price: 100_000.0,
+            quantity: 0.25,
+            expires: 1_900_000_000,
+            premium: 0.02,
+        };
+        insert_contract(&conn, &contract, None).unwrap();
+        trading_state::set(&conn, TradingState::ReduceOnly, Some("drill"), "ops", false).unwrap();
+        conn
+    }
+
+    fn config() -> BTreeMap<String, Option<String>> {
+        BTreeMap::from([
+            ("RISK_MARGIN".to_string(), Some("1.5".to_string())),
+            ("JWT_SECRET".to_string(), None),
+        ])
+    }
+
+    #[test]
+    fn test_secrets_are_left_out() {
+        assert!(documented_settings().contains(&"RISK_MARGIN"));
+        assert!(documented_settings().contains(&"IV_FILE"));
+        assert!(is_secret("JWT_SECRET", "abc"));
+        assert!(is_secret("LIGHTNING_MACAROON", "0201"));
+        assert!(is_secret("SHARED_CACHE_URL", "redis://:hunter2@cache:6379/0"));
+        assert!(!is_secret("SHARED_CACHE_URL", "redis://cache:6379/0"));
+        assert!(!is_secret("DLC_ORACLE_PUBLIC_KEY", "02ab"));
+        assert!(!is_secret("KYC_VERIFICATION_URL", "https://kyc.example/verify?ref=a@b"));
+    }
+
+    #[test]
+    fn test_cells_roundtrip_through_json() {
+        let values = [
+            SqlValue::Null,
+            SqlValue::Integer(-42),
+            SqlValue::Real(0.1),
+            SqlValue::Real(5.0),
+            SqlValue::Text("Put".to_string()),
+            SqlValue::Blob(vec![0x00, 0xff, 0x10]),
+        ];
+        for value in values {
+            let json = serde_json::to_string(&to_json(value.clone())).unwrap();
+            assert_eq!(from_json(&serde_json::from_str(&json).unwrap()).unwrap(), value);
+        }
+        assert!(from_json(&json!({"blob": "0g"})).is_err());
+        assert!(from_json(&json!(true)).is_err());
+    }
+
+    #[test]
+    fn test_snapshot_rebuilds_the_database() {
+        let dir = temp_dir("roundtrip");
+        let source = database_with_state();
+        let files = BTreeMap::from([("iv.json".to_string(), "[]".to_string())]);
+        let snapshot = create_snapshot(&source, config(), files, 1_760_000_000).unwrap();
+        assert_eq!(snapshot.schema_version, MIGRATIONS.last().unwrap().version);
+        assert!(snapshot.tables.iter().any(|t| t.name == "contracts" && t.rows.len() == 1));
+        assert!(!snapshot.tables.iter().any(|t| t.name == "schema_version"));
+
+        let path = dir.join(snapshot_file_name(1_760_000_000));
+        write_snapshot(&snapshot, &path).unwrap();
+        assert_eq!(path, dir.join("snapshot-20251009T085320Z.json"));
+        let read = read_snapshot(&path).unwrap();
+        assert_eq!(read, snapshot);
+        assert_eq!(read.missing_secrets(), vec!["JWT_SECRET"]);
+        assert!(read.env_file().ends_with("\n# JWT_SECRET=    # Secret, not kept in the snapshot\nRISK_MARGIN=1.5\n"));
+
+        // A fresh instance ends up with the same rows
+        let mut target = Connection::open_in_memory().unwrap();
+        crate::db::init_db(&target).unwrap();
+        restore_snapshot(&mut target, &read, &dir).unwrap();
+        let records = load_contract_records(&target, None).unwrap();
+        assert_eq!((records.len(), records[0].quantity), (1, 0.25));
+        assert_eq!(trading_state::load(&target).unwrap().state, TradingState::ReduceOnly);
+        let restored = create_snapshot(&target, config(), read.files.clone(), 1_760_000_000).unwrap();
+        assert_eq!(restored.tables, snapshot.tables);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_older_snapshots_are_migrated() {
+        let dir = temp_dir("older");
+        let source = Connection::open_in_memory().unwrap();
+        // Before migration 10 turned decimal strings into satoshis
+        migrations::run_migrations_to(&source, 9).unwrap();
+        source
+            .execute(
+                "INSERT INTO contracts (side, strike_price_cents, quantity_str, expires, premium_str)
+                 VALUES ('Put', 9000000, '0.50000000', 1900000000, '0.01000000')",
+                [],
+            )
+            .unwrap();
+        let snapshot = create_snapshot(&source, BTreeMap::new(), BTreeMap::new(), 1_760_000_000).unwrap();
+        assert_eq!(snapshot.schema_version, 9);
+
+        let path = dir.join("rebuilt.db");
+        fs::create_dir_all(&dir).unwrap();
+        rebuild_database(&snapshot, &path).unwrap();
+        let conn = Connection::open(&path).unwrap();
+        assert_eq!(migrations::current_version(&conn).unwrap(), MIGRATIONS.last().unwrap().version);
+        let records = load_contract_records(&conn, None).unwrap();
+        assert_eq!((records[0].strike_price, records[0].quantity), (90_000.0, 0.5));
+        assert!(rebuild_database(&snapshot, &path).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_tampered_snapshots_are_refused() {
+        let snapshot = create_snapshot(&database_with_state(), config(), BTreeMap::new(), 1_760_000_000).unwrap();
+        assert!(snapshot.verify().is_ok());
+
+        let mut tampered = snapshot.clone();
+        let contracts = tampered.tables.iter_mut().find(|t| t.name == "contracts").unwrap();
+        contracts.rows[0][1] = json!("Put");
+        assert!(tampered.verify().unwrap_err().to_string().contains("contracts"));
+
+        let mut tampered = snapshot.clone();
+        tampered.config.insert("RISK_MARGIN".to_string(), Some("0.1".to_string()));
+        assert!(tampered.verify().is_err());
+
+        let mut newer = snapshot.clone();
+        newer.schema_version += 1;
+        newer.checksum = newer.compute_checksum();
+        assert!(newer.verify().is_err());
+
+        let mut target = database_with_state();
+        assert!(matches!(
+            restore_snapshot(&mut target, &tampered, &temp_dir("tampered")),
+            Err(ApiError::ValidationError(_))
+        ));
+        let _ = fs::remove_dir_all(temp_dir("tampered"));
+    }
+}
@@ -0,0 +1,568 @@
+// Abstractions over the external data sources the API depends on.
+// Handlers only see these traits, so tests and alternate backends can be
+// swapped in without touching them.
+
+use crate::iv_oracle::IvOracle;
+use crate::models::{Asset, IvProvenance};
+use crate::mutiny_wallet::{MutinyWallet, MutinyWalletError, Transaction, WalletBalance};
+use crate::price_oracle::PriceOracle;
+use crate::svi::SurfaceFit;
+use crate::utils::duration_to_seconds;
+use async_trait::async_trait;
+use chrono::Utc;
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+pub type SourceError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Spot price in USD. BTC is always available; other underlyings only from
+/// sources that override `get_price`.
+#[async_trait]
+pub trait PriceSource: Send + Sync {
+    async fn get_btc_price(&self) -> Result<f64, SourceError>;
+
+    async fn get_price(&self, asset: Asset) -> Result<f64, SourceError> {
+        match asset {
+            Asset::Btc => self.get_btc_price().await,
+            _ => Err(format!("{} prices are not available from this source", asset).into()),
+        }
+    }
+
+    /// Counter that changes whenever a new price is observed (used for cache invalidation)
+    fn version(&self) -> u64;
+
+    /// True when the last price returned was a cached value served because no source was reachable
+    fn is_stale(&self) -> bool {
+        false
+    }
+
+    /// Live price updates, for sources that push them
+    fn subscribe(&self) -> Option<broadcast::Receiver<PriceUpdate>> {
+        None
+    }
+
+    /// Price with the metadata needed to judge whether it is safe to trade on.
+    /// Sources without that metadata report a fresh single-source observation.
+    async fn get_price_quote(&self) -> Result<PriceQuote, SourceError> {
+        let price = self.get_btc_price().await?;
+        Ok(PriceQuote {
+            price,
+            age: Duration::ZERO,
+            data_points: 1,
+            rejected_points: 0,
+            previous_price: None,
+            stale: self.is_stale(),
+        })
+    }
+
+    /// Latest price of `asset` from each upstream feed behind this source, only those named
+    /// in `sources` unless it is empty. Sources that don't break their price down report none.
+    async fn source_prices(&self, _asset: Asset, _sources: &[String]) -> Result<Vec<SourcePrice>, SourceError> {
+        Ok(Vec::new())
+    }
+
+    /// Price of `asset` from the feeds named in `sources` only: the median of their latest
+    /// prices. With no sources named, the usual `get_price`.
+    async fn get_price_from(&self, asset: Asset, sources: &[String]) -> Result<f64, SourceError> {
+        if sources.is_empty() {
+            return self.get_price(asset).await;
+        }
+        let prices = self.source_prices(asset, sources).await?.into_iter().filter_map(|source| source.price).collect();
+        median(prices).ok_or_else(|| format!("no {} price from {}", asset, sources.join(", ")).into())
+    }
+
+    /// `get_price_quote` for any underlying
+    async fn get_asset_price_quote(&self, asset: Asset) -> Result<PriceQuote, SourceError> {
+        if asset == Asset::Btc {
+            return self.get_price_quote().await;
+        }
+        let price = self.get_price(asset).await?;
+        Ok(PriceQuote {
+            price,
+            age: Duration::ZERO,
+            data_points: 1,
+            rejected_points: 0,
+            previous_price: None,
+            stale: false,
+        })
+    }
+}
+
+/// A spot price together with where it came from
+#[derive(Debug, Clone, PartialEq)]
+pub struct PriceQuote {
+    pub price: f64,
+    pub age: Duration,                // Time since the price was observed
+    pub data_points: u32,             // Oracle nodes or exchange feeds behind the price
+    pub rejected_points: u32,         // Oracle price points dropped as too old or outlying
+    pub previous_price: Option<f64>,  // The observation before this one
+    pub stale: bool,
+}
+
+/// Latest price of one upstream feed, e.g. an exchange as reported by the oracle nodes
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct SourcePrice {
+    pub source: String,           // Exchange name, e.g. "binance"
+    pub via: String,              // "aggregator" for prices reported by oracle nodes, "rest" for tickers polled directly
+    pub price: Option<f64>,       // None when the feed could not be read
+    pub timestamp: Option<i64>,   // Unix seconds of the latest observation
+    pub error: Option<String>,
+}
+
+/// Median of `prices`, None when empty
+pub fn median(mut prices: Vec<f64>) -> Option<f64> {
+    if prices.is_empty() {
+        return None;
+    }
+    prices.sort_by(|a, b| a.total_cmp(b));
+    let mid = prices.len() / 2;
+    if prices.len().is_multiple_of(2) {
+        Some((prices[mid - 1] + prices[mid]) / 2.0)
+    } else {
+        Some(prices[mid])
+    }
+}
+
+/// A BTC price observation pushed to subscribers (e.g. /ws/price)
+#[derive(Debug, Clone, Serialize)]
+pub struct PriceUpdate {
+    pub price: f64,
+    pub timestamp: i64,  // Unix seconds when the price was observed
+    pub stale: bool,
+}
+
+/// Refresh state of one IV surface, as reported by GET /admin/iv/status
+#[derive(Debug, Clone, Serialize)]
+pub struct IvSourceStatus {
+    pub asset: Asset,
+    pub entries: usize,
+    pub last_refresh_at: Option<i64>,         // Unix seconds
+    pub refresh_interval_secs: Option<u64>,  // None for surfaces that are not refreshed
+    pub last_error: Option<String>,          // Of the latest refresh
+}
+
+// Wall clock time of a refresh `age` ago
+fn refreshed_at(age: Option<Duration>) -> Option<i64> {
+    age.map(|age| Utc::now().timestamp() - age.as_secs() as i64)
+}
+
+/// An IV and where it came from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IvQuote {
+    pub value: f64,
+    pub source: IvProvenance,
+    pub age: Option<Duration>,  // Since the IV was quoted; None for defaults
+}
+
+impl IvQuote {
+    /// `value` used for want of any IV
+    pub fn default_iv(value: f64) -> Self {
+        Self { value, source: IvProvenance::Default, age: None }
+    }
+}
+
+/// Implied volatility surface
+#[async_trait]
+pub trait IvSource: Send + Sync {
+    /// IV as a decimal for side "C"/"P", strike in USD and expiry as a millisecond timestamp string
+    fn get_iv(&self, side: &str, strike_price: f64, expire: &str) -> Option<f64>;
+
+    /// `get_iv` on the surface of another underlying; BTC-only sources have no other surface
+    fn get_asset_iv(&self, asset: Asset, side: &str, strike_price: f64, expire: &str) -> Option<f64> {
+        match asset {
+            Asset::Btc => self.get_iv(side, strike_price, expire),
+            _ => None,
+        }
+    }
+
+    /// `get_asset_iv` with its provenance. Unless a source tells them apart, its IVs count as
+    /// quoted for the option, as old as the last refresh.
+    fn get_iv_quote(&self, asset: Asset, side: &str, strike_price: f64, expire: &str) -> Option<IvQuote> {
+        let value = self.get_asset_iv(asset, side, strike_price, expire)?;
+        Some(IvQuote { value, source: IvProvenance::Deribit, age: self.last_refresh_age() })
+    }
+
+    /// Number of IV points currently available
+    fn cache_size(&self) -> usize;
+
+    /// Counter that changes whenever the surface is refreshed (used for cache invalidation)
+    fn version(&self) -> u64;
+
+    /// Time since the surface was last refreshed; None for surfaces that are not refreshed
+    fn last_refresh_age(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Fetch the surface again now rather than at the next scheduled refresh
+    async fn refresh(&self) -> Result<(), SourceError> {
+        Ok(())
+    }
+
+    /// Smiles fitted to each expiry; empty for surfaces that are not fitted
+    fn surface_fits(&self) -> Vec<SurfaceFit> {
+        Vec::new()
+    }
+
+    /// Refresh state of each surface
+    fn status(&self) -> Vec<IvSourceStatus> {
+        vec![IvSourceStatus {
+            asset: Asset::Btc,
+            entries: self.cache_size(),
+            last_refresh_at: refreshed_at(self.last_refresh_age()),
+            refresh_interval_secs: None,
+            last_error: None,
+        }]
+    }
+}
+
+/// On-chain balance of the pool address, and the transactions paying into it
+#[async_trait]
+pub trait WalletSource: Send + Sync {
+    async fn get_wallet_balance(&self, address: &str) -> Result<WalletBalance, MutinyWalletError>;
+
+    /// Recent transactions involving `address`, mempool first
+    async fn get_address_transactions(&self, _address: &str) -> Result<Vec<Transaction>, MutinyWalletError> {
+        Err(MutinyWalletError::ApiError("transaction history not supported".to_string()))
+    }
+
+    /// Height of the chain tip, for counting confirmations
+    async fn get_tip_height(&self) -> Result<u64, MutinyWalletError> {
+        Err(MutinyWalletError::ApiError("chain tip not supported".to_string()))
+    }
+}
+
+#[async_trait]
+impl PriceSource for PriceOracle {
+    async fn get_btc_price(&self) -> Result<f64, SourceError> {
+        PriceOracle::get_btc_price(self).await.map_err(|e| e.to_string().into())
+    }
+
+    async fn get_price(&self, asset: Asset) -> Result<f64, SourceError> {
+        PriceOracle::get_price(self, asset).await.map_err(|e| e.to_string().into())
+    }
+
+    async fn source_prices(&self, asset: Asset, sources: &[String]) -> Result<Vec<SourcePrice>, SourceError> {
+        PriceOracle::get_source_prices(self, asset, sources).await.map_err(|e| e.to_string().into())
+    }
+
+    fn version(&self) -> u64 {
+        PriceOracle::version(self)
+    }
+}
+
+#[async_trait]
+impl IvSource for IvOracle {
+    fn get_iv(&self, side: &str, strike_price: f64, expire: &str) -> Option<f64> {
+        IvOracle::get_iv(self, side, strike_price, expire)
+    }
+
+    fn get_asset_iv(&self, asset: Asset, side: &str, strike_price: f64, expire: &str) -> Option<f64> {
+        if asset == self.currency() {
+            IvOracle::get_iv(self, side, strike_price, expire)
+        } else {
+            None
+        }
+    }
+
+    fn get_iv_quote(&self, asset: Asset, side: &str, strike_price: f64, expire: &str) -> Option<IvQuote> {
+        if asset == self.currency() {
+            IvOracle::get_iv_quote(self, side, strike_price, expire)
+        } else {
+            None
+        }
+    }
+
+    fn cache_size(&self) -> usize {
+        self.get_cache_size()
+    }
+
+    fn version(&self) -> u64 {
+        IvOracle::version(self)
+    }
+
+    fn last_refresh_age(&self) -> Option<Duration> {
+        IvOracle::last_refresh_age(self)
+    }
+
+    async fn refresh(&self) -> Result<(), SourceError> {
+        self.fetch_and_update_iv().await.map_err(|e| e.to_string().into())
+    }
+
+    fn surface_fits(&self) -> Vec<SurfaceFit> {
+        IvOracle::surface_fits(self)
+    }
+
+    fn status(&self) -> Vec<IvSourceStatus> {
+        vec![IvSourceStatus {
+            asset: self.currency(),
+            entries: self.get_cache_size(),
+            last_refresh_at: refreshed_at(IvOracle::last_refresh_age(self)),
+            refresh_interval_secs: Some(self.refresh_config().interval.as_secs()),
+            last_error: self.last_error(),
+        }]
+    }
+}
+
+#[async_trait]
+impl WalletSource for MutinyWallet {
+    async fn get_wallet_balance(&self, address: &str) -> Result<WalletBalance, MutinyWalletError> {
+        MutinyWallet::get_wallet_balance(self, address).await
+    }
+
+    async fn get_address_transactions(&self, address: &str) -> Result<Vec<Transaction>, MutinyWalletError> {
+        MutinyWallet::get_address_transactions(self, address).await
+    }
+
+    async fn get_tip_height(&self) -> Result<u64, MutinyWalletError> {
+        MutinyWallet::get_tip_height(self).await
+    }
+}
+
+/// One IV surface per underlying, e.g. a BTC and an ETH Deribit oracle
+pub struct AssetIvSources {
+    sources: HashMap<Asset, Arc<dyn IvSource>>,
+}
+
+impl AssetIvSources {
+    pub fn new(sources: HashMap<Asset, Arc<dyn IvSource>>) -> Self {
+        Self { sources }
+    }
+}
+
+#[async_trait]
+impl IvSource for AssetIvSources {
+    fn get_iv(&self, side: &str, strike_price: f64, expire: &str) -> Option<f64> {
+        self.get_asset_iv(Asset::Btc, side, strike_price, expire)
+    }
+
+    fn get_asset_iv(&self, asset: Asset, side: &str, strike_price: f64, expire: &str) -> Option<f64> {
+        self.sources.get(&asset)?.get_asset_iv(asset, side, strike_price, expire)
+    }
+
+    fn get_iv_quote(&self, asset: Asset, side: &str, strike_price: f64, expire: &str) -> Option<IvQuote> {
+        self.sources.get(&asset)?.get_iv_quote(asset, side, strike_price, expire)
+    }
+
+    fn cache_size(&self) -> usize {
+        self.sources.values().map(|source| source.cache_size()).sum()
+    }
+
+    fn version(&self) -> u64 {
+        // Each counter only grows, so the sum changes whenever any surface refreshes
+        self.sources.values().map(|source| source.version()).sum()
+    }
+
+    fn last_refresh_age(&self) -> Option<Duration> {
+        // The stalest surface
+        self.sources.values().filter_map(|source| source.last_refresh_age()).max()
+    }
+
+    async fn refresh(&self) -> Result<(), SourceError> {
+        let results = join_all(self.sources.iter().map(|(asset, source)| async move {
+            source.refresh().await.map_err(|e| format!("{}: {}", asset, e))
+        }))
+        .await;
+        let errors: Vec<String> = results.into_iter().filter_map(Result::err).collect();
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors.join("; ").into()),
+        }
+    }
+
+    fn surface_fits(&self) -> Vec<SurfaceFit> {
+        let mut fits: Vec<SurfaceFit> = self.sources.values().flat_map(|source| source.surface_fits()).collect();
+        fits.sort_by_key(|fit| (fit.asset, fit.expires));
+        fits
+    }
+
+    fn status(&self) -> Vec<IvSourceStatus> {
+        let mut statuses: Vec<IvSourceStatus> = self
+            .sources
+            .iter()
+            .flat_map(|(&asset, source)| source.status().into_iter().map(move |status| IvSourceStatus { asset, ..status }))
+            .collect();
+        statuses.sort_by_key(|status| status.asset);
+        statuses
+    }
+}
+
+/// Price source that always reports the same prices (offline mode, demos)
+pub struct FixedPriceSource {
+    prices: HashMap<Asset, f64>,
+}
+
+impl FixedPriceSource {
+    /// Fixed BTC price; add other underlyings with `with_price`
+    pub fn new(price: f64) -> Self {
+        Self { prices: HashMap::from([(Asset::Btc, price)]) }
+    }
+
+    pub fn with_price(mut self, asset: Asset, price: f64) -> Self {
+        self.prices.insert(asset, price);
+        self
+    }
+}
+
+#[async_trait]
+impl PriceSource for FixedPriceSource {
+    async fn get_btc_price(&self) -> Result<f64, SourceError> {
+        self.get_price(Asset::Btc).await
+    }
+
+    async fn get_price(&self, asset: Asset) -> Result<f64, SourceError> {
+        self.prices
+            .get(&asset)
+            .copied()
+            .ok_or_else(|| format!("no fixed {} price configured", asset).into())
+    }
+
+    fn version(&self) -> u64 {
+        0
+    }
+}
+
+// One point of a static IV surface, e.g. {"side": "C", "strike": 100000, "tenor": "7d", "iv": 0.55}.
+// Points without an "asset" belong to the BTC surface.
+#[derive(Debug, Clone, Deserialize)]
+pub struct StaticIvPoint {
+    #[serde(default)]
+    pub asset: Asset,
+    pub side: String,
+    pub strike: f64,
+    pub tenor: String,  // Time to expiry relative to now: 30m, 12h, 7d
+    pub iv: f64,        // Decimal, 0.55 = 55%
+}
+
+/// IV surface loaded once from a JSON file instead of Deribit.
+/// Lookups pick the nearest tenor for the side, then the nearest strike within it.
+pub struct StaticIvSource {
+    points: Vec<(Asset, String, f64, i64, f64)>,  // (asset, side, strike, tenor seconds, iv)
+}
+
+impl StaticIvSource {
+    pub fn new(points: Vec<StaticIvPoint>) -> Result<Self, String> {
+        let points = points
+            .into_iter()
+            .map(|point| {
+                let tenor_secs = duration_to_seconds(&point.tenor);
+                if tenor_secs <= 0 {
+                    return Err(format!("invalid tenor '{}'", point.tenor));
+                }
+                if !(point.iv.is_finite() && point.iv > 0.0) {
+                    return Err(format!("invalid iv {} for {} {} {}", point.iv, point.side, point.strike, point.tenor));
+                }
+                Ok((point.asset, point.side.to_uppercase(), point.strike, tenor_secs, point.iv))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Self { points })
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+        let points: Vec<StaticIvPoint> =
+            serde_json::from_str(&contents).map_err(|e| format!("failed to parse {}: {}", path, e))?;
+        Self::new(points)
+    }
+}
+
+impl IvSource for StaticIvSource {
+    fn get_iv(&self, side: &str, strike_price: f64, expire: &str) -> Option<f64> {
+        self.get_asset_iv(Asset::Btc, side, strike_price, expire)
+    }
+
+    fn get_asset_iv(&self, asset: Asset, side: &str, strike_price: f64, expire: &str) -> Option<f64> {
+        // Millisecond expiry timestamp, or a tenor such as "7d"
+        let tenor_secs = match expire.parse::<i64>() {
+            Ok(timestamp_ms) => timestamp_ms / 1000 - Utc::now().timestamp(),
+            Err(_) => duration_to_seconds(expire),
+        };
+
+        let candidates: Vec<&(Asset, String, f64, i64, f64)> =
+            self.points.iter().filter(|(a, s, _, _, _)| *a == asset && s == side).collect();
+        let nearest_tenor = candidates
+            .iter()
+            .map(|(_, _, _, tenor, _)| *tenor)
+            .min_by_key(|tenor| (tenor - tenor_secs).abs())?;
+
+        candidates
+            .iter()
+            .filter(|(_, _, _, tenor, _)| *tenor == nearest_tenor)
+            .min_by(|a, b| (a.2 - strike_price).abs().total_cmp(&(b.2 - strike_price).abs()))
+            .map(|(_, _, _, _, iv)| *iv)
+    }
+
+    // Points are looked up by nearest tenor and strike, so none is a quote for the option itself
+    fn get_iv_quote(&self, asset: Asset, side: &str, strike_price: f64, expire: &str) -> Option<IvQuote> {
+        let value = self.get_asset_iv(asset, side, strike_price, expire)?;
+        Some(IvQuote { value, source: IvProvenance::Interpolated, age: None })
+    }
+
+    fn cache_size(&self) -> usize {
+        self.points.len()
+    }
+
+    fn version(&self) -> u64 {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(side: &str, strike: f64, tenor: &str, iv: f64) -> StaticIvPoint {
+        StaticIvPoint { asset: Asset::Btc, side: side.to_string(), strike, tenor: tenor.to_string(), iv }
+    }
+
+    #[test]
+    fn test_static_iv_nearest_tenor_then_strike() {
+        let source = StaticIvSource::new(vec![
+            point("C", 100_000.0, "1d", 0.50),
+            point("C", 110_000.0, "1d", 0.55),
+            point("C", 100_000.0, "7d", 0.60),
+            point("p", 100_000.0, "1d", 0.70),
+        ])
+        .unwrap();
+
+        assert_eq!(source.get_iv("C", 108_000.0, "1d"), Some(0.55));
+        assert_eq!(source.get_iv("C", 108_000.0, "6d"), Some(0.60));
+        assert_eq!(source.get_iv("P", 90_000.0, "3d"), Some(0.70));
+
+        let in_two_days_ms = (Utc::now().timestamp() + 2 * 86_400) * 1000;
+        assert_eq!(source.get_iv("C", 99_000.0, &in_two_days_ms.to_string()), Some(0.50));
+        assert_eq!(source.cache_size(), 4);
+    }
+
+    #[test]
+    fn test_static_iv_surfaces_are_per_asset() {
+        let eth = StaticIvPoint { asset: Asset::Eth, ..point("C", 3_500.0, "1d", 0.80) };
+        let source = StaticIvSource::new(vec![point("C", 100_000.0, "1d", 0.50), eth]).unwrap();
+
+        assert_eq!(source.get_iv("C", 3_500.0, "1d"), Some(0.50));
+        assert_eq!(source.get_asset_iv(Asset::Eth, "C", 3_600.0, "1d"), Some(0.80));
+
+        let combined = AssetIvSources::new(HashMap::from([(Asset::Btc, Arc::new(source) as Arc<dyn IvSource>)]));
+        assert_eq!(combined.get_iv("C", 100_000.0, "1d"), Some(0.50));
+        assert!(combined.get_asset_iv(Asset::Eth, "C", 3_500.0, "1d").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_fixed_price_per_asset() {
+        let source = FixedPriceSource::new(100_000.0).with_price(Asset::Eth, 3_500.0);
+        assert_eq!(source.get_btc_price().await.unwrap(), 100_000.0);
+        assert_eq!(source.get_price(Asset::Eth).await.unwrap(), 3_500.0);
+        assert_eq!(FixedPriceSource::new(100_000.0).get_asset_price_quote(Asset::Btc).await.unwrap().price, 100_000.0);
+        assert!(FixedPriceSource::new(100_000.0).get_price(Asset::Eth).await.is_err());
+    }
+
+    #[test]
+    fn test_static_iv_rejects_bad_points() {
+        assert!(StaticIvSource::new(vec![point("C", 100_000.0, "soon", 0.5)]).is_err());
+        assert!(StaticIvSource::new(vec![point("C", 100_000.0, "1d", -0.5)]).is_err());
+        assert!(StaticIvSource::new(vec![]).unwrap().get_iv("C", 100_000.0, "1d").is_none());
+    }
+}
@@ -0,0 +1,223 @@
+// Hourly market statistics.
+// A background job snapshots every completed hour into market_stats: the volume traded
+// during the hour, and the open interest, contract count and notional at its end
+// (less anything exercised or sold back to the pool before the end of the hour).
+// GET /stats/history serves the snapshots as time series for charting.
+
+use crate::error::ApiResult;
+use crate::models::Asset;
+use crate::repository::Repository;
+use crate::sources::PriceSource;
+use crate::supervisor::Supervisor;
+use crate::utils::{btc_to_sats, cents_to_usd, sats_to_btc, usd_to_cents, SATS_PER_BTC};
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::interval;
+
+pub const BUCKET_SECS: i64 = 60 * 60;
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct MarketStats {
+    pub bucket: i64,             // Start of the hour, Unix seconds
+    pub underlying: Asset,
+    pub volume: f64,             // Quantity traded during the hour
+    pub open_interest: f64,      // Quantity open at the end of the hour, net of closes
+    pub open_interest_btc: f64,  // Premium of the open contracts (quantity * premium), as in /topBanner
+    pub contract_count: i64,     // Contracts open at the end of the hour
+    pub notional_usd: f64,       // open_interest at spot_price
+    pub spot_price: f64,         // Underlying price when the snapshot was taken
+}
+
+/// Start of the hour containing `timestamp`
+pub fn bucket_start(timestamp: i64) -> i64 {
+    timestamp - timestamp.rem_euclid(BUCKET_SECS)
+}
+
+/// Statistics of `underlying` for the hour starting at `bucket`, valuing the notional at `spot_price`
+pub fn compute_market_stats(conn: &Connection, underlying: Asset, bucket: i64, spot_price: f64) -> ApiResult<MarketStats> {
+    let end = bucket + BUCKET_SECS;
+    let (volume_sats, open_interest_sats, open_interest_sats_squared, contract_count): (i64, i64, f64, i64) = conn.query_row(
+        "SELECT COALESCE(SUM(CASE WHEN created_at >= ?2 THEN quantity_sats END), 0),
+                COALESCE(SUM(CASE WHEN open_at_end THEN open_sats END), 0),
+                TOTAL(CASE WHEN open_at_end THEN open_sats * premium_sats END),
+                COUNT(CASE WHEN open_at_end THEN 1 END)
+         FROM (SELECT created_at, quantity_sats, premium_sats, open_sats,
+                      open_sats > 0 AND expires > ?3 AND (status != 'exercised' OR settled_at >= ?3) AS open_at_end
+               FROM (SELECT c.*, c.quantity_sats - (SELECT COALESCE(SUM(cc.quantity_sats), 0) FROM contract_closes cc
+                                                    WHERE cc.contract_id = c.id AND cc.closed_at < ?3) AS open_sats
+                     FROM contracts c
+                     WHERE c.underlying = ?1 AND c.created_at < ?3))",
+        params![underlying, bucket, end],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+    )?;
+
+    let open_interest = sats_to_btc(open_interest_sats);
+    Ok(MarketStats {
+        bucket,
+        underlying,
+        volume: sats_to_btc(volume_sats),
+        open_interest,
+        open_interest_btc: open_interest_sats_squared / (SATS_PER_BTC as f64 * SATS_PER_BTC as f64),
+        contract_count,
+        notional_usd: open_interest * spot_price,
+        spot_price,
+    })
+}
+
+/// Store a snapshot, replacing any earlier one for the same hour and underlying
+pub fn record_market_stats(conn: &Connection, stats: &MarketStats) -> ApiResult<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO market_stats
+         (bucket, underlying, volume_sats, open_interest_sats, open_interest_btc_sats, contract_count,
+          notional_cents, spot_price_cents, recorded_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            stats.bucket,
+            stats.underlying,
+            btc_to_sats(stats.volume),
+            btc_to_sats(stats.open_interest),
+            btc_to_sats(stats.open_interest_btc),
+            stats.contract_count,
+            usd_to_cents(stats.notional_usd),
+            usd_to_cents(stats.spot_price),
+            Utc::now().timestamp()
+        ],
+    )?;
+    Ok(())
+}
+
+pub fn is_recorded(conn: &Connection, underlying: Asset, bucket: i64) -> ApiResult<bool> {
+    Ok(conn
+        .query_row(
+            "SELECT 1 FROM market_stats WHERE bucket = ?1 AND underlying = ?2",
+            params![bucket, underlying],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some())
+}
+
+/// Snapshots of `underlying` for hours starting in [from, to), oldest first
+pub fn load_market_stats(conn: &Connection, underlying: Asset, from: i64, to: i64) -> ApiResult<Vec<MarketStats>> {
+    let mut stmt = conn.prepare(
+        "SELECT bucket, volume_sats, open_interest_sats, open_interest_btc_sats, contract_count,
+                notional_cents, spot_price_cents
+         FROM market_stats
+         WHERE underlying = ?1 AND bucket >= ?2 AND bucket < ?3
+         ORDER BY bucket ASC",
+    )?;
+    let rows = stmt.query_map(params![underlying, from, to], |row| {
+        Ok(MarketStats {
+            bucket: row.get(0)?,
+            underlying,
+            volume: sats_to_btc(row.get(1)?),
+            open_interest: sats_to_btc(row.get(2)?),
+            open_interest_btc: sats_to_btc(row.get(3)?),
+            contract_count: row.get(4)?,
+            notional_usd: cents_to_usd(row.get(5)?),
+            spot_price: cents_to_usd(row.get(6)?),
+        })
+    })?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// Record the last completed hour for every underlying that has no snapshot of it yet.
+/// Returns the number of snapshots written.
+pub async fn snapshot_completed_hour(
+    repository: &Repository,
+    price_source: &dyn PriceSource,
+    assets: &[Asset],
+    now: i64,
+) -> ApiResult<usize> {
+    let bucket = bucket_start(now) - BUCKET_SECS;
+    let mut recorded = 0;
+    for &asset in assets {
+        if repository.run(move |conn| is_recorded(conn, asset, bucket)).await? {
+            continue;
+        }
+        let spot_price = match price_source.get_price(asset).await {
+            Ok(price) => price,
+            Err(e) => {
+                eprintln!("⚠️  No {} price for the market stats snapshot, will retry: {}", asset, e);
+                continue;
+            }
+        };
+        repository
+            .run(move |conn| record_market_stats(conn, &compute_market_stats(conn, asset, bucket, spot_price)?))
+            .await?;
+        recorded += 1;
+    }
+    Ok(recorded)
+}
+
+/// Check every STATS_CHECK_INTERVAL_SECS (60) for a completed hour to snapshot
+pub fn start_stats_aggregation(
+    supervisor: &Supervisor,
+    repository: Repository,
+    price_source: Arc<dyn PriceSource>,
+    assets: Vec<Asset>,
+) {
+    let check_interval_secs: u64 = env::var("STATS_CHECK_INTERVAL_SECS")
+        .unwrap_or_else(|_| "60".to_string())
+        .parse()
+        .unwrap_or(60);
+    supervisor.spawn("stats_aggregation", move || {
+        let (repository, price_source, assets) = (repository.clone(), price_source.clone(), assets.clone());
+        async move {
+            let mut ticker = interval(Duration::from_secs(check_interval_secs.max(1)));
+            loop {
+                ticker.tick().await;
+                let now = Utc::now().timestamp();
+                if let Err(e) = snapshot_completed_hour(&repository, price_source.as_ref(), &assets, now).await {
+                    eprintln!("Error recording market stats: {}", e);
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Contract, OptionSide};
+    use crate::repository::insert_contract;
+
+    #[test]
+    fn test_market_stats_snapshot_roundtrip() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init_db(&conn).unwrap();
+
+        let now = Utc::now().timestamp();
+        let bucket = bucket_start(now);
+        let contract = |underlying: Asset, quantity: f64, expires: i64| Contract {
+            underlying,
+            side: OptionSide::Call,
+            strike_price: 105000.0,
+            quantity,
+            expires,
+            premium: 0.01,
+        };
+        insert_contract(&conn, &contract(Asset::Btc, 0.5, bucket + 2 * BUCKET_SECS), None).unwrap();
+        // Traded in the hour but expired by its end: volume only
+        insert_contract(&conn, &contract(Asset::Btc, 0.25, bucket + BUCKET_SECS), None).unwrap();
+        insert_contract(&conn, &contract(Asset::Eth, 3.0, bucket + 2 * BUCKET_SECS), None).unwrap();
+
+        let stats = compute_market_stats(&conn, Asset::Btc, bucket, 100000.0).unwrap();
+        assert_eq!(stats.volume, 0.75);
+        assert_eq!(stats.open_interest, 0.5);
+        assert_eq!(stats.contract_count, 1);
+        assert!((stats.open_interest_btc - 0.005).abs() < 1e-12);
+        assert_eq!(stats.notional_usd, 50000.0);
+
+        assert!(!is_recorded(&conn, Asset::Btc, bucket).unwrap());
+        record_market_stats(&conn, &stats).unwrap();
+        record_market_stats(&conn, &stats).unwrap();
+        assert!(is_recorded(&conn, Asset::Btc, bucket).unwrap());
+        assert_eq!(load_market_stats(&conn, Asset::Btc, bucket, bucket + 1).unwrap(), vec![stats]);
+        assert!(load_market_stats(&conn, Asset::Eth, bucket, bucket + 1).unwrap().is_empty());
+    }
+}
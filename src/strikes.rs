@@ -0,0 +1,170 @@
+// Strike ticks.
+// Strikes arrive as decimals and are stored in cents, so float noise such as 50000.004999 would
+// become a product of its own wherever contracts are grouped. Each underlying trades on a tick
+// (STRIKE_TICK_BTC $100, STRIKE_TICK_ETH $10): strikes of new trades within a cent of a tick
+// are snapped to it and others rejected, the strike grid is rounded to it, and product keys
+// snap the same way. Stored contracts are snapped once at startup, so grouping queries over
+// older trades agree. The ticks are set once at startup with `init` and read through `deployment`.
+
+use crate::error::{ApiError, ApiResult, ErrorCode};
+use crate::models::Asset;
+use crate::utils::{cents_to_usd, usd_to_cents};
+use rusqlite::{params, Connection};
+use serde_json::json;
+use std::env;
+use std::sync::OnceLock;
+
+pub const DEFAULT_BTC_TICK: f64 = 100.0;
+pub const DEFAULT_ETH_TICK: f64 = 10.0;
+
+// Distance from a tick still taken as float noise
+const NOISE_CENTS: i64 = 1;
+
+static DEPLOYMENT: OnceLock<StrikeTicks> = OnceLock::new();
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StrikeTicks {
+    pub btc: f64,
+    pub eth: f64,
+}
+
+impl Default for StrikeTicks {
+    fn default() -> Self {
+        Self { btc: DEFAULT_BTC_TICK, eth: DEFAULT_ETH_TICK }
+    }
+}
+
+impl StrikeTicks {
+    /// STRIKE_TICK_BTC (100) and STRIKE_TICK_ETH (10), in USD and whole cents
+    pub fn from_env() -> Result<Self, String> {
+        let defaults = Self::default();
+        let tick = |name: &str, default: f64| match env::var(name) {
+            Ok(value) => value
+                .trim()
+                .parse()
+                .ok()
+                .filter(|tick: &f64| usd_to_cents(*tick) > 0 && cents_to_usd(usd_to_cents(*tick)) == *tick)
+                .ok_or_else(|| format!("invalid {} '{}', expected a positive USD amount in whole cents", name, value)),
+            Err(_) => Ok(default),
+        };
+        Ok(Self { btc: tick("STRIKE_TICK_BTC", defaults.btc)?, eth: tick("STRIKE_TICK_ETH", defaults.eth)? })
+    }
+
+    /// Tick of `asset` strikes, in USD
+    pub fn tick(&self, asset: Asset) -> f64 {
+        match asset {
+            Asset::Btc => self.btc,
+            Asset::Eth => self.eth,
+        }
+    }
+
+    fn tick_cents(&self, asset: Asset) -> i64 {
+        usd_to_cents(self.tick(asset))
+    }
+
+    /// `strike_cents` snapped to the nearest tick when it is only float noise away from it
+    pub fn normalize_cents(&self, asset: Asset, strike_cents: i64) -> i64 {
+        let tick = self.tick_cents(asset);
+        let nearest = (strike_cents as f64 / tick as f64).round() as i64 * tick;
+        if (strike_cents - nearest).abs() <= NOISE_CENTS {
+            nearest
+        } else {
+            strike_cents
+        }
+    }
+
+    /// `strike_price` snapped to the nearest tick when it is only float noise away from it
+    pub fn normalize(&self, asset: Asset, strike_price: f64) -> f64 {
+        cents_to_usd(self.normalize_cents(asset, usd_to_cents(strike_price)))
+    }
+
+    /// Strike of a new `asset` trade, snapped to its tick, or an INVALID_STRIKE error when it is
+    /// not positive or off the tick
+    pub fn validate(&self, asset: Asset, strike_price: f64) -> ApiResult<f64> {
+        let invalid = |message: String| {
+            Err(ApiError::ValidationError(message)
+                .with_code(ErrorCode::InvalidStrike)
+                .with_details(json!({"underlying": asset, "strike_price": strike_price, "tick_size": self.tick(asset)})))
+        };
+        if !strike_price.is_finite() || strike_price <= 0.0 {
+            return invalid(format!("Strike must be positive, got {}", strike_price));
+        }
+        let cents = self.normalize_cents(asset, usd_to_cents(strike_price));
+        if cents <= 0 || cents % self.tick_cents(asset) != 0 {
+            return invalid(format!("Strike {} is not a multiple of the {} tick size of {}", strike_price, asset, self.tick(asset)));
+        }
+        Ok(cents_to_usd(cents))
+    }
+
+    /// Snap the strikes of stored contracts that are float noise away from a tick. Returns how
+    /// many were.
+    pub fn normalize_stored(&self, conn: &Connection) -> ApiResult<usize> {
+        let mut normalized = 0;
+        for asset in Asset::ALL {
+            let tick = self.tick_cents(asset);
+            if tick <= NOISE_CENTS {
+                continue;
+            }
+            normalized += conn.execute(
+                "UPDATE contracts SET strike_price_cents = ((strike_price_cents + ?2 / 2) / ?2) * ?2
+                 WHERE underlying = ?1 AND (strike_price_cents % ?2 <= ?3 OR strike_price_cents % ?2 >= ?2 - ?3)
+                       AND strike_price_cents % ?2 != 0",
+                params![asset, tick, NOISE_CENTS],
+            )?;
+        }
+        Ok(normalized)
+    }
+}
+
+/// Set the deployment's ticks. Fails if they were already set or read.
+pub fn init(ticks: StrikeTicks) -> Result<(), String> {
+    DEPLOYMENT.set(ticks).map_err(|_| "the strike ticks are already set".to_string())
+}
+
+/// The deployment's ticks; the defaults unless `init` set others
+pub fn deployment() -> &'static StrikeTicks {
+    DEPLOYMENT.get_or_init(StrikeTicks::default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strikes_snap_to_ticks() {
+        let ticks = StrikeTicks::default();
+        assert_eq!(ticks.validate(Asset::Btc, 50_000.004999).unwrap(), 50_000.0);
+        assert_eq!(ticks.validate(Asset::Btc, 49_999.99).unwrap(), 50_000.0);
+        assert_eq!(ticks.validate(Asset::Eth, 3_510.0).unwrap(), 3_510.0);
+        for (asset, strike) in [(Asset::Btc, 50_050.0), (Asset::Btc, 50_000.5), (Asset::Eth, 3_505.0), (Asset::Btc, 0.0), (Asset::Btc, f64::NAN)] {
+            let err = ticks.validate(asset, strike).unwrap_err();
+            assert_eq!(err.code(), ErrorCode::InvalidStrike);
+        }
+        assert_eq!(ticks.validate(Asset::Btc, 50_050.0).unwrap_err().details()["tick_size"], 100.0);
+
+        // Keys only drop noise, strikes off the tick keep their own product
+        assert_eq!(ticks.normalize_cents(Asset::Btc, 5_000_001), 5_000_000);
+        assert_eq!(ticks.normalize_cents(Asset::Btc, 5_005_000), 5_005_000);
+        assert_eq!(ticks.normalize(Asset::Eth, 3_509.99), 3_510.0);
+    }
+
+    #[test]
+    fn test_normalize_stored_contracts() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init_db(&conn).unwrap();
+        for (strike_cents, underlying) in [(5_000_001, "BTC"), (4_999_999, "BTC"), (5_005_000, "BTC"), (351_001, "ETH"), (5_000_000, "BTC")] {
+            conn.execute(
+                "INSERT INTO contracts (side, strike_price_cents, quantity_sats, expires, premium_sats, underlying)
+                 VALUES ('Call', ?1, 100000000, 2000000000, 100000, ?2)",
+                params![strike_cents, underlying],
+            )
+            .unwrap();
+        }
+
+        assert_eq!(StrikeTicks::default().normalize_stored(&conn).unwrap(), 3);
+        let mut stmt = conn.prepare("SELECT strike_price_cents FROM contracts ORDER BY id").unwrap();
+        let strikes: Vec<i64> = stmt.query_map([], |row| row.get(0)).unwrap().collect::<Result<_, _>>().unwrap();
+        assert_eq!(strikes, vec![5_000_000, 5_000_000, 5_005_000, 351_000, 5_000_000]);
+        assert_eq!(StrikeTicks::default().normalize_stored(&conn).unwrap(), 0);
+    }
+}
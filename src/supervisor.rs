@@ -0,0 +1,182 @@
+// Supervision of background workers.
+// Each worker (IV refresh, payment watcher, attestations, stats, notifications, ...) runs as
+// its own tokio task. A panic would otherwise end it silently, so the supervisor awaits every
+// worker and restarts it when it panics or returns, waiting TASK_RESTART_BACKOFF_SECS and
+// doubling the wait on each consecutive failure up to TASK_RESTART_MAX_BACKOFF_SECS. A worker
+// that ran for longer than the maximum backoff starts again from the initial one. The state of
+// every worker is reported by /health.
+
+use chrono::Utc;
+use serde::Serialize;
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::env;
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SupervisorConfig {
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+impl SupervisorConfig {
+    /// TASK_RESTART_BACKOFF_SECS (1) and TASK_RESTART_MAX_BACKOFF_SECS (60)
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let secs = |name: &str, default: Duration| {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .map(|secs: u64| Duration::from_secs(secs.max(1)))
+                .unwrap_or(default)
+        };
+        let initial_backoff = secs("TASK_RESTART_BACKOFF_SECS", defaults.initial_backoff);
+        Self {
+            initial_backoff,
+            max_backoff: secs("TASK_RESTART_MAX_BACKOFF_SECS", defaults.max_backoff).max(initial_backoff),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskState {
+    Running,
+    Restarting,  // Failed, waiting out the backoff
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TaskStatus {
+    pub name: String,
+    pub state: TaskState,
+    pub restarts: u32,
+    pub started_at: i64,                // Start of the current run
+    pub last_failure: Option<String>,   // Panic message, or why the worker stopped
+    pub last_failure_at: Option<i64>,
+}
+
+#[derive(Clone, Default)]
+pub struct Supervisor {
+    config: SupervisorConfig,
+    tasks: Arc<RwLock<BTreeMap<String, TaskStatus>>>,
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => format!("panicked: {}", message),
+        Err(payload) => match payload.downcast::<&'static str>() {
+            Ok(message) => format!("panicked: {}", message),
+            Err(_) => "panicked".to_string(),
+        },
+    }
+}
+
+impl Supervisor {
+    pub fn new(config: SupervisorConfig) -> Self {
+        Self { config, tasks: Arc::default() }
+    }
+
+    /// Run the future made by `worker` as the task `name`, restarting it with a new future
+    /// whenever it panics or returns
+    pub fn spawn<F, Fut>(&self, name: &str, worker: F)
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let supervisor = self.clone();
+        let name = name.to_string();
+        tokio::spawn(async move {
+            let mut backoff = supervisor.config.initial_backoff;
+            loop {
+                let started = Instant::now();
+                supervisor.update(&name, |task| {
+                    task.state = TaskState::Running;
+                    task.started_at = Utc::now().timestamp();
+                });
+
+                let failure = match tokio::spawn(worker()).await {
+                    Ok(()) => "stopped".to_string(),
+                    Err(e) if e.is_panic() => panic_message(e.into_panic()),
+                    Err(e) => e.to_string(),
+                };
+
+                if started.elapsed() > supervisor.config.max_backoff {
+                    backoff = supervisor.config.initial_backoff;
+                }
+                eprintln!("⚠️  Background task {} {}, restarting in {:?}", name, failure, backoff);
+                supervisor.update(&name, |task| {
+                    task.state = TaskState::Restarting;
+                    task.restarts += 1;
+                    task.last_failure = Some(failure);
+                    task.last_failure_at = Some(Utc::now().timestamp());
+                });
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(supervisor.config.max_backoff);
+            }
+        });
+    }
+
+    fn update(&self, name: &str, change: impl FnOnce(&mut TaskStatus)) {
+        let mut tasks = self.tasks.write().unwrap();
+        let task = tasks.entry(name.to_string()).or_insert_with(|| TaskStatus {
+            name: name.to_string(),
+            state: TaskState::Running,
+            restarts: 0,
+            started_at: Utc::now().timestamp(),
+            last_failure: None,
+            last_failure_at: None,
+        });
+        change(task);
+    }
+
+    /// Supervised tasks, by name
+    pub fn tasks(&self) -> Vec<TaskStatus> {
+        self.tasks.read().unwrap().values().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[tokio::test]
+    async fn test_panicking_worker_is_restarted() {
+        let supervisor = Supervisor::new(SupervisorConfig {
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(40),
+        });
+        let runs = Arc::new(AtomicU32::new(0));
+        let counter = runs.clone();
+        supervisor.spawn("flaky", move || {
+            let runs = counter.clone();
+            async move {
+                // Panic on the first two runs, then keep running
+                if runs.fetch_add(1, Ordering::SeqCst) < 2 {
+                    panic!("lost the connection");
+                }
+                std::future::pending::<()>().await
+            }
+        });
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+        let tasks = supervisor.tasks();
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].name, "flaky");
+        assert_eq!(tasks[0].state, TaskState::Running);
+        assert_eq!(tasks[0].restarts, 2);
+        assert_eq!(tasks[0].last_failure.as_deref(), Some("panicked: lost the connection"));
+    }
+}
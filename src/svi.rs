@@ -0,0 +1,333 @@
+// SVI volatility smiles.
+// Each expiry of the Deribit surface is fitted with Gatheral's raw SVI parameterization of
+// total implied variance in log-moneyness k = ln(K / F):
+//     w(k) = a + b (rho (k - m) + sqrt((k - m)^2 + sigma^2))
+// Fits are constrained to Lee's moment bound on the wings and only accepted when the
+// smile's implied density is non-negative, so they are free of butterfly arbitrage.
+// Between and beyond the fitted expiries total variance is interpolated linearly in time,
+// after flooring each slice at the one before it, so served IVs are free of calendar
+// arbitrage as well.
+
+use crate::models::Asset;
+use crate::utils::year_fraction;
+use serde::Serialize;
+
+// Fewer quoted strikes than this leave an expiry unfitted (SVI has five parameters)
+pub const MIN_POINTS: usize = 5;
+
+// Total variance wings may grow by at most this much per unit of log-moneyness (Lee)
+const MAX_WING_SLOPE: f64 = 2.0;
+
+/// Raw SVI parameters of one expiry
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct SviParams {
+    pub a: f64,      // Level of total variance
+    pub b: f64,      // Angle between the wings
+    pub rho: f64,    // Skew, in (-1, 1)
+    pub m: f64,      // Log-moneyness of the vertex
+    pub sigma: f64,  // Curvature at the vertex
+}
+
+impl SviParams {
+    /// Total implied variance (IV^2 * t) at log-moneyness `k`
+    pub fn total_variance(&self, k: f64) -> f64 {
+        let x = k - self.m;
+        self.a + self.b * (self.rho * x + (x * x + self.sigma * self.sigma).sqrt())
+    }
+
+    /// Gatheral's g(k), proportional to the density the smile implies. The smile is free of
+    /// butterfly arbitrage where it is non-negative.
+    pub fn density(&self, k: f64) -> f64 {
+        let x = k - self.m;
+        let root = (x * x + self.sigma * self.sigma).sqrt();
+        let w = self.total_variance(k);
+        if w <= 0.0 {
+            return -1.0;
+        }
+        let w1 = self.b * (self.rho + x / root);
+        let w2 = self.b * self.sigma * self.sigma / (root * root * root);
+        (1.0 - k * w1 / (2.0 * w)).powi(2) - w1 * w1 / 4.0 * (1.0 / w + 0.25) + w2 / 2.0
+    }
+}
+
+/// Fitted smile of one expiry and how well it matches the quotes, as served by GET /ivSurface/fit
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct SurfaceFit {
+    pub asset: Asset,
+    pub expiry: String,          // Deribit expiry, e.g. "27DEC25"
+    pub expires: i64,            // Unix seconds
+    pub time_to_expiry: f64,     // Years, when fitted
+    pub forward: f64,            // USD; log-moneyness is taken against it
+    pub params: SviParams,
+    pub points: usize,           // Strikes fitted
+    pub rmse: f64,               // Root mean square IV error over the fitted strikes
+    pub max_error: f64,          // Largest absolute IV error
+    pub calendar_arbitrage_free: bool,  // Total variance nowhere below the previous expiry's
+    pub fitted_at: i64,          // Unix seconds
+}
+
+impl SurfaceFit {
+    fn total_variance(&self, strike: f64) -> f64 {
+        self.params.total_variance((strike / self.forward).ln())
+    }
+}
+
+// Log-moneyness range the density and calendar checks are made over
+fn check_grid(points: &[(f64, f64)]) -> impl Iterator<Item = f64> {
+    let lo = points.iter().map(|p| p.0).fold(f64::INFINITY, f64::min) - 1.0;
+    let hi = points.iter().map(|p| p.0).fold(f64::NEG_INFINITY, f64::max) + 1.0;
+    (0..=100).map(move |i| lo + (hi - lo) * i as f64 / 100.0)
+}
+
+// Best (a, d = rho b sigma, c = b sigma) for a fixed vertex `m` and curvature `sigma`: linear
+// least squares on the quasi-explicit SVI form, projected onto the arbitrage constraints
+fn fit_inner(points: &[(f64, f64)], m: f64, sigma: f64) -> SviParams {
+    // Normal equations of w = a + d y + c z
+    let mut ata = [[0.0; 3]; 3];
+    let mut atw = [0.0; 3];
+    for &(k, w) in points {
+        let y = (k - m) / sigma;
+        let row = [1.0, y, (y * y + 1.0).sqrt()];
+        for i in 0..3 {
+            for j in 0..3 {
+                ata[i][j] += row[i] * row[j];
+            }
+            atw[i] += row[i] * w;
+        }
+    }
+    let [_, d, c] = solve3(ata, atw).unwrap_or([0.0; 3]);
+
+    // 0 <= |d| <= c and c + |d| <= MAX_WING_SLOPE * sigma, then refit the level to them
+    let c = c.clamp(0.0, MAX_WING_SLOPE * sigma);
+    let d_max = c.min(MAX_WING_SLOPE * sigma - c);
+    let d = d.clamp(-d_max, d_max);
+    let level = points
+        .iter()
+        .map(|&(k, w)| {
+            let y = (k - m) / sigma;
+            w - d * y - c * (y * y + 1.0).sqrt()
+        })
+        .sum::<f64>()
+        / points.len() as f64;
+    // Total variance is lowest at a + sqrt(c^2 - d^2) and may not go negative
+    let a = level.max(-(c * c - d * d).max(0.0).sqrt());
+
+    let b = c / sigma;
+    SviParams { a, b, rho: if c > 0.0 { d / c } else { 0.0 }, m, sigma }
+}
+
+// Solve a 3x3 linear system with Cramer's rule; None when singular
+fn solve3(m: [[f64; 3]; 3], v: [f64; 3]) -> Option<[f64; 3]> {
+    let det = |m: &[[f64; 3]; 3]| {
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1]) - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    };
+    let d = det(&m);
+    if d.abs() < 1e-12 {
+        return None;
+    }
+    let mut x = [0.0; 3];
+    for (col, value) in x.iter_mut().enumerate() {
+        let mut mc = m;
+        for row in 0..3 {
+            mc[row][col] = v[row];
+        }
+        *value = det(&mc) / d;
+    }
+    Some(x)
+}
+
+fn squared_error(params: &SviParams, points: &[(f64, f64)]) -> f64 {
+    points.iter().map(|&(k, w)| (params.total_variance(k) - w).powi(2)).sum()
+}
+
+/// Fit a smile to `quotes` of (log-moneyness, IV) at `t` years to expiry. Returns the
+/// parameters with the RMSE and maximum absolute error in IV, or None with fewer than
+/// MIN_POINTS quotes or when no candidate is free of butterfly arbitrage.
+pub fn fit_smile(quotes: &[(f64, f64)], t: f64) -> Option<(SviParams, f64, f64)> {
+    if quotes.len() < MIN_POINTS || t <= 0.0 {
+        return None;
+    }
+    let points: Vec<(f64, f64)> = quotes.iter().map(|&(k, iv)| (k, iv * iv * t)).collect();
+    let k_min = points.iter().map(|p| p.0).fold(f64::INFINITY, f64::min);
+    let k_max = points.iter().map(|p| p.0).fold(f64::NEG_INFINITY, f64::max);
+    let arbitrage_free = |params: &SviParams| check_grid(&points).all(|k| params.density(k) >= 0.0);
+
+    // Coarse grid over the vertex and curvature, then zoom in around the best candidate
+    let mut best: Option<(f64, SviParams)> = None;
+    let consider = |m: f64, sigma: f64, best: &mut Option<(f64, SviParams)>| {
+        if sigma <= 0.0 {
+            return;
+        }
+        let params = fit_inner(&points, m, sigma);
+        let error = squared_error(&params, &points);
+        if best.as_ref().is_none_or(|(best_error, _)| error < *best_error) && arbitrage_free(&params) {
+            *best = Some((error, params));
+        }
+    };
+    let span = (k_max - k_min).max(0.01);
+    for i in 0..=20 {
+        for j in 0..=20 {
+            let m = k_min - 0.25 * span + 1.5 * span * i as f64 / 20.0;
+            let sigma = 0.005 * 400f64.powf(j as f64 / 20.0);  // 0.005 to 2
+            consider(m, sigma, &mut best);
+        }
+    }
+    let (mut m_step, mut sigma_factor) = (1.5 * span / 20.0, 400f64.powf(1.0 / 20.0));
+    for _ in 0..4 {
+        let (_, center) = best?;
+        for i in -2..=2 {
+            for j in -2..=2 {
+                let m = center.m + m_step * i as f64 / 2.0;
+                let sigma = center.sigma * sigma_factor.powf(j as f64 / 2.0);
+                consider(m, sigma, &mut best);
+            }
+        }
+        m_step /= 2.0;
+        sigma_factor = sigma_factor.sqrt();
+    }
+
+    let (_, params) = best?;
+    let errors: Vec<f64> = quotes
+        .iter()
+        .map(|&(k, iv)| ((params.total_variance(k).max(0.0) / t).sqrt() - iv).abs())
+        .collect();
+    let rmse = (errors.iter().map(|e| e * e).sum::<f64>() / errors.len() as f64).sqrt();
+    let max_error = errors.iter().copied().fold(0.0, f64::max);
+    Some((params, rmse, max_error))
+}
+
+/// Flag the fits, sorted by expiry, whose total variance dips below the previous expiry's
+/// anywhere over the quoted range
+pub fn check_calendar(fits: &mut [SurfaceFit]) {
+    fits.sort_by_key(|fit| fit.expires);
+    for i in 0..fits.len() {
+        fits[i].calendar_arbitrage_free = match i {
+            0 => true,
+            _ => {
+                let (previous, current) = (&fits[i - 1], &fits[i]);
+                let strikes = [0.5, 0.7, 0.8, 0.9, 1.0, 1.1, 1.25, 1.5, 2.0].map(|x| x * current.forward);
+                strikes.iter().all(|&strike| current.total_variance(strike) >= previous.total_variance(strike) - 1e-9)
+            }
+        };
+    }
+}
+
+/// IV at `strike` for an expiry `expires` (unix seconds) at `now`, from fits sorted by
+/// expiry. Total variance is floored at the previous expiry's and interpolated linearly in
+/// time between fitted expiries; before the first and after the last the IV is held flat.
+pub fn surface_iv(fits: &[SurfaceFit], strike: f64, expires: i64, now: i64) -> Option<f64> {
+    let t = year_fraction(expires, now);
+    if fits.is_empty() || t <= 0.0 || strike <= 0.0 {
+        return None;
+    }
+    // (years to expiry, total variance) of each slice that has not expired
+    let mut slices: Vec<(f64, f64)> = Vec::with_capacity(fits.len());
+    for fit in fits {
+        let slice_t = year_fraction(fit.expires, now);
+        if slice_t <= 0.0 {
+            continue;
+        }
+        let floor = slices.last().map_or(0.0, |&(_, w)| w);
+        slices.push((slice_t, fit.total_variance(strike).max(floor)));
+    }
+    let (first, last) = (slices.first()?, slices.last()?);
+    let w = if t <= first.0 {
+        first.1 * t / first.0
+    } else if t >= last.0 {
+        last.1 * t / last.0
+    } else {
+        let i = slices.iter().position(|&(slice_t, _)| slice_t >= t)?;
+        let ((t0, w0), (t1, w1)) = (slices[i - 1], slices[i]);
+        w0 + (w1 - w0) * (t - t0) / (t1 - t0)
+    };
+    (w > 0.0).then(|| (w / t).sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SMILE: SviParams = SviParams { a: 0.004, b: 0.05, rho: -0.3, m: 0.02, sigma: 0.15 };
+
+    fn fit(expiry: &str, expires: i64, params: SviParams) -> SurfaceFit {
+        SurfaceFit {
+            asset: Asset::Btc,
+            expiry: expiry.to_string(),
+            expires,
+            time_to_expiry: 0.0,
+            forward: 100_000.0,
+            params,
+            points: 10,
+            rmse: 0.0,
+            max_error: 0.0,
+            calendar_arbitrage_free: true,
+            fitted_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_fit_recovers_an_svi_smile() {
+        let t = 30.0 / 365.0;
+        let quotes: Vec<(f64, f64)> = (-8..=8)
+            .map(|i| {
+                let k = i as f64 * 0.04;
+                (k, (SMILE.total_variance(k) / t).sqrt())
+            })
+            .collect();
+        let (params, rmse, max_error) = fit_smile(&quotes, t).unwrap();
+        assert!(rmse < 0.002 && max_error < 0.005, "rmse {} max {}", rmse, max_error);
+        assert!(params.b >= 0.0 && params.rho.abs() < 1.0 && params.sigma > 0.0);
+        assert!(params.b * (1.0 + params.rho.abs()) <= MAX_WING_SLOPE + 1e-9);
+        assert!(check_grid(&quotes).all(|k| params.density(k) >= 0.0));
+
+        // Too few strikes to fit
+        assert!(fit_smile(&quotes[..4], t).is_none());
+    }
+
+    #[test]
+    fn test_fit_of_noisy_quotes_stays_arbitrage_free() {
+        let t = 7.0 / 365.0;
+        // A jagged smile with a dip no density could produce
+        let quotes = [(-0.2, 0.75), (-0.1, 0.62), (-0.05, 0.58), (0.0, 0.45), (0.05, 0.57), (0.1, 0.56), (0.2, 0.70)];
+        let (params, rmse, _) = fit_smile(&quotes, t).unwrap();
+        assert!(check_grid(&quotes).all(|k| params.density(k) >= 0.0));
+        assert!(rmse < 0.1);
+    }
+
+    #[test]
+    fn test_surface_is_interpolated_in_total_variance() {
+        let now = 1_800_000_000;
+        let day = 86_400;
+        let near = SviParams { a: 0.002, ..SMILE };
+        let far = SviParams { a: 0.02, ..SMILE };
+        let mut fits = vec![fit("far", now + 30 * day, far), fit("near", now + 7 * day, near)];
+        check_calendar(&mut fits);
+        assert_eq!(fits[0].expiry, "near");
+        assert!(fits.iter().all(|fit| fit.calendar_arbitrage_free));
+
+        // On a fitted expiry the smile itself is served, for any strike
+        let t_near = 7.0 / 365.0;
+        let iv = surface_iv(&fits, 100_000.0, now + 7 * day, now).unwrap();
+        assert!((iv - (near.total_variance(0.0) / t_near).sqrt()).abs() < 1e-9);
+        assert!(surface_iv(&fits, 123_456.0, now + 7 * day, now).is_some());
+
+        // In between, total variance is interpolated linearly in time
+        let w = |params: SviParams| params.total_variance(0.0);
+        let t = 14.0 / 365.0;
+        let expected = w(near) + (w(far) - w(near)) * (14.0 - 7.0) / (30.0 - 7.0);
+        let iv = surface_iv(&fits, 100_000.0, now + 14 * day, now).unwrap();
+        assert!((iv - (expected / t).sqrt()).abs() < 1e-9);
+        // Flat IV outside the fitted expiries
+        let short = surface_iv(&fits, 100_000.0, now + day, now).unwrap();
+        assert!((short - (w(near) / t_near).sqrt()).abs() < 1e-9);
+        assert_eq!(surface_iv(&fits, 100_000.0, now - day, now), None);
+
+        // A later expiry with less variance is flagged and floored when served
+        let mut fits = vec![fit("near", now + 7 * day, far), fit("far", now + 30 * day, near)];
+        check_calendar(&mut fits);
+        assert!(!fits[1].calendar_arbitrage_free);
+        let at_far = surface_iv(&fits, 100_000.0, now + 30 * day, now).unwrap();
+        assert!((at_far * at_far * 30.0 / 365.0 - w(far)).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,87 @@
+// Short-lived cache for computed responses such as the options table.
+// Entries expire after a TTL, are dropped early when any of the source versions
+// they were built from changes (e.g. an oracle refresh), and can be cleared
+// explicitly after writes.
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+struct Entry<V> {
+    value: Arc<V>,
+    source_versions: Vec<u64>,
+    created: Instant,
+}
+
+pub struct ResponseCache<V> {
+    ttl: Duration,
+    entries: RwLock<HashMap<String, Entry<V>>>,
+}
+
+impl<V> ResponseCache<V> {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Cached value, if still fresh for these source versions
+    pub fn get(&self, key: &str, source_versions: &[u64]) -> Option<Arc<V>> {
+        let entries = self.entries.read().unwrap();
+        let entry = entries.get(key)?;
+        if entry.created.elapsed() >= self.ttl || entry.source_versions != source_versions {
+            return None;
+        }
+        Some(entry.value.clone())
+    }
+
+    pub fn insert(&self, key: String, source_versions: Vec<u64>, value: V) -> Arc<V> {
+        let value = Arc::new(value);
+        let mut entries = self.entries.write().unwrap();
+        // Drop anything stale so per-request grid variations don't accumulate
+        entries.retain(|_, entry| entry.created.elapsed() < self.ttl && entry.source_versions == source_versions);
+        entries.insert(
+            key,
+            Entry {
+                value: value.clone(),
+                source_versions,
+                created: Instant::now(),
+            },
+        );
+        value
+    }
+
+    pub fn invalidate(&self) {
+        self.entries.write().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hit_until_sources_change() {
+        let cache = ResponseCache::new(Duration::from_secs(60));
+        cache.insert("grid".to_string(), vec![1, 1], vec![1, 2, 3]);
+
+        let value = cache.get("grid", &[1, 1]).unwrap();
+        assert_eq!(*value, vec![1, 2, 3]);
+
+        assert!(cache.get("grid", &[2, 1]).is_none());
+        assert!(cache.get("other", &[1, 1]).is_none());
+    }
+
+    #[test]
+    fn test_ttl_and_invalidate() {
+        let cache = ResponseCache::new(Duration::from_secs(0));
+        cache.insert("grid".to_string(), vec![], 42);
+        assert!(cache.get("grid", &[]).is_none());
+
+        let cache = ResponseCache::new(Duration::from_secs(60));
+        cache.insert("grid".to_string(), vec![], 42);
+        cache.invalidate();
+        assert!(cache.get("grid", &[]).is_none());
+    }
+}
@@ -0,0 +1,78 @@
+// Deadlines for calls to upstream services made while serving a request.
+// A hung price oracle or Mutiny API would otherwise hold an actix worker until the
+// connection dies. Each call, retries included, has to finish within its deadline or the
+// request fails with 504.
+
+use crate::error::ApiError;
+use std::env;
+use std::future::Future;
+use std::time::Duration;
+use tokio::time::timeout;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UpstreamTimeouts {
+    pub price_oracle: Duration,
+    pub wallet: Duration,
+    pub iv_oracle: Duration,  // A full refresh of the IV surface
+}
+
+impl Default for UpstreamTimeouts {
+    fn default() -> Self {
+        Self {
+            price_oracle: Duration::from_secs(5),
+            wallet: Duration::from_secs(15),
+            iv_oracle: Duration::from_secs(30),
+        }
+    }
+}
+
+impl UpstreamTimeouts {
+    /// PRICE_ORACLE_TIMEOUT_SECS (5), WALLET_TIMEOUT_SECS (15) and IV_ORACLE_TIMEOUT_SECS (30)
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let secs = |name: &str, default: Duration| {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .map(|secs: u64| Duration::from_secs(secs.max(1)))
+                .unwrap_or(default)
+        };
+        Self {
+            price_oracle: secs("PRICE_ORACLE_TIMEOUT_SECS", defaults.price_oracle),
+            wallet: secs("WALLET_TIMEOUT_SECS", defaults.wallet),
+            iv_oracle: secs("IV_ORACLE_TIMEOUT_SECS", defaults.iv_oracle),
+        }
+    }
+}
+
+/// Run `call` to `upstream`, failing with UpstreamTimeout once `limit` has passed
+pub async fn deadline<T>(
+    upstream: &str,
+    limit: Duration,
+    call: impl Future<Output = Result<T, ApiError>>,
+) -> Result<T, ApiError> {
+    timeout(limit, call).await.unwrap_or_else(|_| {
+        Err(ApiError::UpstreamTimeout(format!(
+            "{} did not respond within {}s",
+            upstream,
+            limit.as_secs_f64()
+        )))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_deadline() {
+        let fast = deadline("wallet", Duration::from_millis(50), async { Ok(1) }).await;
+        assert_eq!(fast.unwrap(), 1);
+
+        let hung = deadline("wallet", Duration::from_millis(10), std::future::pending::<Result<(), ApiError>>()).await;
+        match hung {
+            Err(ApiError::UpstreamTimeout(msg)) => assert_eq!(msg, "wallet did not respond within 0.01s"),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,351 @@
+// Venue-wide trading state.
+// Operators can move the venue to ReduceOnly (no new risk, closing positions still allowed)
+// or Halted (no trading at all). The state is stored in the trading_state table so it
+// survives restarts, and every change is written to the audit log and published as a
+// trading_state.changed event, plus a risk.alert when a monitor restricts trading.
+// The oracle monitor moves an Open venue to ReduceOnly while price health checks keep
+// failing, and reopens it once they pass again unless someone else changed the state since.
+
+use crate::audit;
+use crate::outbox;
+use crate::error::ApiError;
+use crate::models::Asset;
+use crate::price_guards::PriceGuards;
+use crate::repository::Repository;
+use crate::sources::PriceSource;
+use crate::supervisor::Supervisor;
+use crate::webhooks::{RISK_ALERT, TRADING_STATE_CHANGED};
+use chrono::Utc;
+use rusqlite::types::{FromSql, FromSqlError, ToSql, ToSqlOutput, ValueRef};
+use rusqlite::{params, Connection, OptionalExtension, Result};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::interval;
+
+// Actor recorded for transitions made by the oracle monitor
+pub const ORACLE_MONITOR_ACTOR: &str = "system:oracle-monitor";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TradingState {
+    #[default]
+    Open,
+    ReduceOnly,  // Only trades that reduce the pool's risk
+    Halted,
+}
+
+impl TradingState {
+    /// Reject trades that add risk unless the venue is open
+    pub fn check_open_position(self) -> std::result::Result<(), ApiError> {
+        match self {
+            TradingState::Open => Ok(()),
+            TradingState::ReduceOnly => Err(ApiError::TradingHalted(
+                "trading is reduce-only, new positions are not accepted".to_string(),
+            )),
+            TradingState::Halted => Err(ApiError::TradingHalted("trading is halted".to_string())),
+        }
+    }
+
+    /// Reject trades that reduce risk only when the venue is halted
+    pub fn check_reduce_position(self) -> std::result::Result<(), ApiError> {
+        match self {
+            TradingState::Halted => Err(ApiError::TradingHalted("trading is halted".to_string())),
+            TradingState::Open | TradingState::ReduceOnly => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for TradingState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TradingState::Open => write!(f, "open"),
+            TradingState::ReduceOnly => write!(f, "reduce_only"),
+            TradingState::Halted => write!(f, "halted"),
+        }
+    }
+}
+
+impl std::str::FromStr for TradingState {
+    type Err = FromSqlError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "open" => Ok(TradingState::Open),
+            "reduce_only" => Ok(TradingState::ReduceOnly),
+            "halted" => Ok(TradingState::Halted),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl ToSql for TradingState {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.to_string().into())
+    }
+}
+
+impl FromSql for TradingState {
+    fn column_result(value: ValueRef<'_>) -> std::result::Result<Self, FromSqlError> {
+        value.as_str()?.parse()
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct TradingStatus {
+    pub state: TradingState,
+    pub reason: Option<String>,
+    pub updated_by: Option<String>,  // None until the state is first changed
+    pub updated_at: Option<i64>,
+    pub automatic: bool,             // Set by a monitor rather than an operator
+}
+
+impl Default for TradingStatus {
+    fn default() -> Self {
+        Self {
+            state: TradingState::Open,
+            reason: None,
+            updated_by: None,
+            updated_at: None,
+            automatic: false,
+        }
+    }
+}
+
+/// Current trading state (Open when it has never been set)
+pub fn load(conn: &Connection) -> Result<TradingStatus> {
+    Ok(conn
+        .query_row(
+            "SELECT state, reason, updated_by, updated_at, automatic FROM trading_state WHERE id = 1",
+            [],
+            |row| {
+                Ok(TradingStatus {
+                    state: row.get(0)?,
+                    reason: row.get(1)?,
+                    updated_by: row.get(2)?,
+                    updated_at: row.get(3)?,
+                    automatic: row.get(4)?,
+                })
+            },
+        )
+        .optional()?
+        .unwrap_or_default())
+}
+
+/// Change the trading state, recording the change in the audit log and the outbox. Automatic
+/// changes away from Open also raise a risk alert.
+pub fn set(
+    conn: &Connection,
+    state: TradingState,
+    reason: Option<&str>,
+    actor: &str,
+    automatic: bool,
+) -> Result<TradingStatus> {
+    let tx = conn.unchecked_transaction()?;
+    let previous = load(&tx)?;
+    let now = Utc::now().timestamp();
+    tx.execute(
+        "INSERT OR REPLACE INTO trading_state (id, state, reason, updated_by, updated_at, automatic)
+         VALUES (1, ?1, ?2, ?3, ?4, ?5)",
+        params![state, reason, actor, now, automatic],
+    )?;
+    let current = load(&tx)?;
+    audit::record(
+        &tx,
+        actor,
+        audit::TRADING_STATE_CHANGE,
+        None,
+        serde_json::to_value(&previous).ok().as_ref(),
+        serde_json::to_value(&current).ok().as_ref(),
+    )?;
+    outbox::record(&tx, TRADING_STATE_CHANGED, None, &serde_json::json!({"trading": current, "previous": previous}))?;
+    if automatic && state != TradingState::Open {
+        outbox::record(&tx, RISK_ALERT, None, &serde_json::json!({"trading": current, "reason": reason}))?;
+    }
+    tx.commit()?;
+    Ok(current)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OracleMonitorConfig {
+    pub check_interval: Duration,
+    pub failure_threshold: u32,  // Consecutive failed checks before going reduce-only
+    pub auto_resume: bool,       // Reopen once checks pass, if the monitor closed the venue
+}
+
+impl Default for OracleMonitorConfig {
+    fn default() -> Self {
+        Self {
+            check_interval: Duration::from_secs(15),
+            failure_threshold: 3,
+            auto_resume: true,
+        }
+    }
+}
+
+impl OracleMonitorConfig {
+    /// ORACLE_CHECK_INTERVAL_SECS (15), ORACLE_FAILURE_THRESHOLD (3) and ORACLE_AUTO_RESUME (true)
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            check_interval: env::var("ORACLE_CHECK_INTERVAL_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .map(|secs: u64| Duration::from_secs(secs.max(1)))
+                .unwrap_or(defaults.check_interval),
+            failure_threshold: env::var("ORACLE_FAILURE_THRESHOLD")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.failure_threshold)
+                .max(1),
+            auto_resume: env::var("ORACLE_AUTO_RESUME")
+                .map(|v| v != "false" && v != "0")
+                .unwrap_or(defaults.auto_resume),
+        }
+    }
+}
+
+/// The transition the oracle monitor should make, if any, given the current status and
+/// the number of consecutive failed checks (0 when the last check passed). The venue is
+/// only reopened if the oracle monitor itself closed it.
+pub fn automatic_transition(
+    current: &TradingStatus,
+    consecutive_failures: u32,
+    config: &OracleMonitorConfig,
+) -> Option<TradingState> {
+    if consecutive_failures >= config.failure_threshold && current.state == TradingState::Open {
+        return Some(TradingState::ReduceOnly);
+    }
+    if consecutive_failures == 0
+        && config.auto_resume
+        && current.automatic
+        && current.updated_by.as_deref() == Some(ORACLE_MONITOR_ACTOR)
+        && current.state == TradingState::ReduceOnly
+    {
+        return Some(TradingState::Open);
+    }
+    None
+}
+
+/// Fetch the price of every underlying and apply the trade-time price guards to it
+pub async fn check_oracle_health(
+    price_source: &dyn PriceSource,
+    guards: &PriceGuards,
+    assets: &[Asset],
+) -> std::result::Result<(), String> {
+    for &asset in assets {
+        let quote = price_source
+            .get_asset_price_quote(asset)
+            .await
+            .map_err(|e| format!("{} price unavailable: {}", asset, e))?;
+        guards.check(asset, &quote).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+pub fn start_oracle_monitor(
+    supervisor: &Supervisor,
+    repository: Repository,
+    price_source: Arc<dyn PriceSource>,
+    guards: PriceGuards,
+    assets: Vec<Asset>,
+    config: OracleMonitorConfig,
+) {
+    supervisor.spawn("oracle_monitor", move || {
+        let (repository, price_source) = (repository.clone(), price_source.clone());
+        let (guards, assets) = (guards.clone(), assets.clone());
+        async move {
+            let mut ticker = interval(config.check_interval);
+            let mut consecutive_failures = 0u32;
+            loop {
+                ticker.tick().await;
+                let health = check_oracle_health(price_source.as_ref(), &guards, &assets).await;
+                let reason = match &health {
+                    Ok(()) => {
+                        consecutive_failures = 0;
+                        "oracle health checks passing again".to_string()
+                    }
+                    Err(e) => {
+                        consecutive_failures += 1;
+                        format!("oracle health check failed {} times: {}", consecutive_failures, e)
+                    }
+                };
+
+                let result = repository
+                    .run(move |conn| {
+                        let current = load(conn)?;
+                        match automatic_transition(&current, consecutive_failures, &config) {
+                            Some(state) => Ok(Some(set(conn, state, Some(&reason), ORACLE_MONITOR_ACTOR, true)?)),
+                            None => Ok(None),
+                        }
+                    })
+                    .await;
+                match result {
+                    Ok(Some(status)) => println!("🚦 Trading state is now {} ({})", status.state, status.reason.unwrap_or_default()),
+                    Ok(None) => {}
+                    Err(e) => eprintln!("Error updating trading state: {}", e),
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trading_state_is_persisted_and_audited() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init_db(&conn).unwrap();
+
+        assert_eq!(load(&conn).unwrap(), TradingStatus::default());
+        let status = set(&conn, TradingState::Halted, Some("exchange outage"), "ops", false).unwrap();
+        assert_eq!(status.state, TradingState::Halted);
+        assert_eq!(load(&conn).unwrap(), status);
+
+        let entries = audit::query(&conn, &audit::AuditFilter::default()).unwrap();
+        assert_eq!(entries[0].action, audit::TRADING_STATE_CHANGE);
+        assert_eq!(entries[0].pre_state.as_ref().unwrap()["state"], "open");
+        assert_eq!(entries[0].post_state.as_ref().unwrap()["state"], "halted");
+    }
+
+    #[test]
+    fn test_trading_state_checks() {
+        assert!(TradingState::Open.check_open_position().is_ok());
+        assert!(TradingState::ReduceOnly.check_open_position().is_err());
+        assert!(TradingState::ReduceOnly.check_reduce_position().is_ok());
+        assert!(TradingState::Halted.check_reduce_position().is_err());
+    }
+
+    #[test]
+    fn test_automatic_transitions() {
+        let config = OracleMonitorConfig::default();
+        let open = TradingStatus::default();
+        assert_eq!(automatic_transition(&open, 2, &config), None);
+        assert_eq!(automatic_transition(&open, 3, &config), Some(TradingState::ReduceOnly));
+
+        let auto_reduce_only = TradingStatus {
+            state: TradingState::ReduceOnly,
+            updated_by: Some(ORACLE_MONITOR_ACTOR.to_string()),
+            automatic: true,
+            ..Default::default()
+        };
+        assert_eq!(automatic_transition(&auto_reduce_only, 0, &config), Some(TradingState::Open));
+        assert_eq!(automatic_transition(&auto_reduce_only, 5, &config), None);
+
+        // Operator decisions are never undone by the monitor
+        let manual_reduce_only = TradingStatus { state: TradingState::ReduceOnly, ..Default::default() };
+        assert_eq!(automatic_transition(&manual_reduce_only, 0, &config), None);
+        let halted = TradingStatus { state: TradingState::Halted, ..Default::default() };
+        assert_eq!(automatic_transition(&halted, 10, &config), None);
+        // Nor are those of the utilization monitor
+        let utilization_reduce_only = TradingStatus {
+            updated_by: Some(crate::utilization::UTILIZATION_MONITOR_ACTOR.to_string()),
+            ..auto_reduce_only
+        };
+        assert_eq!(automatic_transition(&utilization_reduce_only, 0, &config), None);
+    }
+}
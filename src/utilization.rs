@@ -0,0 +1,188 @@
+// Collateral utilization monitor.
+// Utilization is the margin required by the open books of all pools as a share of the
+// collateral the pools hold. Once it reaches UTILIZATION_REDUCE_ONLY_PERCENT the monitor moves
+// an Open venue to ReduceOnly, so only trades that reduce risk are taken, which publishes a
+// trading_state.changed event and a risk.alert. It reopens the venue once utilization falls back under
+// UTILIZATION_RESUME_PERCENT, unless someone else changed the state since.
+
+use crate::api::AppState;
+use crate::error::ApiError;
+use crate::supervisor::Supervisor;
+use crate::trading_state::{self, TradingState, TradingStatus};
+use serde::Serialize;
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::interval;
+
+// Actor recorded for transitions made by the utilization monitor
+pub const UTILIZATION_MONITOR_ACTOR: &str = "system:utilization-monitor";
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct Utilization {
+    pub margin_required_usd: f64,   // Margin of the open books of all pools
+    pub total_collateral_usd: f64,  // Balance of all pools at their collateral rates
+    pub utilization_percent: f64,   // margin_required_usd as % of total_collateral_usd
+}
+
+impl Utilization {
+    /// Utilization of `total_collateral_usd` by `margin_required_usd`. Margin held against
+    /// no collateral at all counts as fully utilized.
+    pub fn new(margin_required_usd: f64, total_collateral_usd: f64) -> Self {
+        let utilization_percent = if total_collateral_usd > 0.0 {
+            margin_required_usd / total_collateral_usd * 100.0
+        } else if margin_required_usd > 0.0 {
+            100.0
+        } else {
+            0.0
+        };
+        Self { margin_required_usd, total_collateral_usd, utilization_percent }
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct UtilizationMonitorConfig {
+    #[serde(skip)]
+    pub check_interval: Duration,
+    pub reduce_only_percent: f64,  // Utilization at which the venue goes reduce-only
+    pub resume_percent: f64,       // Utilization under which the monitor reopens it
+}
+
+impl Default for UtilizationMonitorConfig {
+    fn default() -> Self {
+        Self {
+            check_interval: Duration::from_secs(30),
+            reduce_only_percent: 90.0,
+            resume_percent: 80.0,
+        }
+    }
+}
+
+impl UtilizationMonitorConfig {
+    /// UTILIZATION_REDUCE_ONLY_PERCENT (90, 0 = no monitor), UTILIZATION_RESUME_PERCENT (80,
+    /// at most the reduce-only threshold) and UTILIZATION_CHECK_INTERVAL_SECS (30).
+    /// None when the monitor is off.
+    pub fn from_env() -> Option<Self> {
+        let defaults = Self::default();
+        let reduce_only_percent: f64 = env::var("UTILIZATION_REDUCE_ONLY_PERCENT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(defaults.reduce_only_percent);
+        if reduce_only_percent.is_nan() || reduce_only_percent <= 0.0 {
+            return None;
+        }
+        Some(Self {
+            check_interval: env::var("UTILIZATION_CHECK_INTERVAL_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .map(|secs: u64| Duration::from_secs(secs.max(1)))
+                .unwrap_or(defaults.check_interval),
+            reduce_only_percent,
+            resume_percent: env::var("UTILIZATION_RESUME_PERCENT")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.resume_percent)
+                .min(reduce_only_percent),
+        })
+    }
+}
+
+/// The transition the utilization monitor should make, if any. The venue is only reopened
+/// if the monitor itself closed it, so operator and oracle monitor decisions stand.
+pub fn utilization_transition(
+    current: &TradingStatus,
+    utilization: &Utilization,
+    config: &UtilizationMonitorConfig,
+) -> Option<TradingState> {
+    if utilization.utilization_percent >= config.reduce_only_percent && current.state == TradingState::Open {
+        return Some(TradingState::ReduceOnly);
+    }
+    if utilization.utilization_percent < config.resume_percent
+        && current.state == TradingState::ReduceOnly
+        && current.automatic
+        && current.updated_by.as_deref() == Some(UTILIZATION_MONITOR_ACTOR)
+    {
+        return Some(TradingState::Open);
+    }
+    None
+}
+
+/// Measure utilization once and move the venue in or out of reduce-only accordingly.
+/// Returns the new status when the state changed.
+pub async fn check_utilization(
+    state: &AppState,
+    config: &UtilizationMonitorConfig,
+) -> Result<Option<TradingStatus>, ApiError> {
+    let utilization = state.collateral_utilization().await?;
+    let config = *config;
+    let changed = state
+        .repository()
+        .run(move |conn| {
+            let current = trading_state::load(conn)?;
+            let Some(next) = utilization_transition(&current, &utilization, &config) else {
+                return Ok(None);
+            };
+            let reason = match next {
+                TradingState::ReduceOnly => format!(
+                    "collateral utilization {:.1}% reached {:.1}%",
+                    utilization.utilization_percent, config.reduce_only_percent
+                ),
+                _ => format!(
+                    "collateral utilization {:.1}% back under {:.1}%",
+                    utilization.utilization_percent, config.resume_percent
+                ),
+            };
+            Ok(Some(trading_state::set(conn, next, Some(&reason), UTILIZATION_MONITOR_ACTOR, true)?))
+        })
+        .await?;
+    Ok(changed)
+}
+
+pub fn start_utilization_monitor(supervisor: &Supervisor, state: Arc<AppState>, config: UtilizationMonitorConfig) {
+    supervisor.spawn("utilization_monitor", move || {
+        let state = state.clone();
+        async move {
+            let mut ticker = interval(config.check_interval);
+            loop {
+                ticker.tick().await;
+                match check_utilization(&state, &config).await {
+                    Ok(Some(status)) => println!("🚦 Trading state is now {} ({})", status.state, status.reason.unwrap_or_default()),
+                    Ok(None) => {}
+                    Err(e) => eprintln!("Error checking collateral utilization: {}", e),
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utilization_transitions() {
+        let config = UtilizationMonitorConfig::default();
+        let open = TradingStatus::default();
+        assert_eq!(utilization_transition(&open, &Utilization::new(89.0, 100.0), &config), None);
+        assert_eq!(utilization_transition(&open, &Utilization::new(90.0, 100.0), &config), Some(TradingState::ReduceOnly));
+        // Margin without collateral is full utilization
+        assert_eq!(Utilization::new(1.0, 0.0).utilization_percent, 100.0);
+        assert_eq!(Utilization::new(0.0, 0.0).utilization_percent, 0.0);
+
+        // Reopened only once utilization is back under the resume threshold
+        let reduce_only = TradingStatus {
+            state: TradingState::ReduceOnly,
+            updated_by: Some(UTILIZATION_MONITOR_ACTOR.to_string()),
+            automatic: true,
+            ..Default::default()
+        };
+        assert_eq!(utilization_transition(&reduce_only, &Utilization::new(85.0, 100.0), &config), None);
+        assert_eq!(utilization_transition(&reduce_only, &Utilization::new(79.0, 100.0), &config), Some(TradingState::Open));
+
+        // Reduce-only set by an operator or the oracle monitor is left alone
+        let manual = TradingStatus { updated_by: Some("ops".to_string()), automatic: false, ..reduce_only.clone() };
+        assert_eq!(utilization_transition(&manual, &Utilization::new(0.0, 100.0), &config), None);
+        let oracle = TradingStatus { updated_by: Some(trading_state::ORACLE_MONITOR_ACTOR.to_string()), ..reduce_only };
+        assert_eq!(utilization_transition(&oracle, &Utilization::new(0.0, 100.0), &config), None);
+    }
+}
@@ -1,4 +1,5 @@
-use chrono::Utc;
+use crate::day_count;
+use chrono::{DateTime, Duration, NaiveTime, Utc};
 
 // Constants for floating point precision
 pub const BTC_PRECISION: u32 = 8;
@@ -14,6 +15,28 @@ pub fn cents_to_usd(cents: i64) -> f64 {
     cents as f64 / 100.0
 }
 
+// Satoshis per BTC. Quantities and BTC premiums are stored as integer satoshis
+// (1e-8 units of the underlying for quantities on other assets).
+pub const SATS_PER_BTC: i64 = 100_000_000;
+
+// Convert a BTC amount to satoshis
+pub fn btc_to_sats(btc: f64) -> i64 {
+    (btc * SATS_PER_BTC as f64).round() as i64
+}
+
+// Convert satoshis back to BTC
+pub fn sats_to_btc(sats: i64) -> f64 {
+    sats as f64 / SATS_PER_BTC as f64
+}
+
+// Format satoshis as an exact 8 decimal BTC string for JSON
+pub fn format_sats(sats: i64) -> String {
+    let sign = if sats < 0 { "-" } else { "" };
+    let abs = sats.unsigned_abs();
+    let per_btc = SATS_PER_BTC as u64;
+    format!("{}{}.{:08}", sign, abs / per_btc, abs % per_btc)
+}
+
 // Convert float to string with specified precision for database storage
 pub fn float_to_db_string(value: f64, precision: u32) -> String {
     format!("{:.prec$}", value, prec = precision as usize)
@@ -54,19 +77,76 @@ pub fn format_expires_timestamp(expires: i64) -> String {
     }
 }
 
-// Helper function to parse duration strings (e.g., "30m", "1d") into a year fraction.
+// Seconds in a 365 day calendar year
+pub const SECONDS_PER_YEAR: f64 = 365.0 * 24.0 * 60.0 * 60.0;
+
+// Deribit lists its daily, weekly and monthly expiries at 08:00 UTC
+pub const DEFAULT_EXPIRY_HOUR_UTC: u32 = 8;
+
+// Time from `now` to `expires` as a year fraction under the DAY_COUNT convention, to the
+// second, for Black-Scholes. Negative once expired, which pricing treats as at expiry.
+pub fn year_fraction(expires: i64, now: i64) -> f64 {
+    day_count::deployment().year_fraction(expires, now)
+}
+
+// Expiry timestamp of a tenor (e.g. "30m", "12h", "7d") quoted at `now`.
+// Day tenors snap to the first `expiry_hour` UTC at least the tenor away, like exchange
+// expiries. Intraday tenors expire intraday, rounded up to the next whole minute or hour.
+pub fn tenor_expiry(tenor: &str, now: i64, expiry_hour: u32) -> Option<i64> {
+    let seconds = duration_to_seconds(tenor);
+    if seconds <= 0 {
+        return None;
+    }
+    let target = now + seconds;
+    match tenor.trim().chars().last() {
+        Some('m') => Some(target + (60 - target.rem_euclid(60)) % 60),
+        Some('h') => Some(target + (3600 - target.rem_euclid(3600)) % 3600),
+        _ => {
+            let target = DateTime::from_timestamp(target, 0)?;
+            let expiry_time = NaiveTime::from_hms_opt(expiry_hour, 0, 0)?;
+            let same_day = target.date_naive().and_time(expiry_time).and_utc();
+            let expiry = match same_day >= target {
+                true => same_day,
+                false => same_day + Duration::days(1),
+            };
+            Some(expiry.timestamp())
+        }
+    }
+}
+
+// Helper function to parse duration strings (e.g., "30m", "1d") into a nominal year fraction
+// in days of the DAY_COUNT convention. Prices use year_fraction up to the actual expiry
+// instead, see tenor_expiry.
 pub fn parse_duration(duration: &str) -> f64 {
     let d = duration.trim();
     let (num_str, unit) = d.split_at(d.len() - 1);
     let num: f64 = num_str.parse().unwrap_or(0.0);
+    let days_per_year = day_count::deployment().days_per_year();
     match unit {
-        "m" => num / (365.0 * 24.0 * 60.0),
-        "h" => num / (365.0 * 24.0),
-        "d" => num / 365.0,
+        "m" => num / (days_per_year * 24.0 * 60.0),
+        "h" => num / (days_per_year * 24.0),
+        "d" => num / days_per_year,
         _ => 0.0,
     }
 }
 
+// Helper function to convert duration strings (e.g., "30m", "1d") to seconds
+pub fn duration_to_seconds(duration: &str) -> i64 {
+    let d = duration.trim();
+    if d.is_empty() {
+        return 0;
+    }
+    let (num_str, unit) = d.split_at(d.len() - 1);
+    let num: i64 = num_str.parse().unwrap_or(0);
+    
+    match unit {
+        "m" => num * 60,           // minutes to seconds
+        "h" => num * 60 * 60,      // hours to seconds  
+        "d" => num * 24 * 60 * 60, // days to seconds
+        _ => 0,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -86,6 +166,16 @@ mod tests {
         assert_eq!(cents_to_usd(12345), 123.45);
     }
 
+    #[test]
+    fn test_satoshi_conversions() {
+        assert_eq!(btc_to_sats(0.12345678), 12_345_678);
+        assert_eq!(btc_to_sats(0.1 + 0.2), 30_000_000);
+        assert_eq!(sats_to_btc(150_000_000), 1.5);
+        assert_eq!(format_sats(12_345_678), "0.12345678");
+        assert_eq!(format_sats(250_000_000), "2.50000000");
+        assert_eq!(format_sats(-1), "-0.00000001");
+    }
+
     #[test]
     fn test_float_to_db_string() {
         assert_eq!(float_to_db_string(0.12345678, 8), "0.12345678");
@@ -146,4 +236,34 @@ mod tests {
         assert_eq!(parse_duration("7d"), 7.0 / 365.0);
         assert_eq!(parse_duration("invalid"), 0.0);
     }
+
+    #[test]
+    fn test_tenor_expiry() {
+        // 2025-01-01 07:00:30 UTC
+        let now = 1_735_714_830;
+        let eight = 1_735_718_400;  // 2025-01-01 08:00 UTC
+        let day = 86_400;
+        assert_eq!(tenor_expiry("1d", now, 8), Some(eight + day));
+        assert_eq!(tenor_expiry("7d", now, 8), Some(eight + 7 * day));
+        // Past the expiry hour the expiry rolls to the next day
+        assert_eq!(tenor_expiry("1d", now + 2 * 3600, 8), Some(eight + 2 * day));
+        // Intraday tenors round up to their unit
+        assert_eq!(tenor_expiry("30m", now, 8), Some(now - 30 + 31 * 60));
+        assert_eq!(tenor_expiry("2h", now, 8), Some(eight + 2 * 3600));
+        assert_eq!(tenor_expiry("bogus", now, 8), None);
+
+        // A 1d tenor quoted at 07:00:30 runs a day and an hour, less 30 seconds
+        let t = year_fraction(eight + day, now);
+        assert!((t * 365.0 - (day + 3600 - 30) as f64 / day as f64).abs() < 1e-12);
+        assert!(year_fraction(now - 60, now) < 0.0);
+    }
+
+    #[test]
+    fn test_duration_to_seconds() {
+        assert_eq!(duration_to_seconds("30m"), 1800);
+        assert_eq!(duration_to_seconds("2h"), 7200);
+        assert_eq!(duration_to_seconds("7d"), 604800);
+        assert_eq!(duration_to_seconds("invalid"), 0);
+        assert_eq!(duration_to_seconds(""), 0);
+    }
 }
\ No newline at end of file
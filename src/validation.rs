@@ -0,0 +1,120 @@
+// Validation of trade request fields shared by POST /contract, POST /orderbook/take, FIX
+// orders and closes.
+// Quantities are stored as integer 1e-8 units of the underlying, so a quantity that is not a
+// whole number of those units would be silently rounded, and one under half a unit would be
+// stored as zero. Both are rejected here, as are dust trades under the minimum trade size.
+
+use crate::error::{ApiError, ApiResult, ErrorCode};
+use crate::position_limits::PositionLimits;
+use crate::utils::{format_sats, SATS_PER_BTC};
+use serde_json::json;
+
+// Distance from a whole satoshi still taken as float noise, e.g. 0.1 + 0.2
+const SATS_TOLERANCE: f64 = 1e-3;
+
+/// Satoshis of `quantity`, or an INVALID_QUANTITY / SUB_SATOSHI_QUANTITY error
+pub fn quantity_sats(quantity: f64) -> ApiResult<i64> {
+    if !quantity.is_finite() || quantity <= 0.0 {
+        return Err(ApiError::ValidationError(format!("Quantity must be positive, got {}", quantity))
+            .with_code(ErrorCode::InvalidQuantity)
+            .with_details(json!({"quantity": quantity})));
+    }
+    let sats = quantity * SATS_PER_BTC as f64;
+    if (sats - sats.round()).abs() > SATS_TOLERANCE {
+        return Err(ApiError::ValidationError(format!(
+            "Quantity {} is not a whole number of satoshis (1e-8 units)",
+            quantity
+        ))
+        .with_code(ErrorCode::SubSatoshiQuantity)
+        .with_details(json!({"quantity": quantity, "step": "0.00000001"})));
+    }
+    Ok(sats.round() as i64)
+}
+
+/// Satoshis of `quantity` if it is a valid size for a new contract under `limits`
+pub fn trade_quantity_sats(quantity: f64, limits: &PositionLimits) -> ApiResult<i64> {
+    let sats = quantity_sats(quantity)?;
+    if sats < limits.min_contract_quantity_sats() {
+        return Err(ApiError::ValidationError(format!(
+            "Quantity {} is below the minimum trade size of {}",
+            format_sats(sats),
+            format_sats(limits.min_contract_quantity_sats())
+        ))
+        .with_code(ErrorCode::QuantityBelowMinimum)
+        .with_details(json!({"quantity": format_sats(sats), "min_quantity": format_sats(limits.min_contract_quantity_sats())})));
+    }
+    if quantity > limits.max_contract_quantity {
+        return Err(ApiError::ValidationError(format!(
+            "Quantity {} is above the maximum trade size of {}",
+            format_sats(sats),
+            limits.max_contract_quantity
+        ))
+        .with_code(ErrorCode::QuantityAboveMaximum)
+        .with_details(json!({"quantity": format_sats(sats), "max_quantity": limits.max_contract_quantity})));
+    }
+    Ok(sats)
+}
+
+/// Satoshis of `quantity` if it may be closed out of `open_sats`. Below the minimum trade
+/// size only the whole open quantity may be closed, so no dust position is left behind.
+pub fn close_quantity_sats(quantity: f64, open_sats: i64, limits: &PositionLimits) -> ApiResult<i64> {
+    let sats = quantity_sats(quantity)?;
+    let min_sats = limits.min_contract_quantity_sats();
+    let remaining = open_sats - sats;
+    if sats < open_sats && (sats < min_sats || remaining < min_sats) {
+        return Err(ApiError::ValidationError(format!(
+            "Closing {} of {} would trade or leave less than the minimum trade size of {}; close all of it instead",
+            format_sats(sats),
+            format_sats(open_sats),
+            format_sats(min_sats)
+        ))
+        .with_code(ErrorCode::QuantityBelowMinimum)
+        .with_details(json!({
+            "quantity": format_sats(sats),
+            "open_quantity": format_sats(open_sats),
+            "min_quantity": format_sats(min_sats)
+        })));
+    }
+    Ok(sats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sub_satoshi_and_non_positive_quantities_are_rejected() {
+        assert_eq!(quantity_sats(0.1 + 0.2).unwrap(), 30_000_000);
+        assert_eq!(quantity_sats(1000.00000001).unwrap(), 100_000_000_001);
+        assert_eq!(quantity_sats(0.000000001).unwrap_err().code(), ErrorCode::SubSatoshiQuantity);
+        assert_eq!(quantity_sats(0.123456789).unwrap_err().code(), ErrorCode::SubSatoshiQuantity);
+        for quantity in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert_eq!(quantity_sats(quantity).unwrap_err().code(), ErrorCode::InvalidQuantity);
+        }
+    }
+
+    #[test]
+    fn test_trade_size_limits() {
+        let limits = PositionLimits { min_contract_quantity: 0.001, max_contract_quantity: 5.0, ..Default::default() };
+
+        assert_eq!(trade_quantity_sats(0.001, &limits).unwrap(), 100_000);
+        assert_eq!(trade_quantity_sats(5.0, &limits).unwrap(), 500_000_000);
+        let err = trade_quantity_sats(0.00099999, &limits).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::QuantityBelowMinimum);
+        assert_eq!(err.details()["min_quantity"], "0.00100000");
+        let err = trade_quantity_sats(5.00000001, &limits).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::QuantityAboveMaximum);
+        assert_eq!(err.details()["max_quantity"], 5.0);
+    }
+
+    #[test]
+    fn test_closes_may_not_leave_dust() {
+        let limits = PositionLimits { min_contract_quantity: 0.001, ..Default::default() };
+
+        assert_eq!(close_quantity_sats(0.5, 100_000_000, &limits).unwrap(), 50_000_000);
+        // The whole open quantity may be closed even when it is under the minimum
+        assert_eq!(close_quantity_sats(0.0005, 50_000, &limits).unwrap(), 50_000);
+        assert_eq!(close_quantity_sats(0.0005, 100_000_000, &limits).unwrap_err().code(), ErrorCode::QuantityBelowMinimum);
+        assert_eq!(close_quantity_sats(0.9995, 100_000_000, &limits).unwrap_err().code(), ErrorCode::QuantityBelowMinimum);
+    }
+}
@@ -0,0 +1,203 @@
+use crate::models::Asset;
+use crate::utils::{cents_to_usd, duration_to_seconds, SECONDS_PER_YEAR};
+use chrono::Utc;
+use rusqlite::{params, Connection, Result};
+use serde::Serialize;
+
+// Spot samples are bucketed into hourly bars before computing realized vol
+pub const BAR_SECONDS: i64 = 60 * 60;
+
+// Rolling windows reported by GET /realizedVol
+pub const REALIZED_VOL_WINDOWS: [&str; 3] = ["1d", "7d", "30d"];
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpotSample {
+    pub timestamp: i64, // seconds
+    pub price: f64,     // USD
+}
+
+// OHLC bar built from the spot samples falling into one bucket
+#[derive(Debug, Clone, Copy)]
+struct Bar {
+    timestamp: i64, // bucket start in seconds
+    high: f64,
+    low: f64,
+    close: f64,
+    sample_count: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RealizedVol {
+    pub window: String,
+    pub close_to_close: Option<f64>, // Annualized, decimal (0.45 = 45%)
+    pub parkinson: Option<f64>,      // Annualized, decimal (0.45 = 45%)
+    pub sample_count: usize,
+    pub bar_count: usize,
+}
+
+/// Load the spot samples of `asset` recorded at or after `since` in price_history, ordered by time
+pub fn load_spot_samples(conn: &Connection, asset: Asset, since: i64) -> Result<Vec<SpotSample>> {
+    let mut stmt = conn.prepare(
+        "SELECT timestamp, price_cents FROM price_history WHERE asset = ?1 AND timestamp >= ?2 ORDER BY timestamp ASC"
+    )?;
+    let samples = stmt
+        .query_map(params![asset, since], |row| {
+            Ok(SpotSample {
+                timestamp: row.get(0)?,
+                price: cents_to_usd(row.get(1)?),
+            })
+        })?
+        .collect::<Result<Vec<_>>>()?;
+    Ok(samples)
+}
+
+fn build_bars(samples: &[SpotSample], bar_seconds: i64) -> Vec<Bar> {
+    let mut bars: Vec<Bar> = Vec::new();
+
+    for sample in samples.iter().filter(|s| s.price > 0.0) {
+        let bucket = sample.timestamp - sample.timestamp.rem_euclid(bar_seconds);
+        match bars.last_mut() {
+            Some(bar) if bar.timestamp == bucket => {
+                bar.high = bar.high.max(sample.price);
+                bar.low = bar.low.min(sample.price);
+                bar.close = sample.price;
+                bar.sample_count += 1;
+            }
+            _ => bars.push(Bar {
+                timestamp: bucket,
+                high: sample.price,
+                low: sample.price,
+                close: sample.price,
+                sample_count: 1,
+            }),
+        }
+    }
+
+    bars
+}
+
+/// Close-to-close realized volatility, annualized.
+/// Uses the sum of squared log returns between consecutive bar closes divided by
+/// the elapsed time, so gaps in the sample history don't inflate the estimate.
+pub fn close_to_close_vol(samples: &[SpotSample], bar_seconds: i64) -> Option<f64> {
+    let bars = build_bars(samples, bar_seconds);
+    if bars.len() < 2 {
+        return None;
+    }
+
+    let mut sum_sq_returns = 0.0;
+    let mut elapsed_seconds = 0.0;
+    for pair in bars.windows(2) {
+        let log_return = (pair[1].close / pair[0].close).ln();
+        sum_sq_returns += log_return * log_return;
+        elapsed_seconds += (pair[1].timestamp - pair[0].timestamp) as f64;
+    }
+
+    if elapsed_seconds <= 0.0 {
+        return None;
+    }
+
+    Some((sum_sq_returns / elapsed_seconds * SECONDS_PER_YEAR).sqrt())
+}
+
+/// Parkinson high-low realized volatility, annualized.
+/// Only bars with at least two samples carry range information.
+pub fn parkinson_vol(samples: &[SpotSample], bar_seconds: i64) -> Option<f64> {
+    let ranges: Vec<f64> = build_bars(samples, bar_seconds)
+        .iter()
+        .filter(|bar| bar.sample_count >= 2)
+        .map(|bar| (bar.high / bar.low).ln().powi(2))
+        .collect();
+
+    if ranges.is_empty() {
+        return None;
+    }
+
+    let bar_variance = ranges.iter().sum::<f64>() / (ranges.len() as f64 * 4.0 * 2f64.ln());
+    let bars_per_year = SECONDS_PER_YEAR / bar_seconds as f64;
+    Some((bar_variance * bars_per_year).sqrt())
+}
+
+/// Compute realized volatility of `asset` over a rolling window (e.g. "1d", "7d", "30d") ending now
+pub fn realized_vol(conn: &Connection, asset: Asset, window: &str) -> Result<RealizedVol> {
+    let since = Utc::now().timestamp() - duration_to_seconds(window);
+    let samples = load_spot_samples(conn, asset, since)?;
+
+    Ok(RealizedVol {
+        window: window.to_string(),
+        close_to_close: close_to_close_vol(&samples, BAR_SECONDS),
+        parkinson: parkinson_vol(&samples, BAR_SECONDS),
+        sample_count: samples.len(),
+        bar_count: build_bars(&samples, BAR_SECONDS).len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(timestamp: i64, price: f64) -> SpotSample {
+        SpotSample { timestamp, price }
+    }
+
+    #[test]
+    fn test_constant_price_has_zero_vol() {
+        let samples: Vec<SpotSample> = (0..48)
+            .map(|i| sample(i * 1800, 100000.0))
+            .collect();
+        assert_eq!(close_to_close_vol(&samples, BAR_SECONDS), Some(0.0));
+        assert_eq!(parkinson_vol(&samples, BAR_SECONDS), Some(0.0));
+    }
+
+    #[test]
+    fn test_close_to_close_vol_annualization() {
+        // Alternate +1% / -1% hourly log returns
+        let mut price = 100000.0;
+        let mut samples = vec![sample(0, price)];
+        for i in 1..=24 {
+            let r: f64 = if i % 2 == 0 { 0.01 } else { -0.01 };
+            price *= r.exp();
+            samples.push(sample(i * BAR_SECONDS, price));
+        }
+
+        let vol = close_to_close_vol(&samples, BAR_SECONDS).unwrap();
+        let expected = (0.01f64.powi(2) * 24.0 * 365.0).sqrt();
+        assert!((vol - expected).abs() < 1e-9, "got {}, expected {}", vol, expected);
+    }
+
+    #[test]
+    fn test_insufficient_samples() {
+        let samples = vec![sample(0, 100000.0)];
+        assert_eq!(close_to_close_vol(&samples, BAR_SECONDS), None);
+        assert_eq!(parkinson_vol(&samples, BAR_SECONDS), None);
+    }
+
+    #[test]
+    fn test_parkinson_vol_uses_bar_range() {
+        // Each hourly bar trades between 99,000 and 101,000
+        let mut samples = Vec::new();
+        for i in 0..24 {
+            samples.push(sample(i * BAR_SECONDS, 99000.0));
+            samples.push(sample(i * BAR_SECONDS + 1800, 101000.0));
+        }
+
+        let vol = parkinson_vol(&samples, BAR_SECONDS).unwrap();
+        let bar_variance = (101000.0f64 / 99000.0).ln().powi(2) / (4.0 * 2f64.ln());
+        let expected = (bar_variance * 24.0 * 365.0).sqrt();
+        assert!((vol - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_spot_history_round_trip() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init_db(&conn).unwrap();
+
+        crate::price_history::record(&conn, Asset::Btc, 100000.12, 1000).unwrap();
+        crate::price_history::record(&conn, Asset::Btc, 100500.00, 2000).unwrap();
+        crate::price_history::record(&conn, Asset::Eth, 3500.00, 2000).unwrap();
+
+        let samples = load_spot_samples(&conn, Asset::Btc, 1500).unwrap();
+        assert_eq!(samples, vec![sample(2000, 100500.0)]);
+    }
+}
@@ -0,0 +1,107 @@
+// Outbound event notifications.
+// Events are POSTed as JSON to every URL in WEBHOOK_URLS. Delivery is best effort;
+// callers that must not lose an event retry until publish succeeds, as the outbox poller does.
+
+use crate::sources::SourceError;
+use async_trait::async_trait;
+use chrono::Utc;
+use serde::Serialize;
+use serde_json::Value;
+use std::env;
+use std::time::Duration;
+
+pub const CONTRACT_EXPIRING_SOON: &str = "contract.expiring_soon";
+pub const CONTRACT_EXERCISED: &str = "contract.exercised";
+pub const TRADING_STATE_CHANGED: &str = "trading_state.changed";
+pub const TRADE_CREATED: &str = "trade.created";
+pub const CONTRACT_SETTLED: &str = "contract.settled";
+pub const RISK_ALERT: &str = "risk.alert";
+pub const MARKS_SNAPSHOT: &str = "marks.snapshot";
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct WebhookEvent {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,  // Outbox id of events written with the change they describe
+    pub event: String,
+    pub timestamp: i64,
+    pub data: Value,
+}
+
+impl WebhookEvent {
+    pub fn new(event: &str, data: Value) -> Self {
+        Self {
+            id: None,
+            event: event.to_string(),
+            timestamp: Utc::now().timestamp(),
+            data,
+        }
+    }
+}
+
+/// Destination for events. Succeeds only once every receiver has accepted the event.
+#[async_trait]
+pub trait EventSink: Send + Sync {
+    async fn publish(&self, event: &WebhookEvent) -> Result<(), SourceError>;
+}
+
+pub struct WebhookSink {
+    urls: Vec<String>,
+    client: reqwest::Client,
+}
+
+impl WebhookSink {
+    pub fn new(urls: Vec<String>, timeout: Duration) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .unwrap_or_default();
+        Self { urls, client }
+    }
+
+    /// WEBHOOK_URLS (comma separated) with WEBHOOK_TIMEOUT_SECS per request.
+    /// None when no URL is configured.
+    pub fn from_env() -> Option<Self> {
+        let urls: Vec<String> = env::var("WEBHOOK_URLS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|url| url.trim().to_string())
+            .filter(|url| !url.is_empty())
+            .collect();
+        if urls.is_empty() {
+            return None;
+        }
+        let timeout_secs: u64 = env::var("WEBHOOK_TIMEOUT_SECS")
+            .unwrap_or_else(|_| "5".to_string())
+            .parse()
+            .unwrap_or(5);
+        Some(Self::new(urls, Duration::from_secs(timeout_secs)))
+    }
+
+    pub fn urls(&self) -> &[String] {
+        &self.urls
+    }
+}
+
+#[async_trait]
+impl EventSink for WebhookSink {
+    async fn publish(&self, event: &WebhookEvent) -> Result<(), SourceError> {
+        let mut failures = Vec::new();
+        for url in &self.urls {
+            let result = self
+                .client
+                .post(url)
+                .json(event)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(e) = result {
+                failures.push(format!("{}: {}", url, e));
+            }
+        }
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("webhook delivery failed ({})", failures.join("; ")).into())
+        }
+    }
+}
@@ -78,7 +78,7 @@ api_call() {
 echo -e "\n${BLUE}Step 1: Server Health Check${NC}"
 if ! curl -s "$BASE_URL/health" > /dev/null 2>&1; then
     echo -e "${RED}❌ Server is not running on $BASE_URL${NC}"
-    echo "Please start the server with: cargo run --bin btc_options_api"
+    echo "Please start the server with: AUTH_DISABLED=true cargo run --bin btc_options_api"
     exit 1
 fi
 
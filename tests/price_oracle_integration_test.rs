@@ -1,4 +1,5 @@
 #[cfg(test)]
+#[allow(unused_imports, clippy::manual_is_multiple_of)]
 mod price_oracle_integration_tests {
     use btc_options_api::price_oracle::{PriceOracle, oracle::{GetPriceResponse, PriceDataPoint}};
     use std::collections::HashMap;
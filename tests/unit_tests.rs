@@ -1,4 +1,5 @@
 #[cfg(test)]
+#[allow(clippy::manual_range_contains, clippy::assertions_on_constants)]
 mod tests {
     use btc_options_api::iv_oracle::IvOracle;
     use btc_options_api::utils::{format_expires_timestamp, parse_duration};
@@ -253,6 +254,7 @@ mod tests {
 }
 
 #[cfg(test)]
+#[allow(clippy::assertions_on_constants, clippy::len_zero, clippy::manual_is_multiple_of)]
 mod integration_tests {
     use btc_options_api::mutiny_wallet::{MutinyWallet, Network};
     use btc_options_api::price_oracle::PriceOracle;
@@ -434,24 +436,2807 @@ mod integration_tests {
 
 #[cfg(test)]
 mod endpoint_tests {
-    // Endpoint tests are better suited for integration tests
-    // They require full application setup with all external dependencies:
-    // - gRPC price oracle running on localhost:50051
-    // - Mutiny wallet with valid Bitcoin address
-    // - Deribit API for implied volatility
-    //
-    // Consider using docker-compose for full integration testing
-    
+    // In-process handler tests: the API is mounted with actix_web::test against an
+    // in-memory database and fake price, IV and wallet sources, so no external
+    // services are needed.
+    use actix_web::{middleware, test, web, App};
+    use async_trait::async_trait;
+    use btc_options_api::api::{self, AppState};
+    use btc_options_api::{api_v1, api_v2};
+    use btc_options_api::quote_sanity::SanityMode;
+    use btc_options_api::rates::RateCurve;
+    use btc_options_api::api_keys;
+    use btc_options_api::attestation::{self, OracleSigner};
+    use btc_options_api::basis::{BasisMode, BasisSource};
+    use btc_options_api::auth::{self, AuthConfig, JwtConfig, Role};
+    use btc_options_api::catalog::{self, CatalogConfig};
+    use btc_options_api::db;
+    use btc_options_api::dlc;
+    use btc_options_api::fees::FeeSchedule;
+    use btc_options_api::iv_history;
+    use btc_options_api::iv_policy::{DefaultIvMode, DefaultIvPolicy, IvResolver};
+    use btc_options_api::kyc::{KycConfig, KycStatus};
+    use btc_options_api::lightning::{Invoice, InvoiceState, LightningError, LightningNode};
+    use btc_options_api::models::{Asset, Contract, OptionSide};
+    use btc_options_api::mutiny_wallet::{MutinyWalletError, Network, Transaction, WalletBalance};
+    use btc_options_api::options_grid::GridConfig;
+    use btc_options_api::outbox;
+    use btc_options_api::reservations::ReservedMargin;
+    use btc_options_api::payments::{self, PaymentConfig};
+    use btc_options_api::pools::{self, NewPool};
+    use btc_options_api::position_limits::PositionLimits;
+    use btc_options_api::price_history;
+    use btc_options_api::pricing;
+    use btc_options_api::quoting::QuotingConfig;
+    use btc_options_api::repository::Repository;
+    use btc_options_api::request_id;
+    use btc_options_api::settlement::{self, SettlementConfig};
+    use btc_options_api::stats;
+    use btc_options_api::supervisor::{Supervisor, SupervisorConfig};
+    use btc_options_api::timeouts::UpstreamTimeouts;
+    use btc_options_api::trading_state::TradingState;
+    use btc_options_api::utilization::{self, UtilizationMonitorConfig};
+    use btc_options_api::webhooks::{EventSink, WebhookEvent};
+    use btc_options_api::sources::{IvSource, PriceQuote, PriceSource, SourceError, WalletSource};
+    use chrono::Utc;
+    use serde_json::Value;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    const BTC_PRICE: f64 = 100_000.0;
+    const ETH_PRICE: f64 = 3_500.0;
+    // Endpoint tests run without credentials, as in local development; auth tests build their
+    // state without it
+    const DEV_AUTH: AuthConfig = AuthConfig { disabled: true };
+
+    // BTC at the given price, ETH at ETH_PRICE
+    struct FakePrice(f64);
+
+    #[async_trait]
+    impl PriceSource for FakePrice {
+        async fn get_btc_price(&self) -> Result<f64, SourceError> {
+            Ok(self.0)
+        }
+
+        async fn get_price(&self, asset: Asset) -> Result<f64, SourceError> {
+            match asset {
+                Asset::Btc => Ok(self.0),
+                Asset::Eth => Ok(ETH_PRICE),
+            }
+        }
+
+        fn version(&self) -> u64 {
+            0
+        }
+    }
+
+    // Price last observed ten minutes ago
+    struct OldPrice(f64);
+
+    #[async_trait]
+    impl PriceSource for OldPrice {
+        async fn get_btc_price(&self) -> Result<f64, SourceError> {
+            Ok(self.0)
+        }
+
+        fn version(&self) -> u64 {
+            0
+        }
+
+        async fn get_price_quote(&self) -> Result<PriceQuote, SourceError> {
+            Ok(PriceQuote {
+                price: self.0,
+                age: Duration::from_secs(600),
+                data_points: 3,
+                rejected_points: 0,
+                previous_price: None,
+                stale: false,
+            })
+        }
+    }
+
+    // Flat surface so every strike and expiry prices at the same IV
+    struct FakeIv(f64);
+
+    impl IvSource for FakeIv {
+        fn get_iv(&self, _side: &str, _strike_price: f64, _expire: &str) -> Option<f64> {
+            Some(self.0)
+        }
+
+        fn get_asset_iv(&self, _asset: Asset, _side: &str, _strike_price: f64, _expire: &str) -> Option<f64> {
+            Some(self.0)
+        }
+
+        fn cache_size(&self) -> usize {
+            1
+        }
+
+        fn version(&self) -> u64 {
+            0
+        }
+    }
+
+    // Reports a fixed balance, or a network error when `None`
+    struct FakeWallet(Option<u64>);
+
+    #[async_trait]
+    impl WalletSource for FakeWallet {
+        async fn get_wallet_balance(&self, address: &str) -> Result<WalletBalance, MutinyWalletError> {
+            let sats = self
+                .0
+                .ok_or_else(|| MutinyWalletError::NetworkError("wallet unreachable".to_string()))?;
+            Ok(WalletBalance {
+                address: address.to_string(),
+                confirmed_balance: sats,
+                unconfirmed_balance: 0,
+                total_balance: sats,
+                confirmed_utxo_count: 1,
+                unconfirmed_utxo_count: 0,
+                total_utxo_count: 1,
+            })
+        }
+    }
+
+    fn test_state(wallet_sats: Option<u64>) -> Arc<AppState> {
+        test_state_with_pool(db::create_in_memory_pool().unwrap(), wallet_sats)
+    }
+
+    fn test_state_with_pool(pool: db::DbPool, wallet_sats: Option<u64>) -> Arc<AppState> {
+        Arc::new(AppState::new(
+            Repository::new(pool),
+            Arc::new(FakeIv(0.5)),
+            Arc::new(FakePrice(BTC_PRICE)),
+            Arc::new(FakeWallet(wallet_sats)),
+            "test-pool-address".to_string(),
+            GridConfig::default(),
+            Duration::from_secs(5),
+        ).with_auth(DEV_AUTH))
+    }
+
+    macro_rules! test_app {
+        ($state:expr) => {
+            test::init_service(
+                App::new()
+                    .app_data(web::Data::new($state.clone()))
+                    .wrap(middleware::from_fn(auth::middleware))
+                    .wrap(middleware::from_fn(api_v1::middleware))
+                    .wrap(middleware::from_fn(request_id::middleware))
+                    .service(web::scope(api_v1::PREFIX).configure(api::configure))
+                    .service(web::scope(api_v2::PREFIX).wrap(middleware::from_fn(api_v2::middleware)).configure(api::configure))
+                    .configure(api::configure),
+            )
+            .await
+        };
+    }
+
+    fn contract(side: OptionSide, strike_price: f64, quantity: f64, expires_in_secs: i64) -> Contract {
+        Contract {
+            underlying: Asset::Btc,
+            side,
+            strike_price,
+            quantity,
+            expires: Utc::now().timestamp() + expires_in_secs,
+            premium: 0.01,
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_health_check() {
+        let state = test_state(Some(100_000_000));
+        let app = test_app!(state);
+
+        let body: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri("/health").to_request()).await;
+        assert_eq!(body["status"], "healthy");
+        assert_eq!(body["price_stale"], false);
+        assert_eq!(body["dependencies"]["database"]["status"], "ok");
+        assert_eq!(body["dependencies"]["price_oracle"]["data_points"], 1);
+        assert_eq!(body["dependencies"]["iv_oracle"]["cache_size"], 1);
+        assert_eq!(body["dependencies"]["wallet"]["status"], "ok");
+    }
+
+    #[actix_web::test]
+    async fn test_health_check_reports_failing_dependencies() {
+        // The wallet is not critical: degraded, still in rotation
+        let app = test_app!(test_state(None));
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/health").to_request()).await;
+        assert_eq!(resp.status(), 200);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["status"], "degraded");
+        assert_eq!(body["dependencies"]["wallet"]["status"], "down");
+        assert!(body["dependencies"]["wallet"]["error"].as_str().unwrap().contains("wallet unreachable"));
+
+        // Without a price nothing can be quoted: unhealthy, out of rotation
+        struct NoPrice;
+
+        #[async_trait]
+        impl PriceSource for NoPrice {
+            async fn get_btc_price(&self) -> Result<f64, SourceError> {
+                Err("all price feeds down".into())
+            }
+
+            fn version(&self) -> u64 {
+                0
+            }
+        }
+
+        let state = Arc::new(AppState::new(
+            Repository::new(db::create_in_memory_pool().unwrap()),
+            Arc::new(FakeIv(0.5)),
+            Arc::new(NoPrice),
+            Arc::new(FakeWallet(Some(100_000_000))),
+            "test-pool-address".to_string(),
+            GridConfig::default(),
+            Duration::from_secs(5),
+        ).with_auth(DEV_AUTH));
+        let app = test_app!(state);
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/health").to_request()).await;
+        assert_eq!(resp.status(), 503);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["status"], "unhealthy");
+        assert_eq!(body["dependencies"]["price_oracle"]["status"], "down");
+        assert_eq!(body["dependencies"]["database"]["status"], "ok");
+    }
+
+    #[actix_web::test]
+    async fn test_oracle_prices_per_source() {
+        use btc_options_api::price_feeds::{FallbackConfig, FallbackPriceSource};
+        use btc_options_api::price_oracle::OutlierFilter;
+        use btc_options_api::sources::FixedPriceSource;
+
+        struct DownFeed;
+
+        #[async_trait]
+        impl PriceSource for DownFeed {
+            async fn get_btc_price(&self) -> Result<f64, SourceError> {
+                Err("ticker unreachable".into())
+            }
+
+            fn version(&self) -> u64 {
+                0
+            }
+        }
+
+        let feed = |name: &str, source: Arc<dyn PriceSource>| (name.to_string(), source);
+        let config = FallbackConfig {
+            exchanges: vec![],
+            breaker_failures: 3,
+            breaker_cooldown: Duration::from_secs(30),
+            feed_timeout: Duration::from_secs(1),
+            aggregator_timeout: Duration::from_secs(1),
+            cache_duration: Duration::from_secs(10),
+            outlier_filter: OutlierFilter::default(),
+        };
+        let price_source = FallbackPriceSource::new(
+            None,
+            vec![
+                feed("coinbase", Arc::new(FixedPriceSource::new(100_000.0))),
+                feed("kraken", Arc::new(FixedPriceSource::new(100_200.0))),
+                feed("binance", Arc::new(DownFeed)),
+            ],
+            &config,
+        );
+        let state = Arc::new(AppState::new(
+            Repository::new(db::create_in_memory_pool().unwrap()),
+            Arc::new(FakeIv(0.5)),
+            Arc::new(price_source),
+            Arc::new(FakeWallet(Some(100_000_000))),
+            "test-pool-address".to_string(),
+            GridConfig::default(),
+            Duration::from_secs(5),
+        ).with_auth(DEV_AUTH));
+        let app = test_app!(state);
+
+        let body: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri("/oracle/prices").to_request()).await;
+        assert_eq!(body["asset"], "BTC");
+        assert_eq!(body["price"], 100_100.0);
+        let statuses: Vec<(&str, &str)> = body["sources"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|source| (source["source"].as_str().unwrap(), source["status"].as_str().unwrap()))
+            .collect();
+        assert_eq!(statuses, vec![("coinbase", "ok"), ("kraken", "ok"), ("binance", "down")]);
+        assert_eq!(body["sources"][2]["error"], "ticker unreachable");
+        assert_eq!(body["sources"][0]["via"], "rest");
+
+        // Only the named feeds, priced at their median
+        let uri = "/oracle/prices?sources=Kraken,binance";
+        let body: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri(uri).to_request()).await;
+        assert_eq!(body["price"], 100_200.0);
+        assert_eq!(body["sources"].as_array().unwrap().len(), 2);
+
+        let body: Value =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/oracle/prices?sources=binance").to_request()).await;
+        assert!(body["price"].is_null());
+        assert!(body["error"].as_str().unwrap().contains("no BTC price from binance"), "{}", body);
+
+        let body: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri("/health").to_request()).await;
+        assert_eq!(body["dependencies"]["price_oracle"]["sources"].as_array().unwrap().len(), 3);
+    }
+
+    #[actix_web::test]
+    async fn test_hung_wallet_times_out() {
+        struct HungWallet;
+
+        #[async_trait]
+        impl WalletSource for HungWallet {
+            async fn get_wallet_balance(&self, _address: &str) -> Result<WalletBalance, MutinyWalletError> {
+                std::future::pending().await
+            }
+        }
+
+        let state = Arc::new(
+            AppState::new(
+                Repository::new(db::create_in_memory_pool().unwrap()),
+                Arc::new(FakeIv(0.5)),
+                Arc::new(FakePrice(BTC_PRICE)),
+                Arc::new(HungWallet),
+                "test-pool-address".to_string(),
+                GridConfig::default(),
+                Duration::from_secs(5),
+            )
+            .with_auth(DEV_AUTH)
+            .with_upstream_timeouts(UpstreamTimeouts { wallet: Duration::from_millis(50), ..UpstreamTimeouts::default() }),
+        );
+        let app = test_app!(state);
+        let expires = Utc::now().timestamp() + 86400;
+        let uri = format!("/maxQuantity?side=Call&strike=105000&expires={}&premium=0.01", expires);
+        let resp = test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(resp.status(), 504);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"], "Gateway timeout");
+        assert!(body["message"].as_str().unwrap().contains("Mutiny wallet did not respond within 0.05s"));
+    }
+
+    #[actix_web::test]
+    async fn test_health_check_reports_restarting_tasks() {
+        let supervisor = Supervisor::new(SupervisorConfig {
+            initial_backoff: Duration::from_secs(60),
+            max_backoff: Duration::from_secs(60),
+        });
+        supervisor.spawn("payment_watcher", || async { panic!("wallet response malformed") });
+        supervisor.spawn("stats_aggregation", std::future::pending);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let state = Arc::new(
+            AppState::new(
+                Repository::new(db::create_in_memory_pool().unwrap()),
+                Arc::new(FakeIv(0.5)),
+                Arc::new(FakePrice(BTC_PRICE)),
+                Arc::new(FakeWallet(Some(100_000_000))),
+                "test-pool-address".to_string(),
+                GridConfig::default(),
+                Duration::from_secs(5),
+            )
+            .with_auth(DEV_AUTH)
+            .with_supervisor(supervisor),
+        );
+        let app = test_app!(state);
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/health").to_request()).await;
+        assert_eq!(resp.status(), 200);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["status"], "degraded");
+        let tasks = body["tasks"].as_array().unwrap();
+        assert_eq!(tasks.len(), 2);
+        assert_eq!(tasks[0]["name"], "payment_watcher");
+        assert_eq!(tasks[0]["state"], "restarting");
+        assert_eq!(tasks[0]["restarts"], 1);
+        assert_eq!(tasks[0]["last_failure"], "panicked: wallet response malformed");
+        assert_eq!(tasks[1]["state"], "running");
+    }
+
+    #[actix_web::test]
+    async fn test_post_contract_accepts_and_lists() {
+        let state = test_state(Some(100_000_000));
+        let app = test_app!(state);
+
+        let req = test::TestRequest::post()
+            .uri("/contract")
+            .set_json(contract(OptionSide::Call, 105_000.0, 0.01, 86_400))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200);
+        // The risk check comes back with the contract
+        let created: Value = test::read_body_json(resp).await;
+        assert_eq!(created["contract_id"], 1);
+        assert_eq!(created["pool_id"], 1);
+        assert_eq!(created["spot_price"], BTC_PRICE);
+        assert_eq!(created["btc_price"], BTC_PRICE);
+        assert_eq!(created["iv"], 0.5);
+        assert!(created["iv_source"].is_string());
+        let margin = created["margin_consumed_usd"].as_f64().unwrap();
+        let remaining = created["remaining_collateral_usd"].as_f64().unwrap();
+        let utilization = created["pool_utilization_percent"].as_f64().unwrap();
+        assert!(margin > 0.0);
+        assert!((utilization - margin / (margin + remaining) * 100.0).abs() < 1e-9);
+
+        // A second trade consumes more of what the first left
+        let req = test::TestRequest::post()
+            .uri("/contract")
+            .set_json(contract(OptionSide::Call, 105_000.0, 0.01, 86_400))
+            .to_request();
+        let second: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(second["contract_id"], 2);
+        assert!(second["remaining_collateral_usd"].as_f64().unwrap() < remaining);
+        assert!(second["pool_utilization_percent"].as_f64().unwrap() > utilization);
+
+        let contracts: Vec<Value> =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/contracts").to_request()).await;
+        assert_eq!(contracts.len(), 2);
+        assert_eq!(contracts[0]["side"], "Call");
+        assert_eq!(contracts[0]["strike_price"], 105_000.0);
+        assert_eq!(contracts[0]["quantity"], "0.01000000");
+        // Expires within the default 24h notice window
+        assert_eq!(contracts[0]["expiring_soon"], true);
+    }
+
+    #[actix_web::test]
+    async fn test_post_contract_rejects_expired_contract() {
+        let state = test_state(Some(100_000_000));
+        let app = test_app!(state);
+
+        let req = test::TestRequest::post()
+            .uri("/contract")
+            .set_json(contract(OptionSide::Put, 95_000.0, 0.01, -60))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400);
+        let body: Value = test::read_body_json(resp).await;
+        assert!(body["message"].as_str().unwrap().contains("must be in the future"));
+        assert_eq!(body["error_code"], "INVALID_EXPIRY");
+        assert!(body["details"]["expires"].as_i64().unwrap() < body["details"]["now"].as_i64().unwrap());
+    }
+
+    #[actix_web::test]
+    async fn test_post_contract_normalizes_strikes_to_the_tick() {
+        let state = test_state(Some(100_000_000));
+        let app = test_app!(state);
+
+        let req = test::TestRequest::post()
+            .uri("/contract")
+            .set_json(contract(OptionSide::Put, 95_050.0, 0.01, 86_400))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["error_code"], "INVALID_STRIKE");
+        assert_eq!(body["details"]["tick_size"], 100.0);
+
+        // Float noise is dropped, so both trades are on one product
+        for strike in [95_000.004999, 94_999.99] {
+            let req = test::TestRequest::post()
+                .uri("/contract")
+                .set_json(contract(OptionSide::Put, strike, 0.01, 86_400))
+                .to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), 200);
+        }
+        for id in [1, 2] {
+            let detail: Value =
+                test::call_and_read_body_json(&app, test::TestRequest::get().uri(&format!("/contract/{}", id)).to_request()).await;
+            assert_eq!(detail["strike_price"], 95_000.0);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_post_contract_rejects_sub_satoshi_and_dust_quantities() {
+        let state = test_state(Some(100_000_000));
+        let app = test_app!(state);
+
+        for (quantity, error_code) in [
+            (0.000000001, "SUB_SATOSHI_QUANTITY"),
+            (0.010000001, "SUB_SATOSHI_QUANTITY"),
+            (0.000005, "QUANTITY_BELOW_MINIMUM"),
+            (1000.5, "QUANTITY_ABOVE_MAXIMUM"),
+            (0.0, "INVALID_QUANTITY"),
+        ] {
+            let req = test::TestRequest::post()
+                .uri("/contract")
+                .set_json(contract(OptionSide::Call, 105_000.0, quantity, 86_400))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), 400, "{}", quantity);
+            let body: Value = test::read_body_json(resp).await;
+            assert_eq!(body["error_code"], error_code, "{}", quantity);
+        }
+        let contracts: Vec<Value> =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/contracts").to_request()).await;
+        assert!(contracts.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_amend_contract_rechecks_collateral() {
+        let state = test_state(Some(100_000_000));
+        let app = test_app!(state);
+
+        let put = contract(OptionSide::Put, 95_000.0, 0.1, 86_400);
+        let req = test::TestRequest::post().uri("/contract").set_json(&put).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+
+        let expires = put.expires + 6 * 86_400;
+        let req = test::TestRequest::patch()
+            .uri("/contract/1")
+            .set_json(serde_json::json!({"expires": expires, "quantity": 0.2, "premium": 0.02}))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["expires"], expires);
+        assert_eq!(body["quantity"], 0.2);
+        assert_eq!(body["premium"], 0.02);
+        assert_eq!(body["additional_premium"], "0.00300000");
+
+        // Amendments only extend a contract, and must fit the pool's collateral
+        for (amendment, error_code) in [
+            (serde_json::json!({"quantity": 0.1}), "INVALID_QUANTITY"),
+            (serde_json::json!({"expires": put.expires}), "INVALID_EXPIRY"),
+            (serde_json::json!({"quantity": 10.0}), "INSUFFICIENT_COLLATERAL"),
+            (serde_json::json!({}), "VALIDATION_ERROR"),
+        ] {
+            let req = test::TestRequest::patch().uri("/contract/1").set_json(&amendment).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), 400, "{}", amendment);
+            let body: Value = test::read_body_json(resp).await;
+            assert_eq!(body["error_code"], error_code, "{}", amendment);
+        }
+        let req = test::TestRequest::patch().uri("/contract/9").set_json(serde_json::json!({"quantity": 1.0})).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 404);
+
+        let req = test::TestRequest::get().uri("/admin/audit?action=contract.amend").to_request();
+        let entries: Vec<Value> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["pre_state"]["quantity"], 0.1);
+        assert_eq!(entries[0]["post_state"]["quantity"], 0.2);
+        assert_eq!(entries[0]["post_state"]["expires"], expires);
+    }
+
+    #[actix_web::test]
+    async fn test_contracts_are_margined_against_their_own_pool() {
+        let db_pool = db::create_in_memory_pool().unwrap();
+        let mainnet = NewPool {
+            name: "mainnet".to_string(),
+            address: "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4".to_string(),
+            network: Network::Mainnet,
+            collateral_rate: 0.5,
+            risk_margin: 1.2,
+        };
+        pools::add_pool(&db_pool.get().unwrap(), &mainnet, "ops", Utc::now().timestamp()).unwrap();
+        let state = Arc::new(
+            AppState::new(
+                Repository::new(db_pool),
+                Arc::new(FakeIv(0.5)),
+                Arc::new(FakePrice(BTC_PRICE)),
+                Arc::new(FakeWallet(Some(100_000_000))),
+                "test-pool-address".to_string(),
+                GridConfig::default(),
+                Duration::from_secs(5),
+            )
+            .with_auth(DEV_AUTH)
+            .with_network_wallets(HashMap::from([(
+                Network::Mainnet,
+                Arc::new(FakeWallet(Some(1_000_000_000))) as Arc<dyn WalletSource>,
+            )])),
+        );
+        let app = test_app!(state);
+
+        // 1 BTC in the default pool cannot back this put, 10 BTC in the mainnet pool can
+        let put = contract(OptionSide::Put, 95_000.0, 1.0, 86_400);
+        let req = test::TestRequest::post().uri("/contract").set_json(&put).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["error_code"], "INSUFFICIENT_COLLATERAL");
+
+        let req = test::TestRequest::post().uri("/pools/2/contract").set_json(&put).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200);
+        let req = test::TestRequest::get().uri("/contract/1").to_request();
+        let body: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["pool_id"], 2);
+
+        let req = test::TestRequest::get().uri("/pools").to_request();
+        let pools: Vec<Value> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(pools.iter().map(|p| p["name"].as_str().unwrap()).collect::<Vec<_>>(), ["default", "mainnet"]);
+
+        let req = test::TestRequest::get().uri("/pools/2").to_request();
+        let body: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["network"], "mainnet");
+        assert_eq!(body["balance_btc"], 10.0);
+        assert_eq!(body["active_contracts"], 1);
+        assert_eq!(body["contracts_24hr"], 1);
+        assert!(body["margin_used_usd"].as_f64().unwrap() > 0.0);
+
+        // The default pool's book and capacity are untouched by the mainnet pool's sale
+        let req = test::TestRequest::get().uri("/pools/1").to_request();
+        let body: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["active_contracts"], 0);
+        assert_eq!(body["margin_used_usd"], 0.0);
+
+        let req = test::TestRequest::get().uri("/pools/9").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 404);
+    }
+
+    #[actix_web::test]
+    async fn test_request_id_is_echoed() {
+        let state = test_state(Some(100_000_000));
+        let app = test_app!(state);
+
+        // A caller's ID is kept, in the header and in error bodies
+        let req = test::TestRequest::post()
+            .uri("/contract")
+            .insert_header(("X-Request-Id", "trace-abc"))
+            .set_json(contract(OptionSide::Put, 95_000.0, 0.01, -60))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400);
+        assert_eq!(resp.headers().get("x-request-id").unwrap(), "trace-abc");
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["request_id"], "trace-abc");
+
+        // Otherwise one is generated
+        let req = test::TestRequest::post()
+            .uri("/contract")
+            .insert_header(("X-Request-Id", "not valid"))
+            .set_json(contract(OptionSide::Put, 95_000.0, 0.01, -60))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let id = resp.headers().get("x-request-id").unwrap().to_str().unwrap().to_string();
+        assert_eq!(id.len(), 32);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["request_id"], id.as_str());
+    }
+
+    #[actix_web::test]
+    async fn test_post_contract_rejects_quantity_above_collateral() {
+        // 0.01 BTC pool: far too small for a 10 BTC contract
+        let state = test_state(Some(1_000_000));
+        let app = test_app!(state);
+
+        let req = test::TestRequest::post()
+            .uri("/contract")
+            .set_json(contract(OptionSide::Put, 100_000.0, 10.0, 86_400))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400);
+        let body: Value = test::read_body_json(resp).await;
+        assert!(body["message"].as_str().unwrap().contains("exceeds maximum allowed quantity"));
+        assert_eq!(body["error_code"], "INSUFFICIENT_COLLATERAL");
+        assert_eq!(body["details"]["requested_quantity"], 10.0);
+        let max_quantity = body["details"]["max_quantity"].as_f64().unwrap();
+        assert!(max_quantity > 0.0 && max_quantity < 10.0);
+        assert!(body["details"]["available_collateral_usd"].is_number());
+
+        let contracts: Vec<Value> =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/contracts").to_request()).await;
+        assert!(contracts.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_post_contract_respects_reservations_of_other_instances() {
+        // 0.01 BTC pool, all of it held by a trade another instance is in the middle of
+        let pool = db::create_in_memory_pool().unwrap();
+        let repository = Repository::new(pool.clone());
+        let state = test_state_with_pool(pool, Some(1_000_000));
+        let app = test_app!(state);
+        let now = Utc::now().timestamp();
+        let held = contract(OptionSide::Put, 100_000.0, 0.0001, 86_400);
+        let margin = ReservedMargin { margin_usd: 1_000.0, available_usd: 1_000.0 };
+        let (reservation, ()) = repository
+            .reserve_collateral(1, held, "other-instance".to_string(), now, Duration::from_secs(30), move |_, _| Ok((margin, ())))
+            .await
+            .unwrap();
+
+        let post = || {
+            test::TestRequest::post()
+                .uri("/contract")
+                .set_json(contract(OptionSide::Put, 100_000.0, 0.001, 86_400))
+                .to_request()
+        };
+        let resp = test::call_service(&app, post()).await;
+        assert_eq!(resp.status(), 400);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["error_code"], "INSUFFICIENT_COLLATERAL");
+        assert_eq!(body["details"]["reserved_margin_usd"], 1_000.0);
+
+        // Once that trade falls through, the collateral is free again
+        repository.release_reservation(reservation).await.unwrap();
+        let resp = test::call_service(&app, post()).await;
+        assert_eq!(resp.status(), 200);
+        let statuses: Vec<String> = repository
+            .run(|conn| {
+                let mut stmt = conn.prepare("SELECT status FROM collateral_reservations ORDER BY id")?;
+                let statuses = stmt.query_map([], |row| row.get(0))?.collect::<Result<_, _>>()?;
+                Ok(statuses)
+            })
+            .await
+            .unwrap();
+        // The refused trade never got a reservation
+        assert_eq!(statuses, vec!["released", "confirmed"]);
+    }
+
+    #[actix_web::test]
+    async fn test_post_contract_wallet_failure_is_service_unavailable() {
+        let state = test_state(None);
+        let app = test_app!(state);
+
+        let req = test::TestRequest::post()
+            .uri("/contract")
+            .set_json(contract(OptionSide::Call, 105_000.0, 0.01, 86_400))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 503);
+    }
+
+    #[actix_web::test]
+    async fn test_post_contract_rejects_stale_price() {
+        let state = Arc::new(AppState::new(
+            Repository::new(db::create_in_memory_pool().unwrap()),
+            Arc::new(FakeIv(0.5)),
+            Arc::new(OldPrice(BTC_PRICE)),
+            Arc::new(FakeWallet(Some(100_000_000))),
+            "test-pool-address".to_string(),
+            GridConfig::default(),
+            Duration::from_secs(5),
+        ).with_auth(DEV_AUTH));
+        let app = test_app!(state);
+
+        let req = test::TestRequest::post()
+            .uri("/contract")
+            .set_json(contract(OptionSide::Call, 105_000.0, 0.01, 86_400))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 503);
+        let body: Value = test::read_body_json(resp).await;
+        assert!(body["message"].as_str().unwrap().starts_with("Stale price"));
+        assert_eq!(body["error_code"], "STALE_PRICE");
+        assert_eq!(body["details"]["asset"], "BTC");
+    }
+
+    #[actix_web::test]
+    async fn test_post_contract_requires_api_key() {
+        let pool = db::create_in_memory_pool().unwrap();
+        let state = Arc::new(AppState::new(
+            Repository::new(pool.clone()),
+            Arc::new(FakeIv(0.5)),
+            Arc::new(FakePrice(BTC_PRICE)),
+            Arc::new(FakeWallet(Some(100_000_000))),
+            "test-pool-address".to_string(),
+            GridConfig::default(),
+            Duration::from_secs(5),
+        ));
+        let app = test_app!(state);
+
+        // Refused before any key has been issued, not just after
+        let body = contract(OptionSide::Call, 105_000.0, 0.01, 86_400);
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::post().uri("/contract").set_json(&body).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), 401);
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/admin/audit").to_request()).await;
+        assert_eq!(resp.status(), 401);
+
+        let key = api_keys::rotate_api_key(&pool.get().unwrap(), "tests", "test").unwrap();
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::post().uri("/contract").set_json(&body).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), 401);
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri("/contract")
+                .insert_header((api_keys::API_KEY_HEADER, key.clone()))
+                .set_json(&body)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), 200);
+
+        // The audit log is keyed too, and records the key name as the actor
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/admin/audit").to_request()).await;
+        assert_eq!(resp.status(), 401);
+        let entries: Vec<Value> = test::call_and_read_body_json(
+            &app,
+            test::TestRequest::get()
+                .uri("/admin/audit?action=contract.create")
+                .insert_header((api_keys::API_KEY_HEADER, key))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["actor"], "tests");
+        assert_eq!(entries[0]["post_state"]["strike_price"], 105_000.0);
+        assert!(entries[0]["pre_state"].is_null());
+    }
+
+    #[actix_web::test]
+    async fn test_analytics_endpoints() {
+        let state = test_state(Some(100_000_000));
+        let app = test_app!(state);
+
+        // Shared expiry so both calls land in the same product
+        let expires = Utc::now().timestamp() + 86_400;
+        for (side, strike, quantity) in [
+            (OptionSide::Call, 105_000.0, 0.02),
+            (OptionSide::Call, 105_000.0, 0.01),
+            (OptionSide::Put, 95_000.0, 0.05),
+        ] {
+            let resp = test::call_service(
+                &app,
+                test::TestRequest::post()
+                    .uri("/contract")
+                    .set_json(Contract { expires, ..contract(side, strike, quantity, 0) })
+                    .to_request(),
+            )
+            .await;
+            assert_eq!(resp.status(), 200);
+        }
+
+        let banner: Value =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/topBanner").to_request()).await;
+        assert_eq!(banner["contract_count"], 3);
+        assert!((banner["volume_24hr"].as_f64().unwrap() - 0.08).abs() < 1e-9);
+        // Open interest is quantity * premium (0.01 BTC each) valued at spot
+        assert!((banner["open_interest_usd"].as_f64().unwrap() - 0.08 * 0.01 * BTC_PRICE).abs() < 1e-6);
+
+        let highlights: Vec<Value> =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/marketHighlights").to_request()).await;
+        assert_eq!(highlights.len(), 2);
+        assert_eq!(highlights[0]["side"], "Put");
+        assert_eq!(highlights[0]["strike_price"], 95_000.0);
+
+        let gainers: Vec<Value> =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/topGainers").to_request()).await;
+        assert!(gainers.len() <= 2);
+
+        // Leaderboards over other windows; everything traded at one premium, so nothing moved
+        let losers: Vec<Value> =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/topLosers?window=1h").to_request()).await;
+        assert_eq!(losers.len(), 2);
+        assert_eq!((&losers[0]["window"], &losers[0]["change_percent"]), (&serde_json::json!("1h"), &serde_json::json!(0.0)));
+        let top_volume: Vec<Value> =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/topVolume?window=7d").to_request()).await;
+        assert_eq!((top_volume.len(), &top_volume[0]["window"]), (2, &serde_json::json!("7d")));
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/topGainers?window=2h").to_request()).await;
+        assert_eq!(resp.status(), 400);
+
+        let top_volume: Vec<Value> =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/topVolume").to_request()).await;
+        assert_eq!(top_volume.len(), 2);
+        assert_eq!(top_volume[0]["side"], "Put");
+    }
+
+    #[actix_web::test]
+    async fn test_graphql_query() {
+        let state = test_state(Some(100_000_000));
+        let app = test_app!(state);
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri("/contract")
+                .set_json(contract(OptionSide::Put, 95_000.0, 0.05, 86_400))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), 200);
+
+        let query = r#"{
+            contracts { side strikePrice quantity }
+            optionsTable(tenors: "1d") { side premium }
+            analytics { topBanner { contractCount } }
+            pool { address balanceBtc }
+        }"#;
+        let body: Value = test::call_and_read_body_json(
+            &app,
+            test::TestRequest::post().uri("/graphql").set_json(serde_json::json!({ "query": query })).to_request(),
+        )
+        .await;
+        assert!(body.get("errors").is_none(), "{}", body);
+        let data = &body["data"];
+        // Only the selected fields come back
+        assert_eq!(data["contracts"], serde_json::json!([{"side": "PUT", "strikePrice": 95_000.0, "quantity": "0.05000000"}]));
+        assert!(!data["optionsTable"].as_array().unwrap().is_empty());
+        assert!(data["optionsTable"][0].get("iv").is_none());
+        assert_eq!(data["analytics"]["topBanner"]["contractCount"], 1);
+        assert_eq!(data["pool"], serde_json::json!({"address": "test-pool-address", "balanceBtc": 1.0}));
+
+        // Errors carry the REST error code
+        let body: Value = test::call_and_read_body_json(
+            &app,
+            test::TestRequest::post()
+                .uri("/graphql")
+                .set_json(serde_json::json!({ "query": "{ optionsTable(asset: ETH) { premium } }" }))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(body["errors"][0]["extensions"]["error_code"], "ASSET_NOT_ENABLED");
+        assert_eq!(body["errors"][0]["extensions"]["details"]["asset"], "ETH");
+    }
+
+    #[actix_web::test]
+    async fn test_fix_gateway() {
+        use btc_options_api::fix::{self, msg_type, tag, FixConfig, Header, Message};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::{TcpListener, TcpStream};
+
+        async fn send(stream: &mut TcpStream, seq_num: u64, message: Message) {
+            let header = Header {
+                sender_comp_id: "TAKER",
+                target_comp_id: "BTCOPTIONS",
+                seq_num,
+                sending_time: &fix::sending_time(),
+                orig_sending_time: None,
+            };
+            stream.write_all(&fix::encode(&header, &message)).await.unwrap();
+        }
+
+        async fn receive(stream: &mut TcpStream, buf: &mut Vec<u8>) -> Message {
+            loop {
+                if let Some((message, used)) = fix::decode(buf).unwrap() {
+                    buf.drain(..used);
+                    return message;
+                }
+                let mut chunk = [0u8; 4096];
+                let n = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut chunk)).await.unwrap().unwrap();
+                assert!(n > 0, "connection closed");
+                buf.extend_from_slice(&chunk[..n]);
+            }
+        }
+
+        let logon = || Message::new(msg_type::LOGON).with(tag::ENCRYPT_METHOD, 0).with(tag::HEART_BT_INT, 30);
+
+        let pool = db::create_in_memory_pool().unwrap();
+        let state = test_state_with_pool(pool.clone(), Some(100_000_000));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(fix::serve(listener, FixConfig::default(), Repository::new(pool.clone()), state.clone()));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let mut buf = Vec::new();
+        send(&mut stream, 1, logon()).await;
+        let reply = receive(&mut stream, &mut buf).await;
+        assert_eq!(reply.msg_type(), msg_type::LOGON);
+        assert_eq!(reply.seq_num(), Some(1));
+
+        let maturity = (Utc::now() + chrono::Duration::days(2)).format("%Y%m%d").to_string();
+        let order = Message::new(msg_type::NEW_ORDER_SINGLE)
+            .with(tag::CL_ORD_ID, "ord-1")
+            .with(tag::SYMBOL, "BTC")
+            .with(tag::SIDE, "1")
+            .with(tag::ORDER_QTY, "0.01")
+            .with(tag::ORD_TYPE, "2")
+            .with(tag::PRICE, "0.01")
+            .with(tag::PUT_OR_CALL, "1")
+            .with(tag::STRIKE_PRICE, "105000")
+            .with(tag::MATURITY_DATE, &maturity);
+        send(&mut stream, 2, order.clone()).await;
+        let report = receive(&mut stream, &mut buf).await;
+        assert_eq!(report.msg_type(), msg_type::EXECUTION_REPORT);
+        assert_eq!(report.get(tag::CL_ORD_ID), Some("ord-1"));
+        assert_eq!(report.get(tag::ORD_STATUS), Some("2"), "{:?}", report.get(tag::TEXT));
+        assert_eq!(report.get(tag::CUM_QTY), Some("0.01000000"));
+        assert_eq!(report.get(tag::ORDER_ID), Some("1"));
+        let app = test_app!(state);
+        let contracts: Vec<Value> =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/contracts").to_request()).await;
+        assert_eq!(contracts.len(), 1);
+        assert_eq!(contracts[0]["strike_price"], 105_000.0);
+
+        // Rejections carry the error code
+        send(&mut stream, 3, Message::new(msg_type::NEW_ORDER_SINGLE).with(tag::CL_ORD_ID, "ord-2")).await;
+        let report = receive(&mut stream, &mut buf).await;
+        assert_eq!(report.get(tag::ORD_STATUS), Some("8"));
+        assert_eq!(report.get(tag::TEXT), Some("VALIDATION_ERROR: Symbol (55) is required"));
+        drop(stream);
+
+        // Sequence numbers carry over to the next connection
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let mut buf = Vec::new();
+        send(&mut stream, 4, logon()).await;
+        let reply = receive(&mut stream, &mut buf).await;
+        assert_eq!(reply.msg_type(), msg_type::LOGON);
+        assert_eq!(reply.seq_num(), Some(4));
+
+        // Reports missed while disconnected are resent, session messages gap filled
+        send(&mut stream, 5, Message::new(msg_type::RESEND_REQUEST).with(tag::BEGIN_SEQ_NO, 2).with(tag::END_SEQ_NO, 0)).await;
+        let resent = receive(&mut stream, &mut buf).await;
+        assert_eq!(resent.seq_num(), Some(2));
+        assert_eq!(resent.get(tag::POSS_DUP_FLAG), Some("Y"));
+        assert_eq!(resent.get(tag::CL_ORD_ID), Some("ord-1"));
+        let resent = receive(&mut stream, &mut buf).await;
+        assert_eq!(resent.seq_num(), Some(3));
+        assert_eq!(resent.get(tag::CL_ORD_ID), Some("ord-2"));
+        let fill = receive(&mut stream, &mut buf).await;
+        assert_eq!(fill.msg_type(), msg_type::SEQUENCE_RESET);
+        assert_eq!((fill.seq_num(), fill.get(tag::NEW_SEQ_NO)), (Some(4), Some("5")));
+        drop(stream);
+
+        // A Logon below the expected sequence number is refused
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let mut buf = Vec::new();
+        send(&mut stream, 1, logon()).await;
+        let reply = receive(&mut stream, &mut buf).await;
+        assert_eq!(reply.msg_type(), msg_type::LOGOUT);
+        assert_eq!(reply.get(tag::TEXT), Some("MsgSeqNum too low, expecting 6"));
+
+        // With auth enabled, a Logon needs an API key as Password even before any is issued
+        let pool = db::create_in_memory_pool().unwrap();
+        let state = Arc::new(AppState::new(
+            Repository::new(pool.clone()),
+            Arc::new(FakeIv(0.5)),
+            Arc::new(FakePrice(BTC_PRICE)),
+            Arc::new(FakeWallet(Some(100_000_000))),
+            "test-pool-address".to_string(),
+            GridConfig::default(),
+            Duration::from_secs(5),
+        ));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(fix::serve(listener, FixConfig::default(), Repository::new(pool.clone()), state));
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let mut buf = Vec::new();
+        send(&mut stream, 1, logon()).await;
+        let reply = receive(&mut stream, &mut buf).await;
+        assert_eq!(reply.msg_type(), msg_type::LOGOUT);
+        assert_eq!(reply.get(tag::TEXT), Some("invalid Password"));
+
+        let key = api_keys::rotate_api_key(&pool.get().unwrap(), "taker", "test").unwrap();
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let mut buf = Vec::new();
+        send(&mut stream, 1, logon().with(tag::PASSWORD, key)).await;
+        let reply = receive(&mut stream, &mut buf).await;
+        assert_eq!(reply.msg_type(), msg_type::LOGON);
+    }
+
+    #[actix_web::test]
+    async fn test_options_table_and_max_quantity() {
+        let state = test_state(Some(100_000_000));
+        let app = test_app!(state);
+
+        // 11 strikes x 5 tenors x 2 sides with the default grid
+        let table: Vec<Value> =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/optionsTable").to_request()).await;
+        assert_eq!(table.len(), 110);
+        assert!(table.iter().all(|row| row["iv"] == 0.5));
+        assert!(table.iter().all(|row| row["warnings"] == serde_json::json!([])));
+        assert!(table.iter().all(|row| row["margin_per_contract"].as_f64().unwrap() > 0.0));
+        // Selling the max of a row uses up the pool's collateral, unless the contract size caps it
+        assert!(table.iter().all(|row| row["pool_utilization_if_max"].as_f64().unwrap() <= 100.0 + 1e-6));
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get().uri("/optionsTable?strikes_per_side=1000").to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), 400);
+
+        let expires = Utc::now().timestamp() + 86_400;
+        let uri = format!("/maxQuantity?side=Call&strike=105000&expires={}&premium=0.01", expires);
+        let max_quantity: Value =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(max_quantity["btc_price"], BTC_PRICE);
+        assert_eq!(max_quantity["pool_balance_btc"], 1.0);
+        assert!(max_quantity["max_quantity"].as_f64().unwrap() > 0.0);
+    }
+
+    #[actix_web::test]
+    async fn test_options_table_warns_of_default_iv() {
+        // Quotes calls only, so puts fall back to the default IV
+        struct CallIv;
+
+        impl IvSource for CallIv {
+            fn get_iv(&self, side: &str, _strike_price: f64, _expire: &str) -> Option<f64> {
+                (side == "C").then_some(0.5)
+            }
+
+            fn get_asset_iv(&self, _asset: Asset, side: &str, strike_price: f64, expire: &str) -> Option<f64> {
+                self.get_iv(side, strike_price, expire)
+            }
+
+            fn cache_size(&self) -> usize {
+                1
+            }
+
+            fn version(&self) -> u64 {
+                0
+            }
+        }
+
+        let state = || {
+            AppState::new(
+                Repository::new(db::create_in_memory_pool().unwrap()),
+                Arc::new(CallIv),
+                Arc::new(FakePrice(BTC_PRICE)),
+                Arc::new(FakeWallet(Some(100_000_000))),
+                "test-pool-address".to_string(),
+                GridConfig::default(),
+                Duration::from_secs(5),
+            )
+            .with_auth(DEV_AUTH)
+        };
+        let app = test_app!(Arc::new(state()));
+        let table: Vec<Value> =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/optionsTable").to_request()).await;
+        // Puts at the default IV are too cheap for the calls at 0.5, so their asks are raised
+        // back to put-call parity
+        for row in &table {
+            let (expected, raised) = if row["side"] == "Put" {
+                (serde_json::json!(["low_liquidity_iv", "parity_breach"]), true)
+            } else {
+                (serde_json::json!(["parity_breach"]), false)
+            };
+            assert_eq!(row["warnings"], expected);
+            let price = |field: &str| row[field].as_str().unwrap().parse::<f64>().unwrap();
+            assert_eq!(price("premium") > price("mid"), raised);
+            assert!(row["max_quantity"].as_str().unwrap().parse::<f64>().unwrap() > 0.0);
+        }
+
+        // The reject policy refuses to sell what has no IV
+        let state = state().with_iv_policy(Arc::new(DefaultIvPolicy::new(DefaultIvMode::Reject, 0.4)));
+        let app = test_app!(Arc::new(state));
+        let table: Vec<Value> =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/optionsTable").to_request()).await;
+        for row in &table {
+            assert_eq!(row["max_quantity"].as_str().unwrap().parse::<f64>().unwrap() == 0.0, row["side"] == "Put");
+        }
+        let req = test::TestRequest::post().uri("/contract").set_json(contract(OptionSide::Put, 95_000.0, 0.1, 86_400)).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 503);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["error_code"], "IV_UNAVAILABLE");
+        assert_eq!(body["details"]["policy"], "reject");
+        let req = test::TestRequest::post().uri("/contract").set_json(contract(OptionSide::Call, 105_000.0, 0.1, 86_400)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_price_endpoint() {
+        let app = test_app!(test_state(Some(100_000_000)));
+        let expires = Utc::now().timestamp() + 30 * 86_400;
+
+        // Spot and IV from the oracles
+        let uri = format!("/price?side=Call&strike=105000&expires={}", expires);
+        let price: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!((price["spot_price"].as_f64(), price["spot_source"].as_str()), (Some(BTC_PRICE), Some("oracle")));
+        assert_eq!((price["iv"].as_f64(), price["iv_source"].as_str()), (Some(0.5), Some("oracle")));
+        let t = price["time_to_expiry"].as_f64().unwrap();
+        let premium_usd = pricing::option_price(&OptionSide::Call, BTC_PRICE, 105_000.0, 0.0, 0.5, t);
+        assert!((price["premium_usd"].as_f64().unwrap() - premium_usd).abs() < 1e-6);
+        assert_eq!(price["premium_btc"], format!("{:.8}", premium_usd / BTC_PRICE));
+        assert!(price["greeks"]["delta"].as_f64().unwrap() > 0.0);
+        assert!(price["greeks"]["vega"].as_f64().unwrap() > 0.0);
+
+        // Inputs given by the caller win, and a BTC option converts at the given spot
+        let uri = format!("/price?side=Put&strike=90000&expires={}&iv=0.8&spot=80000", expires);
+        let price: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!((price["iv"].as_f64(), price["iv_source"].as_str()), (Some(0.8), Some("request")));
+        assert_eq!(price["spot_source"], "request");
+        assert_eq!(price["btc_price"], 80_000.0);
+        assert!(price["premium_usd"].as_f64().unwrap() > 10_000.0);
+        assert!(price["greeks"]["delta"].as_f64().unwrap() < 0.0);
+
+        let expired = format!("/price?side=Call&strike=105000&expires={}", Utc::now().timestamp() - 60);
+        let resp = test::call_service(&app, test::TestRequest::get().uri(&expired).to_request()).await;
+        assert_eq!(resp.status(), 400);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["error_code"], "INVALID_EXPIRY");
+        let uri = format!("/price?side=Call&strike=105000&expires={}&iv=-0.5", expires);
+        assert_eq!(test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await.status(), 400);
+    }
+
+    #[actix_web::test]
+    async fn test_price_on_the_funding_basis() {
+        let basis = Arc::new(BasisSource::new(BasisMode::Funding, String::new()));
+        let state = AppState::new(
+            Repository::new(db::create_in_memory_pool().unwrap()),
+            Arc::new(FakeIv(0.5)),
+            Arc::new(FakePrice(BTC_PRICE)),
+            Arc::new(FakeWallet(Some(100_000_000))),
+            "test-pool-address".to_string(),
+            GridConfig::default(),
+            Duration::from_secs(5),
+        ).with_auth(DEV_AUTH);
+        let app = test_app!(Arc::new(state.with_basis(basis.clone())));
+        let uri = format!("/price?side=Call&strike=105000&expires={}", Utc::now().timestamp() + 30 * 86_400);
+
+        // Priced on spot until the basis is fetched
+        let price: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(price["carry"], Value::Null);
+        assert_eq!(price["forward_price"], BTC_PRICE);
+        let spot_premium = price["premium_usd"].as_f64().unwrap();
+
+        // A 0.01% 8h funding rate is a 10.95% carry, pricing the call on a forward above spot
+        basis.set_carry(Asset::Btc, RateCurve::flat(0.1095));
+        let price: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(price["carry"], 0.1095);
+        let t = price["time_to_expiry"].as_f64().unwrap();
+        let forward = BTC_PRICE * (0.1095 * t).exp();
+        assert!((price["forward_price"].as_f64().unwrap() - forward).abs() < 1e-6);
+        let premium_usd = pricing::option_price(&OptionSide::Call, forward, 105_000.0, 0.0, 0.5, t);
+        assert!((price["premium_usd"].as_f64().unwrap() - premium_usd).abs() < 1e-6);
+        assert!(premium_usd > spot_premium);
+    }
+
+    #[actix_web::test]
+    async fn test_options_table_quotes_around_the_mid() {
+        let pool = db::create_in_memory_pool().unwrap();
+        let quoting = QuotingConfig { spread_percent: 10.0, inventory_skew_percent: 50.0, ..Default::default() };
+        let new_state = || {
+            Arc::new(
+                AppState::new(
+                    Repository::new(pool.clone()),
+                    Arc::new(FakeIv(0.5)),
+                    Arc::new(FakePrice(BTC_PRICE)),
+                    Arc::new(FakeWallet(Some(1_000_000_000))),
+                    "test-pool-address".to_string(),
+                    GridConfig::default(),
+                    Duration::from_secs(5),
+                )
+                .with_auth(DEV_AUTH)
+                .with_quoting(quoting),
+            )
+        };
+        let ratio = |row: &Value, field: &str| {
+            row[field].as_str().unwrap().parse::<f64>().unwrap() / row["mid"].as_str().unwrap().parse::<f64>().unwrap()
+        };
+        let atm = |table: &[Value], side: &str| {
+            table.iter().find(|row| row["side"] == side && row["strike_price"] == 100_000.0 && row["expire"] == "7d").unwrap().clone()
+        };
+
+        // An empty book quotes the spread evenly around the mid
+        let state = new_state();
+        let app = test_app!(state);
+        let table: Vec<Value> =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/optionsTable").to_request()).await;
+        for side in ["Call", "Put"] {
+            let row = atm(&table, side);
+            assert!((ratio(&row, "premium") - 1.05).abs() < 1e-4);
+            assert!((ratio(&row, "bid") - 0.95).abs() < 1e-4);
+        }
+
+        // Once calls have been sold, only call asks are skewed up
+        let post = test::TestRequest::post()
+            .uri("/contract")
+            .set_json(contract(OptionSide::Call, 110_000.0, 1.0, 7 * 86_400))
+            .to_request();
+        assert_eq!(test::call_service(&app, post).await.status(), 200);
+        let state = new_state();
+        let app = test_app!(state);
+        let table: Vec<Value> =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/optionsTable").to_request()).await;
+        let (call, put) = (atm(&table, "Call"), atm(&table, "Put"));
+        assert!(ratio(&call, "premium") > 1.06);
+        assert!((ratio(&call, "bid") - 0.95).abs() < 1e-4);
+        assert!((ratio(&put, "premium") - 1.05).abs() < 1e-4);
+    }
+
+    #[actix_web::test]
+    async fn test_options_table_shades_with_the_book() {
+        let state = Arc::new(
+            AppState::new(
+                Repository::new(db::create_in_memory_pool().unwrap()),
+                Arc::new(FakeIv(0.5)),
+                Arc::new(FakePrice(BTC_PRICE)),
+                Arc::new(FakeWallet(Some(1_000_000_000))),
+                "test-pool-address".to_string(),
+                GridConfig::default(),
+                Duration::from_secs(5),
+            )
+            .with_auth(DEV_AUTH)
+            .with_quoting(QuotingConfig {
+                concentration_percent: 100.0,
+                hedge_discount_percent: 100.0,
+                // Shading breaks put-call parity on purpose here
+                sanity: SanityMode::Off,
+                ..Default::default()
+            }),
+        );
+        let app = test_app!(state);
+        let table = || async {
+            let table: Vec<Value> =
+                test::call_and_read_body_json(&app, test::TestRequest::get().uri("/optionsTable").to_request()).await;
+            table
+        };
+        let ratio = |table: &[Value], side: &str, strike: f64| {
+            let row = table
+                .iter()
+                .find(|row| row["side"] == side && row["strike_price"] == strike && row["expire"] == "7d")
+                .unwrap();
+            row["premium"].as_str().unwrap().parse::<f64>().unwrap() / row["mid"].as_str().unwrap().parse::<f64>().unwrap()
+        };
+        assert!((ratio(&table().await, "Call", 105_000.0) - 1.0).abs() < 1e-4);
+
+        // Writing 105k calls reprices the table at once: that strike is marked up, other calls
+        // are not, and puts, which offset the short call delta, are discounted
+        let post = test::TestRequest::post()
+            .uri("/contract")
+            .set_json(contract(OptionSide::Call, 105_000.0, 1.0, 7 * 86_400))
+            .to_request();
+        assert_eq!(test::call_service(&app, post).await.status(), 200);
+        let table = table().await;
+        assert!(ratio(&table, "Call", 105_000.0) > 1.01);
+        assert!((ratio(&table, "Call", 100_000.0) - 1.0).abs() < 1e-4);
+        assert!(ratio(&table, "Put", 100_000.0) < 0.9999);
+    }
+
+    #[actix_web::test]
+    async fn test_eth_options_share_the_pool() {
+        let pool = db::create_in_memory_pool().unwrap();
+        let btc_only = test_state_with_pool(pool.clone(), Some(100_000_000));
+        let eth_contract = Contract { underlying: Asset::Eth, ..contract(OptionSide::Put, 3_400.0, 2.0, 86_400) };
+
+        // ETH is opt-in
+        let app = test_app!(btc_only);
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::post().uri("/contract").set_json(&eth_contract).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), 400);
+
+        let state = Arc::new(
+            AppState::new(
+                Repository::new(pool),
+                Arc::new(FakeIv(0.5)),
+                Arc::new(FakePrice(BTC_PRICE)),
+                Arc::new(FakeWallet(Some(100_000_000))),
+                "test-pool-address".to_string(),
+                GridConfig::default(),
+                Duration::from_secs(5),
+            )
+            .with_auth(DEV_AUTH)
+            .with_assets(vec![Asset::Btc, Asset::Eth]),
+        );
+        let app = test_app!(state);
+        for body in [eth_contract, contract(OptionSide::Call, 105_000.0, 0.01, 86_400)] {
+            let resp = test::call_service(&app, test::TestRequest::post().uri("/contract").set_json(&body).to_request()).await;
+            assert_eq!(resp.status(), 200);
+        }
+
+        let contracts: Vec<Value> =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/contracts").to_request()).await;
+        assert_eq!(contracts[0]["underlying"], "ETH");
+        assert_eq!(contracts[1]["underlying"], "BTC");
+
+        // ETH table is centred on the ETH spot
+        let table: Vec<Value> =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/optionsTable?asset=ETH").to_request()).await;
+        assert_eq!(table.len(), 110);
+        assert!(table.iter().all(|row| row["underlying"] == "ETH"));
+        assert!(table.iter().all(|row| (3_250.0..=3_750.0).contains(&row["strike_price"].as_f64().unwrap())));
+
+        // The ETH put eats into the collateral left for BTC options
+        let expires = Utc::now().timestamp() + 86_400;
+        let uri = format!("/maxQuantity?asset=ETH&side=Put&strike=3400&expires={}&premium=0.001", expires);
+        let max_quantity: Value =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(max_quantity["spot_price"], ETH_PRICE);
+        assert_eq!(max_quantity["btc_price"], BTC_PRICE);
+        assert!(max_quantity["existing_risk_usd"].as_f64().unwrap() > 2.0 * 3_000.0);
+
+        // Risk and analytics are scoped per underlying
+        let eth_delta: f64 =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/delta?asset=ETH").to_request()).await;
+        assert!(eth_delta < 0.0);
+        let banner: Value =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/topBanner?asset=ETH").to_request()).await;
+        assert_eq!(banner["contract_count"], 1);
+        let banner: Value =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/topBanner").to_request()).await;
+        assert_eq!(banner["contract_count"], 2);
+        let top_volume: Vec<Value> =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/topVolume?asset=ETH").to_request()).await;
+        assert_eq!(top_volume.len(), 1);
+        // Symbols name the expiry date, e.g. ETH-28MAR25-3400-P; the relative one is kept alongside
+        let expiry_date = chrono::DateTime::from_timestamp(contracts[0]["expires"].as_i64().unwrap(), 0).unwrap();
+        let symbol = format!("ETH-{}-3400-P", expiry_date.format("%-d%b%y").to_string().to_uppercase());
+        assert_eq!(top_volume[0]["product_symbol"], symbol);
+        assert!(top_volume[0]["legacy_product_symbol"].as_str().unwrap().ends_with("-3400-Put"));
+    }
+
+    #[actix_web::test]
+    async fn test_export_contracts_as_csv_and_parquet() {
+        let state = test_state(Some(100_000_000));
+        let app = test_app!(state);
+        let req = test::TestRequest::post()
+            .uri("/contract")
+            .set_json(contract(OptionSide::Call, 105_000.0, 0.01, 86_400))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/export/contracts").to_request()).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers().get("content-type").unwrap(), "text/csv; charset=utf-8");
+        let csv = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        assert_eq!(csv.lines().count(), 2);
+        assert!(csv.lines().nth(1).unwrap().starts_with("1,BTC,Call,105000,0.01,"));
+
+        let req = test::TestRequest::get().uri("/export/premiumHistory?format=parquet&from=2020-01-01").to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert!(body.starts_with(b"PAR1") && body.ends_with(b"PAR1"));
+
+        let req = test::TestRequest::get().uri("/export/contracts?from=2020-01-01&to=2019-12-31").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
+        let req = test::TestRequest::get().uri("/export/contracts?format=xlsx").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
+    }
+
+    #[actix_web::test]
+    async fn test_usd_quoted_premium_is_converted_and_recorded() {
+        let state = test_state(Some(100_000_000));
+        let app = test_app!(state);
+
+        // $1,000 premium at a $100k BTC price
+        let mut body = serde_json::to_value(contract(OptionSide::Put, 95_000.0, 0.1, 86_400)).unwrap();
+        body["premium"] = 1_000.0.into();
+        body["premium_currency"] = "USD".into();
+        let resp = test::call_service(&app, test::TestRequest::post().uri("/contract").set_json(&body).to_request()).await;
+        assert_eq!(resp.status(), 200);
+
+        let contracts: Vec<Value> =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/contracts").to_request()).await;
+        assert_eq!(contracts[0]["premium"], "0.01000000");
+        assert_eq!(contracts[0]["premium_currency"], "USD");
+        assert_eq!(contracts[0]["quoted_premium"], "1000.00");
+        assert_eq!(contracts[0]["trade_btc_price"], BTC_PRICE);
+
+        // The table can be quoted in USDT
+        let table: Vec<Value> = test::call_and_read_body_json(
+            &app,
+            test::TestRequest::get().uri("/optionsTable?premium_currency=USDT").to_request(),
+        )
+        .await;
+        assert!(table.iter().all(|row| row["premium_currency"] == "USDT"));
+        assert!(table.iter().any(|row| row["premium"].as_str().unwrap().parse::<f64>().unwrap() > 1.0));
+    }
+
+    #[actix_web::test]
+    async fn test_contract_detail_has_live_analytics() {
+        let state = test_state(Some(100_000_000));
+        let app = test_app!(state);
+
+        let req = test::TestRequest::post()
+            .uri("/contract")
+            .set_json(contract(OptionSide::Put, 95_000.0, 0.1, 7 * 86_400))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+
+        let detail: Value =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/contract/1").to_request()).await;
+        assert_eq!(detail["id"], 1);
+        assert_eq!(detail["status"], "open");
+        assert_eq!(detail["iv"], 0.5);
+        assert_eq!(detail["moneyness_label"], "OTM");
+        assert!(detail["time_to_expiry_secs"].as_i64().unwrap() > 6 * 86_400);
+        assert!(detail["mark_premium_usd"].as_f64().unwrap() > 0.0);
+        let delta = detail["greeks"]["delta"].as_f64().unwrap();
+        assert!(delta < 0.0 && delta > -0.5);
+        assert!((detail["position_greeks"]["delta"].as_f64().unwrap() - delta * 0.1).abs() < 1e-12);
+        // Alone in the book, the contract carries all of the book margin
+        let margin = detail["margin_usd"].as_f64().unwrap();
+        assert!(margin > 0.0);
+        assert!((detail["marginal_margin_usd"].as_f64().unwrap() - margin).abs() < 1e-6);
+        // The market it traded in is kept with it; spot and IV have not moved since
+        assert_eq!(detail["spot_at_trade"], BTC_PRICE);
+        assert_eq!(detail["iv_at_trade"], 0.5);
+        assert_eq!(detail["iv_source"], "deribit");
+        let mark_at_trade = detail["mark_premium_at_trade"].as_f64().unwrap();
+        assert!((mark_at_trade - detail["mark_premium_btc"].as_f64().unwrap()).abs() < 1e-6);
+        let edge = detail["edge_at_trade_btc"].as_f64().unwrap();
+        assert!((edge - (detail["premium"].as_f64().unwrap() - mark_at_trade)).abs() < 1e-12);
+        // The premium received is recorded with the BTC price it was taken at
+        let conversions = detail["conversions"].as_array().unwrap();
+        assert_eq!(conversions.len(), 1);
+        assert_eq!(conversions[0]["kind"], "premium");
+        assert_eq!(conversions[0]["currency"], "BTC");
+        assert_eq!(conversions[0]["btc_price"], BTC_PRICE);
+        let premium_btc = detail["premium"].as_f64().unwrap() * 0.1;
+        assert!((conversions[0]["btc"].as_str().unwrap().parse::<f64>().unwrap() - premium_btc).abs() < 1e-8);
+
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/contract/42").to_request()).await;
+        assert_eq!(resp.status(), 404);
+    }
+
+    #[actix_web::test]
+    async fn test_contract_pricing_audit_replays_trade() {
+        let state = test_state(Some(100_000_000));
+        let app = test_app!(state);
+
+        let req = test::TestRequest::post()
+            .uri("/contract")
+            .set_json(contract(OptionSide::Call, 105_000.0, 0.1, 7 * 86_400))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+
+        let audit: Value =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/contract/1/pricing-audit").to_request()).await;
+        assert_eq!(audit["contract_id"], 1);
+        assert_eq!(audit["spot_at_trade"], BTC_PRICE);
+        assert_eq!(audit["btc_price_at_trade"], BTC_PRICE);
+        assert_eq!(audit["iv_at_trade"], 0.5);
+        assert_eq!(audit["iv_source"], "deribit");
+        // The replay reproduces the mark recorded at trade time
+        let model = audit["model_premium"].as_f64().unwrap();
+        assert!((model - audit["recorded_mark_premium"].as_f64().unwrap()).abs() < 1e-6);
+        let deviation = audit["deviation"].as_f64().unwrap();
+        assert!((deviation - (audit["premium"].as_f64().unwrap() - model)).abs() < 1e-12);
+        // Quoted off the same model, the trade is close to fair value
+        assert!(audit["deviation_percent"].as_f64().unwrap().abs() < audit["threshold_percent"].as_f64().unwrap());
+        assert_eq!(audit["flagged"], false);
+
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/contract/42/pricing-audit").to_request()).await;
+        assert_eq!(resp.status(), 404);
+    }
+
+    #[actix_web::test]
+    async fn test_contracts_search_filters() {
+        let state = test_state(Some(100_000_000));
+        let app = test_app!(state);
+
+        for (side, strike, quantity, expires_in) in [
+            (OptionSide::Call, 105_000.0, 0.01, 7 * 86_400),
+            (OptionSide::Put, 80_000.0, 0.02, 30 * 86_400),
+            (OptionSide::Call, 100_000.0, 0.03, 2 * 86_400),
+        ] {
+            let req = test::TestRequest::post().uri("/contract").set_json(contract(side, strike, quantity, expires_in)).to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), 200);
+        }
+
+        let search = |query: &str| test::TestRequest::get().uri(&format!("/contracts/search{}", query)).to_request();
+        let ids = |results: &Value| results.as_array().unwrap().iter().map(|c| c["id"].as_i64().unwrap()).collect::<Vec<_>>();
+
+        let all: Value = test::call_and_read_body_json(&app, search("")).await;
+        assert_eq!(ids(&all), vec![3, 2, 1]);
+        assert!(all[2]["product_symbol"].as_str().unwrap().ends_with("-105000-C"));
+        assert_eq!(all[2]["status"], "open");
+
+        let found: Value = test::call_and_read_body_json(&app, search("?moneyness_percent=10")).await;
+        assert_eq!(ids(&found), vec![3, 1]);
+        let found: Value = test::call_and_read_body_json(&app, search("?expiring_within_days=7")).await;
+        assert_eq!(ids(&found), vec![3, 1]);
+        let found: Value = test::call_and_read_body_json(&app, search("?q=80000-p")).await;
+        assert_eq!(ids(&found), vec![2]);
+        let found: Value = test::call_and_read_body_json(&app, search("?side=Call&min_quantity=0.025&underlying=BTC")).await;
+        assert_eq!(ids(&found), vec![3]);
+        let found: Value = test::call_and_read_body_json(&app, search("?limit=1")).await;
+        assert_eq!(ids(&found), vec![3]);
+
+        assert_eq!(test::call_service(&app, search("?underlying=ETH")).await.status(), 400);
+        assert_eq!(test::call_service(&app, search("?limit=0")).await.status(), 400);
+        assert_eq!(test::call_service(&app, search("?moneyness_percent=-5")).await.status(), 400);
+    }
+
+    #[actix_web::test]
+    async fn test_exercise_american_contract() {
+        let pool = db::create_in_memory_pool().unwrap();
+        let state = test_state_with_pool(pool.clone(), Some(100_000_000));
+        let app = test_app!(state);
+        let post = |contract: Contract, style: &str| {
+            let mut body = serde_json::to_value(contract).unwrap();
+            body["exercise_style"] = Value::from(style);
+            test::TestRequest::post().uri("/contract").set_json(body).to_request()
+        };
+        let exercise = |id: i64| test::TestRequest::post().uri(&format!("/contract/{}/exercise", id)).to_request();
+
+        // 1: in-the-money European put, 2: in-the-money American put, 3: out-of-the-money American call
+        assert_eq!(test::call_service(&app, post(contract(OptionSide::Put, 105_000.0, 0.1, 86_400), "european")).await.status(), 200);
+        assert_eq!(test::call_service(&app, post(contract(OptionSide::Put, 105_000.0, 0.1, 86_400), "american")).await.status(), 200);
+        assert_eq!(test::call_service(&app, post(contract(OptionSide::Call, 110_000.0, 0.1, 86_400), "american")).await.status(), 200);
+
+        let resp = test::call_service(&app, exercise(1)).await;
+        assert_eq!(resp.status(), 400);
+        let body: Value = test::read_body_json(resp).await;
+        assert!(body["message"].as_str().unwrap().contains("European"));
+        let resp = test::call_service(&app, exercise(3)).await;
+        assert_eq!(resp.status(), 400);
+        let body: Value = test::read_body_json(resp).await;
+        assert!(body["message"].as_str().unwrap().contains("out of the money"));
+
+        let body: Value = test::call_and_read_body_json(&app, exercise(2)).await;
+        assert_eq!(body["status"], "exercised");
+        assert_eq!(body["exercise_style"], "american");
+        assert_eq!(body["settlement_price"], BTC_PRICE);
+        assert!((body["payoff_usd"].as_f64().unwrap() - 500.0).abs() < 1e-6);
+        assert_eq!(body["payoff"], "0.00500000");
+        assert!(body["released_margin_usd"].as_f64().unwrap() > 0.0);
+
+        // The exercised contract leaves the book and cannot be exercised twice
+        let positions: Vec<Value> =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/positions").to_request()).await;
+        let put = positions.iter().find(|p| p["side"] == "Put").unwrap();
+        assert_eq!(put["contract_count"], 1);
+        assert_eq!(test::call_service(&app, exercise(2)).await.status(), 400);
+
+        // The exercise shows up in the settlement report with the payoff it cost the pool
+        let report: Value =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/settlements").to_request()).await;
+        let settlements = report["settlements"].as_array().unwrap();
+        assert_eq!(settlements.len(), 1);
+        assert_eq!(settlements[0]["status"], "exercised");
+        assert_eq!(settlements[0]["payoff_btc"], "0.00500000");
+        assert_eq!(report["totals"]["contracts"], 1);
+        let req = test::TestRequest::get().uri("/settlements?from=2020-01-01&to=2019-12-31").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+        // Only the buyer may exercise: contract 4 was bought anonymously
+        assert_eq!(test::call_service(&app, post(contract(OptionSide::Put, 105_000.0, 0.1, 86_400), "american")).await.status(), 200);
+        let key = api_keys::rotate_api_key(&pool.get().unwrap(), "tests", "test").unwrap();
+        let req = test::TestRequest::post()
+            .uri("/contract/4/exercise")
+            .insert_header((api_keys::API_KEY_HEADER, key.as_str()))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 401);
+    }
+
+    #[actix_web::test]
+    async fn test_partial_and_full_close() {
+        let state = test_state(Some(1_000_000_000));
+        let app = test_app!(state);
+        let post = test::TestRequest::post()
+            .uri("/contract")
+            .set_json(contract(OptionSide::Put, 95_000.0, 0.5, 7 * 86_400))
+            .to_request();
+        assert_eq!(test::call_service(&app, post).await.status(), 200);
+        let close = |quantity: f64| {
+            test::TestRequest::post()
+                .uri("/contract/1/close")
+                .set_json(serde_json::json!({ "quantity": quantity }))
+                .to_request()
+        };
+
+        let body: Value = test::call_and_read_body_json(&app, close(0.2)).await;
+        assert_eq!(body["status"], "open");
+        assert_eq!(body["closed_quantity"], 0.2);
+        assert_eq!(body["closed_quantity_now"], "0.20000000");
+        assert!(body["close_price_usd"].as_f64().unwrap() > 0.0);
+        assert!((body["proceeds_usd"].as_f64().unwrap() - body["close_price_usd"].as_f64().unwrap() * 0.2).abs() < 1e-6);
+        assert!(body["released_margin_usd"].as_f64().unwrap() > 0.0);
+
+        // Positions and the contract list only count what is still open
+        let positions: Vec<Value> =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/positions").to_request()).await;
+        assert!((positions[0]["net_quantity"].as_f64().unwrap() - 0.3).abs() < 1e-9);
+        let contracts: Vec<Value> =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/contracts").to_request()).await;
+        assert_eq!(contracts[0]["quantity"], "0.50000000");
+        assert_eq!(contracts[0]["open_quantity"], "0.30000000");
+
+        // Cannot close more than is open
+        let resp = test::call_service(&app, close(0.4)).await;
+        assert_eq!(resp.status(), 400);
+        let body: Value = test::read_body_json(resp).await;
+        assert!(body["message"].as_str().unwrap().contains("at most the open quantity 0.30000000"));
+
+        let body: Value = test::call_and_read_body_json(&app, close(0.3)).await;
+        assert_eq!(body["status"], "closed");
+        let positions: Vec<Value> =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/positions").to_request()).await;
+        assert!(positions.is_empty());
+        assert_eq!(test::call_service(&app, close(0.1)).await.status(), 400);
+    }
+
+    #[actix_web::test]
+    async fn test_orderbook_quotes_can_be_taken() {
+        let state = test_state(Some(1_000_000_000));
+        let app = test_app!(state);
+
+        let book: Vec<Value> =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/orderbook").to_request()).await;
+        assert!(!book.is_empty());
+        let quote = book
+            .iter()
+            .find(|q| q["side"] == "Put" && q["remaining"].as_f64().unwrap() > 0.1)
+            .unwrap()
+            .clone();
+        assert!(quote["product_symbol"].as_str().unwrap().starts_with("BTC-"));
+        assert_eq!(quote["size"], quote["remaining"]);
+        assert!(quote["valid_until"].as_i64().unwrap() > quote["posted_at"].as_i64().unwrap());
+
+        // The same quotes are served until they expire
+        let again: Vec<Value> =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/orderbook").to_request()).await;
+        assert_eq!(again[0]["id"], book[0]["id"]);
+
+        let take = |quantity: f64| {
+            test::TestRequest::post()
+                .uri("/orderbook/take")
+                .set_json(serde_json::json!({ "quote_id": quote["id"], "quantity": quantity }))
+                .to_request()
+        };
+        let body: Value = test::call_and_read_body_json(&app, take(0.1)).await;
+        assert_eq!(body["contract_id"], 1);
+        let remaining = quote["remaining"].as_f64().unwrap() - 0.1;
+        assert!((body["quote"]["remaining"].as_f64().unwrap() - remaining).abs() < 1e-8);
+
+        // The contract is at the quote's terms and price
+        let contract: Value =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/contract/1").to_request()).await;
+        assert_eq!(contract["strike_price"], quote["strike_price"]);
+        assert_eq!(contract["expires"], quote["expires"]);
+        assert_eq!(contract["premium"], quote["price"]);
+
+        // Cannot take more than is left on the quote
+        let resp = test::call_service(&app, take(remaining + 0.01)).await;
+        assert_eq!(resp.status(), 400);
+        let body: Value = test::read_body_json(resp).await;
+        assert!(body["message"].as_str().unwrap().contains("left on quote"));
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri("/orderbook/take")
+                .set_json(serde_json::json!({ "quote_id": 100_000, "quantity": 0.1 }))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), 404);
+    }
+
+    // Pool wallet that has received one payment of `amount_sats`, confirmed at height 100 of 101
+    struct PaidWallet {
+        amount_sats: u64,
+    }
+
+    #[async_trait]
+    impl WalletSource for PaidWallet {
+        async fn get_wallet_balance(&self, address: &str) -> Result<WalletBalance, MutinyWalletError> {
+            FakeWallet(Some(1_000_000_000)).get_wallet_balance(address).await
+        }
+
+        async fn get_address_transactions(&self, address: &str) -> Result<Vec<Transaction>, MutinyWalletError> {
+            Ok(vec![serde_json::from_value(serde_json::json!({
+                "txid": "ab".repeat(32),
+                "version": 2,
+                "locktime": 0,
+                "vin": [],
+                "vout": [{
+                    "scriptpubkey": "",
+                    "scriptpubkey_asm": "",
+                    "scriptpubkey_type": "v0_p2wpkh",
+                    "scriptpubkey_address": address,
+                    "value": self.amount_sats
+                }],
+                "size": 200,
+                "weight": 800,
+                "fee": 300,
+                "status": { "confirmed": true, "block_height": 100, "block_hash": null, "block_time": null }
+            }))
+            .unwrap()])
+        }
+
+        async fn get_tip_height(&self) -> Result<u64, MutinyWalletError> {
+            Ok(101)
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_contract_opens_once_premium_is_paid() {
+        let pool = db::create_in_memory_pool().unwrap();
+        let state = Arc::new(
+            AppState::new(
+                Repository::new(pool.clone()),
+                Arc::new(FakeIv(0.5)),
+                Arc::new(FakePrice(BTC_PRICE)),
+                Arc::new(FakeWallet(Some(1_000_000_000))),
+                "test-pool-address".to_string(),
+                GridConfig::default(),
+                Duration::from_secs(5),
+            )
+            .with_auth(DEV_AUTH)
+            .with_payments(PaymentConfig { required: true, confirmations: 2, ..Default::default() }),
+        );
+        let app = test_app!(state);
+
+        let post = test::TestRequest::post()
+            .uri("/contract")
+            .set_json(contract(OptionSide::Put, 95_000.0, 0.1, 7 * 86_400))
+            .to_request();
+        let resp = test::call_service(&app, post).await;
+        assert_eq!(resp.status(), 202);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["contract_id"], 1);
+        assert_eq!(body["payment"]["status"], "pending");
+        assert_eq!(body["payment"]["address"], "test-pool-address");
+        assert_eq!(body["payment"]["amount_sats"], 100_000);
+        assert_eq!(body["payment"]["amount"], "0.00100000");
+        assert_eq!(body["payment"]["confirmations_required"], 2);
+
+        // Pending contracts hold collateral but cannot be closed
+        let record: Value =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/contract/1").to_request()).await;
+        assert_eq!(record["status"], "pending");
+        assert!(record["margin_usd"].as_f64().unwrap() > 0.0);
+        let close = test::TestRequest::post()
+            .uri("/contract/1/close")
+            .set_json(serde_json::json!({ "quantity": 0.1 }))
+            .to_request();
+        assert_eq!(test::call_service(&app, close).await.status(), 400);
+
+        let repository = Repository::new(pool);
+        let now = Utc::now().timestamp();
+        let wallet = PaidWallet { amount_sats: 100_000 };
+        assert_eq!(payments::check_payments(&repository, &wallet, None, now).await.unwrap(), (1, 0));
+
+        let payment: Value =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/contract/1/payment").to_request()).await;
+        assert_eq!(payment["status"], "confirmed");
+        assert_eq!(payment["txid"], "ab".repeat(32));
+        let record: Value =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/contract/1").to_request()).await;
+        assert_eq!(record["status"], "open");
+
+        // The same transaction cannot pay for a second contract
+        let post = test::TestRequest::post()
+            .uri("/contract")
+            .set_json(contract(OptionSide::Put, 95_000.0, 0.1, 7 * 86_400))
+            .to_request();
+        assert_eq!(test::call_service(&app, post).await.status(), 202);
+        assert_eq!(payments::check_payments(&repository, &wallet, None, now).await.unwrap(), (0, 0));
+        let due = now + PaymentConfig::default().timeout.as_secs() as i64;
+        assert_eq!(payments::check_payments(&repository, &wallet, None, due).await.unwrap(), (0, 1));
+        let record: Value =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/contract/2").to_request()).await;
+        assert_eq!(record["status"], "cancelled");
+    }
+
+    // Lightning node whose invoices are paid as soon as they are issued
+    struct PaidLightning;
+
+    #[async_trait]
+    impl LightningNode for PaidLightning {
+        fn name(&self) -> &'static str {
+            "test"
+        }
+
+        async fn create_invoice(&self, amount_sats: i64, _memo: &str, _expiry_secs: i64) -> Result<Invoice, LightningError> {
+            Ok(Invoice { payment_hash: "cd".repeat(32), bolt11: format!("lntbs{}n1test", amount_sats * 10) })
+        }
+
+        async fn lookup_invoice(&self, _payment_hash: &str) -> Result<InvoiceState, LightningError> {
+            Ok(InvoiceState::Settled { settled_at: Utc::now().timestamp() })
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_premium_paid_over_lightning() {
+        let pool = db::create_in_memory_pool().unwrap();
+        let state = |lightning: Option<Arc<dyn LightningNode>>| {
+            Arc::new(
+                AppState::new(
+                    Repository::new(pool.clone()),
+                    Arc::new(FakeIv(0.5)),
+                    Arc::new(FakePrice(BTC_PRICE)),
+                    Arc::new(FakeWallet(Some(1_000_000_000))),
+                    "test-pool-address".to_string(),
+                    GridConfig::default(),
+                    Duration::from_secs(5),
+                )
+                .with_auth(DEV_AUTH)
+                .with_payments(PaymentConfig { required: true, ..Default::default() })
+                .with_lightning(lightning),
+            )
+        };
+        let mut body = serde_json::to_value(contract(OptionSide::Put, 95_000.0, 0.1, 7 * 86_400)).unwrap();
+        body["payment_method"] = "lightning".into();
+
+        // Refused when the pool has no Lightning node
+        let app = test_app!(state(None));
+        let post = test::TestRequest::post().uri("/contract").set_json(&body).to_request();
+        assert_eq!(test::call_service(&app, post).await.status(), 400);
+
+        let app = test_app!(state(Some(Arc::new(PaidLightning))));
+        let post = test::TestRequest::post().uri("/contract").set_json(&body).to_request();
+        let resp = test::call_service(&app, post).await;
+        assert_eq!(resp.status(), 202);
+        let created: Value = test::read_body_json(resp).await;
+        assert_eq!(created["payment"]["method"], "lightning");
+        assert_eq!(created["payment"]["invoice"], "lntbs1000000n1test");
+        assert_eq!(created["payment"]["payment_hash"], "cd".repeat(32));
+        assert_eq!(created["payment"]["address"], Value::Null);
+        assert_eq!(created["payment"]["amount_sats"], 100_000);
+
+        let repository = Repository::new(pool.clone());
+        let now = Utc::now().timestamp();
+        // Invoices can only be checked with a node
+        assert!(payments::check_payments(&repository, &FakeWallet(None), None, now).await.is_err());
+        let opened = payments::check_payments(&repository, &FakeWallet(None), Some(&PaidLightning), now).await.unwrap();
+        assert_eq!(opened, (1, 0));
+        let record: Value =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/contract/1").to_request()).await;
+        assert_eq!(record["status"], "open");
+        let payment: Value =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/contract/1/payment").to_request()).await;
+        assert_eq!(payment["status"], "confirmed");
+        assert_eq!(payment["txid"], Value::Null);
+    }
+
+    #[actix_web::test]
+    async fn test_contract_dlc_descriptor() {
+        // Without a signing key there is no oracle to announce the event
+        let state = test_state(Some(1_000_000_000));
+        let app = test_app!(state);
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/contract/1/dlc").to_request()).await;
+        assert_eq!(resp.status(), 503);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["error_code"], "ORACLE_DISABLED");
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/attestations/2020-01-01").to_request()).await;
+        assert_eq!(resp.status(), 503);
+
+        let signer = Arc::new(OracleSigner::generate());
+        let state = Arc::new(
+            AppState::new(
+                Repository::new(db::create_in_memory_pool().unwrap()),
+                Arc::new(FakeIv(0.5)),
+                Arc::new(FakePrice(BTC_PRICE)),
+                Arc::new(FakeWallet(Some(1_000_000_000))),
+                "test-pool-address".to_string(),
+                GridConfig::default(),
+                Duration::from_secs(5),
+            )
+            .with_auth(DEV_AUTH)
+            .with_oracle_signer(Some(signer.clone())),
+        );
+        let app = test_app!(state);
+        let post = test::TestRequest::post()
+            .uri("/contract")
+            .set_json(contract(OptionSide::Call, 105_000.0, 0.2, 7 * 86_400))
+            .to_request();
+        assert!(test::call_service(&app, post).await.status().is_success());
+
+        let descriptor: Value =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/contract/1/dlc").to_request()).await;
+        assert_eq!(descriptor["contract_id"], 1);
+        assert_eq!(descriptor["total_collateral_sats"], 20_000_000);
+        assert_eq!(descriptor["accept_collateral_sats"], 0);
+        assert_eq!(descriptor["contract_descriptor"]["num_digits"], 20);
+        let event = &descriptor["oracle_announcement"]["oracle_event"];
+        let expires = event["event_maturity_epoch"].as_i64().unwrap();
+        assert_eq!(event["event_id"], format!("btcusd-{}", expires));
+        assert_eq!(event["event_descriptor"]["unit"], "USD");
+        assert_eq!(descriptor["oracle_announcement"]["oracle_public_key"], signer.public_key_hex());
+        // The digits of the price are attested with the announced nonces
+        let nonces = event["oracle_nonces"].as_array().unwrap();
+        assert_eq!(nonces.len(), 20);
+        let event_id = event["event_id"].as_str().unwrap();
+        for (signature, nonce) in signer.sign_digits(event_id, &dlc::outcome_digits(BTC_PRICE, 20)).iter().zip(nonces) {
+            assert_eq!(signature[..64], *nonce.as_str().unwrap());
+        }
+        let points = descriptor["contract_descriptor"]["payout_points"].as_array().unwrap();
+        assert_eq!(points[0]["outcome"], 0);
+        assert_eq!(points[0]["accept_payout_sats"], 0);
+
+        let missing = test::TestRequest::get().uri("/contract/99/dlc").to_request();
+        assert_eq!(test::call_service(&app, missing).await.status(), 404);
+    }
+
+    #[actix_web::test]
+    async fn test_product_catalog() {
+        let pool = db::create_in_memory_pool().unwrap();
+        let state = test_state_with_pool(pool.clone(), Some(100_000_000));
+        let app = test_app!(state);
+        let repository = Repository::new(pool);
+        let config = CatalogConfig::default();
+        let now = Utc::now().timestamp();
+
+        // 11 strikes around spot, both sides, 5 tenors; listing again adds nothing
+        let grid = GridConfig::default();
+        let refreshed = catalog::refresh_catalog(&repository, &FakePrice(BTC_PRICE), &grid, &[Asset::Btc], &config, now).await.unwrap();
+        assert_eq!(refreshed, (110, 0));
+        let refreshed = catalog::refresh_catalog(&repository, &FakePrice(BTC_PRICE), &grid, &[Asset::Btc], &config, now).await.unwrap();
+        assert_eq!(refreshed, (0, 0));
+
+        let products: Vec<Value> =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/products?asset=BTC").to_request()).await;
+        assert_eq!(products.len(), 110);
+        assert_eq!(products[0]["status"], "active");
+        assert_eq!(products[0]["expires"], config.maturity("1d", now).unwrap());
+        assert!(products.iter().any(|p| p["strike_price"] == BTC_PRICE));
+
+        // At the first maturity its products expire, and tenors falling between listed
+        // maturities are listed
+        let first_maturity = config.maturity("1d", now).unwrap();
+        let refreshed = catalog::refresh_catalog(&repository, &FakePrice(BTC_PRICE), &grid, &[Asset::Btc], &config, first_maturity).await.unwrap();
+        assert_eq!(refreshed, (66, 22));
+        let expired: Vec<Value> =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/products?status=expired").to_request()).await;
+        assert_eq!(expired.len(), 22);
+    }
+
+    #[actix_web::test]
+    async fn test_attestations_by_date() {
+        let pool = db::create_in_memory_pool().unwrap();
+        let signer = Arc::new(OracleSigner::generate());
+        let state = Arc::new(
+            AppState::new(
+                Repository::new(pool.clone()),
+                Arc::new(FakeIv(0.5)),
+                Arc::new(FakePrice(BTC_PRICE)),
+                Arc::new(FakeWallet(Some(1_000_000_000))),
+                "test-pool-address".to_string(),
+                GridConfig::default(),
+                Duration::from_secs(5),
+            )
+            .with_auth(DEV_AUTH)
+            .with_oracle_signer(Some(signer.clone())),
+        );
+        let app = test_app!(state);
+        let post = test::TestRequest::post()
+            .uri("/contract")
+            .set_json(contract(OptionSide::Call, 105_000.0, 0.2, 60))
+            .to_request();
+        assert!(test::call_service(&app, post).await.status().is_success());
+
+        // Nothing to attest before expiry
+        let repository = Repository::new(pool);
+        let now = Utc::now().timestamp();
+        let config = SettlementConfig { min_samples: 1, ..Default::default() };
+        assert_eq!(attestation::attest_matured(&repository, &FakePrice(BTC_PRICE), &signer, &config, 20, now).await.unwrap(), 0);
+        // The maturity is inside the settlement window, so the price is sampled
+        assert_eq!(settlement::sample_prices(&repository, &FakePrice(BTC_PRICE), &config, now).await.unwrap(), 1);
+        assert_eq!(attestation::attest_matured(&repository, &FakePrice(BTC_PRICE), &signer, &config, 20, now + 120).await.unwrap(), 1);
+        assert_eq!(attestation::attest_matured(&repository, &FakePrice(BTC_PRICE), &signer, &config, 20, now + 180).await.unwrap(), 0);
+
+        let expires = repository.contract_record(1).await.unwrap().expires;
+        let date = chrono::DateTime::from_timestamp(expires, 0).unwrap().format("%Y-%m-%d").to_string();
+        let body: Value = test::call_and_read_body_json(
+            &app,
+            test::TestRequest::get().uri(&format!("/attestations/{}", date)).to_request(),
+        )
+        .await;
+        let attested = &body["attestations"][0];
+        assert_eq!(attested["timestamp"], expires);
+        assert_eq!(attested["price"], BTC_PRICE);
+        assert_eq!(attested["method"], "twap");
+        assert_eq!(attested["sample_count"], 1);
+        assert_eq!(attested["event_id"], format!("btcusd-{}", expires));
+        assert_eq!(attested["public_key"], signer.public_key_hex());
+        assert!(attestation::verify(
+            &signer.public_key_hex(),
+            attested["message"].as_str().unwrap(),
+            attested["signature"].as_str().unwrap()
+        ));
+        // Every digit of the price is signed for DLCs, most significant first
+        let digit_signatures = attested["digit_signatures"].as_array().unwrap();
+        assert_eq!(digit_signatures.len(), 20);
+        for (digit, signature) in dlc::outcome_digits(BTC_PRICE, 20).iter().zip(digit_signatures) {
+            assert!(attestation::verify(&signer.public_key_hex(), &digit.to_string(), signature.as_str().unwrap()));
+        }
+
+        let empty: Value =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/attestations/2020-01-01").to_request()).await;
+        assert_eq!(empty["attestations"].as_array().unwrap().len(), 0);
+        let invalid = test::TestRequest::get().uri("/attestations/yesterday").to_request();
+        assert_eq!(test::call_service(&app, invalid).await.status(), 400);
+    }
+
+    #[actix_web::test]
+    async fn test_positions_group_contracts_by_product() {
+        let state = test_state(Some(100_000_000));
+        let app = test_app!(state);
+
+        let expires_in = 7 * 86_400;
+        let mut put = contract(OptionSide::Put, 95_000.0, 0.1, expires_in);
+        for premium in [0.01, 0.02] {
+            put.premium = premium;
+            let resp = test::call_service(&app, test::TestRequest::post().uri("/contract").set_json(&put).to_request()).await;
+            assert_eq!(resp.status(), 200);
+        }
+        let call = contract(OptionSide::Call, 105_000.0, 0.05, expires_in);
+        let resp = test::call_service(&app, test::TestRequest::post().uri("/contract").set_json(&call).to_request()).await;
+        assert_eq!(resp.status(), 200);
+
+        let positions: Vec<Value> =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/positions").to_request()).await;
+        assert_eq!(positions.len(), 2);
+        // Calls sort before puts within an underlying
+        assert_eq!(positions[0]["side"], "Call");
+        assert_eq!(positions[1]["side"], "Put");
+        assert_eq!(positions[1]["contract_count"], 2);
+        assert!((positions[1]["net_quantity"].as_f64().unwrap() - 0.2).abs() < 1e-9);
+        assert!((positions[1]["average_premium"].as_f64().unwrap() - 0.015).abs() < 1e-9);
+        assert!(positions[1]["mark_premium_usd"].as_f64().unwrap() > 0.0);
+        assert!(positions[1]["margin_usd"].as_f64().unwrap() > 0.0);
+        assert!(positions[1]["product_symbol"].as_str().unwrap().starts_with("BTC-"));
+
+        let eth: Vec<Value> =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/positions?asset=ETH").to_request()).await;
+        assert!(eth.is_empty());
+    }
+
     #[actix_web::test]
-    #[ignore] // These tests require full app setup which needs external services
-    async fn test_options_table_endpoint() {
-        // This test would require setting up the full AppState with all dependencies
-        // Including gRPC price oracle and Mutiny wallet
-        // Better suited for integration tests with docker-compose
+    async fn test_trading_state_blocks_new_positions() {
+        let state = test_state(Some(100_000_000));
+        let app = test_app!(state);
+
+        let status: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri("/tradingState").to_request()).await;
+        assert_eq!(status["state"], "open");
+
+        let req = test::TestRequest::post()
+            .uri("/admin/tradingState")
+            .set_json(serde_json::json!({"state": "reduce_only", "reason": "extreme volatility"}))
+            .to_request();
+        let status: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(status["state"], "reduce_only");
+        assert_eq!(status["reason"], "extreme volatility");
+        assert_eq!(status["automatic"], false);
+
+        let put = contract(OptionSide::Put, 95_000.0, 0.01, 86_400);
+        let resp = test::call_service(&app, test::TestRequest::post().uri("/contract").set_json(&put).to_request()).await;
+        assert_eq!(resp.status(), 503);
+        let body: Value = test::read_body_json(resp).await;
+        assert!(body["message"].as_str().unwrap().contains("reduce-only"));
+        let table: Vec<Value> =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/optionsTable").to_request()).await;
+        assert!(table.iter().all(|row| row["warnings"] == serde_json::json!(["no_capacity"])));
+
+        let health: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri("/health").to_request()).await;
+        assert_eq!(health["trading_state"], "reduce_only");
+
+        let req = test::TestRequest::post()
+            .uri("/admin/tradingState")
+            .set_json(serde_json::json!({"state": "open"}))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+        let resp = test::call_service(&app, test::TestRequest::post().uri("/contract").set_json(&put).to_request()).await;
+        assert_eq!(resp.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_high_utilization_switches_to_reduce_only() {
+        struct RecordingSink(std::sync::Mutex<Vec<WebhookEvent>>);
+
+        #[async_trait]
+        impl EventSink for RecordingSink {
+            async fn publish(&self, event: &WebhookEvent) -> Result<(), SourceError> {
+                self.0.lock().unwrap().push(event.clone());
+                Ok(())
+            }
+        }
+
+        let db_pool = db::create_in_memory_pool().unwrap();
+        let sink = Arc::new(RecordingSink(std::sync::Mutex::new(Vec::new())));
+        let config = UtilizationMonitorConfig::default();
+        let state = Arc::new(
+            AppState::new(
+                Repository::new(db_pool.clone()),
+                Arc::new(FakeIv(0.5)),
+                Arc::new(FakePrice(BTC_PRICE)),
+                Arc::new(FakeWallet(Some(100_000_000))),
+                "test-pool-address".to_string(),
+                GridConfig::default(),
+                Duration::from_secs(5),
+            )
+            .with_auth(DEV_AUTH)
+            .with_utilization_monitor(Some(config)),
+        );
+        let app = test_app!(state);
+        let max_quantities = || async {
+            let table: Vec<Value> =
+                test::call_and_read_body_json(&app, test::TestRequest::get().uri("/optionsTable").to_request()).await;
+            table.into_iter().map(|row| row["max_quantity"].as_str().unwrap().to_string()).collect::<Vec<_>>()
+        };
+        assert!(max_quantities().await.iter().any(|q| q != "0.00000000"));
+        assert_eq!(utilization::check_utilization(&state, &config).await.unwrap(), None);
+
+        // A put whose margin takes up more of the $50k of collateral than the threshold allows
+        Repository::new(db_pool.clone()).insert_contract(contract(OptionSide::Put, 95_000.0, 0.45, 86_400)).await.unwrap();
+        let status = utilization::check_utilization(&state, &config).await.unwrap().unwrap();
+        assert_eq!(status.state, TradingState::ReduceOnly);
+        assert!(status.automatic);
+        assert_eq!(status.updated_by.as_deref(), Some(utilization::UTILIZATION_MONITOR_ACTOR));
+        // Already reduce-only: nothing more to do
+        assert_eq!(utilization::check_utilization(&state, &config).await.unwrap(), None);
+
+        // The change and its risk alert go out through the outbox
+        let published = outbox::publish_pending(&Repository::new(db_pool), Some(sink.as_ref()), state.events(), 100).await.unwrap();
+        assert_eq!(published, 2);
+        let events = sink.0.lock().unwrap().clone();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].event, "trading_state.changed");
+        assert_eq!(events[0].data["trading"]["state"], "reduce_only");
+        assert_eq!(events[0].data["previous"]["state"], "open");
+        assert_eq!(events[1].event, "risk.alert");
+        assert!(events[1].data["reason"].as_str().unwrap().starts_with("collateral utilization"));
+
+        let risk: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri("/riskStatus").to_request()).await;
+        assert_eq!(risk["trading"]["state"], "reduce_only");
+        assert_eq!(risk["total_collateral_usd"], 50_000.0);
+        assert!(risk["utilization_percent"].as_f64().unwrap() >= 90.0);
+        assert_eq!(risk["utilization_monitor"]["reduce_only_percent"], 90.0);
+
+        // Nothing is for sale while the venue is reduce-only
+        assert!(max_quantities().await.iter().all(|q| q == "0.00000000"));
+    }
+
+    #[actix_web::test]
+    async fn test_iv_status_and_refresh() {
+        let state = test_state(Some(100_000_000));
+        let app = test_app!(state);
+
+        let status: Vec<Value> =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/admin/iv/status").to_request()).await;
+        assert_eq!(status.len(), 1);
+        assert_eq!(status[0]["asset"], "BTC");
+        assert_eq!(status[0]["entries"], 1);
+        // The flat test surface is never refreshed
+        assert!(status[0]["last_refresh_at"].is_null());
+        assert!(status[0]["refresh_interval_secs"].is_null());
+
+        let resp = test::call_service(&app, test::TestRequest::post().uri("/admin/iv/refresh").to_request()).await;
+        assert_eq!(resp.status(), 200);
+        let status: Vec<Value> = test::read_body_json(resp).await;
+        assert!(status[0]["last_error"].is_null());
+
+        // The flat test surface has no fitted smiles
+        let fits: Vec<Value> =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/ivSurface/fit").to_request()).await;
+        assert!(fits.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_post_contract_enforces_position_limits() {
+        let state = Arc::new(
+            AppState::new(
+                Repository::new(db::create_in_memory_pool().unwrap()),
+                Arc::new(FakeIv(0.5)),
+                Arc::new(FakePrice(BTC_PRICE)),
+                Arc::new(FakeWallet(Some(100_000_000))),
+                "test-pool-address".to_string(),
+                GridConfig::default(),
+                Duration::from_secs(5),
+            )
+            .with_auth(DEV_AUTH)
+            .with_position_limits(PositionLimits {
+                max_product_quantity: Some(0.15),
+                max_counterparty_notional_usd: Some(0.25 * BTC_PRICE),
+                ..Default::default()
+            }),
+        );
+        let app = test_app!(state);
+        let post = |contract: &Contract| test::TestRequest::post().uri("/contract").set_json(contract).to_request();
+
+        let put = contract(OptionSide::Put, 95_000.0, 0.1, 86_400);
+        assert_eq!(test::call_service(&app, post(&put)).await.status(), 200);
+        let resp = test::call_service(&app, post(&put)).await;
+        assert_eq!(resp.status(), 400);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"], "Position limit exceeded");
+        assert!(body["message"].as_str().unwrap().contains("open quantity of BTC Put 95000"));
+
+        // Another strike is a separate product, but counts towards the counterparty's notional
+        let other_strike = contract(OptionSide::Put, 90_000.0, 0.1, 86_400);
+        assert_eq!(test::call_service(&app, post(&other_strike)).await.status(), 200);
+        let call = contract(OptionSide::Call, 105_000.0, 0.1, 86_400);
+        let resp = test::call_service(&app, post(&call)).await;
+        assert_eq!(resp.status(), 400);
+        let body: Value = test::read_body_json(resp).await;
+        assert!(body["message"].as_str().unwrap().contains("open notional of counterparty anonymous"));
+    }
+
+    #[actix_web::test]
+    async fn test_post_contract_enforces_greek_limits() {
+        let state = Arc::new(
+            AppState::new(
+                Repository::new(db::create_in_memory_pool().unwrap()),
+                Arc::new(FakeIv(0.5)),
+                Arc::new(FakePrice(BTC_PRICE)),
+                Arc::new(FakeWallet(Some(100_000_000))),
+                "test-pool-address".to_string(),
+                GridConfig::default(),
+                Duration::from_secs(5),
+            )
+            .with_auth(DEV_AUTH)
+            .with_position_limits(PositionLimits { max_net_delta_usd: Some(0.1 * BTC_PRICE), ..Default::default() }),
+        );
+        let app = test_app!(state);
+        let post = |contract: &Contract| test::TestRequest::post().uri("/contract").set_json(contract).to_request();
+
+        // An at-the-money call sold leaves the pool short about half its quantity
+        let call = contract(OptionSide::Call, 100_000.0, 0.1, 86_400);
+        assert_eq!(test::call_service(&app, post(&call)).await.status(), 200);
+        let resp = test::call_service(&app, post(&call)).await;
+        assert_eq!(resp.status(), 400);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["error_code"], "POSITION_LIMIT_EXCEEDED");
+        assert_eq!(body["details"]["limit"], "max_net_delta_usd");
+        assert!(body["message"].as_str().unwrap().contains("net delta of the pool would be $-"));
+
+        // A put sold offsets the short delta
+        let put = contract(OptionSide::Put, 100_000.0, 0.1, 86_400);
+        assert_eq!(test::call_service(&app, post(&put)).await.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_post_contract_enforces_credit_tiers() {
+        let state = test_state(Some(100_000_000));
+        let app = test_app!(state);
+        let post = |contract: &Contract| test::TestRequest::post().uri("/contract").set_json(contract).to_request();
+
+        let tier = serde_json::json!({
+            "max_open_notional_usd": 0.15 * BTC_PRICE,
+            "max_trade_notional_usd": 0.1 * BTC_PRICE,
+            "allowed_products": ["btc-p"],
+        });
+        let req = test::TestRequest::put().uri("/admin/creditTiers/retail").set_json(&tier).to_request();
+        let body: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["allowed_products"], serde_json::json!(["BTC-P"]));
+        let req = test::TestRequest::put()
+            .uri("/admin/accounts/anonymous/creditTier")
+            .set_json(serde_json::json!({"tier": "retail"}))
+            .to_request();
+        let body: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["assigned_tier"], "retail");
+        assert_eq!(body["effective_tier"]["name"], "retail");
+
+        // Calls are not in the tier, and puts are capped per contract and in total
+        let resp = test::call_service(&app, post(&contract(OptionSide::Call, 105_000.0, 0.05, 86_400))).await;
+        assert_eq!(resp.status(), 400);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["error_code"], "PRODUCT_NOT_ALLOWED");
+        let resp = test::call_service(&app, post(&contract(OptionSide::Put, 95_000.0, 0.2, 86_400))).await;
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["details"]["limit"], "tier_max_trade_notional_usd");
+        let put = contract(OptionSide::Put, 95_000.0, 0.1, 86_400);
+        assert_eq!(test::call_service(&app, post(&put)).await.status(), 200);
+        let resp = test::call_service(&app, post(&put)).await;
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["error_code"], "POSITION_LIMIT_EXCEEDED");
+        assert_eq!(body["details"]["limit"], "tier_max_open_notional_usd");
+
+        // Back on the default tier, which is not defined, only the venue limits apply
+        let req = test::TestRequest::put()
+            .uri("/admin/accounts/anonymous/creditTier")
+            .set_json(serde_json::json!({"tier": null}))
+            .to_request();
+        let body: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["effective_tier"], Value::Null);
+        assert_eq!(test::call_service(&app, post(&put)).await.status(), 200);
+        let req = test::TestRequest::delete().uri("/admin/creditTiers/retail").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 204);
+        let body: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri("/admin/creditTiers").to_request()).await;
+        assert_eq!(body, serde_json::json!({"tiers": [], "assignments": []}));
+    }
+
+    #[actix_web::test]
+    async fn test_v2_responses_carry_codes_and_iso_expiries() {
+        let state = test_state(Some(1_000_000_000));
+        let app = test_app!(state);
+
+        // v2 clients may send the codes back
+        let mut body = serde_json::to_value(contract(OptionSide::Put, 95_000.0, 0.1, 7 * 86_400)).unwrap();
+        body["side"] = "put".into();
+        body["underlying"] = "btc".into();
+        let expires = body["expires"].as_i64().unwrap();
+        let req = test::TestRequest::post().uri("/v2/contract").set_json(&body).to_request();
+        let created: Value = test::call_and_read_body_json(&app, req).await;
+        let id = created["contract_id"].as_i64().unwrap();
+
+        let req = test::TestRequest::get().uri(&format!("/v2/contract/{}", id)).to_request();
+        let v2: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!((v2["side"].as_str(), v2["side_code"].as_str()), (Some("Put"), Some("put")));
+        assert_eq!(v2["underlying_code"], "btc");
+        let iso = chrono::DateTime::from_timestamp(expires, 0).unwrap().to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+        assert_eq!(v2["expires_iso"], iso);
+
+        // v1 is unchanged
+        let req = test::TestRequest::get().uri(&format!("/contract/{}", id)).to_request();
+        let v1: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(v1["side"], "Put");
+        assert!(v1.get("side_code").is_none() && v1.get("expires_iso").is_none());
+
+        let req = test::TestRequest::get().uri("/v2/contract/999").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 404);
+    }
+
+    #[actix_web::test]
+    async fn test_v1_responses_announce_deprecation() {
+        let state = Arc::new(
+            AppState::new(
+                Repository::new(db::create_in_memory_pool().unwrap()),
+                Arc::new(FakeIv(0.5)),
+                Arc::new(FakePrice(BTC_PRICE)),
+                Arc::new(FakeWallet(Some(1_000_000_000))),
+                "test-pool-address".to_string(),
+                GridConfig::default(),
+                Duration::from_secs(5),
+            )
+            .with_auth(DEV_AUTH)
+            .with_api_v1(api_v1::DeprecationConfig { sunset_at: Some(1_751_328_000), ..Default::default() }),
+        );
+        let app = test_app!(state);
+
+        for uri in ["/v1/contracts", "/contracts"] {
+            let resp = test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(resp.status(), 200);
+            assert_eq!(resp.headers().get("deprecation").unwrap(), "@1792281600");
+            assert_eq!(resp.headers().get("sunset").unwrap(), "Tue, 01 Jul 2025 00:00:00 GMT");
+            assert_eq!(resp.headers().get("link").unwrap(), "</v2/contracts>; rel=\"successor-version\"");
+        }
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/v2/contracts").to_request()).await;
+        assert_eq!(resp.status(), 200);
+        assert!(resp.headers().get("deprecation").is_none() && resp.headers().get("sunset").is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_post_contract_charges_fees() {
+        let state = Arc::new(
+            AppState::new(
+                Repository::new(db::create_in_memory_pool().unwrap()),
+                Arc::new(FakeIv(0.5)),
+                Arc::new(FakePrice(BTC_PRICE)),
+                Arc::new(FakeWallet(Some(1_000_000_000))),
+                "test-pool-address".to_string(),
+                GridConfig::default(),
+                Duration::from_secs(5),
+            )
+            .with_auth(DEV_AUTH)
+            .with_payments(PaymentConfig { required: true, ..Default::default() })
+            .with_fees(FeeSchedule { percent: 1.0, min_fee_sats: 500 }),
+        );
+        let app = test_app!(state);
+        let post = || {
+            test::TestRequest::post()
+                .uri("/contract")
+                .set_json(contract(OptionSide::Put, 95_000.0, 0.1, 7 * 86_400))
+                .to_request()
+        };
+
+        // 1% of the 100,000 sat premium is paid with it
+        let body: Value = test::call_and_read_body_json(&app, post()).await;
+        assert_eq!(body["fee"]["tier"], Value::Null);
+        assert_eq!(body["fee"]["fee_sats"], 1_000);
+        assert_eq!(body["fee"]["fee_usd"], BTC_PRICE * 0.00001);
+        assert_eq!(body["payment"]["amount_sats"], 101_000);
+
+        // Makers in a rebate tier pay the premium less their rebate
+        let req = test::TestRequest::put()
+            .uri("/admin/creditTiers/maker")
+            .set_json(serde_json::json!({"fee_percent": -0.5}))
+            .to_request();
+        let tier: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!((tier["fee_percent"].as_f64(), tier["min_fee_sats"].as_i64()), (Some(-0.5), None));
+        let req = test::TestRequest::put()
+            .uri("/admin/accounts/anonymous/creditTier")
+            .set_json(serde_json::json!({"tier": "maker"}))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+        let body: Value = test::call_and_read_body_json(&app, post()).await;
+        assert_eq!(body["fee"]["tier"], "maker");
+        assert_eq!(body["fee"]["fee_sats"], -500);
+        assert_eq!(body["payment"]["amount_sats"], 99_500);
+
+        let req = test::TestRequest::put()
+            .uri("/admin/creditTiers/maker")
+            .set_json(serde_json::json!({"fee_percent": -100}))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
+    }
+
+    #[actix_web::test]
+    async fn test_referral_codes_are_summarized() {
+        let state = Arc::new(
+            AppState::new(
+                Repository::new(db::create_in_memory_pool().unwrap()),
+                Arc::new(FakeIv(0.5)),
+                Arc::new(FakePrice(BTC_PRICE)),
+                Arc::new(FakeWallet(Some(1_000_000_000))),
+                "test-pool-address".to_string(),
+                GridConfig::default(),
+                Duration::from_secs(5),
+            )
+            .with_auth(DEV_AUTH)
+            .with_fees(FeeSchedule { percent: 1.0, min_fee_sats: 0 }),
+        );
+        let app = test_app!(state);
+        let post = |referral_code: Option<&str>| {
+            let mut body = serde_json::to_value(contract(OptionSide::Put, 95_000.0, 0.1, 7 * 86_400)).unwrap();
+            body["referral_code"] = serde_json::json!(referral_code);
+            test::TestRequest::post().uri("/contract").set_json(body).to_request()
+        };
+        for code in [Some("spring"), Some("SPRING"), None] {
+            assert_eq!(test::call_service(&app, post(code)).await.status(), 200);
+        }
+        assert_eq!(test::call_service(&app, post(Some("not a code"))).await.status(), 400);
+
+        let body: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri("/admin/referrals").to_request()).await;
+        let referrals = body["referrals"].as_array().unwrap();
+        assert_eq!(referrals.len(), 1);
+        assert_eq!(referrals[0]["code"], "SPRING");
+        assert_eq!((referrals[0]["trades"].as_i64(), referrals[0]["accounts"].as_i64()), (Some(2), Some(1)));
+        assert_eq!(referrals[0]["notional_usd"], 2.0 * 0.1 * BTC_PRICE);
+        assert_eq!(referrals[0]["premium_btc"], "0.00200000");
+        assert_eq!(referrals[0]["fee_btc"], "0.00002000");
+
+        let req = test::TestRequest::get().uri("/admin/referrals?from=2030-01-01&to=2029-01-01").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
+    }
+
+    #[actix_web::test]
+    async fn test_post_contract_gated_by_kyc_status() {
+        let state = Arc::new(
+            AppState::new(
+                Repository::new(db::create_in_memory_pool().unwrap()),
+                Arc::new(FakeIv(0.5)),
+                Arc::new(FakePrice(BTC_PRICE)),
+                Arc::new(FakeWallet(Some(100_000_000))),
+                "test-pool-address".to_string(),
+                GridConfig::default(),
+                Duration::from_secs(5),
+            )
+            .with_auth(DEV_AUTH)
+            .with_kyc(Some(KycConfig {
+                limits: HashMap::from([(KycStatus::None, 0.15 * BTC_PRICE)]),
+                verification_url: Some("https://example.com/verify".to_string()),
+            })),
+        );
+        let app = test_app!(state);
+        let put = contract(OptionSide::Put, 95_000.0, 0.1, 86_400);
+        let post = || test::TestRequest::post().uri("/contract").set_json(&put).to_request();
+
+        // Unverified accounts may trade up to their cap in total
+        assert_eq!(test::call_service(&app, post()).await.status(), 200);
+        let resp = test::call_service(&app, post()).await;
+        assert_eq!(resp.status(), 400);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["error_code"], "KYC_REQUIRED");
+        assert_eq!(body["details"]["verification_url"], "https://example.com/verify");
+        assert_eq!(body["details"]["current"], 0.1 * BTC_PRICE);
+
+        let req = test::TestRequest::put()
+            .uri("/admin/accounts/anonymous/kyc")
+            .set_json(serde_json::json!({"kyc_status": "verified"}))
+            .to_request();
+        let body: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["kyc_status"], "verified");
+        assert_eq!(body["cumulative_notional_usd"], 0.1 * BTC_PRICE);
+        assert_eq!(body["max_cumulative_notional_usd"], Value::Null);
+        assert_eq!(test::call_service(&app, post()).await.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_session_roles_guard_routes() {
+        let pool = db::create_in_memory_pool().unwrap();
+        {
+            let conn = pool.get().unwrap();
+            auth::set_user(&conn, "viv", "viewer password", Role::Viewer, "ops", 0).unwrap();
+            auth::set_user(&conn, "tom", "trader password", Role::Trader, "ops", 0).unwrap();
+            auth::set_user(&conn, "ada", "admin password!", Role::Admin, "ops", 0).unwrap();
+        }
+        let jwt = JwtConfig::new(b"0123456789abcdef0123456789abcdef", Duration::from_secs(900)).unwrap();
+        let state = Arc::new(
+            AppState::new(
+                Repository::new(pool.clone()),
+                Arc::new(FakeIv(0.5)),
+                Arc::new(FakePrice(BTC_PRICE)),
+                Arc::new(FakeWallet(Some(100_000_000))),
+                "test-pool-address".to_string(),
+                GridConfig::default(),
+                Duration::from_secs(5),
+            )
+            .with_jwt(Some(jwt.clone())),
+        );
+        let repository = Repository::new(pool);
+        let app = test_app!(state);
+        let login = |username: &str, password: &str| {
+            test::TestRequest::post()
+                .uri("/auth/login")
+                .set_json(serde_json::json!({"username": username, "password": password}))
+                .to_request()
+        };
+        let bearer = |token: &str| ("Authorization", format!("Bearer {}", token));
+
+        let resp = test::call_service(&app, login("tom", "wrong password")).await;
+        assert_eq!(resp.status(), 401);
+        let mut tokens = HashMap::new();
+        for (username, password) in [("viv", "viewer password"), ("tom", "trader password"), ("ada", "admin password!")] {
+            let session: Value = test::call_and_read_body_json(&app, login(username, password)).await;
+            assert_eq!(session["token_type"], "Bearer");
+            tokens.insert(username, session["token"].as_str().unwrap().to_string());
+        }
+        let req = test::TestRequest::get().uri("/auth/session").insert_header(bearer(&tokens["tom"])).to_request();
+        let claims: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!((claims["sub"].as_str(), claims["role"].as_str()), (Some("tom"), Some("trader")));
+
+        // Each role reaches only its own routes
+        let put = contract(OptionSide::Put, 95_000.0, 0.1, 86_400);
+        let post = |token: Option<&str>| {
+            let req = test::TestRequest::post().uri("/contract").set_json(&put);
+            match token {
+                Some(token) => req.insert_header(bearer(token)).to_request(),
+                None => req.to_request(),
+            }
+        };
+        assert_eq!(test::call_service(&app, post(None)).await.status(), 401);
+        let resp = test::call_service(&app, post(Some(&tokens["viv"]))).await;
+        assert_eq!(resp.status(), 403);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["error_code"], "FORBIDDEN");
+        assert_eq!(body["details"]["required_role"], "trader");
+        assert_eq!(test::call_service(&app, post(Some(&tokens["tom"]))).await.status(), 200);
+        assert_eq!(repository.contract_record(1).await.unwrap().counterparty.as_deref(), Some("tom"));
+
+        let audit = |token: &str| test::TestRequest::get().uri("/admin/audit").insert_header(bearer(token)).to_request();
+        assert_eq!(test::call_service(&app, audit(&tokens["tom"])).await.status(), 403);
+        assert_eq!(test::call_service(&app, audit(&tokens["ada"])).await.status(), 200);
+        let settlements = test::TestRequest::get().uri("/settlements").insert_header(bearer(&tokens["viv"])).to_request();
+        assert_eq!(test::call_service(&app, settlements).await.status(), 200);
+
+        // Contracts are read with the viewer role, over REST and GraphQL alike
+        for uri in ["/contracts", "/contracts/search", "/contract/1", "/contract/1/dlc", "/contract/1/pricing-audit"] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), 401, "{}", uri);
+        }
+        let req = test::TestRequest::get().uri("/contracts").insert_header(bearer(&tokens["viv"])).to_request();
+        let contracts: Vec<Value> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(contracts.len(), 1);
+        let graphql = |query: &str, token: Option<&str>| {
+            let req = test::TestRequest::post().uri("/graphql").set_json(serde_json::json!({"query": query}));
+            match token {
+                Some(token) => req.insert_header(bearer(token)).to_request(),
+                None => req.to_request(),
+            }
+        };
+        let body: Value = test::call_and_read_body_json(&app, graphql("{ contracts { strikePrice } }", None)).await;
+        assert_eq!(body["errors"][0]["extensions"]["error_code"], "UNAUTHORIZED");
+        let body: Value = test::call_and_read_body_json(&app, graphql("{ pool { address } }", None)).await;
+        assert_eq!(body["data"]["pool"]["address"], "test-pool-address");
+        let body: Value = test::call_and_read_body_json(&app, graphql("{ contracts { strikePrice } }", Some(&tokens["viv"]))).await;
+        assert!(body["errors"].is_null(), "{}", body);
+        assert_eq!(body["data"]["contracts"][0]["strikePrice"], 95_000.0);
+
+        // Tampered and expired tokens are refused before the handler runs, even on public routes
+        let tampered = format!("{}x", tokens["ada"]);
+        let req = test::TestRequest::get().uri("/health").insert_header(bearer(&tampered)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 401);
+        let expired = jwt.issue("ada", Role::Admin, Utc::now().timestamp() - 3600).unwrap();
+        assert_eq!(test::call_service(&app, audit(&expired.token)).await.status(), 401);
+    }
+
+    #[actix_web::test]
+    async fn test_stats_history_serves_hourly_snapshots() {
+        let pool = db::create_in_memory_pool().unwrap();
+        let repository = Repository::new(pool.clone());
+        let state = test_state_with_pool(pool, Some(100_000_000));
+        let app = test_app!(state);
+
+        repository.insert_contract(contract(OptionSide::Call, 105_000.0, 0.5, 7 * 86_400)).await.unwrap();
+        // Snapshot the current hour as if it had just completed
+        let bucket = stats::bucket_start(Utc::now().timestamp());
+        let later = bucket + stats::BUCKET_SECS;
+        let recorded = stats::snapshot_completed_hour(&repository, &FakePrice(BTC_PRICE), &[Asset::Btc], later)
+            .await
+            .unwrap();
+        assert_eq!(recorded, 1);
+        assert_eq!(stats::snapshot_completed_hour(&repository, &FakePrice(BTC_PRICE), &[Asset::Btc], later).await.unwrap(), 0);
+
+        let uri = format!("/stats/history?from={}&to={}", bucket, later);
+        let history: Vec<Value> = test::call_and_read_body_json(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0]["bucket"], bucket);
+        assert_eq!(history[0]["volume"], 0.5);
+        assert_eq!(history[0]["open_interest"], 0.5);
+        assert_eq!(history[0]["contract_count"], 1);
+        assert_eq!(history[0]["notional_usd"], 0.5 * BTC_PRICE);
+
+        let uri = format!("/stats/history?from={}&to={}", later, bucket);
+        let resp = test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(resp.status(), 400);
+    }
+
+    #[actix_web::test]
+    async fn test_spot_history_candles_from_sampled_prices() {
+        let pool = db::create_in_memory_pool().unwrap();
+        let repository = Repository::new(pool.clone());
+        let state = test_state_with_pool(pool, Some(100_000_000));
+        let app = test_app!(state);
+
+        let hour = Utc::now().timestamp() / 3_600 * 3_600 - 3_600;
+        for (offset, price) in [(0, 100_000.0), (600, 101_000.0), (1_200, 99_500.0)] {
+            let recorded = price_history::sample_prices(&repository, &FakePrice(price), &[Asset::Btc, Asset::Eth], hour + offset)
+                .await
+                .unwrap();
+            assert_eq!(recorded, 2);
+        }
+
+        let uri = format!("/spot/history?from={}&to={}&interval=1h", hour, hour + 3_600);
+        let body: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(body["interval_secs"], 3_600);
+        let candles = body["candles"].as_array().unwrap();
+        assert_eq!(candles.len(), 1);
+        assert_eq!(candles[0]["timestamp"], hour);
+        assert_eq!((candles[0]["open"].as_f64(), candles[0]["close"].as_f64()), (Some(100_000.0), Some(99_500.0)));
+        assert_eq!((candles[0]["high"].as_f64(), candles[0]["low"].as_f64()), (Some(101_000.0), Some(99_500.0)));
+        assert_eq!(candles[0]["sample_count"], 3);
+
+        // Realized vol reads the same history
+        let realized: Vec<Value> = test::call_and_read_body_json(&app, test::TestRequest::get().uri("/realizedVol").to_request()).await;
+        assert_eq!(realized[0]["sample_count"], 3);
+
+        for uri in ["/spot/history?interval=soon", "/spot/history?interval=1m&from=2024-01-01&to=2024-12-31", "/spot/history?asset=ETH"] {
+            let resp = test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(resp.status(), 400, "{}", uri);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_vol_movers_from_iv_snapshots() {
+        let pool = db::create_in_memory_pool().unwrap();
+        let repository = Repository::new(pool.clone());
+        let state = test_state_with_pool(pool, Some(100_000_000));
+        let app = test_app!(state);
+
+        let now = Utc::now().timestamp();
+        let expires = now + 7 * 86_400;
+        repository
+            .run(move |conn| {
+                catalog::list_product(conn, Asset::Btc, OptionSide::Call, 105_000.0, expires, now)?;
+                catalog::list_product(conn, Asset::Btc, OptionSide::Put, 95_000.0, expires, now)
+            })
+            .await
+            .unwrap();
+        // IVs rise from 50% to 62.5% within the last 4 hours
+        let policy = Arc::new(DefaultIvPolicy::default());
+        for (iv, at) in [(0.5, now - 3 * 3_600), (0.625, now - 60)] {
+            let ivs = IvResolver::new(Arc::new(FakeIv(iv)), policy.clone());
+            assert_eq!(iv_history::snapshot_products(&repository, &ivs, at).await.unwrap(), 2);
+        }
+
+        let movers: Vec<Value> =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/volMovers?window=4h&limit=1").to_request()).await;
+        assert_eq!(movers.len(), 1);
+        assert_eq!(movers[0]["window"], "4h");
+        assert_eq!((movers[0]["iv"].as_f64(), movers[0]["iv_before"].as_f64()), (Some(0.625), Some(0.5)));
+        assert_eq!((movers[0]["iv_change"].as_f64(), movers[0]["iv_change_percent"].as_f64()), (Some(0.125), Some(25.0)));
+        // Shorter windows compare against the last snapshot before them
+        let movers: Vec<Value> =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/volMovers?window=1h").to_request()).await;
+        assert_eq!(movers.len(), 2);
+        assert_eq!(movers[1]["iv_change"], 0.125);
+
+        // Each snapshot is published as a marks event
+        let marks = repository.run(|conn| Ok(outbox::pending(conn, 10)?)).await.unwrap();
+        assert_eq!(marks.iter().map(|e| e.event.as_str()).collect::<Vec<_>>(), vec!["marks.snapshot"; 2]);
+        assert_eq!(marks[1].data["timestamp"], now - 60);
+        assert_eq!(marks[1].data["marks"][0]["iv"], 0.625);
+        assert!(marks[1].data["marks"][0]["product_symbol"].as_str().unwrap().ends_with("-105000-C"));
+    }
+}
+
+#[cfg(test)]
+mod asset_tests {
+    use btc_options_api::models::{legacy_product_symbol, product_symbol, Asset, Contract, OptionSide};
+
+    #[test]
+    fn test_asset_parsing_and_serde() {
+        assert_eq!("eth".parse::<Asset>().unwrap(), Asset::Eth);
+        assert!("DOGE".parse::<Asset>().is_err());
+        assert_eq!(Asset::parse_list("").unwrap(), vec![Asset::Btc]);
+        assert_eq!(Asset::parse_list("ETH, btc,ETH").unwrap(), vec![Asset::Btc, Asset::Eth]);
+        assert!(Asset::parse_list("BTC,SOL").is_err());
+
+        // Contracts without an underlying are BTC, as before multi-asset support
+        let contract: Contract = serde_json::from_str(
+            r#"{"side": "Call", "strike_price": 100000.0, "quantity": 1.0, "expires": 1, "premium": 0.01}"#,
+        )
+        .unwrap();
+        assert_eq!(contract.underlying, Asset::Btc);
+        assert_eq!(serde_json::to_value(Asset::Eth).unwrap(), "ETH");
+    }
+
+    #[test]
+    fn test_product_symbols() {
+        // 2025-03-28 08:00 UTC and 2025-03-07 08:00 UTC
+        assert_eq!(product_symbol(Asset::Btc, &OptionSide::Call, 50000.0, 1_743_148_800), "BTC-28MAR25-50000-C");
+        assert_eq!(product_symbol(Asset::Eth, &OptionSide::Put, 3512.5, 1_741_334_400), "ETH-7MAR25-3512.5-P");
+        assert_eq!(legacy_product_symbol(Asset::Btc, &OptionSide::Put, 50000.0, 0), "BTC-EXPIRED-50000-Put");
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::needless_borrows_for_generic_args)]
 mod database_tests {
     use rusqlite::Connection;
     use chrono::Utc;